@@ -0,0 +1,136 @@
+//! Benchmarks of the meshing and editing pipeline on a synthetic map. Run with `cargo bench`, and
+//! compare against a saved one with `cargo bench -- --baseline <name>`.
+
+use voxel_mapper::{
+    collision::{insert_all_chunk_bvts, VoxelBVT},
+    voxel::{
+        bench_map::{generate_bench_map, BenchMapSpec},
+        double_buffer::EditedChunksBackBuffer,
+        meshing::{
+            generate_mesh_vertices_with_greedy_quads, generate_mesh_vertices_with_surface_nets,
+            MeshLayer,
+        },
+        morton::morton_ordered_chunk_mins,
+        LocalVoxelCache, Voxel, VoxelDistance, VoxelMap, VoxelType, VOXEL_CHUNK_SHAPE,
+    },
+};
+
+use building_blocks::{prelude::*, storage::OctreeSet};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+
+fn bench_map() -> VoxelMap {
+    generate_bench_map(&BenchMapSpec {
+        size_chunks: [4, 4],
+        ..Default::default()
+    })
+}
+
+fn chunk_extents(map: &VoxelMap) -> Vec<Extent3i> {
+    morton_ordered_chunk_mins(map)
+        .into_iter()
+        .map(|chunk_min| Extent3i::from_min_and_shape(chunk_min, VOXEL_CHUNK_SHAPE))
+        .collect()
+}
+
+fn meshing(c: &mut Criterion) {
+    let map = bench_map();
+    let extents = chunk_extents(&map);
+    // The cache is shared by every iteration, so after the first one the chunks are already
+    // decompressed and only the meshing is measured.
+    let local_cache = LocalVoxelCache::new();
+
+    let mut group = c.benchmark_group("meshing");
+    group.bench_function("surface_nets", |b| {
+        b.iter(|| {
+            for extent in extents.iter() {
+                black_box(generate_mesh_vertices_with_surface_nets(
+                    &map,
+                    extent,
+                    &local_cache,
+                    MeshLayer::Opaque,
+                ));
+            }
+        })
+    });
+    group.bench_function("greedy_quads", |b| {
+        b.iter(|| {
+            for extent in extents.iter() {
+                black_box(generate_mesh_vertices_with_greedy_quads(
+                    &map,
+                    extent,
+                    &local_cache,
+                    MeshLayer::Opaque,
+                ));
+            }
+        })
+    });
+    group.finish();
+}
+
+fn editing(c: &mut Criterion) {
+    let map = bench_map();
+    let local_cache = LocalVoxelCache::new();
+    let reader = map.voxels.reader(&local_cache);
+    // A sphere on the surface in the middle of the map, which spans several chunks.
+    let center = PointN([2, 2, 2]) * VOXEL_CHUNK_SHAPE;
+    let radius = 12;
+    let extent =
+        Extent3i::from_min_and_shape(center - PointN([radius; 3]), PointN([2 * radius; 3]));
+
+    c.bench_function("edit_voxels_out_of_place", |b| {
+        b.iter_batched(
+            EditedChunksBackBuffer::new,
+            |mut backbuffer| {
+                backbuffer.edit_voxels_out_of_place(
+                    &reader,
+                    &extent,
+                    |p: Point3i, v: &mut Voxel| {
+                        let dist = (p - center).norm() - radius as f32;
+                        if dist < 0.0 {
+                            v.distance = VoxelDistance::from(dist);
+                            v.voxel_type = VoxelType(1);
+                        }
+                    },
+                );
+
+                backbuffer
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn octrees(c: &mut Criterion) {
+    let map = bench_map();
+    let local_cache = LocalVoxelCache::new();
+    let reader = map.voxels.reader(&local_cache);
+    let chunks: Vec<Array3x1<Voxel>> = morton_ordered_chunk_mins(&map)
+        .into_iter()
+        .filter_map(|chunk_min| reader.get_chunk(ChunkKey::new(0, chunk_min)).cloned())
+        .collect();
+
+    let mut group = c.benchmark_group("octrees");
+    group.bench_function("from_chunks", |b| {
+        b.iter(|| {
+            for chunk in chunks.iter() {
+                let chunk_infos = TransformMap::new(chunk, map.voxel_info_transform());
+                black_box(OctreeSet::from_array3(&chunk_infos, *chunk_infos.extent()));
+            }
+        })
+    });
+    group.bench_function("insert_all_chunk_bvts", |b| {
+        b.iter_batched(
+            VoxelBVT::default,
+            |mut bvt| {
+                insert_all_chunk_bvts(&mut bvt, &map);
+
+                bvt
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, meshing, editing, octrees);
+criterion_main!(benches);
@@ -1,10 +1,22 @@
-use crate::geometry::ritter_sphere_bounding_positions;
+use crate::{
+    geometry::{aabb_bounding_positions, octahedral_encode, ritter_sphere_bounding_positions},
+    rendering::{
+        aabb_culling::BoundingBox,
+        blocky_pbr_pass::MaterialLayer,
+        glow_shell_pass::GlowEmission,
+        splatted_triplanar_pbr_pass::{
+            ArrayMaterialIndex, ArrayMaterialIndexInt, ChunkVertexFormat, Emission,
+            MaterialIndices, PackedEmission, PackedMaterialWeights, PackedNormal,
+            VoxelRenderConfig, MAX_PACKED_EMISSION,
+        },
+    },
+};
 
 use amethyst::{
     assets::{AssetLoaderSystemData, Handle, Progress},
-    core::ecs::prelude::*,
+    core::{ecs::prelude::*, math::Vector3},
     renderer::{
-        rendy::mesh::{Color, MeshBuilder, Normal, Position},
+        rendy::mesh::{Color, MeshBuilder, Normal, Position, TexCoord},
         visibility::BoundingSphere,
         Mesh,
     },
@@ -18,11 +30,70 @@ use std::path::Path;
 #[cfg(feature = "profiler")]
 use thread_profiler::profile_scope;
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct PosColorNormVertices {
     pub positions: Vec<Position>,
-    pub colors: Vec<Color>,
+    /// Converted to the "color" attribute (or the material indices and weights) that the splatted
+    /// triplanar pass expects, depending on the `ChunkVertexFormat`.
+    pub materials: Vec<VertexMaterials>,
     pub normals: Vec<Normal>,
+    /// Linear RGB light given off by the voxels at each vertex.
+    pub emissions: Vec<Emission>,
+    /// Only set for blocky (greedy quads) meshes, which are drawn by the blocky pass with a single
+    /// material per quad instead of the splatted triplanar pass.
+    pub tex_coords: Vec<TexCoord>,
+}
+
+/// The (up to) 4 array material layers that are blended at a vertex, with their weights. Unused
+/// slots have zero weight.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct VertexMaterials {
+    pub indices: [ArrayMaterialIndexInt; 4],
+    pub weights: [f32; 4],
+}
+
+impl VertexMaterials {
+    pub fn single(index: ArrayMaterialIndex) -> Self {
+        Self {
+            indices: [index.0, 0, 0, 0],
+            weights: [1.0, 0.0, 0.0, 0.0],
+        }
+    }
+
+    /// Keeps the 4 heaviest of the (layer, weight) pairs, which must have distinct layers.
+    pub fn from_weights(weights: &mut [(ArrayMaterialIndexInt, f32)]) -> Self {
+        weights.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+
+        let mut materials = Self::default();
+        for (slot, (index, weight)) in weights.iter().take(4).enumerate() {
+            materials.indices[slot] = *index;
+            materials.weights[slot] = *weight;
+        }
+
+        materials
+    }
+
+    pub fn weight_of(&self, index: ArrayMaterialIndexInt) -> f32 {
+        self.indices
+            .iter()
+            .zip(self.weights.iter())
+            .filter(|(i, _)| **i == index)
+            .map(|(_, w)| *w)
+            .sum()
+    }
+
+    /// Weights of the first 4 layers, as expected by the `Full` and `Packed` vertex formats. Any
+    /// other layers are dropped.
+    fn first_four_layer_weights(&self) -> [f32; 4] {
+        let mut weights = [0.0; 4];
+        for (index, weight) in self.indices.iter().zip(self.weights.iter()) {
+            if let Some(w) = weights.get_mut(*index as usize) {
+                *w += weight;
+            }
+        }
+
+        weights
+    }
 }
 
 pub struct IndexedPosColorNormVertices {
@@ -34,12 +105,15 @@ pub struct IndexedPosColorNormVertices {
 pub struct BoundedMesh {
     pub mesh: Handle<Mesh>,
     pub sphere: BoundingSphere,
+    /// A tighter bound than the sphere for long, flat meshes. Used by the `AabbCullingSystem`.
+    pub aabb: BoundingBox,
 }
 
 /// Loads vertices into `BoundedMesh` objects.
 #[derive(SystemData)]
 pub struct MeshLoader<'a> {
     loader: AssetLoaderSystemData<'a, Mesh>,
+    render_config: Read<'a, VoxelRenderConfig>,
 }
 
 impl<'a> MeshLoader<'a> {
@@ -59,19 +133,263 @@ impl<'a> MeshLoader<'a> {
 
             BoundingSphere::new(sphere.center, sphere.radius)
         };
+        let aabb = BoundingBox(aabb_bounding_positions(&ivs.vertices.positions));
 
-        let mesh = self.loader.load_from_data(
-            MeshBuilder::new()
-                .with_vertices(ivs.vertices.positions)
-                .with_vertices(ivs.vertices.colors)
+        if !ivs.vertices.tex_coords.is_empty() {
+            return BoundedMesh {
+                mesh: self.start_loading_blocky_mesh(ivs, progress),
+                sphere,
+                aabb,
+            };
+        }
+
+        let ivs = match self.render_config.vertex_format {
+            ChunkVertexFormat::Indexed => unweld_triangle_materials(ivs),
+            _ => ivs,
+        };
+
+        let num_vertices = ivs.vertices.positions.len();
+        let builder = MeshBuilder::new().with_vertices(ivs.vertices.positions);
+        let builder = match self.render_config.vertex_format {
+            ChunkVertexFormat::Full => builder
+                .with_vertices(material_weight_colors(&ivs.vertices.materials))
+                .with_vertices(ivs.vertices.normals)
+                .with_vertices(ivs.vertices.emissions),
+            ChunkVertexFormat::Packed => builder
+                .with_vertices(pack_material_weights(&ivs.vertices.materials))
+                .with_vertices(pack_normals(&ivs.vertices.normals))
+                .with_vertices(pack_emissions(&ivs.vertices.emissions)),
+            ChunkVertexFormat::Indexed => builder
+                .with_vertices(
+                    ivs.vertices
+                        .materials
+                        .iter()
+                        .map(|m| MaterialIndices(m.indices))
+                        .collect::<Vec<_>>(),
+                )
+                .with_vertices(
+                    ivs.vertices
+                        .materials
+                        .iter()
+                        .map(|m| pack_weights(m.weights))
+                        .collect::<Vec<_>>(),
+                )
                 .with_vertices(ivs.vertices.normals)
-                .with_indices(ivs.indices)
-                .into(),
-            progress,
-        );
+                .with_vertices(pack_emissions(&ivs.vertices.emissions)),
+        };
+        let builder = with_small_indices(builder, ivs.indices, num_vertices);
+
+        let mesh = self.loader.load_from_data(builder.into(), progress);
 
-        BoundedMesh { mesh, sphere }
+        BoundedMesh { mesh, sphere, aabb }
     }
+
+    /// (vec3 position, vec2 tex coord, u32 material layer, vec3 normal, vec3 emission), as expected
+    /// by the blocky pass. Each vertex uses the heaviest layer of its materials.
+    fn start_loading_blocky_mesh<P: Progress>(
+        &self,
+        ivs: IndexedPosColorNormVertices,
+        progress: P,
+    ) -> Handle<Mesh> {
+        let IndexedPosColorNormVertices { indices, vertices } = ivs;
+        let num_vertices = vertices.positions.len();
+        let layers: Vec<_> = vertices
+            .materials
+            .iter()
+            .map(|m| MaterialLayer(m.indices[0] as u32))
+            .collect();
+        let builder = MeshBuilder::new()
+            .with_vertices(vertices.positions)
+            .with_vertices(vertices.tex_coords)
+            .with_vertices(layers)
+            .with_vertices(vertices.normals)
+            .with_vertices(vertices.emissions);
+        let builder = with_small_indices(builder, indices, num_vertices);
+
+        self.loader.load_from_data(builder.into(), progress)
+    }
+
+    /// Copies the triangles with any emissive corner into a (vec3 position, vec3 normal, vec3 glow
+    /// emission) mesh for the glow shell pass. Returns `None` if glow shells are disabled or nothing
+    /// glows.
+    pub fn start_loading_glow_mesh<P: Progress>(
+        &self,
+        ivs: &IndexedPosColorNormVertices,
+        progress: P,
+    ) -> Option<BoundedMesh> {
+        if !self.render_config.glow_shells {
+            return None;
+        }
+
+        let IndexedPosColorNormVertices { indices, vertices } = ivs;
+        let is_emissive = |i: u32| {
+            vertices
+                .emissions
+                .get(i as usize)
+                .map_or(false, |Emission(e)| e.iter().any(|c| *c > 0.0))
+        };
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut emissions = Vec::new();
+        for triangle in indices.chunks_exact(3) {
+            if !triangle.iter().any(|i| is_emissive(*i)) {
+                continue;
+            }
+            for &i in triangle.iter() {
+                let i = i as usize;
+                positions.push(vertices.positions[i]);
+                normals.push(vertices.normals[i]);
+                emissions.push(GlowEmission(vertices.emissions[i].0));
+            }
+        }
+        if positions.is_empty() {
+            return None;
+        }
+
+        let sphere = ritter_sphere_bounding_positions(&positions);
+        let sphere = BoundingSphere::new(sphere.center, sphere.radius);
+        let aabb = BoundingBox(aabb_bounding_positions(&positions));
+
+        let num_vertices = positions.len();
+        let builder = MeshBuilder::new()
+            .with_vertices(positions)
+            .with_vertices(normals)
+            .with_vertices(emissions);
+        let builder = with_small_indices(builder, (0..num_vertices as u32).collect(), num_vertices);
+        let mesh = self.loader.load_from_data(builder.into(), progress);
+
+        Some(BoundedMesh { mesh, sphere, aabb })
+    }
+}
+
+// Small meshes (the common case for a single chunk) only need 16-bit indices, which halves the size
+// of the index buffer.
+fn with_small_indices(
+    builder: MeshBuilder<'static>,
+    indices: Vec<u32>,
+    num_vertices: usize,
+) -> MeshBuilder<'static> {
+    if num_vertices <= u16::MAX as usize + 1 {
+        builder.with_indices(indices.into_iter().map(|i| i as u16).collect::<Vec<_>>())
+    } else {
+        builder.with_indices(indices)
+    }
+}
+
+fn material_weight_colors(materials: &[VertexMaterials]) -> Vec<Color> {
+    materials
+        .iter()
+        .map(|m| Color(m.first_four_layer_weights()))
+        .collect()
+}
+
+fn pack_material_weights(materials: &[VertexMaterials]) -> Vec<PackedMaterialWeights> {
+    materials
+        .iter()
+        .map(|m| pack_weights(m.first_four_layer_weights()))
+        .collect()
+}
+
+fn pack_weights(weights: [f32; 4]) -> PackedMaterialWeights {
+    let mut packed = [0; 4];
+    for (p, w) in packed.iter_mut().zip(weights.iter()) {
+        *p = (w.max(0.0).min(1.0) * 255.0).round() as u8;
+    }
+
+    PackedMaterialWeights(packed)
+}
+
+/// RGBM encoding: the alpha holds the largest channel as a fraction of `MAX_PACKED_EMISSION`, and
+/// the RGB is relative to it.
+fn pack_emissions(emissions: &[Emission]) -> Vec<PackedEmission> {
+    emissions
+        .iter()
+        .map(|Emission(e)| {
+            let max = e
+                .iter()
+                .cloned()
+                .fold(0.0, f32::max)
+                .min(MAX_PACKED_EMISSION);
+            if max <= 0.0 {
+                return PackedEmission::default();
+            }
+            // Round the multiplier up, so the RGB doesn't need to exceed 1.
+            let m = (max / MAX_PACKED_EMISSION * 255.0).ceil();
+            let scale = m / 255.0 * MAX_PACKED_EMISSION;
+            let mut packed = [0, 0, 0, m as u8];
+            for (p, c) in packed.iter_mut().zip(e.iter()) {
+                *p = ((c / scale).max(0.0).min(1.0) * 255.0).round() as u8;
+            }
+
+            PackedEmission(packed)
+        })
+        .collect()
+}
+
+/// Gives every triangle its own 3 vertices, all with the same material indices: the 4 layers with
+/// the most weight over the triangle's corners. Each corner keeps its own weights for those layers,
+/// so the blend is still continuous across triangles that agree on their layers.
+fn unweld_triangle_materials(ivs: IndexedPosColorNormVertices) -> IndexedPosColorNormVertices {
+    let IndexedPosColorNormVertices { indices, vertices } = ivs;
+    let mut unwelded = PosColorNormVertices::default();
+    for triangle in indices.chunks_exact(3) {
+        let mut totals: Vec<(ArrayMaterialIndexInt, f32)> = Vec::with_capacity(12);
+        for &i in triangle.iter() {
+            let corner = &vertices.materials[i as usize];
+            for (index, weight) in corner.indices.iter().zip(corner.weights.iter()) {
+                match totals.iter_mut().find(|(t, _)| t == index) {
+                    Some((_, total)) => *total += weight,
+                    None => totals.push((*index, *weight)),
+                }
+            }
+        }
+        let triangle_layers = VertexMaterials::from_weights(&mut totals).indices;
+
+        for &i in triangle.iter() {
+            let i = i as usize;
+            let corner = &vertices.materials[i];
+            let mut weights = [0.0; 4];
+            for (w, index) in weights.iter_mut().zip(triangle_layers.iter()) {
+                *w = corner.weight_of(*index);
+            }
+            // A corner might not have any of the triangle's layers, but the weights can't all be
+            // zero, or they can't be normalized.
+            let total: f32 = weights.iter().sum();
+            if total > 0.0 {
+                // Normalize before the weights get packed into 8 bits.
+                for w in weights.iter_mut() {
+                    *w /= total;
+                }
+            } else {
+                weights[0] = 1.0;
+            }
+
+            unwelded.positions.push(vertices.positions[i]);
+            unwelded.normals.push(vertices.normals[i]);
+            unwelded.emissions.push(vertices.emissions[i]);
+            unwelded.materials.push(VertexMaterials {
+                indices: triangle_layers,
+                weights,
+            });
+        }
+    }
+    let indices = (0..unwelded.positions.len() as u32).collect();
+
+    IndexedPosColorNormVertices {
+        indices,
+        vertices: unwelded,
+    }
+}
+
+fn pack_normals(normals: &[Normal]) -> Vec<PackedNormal> {
+    normals
+        .iter()
+        .map(|Normal(n)| {
+            let [x, y] = octahedral_encode(&Vector3::new(n[0], n[1], n[2]).normalize());
+
+            PackedNormal([(x * 32767.0).round() as i16, (y * 32767.0).round() as i16])
+        })
+        .collect()
 }
 
 #[derive(Debug)]
@@ -1,45 +1,198 @@
-use voxel_mapper::{
-    assets::{read_bincode_file, BincodeFileError},
-    voxel::Voxel,
-};
+use voxel_mapper::voxel::{map_file::load_voxel_map, Voxel, EMPTY_VOXEL};
 
 use building_blocks::prelude::*;
-use std::path::PathBuf;
+use std::{path::PathBuf, str::FromStr};
 use structopt::StructOpt;
 
+/// Prints the voxels of a map in an extent, or renders one Y layer of it as ASCII.
 #[derive(StructOpt, Debug)]
 #[structopt(name = "dump-voxels")]
 struct Opt {
+    /// A map file, like "assets/maps/example_map.ron".
     #[structopt(parse(from_os_str))]
     map_file: PathBuf,
 
-    #[structopt(long)]
+    #[structopt(long, default_value = "0")]
     x: i32,
-    #[structopt(long)]
+    #[structopt(long, default_value = "0")]
     y: i32,
-    #[structopt(long)]
+    #[structopt(long, default_value = "0")]
     z: i32,
-    #[structopt(long)]
+    #[structopt(long, default_value = "16")]
     sx: i32,
-    #[structopt(long)]
+    #[structopt(long, default_value = "16")]
     sy: i32,
-    #[structopt(long)]
+    #[structopt(long, default_value = "16")]
     sz: i32,
+
+    /// Only include voxels of these types. Can be repeated.
+    #[structopt(long = "voxel-type")]
+    voxel_types: Vec<u8>,
+    /// Only include voxels with this SDF sign: "solid" (negative) or "empty".
+    #[structopt(long)]
+    sign: Option<SdfSign>,
+    /// One of "debug", "csv" or "json".
+    #[structopt(long, default_value = "debug")]
+    format: Format,
+    /// Renders the XZ cross-section at this Y instead, ignoring --y and --sy. Each solid voxel is
+    /// drawn as its type in base 36, empty voxels as '.', and filtered voxels as ' '.
+    #[structopt(long)]
+    slice_y: Option<i32>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum SdfSign {
+    Solid,
+    Empty,
+}
+
+impl FromStr for SdfSign {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "solid" => Ok(SdfSign::Solid),
+            "empty" => Ok(SdfSign::Empty),
+            other => Err(format!(
+                "Unknown SDF sign {:?}, expected solid or empty",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Format {
+    Debug,
+    Csv,
+    Json,
 }
 
-fn main() -> Result<(), BincodeFileError> {
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "debug" => Ok(Format::Debug),
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            other => Err(format!(
+                "Unknown format {:?}, expected debug, csv or json",
+                other
+            )),
+        }
+    }
+}
+
+impl Opt {
+    fn includes(&self, voxel: &Voxel) -> bool {
+        if !self.voxel_types.is_empty() && !self.voxel_types.contains(&voxel.voxel_type.0) {
+            return false;
+        }
+        match self.sign {
+            Some(SdfSign::Solid) => voxel.distance.0 < 0,
+            Some(SdfSign::Empty) => voxel.distance.0 >= 0,
+            None => true,
+        }
+    }
+}
+
+fn main() -> Result<(), String> {
     let opt = Opt::from_args();
 
-    let voxels: ChunkMap3<Voxel> = read_bincode_file(opt.map_file)?;
+    let map = load_voxel_map(&opt.map_file)
+        .map_err(|e| format!("Failed to load {}: {:?}", opt.map_file.display(), e))?;
 
-    let min = [opt.x, opt.y, opt.z];
-    let shape = [opt.sx, opt.sy, opt.sz];
-    let dump_extent = Extent3i::from_min_and_shape(PointN(min), PointN(shape));
-    println!("extent = {:?}", dump_extent);
+    let (y, sy) = opt.slice_y.map_or((opt.y, opt.sy), |y| (y, 1));
+    let dump_extent =
+        Extent3i::from_min_and_shape(PointN([opt.x, y, opt.z]), PointN([opt.sx, sy, opt.sz]));
+    let local_cache = LocalChunkCache3::new();
+    let reader = map.voxels.reader(&local_cache);
+    let mut voxels = Array3x1::fill(dump_extent, EMPTY_VOXEL);
+    copy_extent(&dump_extent, &reader.lod_view(0), &mut voxels);
 
-    voxels.for_each_ref(dump_extent, |p: Point3i, voxel|) {
-        println!("{:?} {:?}", p, voxel);
+    if opt.slice_y.is_some() {
+        print_slice(&opt, &voxels);
+    } else {
+        print_voxels(&opt, &voxels);
     }
 
     Ok(())
 }
+
+fn print_voxels(opt: &Opt, voxels: &Array3x1<Voxel>) {
+    let extent = *voxels.extent();
+    match opt.format {
+        Format::Debug => println!("extent = {:?}", extent),
+        Format::Csv => println!("x,y,z,voxel_type,distance"),
+        Format::Json => println!("["),
+    }
+
+    let mut first = true;
+    voxels.for_each(&extent, |p: Point3i, voxel: Voxel| {
+        if !opt.includes(&voxel) {
+            return;
+        }
+        let distance: f32 = voxel.distance.into();
+        match opt.format {
+            Format::Debug => println!("{:?} {:?}", p, voxel),
+            Format::Csv => println!(
+                "{},{},{},{},{}",
+                p.x(),
+                p.y(),
+                p.z(),
+                voxel.voxel_type.0,
+                distance
+            ),
+            Format::Json => {
+                // Objects are separated by a comma on the line of the next one, so the last one
+                // doesn't need lookahead.
+                let separator = if first { " " } else { "," };
+                println!(
+                    "{} {{\"x\": {}, \"y\": {}, \"z\": {}, \"voxel_type\": {}, \"distance\": {}}}",
+                    separator,
+                    p.x(),
+                    p.y(),
+                    p.z(),
+                    voxel.voxel_type.0,
+                    distance
+                );
+            }
+        }
+        first = false;
+    });
+
+    if let Format::Json = opt.format {
+        println!("]");
+    }
+}
+
+/// Draws one row per Z, with X increasing to the right.
+fn print_slice(opt: &Opt, voxels: &Array3x1<Voxel>) {
+    let extent = *voxels.extent();
+    let y = extent.minimum.y();
+    println!(
+        "Y = {}, X from {} to {}, Z from {} (top) to {} (bottom)",
+        y,
+        extent.minimum.x(),
+        extent.max().x(),
+        extent.minimum.z(),
+        extent.max().z()
+    );
+
+    for z in extent.minimum.z()..=extent.max().z() {
+        let row: String = (extent.minimum.x()..=extent.max().x())
+            .map(|x| {
+                let voxel = voxels.get(PointN([x, y, z]));
+                if !opt.includes(&voxel) {
+                    ' '
+                } else if voxel.distance.0 < 0 {
+                    std::char::from_digit(voxel.voxel_type.0 as u32 % 36, 36).unwrap()
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        println!("{:>5} {}", z, row);
+    }
+}
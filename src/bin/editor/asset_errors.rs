@@ -0,0 +1,85 @@
+use voxel_mapper::voxel::material_fallback::MissingAssetsEvent;
+
+use amethyst::{
+    assets::{AssetStorage, Loader},
+    core::ecs::prelude::*,
+    derive::SystemDesc,
+    shrev::EventChannel,
+    ui::{get_default_font, Anchor, FontAsset, LineMode, UiText, UiTransform},
+};
+
+#[derive(Default)]
+pub struct AssetErrorText;
+
+impl Component for AssetErrorText {
+    type Storage = NullStorage<Self>;
+}
+
+const ERROR_TEXT_WIDTH: f32 = 1000.0;
+const ERROR_TEXT_HEIGHT: f32 = 100.0;
+const ERROR_FONT_SIZE: f32 = 18.0;
+
+/// Creates an empty label along the top of the screen for reporting assets that failed to load.
+pub fn make_asset_error_ui(world: &mut World) {
+    let font = world.exec(
+        |(loader, font_storage): (ReadExpect<Loader>, Read<AssetStorage<FontAsset>>)| {
+            get_default_font(&loader, &font_storage)
+        },
+    );
+
+    let transform = UiTransform::new(
+        "asset_errors".to_string(),
+        Anchor::TopMiddle,
+        Anchor::TopMiddle,
+        0.0,
+        0.0,
+        1.0,
+        ERROR_TEXT_WIDTH,
+        ERROR_TEXT_HEIGHT,
+    );
+    let mut text = UiText::new(font, String::new(), [1.0, 0.2, 0.2, 1.0], ERROR_FONT_SIZE);
+    text.line_mode = LineMode::Wrap;
+
+    world
+        .create_entity()
+        .with(transform)
+        .with(text)
+        .with(AssetErrorText)
+        .build();
+}
+
+/// Lists any missing assets on screen, so it's clear why some chunks are drawn with the fallback
+/// material.
+#[derive(SystemDesc)]
+#[system_desc(name(AssetErrorSystemDesc))]
+pub struct AssetErrorSystem {
+    #[system_desc(event_channel_reader)]
+    reader_id: ReaderId<MissingAssetsEvent>,
+}
+
+impl AssetErrorSystem {
+    pub fn new(reader_id: ReaderId<MissingAssetsEvent>) -> Self {
+        AssetErrorSystem { reader_id }
+    }
+}
+
+impl<'a> System<'a> for AssetErrorSystem {
+    type SystemData = (
+        Read<'a, EventChannel<MissingAssetsEvent>>,
+        ReadStorage<'a, AssetErrorText>,
+        WriteStorage<'a, UiText>,
+    );
+
+    fn run(&mut self, (missing_events, is_error_text, mut texts): Self::SystemData) {
+        for event in missing_events.read(&mut self.reader_id) {
+            for (_, text) in (&is_error_text, &mut texts).join() {
+                if text.text.is_empty() {
+                    text.text = "Missing assets (see assets/array_materials):".to_string();
+                }
+                for path in event.asset_paths.iter() {
+                    text.text.push_str(&format!("\n{}", path));
+                }
+            }
+        }
+    }
+}
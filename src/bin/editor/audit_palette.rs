@@ -0,0 +1,86 @@
+use crate::{
+    hotbar::HotbarConfig,
+    voxel_brush::{BrushConfig, PaintBrush},
+};
+
+use voxel_mapper::voxel::{
+    fluid::FluidConfig,
+    map_file::{snapshot_chunks, write_voxels_file, VoxelMapFile},
+    palette_audit::{palette_usage, remap_palette, PaletteRemap},
+};
+
+use amethyst::{config::Config, utils::application_dir};
+use std::path::Path;
+
+/// Prints how many voxels use each palette entry of `map_file`. With `compact`, unused entries are
+/// removed from the palette, which is saved in the map's edits file, and the voxels are renumbered
+/// to match.
+///
+/// Entries placed by the map's generators, or used by the hotbar, the brush or the fluid config,
+/// are kept even if no voxel uses them yet. Compacting is refused while any voxel has a type
+/// outside of the palette.
+pub fn audit_palette_file(map_file: &Path, compact: bool) -> amethyst::Result<()> {
+    let mut map_spec = VoxelMapFile::load_with_edits(map_file)?;
+    let mut map = map_spec
+        .load_voxel_map()
+        .map_err(|e| amethyst::Error::from_string(format!("Failed to load map: {:?}", e)))?;
+
+    let mut usage = palette_usage(&map);
+    usage.add_references(map_spec.generated_voxel_types().map_err(|e| {
+        amethyst::Error::from_string(format!("Failed to load map generators: {}", e))
+    })?);
+    let config_dir = application_dir("assets/config")?;
+    let hotbar = HotbarConfig::load(config_dir.join("hotbar.ron"))?;
+    usage.add_references(hotbar.slots.iter().map(|slot| slot.voxel_type));
+    let brush = PaintBrush::new(BrushConfig::load(config_dir.join("brush.ron"))?);
+    usage.add_references(vec![brush.voxel_type, brush.secondary_voxel_type]);
+    let fluid = FluidConfig::load(config_dir.join("fluid.ron"))?;
+    usage.add_references(vec![fluid.voxel_type]);
+
+    for (i, count) in usage.counts.iter().enumerate() {
+        println!("Voxel type {}: {} voxels", i, count);
+    }
+    if usage.out_of_range > 0 {
+        println!(
+            "{} voxels have types outside the palette, see validate-map",
+            usage.out_of_range
+        );
+    }
+    println!(
+        "Entries referenced by generators and editor configs: {:?}",
+        usage.referenced
+    );
+    let unused = usage.unused();
+    println!("Unused entries: {:?}", unused);
+
+    if !compact {
+        return Ok(());
+    }
+    let remap = PaletteRemap::compacting(&usage).ok_or_else(|| {
+        amethyst::Error::from_string(
+            "Can't compact a palette while voxels have types outside of it; \
+             fix them with validate-map --fix first",
+        )
+    })?;
+    if remap.is_identity() {
+        println!("Nothing to compact");
+        return Ok(());
+    }
+
+    remap_palette(&mut map, &remap);
+    let voxels_path = map_spec.voxels_save_path();
+    write_voxels_file(&voxels_path, map.chunk_shape(), snapshot_chunks(&map))
+        .map_err(|e| amethyst::Error::from_string(format!("Failed to save voxels: {:?}", e)))?;
+    map_spec.set_palette(&map.palette);
+    map_spec.write_edits(map_file)?;
+
+    println!("Renumbered voxel types (update any hotbar slots that use them):");
+    for (old_type, new_type) in remap.iter_changed() {
+        match new_type {
+            Some(new_type) => println!("{} -> {}", old_type.0, new_type.0),
+            None => println!("{} removed", old_type.0),
+        }
+    }
+
+    Ok(())
+}
@@ -1,15 +1,93 @@
-use amethyst::input::BindingTypes;
+use amethyst::input::{BindingTypes, Bindings, Button};
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
 #[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ActionBinding {
     ExitApp,
+    Undo,
+    Redo,
     ChangeMeshMode,
     CreateVoxel,
     RemoveVoxel,
     IncreaseBrushRadius,
     DecreaseBrushRadius,
+    IncreaseBrushHardness,
+    DecreaseBrushHardness,
+    IncreaseShellCutoff,
+    DecreaseShellCutoff,
+    PushBrush,
+    PullBrush,
+    ResetBrushDepth,
+    ErodeTerrain,
+    CycleBrushMode,
+    CycleBrushFalloff,
+    /// Selects a hotbar slot, counting from 0.
+    SelectHotbarSlot(usize),
+    SwapBrushVoxelTypes,
+    AddPathPoint,
+    CarvePath,
+    ClearPath,
+    PlaceBlockOut,
+    SelectCorner,
+    ClearSelection,
+    FillSelection,
+    EmptySelection,
+    SmoothSelection,
+    NoiseSelection,
+    ReplaceInSelection,
+    CopySelection,
+    PasteClipboard,
+    RotateClipboard,
+    FlipClipboardX,
+    FlipClipboardZ,
+    MoveSelection,
+    GizmoPaste,
+    GizmoMoveSelection,
+    GrabGizmo,
+    CommitGizmo,
+    ExportClipboard,
+    ImportClipboard,
+    ToggleChunkLock,
+    ToggleCacheStats,
+    SaveMap,
+    ToggleMarker,
+    CycleMarkerKind,
+    AddCameraBookmark,
+    NextCameraBookmark,
+    CreateZone,
+    RemoveZone,
+    ToggleCameraController,
+    FloodFillMaterial,
+    AddPaletteEntry,
+    NextPaletteMaterial,
+    PreviousPaletteMaterial,
+    TogglePaletteFloor,
+    TogglePaletteEmpty,
+    TogglePaletteGravity,
+    ToggleLight,
+    ToggleFluidSource,
+    DrainFluid,
+    PaintMetadata,
+    EraseMetadata,
+    CycleMetadataTag,
+    PlaceProp,
+    GrabProp,
+    RotateProp,
+    NextProp,
+    RunScript,
+    SaveStamp,
+    NextStamp,
+    RotateStamp,
+    PlacePrimitive,
+    CarvePrimitive,
+    CyclePrimitiveKind,
+    UnionClipboard,
+    SubtractClipboard,
+    IntersectClipboard,
+    ToggleKeyHelp,
+    TakeScreenshot,
+    ToggleTurntable,
 }
 
 impl fmt::Display for ActionBinding {
@@ -22,6 +100,9 @@ impl fmt::Display for ActionBinding {
 pub enum AxisBinding {
     Forward,
     Lateral,
+    CameraYaw,
+    CameraPitch,
+    CameraZoom,
 }
 
 impl fmt::Display for AxisBinding {
@@ -37,3 +118,22 @@ impl BindingTypes for GameBindings {
     type Axis = AxisBinding;
     type Action = ActionBinding;
 }
+
+/// Describes the first key combination bound to `action` for the UI, e.g. "LControl+Z".
+pub fn action_key_name(
+    bindings: &Bindings<GameBindings>,
+    action: &ActionBinding,
+) -> Option<String> {
+    bindings.action_bindings(action).next().map(key_combo_name)
+}
+
+pub fn key_combo_name(combo: &[Button]) -> String {
+    combo
+        .iter()
+        .map(|button| match button {
+            Button::Key(key) => format!("{:?}", key),
+            other => format!("{:?}", other),
+        })
+        .collect::<Vec<_>>()
+        .join("+")
+}
@@ -0,0 +1,124 @@
+use crate::bindings::{ActionBinding, GameBindings};
+
+use voxel_mapper::voxel::{
+    chunk_cache_compressor::ChunkBudgetDiagnostics, chunk_cache_stats::ChunkCacheStats,
+};
+
+use amethyst::{
+    assets::{AssetStorage, Loader},
+    core::ecs::prelude::*,
+    derive::SystemDesc,
+    input::InputEvent,
+    shrev::EventChannel,
+    ui::{get_default_font, Anchor, FontAsset, LineMode, UiText, UiTransform},
+};
+
+#[derive(Default)]
+pub struct CacheStatsText;
+
+impl Component for CacheStatsText {
+    type Storage = NullStorage<Self>;
+}
+
+const STATS_TEXT_WIDTH: f32 = 400.0;
+const STATS_TEXT_HEIGHT: f32 = 150.0;
+const STATS_FONT_SIZE: f32 = 16.0;
+
+/// Creates an empty label in the top left corner for the chunk cache stats.
+pub fn make_cache_stats_ui(world: &mut World) {
+    let font = world.exec(
+        |(loader, font_storage): (ReadExpect<Loader>, Read<AssetStorage<FontAsset>>)| {
+            get_default_font(&loader, &font_storage)
+        },
+    );
+
+    let transform = UiTransform::new(
+        "cache_stats".to_string(),
+        Anchor::TopLeft,
+        Anchor::TopLeft,
+        10.0,
+        -10.0,
+        1.0,
+        STATS_TEXT_WIDTH,
+        STATS_TEXT_HEIGHT,
+    );
+    let mut text = UiText::new(font, String::new(), [1.0, 1.0, 1.0, 1.0], STATS_FONT_SIZE);
+    text.line_mode = LineMode::Wrap;
+    text.align = Anchor::TopLeft;
+
+    world
+        .create_entity()
+        .with(transform)
+        .with(text)
+        .with(CacheStatsText)
+        .build();
+}
+
+/// Toggles an overlay showing the `ChunkCacheStats` and `ChunkBudgetDiagnostics`.
+#[derive(SystemDesc)]
+#[system_desc(name(CacheStatsOverlaySystemDesc))]
+pub struct CacheStatsOverlaySystem {
+    #[system_desc(event_channel_reader)]
+    reader_id: ReaderId<InputEvent<GameBindings>>,
+    #[system_desc(skip)]
+    visible: bool,
+}
+
+impl CacheStatsOverlaySystem {
+    pub fn new(reader_id: ReaderId<InputEvent<GameBindings>>) -> Self {
+        CacheStatsOverlaySystem {
+            reader_id,
+            visible: false,
+        }
+    }
+}
+
+impl<'a> System<'a> for CacheStatsOverlaySystem {
+    type SystemData = (
+        Read<'a, EventChannel<InputEvent<GameBindings>>>,
+        Read<'a, ChunkCacheStats>,
+        Read<'a, ChunkBudgetDiagnostics>,
+        ReadStorage<'a, CacheStatsText>,
+        WriteStorage<'a, UiText>,
+    );
+
+    fn run(
+        &mut self,
+        (input_events, stats, diagnostics, is_stats_text, mut texts): Self::SystemData,
+    ) {
+        for input_event in input_events.read(&mut self.reader_id) {
+            if let InputEvent::ActionPressed(ActionBinding::ToggleCacheStats) = input_event {
+                self.visible = !self.visible;
+            }
+        }
+
+        for (_, text) in (&is_stats_text, &mut texts).join() {
+            text.text = if self.visible {
+                format_stats(&stats, &diagnostics)
+            } else {
+                String::new()
+            };
+        }
+    }
+}
+
+fn format_stats(stats: &ChunkCacheStats, diagnostics: &ChunkBudgetDiagnostics) -> String {
+    const MIB: f32 = (1 << 20) as f32;
+
+    format!(
+        "Resident chunks: {} / {} ({:.1} MiB)\n\
+         Compressed chunks: {} ({:.1} MiB)\n\
+         Compressed this frame: {}\n\
+         Decompressed this frame: {}\n\
+         Over budget: {} chunks for {} frames",
+        stats.resident_chunks,
+        diagnostics.max_resident_chunks,
+        stats.resident_bytes as f32 / MIB,
+        stats.compressed_chunks,
+        stats.compressed_bytes as f32 / MIB,
+        stats.compressed_this_frame,
+        stats.decompressed_this_frame,
+        diagnostics.chunks_over_budget,
+        diagnostics.frames_over_budget,
+    )
+}
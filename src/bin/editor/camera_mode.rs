@@ -0,0 +1,42 @@
+use crate::{
+    bindings::{ActionBinding, GameBindings},
+    control::camera::{CameraControllerComponent, CameraMode, MainCameraTag},
+};
+
+use amethyst::{core::ecs::prelude::*, derive::SystemDesc, input::InputEvent, shrev::EventChannel};
+
+/// Switches the main camera between the third-person and first-person controllers.
+#[derive(SystemDesc)]
+#[system_desc(name(CameraModeSystemDesc))]
+pub struct CameraModeSystem {
+    #[system_desc(event_channel_reader)]
+    reader_id: ReaderId<InputEvent<GameBindings>>,
+}
+
+impl CameraModeSystem {
+    pub fn new(reader_id: ReaderId<InputEvent<GameBindings>>) -> Self {
+        CameraModeSystem { reader_id }
+    }
+}
+
+impl<'a> System<'a> for CameraModeSystem {
+    type SystemData = (
+        Read<'a, EventChannel<InputEvent<GameBindings>>>,
+        ReadStorage<'a, MainCameraTag>,
+        WriteStorage<'a, CameraControllerComponent>,
+    );
+
+    fn run(&mut self, (input_events, is_main_camera, mut controllers): Self::SystemData) {
+        for input_event in input_events.read(&mut self.reader_id) {
+            if let InputEvent::ActionPressed(ActionBinding::ToggleCameraController) = input_event {
+                for (_, controller) in (&is_main_camera, &mut controllers).join() {
+                    controller.toggle_mode();
+                    match controller.mode() {
+                        CameraMode::ThirdPerson => log::info!("Third-person camera"),
+                        CameraMode::FirstPerson => log::info!("First-person camera"),
+                    }
+                }
+            }
+        }
+    }
+}
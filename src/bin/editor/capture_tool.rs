@@ -0,0 +1,136 @@
+use crate::{
+    bindings::{ActionBinding, GameBindings},
+    control::camera::{CameraControllerComponent, MainCameraTag, ThirdPersonCameraState},
+};
+
+use voxel_mapper::rendering::frame_capture::{
+    numbered_frame_path, FrameCaptureConfig, FrameCaptureRequests,
+};
+
+use amethyst::{
+    core::{ecs::prelude::*, math::Point3},
+    derive::SystemDesc,
+    input::InputEvent,
+    shrev::EventChannel,
+};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+struct Turntable {
+    target: Point3<f32>,
+    radius: f32,
+    next_frame: u32,
+    dir: PathBuf,
+}
+
+/// Requests a screenshot of the next frame, or starts a turntable render that orbits the camera
+/// around its target and requests a frame at each step. Must run before the camera control, so the
+/// turntable frames are rendered from where the camera was put.
+#[derive(SystemDesc)]
+#[system_desc(name(CaptureToolSystemDesc))]
+pub struct CaptureToolSystem {
+    #[system_desc(event_channel_reader)]
+    reader_id: ReaderId<InputEvent<GameBindings>>,
+    #[system_desc(skip)]
+    turntable: Option<Turntable>,
+}
+
+impl CaptureToolSystem {
+    pub fn new(reader_id: ReaderId<InputEvent<GameBindings>>) -> Self {
+        CaptureToolSystem {
+            reader_id,
+            turntable: None,
+        }
+    }
+}
+
+impl<'a> System<'a> for CaptureToolSystem {
+    type SystemData = (
+        Read<'a, EventChannel<InputEvent<GameBindings>>>,
+        Read<'a, FrameCaptureConfig>,
+        Write<'a, FrameCaptureRequests>,
+        ReadStorage<'a, MainCameraTag>,
+        WriteStorage<'a, ThirdPersonCameraState>,
+        WriteStorage<'a, CameraControllerComponent>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            input_events,
+            config,
+            mut requests,
+            is_main_camera,
+            mut tpc_states,
+            mut controllers,
+        ): Self::SystemData,
+    ) {
+        // Requests from the last frame that are still here weren't picked up by the renderer.
+        let dropped = requests.take();
+        if !dropped.is_empty() {
+            log::warn!(
+                "Frame capture isn't supported by the renderer yet, {} frame(s) weren't saved",
+                dropped.len()
+            );
+        }
+
+        let output_dir = PathBuf::from(&config.output_dir);
+        for input_event in input_events.read(&mut self.reader_id) {
+            match input_event {
+                InputEvent::ActionPressed(ActionBinding::TakeScreenshot) => {
+                    let path = output_dir.join(format!("screenshot_{}.png", unix_seconds()));
+                    log::info!("Saving a screenshot to {}", path.display());
+                    requests.request(path);
+                }
+                InputEvent::ActionPressed(ActionBinding::ToggleTurntable) => {
+                    if self.turntable.take().is_some() {
+                        log::info!("Stopped the turntable render");
+                        continue;
+                    }
+                    if let Some((_, tpc_state)) = (&is_main_camera, &tpc_states).join().next() {
+                        let dir = output_dir.join(format!("turntable_{}", unix_seconds()));
+                        log::info!("Rendering a turntable into {}", dir.display());
+                        self.turntable = Some(Turntable {
+                            target: tpc_state.target,
+                            radius: (tpc_state.actual_position - tpc_state.target).norm(),
+                            next_frame: 0,
+                            dir,
+                        });
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        let turntable = match &mut self.turntable {
+            Some(t) => t,
+            None => return,
+        };
+        let eye = config
+            .turntable
+            .eye(turntable.target, turntable.radius, turntable.next_frame);
+        for (_, tpc_state, controller) in
+            (&is_main_camera, &mut tpc_states, &mut controllers).join()
+        {
+            *tpc_state = ThirdPersonCameraState::new(eye, turntable.target);
+            controller.reset_smoothing();
+        }
+        requests.request(numbered_frame_path(
+            &turntable.dir,
+            "turntable",
+            turntable.next_frame,
+        ));
+
+        turntable.next_frame += 1;
+        if turntable.next_frame >= config.turntable.num_frames {
+            log::info!("Finished the turntable render");
+            self.turntable = None;
+        }
+    }
+}
+
+fn unix_seconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
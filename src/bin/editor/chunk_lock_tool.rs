@@ -0,0 +1,114 @@
+use crate::{
+    bindings::{ActionBinding, GameBindings},
+    control::hover_3d::ObjectsUnderCursor,
+};
+
+use voxel_mapper::voxel::{
+    chunk_lock::{chunk_min_containing_point, LockedChunkEditEvent, LockedChunks},
+    VoxelMap,
+};
+
+use amethyst::{
+    core::{ecs::prelude::*, math::Point3},
+    derive::SystemDesc,
+    input::InputEvent,
+    renderer::{debug_drawing::DebugLinesComponent, palette::Srgba},
+    shrev::EventChannel,
+};
+use building_blocks::prelude::*;
+
+#[derive(Default)]
+pub struct LockedChunkHintTag;
+
+impl Component for LockedChunkHintTag {
+    type Storage = NullStorage<Self>;
+}
+
+pub fn make_locked_chunk_hint_lines(world: &mut World) {
+    world
+        .create_entity()
+        .with(LockedChunkHintTag)
+        .with(DebugLinesComponent::new())
+        .build();
+}
+
+/// Toggles the lock on the chunk under the cursor, outlines all locked chunks, and warns when
+/// edits to locked chunks are discarded.
+#[derive(SystemDesc)]
+#[system_desc(name(ChunkLockToolSystemDesc))]
+pub struct ChunkLockToolSystem {
+    #[system_desc(event_channel_reader)]
+    input_reader_id: ReaderId<InputEvent<GameBindings>>,
+    #[system_desc(event_channel_reader)]
+    locked_edit_reader_id: ReaderId<LockedChunkEditEvent>,
+}
+
+impl ChunkLockToolSystem {
+    pub fn new(
+        input_reader_id: ReaderId<InputEvent<GameBindings>>,
+        locked_edit_reader_id: ReaderId<LockedChunkEditEvent>,
+    ) -> Self {
+        ChunkLockToolSystem {
+            input_reader_id,
+            locked_edit_reader_id,
+        }
+    }
+}
+
+impl<'a> System<'a> for ChunkLockToolSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        Read<'a, EventChannel<InputEvent<GameBindings>>>,
+        Read<'a, EventChannel<LockedChunkEditEvent>>,
+        Read<'a, ObjectsUnderCursor>,
+        ReadExpect<'a, VoxelMap>,
+        Write<'a, LockedChunks>,
+        ReadStorage<'a, LockedChunkHintTag>,
+        WriteStorage<'a, DebugLinesComponent>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            input_events,
+            locked_edit_events,
+            objects,
+            voxel_map,
+            mut locked_chunks,
+            is_hint,
+            mut debug_lines,
+        ): Self::SystemData,
+    ) {
+        let chunk_shape = voxel_map.chunk_shape();
+        for input_event in input_events.read(&mut self.input_reader_id) {
+            if let InputEvent::ActionPressed(ActionBinding::ToggleChunkLock) = input_event {
+                if let Some(v) = &objects.voxel {
+                    let chunk_min = chunk_min_containing_point(*v.point(), chunk_shape);
+                    let locked = locked_chunks.toggle(chunk_min);
+                    log::info!(
+                        "{} chunk at {:?}",
+                        if locked { "Locked" } else { "Unlocked" },
+                        chunk_min
+                    );
+                }
+            }
+        }
+
+        for event in locked_edit_events.read(&mut self.locked_edit_reader_id) {
+            log::warn!(
+                "Discarded edits to {} locked chunk(s): {:?}",
+                event.chunk_mins.len(),
+                event.chunk_mins
+            );
+        }
+
+        for (_, lines) in (&is_hint, &mut debug_lines).join() {
+            lines.clear();
+            for chunk_min in locked_chunks.iter() {
+                let box_min = Point3::from(Point3f::from(*chunk_min).0);
+                let box_max = Point3::from(Point3f::from(*chunk_min + chunk_shape).0);
+                lines.add_box(box_min, box_max, Srgba::new(1.0, 0.0, 0.0, 1.0));
+            }
+        }
+    }
+}
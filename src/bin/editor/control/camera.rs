@@ -4,17 +4,19 @@ pub mod state;
 
 mod colliding_controller;
 mod final_controller;
+mod first_person_controller;
 mod smoother;
 
 pub use self::final_controller::FinalController;
-pub use self::input::{InputConfig, InputProcessor, ProcessedInput};
+pub use self::first_person_controller::FirstPersonController;
+pub use self::input::{CameraAxes, InputConfig, InputProcessor, ProcessedInput};
 pub use self::state::ThirdPersonCameraState;
 
 use self::colliding_controller::CameraCollisionConfig;
 
 use voxel_mapper::{
     collision::VoxelBVT,
-    voxel::{chunk_cache_flusher::ChunkCacheFlusher, VoxelMap},
+    voxel::{chunk_cache_flusher::ChunkCacheFlusher, IsFloor, VoxelMap},
 };
 
 use amethyst::{
@@ -23,7 +25,7 @@ use amethyst::{
         ecs::prelude::*,
         math::Point3,
         shrev::{EventChannel, ReaderId},
-        SystemDesc, Transform,
+        SystemDesc, Time, Transform,
     },
     input::{BindingTypes, InputEvent, InputHandler},
     renderer::camera::Camera,
@@ -32,7 +34,6 @@ use amethyst::{
 };
 use building_blocks::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::marker::PhantomData;
 
 #[cfg(feature = "profiler")]
 use thread_profiler::profile_scope;
@@ -47,7 +48,10 @@ pub fn make_camera(position: Point3<f32>, target: Point3<f32>, world: &mut World
 
     let camera_state = ThirdPersonCameraState::new(position, target);
     let input_processor = InputProcessor::new(config.input);
-    let controller = CameraControllerComponent(FinalController::new(config.control));
+    let controller = CameraControllerComponent::new(
+        FinalController::new(config.control),
+        FirstPersonController::new(config.first_person),
+    );
 
     world
         .create_entity()
@@ -64,6 +68,7 @@ pub fn make_camera(position: Point3<f32>, target: Point3<f32>, world: &mut World
 pub struct CameraConfig {
     pub input: InputConfig,
     pub control: ThirdPersonControlConfig,
+    pub first_person: FirstPersonControlConfig,
 }
 
 #[derive(Default)]
@@ -83,12 +88,110 @@ pub struct ThirdPersonControlConfig {
     pub collision: CameraCollisionConfig,
 }
 
-pub struct CameraControllerComponent(pub FinalController);
+#[derive(Deserialize, Serialize)]
+pub struct FirstPersonControlConfig {
+    /// The distance from the camera feet to the eye (along the Y axis).
+    pub eye_height: f32,
+    pub smoothing_weight: f32,
+}
+
+/// Moves the camera in response to input. Every controller shares the same
+/// `ThirdPersonCameraState`, so the camera stays in place when switching between them.
+pub trait CameraController {
+    fn update<V, T>(
+        &mut self,
+        camera_state: &ThirdPersonCameraState,
+        input: &ProcessedInput,
+        voxels: &V,
+        world_scale: f32,
+        voxel_bvt: &VoxelBVT,
+    ) -> (Transform, ThirdPersonCameraState)
+    where
+        V: Get<Point3i, Item = T>,
+        T: IsEmpty + IsFloor;
+
+    /// Called when the controller becomes active, so it doesn't smooth towards a stale transform.
+    fn activate(&mut self);
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CameraMode {
+    ThirdPerson,
+    FirstPerson,
+}
+
+pub struct CameraControllerComponent {
+    third_person: FinalController,
+    first_person: FirstPersonController,
+    mode: CameraMode,
+}
 
 impl Component for CameraControllerComponent {
     type Storage = HashMapStorage<Self>;
 }
 
+impl CameraControllerComponent {
+    pub fn new(third_person: FinalController, first_person: FirstPersonController) -> Self {
+        Self {
+            third_person,
+            first_person,
+            mode: CameraMode::ThirdPerson,
+        }
+    }
+
+    pub fn mode(&self) -> CameraMode {
+        self.mode
+    }
+
+    /// Snaps the camera to its state on the next update instead of smoothing towards it, e.g. after
+    /// jumping somewhere else in the map.
+    pub fn reset_smoothing(&mut self) {
+        match self.mode {
+            CameraMode::ThirdPerson => self.third_person.activate(),
+            CameraMode::FirstPerson => self.first_person.activate(),
+        }
+    }
+
+    pub fn toggle_mode(&mut self) {
+        self.mode = match self.mode {
+            CameraMode::ThirdPerson => {
+                self.first_person.activate();
+
+                CameraMode::FirstPerson
+            }
+            CameraMode::FirstPerson => {
+                self.third_person.activate();
+
+                CameraMode::ThirdPerson
+            }
+        };
+    }
+
+    fn update<V, T>(
+        &mut self,
+        camera_state: &ThirdPersonCameraState,
+        input: &ProcessedInput,
+        voxels: &V,
+        world_scale: f32,
+        voxel_bvt: &VoxelBVT,
+    ) -> (Transform, ThirdPersonCameraState)
+    where
+        V: Get<Point3i, Item = T>,
+        T: IsEmpty + IsFloor,
+    {
+        match self.mode {
+            CameraMode::ThirdPerson => {
+                self.third_person
+                    .update(camera_state, input, voxels, world_scale, voxel_bvt)
+            }
+            CameraMode::FirstPerson => {
+                self.first_person
+                    .update(camera_state, input, voxels, world_scale, voxel_bvt)
+            }
+        }
+    }
+}
+
 #[derive(SystemData)]
 pub struct CameraControlData<'a, B>
 where
@@ -104,13 +207,14 @@ where
     cache_flusher: ReadExpect<'a, ChunkCacheFlusher>,
     voxel_bvt: ReadExpect<'a, VoxelBVT>,
     screen_dims: ReadExpect<'a, ScreenDimensions>,
+    time: Read<'a, Time>,
 }
 
 impl<B> CameraControlData<'_, B>
 where
     B: BindingTypes,
 {
-    fn update(&mut self, events: &[InputEvent<B>]) {
+    fn update(&mut self, events: &[InputEvent<B>], camera_axes: &CameraAxes<B>) {
         if let Some((ctrlr, input_proc, tpc_state, cam, cam_tfm)) = (
             &mut self.controllers,
             &mut self.input_processors,
@@ -124,6 +228,8 @@ where
             let proc_input = input_proc.process_input(
                 &self.input_handler,
                 events,
+                camera_axes,
+                self.time.delta_seconds(),
                 &tpc_state.drag_plane(),
                 &tpc_state.floor_plane(),
                 cam,
@@ -135,9 +241,13 @@ where
             let lod0_reader = map_reader.lod_view(0);
             let voxel_infos =
                 TransformMap::new(&lod0_reader, self.voxel_map.voxel_info_transform());
-            let CameraControllerComponent(ctrlr) = ctrlr;
-            let (new_cam_tfm, new_camera_state) =
-                ctrlr.update(&tpc_state, &proc_input, &voxel_infos, &self.voxel_bvt);
+            let (new_cam_tfm, new_camera_state) = ctrlr.update(
+                &tpc_state,
+                &proc_input,
+                &voxel_infos,
+                self.voxel_map.world_scale,
+                &self.voxel_bvt,
+            );
             *tpc_state = new_camera_state;
 
             // Make sure not to overwrite the global matrix.
@@ -154,11 +264,23 @@ where
     B: BindingTypes,
 {
     reader_id: ReaderId<InputEvent<B>>,
+    camera_axes: CameraAxes<B>,
 }
 
-#[derive(Default)]
-pub struct CameraControlSystemDesc<B> {
-    bindings: PhantomData<B>,
+pub struct CameraControlSystemDesc<B>
+where
+    B: BindingTypes,
+{
+    camera_axes: CameraAxes<B>,
+}
+
+impl<B> CameraControlSystemDesc<B>
+where
+    B: BindingTypes,
+{
+    pub fn new(camera_axes: CameraAxes<B>) -> Self {
+        CameraControlSystemDesc { camera_axes }
+    }
 }
 
 impl<'a, 'b, B> SystemDesc<'a, 'b, CameraControlSystem<B>> for CameraControlSystemDesc<B>
@@ -171,7 +293,10 @@ where
         let mut channel = world.write_resource::<EventChannel<InputEvent<B>>>();
         let reader_id = channel.register_reader();
 
-        CameraControlSystem { reader_id }
+        CameraControlSystem {
+            reader_id,
+            camera_axes: self.camera_axes,
+        }
     }
 }
 
@@ -190,6 +315,6 @@ where
 
         let events: Vec<_> = events.read(&mut self.reader_id).cloned().collect();
 
-        data.update(&events);
+        data.update(&events, &self.camera_axes);
     }
 }
@@ -4,8 +4,8 @@ use voxel_mapper::{
     collision::{floor_translation::translate_over_floor, VoxelBVT},
     geometry::{project_point_onto_line, upgrade_ray, Line, UP},
     voxel::{
-        search::greedy_path_with_l1_and_linear_heuristic, voxel_center, voxel_containing_point,
-        IsFloor,
+        raycast::voxel_space_ray, search::greedy_path_with_l1_and_linear_heuristic, voxel_center,
+        voxel_containing_point, IsFloor,
     },
 };
 
@@ -72,6 +72,7 @@ impl CollidingController {
         mut cam_state: ThirdPersonCameraState,
         input: &ProcessedInput,
         voxels: &V,
+        world_scale: f32,
         voxel_bvt: &VoxelBVT,
     ) -> ThirdPersonCameraState
     where
@@ -79,8 +80,13 @@ impl CollidingController {
         T: IsEmpty + IsFloor,
     {
         // Figure out the where the camera feet are.
-        cam_state.feet =
-            translate_over_floor(&cam_state.feet, &input.feet_translation, voxels, true);
+        cam_state.feet = translate_over_floor(
+            &cam_state.feet,
+            &input.feet_translation,
+            voxels,
+            world_scale,
+            true,
+        );
         // Figure out where the camera target is.
         cam_state.target = cam_state.feet + config.target_height_above_feet * Vector3::from(UP);
 
@@ -90,6 +96,7 @@ impl CollidingController {
         self.resolve_camera_collisions(
             &config.collision,
             &voxel_is_empty_fn,
+            world_scale,
             voxel_bvt,
             &mut cam_state,
         );
@@ -101,13 +108,14 @@ impl CollidingController {
         &mut self,
         config: &CameraCollisionConfig,
         voxel_is_empty_fn: &impl Fn(&Point3i) -> bool,
+        world_scale: f32,
         voxel_bvt: &VoxelBVT,
         cam_state: &mut ThirdPersonCameraState,
     ) {
         let desired_position = cam_state.get_desired_position();
 
         // Choose an empty voxel to start our search path.
-        let feet_voxel = voxel_containing_point(cam_state.feet);
+        let feet_voxel = voxel_containing_point(cam_state.feet, world_scale);
         self.set_last_empty_feet_voxel(voxel_is_empty_fn, feet_voxel);
         let empty_path_start = self.last_empty_feet_point.clone().unwrap();
 
@@ -118,6 +126,7 @@ impl CollidingController {
             cam_state.target,
             desired_position,
             voxel_is_empty_fn,
+            world_scale,
             config,
         );
         let (was_collision, camera_after_collisions) = move_ball_until_collision(
@@ -125,6 +134,7 @@ impl CollidingController {
             config.ball_radius,
             sphere_cast_start,
             desired_position,
+            world_scale,
         );
         self.colliding = was_collision;
 
@@ -138,7 +148,10 @@ impl CollidingController {
             cam_state.actual_position = camera_after_collisions;
         }
 
-        self.previous_camera_voxel = Some(voxel_containing_point(cam_state.actual_position));
+        self.previous_camera_voxel = Some(voxel_containing_point(
+            cam_state.actual_position,
+            world_scale,
+        ));
     }
 
     /// Try to find the ideal location to cast a sphere from.
@@ -148,6 +161,7 @@ impl CollidingController {
         target: Point3<f32>,
         camera: Point3<f32>,
         voxel_is_empty_fn: &impl Fn(&Point3i) -> bool,
+        world_scale: f32,
         config: &CameraCollisionConfig,
     ) -> Point3<f32> {
         // If we want to be close to the camera, there's not much use in finding a path around
@@ -163,7 +177,7 @@ impl CollidingController {
 
         // Graph search away from the target to get as close to the camera as possible. It's OK if
         // we don't reach the camera, since we'll still return the path that got closest.
-        let path_finish = voxel_containing_point(camera);
+        let path_finish = voxel_containing_point(camera, world_scale);
         let (_reached_finish, path) = greedy_path_with_l1_and_linear_heuristic(
             *path_start,
             path_finish,
@@ -172,13 +186,14 @@ impl CollidingController {
         );
 
         let unobstructed_ranges =
-            find_unobstructed_ranges(&path, &eye_ray, voxel_is_empty_fn, config);
+            find_unobstructed_ranges(&path, &eye_ray, voxel_is_empty_fn, world_scale, config);
 
         self.find_start_of_sphere_cast_in_ranges(
             &unobstructed_ranges,
             &path,
             &eye_ray,
             voxel_is_empty_fn,
+            world_scale,
             config,
         )
         .unwrap_or(target)
@@ -192,6 +207,7 @@ impl CollidingController {
         path: &[Point3i],
         eye_line: &Line,
         voxel_is_empty_fn: &impl Fn(&Point3i) -> bool,
+        world_scale: f32,
         config: &CameraCollisionConfig,
     ) -> Option<Point3<f32>> {
         let mut best_point = None;
@@ -237,7 +253,7 @@ impl CollidingController {
             if closeness < best_point_closeness {
                 best_point_closeness = closeness;
                 best_point = Some(project_point_onto_line(
-                    &voxel_center(point_in_range),
+                    &voxel_center(point_in_range, world_scale),
                     eye_line,
                 ));
             }
@@ -309,6 +325,7 @@ fn find_unobstructed_ranges(
     path: &[Point3i],
     eye_line: &Line,
     voxel_is_empty_fn: &impl Fn(&Point3i) -> bool,
+    world_scale: f32,
     config: &CameraCollisionConfig,
 ) -> Vec<([usize; 2], [f32; 2])> {
     let mut unobstructed_ranges = Vec::new();
@@ -329,10 +346,10 @@ fn find_unobstructed_ranges(
         };
 
     for (i, &p) in path.iter().enumerate() {
-        let p_center = voxel_center(p);
+        let p_center = voxel_center(p, world_scale);
         let p_proj = project_point_onto_line(&p_center, &eye_line);
 
-        if point_is_obstructed(p, p_center, p_proj, voxel_is_empty_fn, config) {
+        if point_is_obstructed(p, p_center, p_proj, voxel_is_empty_fn, world_scale, config) {
             try_add_range(i, p_proj, &mut current_range_start);
         } else if let None = current_range_start {
             // We're no longer obstructed, so start a new range.
@@ -344,7 +361,7 @@ fn find_unobstructed_ranges(
     if let Some(&p) = path.last() {
         try_add_range(
             path.len(),
-            project_point_onto_line(&voxel_center(p), &eye_line),
+            project_point_onto_line(&voxel_center(p, world_scale), &eye_line),
             &mut current_range_start,
         );
     }
@@ -360,13 +377,14 @@ fn point_is_obstructed(
     p_float: Point3<f32>,
     p_proj: Point3<f32>,
     voxel_is_empty_fn: &impl Fn(&Point3i) -> bool,
+    world_scale: f32,
     config: &CameraCollisionConfig,
 ) -> bool {
     let p_rej = p_float - p_proj;
     if p_rej.norm_squared() < config.min_obstruction_width.powi(2) {
         return false;
     } else {
-        let voxel_p_proj = voxel_containing_point(p_proj);
+        let voxel_p_proj = voxel_containing_point(p_proj, world_scale);
         if voxel_is_empty_fn(&voxel_p_proj) {
             // Projection must still be path-connected to empty space.
             let (connected, _) = greedy_path_with_l1_heuristic(
@@ -387,8 +405,11 @@ fn move_ball_until_collision(
     ball_radius: f32,
     start: Point3<f32>,
     end: Point3<f32>,
+    world_scale: f32,
 ) -> (bool, Point3<f32>) {
-    let ray = Ray::new(start, end - start);
+    // Cast in voxel units, since that's what the BVT is in.
+    let ray = voxel_space_ray(&Ray::new(start, end - start), world_scale);
+    let ball_radius = ball_radius / world_scale;
     let max_toi = 1.0;
 
     if let Some(impact) =
@@ -442,7 +463,8 @@ mod tests {
             path.push([i, 0, 0].into());
         }
 
-        let ranges = find_unobstructed_ranges(&path, &eye_line, &voxel_is_empty_fn, &TEST_CONFIG);
+        let ranges =
+            find_unobstructed_ranges(&path, &eye_line, &voxel_is_empty_fn, 1.0, &TEST_CONFIG);
 
         assert_eq!(ranges, vec![([0, 10], [0.0, 9.5])]);
     }
@@ -465,7 +487,8 @@ mod tests {
             greedy_path_with_l1_and_linear_heuristic(&start, &finish, &voxel_is_empty_fn, 300);
         assert!(reached_finish);
 
-        let ranges = find_unobstructed_ranges(&path, &eye_line, &voxel_is_empty_fn, &TEST_CONFIG);
+        let ranges =
+            find_unobstructed_ranges(&path, &eye_line, &voxel_is_empty_fn, 1.0, &TEST_CONFIG);
 
         println!("ranges = {:?}", ranges);
 
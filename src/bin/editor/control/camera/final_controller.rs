@@ -1,6 +1,6 @@
 use super::{
     colliding_controller::CollidingController, input::ProcessedInput, smoother::TransformSmoother,
-    ThirdPersonCameraState, ThirdPersonControlConfig,
+    CameraController, ThirdPersonCameraState, ThirdPersonControlConfig,
 };
 
 use voxel_mapper::{collision::VoxelBVT, voxel::IsFloor};
@@ -24,12 +24,15 @@ impl FinalController {
             smoother,
         }
     }
+}
 
-    pub fn update<V, T>(
+impl CameraController for FinalController {
+    fn update<V, T>(
         &mut self,
         camera_state: &ThirdPersonCameraState,
         input: &ProcessedInput,
         voxels: &V,
+        world_scale: f32,
         voxel_bvt: &VoxelBVT,
     ) -> (Transform, ThirdPersonCameraState)
     where
@@ -41,10 +44,15 @@ impl FinalController {
             *camera_state,
             input,
             voxels,
+            world_scale,
             voxel_bvt,
         );
         let smooth_tfm = self.smoother.smooth_transform(&new_camera_state);
 
         (smooth_tfm, new_camera_state)
     }
+
+    fn activate(&mut self) {
+        self.smoother.reset();
+    }
 }
@@ -0,0 +1,71 @@
+use super::{
+    input::ProcessedInput, smoother::TransformSmoother, CameraController, FirstPersonControlConfig,
+    ThirdPersonCameraState,
+};
+
+use voxel_mapper::{
+    collision::{floor_translation::translate_over_floor, VoxelBVT},
+    geometry::UP,
+    voxel::IsFloor,
+};
+
+use amethyst::core::{math::Vector3, Transform};
+use building_blocks::prelude::*;
+
+/// Puts the eye directly above the feet, looking along the (negated) eye vector. Unlike the
+/// `FinalController`, there's no target to orbit, so the camera can't be occluded and doesn't need
+/// any collision handling besides walking over the floor.
+pub struct FirstPersonController {
+    config: FirstPersonControlConfig,
+    smoother: TransformSmoother,
+}
+
+impl FirstPersonController {
+    pub fn new(config: FirstPersonControlConfig) -> Self {
+        let smoother = TransformSmoother::new(config.smoothing_weight);
+
+        FirstPersonController { config, smoother }
+    }
+}
+
+impl CameraController for FirstPersonController {
+    fn update<V, T>(
+        &mut self,
+        camera_state: &ThirdPersonCameraState,
+        input: &ProcessedInput,
+        voxels: &V,
+        world_scale: f32,
+        _voxel_bvt: &VoxelBVT,
+    ) -> (Transform, ThirdPersonCameraState)
+    where
+        V: Get<Point3i, Item = T>,
+        T: IsEmpty + IsFloor,
+    {
+        let mut new_camera_state = *camera_state;
+        new_camera_state.feet = translate_over_floor(
+            &camera_state.feet,
+            &input.feet_translation,
+            voxels,
+            world_scale,
+            true,
+        );
+        new_camera_state.add_yaw(input.delta_yaw);
+        new_camera_state.add_pitch(input.delta_pitch);
+
+        // The zoom radius is left alone, so it's still there when switching back to third person.
+        new_camera_state.actual_position =
+            new_camera_state.feet + self.config.eye_height * Vector3::from(UP);
+        // Keep the target one unit in front of the eye, so the smoother can recover the look
+        // direction from it.
+        new_camera_state.target =
+            new_camera_state.actual_position - new_camera_state.eye_vec.unit_vector();
+
+        let smooth_tfm = self.smoother.smooth_transform(&new_camera_state);
+
+        (smooth_tfm, new_camera_state)
+    }
+
+    fn activate(&mut self) {
+        self.smoother.reset();
+    }
+}
@@ -20,6 +20,25 @@ pub struct InputConfig {
     pub rotate_sensitivity_x: f32,
     pub rotate_sensitivity_y: f32,
     pub zoom_sensitivity: f32,
+    /// How fast the movement axes translate the camera feet, in voxels per second.
+    pub move_speed: f32,
+    /// Radians per second at full deflection of the yaw and pitch axes.
+    pub stick_rotate_speed: f32,
+    /// Fraction of the camera radius per second at full deflection of the zoom axis.
+    pub stick_zoom_speed: f32,
+    /// Analog sticks report small values when they're at rest, so axis values below this magnitude
+    /// are ignored. Values above it are rescaled to start from 0.
+    pub stick_dead_zone: f32,
+}
+
+/// The axes that move and rotate the camera, besides the mouse. The forward and lateral axes move
+/// the camera feet relative to where the camera is looking.
+pub struct CameraAxes<B: BindingTypes> {
+    pub forward: B::Axis,
+    pub lateral: B::Axis,
+    pub yaw: B::Axis,
+    pub pitch: B::Axis,
+    pub zoom: B::Axis,
 }
 
 #[derive(Debug)]
@@ -47,6 +66,15 @@ impl InputProcessor {
         }
     }
 
+    pub fn config(&self) -> &InputConfig {
+        &self.config
+    }
+
+    /// For tweaking the camera while the editor is running, e.g. from the editor panel.
+    pub fn config_mut(&mut self) -> &mut InputConfig {
+        &mut self.config
+    }
+
     fn get_camera_radius_scalar_from_mouse_wheel_events<B>(
         &mut self,
         events: &[InputEvent<B>],
@@ -70,10 +98,13 @@ impl InputProcessor {
         radius_scalar
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn process_input<B>(
         &mut self,
         input: &InputHandler<B>,
         events: &[InputEvent<B>],
+        camera_axes: &CameraAxes<B>,
+        delta_seconds: f32,
         drag_plane: &Plane,
         floor_plane: &Plane,
         camera: &Camera,
@@ -83,7 +114,7 @@ impl InputProcessor {
     where
         B: BindingTypes,
     {
-        let radius_scalar = self.get_camera_radius_scalar_from_mouse_wheel_events(&events);
+        let mut radius_scalar = self.get_camera_radius_scalar_from_mouse_wheel_events(&events);
 
         let mut delta_yaw = 0.0;
         let mut delta_pitch = 0.0;
@@ -113,6 +144,27 @@ impl InputProcessor {
             self.prev_cursor_pos = cursor_pos;
         }
 
+        let axis_value = |axis: &B::Axis| input.axis_value(axis).unwrap_or(0.0);
+        let dead_zone = self.config.stick_dead_zone;
+
+        let mut movement = [
+            axis_value(&camera_axes.forward),
+            axis_value(&camera_axes.lateral),
+        ];
+        radial_dead_zone(&mut movement, dead_zone);
+        feet_translation += self.config.move_speed
+            * delta_seconds
+            * axis_translation(movement[0], movement[1], camera_tfm);
+
+        let mut rotation = [axis_value(&camera_axes.yaw), axis_value(&camera_axes.pitch)];
+        radial_dead_zone(&mut rotation, dead_zone);
+        delta_yaw -= rotation[0] * self.config.stick_rotate_speed * delta_seconds;
+        delta_pitch += rotation[1] * self.config.stick_rotate_speed * delta_seconds;
+
+        let mut zoom = [axis_value(&camera_axes.zoom)];
+        radial_dead_zone(&mut zoom, dead_zone);
+        radius_scalar *= 1.0 - zoom[0] * self.config.stick_zoom_speed * delta_seconds;
+
         ProcessedInput {
             radius_scalar,
             delta_yaw,
@@ -122,6 +174,39 @@ impl InputProcessor {
     }
 }
 
+/// Zeroes out `values` when their combined magnitude is within `dead_zone`, and otherwise rescales
+/// them so the magnitude ramps up smoothly from the edge of the dead zone to 1.
+fn radial_dead_zone(values: &mut [f32], dead_zone: f32) {
+    let magnitude = values.iter().map(|v| v * v).sum::<f32>().sqrt();
+    let scale = if magnitude <= dead_zone {
+        0.0
+    } else {
+        ((magnitude - dead_zone) / (1.0 - dead_zone)).min(1.0) / magnitude
+    };
+    for v in values.iter_mut() {
+        *v *= scale;
+    }
+}
+
+/// Converts the movement axis values into a direction in the XZ (floor) plane, relative to the
+/// camera yaw. The length is at most 1, so moving diagonally isn't any faster.
+fn axis_translation(forward: f32, lateral: f32, camera_tfm: &Transform) -> Vector3<f32> {
+    let look = camera_tfm.rotation() * -Vector3::z();
+    let forward_dir = Vector3::new(look.x, 0.0, look.z);
+    if forward_dir.norm_squared() < std::f32::EPSILON {
+        return Vector3::zeros();
+    }
+    let forward_dir = forward_dir.normalize();
+    let right_dir = Vector3::new(-forward_dir.z, 0.0, forward_dir.x);
+
+    let translation = forward * forward_dir + lateral * right_dir;
+    if translation.norm_squared() > 1.0 {
+        translation.normalize()
+    } else {
+        translation
+    }
+}
+
 fn floor_drag_translation(
     drag_plane: &Plane,
     floor_plane: &Plane,
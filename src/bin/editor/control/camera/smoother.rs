@@ -18,6 +18,11 @@ impl TransformSmoother {
         }
     }
 
+    /// The next transform will be exactly the new state, without smoothing.
+    pub fn reset(&mut self) {
+        self.lerp_state = None;
+    }
+
     pub fn smooth_transform(&mut self, new_state: &ThirdPersonCameraState) -> Transform {
         let old_lerp_state = self.lerp_state.unwrap_or(*new_state);
 
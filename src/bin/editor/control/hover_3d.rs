@@ -2,7 +2,10 @@ use crate::control::camera::data::CameraData;
 
 use voxel_mapper::{
     collision::VoxelBVT,
-    geometry::{line_plane_intersection, upgrade_ray, Line, LinePlaneIntersection, Plane},
+    geometry::{
+        line_plane_intersection, upgrade_ray, upgrade_vector, Line, LinePlaneIntersection, Plane,
+    },
+    voxel::{raycast::voxel_space_ray, VoxelMap},
 };
 
 use amethyst::{
@@ -11,7 +14,10 @@ use amethyst::{
 };
 use building_blocks::{
     prelude::*,
-    search::collision::{cast_ray_at_voxels, VoxelRayImpact},
+    search::{
+        collision::{cast_ray_at_voxels, VoxelRayImpact},
+        ncollide3d::{query::RayIntersection, shape::FeatureId},
+    },
 };
 use ncollide3d::query::Ray;
 use std::marker::PhantomData;
@@ -49,6 +55,10 @@ impl HoverVoxel {
     }
 }
 
+/// How far to step through the voxel map for the hovered voxel when the BVT misses, e.g. because
+/// the chunks under the cursor were just streamed in and haven't been processed yet.
+const MAX_MAP_RAYCAST_TOI: f32 = 256.0;
+
 #[derive(Default)]
 pub struct ObjectsUnderCursor {
     // A point on the XZ plane.
@@ -65,11 +75,15 @@ where
     type SystemData = (
         Write<'a, ObjectsUnderCursor>,
         ReadExpect<'a, VoxelBVT>,
+        ReadExpect<'a, VoxelMap>,
         Read<'a, InputHandler<B>>,
         CameraData<'a>,
     );
 
-    fn run(&mut self, (mut objects, voxel_bvt, input_handler, raycast_data): Self::SystemData) {
+    fn run(
+        &mut self,
+        (mut objects, voxel_bvt, voxel_map, input_handler, raycast_data): Self::SystemData,
+    ) {
         #[cfg(feature = "profiler")]
         profile_scope!("hover_object");
 
@@ -84,7 +98,22 @@ where
 
         // Check for intersection with a voxel.
         let max_toi = std::f32::MAX;
-        let voxel_impact = cast_ray_at_voxels(&*voxel_bvt, upgrade_ray(ray), max_toi, |_| true);
+        // The BVT is in voxel units, but the time of impact is the same along the world ray.
+        let voxel_ray = upgrade_ray(voxel_space_ray(&ray, voxel_map.world_scale));
+        let voxel_impact =
+            cast_ray_at_voxels(&*voxel_bvt, voxel_ray, max_toi, |_| true).or_else(|| {
+                let hit = voxel_map.cast_ray(&ray, MAX_MAP_RAYCAST_TOI)?;
+                let normal = na::Vector3::from(Point3f::from(hit.normal).0);
+
+                Some(VoxelRayImpact {
+                    point: hit.point,
+                    impact: RayIntersection::new(
+                        hit.toi,
+                        upgrade_vector(normal),
+                        FeatureId::Unknown,
+                    ),
+                })
+            });
         objects.voxel = voxel_impact.map(|impact| HoverVoxel { impact, ray });
 
         // Check for intersection with the XZ plane.
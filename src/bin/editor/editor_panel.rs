@@ -0,0 +1,184 @@
+use crate::{
+    control::camera::{InputProcessor, MainCameraTag},
+    hotbar::Hotbar,
+    voxel_brush::PaintBrush,
+};
+
+use voxel_mapper::voxel::{
+    chunk_processor::MeshMode, edit_limits::EditLimits, VoxelMap, VoxelType,
+};
+
+use amethyst::{
+    core::ecs::prelude::*,
+    derive::SystemDesc,
+    shrev::EventChannel,
+    ui::{UiCreator, UiEvent, UiEventType, UiText, UiTransform},
+};
+
+/// Loads the editor panel prefab. The panel's widgets are identified by the ids of their
+/// `UiTransform`s.
+pub fn make_editor_panel(world: &mut World) {
+    world.exec(|mut creator: UiCreator<'_>| creator.create("ui/editor_panel.ron", ()));
+}
+
+/// Multiplies the camera speed or sensitivity on each click.
+const CAMERA_STEP: f32 = 1.25;
+
+/// Applies clicks on the editor panel's buttons to the `PaintBrush`, `MeshMode` and camera
+/// `InputProcessor`, and shows their current values. The panel has no state of its own, so changes
+/// made with the key bindings show up too.
+#[derive(SystemDesc)]
+#[system_desc(name(EditorPanelSystemDesc))]
+pub struct EditorPanelSystem {
+    #[system_desc(event_channel_reader)]
+    reader_id: ReaderId<UiEvent>,
+}
+
+impl EditorPanelSystem {
+    pub fn new(reader_id: ReaderId<UiEvent>) -> Self {
+        EditorPanelSystem { reader_id }
+    }
+}
+
+impl<'a> System<'a> for EditorPanelSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        Read<'a, EventChannel<UiEvent>>,
+        Read<'a, EditLimits>,
+        ReadExpect<'a, VoxelMap>,
+        WriteExpect<'a, PaintBrush>,
+        WriteExpect<'a, Hotbar>,
+        WriteExpect<'a, MeshMode>,
+        ReadStorage<'a, MainCameraTag>,
+        WriteStorage<'a, InputProcessor>,
+        ReadStorage<'a, UiTransform>,
+        WriteStorage<'a, UiText>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            ui_events,
+            edit_limits,
+            voxel_map,
+            mut brush,
+            mut hotbar,
+            mut mesh_mode,
+            main_camera_tags,
+            mut input_processors,
+            transforms,
+            mut texts,
+        ): Self::SystemData,
+    ) {
+        let mut camera_input = (&main_camera_tags, &mut input_processors)
+            .join()
+            .next()
+            .map(|(_, p)| p);
+
+        for event in ui_events.read(&mut self.reader_id) {
+            if event.event_type != UiEventType::Click {
+                continue;
+            }
+            let id = match transforms.get(event.target) {
+                Some(t) => t.id.as_str(),
+                None => continue,
+            };
+            match id {
+                "radius_decrease" => brush.radius = (brush.radius - 1).max(1),
+                "radius_increase" => {
+                    brush.radius = (brush.radius + 1).min(edit_limits.max_brush_radius)
+                }
+                "shape_next" => brush.mode = brush.mode.next(),
+                "material_decrease" | "material_increase" => {
+                    let num_types = voxel_map.palette.infos.len();
+                    if num_types == 0 {
+                        continue;
+                    }
+                    let step = if id == "material_increase" {
+                        1
+                    } else {
+                        num_types - 1
+                    };
+                    let voxel_type =
+                        VoxelType(((brush.voxel_type.0 as usize + step) % num_types) as u8);
+                    brush.voxel_type = voxel_type;
+                    // Keep the hotbar highlight in sync when the type is in one of its slots.
+                    if let Some(slot) = hotbar
+                        .config
+                        .slots
+                        .iter()
+                        .position(|s| s.voxel_type == voxel_type)
+                    {
+                        hotbar.selected = slot;
+                    }
+                }
+                "mesh_mode_next" => {
+                    *mesh_mode = match *mesh_mode {
+                        MeshMode::SurfaceNets => MeshMode::GreedyQuads,
+                        MeshMode::GreedyQuads => MeshMode::SurfaceNets,
+                    };
+                }
+                "camera_speed_decrease" | "camera_speed_increase" => {
+                    if let Some(input) = camera_input.as_mut() {
+                        let config = input.config_mut();
+                        config.move_speed *= camera_step(id);
+                    }
+                }
+                "camera_sensitivity_decrease" | "camera_sensitivity_increase" => {
+                    if let Some(input) = camera_input.as_mut() {
+                        let config = input.config_mut();
+                        config.rotate_sensitivity_x *= camera_step(id);
+                        config.rotate_sensitivity_y *= camera_step(id);
+                    }
+                }
+                _ => continue,
+            }
+        }
+
+        let material = hotbar
+            .config
+            .slots
+            .iter()
+            .find(|s| s.voxel_type == brush.voxel_type)
+            .map_or_else(
+                || format!("Type {}", brush.voxel_type.0),
+                |s| s.name.clone(),
+            );
+        let mesh_mode = match *mesh_mode {
+            MeshMode::SurfaceNets => "Surface nets",
+            MeshMode::GreedyQuads => "Greedy quads",
+        };
+        let (camera_speed, camera_sensitivity) = camera_input.map_or_else(
+            || (String::new(), String::new()),
+            |input| {
+                (
+                    format!("{:.1}", input.config().move_speed),
+                    format!("{:.4}", input.config().rotate_sensitivity_x),
+                )
+            },
+        );
+
+        for (transform, text) in (&transforms, &mut texts).join() {
+            let value = match transform.id.as_str() {
+                "radius_value" => brush.radius.to_string(),
+                "shape_value" => format!("{:?}", brush.mode),
+                "material_value" => material.clone(),
+                "mesh_mode_value" => mesh_mode.to_string(),
+                "camera_speed_value" => camera_speed.clone(),
+                "camera_sensitivity_value" => camera_sensitivity.clone(),
+                _ => continue,
+            };
+            if text.text != value {
+                text.text = value;
+            }
+        }
+    }
+}
+
+fn camera_step(button_id: &str) -> f32 {
+    if button_id.ends_with("_increase") {
+        CAMERA_STEP
+    } else {
+        1.0 / CAMERA_STEP
+    }
+}
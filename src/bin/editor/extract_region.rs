@@ -0,0 +1,56 @@
+use voxel_mapper::voxel::{
+    map_file::{load_voxel_map, snapshot_chunks, write_new_map_file, write_voxels_file},
+    region_extract::extract_region,
+};
+
+use building_blocks::prelude::*;
+use std::path::Path;
+
+/// Writes the voxels of `map_file` in the extent at `min` with `shape` to a new map at `output`,
+/// with its voxels next to it in a ".bin" file. With `move_to_origin`, the extent's minimum is
+/// moved to (0, 0, 0).
+pub fn extract_region_file(
+    map_file: &Path,
+    min: [i32; 3],
+    shape: [i32; 3],
+    output: &Path,
+    move_to_origin: bool,
+) -> amethyst::Result<()> {
+    let extent = Extent3i::from_min_and_shape(PointN(min), PointN(shape));
+    let map = load_voxel_map(map_file)
+        .map_err(|e| amethyst::Error::from_string(format!("Failed to load map: {:?}", e)))?;
+
+    let offset = if move_to_origin {
+        PointN([0; 3]) - extent.minimum
+    } else {
+        PointN([0; 3])
+    };
+    let (region_map, remap) = extract_region(&map, &extent, offset);
+
+    let voxels_path = output.with_extension("bin");
+    write_voxels_file(
+        &voxels_path,
+        region_map.chunk_shape(),
+        snapshot_chunks(&region_map),
+    )
+    .map_err(|e| amethyst::Error::from_string(format!("Failed to save voxels: {:?}", e)))?;
+    write_new_map_file(output, &region_map, &voxels_path.to_string_lossy())?;
+
+    println!(
+        "Wrote {:?} to {} and {}",
+        extent,
+        output.display(),
+        voxels_path.display()
+    );
+    if !remap.is_identity() {
+        println!("Renumbered voxel types:");
+        for (old_type, new_type) in remap.iter_changed() {
+            match new_type {
+                Some(new_type) => println!("{} -> {}", old_type.0, new_type.0),
+                None => println!("{} removed", old_type.0),
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,161 @@
+use amethyst::{
+    config::{Config, ConfigError},
+    utils::application_dir,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{self, Cursor, Read};
+use std::path::Path;
+
+/// Where to download the array material bundle from. The bundle is a zip file with a top-level
+/// "array_materials" directory.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct AssetSourcesConfig {
+    /// Mirrors to try in order until one succeeds.
+    pub urls: Vec<String>,
+    /// Hex-encoded SHA-256 of the bundle. If missing, fetching fails unless unverified bundles are
+    /// allowed, and the checksum of the downloaded file is logged so it can be pinned.
+    pub sha256: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum FetchAssetsError {
+    ConfigError(ConfigError),
+    /// None of the mirrors could be downloaded. Contains the error for each URL.
+    DownloadFailed(Vec<(String, String)>),
+    ChecksumMismatch {
+        expected: String,
+        actual: String,
+    },
+    /// No checksum is configured, and unverified bundles aren't allowed.
+    ChecksumNotPinned {
+        actual: String,
+    },
+    ZipError(zip::result::ZipError),
+    IoError(io::Error),
+}
+
+impl From<ConfigError> for FetchAssetsError {
+    fn from(other: ConfigError) -> Self {
+        FetchAssetsError::ConfigError(other)
+    }
+}
+
+impl From<zip::result::ZipError> for FetchAssetsError {
+    fn from(other: zip::result::ZipError) -> Self {
+        FetchAssetsError::ZipError(other)
+    }
+}
+
+impl From<io::Error> for FetchAssetsError {
+    fn from(other: io::Error) -> Self {
+        FetchAssetsError::IoError(other)
+    }
+}
+
+/// Downloads the array material bundle described by "assets/config/asset_sources.ron", verifies
+/// it, and unpacks it into "assets/array_materials". A bundle can only be unpacked without a
+/// configured checksum if `allow_unverified` is set.
+pub fn fetch_assets(allow_unverified: bool) -> Result<(), FetchAssetsError> {
+    let assets_dir = application_dir("assets")?;
+    let config = AssetSourcesConfig::load(assets_dir.join("config").join("asset_sources.ron"))?;
+
+    let bundle = download_first_available(&config.urls)?;
+
+    let actual = sha256_hex(&bundle);
+    match &config.sha256 {
+        Some(expected) if !expected.eq_ignore_ascii_case(&actual) => {
+            return Err(FetchAssetsError::ChecksumMismatch {
+                expected: expected.clone(),
+                actual,
+            });
+        }
+        Some(_) => log::info!("Verified checksum {}", actual),
+        None if allow_unverified => log::warn!(
+            "No checksum configured, skipping verification. Downloaded bundle has SHA-256 {}",
+            actual
+        ),
+        None => return Err(FetchAssetsError::ChecksumNotPinned { actual }),
+    }
+
+    unpack_bundle(bundle, &assets_dir)?;
+    log::info!(
+        "Unpacked array materials into {:?}",
+        assets_dir.join("array_materials")
+    );
+
+    Ok(())
+}
+
+fn download_first_available(urls: &[String]) -> Result<Vec<u8>, FetchAssetsError> {
+    let mut failures = Vec::new();
+    for url in urls.iter() {
+        log::info!("Downloading {}", url);
+        match download(url) {
+            Ok(bytes) => return Ok(bytes),
+            Err(e) => {
+                log::warn!("Failed to download {}: {}", url, e);
+                failures.push((url.clone(), e));
+            }
+        }
+    }
+
+    Err(FetchAssetsError::DownloadFailed(failures))
+}
+
+fn download(url: &str) -> Result<Vec<u8>, String> {
+    let response = ureq::get(url).call().map_err(|e| e.to_string())?;
+
+    // Google Drive answers with a virus scan warning page instead of files too large to scan. Like
+    // "run/google_drive.py", take the confirm token from the warning's cookie and ask again.
+    let response = match drive_confirm_cookie(&response) {
+        Some((cookie, token)) => ureq::get(&format!("{}&confirm={}", url, token))
+            .set("Cookie", &cookie)
+            .call()
+            .map_err(|e| e.to_string())?,
+        None => response,
+    };
+
+    let mut bytes = Vec::new();
+    response
+        .into_reader()
+        .read_to_end(&mut bytes)
+        .map_err(|e| e.to_string())?;
+
+    Ok(bytes)
+}
+
+/// Returns the "download_warning" cookie and its value, the confirm token.
+fn drive_confirm_cookie(response: &ureq::Response) -> Option<(String, String)> {
+    response.all("set-cookie").into_iter().find_map(|header| {
+        let cookie = header.split(';').next()?.trim();
+        let mut name_value = cookie.splitn(2, '=');
+        let name = name_value.next()?;
+        let value = name_value.next()?;
+        if name.starts_with("download_warning") {
+            Some((cookie.to_string(), value.to_string()))
+        } else {
+            None
+        }
+    })
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+fn unpack_bundle(bundle: Vec<u8>, assets_dir: &Path) -> Result<(), FetchAssetsError> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(bundle))?;
+    if !archive
+        .file_names()
+        .any(|name| name.starts_with("array_materials/"))
+    {
+        return Err(FetchAssetsError::IoError(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Bundle does not contain an array_materials directory",
+        )));
+    }
+    archive.extract(assets_dir)?;
+
+    Ok(())
+}
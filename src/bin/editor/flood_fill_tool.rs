@@ -0,0 +1,85 @@
+use crate::{
+    bindings::{ActionBinding, GameBindings},
+    control::hover_3d::ObjectsUnderCursor,
+    selection::Selection,
+    voxel_brush::PaintBrush,
+};
+
+use voxel_mapper::voxel::{
+    chunk_cache_flusher::ChunkCacheFlusher, double_buffer::EditedChunksBackBuffer,
+    flood_fill::flood_fill_voxel_type, VoxelMap,
+};
+
+use amethyst::{core::ecs::prelude::*, derive::SystemDesc, input::InputEvent, shrev::EventChannel};
+use building_blocks::prelude::*;
+
+/// Without a selection, the fill doesn't spread farther than this from the hovered voxel.
+const MAX_FILL_RADIUS: i32 = 32;
+
+/// Repaints the connected solid voxels that have the same type as the hovered voxel with the brush
+/// type. The fill stays inside of the selection, if there is one.
+#[derive(SystemDesc)]
+#[system_desc(name(FloodFillToolSystemDesc))]
+pub struct FloodFillToolSystem {
+    #[system_desc(event_channel_reader)]
+    reader_id: ReaderId<InputEvent<GameBindings>>,
+}
+
+impl FloodFillToolSystem {
+    pub fn new(reader_id: ReaderId<InputEvent<GameBindings>>) -> Self {
+        FloodFillToolSystem { reader_id }
+    }
+}
+
+impl<'a> System<'a> for FloodFillToolSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        Read<'a, EventChannel<InputEvent<GameBindings>>>,
+        Read<'a, ObjectsUnderCursor>,
+        Read<'a, Selection>,
+        ReadExpect<'a, VoxelMap>,
+        ReadExpect<'a, ChunkCacheFlusher>,
+        ReadExpect<'a, PaintBrush>,
+        WriteExpect<'a, EditedChunksBackBuffer>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            input_events,
+            objects,
+            selection,
+            voxel_map,
+            cache_flusher,
+            brush,
+            mut voxel_backbuffer,
+        ): Self::SystemData,
+    ) {
+        let local_cache = LocalChunkCache3::new();
+        let map_reader = voxel_map.voxels.reader(&local_cache);
+
+        for input_event in input_events.read(&mut self.reader_id) {
+            if let InputEvent::ActionPressed(ActionBinding::FloodFillMaterial) = input_event {
+                if let Some(v) = &objects.voxel {
+                    let start = *v.point();
+                    let bounds = selection.extent.unwrap_or_else(|| {
+                        Extent3i::from_min_and_shape(
+                            start - PointN([MAX_FILL_RADIUS; 3]),
+                            PointN([2 * MAX_FILL_RADIUS + 1; 3]),
+                        )
+                    });
+                    let num_filled = flood_fill_voxel_type(
+                        &map_reader,
+                        start,
+                        &bounds,
+                        brush.voxel_type,
+                        &mut *voxel_backbuffer,
+                    );
+                    log::info!("Flood filled {} voxels", num_filled);
+                }
+            }
+        }
+
+        cache_flusher.flush(local_cache);
+    }
+}
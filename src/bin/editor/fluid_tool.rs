@@ -0,0 +1,107 @@
+use crate::{
+    bindings::{ActionBinding, GameBindings},
+    control::hover_3d::ObjectsUnderCursor,
+};
+
+use voxel_mapper::voxel::{
+    fluid::{FluidField, FluidSources},
+    voxel_center, VoxelMap,
+};
+
+use amethyst::{
+    core::{ecs::prelude::*, math::Vector3},
+    derive::SystemDesc,
+    input::InputEvent,
+    renderer::{debug_drawing::DebugLinesComponent, palette::Srgba},
+    shrev::EventChannel,
+};
+
+#[derive(Default)]
+pub struct FluidSourceHintTag;
+
+impl Component for FluidSourceHintTag {
+    type Storage = NullStorage<Self>;
+}
+
+pub fn make_fluid_source_hint_lines(world: &mut World) {
+    world
+        .create_entity()
+        .with(FluidSourceHintTag)
+        .with(DebugLinesComponent::new())
+        .build();
+}
+
+/// Adds a fluid source in the empty voxel next to the hovered surface, or removes the source
+/// that's already there, and draws a small box at every source. Also drains all of the fluid, so
+/// it can flow again from the current sources.
+#[derive(SystemDesc)]
+#[system_desc(name(FluidToolSystemDesc))]
+pub struct FluidToolSystem {
+    #[system_desc(event_channel_reader)]
+    reader_id: ReaderId<InputEvent<GameBindings>>,
+}
+
+impl FluidToolSystem {
+    pub fn new(reader_id: ReaderId<InputEvent<GameBindings>>) -> Self {
+        FluidToolSystem { reader_id }
+    }
+}
+
+impl<'a> System<'a> for FluidToolSystem {
+    type SystemData = (
+        Read<'a, EventChannel<InputEvent<GameBindings>>>,
+        Read<'a, ObjectsUnderCursor>,
+        ReadExpect<'a, VoxelMap>,
+        Write<'a, FluidSources>,
+        Write<'a, FluidField>,
+        ReadStorage<'a, FluidSourceHintTag>,
+        WriteStorage<'a, DebugLinesComponent>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            input_events,
+            objects,
+            voxel_map,
+            mut sources,
+            mut field,
+            is_hint,
+            mut debug_lines,
+        ): Self::SystemData,
+    ) {
+        for input_event in input_events.read(&mut self.reader_id) {
+            match input_event {
+                InputEvent::ActionPressed(ActionBinding::ToggleFluidSource) => {
+                    let p = match &objects.voxel {
+                        Some(v) => v.hover_adjacent_point(),
+                        None => continue,
+                    };
+                    if sources.toggle(p) {
+                        log::info!("Added fluid source at {:?}", p);
+                    } else {
+                        log::info!("Removed fluid source at {:?}", p);
+                    }
+                }
+                InputEvent::ActionPressed(ActionBinding::DrainFluid) => {
+                    field.clear();
+                    log::info!("Drained all fluid");
+                }
+                _ => (),
+            }
+        }
+
+        for (_, lines) in (&is_hint, &mut debug_lines).join() {
+            lines.clear();
+            for p in sources.iter() {
+                let center = voxel_center(*p, voxel_map.world_scale);
+                let half_size = Vector3::repeat(0.3 * voxel_map.world_scale);
+                lines.add_box(
+                    center - half_size,
+                    center + half_size,
+                    Srgba::new(0.2, 0.5, 1.0, 1.0),
+                );
+            }
+        }
+    }
+}
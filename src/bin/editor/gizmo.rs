@@ -0,0 +1,365 @@
+use crate::{
+    bindings::{ActionBinding, GameBindings},
+    control::{camera::data::CameraData, hover_3d::ObjectsUnderCursor},
+    selection::Selection,
+};
+
+use voxel_mapper::{
+    geometry::{
+        closest_points_on_lines, line_plane_intersection, Line, LinePlaneIntersection, Plane,
+    },
+    voxel::{
+        chunk_cache_flusher::ChunkCacheFlusher, clipboard::move_extent,
+        double_buffer::EditedChunksBackBuffer, VoxelMap,
+    },
+};
+
+use amethyst::{
+    core::{
+        ecs::prelude::*,
+        math::{Point3, Vector3},
+    },
+    derive::SystemDesc,
+    input::{InputEvent, InputHandler},
+    renderer::{debug_drawing::DebugLinesComponent, palette::Srgba},
+    shrev::EventChannel,
+};
+use building_blocks::prelude::*;
+use std::f32::consts::PI;
+
+const ARROW_LENGTH: f32 = 4.0;
+const RING_RADIUS: f32 = 3.0;
+const RING_SEGMENTS: usize = 32;
+/// How close the camera ray needs to pass to a handle in order to grab it.
+const PICK_RADIUS: f32 = 0.4;
+
+/// What the gizmo places when it's committed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GizmoTarget {
+    /// Paste the selection clipboard at the gizmo.
+    Clipboard,
+    /// Move the selected voxels to the gizmo.
+    Selection,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum GizmoHandle {
+    Axis(usize),
+    /// Rotates the clipboard about the Y axis in quarter turns.
+    Ring,
+}
+
+#[derive(Clone, Copy)]
+struct GizmoDrag {
+    handle: GizmoHandle,
+    start_position: Point3i,
+    /// The axis parameter or ring angle where the handle was grabbed.
+    start_param: f32,
+    quarter_turns_applied: i32,
+}
+
+/// A 3D handle for precisely positioning a paste or a moved selection before committing the edit.
+/// The gizmo sits at the minimum corner of the placed voxels.
+#[derive(Default)]
+pub struct Gizmo {
+    pub target: Option<GizmoTarget>,
+    pub position: Point3i,
+    drag: Option<GizmoDrag>,
+}
+
+impl Gizmo {
+    fn origin(&self) -> Point3<f32> {
+        Point3::from(Point3f::from(self.position).0)
+    }
+
+    fn axis_line(&self, axis: usize) -> Line {
+        let mut v = Vector3::zeros();
+        v[axis] = 1.0;
+
+        Line {
+            p: self.origin(),
+            v,
+        }
+    }
+
+    fn ring_plane(&self) -> Plane {
+        Plane {
+            p: self.origin(),
+            n: Vector3::new(0.0, 1.0, 0.0),
+        }
+    }
+
+    fn ring_angle(&self, ray_line: &Line) -> Option<(f32, f32)> {
+        if let LinePlaneIntersection::IntersectionPoint(q) =
+            line_plane_intersection(ray_line, &self.ring_plane())
+        {
+            let d = q - self.origin();
+            Some((d.norm(), d.z.atan2(d.x)))
+        } else {
+            None
+        }
+    }
+
+    /// Finds the handle closest to the camera that `ray_line` passes near, along with the
+    /// parameter where it was grabbed.
+    fn pick(&self, ray_line: &Line, can_rotate: bool) -> Option<(GizmoHandle, f32)> {
+        let mut best: Option<(f32, GizmoHandle, f32)> = None;
+        for axis in 0..3 {
+            let axis_line = self.axis_line(axis);
+            if let Some((ray_t, axis_t)) = closest_points_on_lines(ray_line, &axis_line) {
+                if ray_t < 0.0 || axis_t < 0.0 || axis_t > ARROW_LENGTH {
+                    continue;
+                }
+                let on_ray = ray_line.p + ray_t * ray_line.v;
+                let on_axis = axis_line.p + axis_t * axis_line.v;
+                if (on_ray - on_axis).norm() < PICK_RADIUS
+                    && best.map(|(t, _, _)| ray_t < t).unwrap_or(true)
+                {
+                    best = Some((ray_t, GizmoHandle::Axis(axis), axis_t));
+                }
+            }
+        }
+        if can_rotate && best.is_none() {
+            if let Some((radius, angle)) = self.ring_angle(ray_line) {
+                if (radius - RING_RADIUS).abs() < PICK_RADIUS {
+                    best = Some((0.0, GizmoHandle::Ring, angle));
+                }
+            }
+        }
+
+        best.map(|(_, handle, param)| (handle, param))
+    }
+}
+
+#[derive(Default)]
+pub struct GizmoTag;
+
+impl Component for GizmoTag {
+    type Storage = NullStorage<Self>;
+}
+
+pub fn make_gizmo_lines(world: &mut World) {
+    world
+        .create_entity()
+        .with(GizmoTag)
+        .with(DebugLinesComponent::new())
+        .build();
+}
+
+/// Shows the gizmo for the clipboard or selection and lets the user drag its axis arrows to
+/// translate in whole voxels, or drag its ring to rotate the clipboard in quarter turns.
+#[derive(SystemDesc)]
+#[system_desc(name(GizmoSystemDesc))]
+pub struct GizmoSystem {
+    #[system_desc(event_channel_reader)]
+    reader_id: ReaderId<InputEvent<GameBindings>>,
+}
+
+impl GizmoSystem {
+    pub fn new(reader_id: ReaderId<InputEvent<GameBindings>>) -> Self {
+        GizmoSystem { reader_id }
+    }
+}
+
+impl<'a> System<'a> for GizmoSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        Read<'a, EventChannel<InputEvent<GameBindings>>>,
+        Read<'a, InputHandler<GameBindings>>,
+        Read<'a, ObjectsUnderCursor>,
+        ReadExpect<'a, VoxelMap>,
+        ReadExpect<'a, ChunkCacheFlusher>,
+        Write<'a, Gizmo>,
+        Write<'a, Selection>,
+        WriteExpect<'a, EditedChunksBackBuffer>,
+        CameraData<'a>,
+        ReadStorage<'a, GizmoTag>,
+        WriteStorage<'a, DebugLinesComponent>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            input_events,
+            input_handler,
+            objects,
+            voxel_map,
+            cache_flusher,
+            mut gizmo,
+            mut selection,
+            mut voxel_backbuffer,
+            camera_data,
+            is_gizmo,
+            mut debug_lines,
+        ): Self::SystemData,
+    ) {
+        let ray_line = input_handler
+            .mouse_position()
+            .and_then(|(x, y)| camera_data.get_camera_ray(x, y))
+            .map(|ray| Line {
+                p: ray.origin,
+                v: ray.dir,
+            });
+
+        let local_cache = LocalChunkCache3::new();
+        let map_reader = voxel_map.voxels.reader(&local_cache);
+
+        for input_event in input_events.read(&mut self.reader_id) {
+            match input_event {
+                InputEvent::ActionPressed(ActionBinding::GizmoPaste) => {
+                    if gizmo.target.take().is_some() {
+                        continue;
+                    }
+                    if let (Some(_), Some(v)) = (&selection.clipboard, &objects.voxel) {
+                        gizmo.target = Some(GizmoTarget::Clipboard);
+                        gizmo.position = v.hover_adjacent_point();
+                    }
+                }
+                InputEvent::ActionPressed(ActionBinding::GizmoMoveSelection) => {
+                    if gizmo.target.take().is_some() {
+                        continue;
+                    }
+                    if let Some(extent) = &selection.extent {
+                        gizmo.target = Some(GizmoTarget::Selection);
+                        gizmo.position = extent.minimum;
+                    }
+                }
+                InputEvent::ActionPressed(ActionBinding::GrabGizmo) => {
+                    if gizmo.target.is_none() {
+                        continue;
+                    }
+                    let can_rotate = gizmo.target == Some(GizmoTarget::Clipboard);
+                    if let Some((handle, start_param)) = ray_line
+                        .as_ref()
+                        .and_then(|ray_line| gizmo.pick(ray_line, can_rotate))
+                    {
+                        gizmo.drag = Some(GizmoDrag {
+                            handle,
+                            start_position: gizmo.position,
+                            start_param,
+                            quarter_turns_applied: 0,
+                        });
+                    }
+                }
+                InputEvent::ActionReleased(ActionBinding::GrabGizmo) => {
+                    gizmo.drag = None;
+                }
+                InputEvent::ActionPressed(ActionBinding::CommitGizmo) => {
+                    match gizmo.target.take() {
+                        Some(GizmoTarget::Clipboard) => {
+                            if let Some(clipboard) = &selection.clipboard {
+                                clipboard.paste(
+                                    &map_reader,
+                                    gizmo.position,
+                                    &mut *voxel_backbuffer,
+                                );
+                            }
+                        }
+                        Some(GizmoTarget::Selection) => {
+                            if let Some(extent) = selection.extent {
+                                move_extent(
+                                    &map_reader,
+                                    &extent,
+                                    gizmo.position,
+                                    &mut *voxel_backbuffer,
+                                );
+                                selection.extent = Some(Extent3i::from_min_and_shape(
+                                    gizmo.position,
+                                    extent.shape,
+                                ));
+                            }
+                        }
+                        None => (),
+                    }
+                    gizmo.drag = None;
+                }
+                _ => (),
+            }
+        }
+
+        cache_flusher.flush(local_cache);
+
+        // Follow the cursor with the grabbed handle.
+        if let (Some(drag), Some(ray_line)) = (gizmo.drag, &ray_line) {
+            match drag.handle {
+                GizmoHandle::Axis(axis) => {
+                    let start_gizmo = Gizmo {
+                        position: drag.start_position,
+                        ..Default::default()
+                    };
+                    if let Some((_, axis_t)) =
+                        closest_points_on_lines(ray_line, &start_gizmo.axis_line(axis))
+                    {
+                        let mut position = drag.start_position;
+                        position.0[axis] += (axis_t - drag.start_param).round() as i32;
+                        gizmo.position = position;
+                    }
+                }
+                GizmoHandle::Ring => {
+                    if let Some((_, angle)) = gizmo.ring_angle(ray_line) {
+                        let quarter_turns =
+                            ((angle - drag.start_param) / (PI / 2.0)).round() as i32;
+                        let new_turns = (quarter_turns - drag.quarter_turns_applied).rem_euclid(4);
+                        if let Some(clipboard) = &mut selection.clipboard {
+                            for _ in 0..new_turns {
+                                clipboard.rotate_y_90();
+                            }
+                        }
+                        if let Some(drag) = &mut gizmo.drag {
+                            drag.quarter_turns_applied = quarter_turns;
+                        }
+                    }
+                }
+            }
+        }
+
+        for (_, lines) in (&is_gizmo, &mut debug_lines).join() {
+            lines.clear();
+            let shape = match gizmo.target {
+                Some(GizmoTarget::Clipboard) => selection.clipboard.as_ref().map(|c| c.shape()),
+                Some(GizmoTarget::Selection) => selection.extent.map(|e| e.shape),
+                None => None,
+            };
+            let shape = match shape {
+                Some(s) => s,
+                None => continue,
+            };
+
+            let origin = gizmo.origin();
+            let grabbed = gizmo.drag.as_ref().map(|d| d.handle);
+            let highlight = |handle, color: Srgba| {
+                if grabbed == Some(handle) {
+                    Srgba::new(1.0, 1.0, 0.0, 1.0)
+                } else {
+                    color
+                }
+            };
+
+            let axis_colors = [
+                Srgba::new(1.0, 0.0, 0.0, 1.0),
+                Srgba::new(0.0, 1.0, 0.0, 1.0),
+                Srgba::new(0.0, 0.0, 1.0, 1.0),
+            ];
+            for (axis, color) in axis_colors.iter().enumerate() {
+                let color = highlight(GizmoHandle::Axis(axis), *color);
+                let dir = gizmo.axis_line(axis).v * ARROW_LENGTH;
+                lines.add_direction(origin, dir, color);
+                lines.add_sphere(origin + dir, 0.2, 6, 6, color);
+            }
+
+            if gizmo.target == Some(GizmoTarget::Clipboard) {
+                let color = highlight(GizmoHandle::Ring, Srgba::new(1.0, 0.5, 0.0, 1.0));
+                let ring_point = |i: usize| {
+                    let angle = 2.0 * PI * i as f32 / RING_SEGMENTS as f32;
+                    origin + RING_RADIUS * Vector3::new(angle.cos(), 0.0, angle.sin())
+                };
+                for i in 0..RING_SEGMENTS {
+                    lines.add_line(ring_point(i), ring_point(i + 1), color);
+                }
+            }
+
+            let box_max = Point3::from(Point3f::from(gizmo.position + shape).0);
+            lines.add_box(origin, box_max, Srgba::new(1.0, 1.0, 1.0, 1.0));
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use crate::{
+    bindings::{action_key_name, ActionBinding, GameBindings},
+    voxel_brush::PaintBrush,
+};
+
+use voxel_mapper::voxel::VoxelType;
+
+use amethyst::{
+    assets::{AssetStorage, Loader},
+    core::ecs::prelude::*,
+    derive::SystemDesc,
+    input::{InputEvent, InputHandler},
+    shrev::EventChannel,
+    ui::{get_default_font, Anchor, FontAsset, UiText, UiTransform},
+};
+use serde::{Deserialize, Serialize};
+
+/// A curated set of palette entries that can be selected with the `SelectHotbarSlot` actions. By
+/// default, slot 0 is on key 1, and slot 9 is on key 0.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct HotbarConfig {
+    pub slots: Vec<HotbarSlot>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct HotbarSlot {
+    pub voxel_type: VoxelType,
+    /// Shown in the UI.
+    pub name: String,
+}
+
+pub const MAX_HOTBAR_SLOTS: usize = 10;
+
+pub struct Hotbar {
+    pub config: HotbarConfig,
+    pub selected: usize,
+}
+
+impl Hotbar {
+    pub fn new(mut config: HotbarConfig) -> Self {
+        if config.slots.len() > MAX_HOTBAR_SLOTS {
+            log::warn!(
+                "Hotbar has {} slots, only the first {} can be selected",
+                config.slots.len(),
+                MAX_HOTBAR_SLOTS
+            );
+            config.slots.truncate(MAX_HOTBAR_SLOTS);
+        }
+
+        Self {
+            config,
+            selected: 0,
+        }
+    }
+
+    pub fn selected_voxel_type(&self) -> Option<VoxelType> {
+        self.config.slots.get(self.selected).map(|s| s.voxel_type)
+    }
+}
+
+pub struct HotbarSlotText {
+    pub slot: usize,
+}
+
+impl Component for HotbarSlotText {
+    type Storage = VecStorage<Self>;
+}
+
+const SLOT_WIDTH: f32 = 120.0;
+const SLOT_HEIGHT: f32 = 30.0;
+const SLOT_FONT_SIZE: f32 = 18.0;
+const SELECTED_COLOR: [f32; 4] = [1.0, 1.0, 0.0, 1.0];
+const UNSELECTED_COLOR: [f32; 4] = [0.8, 0.8, 0.8, 1.0];
+
+/// Creates a row of labels along the bottom of the screen, one for each hotbar slot.
+pub fn make_hotbar_ui(hotbar: &Hotbar, world: &mut World) {
+    let font = world.exec(
+        |(loader, font_storage): (ReadExpect<Loader>, Read<AssetStorage<FontAsset>>)| {
+            get_default_font(&loader, &font_storage)
+        },
+    );
+    let key_names: Vec<Option<String>> = world.exec(|input: Read<InputHandler<GameBindings>>| {
+        (0..hotbar.config.slots.len())
+            .map(|i| action_key_name(&input.bindings, &ActionBinding::SelectHotbarSlot(i)))
+            .collect()
+    });
+
+    let num_slots = hotbar.config.slots.len();
+    for (i, slot) in hotbar.config.slots.iter().enumerate() {
+        let x = (i as f32 - (num_slots as f32 - 1.0) / 2.0) * SLOT_WIDTH;
+        let transform = UiTransform::new(
+            format!("hotbar_slot_{}", i),
+            Anchor::BottomMiddle,
+            Anchor::BottomMiddle,
+            x,
+            SLOT_HEIGHT,
+            1.0,
+            SLOT_WIDTH,
+            SLOT_HEIGHT,
+        );
+        let color = if i == hotbar.selected {
+            SELECTED_COLOR
+        } else {
+            UNSELECTED_COLOR
+        };
+        let label = match &key_names[i] {
+            Some(key) => format!("{}: {}", key, slot.name),
+            None => slot.name.clone(),
+        };
+        let text = UiText::new(font.clone(), label, color, SLOT_FONT_SIZE);
+
+        world
+            .create_entity()
+            .with(transform)
+            .with(text)
+            .with(HotbarSlotText { slot: i })
+            .build();
+    }
+}
+
+/// Selects hotbar slots with the `SelectHotbarSlot` actions, sets the brush type, and highlights
+/// the selected slot in the UI.
+#[derive(SystemDesc)]
+#[system_desc(name(HotbarSystemDesc))]
+pub struct HotbarSystem {
+    #[system_desc(event_channel_reader)]
+    reader_id: ReaderId<InputEvent<GameBindings>>,
+}
+
+impl HotbarSystem {
+    pub fn new(reader_id: ReaderId<InputEvent<GameBindings>>) -> Self {
+        HotbarSystem { reader_id }
+    }
+}
+
+impl<'a> System<'a> for HotbarSystem {
+    type SystemData = (
+        Read<'a, EventChannel<InputEvent<GameBindings>>>,
+        WriteExpect<'a, Hotbar>,
+        WriteExpect<'a, PaintBrush>,
+        ReadStorage<'a, HotbarSlotText>,
+        WriteStorage<'a, UiText>,
+    );
+
+    fn run(
+        &mut self,
+        (input_events, mut hotbar, mut brush, slot_texts, mut texts): Self::SystemData,
+    ) {
+        for input_event in input_events.read(&mut self.reader_id) {
+            if let InputEvent::ActionPressed(ActionBinding::SelectHotbarSlot(slot)) = input_event {
+                if *slot < hotbar.config.slots.len() {
+                    hotbar.selected = *slot;
+                    brush.voxel_type = hotbar.selected_voxel_type().unwrap();
+                    log::info!("Set voxel paintbrush to {:?}", brush.voxel_type);
+                }
+            }
+        }
+
+        for (slot_text, text) in (&slot_texts, &mut texts).join() {
+            text.color = if slot_text.slot == hotbar.selected {
+                SELECTED_COLOR
+            } else {
+                UNSELECTED_COLOR
+            };
+        }
+    }
+}
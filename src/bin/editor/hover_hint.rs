@@ -1,6 +1,6 @@
 use crate::control::hover_3d::ObjectsUnderCursor;
 
-use voxel_mapper::voxel::voxel_containing_point;
+use voxel_mapper::voxel::{voxel_containing_point, VoxelMap};
 
 use amethyst::{
     core::{ecs::prelude::*, math as na},
@@ -28,24 +28,26 @@ pub struct HoverHintSystem;
 impl<'a> System<'a> for HoverHintSystem {
     type SystemData = (
         Read<'a, ObjectsUnderCursor>,
+        ReadExpect<'a, VoxelMap>,
         ReadStorage<'a, HoverHintTag>,
         WriteStorage<'a, DebugLinesComponent>,
     );
 
-    fn run(&mut self, (objects, is_hint, mut debug_lines): Self::SystemData) {
+    fn run(&mut self, (objects, voxel_map, is_hint, mut debug_lines): Self::SystemData) {
         for (_, lines) in (&is_hint, &mut debug_lines).join() {
             lines.clear();
             let box_p = if let Some(v) = &objects.voxel {
                 v.hover_adjacent_point()
             } else if let Some(p) = objects.xz_plane {
-                voxel_containing_point(p)
+                voxel_containing_point(p, voxel_map.world_scale)
             } else {
                 continue;
             };
             // TODO: amethyst is using an older version of nalgebra than building-blocks, so we
             // can't do the simplest conversion
             let box_min: na::Point3<f32> = Point3f::from(box_p).0.into();
-            let box_max = box_min + na::Vector3::new(1.0, 1.0, 1.0);
+            let box_min = box_min * voxel_map.world_scale;
+            let box_max = box_min + na::Vector3::repeat(voxel_map.world_scale);
             lines.add_box(box_min, box_max, Srgba::new(1.0, 0.0, 1.0, 1.0));
         }
     }
@@ -0,0 +1,166 @@
+use crate::{
+    bindings::{ActionBinding, GameBindings},
+    control::hover_3d::ObjectsUnderCursor,
+};
+
+use voxel_mapper::voxel::{
+    lights::{MapLights, VoxelLight},
+    voxel_center, VoxelMap,
+};
+
+use amethyst::{
+    core::{ecs::prelude::*, math::Vector3, Transform},
+    derive::SystemDesc,
+    input::InputEvent,
+    renderer::{
+        debug_drawing::DebugLinesComponent,
+        light::{Light, PointLight},
+        palette::{rgb::Rgb, Srgba},
+    },
+    shrev::EventChannel,
+};
+use building_blocks::prelude::*;
+
+/// The voxel that a `PointLight` entity was created for, so the entity can be found again when the
+/// light is removed.
+pub struct VoxelLightAnchor(pub Point3i);
+
+impl Component for VoxelLightAnchor {
+    type Storage = DenseVecStorage<Self>;
+}
+
+#[derive(Default)]
+pub struct LightHintTag;
+
+impl Component for LightHintTag {
+    type Storage = NullStorage<Self>;
+}
+
+pub fn make_light_hint_lines(world: &mut World) {
+    world
+        .create_entity()
+        .with(LightHintTag)
+        .with(DebugLinesComponent::new())
+        .build();
+}
+
+/// Creates the `PointLight` entities for all of the map's lights.
+pub fn make_map_lights(lights: &MapLights, world: &mut World) {
+    let world_scale = world.read_resource::<VoxelMap>().world_scale;
+    for light in lights.iter() {
+        let (light, tfm, anchor) = light_components(light, world_scale);
+        world
+            .create_entity()
+            .with(light)
+            .with(tfm)
+            .with(anchor)
+            .build();
+    }
+}
+
+fn light_components(light: &VoxelLight, world_scale: f32) -> (Light, Transform, VoxelLightAnchor) {
+    let [r, g, b] = light.color;
+    let point_light: Light = PointLight {
+        intensity: light.intensity,
+        radius: light.radius,
+        color: Rgb::new(r, g, b),
+        ..PointLight::default()
+    }
+    .into();
+    let mut tfm = Transform::default();
+    *tfm.translation_mut() = voxel_center(light.position(), world_scale).coords;
+
+    (point_light, tfm, VoxelLightAnchor(light.position()))
+}
+
+/// Places a point light in the empty voxel next to the hovered surface, or removes the light that's
+/// already there, and draws a small box at every light.
+#[derive(SystemDesc)]
+#[system_desc(name(LightToolSystemDesc))]
+pub struct LightToolSystem {
+    #[system_desc(event_channel_reader)]
+    reader_id: ReaderId<InputEvent<GameBindings>>,
+}
+
+impl LightToolSystem {
+    pub fn new(reader_id: ReaderId<InputEvent<GameBindings>>) -> Self {
+        LightToolSystem { reader_id }
+    }
+}
+
+/// Clicking within this many voxels of a light removes it instead of placing a new one.
+const LIGHT_PICK_RADIUS: f32 = 1.5;
+
+impl<'a> System<'a> for LightToolSystem {
+    type SystemData = (
+        Read<'a, EventChannel<InputEvent<GameBindings>>>,
+        Read<'a, ObjectsUnderCursor>,
+        ReadExpect<'a, VoxelMap>,
+        Write<'a, MapLights>,
+        Entities<'a>,
+        WriteStorage<'a, Light>,
+        WriteStorage<'a, Transform>,
+        WriteStorage<'a, VoxelLightAnchor>,
+        ReadStorage<'a, LightHintTag>,
+        WriteStorage<'a, DebugLinesComponent>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            input_events,
+            objects,
+            voxel_map,
+            mut lights,
+            entities,
+            mut light_storage,
+            mut transforms,
+            mut anchors,
+            is_hint,
+            mut debug_lines,
+        ): Self::SystemData,
+    ) {
+        for input_event in input_events.read(&mut self.reader_id) {
+            if let InputEvent::ActionPressed(ActionBinding::ToggleLight) = input_event {
+                let p = match &objects.voxel {
+                    Some(v) => v.hover_adjacent_point(),
+                    None => continue,
+                };
+                if let Some(removed) = lights.remove_near(p, LIGHT_PICK_RADIUS) {
+                    for (e, anchor) in (&entities, &anchors).join() {
+                        if anchor.0 == removed.position() {
+                            entities.delete(e).unwrap();
+                        }
+                    }
+                    log::info!("Removed light at {:?}", removed.position());
+                } else {
+                    let light = VoxelLight::new(p);
+                    let (point_light, tfm, anchor) =
+                        light_components(&light, voxel_map.world_scale);
+                    entities
+                        .build_entity()
+                        .with(point_light, &mut light_storage)
+                        .with(tfm, &mut transforms)
+                        .with(anchor, &mut anchors)
+                        .build();
+                    lights.add(light);
+                    log::info!("Placed light at {:?}", p);
+                }
+            }
+        }
+
+        for (_, lines) in (&is_hint, &mut debug_lines).join() {
+            lines.clear();
+            for light in lights.iter() {
+                let [r, g, b] = light.color;
+                let center = voxel_center(light.position(), voxel_map.world_scale);
+                let half_size = Vector3::repeat(0.25 * voxel_map.world_scale);
+                lines.add_box(
+                    center - half_size,
+                    center + half_size,
+                    Srgba::new(r, g, b, 1.0),
+                );
+            }
+        }
+    }
+}
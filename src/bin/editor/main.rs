@@ -1,20 +1,86 @@
+mod asset_errors;
+mod audit_palette;
 mod bindings;
+mod cache_stats_overlay;
+mod camera_mode;
+mod capture_tool;
+mod chunk_lock_tool;
 mod control;
 mod debug_feet;
+mod editor_panel;
+mod extract_region;
+mod fetch_assets;
+mod flood_fill_tool;
+mod fluid_tool;
+mod gizmo;
+mod hotbar;
 mod hover_hint;
+mod light_tool;
+mod map_saving;
+mod marker_tool;
+mod metadata_tool;
+mod minimap;
 mod only_state;
+mod palette_editor;
+mod path_tool;
+mod primitive_tool;
+mod prop_tool;
+#[cfg(feature = "scripting")]
+mod script_tool;
+mod selection;
+mod status_bar;
+mod undo;
+mod validate_map;
 mod voxel_brush;
+mod zone_tool;
 
-use bindings::GameBindings;
-use control::{camera::CameraControlSystemDesc, hover_3d::HoverObjectSystem};
+use asset_errors::AssetErrorSystemDesc;
+use bindings::{AxisBinding, GameBindings};
+use cache_stats_overlay::CacheStatsOverlaySystemDesc;
+use camera_mode::CameraModeSystemDesc;
+use capture_tool::CaptureToolSystemDesc;
+use chunk_lock_tool::ChunkLockToolSystemDesc;
+use control::{
+    camera::{CameraAxes, CameraControlSystemDesc},
+    hover_3d::HoverObjectSystem,
+};
 use debug_feet::DrawCameraFeetSystem;
+use editor_panel::EditorPanelSystemDesc;
+use flood_fill_tool::FloodFillToolSystemDesc;
+use fluid_tool::FluidToolSystemDesc;
+use gizmo::GizmoSystemDesc;
+use hotbar::HotbarSystemDesc;
 use hover_hint::HoverHintSystem;
-use only_state::OnlyState;
+use light_tool::LightToolSystemDesc;
+use map_saving::MapSavingSystemDesc;
+use marker_tool::MarkerToolSystemDesc;
+use metadata_tool::MetadataToolSystemDesc;
+use minimap::MinimapUiSystemDesc;
+use only_state::{OnlyState, SessionOptions};
+use palette_editor::PaletteEditorSystemDesc;
+use path_tool::PathToolSystemDesc;
+use primitive_tool::PrimitiveToolSystemDesc;
+use prop_tool::{PropPrefab, PropToolSystemDesc};
+use selection::SelectionSystemDesc;
+use status_bar::StatusBarSystemDesc;
+use undo::UndoSystemDesc;
 use voxel_brush::VoxelBrushSystemDesc;
+use zone_tool::ZoneToolSystemDesc;
 
 use voxel_mapper::{
-    rendering::splatted_triplanar_pbr_pass::RenderSplattedTriplanarPbr,
-    voxel::bundle::VoxelSystemBundle,
+    rendering::{
+        aabb_culling::AabbCullingSystem,
+        chunk_culling::ChunkCullingSystem,
+        day_night::DayNightSystem,
+        frame_capture::FrameCaptureSystem,
+        splatted_triplanar_pbr_pass::{
+            with_voxel_render_plugin, ChunkVertexFormat, VoxelRenderConfig,
+        },
+    },
+    voxel::{
+        bundle::VoxelSystemBundle, chunk_processor::MeshingConfig, minimap::MinimapSystem,
+        props::PropSpawnSystem,
+    },
 };
 
 use amethyst::{
@@ -27,19 +93,30 @@ use amethyst::{
         formats::mtl::MaterialPrefab, palette::Srgb, types::DefaultBackend, RenderDebugLines,
         RenderSkybox, RenderToWindow, RenderingBundle,
     },
+    ui::{RenderUi, UiBundle},
     utils::application_dir,
     LoggerConfig,
 };
 use std::path::PathBuf;
 use structopt::StructOpt;
 
-fn run_app(map_file: PathBuf) -> amethyst::Result<()> {
+#[cfg(feature = "gamepad")]
+use amethyst::input::SdlEventsSystemDesc;
+#[cfg(feature = "scripting")]
+use script_tool::ScriptToolSystemDesc;
+
+fn run_app(map_file: PathBuf, opt: &Opt) -> amethyst::Result<()> {
     let assets_dir = application_dir("assets")?;
 
     let config_dir = assets_dir.join("config");
     let logger_config_path = config_dir.join("logger.ron");
     let display_config_path = config_dir.join("display_config.ron");
     let input_config_path = config_dir.join("map_editor_bindings.ron");
+    let mut render_config = VoxelRenderConfig::load(config_dir.join("voxel_render.ron"))?;
+    let meshing_config = MeshingConfig::load(config_dir.join("meshing.ron"))?;
+    if opt.packed_vertices {
+        render_config.vertex_format = ChunkVertexFormat::Packed;
+    }
 
     amethyst::Logger::from_config(LoggerConfig::load(&logger_config_path)?).start();
 
@@ -49,14 +126,27 @@ fn run_app(map_file: PathBuf) -> amethyst::Result<()> {
             "material_prefab_loader",
             &[],
         )
+        .with_system_desc(
+            PrefabLoaderSystemDesc::<PropPrefab>::default(),
+            "prop_prefab_loader",
+            &[],
+        )
         .with_bundle(TransformBundle::new())?
         .with_bundle(
             InputBundle::<GameBindings>::new().with_bindings_from_file(&input_config_path)?,
         )?
+        .with_system_desc(CameraModeSystemDesc, "camera_mode", &[])
+        .with_system_desc(CaptureToolSystemDesc, "capture_tool", &[])
         .with_system_desc(
-            CameraControlSystemDesc::<GameBindings>::default(),
+            CameraControlSystemDesc::<GameBindings>::new(CameraAxes {
+                forward: AxisBinding::Forward,
+                lateral: AxisBinding::Lateral,
+                yaw: AxisBinding::CameraYaw,
+                pitch: AxisBinding::CameraPitch,
+                zoom: AxisBinding::CameraZoom,
+            }),
             "camera_control",
-            &[],
+            &["camera_mode", "capture_tool"],
         )
         .with(DrawCameraFeetSystem, "draw_camera_feet", &[])
         .with(
@@ -65,6 +155,10 @@ fn run_app(map_file: PathBuf) -> amethyst::Result<()> {
             &[],
         )
         .with(HoverHintSystem, "hover_hint", &[])
+        .with_bundle(UiBundle::<GameBindings>::new())?
+        .with_system_desc(HotbarSystemDesc, "hotbar", &[])
+        .with_system_desc(EditorPanelSystemDesc, "editor_panel", &["hotbar"])
+        .with_system_desc(StatusBarSystemDesc, "status_bar", &["hover_object"])
         .with_bundle(VoxelSystemBundle)?
         .with_system_desc(
             VoxelBrushSystemDesc,
@@ -73,20 +167,101 @@ fn run_app(map_file: PathBuf) -> amethyst::Result<()> {
             // there will be weird feedback loops that cause voxel flickering.
             &["voxel_double_buffering"],
         )
+        .with_system_desc(PathToolSystemDesc, "path_tool", &["voxel_double_buffering"])
+        .with_system_desc(
+            PrimitiveToolSystemDesc,
+            "primitive_tool",
+            &["voxel_double_buffering"],
+        )
+        .with_system_desc(
+            SelectionSystemDesc,
+            "selection",
+            &["voxel_double_buffering"],
+        )
+        .with_system_desc(GizmoSystemDesc, "gizmo", &["voxel_double_buffering"])
+        .with_system_desc(UndoSystemDesc, "undo", &["voxel_double_buffering"])
+        .with_system_desc(
+            FloodFillToolSystemDesc,
+            "flood_fill_tool",
+            &["voxel_double_buffering"],
+        )
+        .with_system_desc(
+            PaletteEditorSystemDesc,
+            "palette_editor",
+            &["voxel_double_buffering"],
+        )
+        .with_system_desc(ChunkLockToolSystemDesc, "chunk_lock_tool", &[])
+        .with_system_desc(MarkerToolSystemDesc, "marker_tool", &[])
+        .with_system_desc(LightToolSystemDesc, "light_tool", &[])
+        .with_system_desc(FluidToolSystemDesc, "fluid_tool", &[])
+        .with_system_desc(MetadataToolSystemDesc, "metadata_tool", &[])
+        .with_system_desc(PropToolSystemDesc, "prop_tool", &[])
+        .with(
+            PropSpawnSystem::<PropPrefab>::default(),
+            "prop_spawn",
+            &["prop_tool"],
+        )
+        .with_system_desc(ZoneToolSystemDesc, "zone_tool", &["selection"])
+        .with(MinimapSystem, "minimap", &[])
+        .with_system_desc(MinimapUiSystemDesc, "minimap_ui", &["minimap"])
+        .with_system_desc(AssetErrorSystemDesc, "asset_errors", &[])
+        .with_system_desc(CacheStatsOverlaySystemDesc, "cache_stats_overlay", &[])
+        .with_system_desc(MapSavingSystemDesc, "map_saving", &["background_save"])
         .with_bundle(
-            RenderingBundle::<DefaultBackend>::new()
-                .with_plugin(
+            with_voxel_render_plugin(
+                RenderingBundle::<DefaultBackend>::new().with_plugin(
                     RenderToWindow::from_config_path(display_config_path)?
                         .with_clear([0.0, 0.0, 0.0, 1.0]),
-                )
-                .with_plugin(RenderSplattedTriplanarPbr::default())
-                .with_plugin(RenderSkybox::with_colors(
-                    Srgb::new(0.82, 0.51, 0.50),
-                    Srgb::new(0.18, 0.11, 0.85),
-                ))
-                .with_plugin(RenderDebugLines::default()),
-        )?;
-    let mut game = Application::new(&assets_dir, OnlyState::new(map_file), game_data)?;
+                ),
+                &render_config,
+            )
+            .with_plugin(RenderSkybox::with_colors(
+                Srgb::new(0.82, 0.51, 0.50),
+                Srgb::new(0.18, 0.11, 0.85),
+            ))
+            .with_plugin(RenderDebugLines::default())
+            .with_plugin(RenderUi::default()),
+        )?
+        .with(ChunkCullingSystem, "chunk_culling", &["visibility_system"])
+        .with(AabbCullingSystem, "aabb_culling", &["chunk_culling"])
+        .with(DayNightSystem, "day_night", &[])
+        .with(FrameCaptureSystem, "frame_capture", &[]);
+    #[cfg(feature = "scripting")]
+    let game_data = game_data.with_system_desc(
+        ScriptToolSystemDesc,
+        "script_tool",
+        &["voxel_double_buffering"],
+    );
+    // Controller events are polled from SDL on the main thread.
+    #[cfg(feature = "gamepad")]
+    let game_data =
+        game_data.with_thread_local_desc(SdlEventsSystemDesc::<GameBindings>::default());
+    let mut game = Application::build(
+        &assets_dir,
+        OnlyState::new(
+            map_file,
+            SessionOptions {
+                deterministic_edits: opt.deterministic_edits,
+                record_edits: opt.record_edits.clone(),
+                replay_edits: opt
+                    .replay_edits
+                    .clone()
+                    .map(|path| (path, opt.replay_speed)),
+                record_session: opt.record_session.clone(),
+                replay_session: opt
+                    .replay_session
+                    .clone()
+                    .map(|path| (path, opt.replay_speed)),
+                save_as: opt.save_as.clone(),
+                host_session: opt.host.clone(),
+                join_session: opt.join.clone(),
+                script: opt.script.clone(),
+            },
+        ),
+    )?
+    .with_resource(render_config)
+    .with_resource(meshing_config)
+    .build(game_data)?;
     game.run();
 
     Ok(())
@@ -95,11 +270,142 @@ fn run_app(map_file: PathBuf) -> amethyst::Result<()> {
 #[derive(StructOpt, Debug)]
 #[structopt(name = "voxel-mapper-editor")]
 struct Opt {
+    /// The map to open in the editor.
     #[structopt(parse(from_os_str))]
-    map_file: PathBuf,
+    map_file: Option<PathBuf>,
+    /// Load chunk meshes with the packed vertex format, which uses about half the memory. Overrides
+    /// "assets/config/voxel_render.ron".
+    #[structopt(long)]
+    packed_vertices: bool,
+    /// Apply queued edits in order of their source and sequence number, so the same edits always
+    /// produce the same map.
+    #[structopt(long)]
+    deterministic_edits: bool,
+    /// Record queued edits to this file on exit, so they can be replayed later.
+    #[structopt(long, parse(from_os_str))]
+    record_edits: Option<PathBuf>,
+    /// Replay the edits recorded in this file onto the map.
+    #[structopt(long, parse(from_os_str))]
+    replay_edits: Option<PathBuf>,
+    /// Record every chunk merged into the map to this file on exit, so the whole session can be
+    /// played back exactly.
+    #[structopt(long, parse(from_os_str))]
+    record_session: Option<PathBuf>,
+    /// Play back the session recorded in this file, starting from the map as it was recorded.
+    #[structopt(long, parse(from_os_str))]
+    replay_session: Option<PathBuf>,
+    /// Playback speed for --replay-edits and --replay-session, relative to the recording.
+    #[structopt(long, default_value = "1.0")]
+    replay_speed: f64,
+    /// Save the voxels to this file instead of the map's voxels file, leaving the original
+    /// untouched.
+    #[structopt(long, parse(from_os_str))]
+    save_as: Option<PathBuf>,
+    /// Host a shared editing session on this address, e.g. "0.0.0.0:7777".
+    #[structopt(long, conflicts_with = "join")]
+    host: Option<String>,
+    /// Join the shared editing session hosted at this address. The host's map replaces the local
+    /// one.
+    #[structopt(long)]
+    join: Option<String>,
+    /// A Rhai script to run at the hovered voxel when F1 is pressed. Requires the "scripting"
+    /// feature.
+    #[structopt(long, parse(from_os_str))]
+    script: Option<PathBuf>,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Download, verify, and unpack the array material bundle into "assets/array_materials".
+    FetchAssets {
+        /// Unpack the bundle even if "assets/config/asset_sources.ron" has no checksum for it.
+        #[structopt(long)]
+        allow_unverified: bool,
+    },
+    /// Check the map's voxels for types outside the palette, types that disagree with the distance,
+    /// and chunks that don't need to be stored.
+    ValidateMap {
+        /// Fix the issues and save the voxels.
+        #[structopt(long)]
+        fix: bool,
+    },
+    /// Count how many voxels use each palette entry.
+    AuditPalette {
+        /// Remove unused entries from the palette and renumber the voxels to match.
+        #[structopt(long)]
+        compact: bool,
+    },
+    /// Copy the voxels in an extent to a new map file, keeping only the palette entries they use.
+    ExtractRegion {
+        /// The minimum corner of the extent, as X Y Z.
+        #[structopt(
+            long,
+            required = true,
+            number_of_values = 3,
+            allow_hyphen_values = true
+        )]
+        min: Vec<i32>,
+        /// The size of the extent, as X Y Z.
+        #[structopt(long, required = true, number_of_values = 3)]
+        shape: Vec<i32>,
+        /// The new map file. The voxels are written next to it, with a ".bin" extension.
+        #[structopt(long, parse(from_os_str))]
+        output: PathBuf,
+        /// Move the region so its minimum corner is at the origin.
+        #[structopt(long)]
+        move_to_origin: bool,
+    },
 }
 
 fn main() -> amethyst::Result<()> {
     let opt = Opt::from_args();
-    run_app(opt.map_file)
+
+    match (&opt.command, &opt.map_file) {
+        (Some(Command::FetchAssets { allow_unverified }), _) => {
+            amethyst::start_logger(Default::default());
+            fetch_assets::fetch_assets(allow_unverified)
+                .map_err(|e| amethyst::Error::from_string(format!("{:?}", e)))
+        }
+        (Some(Command::ValidateMap { fix }), Some(map_file)) => {
+            amethyst::start_logger(Default::default());
+            validate_map::validate_map_file(map_file, *fix)
+        }
+        (Some(Command::ValidateMap { .. }), None) => Err(amethyst::Error::from_string(
+            "Expected a map file to validate",
+        )),
+        (Some(Command::AuditPalette { compact }), Some(map_file)) => {
+            amethyst::start_logger(Default::default());
+            audit_palette::audit_palette_file(map_file, *compact)
+        }
+        (Some(Command::AuditPalette { .. }), None) => {
+            Err(amethyst::Error::from_string("Expected a map file to audit"))
+        }
+        (
+            Some(Command::ExtractRegion {
+                min,
+                shape,
+                output,
+                move_to_origin,
+            }),
+            Some(map_file),
+        ) => {
+            amethyst::start_logger(Default::default());
+            extract_region::extract_region_file(
+                map_file,
+                [min[0], min[1], min[2]],
+                [shape[0], shape[1], shape[2]],
+                output,
+                *move_to_origin,
+            )
+        }
+        (Some(Command::ExtractRegion { .. }), None) => Err(amethyst::Error::from_string(
+            "Expected a map file to extract from",
+        )),
+        (None, Some(map_file)) => run_app(map_file.clone(), &opt),
+        (None, None) => Err(amethyst::Error::from_string(
+            "Expected a map file or a subcommand, see --help",
+        )),
+    }
 }
@@ -0,0 +1,147 @@
+use crate::bindings::{ActionBinding, GameBindings};
+
+use voxel_mapper::voxel::{
+    background_save::{BackgroundSaves, SaveCompleted},
+    chunk_streaming::StoredChunks,
+    generation::GeneratedChunks,
+    VoxelMap,
+};
+
+use amethyst::{
+    assets::{AssetStorage, Loader},
+    core::ecs::prelude::*,
+    derive::SystemDesc,
+    input::InputEvent,
+    shrev::EventChannel,
+    ui::{get_default_font, Anchor, FontAsset, UiText, UiTransform},
+};
+use std::path::PathBuf;
+
+/// Where the editor saves the voxels of the open map.
+pub struct VoxelsSavePath(pub PathBuf);
+
+#[derive(Default)]
+pub struct SaveStatusText;
+
+impl Component for SaveStatusText {
+    type Storage = NullStorage<Self>;
+}
+
+const STATUS_TEXT_WIDTH: f32 = 600.0;
+const STATUS_TEXT_HEIGHT: f32 = 30.0;
+const STATUS_FONT_SIZE: f32 = 18.0;
+
+/// Creates an empty label in the bottom right corner for showing the save status.
+pub fn make_save_status_ui(world: &mut World) {
+    let font = world.exec(
+        |(loader, font_storage): (ReadExpect<Loader>, Read<AssetStorage<FontAsset>>)| {
+            get_default_font(&loader, &font_storage)
+        },
+    );
+
+    let transform = UiTransform::new(
+        "save_status".to_string(),
+        Anchor::BottomRight,
+        Anchor::BottomRight,
+        -10.0,
+        10.0,
+        1.0,
+        STATUS_TEXT_WIDTH,
+        STATUS_TEXT_HEIGHT,
+    );
+    let mut text = UiText::new(font, String::new(), [1.0, 1.0, 1.0, 1.0], STATUS_FONT_SIZE);
+    text.align = Anchor::BottomRight;
+
+    world
+        .create_entity()
+        .with(transform)
+        .with(text)
+        .with(SaveStatusText)
+        .build();
+}
+
+/// Starts a background save of the map when the save key is pressed, and shows the progress.
+#[derive(SystemDesc)]
+#[system_desc(name(MapSavingSystemDesc))]
+pub struct MapSavingSystem {
+    #[system_desc(event_channel_reader)]
+    input_reader_id: ReaderId<InputEvent<GameBindings>>,
+    #[system_desc(event_channel_reader)]
+    save_reader_id: ReaderId<SaveCompleted>,
+}
+
+impl MapSavingSystem {
+    pub fn new(
+        input_reader_id: ReaderId<InputEvent<GameBindings>>,
+        save_reader_id: ReaderId<SaveCompleted>,
+    ) -> Self {
+        MapSavingSystem {
+            input_reader_id,
+            save_reader_id,
+        }
+    }
+}
+
+impl<'a> System<'a> for MapSavingSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        Read<'a, EventChannel<InputEvent<GameBindings>>>,
+        Read<'a, EventChannel<SaveCompleted>>,
+        ReadExpect<'a, VoxelsSavePath>,
+        ReadExpect<'a, VoxelMap>,
+        Read<'a, StoredChunks>,
+        Read<'a, GeneratedChunks>,
+        Write<'a, BackgroundSaves>,
+        ReadStorage<'a, SaveStatusText>,
+        WriteStorage<'a, UiText>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            input_events,
+            save_events,
+            save_path,
+            voxel_map,
+            stored,
+            generated,
+            mut saves,
+            is_status_text,
+            mut texts,
+        ): Self::SystemData,
+    ) {
+        let mut status = None;
+
+        for input_event in input_events.read(&mut self.input_reader_id) {
+            if let InputEvent::ActionPressed(ActionBinding::SaveMap) = input_event {
+                if saves.start(&voxel_map, &stored, &generated, save_path.0.clone()) {
+                    log::info!("Saving voxels to {}", save_path.0.display());
+                    status = Some("Saving...".to_string());
+                } else {
+                    log::warn!("Already saving, try again when the current save is finished");
+                }
+            }
+        }
+
+        for event in save_events.read(&mut self.save_reader_id) {
+            status = Some(match &event.result {
+                Ok(()) => {
+                    log::info!("Saved voxels to {}", event.path.display());
+
+                    format!("Saved {}", event.path.display())
+                }
+                Err(e) => {
+                    log::error!("Failed to save {}: {}", event.path.display(), e);
+
+                    format!("Failed to save {}", event.path.display())
+                }
+            });
+        }
+
+        if let Some(status) = status {
+            for (_, text) in (&is_status_text, &mut texts).join() {
+                text.text = status.clone();
+            }
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use crate::{
+    bindings::{ActionBinding, GameBindings},
+    control::{
+        camera::{CameraControllerComponent, MainCameraTag, ThirdPersonCameraState},
+        hover_3d::ObjectsUnderCursor,
+    },
+};
+
+use voxel_mapper::voxel::{
+    markers::{MapMarkers, MarkerKind},
+    voxel_center, voxel_containing_point, VoxelMap,
+};
+
+use amethyst::{
+    core::{
+        ecs::prelude::*,
+        math::{Point3, Vector3},
+    },
+    derive::SystemDesc,
+    input::InputEvent,
+    renderer::{debug_drawing::DebugLinesComponent, palette::Srgba},
+    shrev::EventChannel,
+};
+use building_blocks::prelude::*;
+
+#[derive(Default)]
+pub struct MarkerHintTag;
+
+impl Component for MarkerHintTag {
+    type Storage = NullStorage<Self>;
+}
+
+pub fn make_marker_hint_lines(world: &mut World) {
+    world
+        .create_entity()
+        .with(MarkerHintTag)
+        .with(DebugLinesComponent::new())
+        .build();
+}
+
+/// Places a marker of the selected kind on the hovered surface, or removes the marker that's
+/// already there, and draws every marker as a post with a box on top.
+///
+/// Camera bookmarks are made from the main camera's current view instead, and the camera can jump
+/// through them in order.
+#[derive(SystemDesc)]
+#[system_desc(name(MarkerToolSystemDesc))]
+pub struct MarkerToolSystem {
+    #[system_desc(event_channel_reader)]
+    reader_id: ReaderId<InputEvent<GameBindings>>,
+    #[system_desc(skip)]
+    kind: MarkerKind,
+    /// The bookmark that the camera jumps to next.
+    #[system_desc(skip)]
+    next_bookmark: usize,
+}
+
+impl MarkerToolSystem {
+    pub fn new(reader_id: ReaderId<InputEvent<GameBindings>>) -> Self {
+        MarkerToolSystem {
+            reader_id,
+            kind: MarkerKind::SpawnPoint,
+            next_bookmark: 0,
+        }
+    }
+}
+
+/// Clicking within this many voxels of a marker removes it instead of placing a new one.
+const MARKER_PICK_RADIUS: f32 = 1.5;
+const MARKER_POST_HEIGHT: f32 = 3.0;
+
+impl<'a> System<'a> for MarkerToolSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        Read<'a, EventChannel<InputEvent<GameBindings>>>,
+        Read<'a, ObjectsUnderCursor>,
+        ReadExpect<'a, VoxelMap>,
+        Write<'a, MapMarkers>,
+        ReadStorage<'a, MainCameraTag>,
+        WriteStorage<'a, ThirdPersonCameraState>,
+        WriteStorage<'a, CameraControllerComponent>,
+        ReadStorage<'a, MarkerHintTag>,
+        WriteStorage<'a, DebugLinesComponent>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            input_events,
+            objects,
+            voxel_map,
+            mut markers,
+            is_main_camera,
+            mut tpc_states,
+            mut controllers,
+            is_hint,
+            mut debug_lines,
+        ): Self::SystemData,
+    ) {
+        let world_scale = voxel_map.world_scale;
+        for input_event in input_events.read(&mut self.reader_id) {
+            match input_event {
+                InputEvent::ActionPressed(ActionBinding::CycleMarkerKind) => {
+                    self.kind = self.kind.next();
+                    log::info!("Set marker kind to {:?}", self.kind);
+                }
+                InputEvent::ActionPressed(ActionBinding::ToggleMarker) => {
+                    if let Some(v) = &objects.voxel {
+                        let p = v.hover_adjacent_point();
+                        if let Some(removed) = markers.remove_near(p, MARKER_PICK_RADIUS) {
+                            log::info!("Removed marker {:?}", removed.name);
+                        } else {
+                            let name = markers.add(self.kind, p);
+                            log::info!("Placed marker {:?} at {:?}", name, p);
+                        }
+                    }
+                }
+                InputEvent::ActionPressed(ActionBinding::AddCameraBookmark) => {
+                    for (_, tpc_state) in (&is_main_camera, &tpc_states).join() {
+                        let name = markers.add_camera_bookmark(
+                            tpc_state.actual_position,
+                            voxel_containing_point(tpc_state.target, world_scale),
+                        );
+                        log::info!("Bookmarked the camera view as {:?}", name);
+                    }
+                }
+                InputEvent::ActionPressed(ActionBinding::NextCameraBookmark) => {
+                    let num_bookmarks = markers.camera_bookmarks().count();
+                    if num_bookmarks == 0 {
+                        log::warn!("There are no camera bookmarks");
+                        continue;
+                    }
+                    let i = self.next_bookmark % num_bookmarks;
+                    self.next_bookmark = i + 1;
+                    let bookmark = markers.camera_bookmarks().nth(i).unwrap();
+                    let eye = match bookmark.eye {
+                        Some(eye) => Point3::from(eye),
+                        None => continue,
+                    };
+                    let target = voxel_center(bookmark.position(), world_scale);
+                    for (_, tpc_state, controller) in
+                        (&is_main_camera, &mut tpc_states, &mut controllers).join()
+                    {
+                        *tpc_state = ThirdPersonCameraState::new(eye, target);
+                        controller.reset_smoothing();
+                    }
+                    log::info!("Jumped to camera bookmark {:?}", bookmark.name);
+                }
+                _ => (),
+            }
+        }
+
+        for (_, lines) in (&is_hint, &mut debug_lines).join() {
+            lines.clear();
+            for marker in markers.iter() {
+                let color = marker_color(marker.kind);
+                let base = (Point3::from(Point3f::from(marker.position()).0)
+                    + Vector3::new(0.5, 0.0, 0.5))
+                    * world_scale;
+                if let Some(eye) = marker.eye {
+                    // Bookmarks are drawn as the line of sight from the camera.
+                    lines.add_line(
+                        Point3::from(eye),
+                        voxel_center(marker.position(), world_scale),
+                        color,
+                    );
+                    continue;
+                }
+                let top = base + Vector3::new(0.0, MARKER_POST_HEIGHT * world_scale, 0.0);
+                let half_size = Vector3::repeat(0.5 * world_scale);
+                lines.add_line(base, top, color);
+                lines.add_box(top - half_size, top + half_size, color);
+            }
+        }
+    }
+}
+
+fn marker_color(kind: MarkerKind) -> Srgba {
+    match kind {
+        MarkerKind::SpawnPoint => Srgba::new(0.0, 1.0, 0.0, 1.0),
+        MarkerKind::Light => Srgba::new(1.0, 1.0, 0.0, 1.0),
+        MarkerKind::Item => Srgba::new(0.0, 0.5, 1.0, 1.0),
+        MarkerKind::Other => Srgba::new(1.0, 0.0, 1.0, 1.0),
+        MarkerKind::CameraBookmark => Srgba::new(1.0, 1.0, 1.0, 1.0),
+    }
+}
@@ -0,0 +1,162 @@
+use crate::{
+    bindings::{ActionBinding, GameBindings},
+    control::hover_3d::ObjectsUnderCursor,
+    voxel_brush::PaintBrush,
+};
+
+use voxel_mapper::voxel::{
+    centered_extent,
+    metadata::{VoxelMetadata, UNTAGGED},
+    voxel_center, VoxelMap,
+};
+
+use amethyst::{
+    core::{ecs::prelude::*, math::Vector3},
+    derive::SystemDesc,
+    input::{InputEvent, InputHandler},
+    renderer::{debug_drawing::DebugLinesComponent, palette::Srgba},
+    shrev::EventChannel,
+};
+use serde::{Deserialize, Serialize};
+
+/// The metadata values that can be painted, with the names they have in this project.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct MetadataTagsConfig {
+    pub tags: Vec<MetadataTag>,
+    /// Tagged voxels are only drawn within this many voxels of the cursor.
+    pub display_radius: u32,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MetadataTag {
+    pub value: u8,
+    pub name: String,
+    pub color: [f32; 3],
+}
+
+#[derive(Default)]
+pub struct MetadataHintTag;
+
+impl Component for MetadataHintTag {
+    type Storage = NullStorage<Self>;
+}
+
+pub fn make_metadata_hint_lines(world: &mut World) {
+    world
+        .create_entity()
+        .with(MetadataHintTag)
+        .with(DebugLinesComponent::new())
+        .build();
+}
+
+/// Paints the selected metadata tag into a sphere of the brush radius around the hovered voxel, or
+/// erases the tags there, while the keys are held. Tagged voxels near the cursor are drawn as small
+/// boxes in the color of their tag.
+#[derive(SystemDesc)]
+#[system_desc(name(MetadataToolSystemDesc))]
+pub struct MetadataToolSystem {
+    #[system_desc(event_channel_reader)]
+    reader_id: ReaderId<InputEvent<GameBindings>>,
+    #[system_desc(skip)]
+    selected_tag: usize,
+}
+
+impl MetadataToolSystem {
+    pub fn new(reader_id: ReaderId<InputEvent<GameBindings>>) -> Self {
+        MetadataToolSystem {
+            reader_id,
+            selected_tag: 0,
+        }
+    }
+}
+
+impl<'a> System<'a> for MetadataToolSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        Read<'a, EventChannel<InputEvent<GameBindings>>>,
+        Read<'a, InputHandler<GameBindings>>,
+        Read<'a, ObjectsUnderCursor>,
+        Read<'a, MetadataTagsConfig>,
+        ReadExpect<'a, PaintBrush>,
+        ReadExpect<'a, VoxelMap>,
+        Write<'a, VoxelMetadata>,
+        ReadStorage<'a, MetadataHintTag>,
+        WriteStorage<'a, DebugLinesComponent>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            input_events,
+            input_handler,
+            objects,
+            config,
+            brush,
+            voxel_map,
+            mut metadata,
+            is_hint,
+            mut debug_lines,
+        ): Self::SystemData,
+    ) {
+        for input_event in input_events.read(&mut self.reader_id) {
+            if let InputEvent::ActionPressed(ActionBinding::CycleMetadataTag) = input_event {
+                if config.tags.is_empty() {
+                    continue;
+                }
+                self.selected_tag = (self.selected_tag + 1) % config.tags.len();
+                let tag = &config.tags[self.selected_tag];
+                log::info!("Selected metadata tag {} ({})", tag.name, tag.value);
+            }
+        }
+
+        let hover_point = objects.voxel.as_ref().map(|v| v.hover_adjacent_point());
+        if let Some(center) = hover_point {
+            let value = if input_handler
+                .action_is_down(&ActionBinding::PaintMetadata)
+                .unwrap()
+            {
+                config.tags.get(self.selected_tag).map(|tag| tag.value)
+            } else if input_handler
+                .action_is_down(&ActionBinding::EraseMetadata)
+                .unwrap()
+            {
+                Some(UNTAGGED)
+            } else {
+                None
+            };
+            if let Some(value) = value {
+                let radius = brush.radius as f32;
+                metadata.paint(
+                    &centered_extent(center, brush.radius),
+                    |p| (p - center).norm() <= radius,
+                    value,
+                );
+            }
+        }
+
+        for (_, lines) in (&is_hint, &mut debug_lines).join() {
+            lines.clear();
+            let center = match hover_point {
+                Some(p) => p,
+                None => continue,
+            };
+            for (p, value) in metadata
+                .tagged_in_extent(&centered_extent(center, config.display_radius))
+                .into_iter()
+            {
+                let color = config
+                    .tags
+                    .iter()
+                    .find(|tag| tag.value == value)
+                    .map_or([1.0; 3], |tag| tag.color);
+                let center = voxel_center(p, voxel_map.world_scale);
+                let half_size = Vector3::repeat(0.2 * voxel_map.world_scale);
+                lines.add_box(
+                    center - half_size,
+                    center + half_size,
+                    Srgba::new(color[0], color[1], color[2], 1.0),
+                );
+            }
+        }
+    }
+}
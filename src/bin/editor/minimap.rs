@@ -0,0 +1,201 @@
+use crate::{
+    bindings::GameBindings,
+    control::camera::{CameraControllerComponent, MainCameraTag, ThirdPersonCameraState},
+};
+
+use voxel_mapper::{
+    rendering::atlas::rgba8_texture,
+    voxel::{
+        minimap::{Minimap, MinimapConfig},
+        voxel_center, voxel_containing_point, VoxelMap,
+    },
+};
+
+use amethyst::{
+    assets::AssetLoaderSystemData,
+    core::ecs::prelude::*,
+    derive::SystemDesc,
+    input::{InputEvent, InputHandler},
+    renderer::{rendy::hal::image::Filter, Texture},
+    shrev::EventChannel,
+    ui::{Anchor, UiImage, UiTransform},
+    window::ScreenDimensions,
+    winit::MouseButton,
+};
+use building_blocks::prelude::*;
+
+#[derive(Default)]
+pub struct MinimapImage;
+
+impl Component for MinimapImage {
+    type Storage = NullStorage<Self>;
+}
+
+/// Each minimap pixel is drawn this many screen pixels wide.
+const MINIMAP_SCALE: f32 = 2.0;
+/// The distance of the minimap from the bottom left corner of the window.
+const MINIMAP_MARGIN: f32 = 10.0;
+const CAMERA_COLOR: [u8; 4] = [255, 255, 255, 255];
+
+/// Creates an empty image in the bottom left corner for the minimap.
+pub fn make_minimap_ui(world: &mut World) {
+    let size = world.read_resource::<MinimapConfig>().size_pixels;
+    let texture = world.exec(|loader: AssetLoaderSystemData<Texture>| {
+        let pixels = vec![[0; 4]; (size * size) as usize];
+
+        loader.load_from_data(
+            rgba8_texture(size, size, 1, pixels, true, Filter::Nearest),
+            (),
+        )
+    });
+
+    let display_size = size as f32 * MINIMAP_SCALE;
+    let transform = UiTransform::new(
+        "minimap".to_string(),
+        Anchor::BottomLeft,
+        Anchor::BottomLeft,
+        MINIMAP_MARGIN,
+        MINIMAP_MARGIN,
+        1.0,
+        display_size,
+        display_size,
+    );
+
+    world
+        .create_entity()
+        .with(transform)
+        .with(UiImage::Texture(texture))
+        .with(MinimapImage)
+        .build();
+}
+
+/// Draws the `Minimap` around the camera into the minimap image whenever the map changes or the
+/// camera moves to another pixel. Clicking a pixel of the minimap moves the camera over the top of
+/// those columns.
+#[derive(SystemDesc)]
+#[system_desc(name(MinimapUiSystemDesc))]
+pub struct MinimapUiSystem {
+    #[system_desc(event_channel_reader)]
+    reader_id: ReaderId<InputEvent<GameBindings>>,
+    /// The minimap revision and first pixel of the image that was drawn last.
+    #[system_desc(skip)]
+    drawn: Option<(u64, [i32; 2])>,
+}
+
+impl MinimapUiSystem {
+    pub fn new(reader_id: ReaderId<InputEvent<GameBindings>>) -> Self {
+        MinimapUiSystem {
+            reader_id,
+            drawn: None,
+        }
+    }
+}
+
+impl<'a> System<'a> for MinimapUiSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        Read<'a, EventChannel<InputEvent<GameBindings>>>,
+        Read<'a, InputHandler<GameBindings>>,
+        ReadExpect<'a, ScreenDimensions>,
+        Read<'a, MinimapConfig>,
+        ReadExpect<'a, Minimap>,
+        ReadExpect<'a, VoxelMap>,
+        ReadStorage<'a, MainCameraTag>,
+        WriteStorage<'a, ThirdPersonCameraState>,
+        WriteStorage<'a, CameraControllerComponent>,
+        ReadStorage<'a, MinimapImage>,
+        WriteStorage<'a, UiImage>,
+        AssetLoaderSystemData<'a, Texture>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            input_events,
+            input_handler,
+            screen_dims,
+            config,
+            minimap,
+            voxel_map,
+            is_main_camera,
+            mut tpc_states,
+            mut controllers,
+            is_minimap_image,
+            mut images,
+            texture_loader,
+        ): Self::SystemData,
+    ) {
+        let size = config.size_pixels as i32;
+        let camera_feet = match (&is_main_camera, &tpc_states).join().next() {
+            Some((_, tpc_state)) => voxel_containing_point(tpc_state.feet, voxel_map.world_scale),
+            None => return,
+        };
+        let camera_pixel = minimap.pixel_containing(camera_feet.x(), camera_feet.z());
+        let min_pixel = [camera_pixel[0] - size / 2, camera_pixel[1] - size / 2];
+
+        // Find the minimap pixel that was clicked, if any. The image is anchored to the bottom of
+        // the window, but the mouse is measured from the top.
+        let mut clicked_pixel = None;
+        for input_event in input_events.read(&mut self.reader_id) {
+            if let InputEvent::MouseButtonPressed(MouseButton::Left) = input_event {
+                if let Some((x, y)) = input_handler.mouse_position() {
+                    let image_top =
+                        screen_dims.height() - MINIMAP_MARGIN - size as f32 * MINIMAP_SCALE;
+                    let col = ((x - MINIMAP_MARGIN) / MINIMAP_SCALE).floor() as i32;
+                    let row = ((y - image_top) / MINIMAP_SCALE).floor() as i32;
+                    if (0..size).contains(&col) && (0..size).contains(&row) {
+                        clicked_pixel = Some([min_pixel[0] + col, min_pixel[1] + row]);
+                    }
+                }
+            }
+        }
+        if let Some(pixel) = clicked_pixel {
+            if let Some(cell) = minimap.cell(pixel) {
+                let half = minimap.voxels_per_pixel() / 2;
+                let target = voxel_center(
+                    PointN([
+                        pixel[0] * minimap.voxels_per_pixel() + half,
+                        cell.top + 1,
+                        pixel[1] * minimap.voxels_per_pixel() + half,
+                    ]),
+                    voxel_map.world_scale,
+                );
+                for (_, tpc_state, controller) in
+                    (&is_main_camera, &mut tpc_states, &mut controllers).join()
+                {
+                    // Keep looking from the same direction and distance.
+                    let eye = target + (tpc_state.actual_position - tpc_state.target);
+                    *tpc_state = ThirdPersonCameraState::new(eye, target);
+                    controller.reset_smoothing();
+                }
+                log::info!("Moved the camera to {:?}", target);
+            }
+        }
+
+        if self.drawn == Some((minimap.revision(), min_pixel)) {
+            return;
+        }
+        self.drawn = Some((minimap.revision(), min_pixel));
+
+        let mut pixels = minimap.draw(min_pixel, config.size_pixels, |t| config.color(t));
+        // Mark the camera with a small cross.
+        let center = size / 2;
+        for (dx, dy) in [(0, 0), (-1, 0), (1, 0), (0, -1), (0, 1)].iter() {
+            pixels[((center + dy) * size + center + dx) as usize] = CAMERA_COLOR;
+        }
+        let texture = texture_loader.load_from_data(
+            rgba8_texture(
+                config.size_pixels,
+                config.size_pixels,
+                1,
+                pixels,
+                true,
+                Filter::Nearest,
+            ),
+            (),
+        );
+        for (_, image) in (&is_minimap_image, &mut images).join() {
+            *image = UiImage::Texture(texture.clone());
+        }
+    }
+}
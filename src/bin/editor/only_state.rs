@@ -1,41 +1,117 @@
 use crate::{
-    control::camera::make_camera, debug_feet::make_camera_feet_lines,
-    hover_hint::make_hover_hint_lines, voxel_brush::PaintBrush,
+    asset_errors::make_asset_error_ui,
+    cache_stats_overlay::make_cache_stats_ui,
+    chunk_lock_tool::make_locked_chunk_hint_lines,
+    control::camera::{make_camera, MainCameraTag, ThirdPersonCameraState},
+    debug_feet::make_camera_feet_lines,
+    editor_panel::make_editor_panel,
+    fluid_tool::make_fluid_source_hint_lines,
+    gizmo::make_gizmo_lines,
+    hotbar::{make_hotbar_ui, Hotbar, HotbarConfig},
+    hover_hint::make_hover_hint_lines,
+    light_tool::{make_light_hint_lines, make_map_lights},
+    map_saving::{make_save_status_ui, VoxelsSavePath},
+    marker_tool::make_marker_hint_lines,
+    metadata_tool::{make_metadata_hint_lines, MetadataTagsConfig},
+    minimap::make_minimap_ui,
+    palette_editor::PaletteChanged,
+    path_tool::make_path_hint_lines,
+    primitive_tool::make_primitive_hint_lines,
+    prop_tool::{make_prop_hint_lines, PropLibrary},
+    selection::make_selection_hint_lines,
+    status_bar::make_status_bar_ui,
+    voxel_brush::{BrushConfig, PaintBrush},
+    zone_tool::make_zone_hint_lines,
 };
 
+#[cfg(feature = "scripting")]
+use crate::script_tool::ScriptFile;
+
 use voxel_mapper::{
     collision::{insert_all_chunk_bvts, VoxelBVT},
+    rendering::{
+        chunk_culling::{insert_all_occluder_chunks, OccluderChunks},
+        day_night::{start_day_night_cycle, DayNightConfig},
+        frame_capture::FrameCaptureConfig,
+        merged_chunk_meshes::MergedChunkMeshes,
+    },
     voxel::{
-        asset_loader::VoxelAssetLoader, map_file::load_voxel_map,
-        meshing::manager::VoxelMeshManager, VoxelMap, VoxelType,
+        asset_loader::VoxelAssetLoader,
+        background_save::BackgroundSaves,
+        chunk_lock::LockedChunks,
+        double_buffer::EditedChunksBackBuffer,
+        edit_journal::{EditJournal, EditReplay},
+        edit_limits::EditLimits,
+        erosion::ErosionConfig,
+        falling::FallingVoxelsConfig,
+        fluid::{FluidConfig, FluidField, FluidSources},
+        generation::{ChunkGenerationRequests, StreamingConfig},
+        lights::MapLights,
+        map_file::{MapFileError, VoxelMapFile},
+        markers::MapMarkers,
+        meshing::manager::VoxelMeshManager,
+        metadata::VoxelMetadata,
+        minimap::{insert_all_minimap_chunks, Minimap, MinimapConfig},
+        network::EditSession,
+        props::MapProps,
+        session_recording::{SessionPlayback, SessionRecorder, SessionRecording},
+        stamps::StampLibrary,
+        voxel_containing_point,
+        zones::MapZones,
+        VoxelMap,
     },
 };
 
 use amethyst::{
     assets::ProgressCounter,
+    config::Config,
     core::{
         ecs::prelude::*,
         math::{Point3, Vector3},
-        Transform,
     },
     input::{is_key_down, VirtualKeyCode},
     prelude::*,
-    renderer::{
-        debug_drawing::DebugLinesComponent,
-        light::{Light, PointLight},
-        palette::{rgb::Rgb, Srgba},
-    },
+    renderer::{debug_drawing::DebugLinesComponent, palette::Srgba},
+    utils::application_dir,
 };
-use building_blocks::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+/// Options for an editing session, from the command line.
+#[derive(Default)]
+pub struct SessionOptions {
+    pub deterministic_edits: bool,
+    /// Where to save the `EditJournal` on exit.
+    pub record_edits: Option<PathBuf>,
+    /// A journal to replay onto the map, and the playback speed.
+    pub replay_edits: Option<(PathBuf, f64)>,
+    /// Where to save the `SessionRecording` on exit.
+    pub record_session: Option<PathBuf>,
+    /// A session recording to play back, and the playback speed.
+    pub replay_session: Option<(PathBuf, f64)>,
+    /// Where to save the voxels, if not the map's own voxels file.
+    pub save_as: Option<PathBuf>,
+    /// The address to host a shared editing session on.
+    pub host_session: Option<String>,
+    /// The address of a shared editing session to join.
+    pub join_session: Option<String>,
+    /// A script for the `ScriptToolSystem`.
+    pub script: Option<PathBuf>,
+}
 
 pub struct OnlyState {
     map_file: PathBuf,
+    /// Parsed from `map_file` on start, and written back on exit if anything in it changed.
+    map_spec: Option<VoxelMapFile>,
+    options: SessionOptions,
 }
 
 impl OnlyState {
-    pub fn new(map_file: PathBuf) -> Self {
-        OnlyState { map_file }
+    pub fn new(map_file: PathBuf, options: SessionOptions) -> Self {
+        OnlyState {
+            map_file,
+            map_spec: None,
+            options,
+        }
     }
 }
 
@@ -43,38 +119,178 @@ impl SimpleState for OnlyState {
     fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
         let StateData { world, .. } = data;
 
-        world.insert(PaintBrush {
-            radius: 10,
-            voxel_type: VoxelType(1),
-            dist_from_camera: None,
-        });
-
-        // TODO: eventually, we will have very large maps that we shouldn't load in entirety here
-
-        let map = load_voxel_map(&self.map_file).expect("Failed to load voxel map");
+        let config_dir = application_dir("assets/config").unwrap();
+        let mut brush = PaintBrush::new(
+            BrushConfig::load(config_dir.join("brush.ron")).expect("Failed to load brush config"),
+        );
+        let hotbar = Hotbar::new(
+            HotbarConfig::load(config_dir.join("hotbar.ron"))
+                .expect("Failed to load hotbar config"),
+        );
+        if let Some(voxel_type) = hotbar.selected_voxel_type() {
+            brush.voxel_type = voxel_type;
+        }
+        make_hotbar_ui(&hotbar, world);
+        world.insert(
+            MetadataTagsConfig::load(config_dir.join("metadata_tags.ron"))
+                .expect("Failed to load metadata tags config"),
+        );
+        make_asset_error_ui(world);
+        make_cache_stats_ui(world);
+        make_editor_panel(world);
+        make_status_bar_ui(world);
+        make_save_status_ui(world);
+        world.insert(hotbar);
+        world.insert(brush);
+        let stamps = StampLibrary::load_dir(application_dir("assets/stamps").unwrap());
+        log::info!("Loaded {} stamps", stamps.stamps().len());
+        world.insert(stamps);
+        let prop_library = PropLibrary::load_dir(application_dir("assets/props").unwrap());
+        world.insert(prop_library);
+        world.insert(
+            ErosionConfig::load(config_dir.join("erosion.ron"))
+                .expect("Failed to load erosion config"),
+        );
+        world.insert(
+            EditLimits::load(config_dir.join("edit_limits.ron"))
+                .expect("Failed to load edit limits"),
+        );
+        world.insert(
+            StreamingConfig::load(config_dir.join("streaming.ron"))
+                .expect("Failed to load streaming config"),
+        );
+        world.insert(
+            FallingVoxelsConfig::load(config_dir.join("falling.ron"))
+                .expect("Failed to load falling voxels config"),
+        );
+        world.insert(
+            FluidConfig::load(config_dir.join("fluid.ron")).expect("Failed to load fluid config"),
+        );
+        world.insert(
+            FrameCaptureConfig::load(config_dir.join("frame_capture.ron"))
+                .expect("Failed to load frame capture config"),
+        );
+        let minimap_config = MinimapConfig::load(config_dir.join("minimap.ron"))
+            .expect("Failed to load minimap config");
+        let mut minimap = Minimap::new(minimap_config.voxels_per_pixel);
+        world.insert(minimap_config);
+        make_minimap_ui(world);
 
-        let local_chunk_cache = LocalChunkCache3::new();
+        // Chunks are streamed in around the camera by the `ChunkStreamingSystem`, so large maps
+        // don't need to fit in memory all at once.
+        let map_spec =
+            VoxelMapFile::load_with_edits(&self.map_file).expect("Failed to load map file");
+        let (map, stored_chunks) = map_spec
+            .load_streamed_voxel_map()
+            .expect("Failed to load voxel map");
+        {
+            let mut backbuffer = world.write_resource::<EditedChunksBackBuffer>();
+            *backbuffer = EditedChunksBackBuffer::with_chunk_shape(map.chunk_shape());
+            backbuffer.set_voxel_source(map_spec.voxel_source());
+            // Edits made before the stored chunks stream in start from their stored copies.
+            backbuffer.set_stored_chunks(Some(stored_chunks.reader()));
+            backbuffer.set_deterministic(self.options.deterministic_edits);
+        }
+        if self.options.record_edits.is_some() {
+            world.insert(EditJournal::recording());
+        }
+        if let Some((journal_path, speed)) = &self.options.replay_edits {
+            let journal = EditJournal::load(journal_path).expect("Failed to load edit journal");
+            world.insert(EditReplay::new(journal, *speed));
+        }
+        if let Some((recording_path, speed)) = &self.options.replay_session {
+            let recording =
+                SessionRecording::load(recording_path).expect("Failed to load session recording");
+            world.insert(SessionPlayback::new(recording, *speed));
+        }
+        world.insert(stored_chunks);
+        world.insert(map_spec.locked_chunks());
+        world.insert(map_spec.markers());
+        world.insert(map_spec.zones());
+        world.insert(map_spec.fluid_sources());
+        world.insert(FluidField::with_chunk_shape(map.chunk_shape()));
+        world.insert(MergedChunkMeshes::with_chunk_shape(map.chunk_shape()));
+        world.insert(
+            map_spec
+                .load_voxel_metadata()
+                .expect("Failed to load voxel metadata"),
+        );
+        world.insert(map_spec.props());
+        let save_path = self
+            .options
+            .save_as
+            .clone()
+            .unwrap_or_else(|| map_spec.voxels_save_path());
+        log::info!("Voxels will be saved to {}", save_path.display());
+        world.insert(VoxelsSavePath(save_path));
 
         let mut assets = world.exec(|mut loader: VoxelAssetLoader| {
             let mut unused_progress = ProgressCounter::new();
 
-            loader.start_loading(&map, &local_chunk_cache, &mut unused_progress)
+            loader.start_loading(&map, &mut unused_progress)
         });
         world.exec(
-            |(mut voxel_bvt, mut manager): (WriteExpect<VoxelBVT>, VoxelMeshManager)| {
-                insert_all_chunk_bvts(&mut voxel_bvt, &map, &local_chunk_cache);
+            |(mut voxel_bvt, mut occluders, mut manager): (
+                WriteExpect<VoxelBVT>,
+                Write<OccluderChunks>,
+                VoxelMeshManager,
+            )| {
+                insert_all_chunk_bvts(&mut voxel_bvt, &map);
+                insert_all_occluder_chunks(&mut occluders, &map);
                 manager.make_all_chunk_mesh_entities(&mut assets, &map);
             },
         );
+        insert_all_minimap_chunks(&mut minimap, &map);
+        world.insert(minimap);
         world.insert(assets);
+        if self.options.record_session.is_some() {
+            let mut recorder = SessionRecorder::default();
+            recorder
+                .start(&map)
+                .expect("Failed to start recording the session");
+            world.insert(recorder);
+        }
         world.insert(map);
 
+        let session = if let Some(address) = &self.options.host_session {
+            Some(EditSession::host(address).expect("Failed to host edit session"))
+        } else if let Some(address) = &self.options.join_session {
+            Some(EditSession::connect(address).expect("Failed to join edit session"))
+        } else {
+            None
+        };
+        world.insert(session);
+
+        #[cfg(feature = "scripting")]
+        world.insert(ScriptFile(self.options.script.clone()));
+        #[cfg(not(feature = "scripting"))]
+        if self.options.script.is_some() {
+            log::warn!("Scripts can only be run with the \"scripting\" feature");
+        }
+
         make_hover_hint_lines(world);
+        make_path_hint_lines(world);
+        make_primitive_hint_lines(world);
+        make_selection_hint_lines(world);
+        make_gizmo_lines(world);
+        make_locked_chunk_hint_lines(world);
+        make_marker_hint_lines(world);
+        make_zone_hint_lines(world);
+        make_light_hint_lines(world);
+        make_fluid_source_hint_lines(world);
+        make_metadata_hint_lines(world);
+        make_prop_hint_lines(world);
         make_gridlines(100, world);
-        make_sunlight([-100.0, 100.0, -100.0], 2.0, world);
-        make_sunlight([-100.0, 100.0, 100.0], 2.0, world);
-        make_sunlight([100.0, 100.0, -100.0], 2.0, world);
-        make_sunlight([100.0, 100.0, 100.0], 2.0, world);
+
+        start_day_night_cycle(
+            DayNightConfig::load(config_dir.join("day_night.ron"))
+                .expect("Failed to load day/night config"),
+            world,
+        );
+        let lights = map_spec.lights();
+        make_map_lights(&lights, world);
+        world.insert(lights);
+        self.map_spec = Some(map_spec);
 
         // Make sure the camera position is not too close to the target, or you won't see anything
         // on start.
@@ -85,6 +301,26 @@ impl SimpleState for OnlyState {
         make_camera_feet_lines(world);
     }
 
+    fn update(&mut self, data: &mut StateData<'_, GameData<'_, '_>>) -> SimpleTrans {
+        // Stream in (or generate) the chunks around the camera, so there's something to stand on
+        // wherever it goes.
+        data.world.exec(
+            |(is_main_camera, tpc_states, voxel_map, mut generation_requests): (
+                ReadStorage<MainCameraTag>,
+                ReadStorage<ThirdPersonCameraState>,
+                ReadExpect<VoxelMap>,
+                Write<ChunkGenerationRequests>,
+            )| {
+                for (_, tpc_state) in (&is_main_camera, &tpc_states).join() {
+                    let feet = voxel_containing_point(tpc_state.feet, voxel_map.world_scale);
+                    generation_requests.request_around(feet);
+                }
+            },
+        );
+
+        Trans::None
+    }
+
     fn handle_event(
         &mut self,
         _data: StateData<'_, GameData<'_, '_>>,
@@ -101,12 +337,42 @@ impl SimpleState for OnlyState {
 
     fn on_stop(&mut self, data: StateData<'_, GameData<'_, '_>>) {
         data.world.exec(
-            |(mut manager, map): (VoxelMeshManager, ReadExpect<VoxelMap>)| {
+            |(mut manager, mut merged_meshes): (VoxelMeshManager, Write<MergedChunkMeshes>)| {
                 manager.destroy();
-
-                // save_voxel_map("saved_voxels.bin", &map).expect("Failed to save voxels");
+                merged_meshes.clear();
             },
         );
+
+        // Don't exit in the middle of writing the voxels file.
+        for completed in data
+            .world
+            .write_resource::<BackgroundSaves>()
+            .wait_for_all()
+        {
+            if let Err(e) = completed.result {
+                log::error!("Failed to save {}: {}", completed.path.display(), e);
+            }
+        }
+
+        if let Some(map_spec) = self.map_spec.as_mut() {
+            if let Err(e) = save_map_changes(data.world, &self.map_file, map_spec) {
+                log::error!("Failed to save {}: {}", self.map_file.display(), e);
+            }
+        }
+
+        if let Some(journal_path) = &self.options.record_edits {
+            let journal = data.world.read_resource::<EditJournal>();
+            if let Err(e) = journal.save(journal_path) {
+                log::error!("Failed to save edit journal: {:?}", e);
+            }
+        }
+
+        if let Some(recording_path) = &self.options.record_session {
+            let recording = data.world.write_resource::<SessionRecorder>().take();
+            if let Some(Err(e)) = recording.map(|r| r.save(recording_path)) {
+                log::error!("Failed to save session recording: {:?}", e);
+            }
+        }
     }
 }
 
@@ -134,15 +400,61 @@ fn make_gridlines(num_grid_lines: usize, world: &mut World) {
     world.create_entity().with(lines).build();
 }
 
-fn make_sunlight(position: [f32; 3], intensity: f32, world: &mut World) {
-    let light: Light = PointLight {
-        intensity,
-        color: Rgb::new(1.0, 1.0, 1.0),
-        ..PointLight::default()
+/// Writes the map's edits file if the palette or any of the things placed in the map changed. The
+/// map file itself is left as it was written.
+fn save_map_changes(
+    world: &World,
+    map_file: &Path,
+    map_spec: &mut VoxelMapFile,
+) -> Result<(), MapFileError> {
+    let mut changed = false;
+
+    let locked_chunks = world.read_resource::<LockedChunks>();
+    if locked_chunks.has_changed() {
+        map_spec.set_locked_chunks(&locked_chunks);
+        changed = true;
+    }
+    let markers = world.read_resource::<MapMarkers>();
+    if markers.has_changed() {
+        map_spec.set_markers(&markers);
+        changed = true;
+    }
+    let zones = world.read_resource::<MapZones>();
+    if zones.has_changed() {
+        map_spec.set_zones(&zones);
+        changed = true;
+    }
+    let lights = world.read_resource::<MapLights>();
+    if lights.has_changed() {
+        map_spec.set_lights(&lights);
+        changed = true;
+    }
+    let fluid_sources = world.read_resource::<FluidSources>();
+    if fluid_sources.has_changed() {
+        map_spec.set_fluid_sources(&fluid_sources);
+        changed = true;
+    }
+    let metadata = world.read_resource::<VoxelMetadata>();
+    if metadata.has_changed() {
+        // The rest of the changes are still worth saving without the metadata.
+        match map_spec.save_voxel_metadata(&metadata) {
+            Ok(()) => changed = true,
+            Err(e) => log::error!("Failed to save voxel metadata: {}", e),
+        }
+    }
+    let props = world.read_resource::<MapProps>();
+    if props.has_changed() {
+        map_spec.set_props(&props);
+        changed = true;
+    }
+    if world.read_resource::<PaletteChanged>().0 {
+        map_spec.set_palette(&world.read_resource::<VoxelMap>().palette);
+        changed = true;
+    }
+
+    if changed {
+        map_spec.write_edits(map_file)?;
     }
-    .into();
-    let mut tfm = Transform::default();
-    *tfm.translation_mut() = Vector3::from(position);
 
-    world.create_entity().with(light).with(tfm).build();
+    Ok(())
 }
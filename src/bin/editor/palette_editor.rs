@@ -0,0 +1,107 @@
+use crate::{
+    bindings::{ActionBinding, GameBindings},
+    voxel_brush::PaintBrush,
+};
+
+use voxel_mapper::voxel::{
+    double_buffer::EditedChunksBackBuffer, morton::morton_ordered_chunk_mins, VoxelMap,
+};
+
+use amethyst::{core::ecs::prelude::*, derive::SystemDesc, input::InputEvent, shrev::EventChannel};
+
+/// Set when the palette is edited at runtime, so it gets written back to the map file on exit.
+#[derive(Default)]
+pub struct PaletteChanged(pub bool);
+
+/// Edits the palette entry of the brush's voxel type: its material index and flags. New entries are
+/// copied from the brush type, and the brush switches to them.
+#[derive(SystemDesc)]
+#[system_desc(name(PaletteEditorSystemDesc))]
+pub struct PaletteEditorSystem {
+    #[system_desc(event_channel_reader)]
+    reader_id: ReaderId<InputEvent<GameBindings>>,
+}
+
+impl PaletteEditorSystem {
+    pub fn new(reader_id: ReaderId<InputEvent<GameBindings>>) -> Self {
+        PaletteEditorSystem { reader_id }
+    }
+}
+
+impl<'a> System<'a> for PaletteEditorSystem {
+    type SystemData = (
+        Read<'a, EventChannel<InputEvent<GameBindings>>>,
+        Write<'a, PaletteChanged>,
+        WriteExpect<'a, PaintBrush>,
+        WriteExpect<'a, VoxelMap>,
+        WriteExpect<'a, EditedChunksBackBuffer>,
+    );
+
+    fn run(
+        &mut self,
+        (input_events, mut changed, mut brush, mut voxel_map, mut voxel_backbuffer): Self::SystemData,
+    ) {
+        let mut needs_remesh = false;
+        for input_event in input_events.read(&mut self.reader_id) {
+            let action = match input_event {
+                InputEvent::ActionPressed(action) => action,
+                _ => continue,
+            };
+            let palette = &mut voxel_map.palette;
+            let voxel_type = brush.voxel_type;
+            match action {
+                ActionBinding::AddPaletteEntry => {
+                    let info = palette.get_voxel_type_info(voxel_type).clone();
+                    if let Some(new_type) = palette.add_voxel_type(info) {
+                        brush.voxel_type = new_type;
+                        log::info!("Added {:?}, copied from {:?}", new_type, voxel_type);
+                    } else {
+                        log::warn!("The palette is full");
+                        continue;
+                    }
+                }
+                ActionBinding::NextPaletteMaterial | ActionBinding::PreviousPaletteMaterial => {
+                    let info = palette.get_voxel_type_info_mut(voxel_type);
+                    let index = &mut info.material_index.0;
+                    *index = if *action == ActionBinding::NextPaletteMaterial {
+                        index.wrapping_add(1)
+                    } else {
+                        index.wrapping_sub(1)
+                    };
+                    log::info!("Set {:?} material index to {}", voxel_type, index);
+                    needs_remesh = true;
+                }
+                ActionBinding::TogglePaletteFloor => {
+                    let flags = &mut palette.get_voxel_type_info_mut(voxel_type).flags;
+                    flags.is_floor = !flags.is_floor;
+                    log::info!("Set {:?} is_floor to {}", voxel_type, flags.is_floor);
+                }
+                ActionBinding::TogglePaletteEmpty => {
+                    let flags = &mut palette.get_voxel_type_info_mut(voxel_type).flags;
+                    flags.is_empty = !flags.is_empty;
+                    log::info!("Set {:?} is_empty to {}", voxel_type, flags.is_empty);
+                    needs_remesh = true;
+                }
+                ActionBinding::TogglePaletteGravity => {
+                    let flags = &mut palette.get_voxel_type_info_mut(voxel_type).flags;
+                    flags.is_gravity_affected = !flags.is_gravity_affected;
+                    log::info!(
+                        "Set {:?} is_gravity_affected to {}",
+                        voxel_type,
+                        flags.is_gravity_affected
+                    );
+                    // Dirty every chunk so voxels that were resting in midair start to fall.
+                    needs_remesh |= flags.is_gravity_affected;
+                }
+                _ => continue,
+            }
+            changed.0 = true;
+        }
+
+        // Meshes and collision octrees are built from the palette, so every chunk that might use the
+        // edited type has to be rebuilt.
+        if needs_remesh {
+            voxel_backbuffer.mark_chunks_dirty(morton_ordered_chunk_mins(&voxel_map));
+        }
+    }
+}
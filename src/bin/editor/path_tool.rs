@@ -0,0 +1,141 @@
+use crate::{
+    bindings::{ActionBinding, GameBindings},
+    control::hover_3d::ObjectsUnderCursor,
+    voxel_brush::PaintBrush,
+};
+
+use voxel_mapper::voxel::{
+    chunk_cache_flusher::ChunkCacheFlusher,
+    double_buffer::EditedChunksBackBuffer,
+    spline::{carve_path, CatmullRomSpline, PathProfile},
+    voxel_center, VoxelMap,
+};
+
+use amethyst::{
+    core::{ecs::prelude::*, math::Point3},
+    derive::SystemDesc,
+    input::InputEvent,
+    renderer::{debug_drawing::DebugLinesComponent, palette::Srgba},
+    shrev::EventChannel,
+};
+use building_blocks::prelude::*;
+
+/// The control points of the path currently being authored.
+#[derive(Default)]
+pub struct PathTool {
+    pub spline: CatmullRomSpline,
+}
+
+#[derive(Default)]
+pub struct PathHintTag;
+
+impl Component for PathHintTag {
+    type Storage = NullStorage<Self>;
+}
+
+pub fn make_path_hint_lines(world: &mut World) {
+    world
+        .create_entity()
+        .with(PathHintTag)
+        .with(DebugLinesComponent::new())
+        .build();
+}
+
+/// Lets the user drop control points on the terrain and carve a road along the spline through
+/// them. The road is as wide as the brush and painted with the brush's voxel type.
+#[derive(SystemDesc)]
+#[system_desc(name(PathToolSystemDesc))]
+pub struct PathToolSystem {
+    #[system_desc(event_channel_reader)]
+    reader_id: ReaderId<InputEvent<GameBindings>>,
+}
+
+impl PathToolSystem {
+    pub fn new(reader_id: ReaderId<InputEvent<GameBindings>>) -> Self {
+        PathToolSystem { reader_id }
+    }
+}
+
+impl<'a> System<'a> for PathToolSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        Read<'a, EventChannel<InputEvent<GameBindings>>>,
+        Read<'a, ObjectsUnderCursor>,
+        ReadExpect<'a, VoxelMap>,
+        ReadExpect<'a, ChunkCacheFlusher>,
+        ReadExpect<'a, PaintBrush>,
+        Write<'a, PathTool>,
+        WriteExpect<'a, EditedChunksBackBuffer>,
+        ReadStorage<'a, PathHintTag>,
+        WriteStorage<'a, DebugLinesComponent>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            input_events,
+            objects,
+            voxel_map,
+            cache_flusher,
+            brush,
+            mut path_tool,
+            mut voxel_backbuffer,
+            is_hint,
+            mut debug_lines,
+        ): Self::SystemData,
+    ) {
+        for input_event in input_events.read(&mut self.reader_id) {
+            match input_event {
+                InputEvent::ActionPressed(ActionBinding::AddPathPoint) => {
+                    if let Some(v) = &objects.voxel {
+                        // Put the control point on top of the hovered face, i.e. the bottom of the
+                        // adjacent empty voxel.
+                        let mut point =
+                            voxel_center(v.hover_adjacent_point(), voxel_map.world_scale);
+                        point.y -= 0.5 * voxel_map.world_scale;
+                        path_tool.spline.control_points.push(point);
+                        log::info!("Added path control point {}", point);
+                    }
+                }
+                InputEvent::ActionPressed(ActionBinding::ClearPath) => {
+                    path_tool.spline.control_points.clear();
+                }
+                InputEvent::ActionPressed(ActionBinding::CarvePath) => {
+                    if path_tool.spline.num_segments() == 0 {
+                        log::warn!("Need at least 2 control points to carve a path");
+                        continue;
+                    }
+                    let profile = PathProfile {
+                        half_width: brush.radius as f32,
+                        clearance: 2.0 * brush.radius as f32,
+                        fill_depth: brush.radius as f32,
+                        voxel_type: brush.voxel_type,
+                    };
+                    let local_cache = LocalChunkCache3::new();
+                    let map_reader = voxel_map.voxels.reader(&local_cache);
+                    carve_path(
+                        &map_reader,
+                        &path_tool.spline,
+                        &profile,
+                        &mut *voxel_backbuffer,
+                    );
+                    cache_flusher.flush(local_cache);
+                    path_tool.spline.control_points.clear();
+                }
+                _ => (),
+            }
+        }
+
+        for (_, lines) in (&is_hint, &mut debug_lines).join() {
+            lines.clear();
+            let color = Srgba::new(1.0, 1.0, 0.0, 1.0);
+            for p in path_tool.spline.control_points.iter() {
+                lines.add_sphere(*p, 0.3, 8, 8, color);
+            }
+            let polyline: Vec<Point3<f32>> = path_tool.spline.to_polyline(1.0);
+            for (a, b) in polyline.iter().zip(polyline.iter().skip(1)) {
+                lines.add_line(*a, *b, color);
+            }
+        }
+    }
+}
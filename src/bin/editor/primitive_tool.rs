@@ -0,0 +1,163 @@
+use crate::{
+    bindings::{ActionBinding, GameBindings},
+    control::hover_3d::ObjectsUnderCursor,
+    voxel_brush::PaintBrush,
+};
+
+use voxel_mapper::voxel::{
+    chunk_cache_flusher::ChunkCacheFlusher,
+    double_buffer::EditedChunksBackBuffer,
+    sdf_primitives::{rasterize_primitive, PrimitiveKind, SdfPrimitive},
+    sphere_brush::SetVoxelOperation,
+    voxel_center, VoxelMap,
+};
+
+use amethyst::{
+    core::{ecs::prelude::*, math as na},
+    derive::SystemDesc,
+    input::InputEvent,
+    renderer::{debug_drawing::DebugLinesComponent, palette::Srgba},
+    shrev::EventChannel,
+};
+use building_blocks::prelude::*;
+
+/// The primitive being dragged out, if any.
+#[derive(Default)]
+pub struct PrimitiveTool {
+    pub kind: PrimitiveKind,
+    /// The center of the primitive, while its size is being dragged.
+    pub anchor: Option<(Point3i, SetVoxelOperation)>,
+}
+
+impl PrimitiveTool {
+    /// The primitive sized so its surface passes through the hovered voxel.
+    fn sized_primitive(&self, anchor: Point3i, objects: &ObjectsUnderCursor) -> SdfPrimitive {
+        let size = objects
+            .voxel
+            .as_ref()
+            .map_or(1.0, |v| (*v.point() - anchor).norm().max(1.0));
+
+        self.kind.with_size(size)
+    }
+}
+
+#[derive(Default)]
+pub struct PrimitiveHintTag;
+
+impl Component for PrimitiveHintTag {
+    type Storage = NullStorage<Self>;
+}
+
+pub fn make_primitive_hint_lines(world: &mut World) {
+    world
+        .create_entity()
+        .with(PrimitiveHintTag)
+        .with(DebugLinesComponent::new())
+        .build();
+}
+
+/// Places an analytic shape centered on the hovered voxel. Hold the place (or carve) key and move
+/// the cursor to size the shape, then release to add it to (or subtract it from) the map.
+#[derive(SystemDesc)]
+#[system_desc(name(PrimitiveToolSystemDesc))]
+pub struct PrimitiveToolSystem {
+    #[system_desc(event_channel_reader)]
+    reader_id: ReaderId<InputEvent<GameBindings>>,
+}
+
+impl PrimitiveToolSystem {
+    pub fn new(reader_id: ReaderId<InputEvent<GameBindings>>) -> Self {
+        PrimitiveToolSystem { reader_id }
+    }
+}
+
+impl<'a> System<'a> for PrimitiveToolSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        Read<'a, EventChannel<InputEvent<GameBindings>>>,
+        Read<'a, ObjectsUnderCursor>,
+        ReadExpect<'a, VoxelMap>,
+        ReadExpect<'a, ChunkCacheFlusher>,
+        ReadExpect<'a, PaintBrush>,
+        Write<'a, PrimitiveTool>,
+        WriteExpect<'a, EditedChunksBackBuffer>,
+        ReadStorage<'a, PrimitiveHintTag>,
+        WriteStorage<'a, DebugLinesComponent>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            input_events,
+            objects,
+            voxel_map,
+            cache_flusher,
+            brush,
+            mut tool,
+            mut voxel_backbuffer,
+            is_hint,
+            mut debug_lines,
+        ): Self::SystemData,
+    ) {
+        for input_event in input_events.read(&mut self.reader_id) {
+            match input_event {
+                InputEvent::ActionPressed(ActionBinding::CyclePrimitiveKind) => {
+                    tool.kind = tool.kind.next();
+                    log::info!("Primitive kind is {:?}", tool.kind);
+                }
+                InputEvent::ActionPressed(ActionBinding::PlacePrimitive) => {
+                    if let Some(v) = &objects.voxel {
+                        tool.anchor =
+                            Some((v.hover_adjacent_point(), SetVoxelOperation::MakeSolid));
+                    }
+                }
+                InputEvent::ActionPressed(ActionBinding::CarvePrimitive) => {
+                    if let Some(v) = &objects.voxel {
+                        tool.anchor = Some((*v.point(), SetVoxelOperation::RemoveSolid));
+                    }
+                }
+                InputEvent::ActionReleased(ActionBinding::PlacePrimitive)
+                | InputEvent::ActionReleased(ActionBinding::CarvePrimitive) => {
+                    if let Some((anchor, operation)) = tool.anchor.take() {
+                        let primitive = tool.sized_primitive(anchor, &objects);
+                        let local_cache = LocalChunkCache3::new();
+                        let map_reader = voxel_map.voxels.reader(&local_cache);
+                        rasterize_primitive(
+                            &map_reader,
+                            &primitive,
+                            anchor,
+                            operation,
+                            brush.voxel_type,
+                            &mut *voxel_backbuffer,
+                        );
+                        cache_flusher.flush(local_cache);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        for (_, lines) in (&is_hint, &mut debug_lines).join() {
+            lines.clear();
+            // Preview the bounds of the primitive being sized.
+            if let Some((anchor, operation)) = tool.anchor {
+                let primitive = tool.sized_primitive(anchor, &objects);
+                let center = voxel_center(anchor, voxel_map.world_scale);
+                // The primitive is sized in voxels.
+                let h = primitive.half_extents();
+                let h = [
+                    h[0] * voxel_map.world_scale,
+                    h[1] * voxel_map.world_scale,
+                    h[2] * voxel_map.world_scale,
+                ];
+                let box_min = na::Point3::new(center.x - h[0], center.y - h[1], center.z - h[2]);
+                let box_max = na::Point3::new(center.x + h[0], center.y + h[1], center.z + h[2]);
+                let color = match operation {
+                    SetVoxelOperation::MakeSolid => Srgba::new(0.0, 1.0, 0.0, 1.0),
+                    SetVoxelOperation::RemoveSolid => Srgba::new(1.0, 0.0, 0.0, 1.0),
+                };
+                lines.add_box(box_min, box_max, color);
+            }
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use crate::{
+    bindings::{ActionBinding, GameBindings},
+    control::hover_3d::ObjectsUnderCursor,
+};
+
+use voxel_mapper::voxel::props::{MapProps, Prop};
+
+use amethyst::{
+    core::{
+        ecs::prelude::*,
+        math::{Point3, Vector3},
+    },
+    derive::SystemDesc,
+    input::InputEvent,
+    renderer::{
+        debug_drawing::DebugLinesComponent,
+        palette::Srgba,
+        rendy::mesh::{Normal, Position, Tangent, TexCoord},
+    },
+    shrev::EventChannel,
+    utils::scene::BasicScenePrefab,
+};
+use building_blocks::prelude::*;
+use std::path::Path;
+
+/// The prefab format of the files in "assets/props".
+pub type PropPrefab = BasicScenePrefab<(Vec<Position>, Vec<Normal>, Vec<Tangent>, Vec<TexCoord>)>;
+
+/// The names of the prop prefabs found in a directory, with one of them selected for placing.
+#[derive(Default)]
+pub struct PropLibrary {
+    names: Vec<String>,
+    selected: usize,
+}
+
+impl PropLibrary {
+    /// Finds every ".ron" file in `dir`, sorted by name. A missing directory is an empty library.
+    pub fn load_dir(dir: impl AsRef<Path>) -> Self {
+        let mut names: Vec<String> = match std::fs::read_dir(dir) {
+            Ok(entries) => entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|path| path.extension().map_or(false, |ext| ext == "ron"))
+                .filter_map(|path| Some(path.file_stem()?.to_string_lossy().into_owned()))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        names.sort();
+
+        Self { names, selected: 0 }
+    }
+
+    pub fn selected(&self) -> Option<&str> {
+        self.names.get(self.selected).map(|s| s.as_str())
+    }
+
+    pub fn select_next(&mut self) {
+        if !self.names.is_empty() {
+            self.selected = (self.selected + 1) % self.names.len();
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct PropHintTag;
+
+impl Component for PropHintTag {
+    type Storage = NullStorage<Self>;
+}
+
+pub fn make_prop_hint_lines(world: &mut World) {
+    world
+        .create_entity()
+        .with(PropHintTag)
+        .with(DebugLinesComponent::new())
+        .build();
+}
+
+/// Places the selected prop on the hovered surface, or removes the prop that's already there.
+/// Grabbing a prop makes it follow the cursor until it's grabbed or placed again. Every prop is
+/// outlined, in case its prefab doesn't have a mesh.
+#[derive(SystemDesc)]
+#[system_desc(name(PropToolSystemDesc))]
+pub struct PropToolSystem {
+    #[system_desc(event_channel_reader)]
+    reader_id: ReaderId<InputEvent<GameBindings>>,
+    #[system_desc(skip)]
+    grabbed: Option<usize>,
+}
+
+impl PropToolSystem {
+    pub fn new(reader_id: ReaderId<InputEvent<GameBindings>>) -> Self {
+        PropToolSystem {
+            reader_id,
+            grabbed: None,
+        }
+    }
+}
+
+/// Clicking within this many voxels of a prop picks it instead of placing a new one.
+const PROP_PICK_RADIUS: f32 = 1.5;
+const PROP_ROTATION_STEP_DEGREES: f32 = 45.0;
+
+impl<'a> System<'a> for PropToolSystem {
+    type SystemData = (
+        Read<'a, EventChannel<InputEvent<GameBindings>>>,
+        Read<'a, ObjectsUnderCursor>,
+        Write<'a, PropLibrary>,
+        Write<'a, MapProps>,
+        ReadStorage<'a, PropHintTag>,
+        WriteStorage<'a, DebugLinesComponent>,
+    );
+
+    fn run(
+        &mut self,
+        (input_events, objects, mut library, mut props, is_hint, mut debug_lines): Self::SystemData,
+    ) {
+        let hover_point = objects.voxel.as_ref().map(|v| v.hover_adjacent_point());
+        let hovered_prop = hover_point.and_then(|p| props.nearest(p, PROP_PICK_RADIUS));
+
+        for input_event in input_events.read(&mut self.reader_id) {
+            match input_event {
+                InputEvent::ActionPressed(ActionBinding::NextProp) => {
+                    library.select_next();
+                    log::info!("Selected prop {:?}", library.selected());
+                }
+                InputEvent::ActionPressed(ActionBinding::PlaceProp) => {
+                    if self.grabbed.take().is_some() {
+                        continue;
+                    }
+                    let p = match hover_point {
+                        Some(p) => p,
+                        None => continue,
+                    };
+                    if let Some(i) = hovered_prop {
+                        let removed = props.remove(i);
+                        log::info!("Removed prop {:?} at {:?}", removed.prefab, p);
+                    } else if let Some(name) = library.selected() {
+                        props.add(Prop::new(name.to_string(), p));
+                        log::info!("Placed prop {:?} at {:?}", name, p);
+                    } else {
+                        log::warn!("There are no prop prefabs in assets/props");
+                    }
+                }
+                InputEvent::ActionPressed(ActionBinding::GrabProp) => {
+                    self.grabbed = if self.grabbed.is_some() {
+                        None
+                    } else {
+                        hovered_prop
+                    };
+                }
+                InputEvent::ActionPressed(ActionBinding::RotateProp) => {
+                    if let Some(i) = self.grabbed.or(hovered_prop) {
+                        props.rotate(i, PROP_ROTATION_STEP_DEGREES);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        if let (Some(i), Some(p)) = (self.grabbed, hover_point) {
+            props.set_position(i, p);
+        }
+
+        for (_, lines) in (&is_hint, &mut debug_lines).join() {
+            lines.clear();
+            for (i, prop) in props.iter().enumerate() {
+                let color = if Some(i) == self.grabbed {
+                    Srgba::new(1.0, 1.0, 1.0, 1.0)
+                } else {
+                    Srgba::new(0.6, 0.4, 0.2, 1.0)
+                };
+                let min = Point3::from(Point3f::from(prop.position()).0);
+                lines.add_box(min, min + Vector3::new(1.0, 1.0, 1.0), color);
+                // Show which way the prop is facing.
+                let yaw = prop.yaw_degrees.to_radians();
+                let center = min + Vector3::new(0.5, 0.5, 0.5);
+                lines.add_line(
+                    center,
+                    center + Vector3::new(yaw.sin(), 0.0, yaw.cos()),
+                    color,
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use crate::{
+    bindings::{ActionBinding, GameBindings},
+    control::hover_3d::ObjectsUnderCursor,
+};
+
+use voxel_mapper::voxel::{
+    double_buffer::EditedChunksBackBuffer, scripting::run_voxel_script_file, VoxelMap,
+};
+
+use amethyst::{core::ecs::prelude::*, derive::SystemDesc, input::InputEvent, shrev::EventChannel};
+use std::path::PathBuf;
+
+/// The script given with `--script`. It's read again every time it runs, so it can be tweaked
+/// without restarting the editor.
+#[derive(Default)]
+pub struct ScriptFile(pub Option<PathBuf>);
+
+/// Runs the `ScriptFile` at the hovered voxel.
+#[derive(SystemDesc)]
+#[system_desc(name(ScriptToolSystemDesc))]
+pub struct ScriptToolSystem {
+    #[system_desc(event_channel_reader)]
+    reader_id: ReaderId<InputEvent<GameBindings>>,
+}
+
+impl ScriptToolSystem {
+    pub fn new(reader_id: ReaderId<InputEvent<GameBindings>>) -> Self {
+        ScriptToolSystem { reader_id }
+    }
+}
+
+impl<'a> System<'a> for ScriptToolSystem {
+    type SystemData = (
+        Read<'a, EventChannel<InputEvent<GameBindings>>>,
+        Read<'a, ObjectsUnderCursor>,
+        Read<'a, ScriptFile>,
+        WriteExpect<'a, VoxelMap>,
+        WriteExpect<'a, EditedChunksBackBuffer>,
+    );
+
+    fn run(
+        &mut self,
+        (input_events, objects, script_file, mut voxel_map, mut voxel_backbuffer): Self::SystemData,
+    ) {
+        for input_event in input_events.read(&mut self.reader_id) {
+            if let InputEvent::ActionPressed(ActionBinding::RunScript) = input_event {
+                let path = match &script_file.0 {
+                    Some(path) => path,
+                    None => {
+                        log::warn!("No script to run, pass one with --script");
+                        continue;
+                    }
+                };
+                let origin = match &objects.voxel {
+                    Some(v) => *v.point(),
+                    None => {
+                        log::warn!("Hover over a voxel to run the script there");
+                        continue;
+                    }
+                };
+                match run_voxel_script_file(path, origin, &mut voxel_map, &mut voxel_backbuffer) {
+                    Ok(num_written) => {
+                        log::info!("Script {} wrote {} voxels", path.display(), num_written)
+                    }
+                    Err(e) => log::error!("Script {} failed: {:?}", path.display(), e),
+                }
+            }
+        }
+    }
+}
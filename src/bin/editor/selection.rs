@@ -0,0 +1,379 @@
+use crate::{
+    bindings::{ActionBinding, GameBindings},
+    control::hover_3d::{HoverVoxel, ObjectsUnderCursor},
+    voxel_brush::PaintBrush,
+};
+
+use voxel_mapper::voxel::{
+    chunk_cache_flusher::ChunkCacheFlusher,
+    clipboard::{move_extent, Axis, VoxelClipboard},
+    csg::{combine_with_map, CsgOperation},
+    double_buffer::EditedChunksBackBuffer,
+    extent_ops::{
+        add_noise_to_extent, clear_extent, fill_extent, replace_voxel_type, smooth_extent,
+    },
+    stamps::StampLibrary,
+    vox::write_vox_file,
+    VoxelMap,
+};
+
+use amethyst::{
+    core::{ecs::prelude::*, math as na},
+    derive::SystemDesc,
+    input::InputEvent,
+    renderer::{debug_drawing::DebugLinesComponent, palette::Srgba},
+    shrev::EventChannel,
+    utils::application_dir,
+};
+use building_blocks::prelude::*;
+use std::path::PathBuf;
+
+const SMOOTH_ITERATIONS: usize = 2;
+const NOISE_AMPLITUDE: f32 = 2.0;
+const NOISE_FREQUENCY: f32 = 0.15;
+
+/// Where the clipboard is exported to and imported from. Exported files can be copied here from
+/// other users to share structures.
+fn clipboard_export_dir() -> PathBuf {
+    application_dir("assets/exports").unwrap()
+}
+
+fn export_clipboard(clipboard: &VoxelClipboard) {
+    let dir = clipboard_export_dir();
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        log::error!("Failed to create {:?}: {}", dir, e);
+        return;
+    }
+    let bin_path = dir.join("selection.bin");
+    match clipboard.save(&bin_path) {
+        Ok(()) => log::info!("Exported selection to {:?}", bin_path),
+        Err(e) => log::error!("Failed to export selection to {:?}: {:?}", bin_path, e),
+    }
+    let vox_path = dir.join("selection.vox");
+    match write_vox_file(&vox_path, clipboard) {
+        Ok(()) => log::info!("Exported selection to {:?}", vox_path),
+        Err(e) => log::error!("Failed to export selection to {:?}: {}", vox_path, e),
+    }
+}
+
+/// A box of voxels that the selection operations apply to.
+#[derive(Default)]
+pub struct Selection {
+    pub extent: Option<Extent3i>,
+    /// The first corner, while waiting for the second one.
+    pub anchor: Option<Point3i>,
+    /// The last copied contents of the selection, ready to be oriented and pasted.
+    pub clipboard: Option<VoxelClipboard>,
+    /// Set while the selection is being dragged to a new location.
+    pub drag: Option<SelectionDrag>,
+    /// Incremented for each noise operation so repeating it doesn't give the same result.
+    noise_seed: u32,
+}
+
+impl Selection {
+    pub fn set_corners(&mut self, a: Point3i, b: Point3i) {
+        self.extent = Some(extent_between_corners(a, b));
+    }
+}
+
+fn extent_between_corners(a: Point3i, b: Point3i) -> Extent3i {
+    let min = PointN([a.x().min(b.x()), a.y().min(b.y()), a.z().min(b.z())]);
+    let max = PointN([a.x().max(b.x()), a.y().max(b.y()), a.z().max(b.z())]);
+
+    Extent3i::from_min_and_max(min, max)
+}
+
+pub struct SelectionDrag {
+    /// Horizontal offset from the selection minimum to the voxel that was grabbed.
+    grab_offset: Point3i,
+}
+
+impl SelectionDrag {
+    /// Where the selection minimum would go if it were dropped with the cursor over `v`. The
+    /// selection rests on top of the hovered surface.
+    fn drop_min(&self, v: &HoverVoxel) -> Point3i {
+        v.hover_adjacent_point() - self.grab_offset
+    }
+}
+
+#[derive(Default)]
+pub struct SelectionHintTag;
+
+impl Component for SelectionHintTag {
+    type Storage = NullStorage<Self>;
+}
+
+pub fn make_selection_hint_lines(world: &mut World) {
+    world
+        .create_entity()
+        .with(SelectionHintTag)
+        .with(DebugLinesComponent::new())
+        .build();
+}
+
+/// Lets the user select a box by picking two corner voxels, or by dragging from one corner to the
+/// other, then apply bulk operations to only the voxels inside of it. The selection can also be
+/// copied, rotated or flipped, and pasted elsewhere, or combined with the selection by CSG.
+#[derive(SystemDesc)]
+#[system_desc(name(SelectionSystemDesc))]
+pub struct SelectionSystem {
+    #[system_desc(event_channel_reader)]
+    reader_id: ReaderId<InputEvent<GameBindings>>,
+}
+
+impl SelectionSystem {
+    pub fn new(reader_id: ReaderId<InputEvent<GameBindings>>) -> Self {
+        SelectionSystem { reader_id }
+    }
+}
+
+impl<'a> System<'a> for SelectionSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        Read<'a, EventChannel<InputEvent<GameBindings>>>,
+        Read<'a, ObjectsUnderCursor>,
+        ReadExpect<'a, VoxelMap>,
+        ReadExpect<'a, ChunkCacheFlusher>,
+        ReadExpect<'a, PaintBrush>,
+        Write<'a, Selection>,
+        Write<'a, StampLibrary>,
+        WriteExpect<'a, EditedChunksBackBuffer>,
+        ReadStorage<'a, SelectionHintTag>,
+        WriteStorage<'a, DebugLinesComponent>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            input_events,
+            objects,
+            voxel_map,
+            cache_flusher,
+            brush,
+            mut selection,
+            mut stamps,
+            mut voxel_backbuffer,
+            is_hint,
+            mut debug_lines,
+        ): Self::SystemData,
+    ) {
+        let local_cache = LocalChunkCache3::new();
+        let map_reader = voxel_map.voxels.reader(&local_cache);
+
+        for input_event in input_events.read(&mut self.reader_id) {
+            let action = match input_event {
+                InputEvent::ActionPressed(action) => action,
+                InputEvent::ActionReleased(ActionBinding::MoveSelection) => {
+                    // Drop the dragged selection where the cursor is.
+                    if let (Some(drag), Some(extent), Some(v)) =
+                        (selection.drag.take(), selection.extent, &objects.voxel)
+                    {
+                        let dst_min = drag.drop_min(v);
+                        move_extent(&map_reader, &extent, dst_min, &mut *voxel_backbuffer);
+                        selection.extent =
+                            Some(Extent3i::from_min_and_shape(dst_min, extent.shape));
+                    }
+                    continue;
+                }
+                InputEvent::ActionReleased(ActionBinding::SelectCorner) => {
+                    // Releasing over a different voxel than the anchor finishes a dragged box.
+                    // Otherwise the anchor waits for a second click.
+                    if let (Some(anchor), Some(v)) = (selection.anchor, &objects.voxel) {
+                        if *v.point() != anchor {
+                            selection.anchor = None;
+                            selection.set_corners(anchor, *v.point());
+                            log::info!("Selected {:?}", selection.extent.unwrap());
+                        }
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+
+            if let ActionBinding::SelectCorner = action {
+                if let Some(v) = &objects.voxel {
+                    let corner = *v.point();
+                    if let Some(anchor) = selection.anchor.take() {
+                        selection.set_corners(anchor, corner);
+                        log::info!("Selected {:?}", selection.extent.unwrap());
+                    } else {
+                        selection.anchor = Some(corner);
+                    }
+                }
+                continue;
+            }
+            if let ActionBinding::ImportClipboard = action {
+                let path = clipboard_export_dir().join("selection.bin");
+                match VoxelClipboard::load(&path) {
+                    Ok(clipboard) => selection.clipboard = Some(clipboard),
+                    Err(e) => log::error!("Failed to import {:?}: {:?}", path, e),
+                }
+                continue;
+            }
+            if let ActionBinding::ClearSelection = action {
+                selection.extent = None;
+                selection.anchor = None;
+                continue;
+            }
+            if let Some(clipboard) = &mut selection.clipboard {
+                match action {
+                    ActionBinding::ExportClipboard => {
+                        export_clipboard(clipboard);
+                        continue;
+                    }
+                    ActionBinding::RotateClipboard => {
+                        clipboard.rotate_y_90();
+                        continue;
+                    }
+                    ActionBinding::FlipClipboardX => {
+                        clipboard.flip(Axis::X);
+                        continue;
+                    }
+                    ActionBinding::FlipClipboardZ => {
+                        clipboard.flip(Axis::Z);
+                        continue;
+                    }
+                    ActionBinding::PasteClipboard => {
+                        if let Some(v) = &objects.voxel {
+                            clipboard.paste(
+                                &map_reader,
+                                v.hover_adjacent_point(),
+                                &mut *voxel_backbuffer,
+                            );
+                        }
+                        continue;
+                    }
+                    _ => (),
+                }
+            }
+
+            let extent = match selection.extent {
+                Some(e) => e,
+                None => continue,
+            };
+            match action {
+                ActionBinding::MoveSelection => {
+                    if let Some(v) = &objects.voxel {
+                        let grabbed = *v.point() - extent.minimum;
+                        selection.drag = Some(SelectionDrag {
+                            grab_offset: PointN([grabbed.x(), 0, grabbed.z()]),
+                        });
+                    }
+                }
+                ActionBinding::CopySelection => {
+                    selection.clipboard = Some(VoxelClipboard::copy_from_map(&map_reader, &extent));
+                }
+                ActionBinding::UnionClipboard
+                | ActionBinding::SubtractClipboard
+                | ActionBinding::IntersectClipboard => {
+                    let operation = match action {
+                        ActionBinding::UnionClipboard => CsgOperation::Union,
+                        ActionBinding::SubtractClipboard => CsgOperation::Subtraction,
+                        _ => CsgOperation::Intersection,
+                    };
+                    // The clipboard is lined up with the minimum corner of the selection.
+                    if let Some(clipboard) = &selection.clipboard {
+                        combine_with_map(
+                            &map_reader,
+                            &extent,
+                            &clipboard.voxels,
+                            extent.minimum,
+                            operation,
+                            &mut *voxel_backbuffer,
+                        );
+                    } else {
+                        log::warn!("Copy something before combining it with the selection");
+                    }
+                }
+                ActionBinding::SaveStamp => {
+                    let name = stamps.unused_name();
+                    let clipboard = VoxelClipboard::copy_from_map(&map_reader, &extent);
+                    match stamps.save_stamp(&name, clipboard) {
+                        Ok(()) => log::info!("Saved the selection as stamp {:?}", name),
+                        Err(e) => log::error!("Failed to save stamp {:?}: {:?}", name, e),
+                    }
+                }
+                ActionBinding::FillSelection => {
+                    fill_extent(
+                        &map_reader,
+                        &extent,
+                        brush.voxel_type,
+                        &mut *voxel_backbuffer,
+                    );
+                }
+                ActionBinding::EmptySelection => {
+                    clear_extent(&map_reader, &extent, &mut *voxel_backbuffer);
+                }
+                ActionBinding::SmoothSelection => {
+                    smooth_extent(
+                        &map_reader,
+                        &extent,
+                        SMOOTH_ITERATIONS,
+                        &mut *voxel_backbuffer,
+                    );
+                }
+                ActionBinding::NoiseSelection => {
+                    selection.noise_seed = selection.noise_seed.wrapping_add(1);
+                    add_noise_to_extent(
+                        &map_reader,
+                        &extent,
+                        selection.noise_seed,
+                        NOISE_AMPLITUDE,
+                        NOISE_FREQUENCY,
+                        &mut *voxel_backbuffer,
+                    );
+                }
+                ActionBinding::ReplaceInSelection => {
+                    // Replace whatever material is under the cursor with the brush material.
+                    if let Some(v) = &objects.voxel {
+                        let from = map_reader.lod_view(0).get(*v.point()).voxel_type;
+                        replace_voxel_type(
+                            &map_reader,
+                            &extent,
+                            from,
+                            brush.voxel_type,
+                            &mut *voxel_backbuffer,
+                        );
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        cache_flusher.flush(local_cache);
+
+        for (_, lines) in (&is_hint, &mut debug_lines).join() {
+            lines.clear();
+            if let Some(extent) = &selection.extent {
+                let box_min: na::Point3<f32> = Point3f::from(extent.minimum).0.into();
+                let box_max: na::Point3<f32> = Point3f::from(extent.least_upper_bound()).0.into();
+                lines.add_box(box_min, box_max, Srgba::new(0.0, 1.0, 1.0, 1.0));
+            }
+            // Preview the box between the anchor and the hovered voxel.
+            if let Some(anchor) = &selection.anchor {
+                let corner = objects.voxel.as_ref().map_or(*anchor, |v| *v.point());
+                let extent = extent_between_corners(*anchor, corner);
+                let box_min: na::Point3<f32> = Point3f::from(extent.minimum).0.into();
+                let box_max: na::Point3<f32> = Point3f::from(extent.least_upper_bound()).0.into();
+                lines.add_box(box_min, box_max, Srgba::new(0.0, 1.0, 1.0, 1.0));
+            }
+            // Preview where a dragged selection would be dropped.
+            if let (Some(drag), Some(extent), Some(v)) =
+                (&selection.drag, &selection.extent, &objects.voxel)
+            {
+                let drop_min = drag.drop_min(v);
+                let box_min: na::Point3<f32> = Point3f::from(drop_min).0.into();
+                let box_max: na::Point3<f32> = Point3f::from(drop_min + extent.shape).0.into();
+                lines.add_box(box_min, box_max, Srgba::new(1.0, 1.0, 1.0, 1.0));
+            }
+            // Preview where the clipboard would be pasted.
+            if let (Some(clipboard), Some(v)) = (&selection.clipboard, &objects.voxel) {
+                let paste_min = v.hover_adjacent_point();
+                let box_min: na::Point3<f32> = Point3f::from(paste_min).0.into();
+                let box_max: na::Point3<f32> =
+                    Point3f::from(paste_min + clipboard.shape()).0.into();
+                lines.add_box(box_min, box_max, Srgba::new(1.0, 0.5, 0.0, 1.0));
+            }
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use crate::{
+    bindings::{key_combo_name, ActionBinding, GameBindings},
+    control::{camera::MainCameraTag, hover_3d::ObjectsUnderCursor},
+    voxel_brush::PaintBrush,
+};
+
+use voxel_mapper::voxel::{
+    chunk_cache_flusher::ChunkCacheFlusher, chunk_processor::MeshMode,
+    double_buffer::EditedChunksBackBuffer, VoxelMap,
+};
+
+use amethyst::{
+    assets::{AssetStorage, Loader},
+    core::{ecs::prelude::*, Transform},
+    derive::SystemDesc,
+    input::{InputEvent, InputHandler},
+    shrev::EventChannel,
+    ui::{get_default_font, Anchor, FontAsset, LineMode, UiText, UiTransform},
+};
+use building_blocks::prelude::*;
+
+#[derive(Default)]
+pub struct StatusBarText;
+
+impl Component for StatusBarText {
+    type Storage = NullStorage<Self>;
+}
+
+/// One column of the key bindings cheat sheet.
+pub struct KeyHelpText {
+    pub column: usize,
+}
+
+impl Component for KeyHelpText {
+    type Storage = VecStorage<Self>;
+}
+
+const STATUS_TEXT_WIDTH: f32 = 1000.0;
+const STATUS_TEXT_HEIGHT: f32 = 24.0;
+const STATUS_FONT_SIZE: f32 = 16.0;
+const KEY_HELP_COLUMNS: usize = 3;
+const KEY_HELP_COLUMN_WIDTH: f32 = 320.0;
+const KEY_HELP_HEIGHT: f32 = 600.0;
+const KEY_HELP_FONT_SIZE: f32 = 14.0;
+
+/// Creates an empty label along the top of the screen for the status bar, and the (empty) columns
+/// of the key bindings cheat sheet in the middle of the screen.
+pub fn make_status_bar_ui(world: &mut World) {
+    let font = world.exec(
+        |(loader, font_storage): (ReadExpect<Loader>, Read<AssetStorage<FontAsset>>)| {
+            get_default_font(&loader, &font_storage)
+        },
+    );
+
+    let transform = UiTransform::new(
+        "status_bar".to_string(),
+        Anchor::TopMiddle,
+        Anchor::TopMiddle,
+        0.0,
+        -10.0,
+        1.0,
+        STATUS_TEXT_WIDTH,
+        STATUS_TEXT_HEIGHT,
+    );
+    let text = UiText::new(
+        font.clone(),
+        String::new(),
+        [1.0, 1.0, 1.0, 1.0],
+        STATUS_FONT_SIZE,
+    );
+    world
+        .create_entity()
+        .with(transform)
+        .with(text)
+        .with(StatusBarText)
+        .build();
+
+    for column in 0..KEY_HELP_COLUMNS {
+        let x = (column as f32 - (KEY_HELP_COLUMNS as f32 - 1.0) / 2.0) * KEY_HELP_COLUMN_WIDTH;
+        let transform = UiTransform::new(
+            format!("key_help_{}", column),
+            Anchor::Middle,
+            Anchor::Middle,
+            x,
+            0.0,
+            1.0,
+            KEY_HELP_COLUMN_WIDTH,
+            KEY_HELP_HEIGHT,
+        );
+        let mut text = UiText::new(
+            font.clone(),
+            String::new(),
+            [1.0, 1.0, 0.6, 1.0],
+            KEY_HELP_FONT_SIZE,
+        );
+        text.line_mode = LineMode::Wrap;
+        text.align = Anchor::TopLeft;
+        world
+            .create_entity()
+            .with(transform)
+            .with(text)
+            .with(KeyHelpText { column })
+            .build();
+    }
+}
+
+/// Shows the brush, mesh mode, hovered voxel and camera position in the status bar, and toggles a
+/// cheat sheet of the key bindings.
+#[derive(SystemDesc)]
+#[system_desc(name(StatusBarSystemDesc))]
+pub struct StatusBarSystem {
+    #[system_desc(event_channel_reader)]
+    reader_id: ReaderId<InputEvent<GameBindings>>,
+    #[system_desc(skip)]
+    show_key_help: bool,
+}
+
+impl StatusBarSystem {
+    pub fn new(reader_id: ReaderId<InputEvent<GameBindings>>) -> Self {
+        StatusBarSystem {
+            reader_id,
+            show_key_help: false,
+        }
+    }
+}
+
+impl<'a> System<'a> for StatusBarSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        Read<'a, EventChannel<InputEvent<GameBindings>>>,
+        Read<'a, InputHandler<GameBindings>>,
+        Read<'a, ObjectsUnderCursor>,
+        ReadExpect<'a, VoxelMap>,
+        ReadExpect<'a, ChunkCacheFlusher>,
+        ReadExpect<'a, PaintBrush>,
+        ReadExpect<'a, MeshMode>,
+        ReadExpect<'a, EditedChunksBackBuffer>,
+        ReadStorage<'a, MainCameraTag>,
+        ReadStorage<'a, Transform>,
+        ReadStorage<'a, StatusBarText>,
+        ReadStorage<'a, KeyHelpText>,
+        WriteStorage<'a, UiText>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            input_events,
+            input_handler,
+            objects,
+            voxel_map,
+            cache_flusher,
+            brush,
+            mesh_mode,
+            backbuffer,
+            main_camera_tags,
+            transforms,
+            status_bar_texts,
+            key_help_texts,
+            mut texts,
+        ): Self::SystemData,
+    ) {
+        let mut toggled = false;
+        for input_event in input_events.read(&mut self.reader_id) {
+            if let InputEvent::ActionPressed(ActionBinding::ToggleKeyHelp) = input_event {
+                self.show_key_help = !self.show_key_help;
+                toggled = true;
+            }
+        }
+
+        let mut status = format!(
+            "{:?} brush  radius {}  {:?} falloff  type {}  |  {}",
+            brush.mode,
+            brush.radius,
+            brush.config.falloff,
+            brush.voxel_type.0,
+            match *mesh_mode {
+                MeshMode::SurfaceNets => "Surface nets",
+                MeshMode::GreedyQuads => "Greedy quads",
+            },
+        );
+        let num_pending_chunks = backbuffer.num_pending_chunks();
+        if num_pending_chunks > 0 {
+            status += &format!("  |  Applying edit, {} chunks left", num_pending_chunks);
+        }
+        if let Some(v) = &objects.voxel {
+            let local_cache = LocalChunkCache3::new();
+            let voxel = voxel_map
+                .voxels
+                .reader(&local_cache)
+                .lod_view(0)
+                .get(*v.point());
+            cache_flusher.flush(local_cache);
+            let distance: f32 = voxel.distance.into();
+            status += &format!(
+                "  |  Voxel {:?}  type {}  SDF {:.2}",
+                v.point().0,
+                voxel.voxel_type.0,
+                distance
+            );
+        }
+        if let Some((_, tfm)) = (&main_camera_tags, &transforms).join().next() {
+            let p = tfm.translation();
+            status += &format!("  |  Camera ({:.1}, {:.1}, {:.1})", p.x, p.y, p.z);
+        }
+        status += "  |  Numpad 0: keys";
+
+        for (_, text) in (&status_bar_texts, &mut texts).join() {
+            if text.text != status {
+                text.text = status.clone();
+            }
+        }
+
+        // The bindings don't change while the editor is running, so only format them when toggled.
+        if toggled {
+            let columns = if self.show_key_help {
+                key_help_columns(&input_handler)
+            } else {
+                vec![String::new(); KEY_HELP_COLUMNS]
+            };
+            for (help, text) in (&key_help_texts, &mut texts).join() {
+                text.text = columns[help.column].clone();
+            }
+        }
+    }
+}
+
+/// Lists every action with its bindings, sorted by name and split into `KEY_HELP_COLUMNS` columns.
+fn key_help_columns(input_handler: &InputHandler<GameBindings>) -> Vec<String> {
+    let bindings = &input_handler.bindings;
+    let mut lines: Vec<String> = bindings
+        .actions()
+        .map(|action| {
+            let keys: Vec<String> = bindings
+                .action_bindings(action)
+                .map(key_combo_name)
+                .collect();
+
+            format!("{}: {}", action, keys.join(", "))
+        })
+        .collect();
+    lines.sort();
+
+    let lines_per_column = (lines.len() + KEY_HELP_COLUMNS - 1) / KEY_HELP_COLUMNS;
+    let mut columns: Vec<String> = lines
+        .chunks(lines_per_column.max(1))
+        .map(|column| column.join("\n"))
+        .collect();
+    columns.resize(KEY_HELP_COLUMNS, String::new());
+
+    columns
+}
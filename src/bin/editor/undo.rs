@@ -0,0 +1,71 @@
+use crate::bindings::{ActionBinding, GameBindings};
+
+use voxel_mapper::voxel::{
+    chunk_cache_flusher::ChunkCacheFlusher, double_buffer::EditedChunksBackBuffer,
+    edit_history::EditHistory, VoxelMap,
+};
+
+use amethyst::{core::ecs::prelude::*, derive::SystemDesc, input::InputEvent, shrev::EventChannel};
+use building_blocks::prelude::*;
+
+/// Undoes and redoes whole edit transactions by restoring the affected chunks through the
+/// backbuffer.
+#[derive(SystemDesc)]
+#[system_desc(name(UndoSystemDesc))]
+pub struct UndoSystem {
+    #[system_desc(event_channel_reader)]
+    reader_id: ReaderId<InputEvent<GameBindings>>,
+}
+
+impl UndoSystem {
+    pub fn new(reader_id: ReaderId<InputEvent<GameBindings>>) -> Self {
+        UndoSystem { reader_id }
+    }
+}
+
+impl<'a> System<'a> for UndoSystem {
+    type SystemData = (
+        Read<'a, EventChannel<InputEvent<GameBindings>>>,
+        ReadExpect<'a, VoxelMap>,
+        ReadExpect<'a, ChunkCacheFlusher>,
+        Write<'a, EditHistory>,
+        WriteExpect<'a, EditedChunksBackBuffer>,
+    );
+
+    fn run(
+        &mut self,
+        (input_events, voxel_map, cache_flusher, mut history, mut voxel_backbuffer): Self::SystemData,
+    ) {
+        let mut restore = None;
+        for input_event in input_events.read(&mut self.reader_id) {
+            match input_event {
+                InputEvent::ActionPressed(ActionBinding::Undo) => {
+                    if voxel_backbuffer.open_transaction().is_some() {
+                        log::warn!("Can't undo in the middle of an edit");
+                        continue;
+                    }
+                    restore = history.undo();
+                }
+                InputEvent::ActionPressed(ActionBinding::Redo) => {
+                    if voxel_backbuffer.open_transaction().is_some() {
+                        log::warn!("Can't redo in the middle of an edit");
+                        continue;
+                    }
+                    restore = history.redo();
+                }
+                _ => (),
+            }
+            // Only one step per frame, since the map doesn't reflect the previous step yet.
+            if restore.is_some() {
+                break;
+            }
+        }
+
+        if let Some(restore) = restore {
+            let local_cache = LocalChunkCache3::new();
+            let reader = voxel_map.voxels.reader(&local_cache);
+            voxel_backbuffer.restore_chunks(&reader, restore);
+            cache_flusher.flush(local_cache);
+        }
+    }
+}
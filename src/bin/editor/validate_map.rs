@@ -0,0 +1,51 @@
+use voxel_mapper::voxel::{
+    map_file::{snapshot_chunks, write_voxels_file, VoxelMapFile},
+    validation::{fix_map, validate_map, MapValidationReport},
+};
+
+use std::path::Path;
+
+/// Checks the voxels stored for `map_file` and prints a report. With `fix`, the fixed voxels are
+/// written back to the map's voxels file.
+pub fn validate_map_file(map_file: &Path, fix: bool) -> amethyst::Result<()> {
+    let map_spec = VoxelMapFile::load_with_edits(map_file)?;
+    let mut map = map_spec
+        .load_voxel_map()
+        .map_err(|e| amethyst::Error::from_string(format!("Failed to load map: {:?}", e)))?;
+
+    let report = if fix {
+        fix_map(&mut map)
+    } else {
+        validate_map(&map)
+    };
+    print_report(&report);
+
+    if fix && !report.is_clean() {
+        let voxels_path = map_spec.voxels_save_path();
+        write_voxels_file(&voxels_path, map.chunk_shape(), snapshot_chunks(&map))
+            .map_err(|e| amethyst::Error::from_string(format!("Failed to save voxels: {:?}", e)))?;
+        println!("Wrote fixed voxels to {}", voxels_path.display());
+    }
+
+    Ok(())
+}
+
+fn print_report(report: &MapValidationReport) {
+    println!("Checked {} chunks", report.chunks_checked);
+    if report.is_clean() {
+        println!("No issues found");
+        return;
+    }
+    for (issue, count) in report.issue_counts.iter() {
+        println!(
+            "{:?}: {} voxels, e.g. at {:?}",
+            issue, count, report.issue_examples[issue].0
+        );
+    }
+    if !report.ambient_chunks.is_empty() {
+        println!(
+            "{} chunks contain only ambient voxels",
+            report.ambient_chunks.len()
+        );
+    }
+}
@@ -4,18 +4,33 @@ use crate::{
 };
 
 use voxel_mapper::voxel::{
-    centered_extent, chunk_cache_flusher::ChunkCacheFlusher, chunk_processor::MeshMode,
-    double_buffer::EditedChunksBackBuffer, voxel_containing_point, Voxel, VoxelChunkReader,
-    VoxelMap, VoxelType, EMPTY_VOXEL,
+    block_out::{write_block_out, BlockOutSpec},
+    cave_brush::{carve_caves, CaveNoiseParams},
+    centered_extent,
+    chunk_cache_flusher::ChunkCacheFlusher,
+    chunk_processor::MeshMode,
+    crater::{apply_crater, CraterParams},
+    double_buffer::{EditSourceId, EditedChunksBackBuffer},
+    edit_journal::{EditJournal, JournaledEdit},
+    edit_limits::{EditLimitViolation, EditLimits, RejectedEditEvent},
+    erosion::{erode_extent, ErosionConfig},
+    sphere_brush::{BrushFalloff, SetVoxelOperation, SphereStroke},
+    stamps::StampLibrary,
+    voxel_containing_point, Voxel, VoxelChunkReader, VoxelDistance, VoxelMap, VoxelType,
+    EMPTY_VOXEL,
 };
 
 use amethyst::{
-    core::ecs::prelude::*,
+    config::Config,
+    core::{ecs::prelude::*, Time},
     derive::SystemDesc,
-    input::{Button, InputEvent, InputHandler, VirtualKeyCode},
+    input::{InputEvent, InputHandler},
     shrev::EventChannel,
+    utils::application_dir,
 };
 use building_blocks::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 #[cfg(feature = "profiler")]
 use thread_profiler::profile_scope;
@@ -25,28 +40,127 @@ use thread_profiler::profile_scope;
 pub struct VoxelBrushSystem {
     #[system_desc(event_channel_reader)]
     reader_id: ReaderId<InputEvent<GameBindings>>,
+    #[system_desc(event_channel_reader)]
+    rejected_edit_reader_id: ReaderId<RejectedEditEvent>,
+    // Only warn once while the same violation keeps repeating, e.g. while the button is held.
+    #[system_desc(skip)]
+    last_violation: Option<EditLimitViolation>,
 }
 
 impl VoxelBrushSystem {
-    pub fn new(reader_id: ReaderId<InputEvent<GameBindings>>) -> Self {
-        VoxelBrushSystem { reader_id }
+    pub fn new(
+        reader_id: ReaderId<InputEvent<GameBindings>>,
+        rejected_edit_reader_id: ReaderId<RejectedEditEvent>,
+    ) -> Self {
+        VoxelBrushSystem {
+            reader_id,
+            rejected_edit_reader_id,
+            last_violation: None,
+        }
+    }
+}
+
+/// Tunable brush feel, loaded from RON.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BrushConfig {
+    /// How much the sphere brush changes the SDF at its center on each frame. Higher is harder.
+    pub sdf_growth_factor: f32,
+    /// The fraction of the brush radius beyond which the sphere brush has no effect.
+    pub shell_cutoff: f32,
+    /// How deep the crater brush digs below the impact point.
+    pub crater_depth: f32,
+    /// How many small spheres the scatter brush places per second.
+    pub scatter_density: f32,
+    /// How the sphere brush fades toward its edge.
+    #[serde(default)]
+    pub falloff: BrushFalloff,
+    /// The noise that the cave brush carves with.
+    #[serde(default)]
+    pub cave_noise: CaveNoiseParams,
+}
+
+impl Default for BrushConfig {
+    fn default() -> Self {
+        Self {
+            sdf_growth_factor: 10.0,
+            shell_cutoff: 1.0,
+            crater_depth: 6.0,
+            scatter_density: 20.0,
+            falloff: BrushFalloff::default(),
+            cave_noise: CaveNoiseParams::default(),
+        }
     }
 }
 
 pub struct PaintBrush {
+    pub mode: BrushMode,
     pub voxel_type: VoxelType,
+    /// The type that the blend brush fades to at the edge of its footprint.
+    pub secondary_voxel_type: VoxelType,
     pub radius: u32,
     pub dist_from_camera: Option<f32>,
+    /// Set when the user pushes or pulls the brush manually, so the distance stops following the
+    /// hovered surface until it's reset.
+    pub manual_depth: bool,
+    pub config: BrushConfig,
+    /// Carries fractional scatter stamps over to the next frame.
+    pub scatter_accumulator: f32,
+    /// Seconds until a large sphere brush can be applied again.
+    pub large_brush_cooldown: f32,
 }
 
-#[derive(Clone, Copy)]
-pub enum SetVoxelOperation {
-    /// Set voxels in the solid to negative distances from the surface, and surrounding voxels to
-    /// the positive distance from the surface.
-    MakeSolid,
-    /// Set voxels in the solid to positive distances from the surface, and surrounding voxels to
-    /// the negative distance from the surface.
-    RemoveSolid,
+impl PaintBrush {
+    pub fn new(config: BrushConfig) -> Self {
+        Self {
+            mode: BrushMode::Sphere,
+            voxel_type: VoxelType(1),
+            secondary_voxel_type: VoxelType(2),
+            radius: 10,
+            dist_from_camera: None,
+            manual_depth: false,
+            config,
+            scatter_accumulator: 0.0,
+            large_brush_cooldown: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BrushMode {
+    /// Grows or shrinks the surface inside a sphere while the button is held.
+    Sphere,
+    /// Blasts a single crater on each click.
+    Crater,
+    /// Sprinkles small random spheres onto the ground within the footprint while the button is
+    /// held.
+    Scatter,
+    /// Repaints solid voxels with a random mix of the primary and secondary types, favoring the
+    /// primary type near the center.
+    Blend,
+    /// Places the selected stamp from the `StampLibrary` on the hovered surface on each click.
+    Stamp,
+    /// Carves cave-like pockets where 3D noise is above a threshold inside the sphere while the
+    /// button is held.
+    Cave,
+}
+
+impl BrushMode {
+    /// Only the sphere brush queues its edits as `JournaledEdit`s. The other modes write to the
+    /// backbuffer directly, so they can't be used while edits are being recorded.
+    pub fn is_journaled(self) -> bool {
+        self == BrushMode::Sphere
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            BrushMode::Sphere => BrushMode::Crater,
+            BrushMode::Crater => BrushMode::Scatter,
+            BrushMode::Scatter => BrushMode::Blend,
+            BrushMode::Blend => BrushMode::Stamp,
+            BrushMode::Stamp => BrushMode::Cave,
+            BrushMode::Cave => BrushMode::Sphere,
+        }
+    }
 }
 
 impl<'a> System<'a> for VoxelBrushSystem {
@@ -57,9 +171,15 @@ impl<'a> System<'a> for VoxelBrushSystem {
         Read<'a, ObjectsUnderCursor>,
         ReadExpect<'a, VoxelMap>,
         ReadExpect<'a, ChunkCacheFlusher>,
+        ReadExpect<'a, ErosionConfig>,
+        Read<'a, EditLimits>,
+        Write<'a, EventChannel<RejectedEditEvent>>,
         WriteExpect<'a, PaintBrush>,
         WriteExpect<'a, MeshMode>,
         WriteExpect<'a, EditedChunksBackBuffer>,
+        Write<'a, EditJournal>,
+        Write<'a, StampLibrary>,
+        Read<'a, Time>,
         CameraData<'a>,
     );
 
@@ -71,9 +191,15 @@ impl<'a> System<'a> for VoxelBrushSystem {
             objects,
             voxel_map,
             cache_flusher,
+            erosion_config,
+            edit_limits,
+            mut rejected_edits,
             mut brush,
             mut mesh_mode,
             mut voxel_backbuffer,
+            mut journal,
+            mut stamps,
+            time,
             ray_data,
         ): Self::SystemData,
     ) {
@@ -84,79 +210,337 @@ impl<'a> System<'a> for VoxelBrushSystem {
         let input_events: Vec<InputEvent<GameBindings>> =
             input_events.read(&mut self.reader_id).cloned().collect();
 
+        brush.large_brush_cooldown = (brush.large_brush_cooldown - time.delta_seconds()).max(0.0);
+
+        // Includes the edits rejected by the `VoxelDoubleBufferingSystem` last frame.
+        let mut violation = None;
+        for event in rejected_edits.read(&mut self.rejected_edit_reader_id) {
+            violation = Some(event.violation);
+        }
+        if violation.is_some() && violation != self.last_violation {
+            log::warn!("Rejected edit: {:?}", violation.unwrap());
+        }
+        self.last_violation = violation;
+
+        let mut erode = false;
+        let mut place_crater = false;
+        let mut place_stamp = false;
+        let mut place_block_out = false;
         for input_event in input_events.iter() {
             match input_event {
                 InputEvent::ActionPressed(ActionBinding::IncreaseBrushRadius) => {
-                    brush.radius += 1;
+                    brush.radius = (brush.radius + 1).min(edit_limits.max_brush_radius);
                     log::info!("Set brush radius to {}", brush.radius);
                 }
                 InputEvent::ActionPressed(ActionBinding::DecreaseBrushRadius) => {
                     brush.radius = (brush.radius - 1).max(1);
                     log::info!("Set brush radius to {}", brush.radius);
                 }
+                InputEvent::ActionPressed(ActionBinding::IncreaseBrushHardness) => {
+                    brush.config.sdf_growth_factor += 1.0;
+                    log::info!(
+                        "Set brush growth factor to {}",
+                        brush.config.sdf_growth_factor
+                    );
+                }
+                InputEvent::ActionPressed(ActionBinding::DecreaseBrushHardness) => {
+                    brush.config.sdf_growth_factor =
+                        (brush.config.sdf_growth_factor - 1.0).max(1.0);
+                    log::info!(
+                        "Set brush growth factor to {}",
+                        brush.config.sdf_growth_factor
+                    );
+                }
+                InputEvent::ActionPressed(ActionBinding::IncreaseShellCutoff) => {
+                    brush.config.shell_cutoff = (brush.config.shell_cutoff + 0.05).min(1.0);
+                    log::info!("Set brush shell cutoff to {}", brush.config.shell_cutoff);
+                }
+                InputEvent::ActionPressed(ActionBinding::DecreaseShellCutoff) => {
+                    brush.config.shell_cutoff = (brush.config.shell_cutoff - 0.05).max(0.05);
+                    log::info!("Set brush shell cutoff to {}", brush.config.shell_cutoff);
+                }
+                InputEvent::ActionPressed(ActionBinding::ResetBrushDepth) => {
+                    brush.manual_depth = false;
+                }
                 InputEvent::ActionPressed(ActionBinding::ChangeMeshMode) => {
                     *mesh_mode = match *mesh_mode {
                         MeshMode::SurfaceNets => MeshMode::GreedyQuads,
                         MeshMode::GreedyQuads => MeshMode::SurfaceNets,
                     };
                 }
-                InputEvent::ButtonPressed(Button::Key(key)) => {
-                    if key_is_number(*key) {
-                        brush.voxel_type = VoxelType(key_number(*key) as u8);
-                        log::info!("Set voxel paintbrush to {:?}", brush.voxel_type);
+                InputEvent::ActionPressed(ActionBinding::ErodeTerrain) => {
+                    if journal.is_recording {
+                        log::warn!("Erosion isn't journaled, so it's off while recording edits");
+                    } else {
+                        erode = true;
+                    }
+                }
+                InputEvent::ActionPressed(ActionBinding::PlaceBlockOut) => {
+                    if journal.is_recording {
+                        log::warn!(
+                            "Block-outs aren't journaled, so they're off while recording edits"
+                        );
+                    } else {
+                        place_block_out = true;
                     }
                 }
+                InputEvent::ActionPressed(ActionBinding::CycleBrushMode) => {
+                    let mut mode = brush.mode.next();
+                    while journal.is_recording && !mode.is_journaled() {
+                        mode = mode.next();
+                    }
+                    if mode == brush.mode {
+                        log::warn!("Only journaled brush modes can be used while recording edits");
+                    } else {
+                        brush.mode = mode;
+                        log::info!("Set brush mode to {:?}", brush.mode);
+                    }
+                }
+                InputEvent::ActionPressed(ActionBinding::CycleBrushFalloff) => {
+                    brush.config.falloff = brush.config.falloff.next();
+                    log::info!("Set brush falloff to {:?}", brush.config.falloff);
+                }
+                InputEvent::ActionPressed(ActionBinding::SwapBrushVoxelTypes) => {
+                    std::mem::swap(&mut brush.voxel_type, &mut brush.secondary_voxel_type);
+                    log::info!(
+                        "Set voxel paintbrush to {:?} (secondary {:?})",
+                        brush.voxel_type,
+                        brush.secondary_voxel_type
+                    );
+                }
+                InputEvent::ActionPressed(ActionBinding::NextStamp) => {
+                    stamps.select_next();
+                    match stamps.selected() {
+                        Some(stamp) => log::info!("Selected stamp {:?}", stamp.name),
+                        None => log::warn!("The stamp library is empty"),
+                    }
+                }
+                InputEvent::ActionPressed(ActionBinding::RotateStamp) => {
+                    stamps.quarter_turns = (stamps.quarter_turns + 1) % 4;
+                    log::info!(
+                        "Set stamp rotation to {} degrees",
+                        90 * stamps.quarter_turns as u32
+                    );
+                }
+                InputEvent::ActionPressed(ActionBinding::CreateVoxel) => {
+                    place_crater = brush.mode == BrushMode::Crater;
+                    place_stamp = brush.mode == BrushMode::Stamp;
+                    // The whole stroke can be undone at once.
+                    voxel_backbuffer.begin_transaction();
+                }
+                InputEvent::ActionPressed(ActionBinding::RemoveVoxel) => {
+                    voxel_backbuffer.begin_transaction();
+                }
+                InputEvent::ActionReleased(ActionBinding::CreateVoxel)
+                | InputEvent::ActionReleased(ActionBinding::RemoveVoxel) => {
+                    voxel_backbuffer.end_transaction();
+                }
                 _ => (),
             }
         }
 
+        // Push or pull the brush along the camera ray. The distance is in world units.
+        let depth_step = BRUSH_DEPTH_SPEED * voxel_map.world_scale * time.delta_seconds();
+        let mut depth_delta = 0.0;
+        if input_handler
+            .action_is_down(&ActionBinding::PushBrush)
+            .unwrap()
+        {
+            depth_delta += depth_step;
+        }
+        if input_handler
+            .action_is_down(&ActionBinding::PullBrush)
+            .unwrap()
+        {
+            depth_delta -= depth_step;
+        }
+        if depth_delta != 0.0 {
+            brush.manual_depth = true;
+            let dist = brush
+                .dist_from_camera
+                .unwrap_or(DEFAULT_BRUSH_DIST_FROM_CAMERA);
+            brush.dist_from_camera = Some((dist + depth_delta).max(1.0));
+        }
+
         let (x, y) = match input_handler.mouse_position() {
             Some((x, y)) => (x, y),
             None => return,
         };
 
         // Figure out where the brush should go.
-        let radius = brush.dist_from_camera.unwrap_or(20.0);
+        let radius = brush
+            .dist_from_camera
+            .unwrap_or(DEFAULT_BRUSH_DIST_FROM_CAMERA);
         let camera_ray = match ray_data.get_camera_ray(x, y) {
             Some(r) => r,
             None => return,
         };
         let center = camera_ray.origin + radius * camera_ray.dir;
-        let brush_center = voxel_containing_point(center);
+        let brush_center = voxel_containing_point(center, voxel_map.world_scale);
+
+        let editing = erode
+            || place_crater
+            || place_stamp
+            || input_handler
+                .action_is_down(&ActionBinding::CreateVoxel)
+                .unwrap()
+            || input_handler
+                .action_is_down(&ActionBinding::RemoveVoxel)
+                .unwrap();
+        if editing {
+            let footprint = centered_extent(brush_center, brush.radius);
+            if let Err(violation) = edit_limits
+                .check_radius(brush.radius)
+                .and_then(|()| edit_limits.check_extent(&footprint))
+            {
+                rejected_edits.single_write(RejectedEditEvent {
+                    stamp: None,
+                    violation,
+                });
+                return;
+            }
+        }
 
         let local_cache = LocalChunkCache3::new();
         let map_reader = voxel_map.voxels.reader(&local_cache);
 
+        if place_block_out {
+            if let Some(v) = &objects.voxel {
+                // Reload the spec every time so it can be tweaked without restarting.
+                let spec_path = application_dir("assets/config")
+                    .unwrap()
+                    .join("block_out.ron");
+                match BlockOutSpec::load(&spec_path) {
+                    Ok(spec) => write_block_out(
+                        &map_reader,
+                        &spec,
+                        v.hover_adjacent_point(),
+                        &mut *voxel_backbuffer,
+                    ),
+                    Err(e) => log::error!("Failed to load {:?}: {}", spec_path, e),
+                }
+            }
+        }
+
         let mut lock_brush_dist_from_camera = false;
-        if input_handler
+        if erode {
+            log::info!("Eroding terrain around {:?}", brush_center);
+            erode_extent(
+                &map_reader,
+                &centered_extent(brush_center, brush.radius),
+                &erosion_config,
+                &mut *voxel_backbuffer,
+            );
+        } else if brush.mode == BrushMode::Crater {
+            if place_crater {
+                apply_crater(
+                    &map_reader,
+                    brush_center,
+                    &CraterParams::with_radius_and_depth(
+                        brush.radius as f32,
+                        brush.config.crater_depth,
+                    ),
+                    None,
+                    &mut *voxel_backbuffer,
+                );
+            }
+        } else if brush.mode == BrushMode::Stamp {
+            if place_stamp {
+                match (stamps.selected(), &objects.voxel) {
+                    (Some(stamp), Some(v)) => stamp.place(
+                        &map_reader,
+                        v.hover_adjacent_point(),
+                        stamps.quarter_turns,
+                        &mut *voxel_backbuffer,
+                    ),
+                    (None, _) => log::warn!("No stamp selected, save one with SaveStamp"),
+                    (_, None) => (),
+                }
+            }
+        } else if brush.mode == BrushMode::Scatter {
+            if input_handler
+                .action_is_down(&ActionBinding::CreateVoxel)
+                .unwrap()
+            {
+                lock_brush_dist_from_camera = true;
+                brush.scatter_accumulator += brush.config.scatter_density * time.delta_seconds();
+                let num_stamps = brush.scatter_accumulator.floor();
+                brush.scatter_accumulator -= num_stamps;
+                scatter_spheres(
+                    num_stamps as usize,
+                    brush_center,
+                    brush.radius,
+                    brush.voxel_type,
+                    &map_reader,
+                    &mut *voxel_backbuffer,
+                );
+            }
+        } else if brush.mode == BrushMode::Blend {
+            if input_handler
+                .action_is_down(&ActionBinding::CreateVoxel)
+                .unwrap()
+            {
+                lock_brush_dist_from_camera = true;
+                blend_paint(
+                    brush_center,
+                    brush.radius,
+                    brush.voxel_type,
+                    brush.secondary_voxel_type,
+                    &map_reader,
+                    &mut *voxel_backbuffer,
+                );
+            }
+        } else if brush.mode == BrushMode::Cave {
+            if input_handler
+                .action_is_down(&ActionBinding::CreateVoxel)
+                .unwrap()
+            {
+                lock_brush_dist_from_camera = true;
+                carve_caves(
+                    &map_reader,
+                    brush_center,
+                    brush.radius,
+                    &brush.config.cave_noise,
+                    &mut *voxel_backbuffer,
+                );
+            }
+        } else if input_handler
             .action_is_down(&ActionBinding::CreateVoxel)
             .unwrap()
         {
             lock_brush_dist_from_camera = true;
-            edit_sphere(
-                SetVoxelOperation::MakeSolid,
-                brush_center,
-                brush.radius,
-                brush.voxel_type,
-                &map_reader,
-                &mut *voxel_backbuffer,
-            );
+            if sphere_brush_is_ready(&mut brush) {
+                edit_sphere(
+                    SetVoxelOperation::MakeSolid,
+                    brush_center,
+                    brush.radius,
+                    brush.voxel_type,
+                    &brush.config,
+                    &mut *voxel_backbuffer,
+                    &mut journal,
+                    time.frame_number(),
+                );
+            }
         } else if input_handler
             .action_is_down(&ActionBinding::RemoveVoxel)
             .unwrap()
         {
             lock_brush_dist_from_camera = true;
-            edit_sphere(
-                SetVoxelOperation::RemoveSolid,
-                brush_center,
-                brush.radius,
-                EMPTY_VOXEL.voxel_type,
-                &map_reader,
-                &mut *voxel_backbuffer,
-            );
+            if sphere_brush_is_ready(&mut brush) {
+                edit_sphere(
+                    SetVoxelOperation::RemoveSolid,
+                    brush_center,
+                    brush.radius,
+                    EMPTY_VOXEL.voxel_type,
+                    &brush.config,
+                    &mut *voxel_backbuffer,
+                    &mut journal,
+                    time.frame_number(),
+                );
+            }
         }
 
-        if !lock_brush_dist_from_camera {
+        if !lock_brush_dist_from_camera && !brush.manual_depth {
             if let Some((_cam, cam_tfm)) = ray_data.get_main_camera() {
                 brush.dist_from_camera =
                     objects
@@ -173,50 +557,158 @@ impl<'a> System<'a> for VoxelBrushSystem {
     }
 }
 
-const SDF_GROWTH_FACTOR: f32 = 10.0;
+const DEFAULT_BRUSH_DIST_FROM_CAMERA: f32 = 20.0;
+/// How fast the brush moves along the camera ray when pushed or pulled, in voxels per second.
+const BRUSH_DEPTH_SPEED: f32 = 20.0;
+
+/// Spheres bigger than this are only applied every `LARGE_BRUSH_INTERVAL_SECONDS` while the
+/// button is held. Spheres of every size are queued the same way; see `edit_sphere`.
+const LARGE_BRUSH_RADIUS: u32 = 32;
+/// Large brushes are applied at most this often while the button is held.
+const LARGE_BRUSH_INTERVAL_SECONDS: f32 = 0.1;
+
+/// Returns true if the sphere brush should be applied this frame. Large brushes are rate-limited,
+/// since each application can touch millions of voxels.
+fn sphere_brush_is_ready(brush: &mut PaintBrush) -> bool {
+    if brush.radius <= LARGE_BRUSH_RADIUS {
+        return true;
+    }
+    if brush.large_brush_cooldown > 0.0 {
+        return false;
+    }
+    brush.large_brush_cooldown = LARGE_BRUSH_INTERVAL_SECONDS;
+
+    true
+}
 
+/// Queues the sphere as a stamped edit, so every stroke, however small, is applied with
+/// `edit_chunks_in_parallel` when the backbuffer is merged. In deterministic mode, only queued
+/// edits like this one are sorted by stamp. The brush modes that write to the backbuffer directly,
+/// like scatter, craters and erosion, are still applied in the order their systems ran.
+#[allow(clippy::too_many_arguments)]
 fn edit_sphere(
     operation: SetVoxelOperation,
     center: Point3i,
     radius: u32,
     voxel_type: VoxelType,
+    config: &BrushConfig,
+    voxel_backbuffer: &mut EditedChunksBackBuffer,
+    journal: &mut EditJournal,
+    frame: u64,
+) {
+    let stroke = SphereStroke {
+        operation,
+        center,
+        radius,
+        voxel_type,
+        shell_cutoff: config.shell_cutoff,
+        sdf_growth_factor: config.sdf_growth_factor,
+        falloff: config.falloff,
+    };
+    let stamp = stroke.queue(EditSourceId::LOCAL, voxel_backbuffer);
+    journal.record(frame, stamp, JournaledEdit::Sphere(stroke));
+}
+
+const SCATTER_MIN_RADIUS: f32 = 0.8;
+const SCATTER_MAX_RADIUS: f32 = 2.5;
+
+/// Drops `num_stamps` small spheres at random points on the surface within `radius` of `center`.
+/// All of the stamps are applied in a single edit so they don't overwrite each other.
+fn scatter_spheres(
+    num_stamps: usize,
+    center: Point3i,
+    radius: u32,
+    voxel_type: VoxelType,
+    map_reader: &VoxelChunkReader,
+    voxel_backbuffer: &mut EditedChunksBackBuffer,
+) {
+    if num_stamps == 0 {
+        return;
+    }
+
+    let mut rng = rand::thread_rng();
+    let r = radius as i32;
+    let map_view = map_reader.lod_view(0);
+    let mut stamps = Vec::with_capacity(num_stamps);
+    for _ in 0..num_stamps {
+        // Uniformly sample the disk of the footprint.
+        let angle = rng.gen_range(0.0, 2.0 * std::f32::consts::PI);
+        let dist = radius as f32 * rng.gen::<f32>().sqrt();
+        let x = center.x() + (dist * angle.cos()).round() as i32;
+        let z = center.z() + (dist * angle.sin()).round() as i32;
+
+        // Find the ground by scanning down the column.
+        let ground = (center.y() - r..=center.y() + r)
+            .rev()
+            .map(|y| PointN([x, y, z]))
+            .find(|p| map_view.get(*p).distance.0 < 0);
+        if let Some(ground) = ground {
+            let stamp_radius = rng.gen_range(SCATTER_MIN_RADIUS, SCATTER_MAX_RADIUS);
+            stamps.push((Point3f::from(ground), stamp_radius));
+        }
+    }
+
+    let pad = SCATTER_MAX_RADIUS.ceil() as u32 + 1;
+    voxel_backbuffer.edit_voxels_out_of_place(
+        map_reader,
+        &centered_extent(center, radius + pad),
+        |p: Point3i, v: &mut Voxel| {
+            let pf = Point3f::from(p);
+            let stamp_dist = stamps
+                .iter()
+                .map(|(c, stamp_radius)| (pf - *c).norm() - stamp_radius)
+                .fold(std::f32::MAX, f32::min);
+            let old_dist: f32 = v.distance.into();
+            if stamp_dist < old_dist {
+                v.distance = VoxelDistance::from(stamp_dist);
+                if v.distance.0 < 0 {
+                    v.voxel_type = voxel_type;
+                }
+            }
+        },
+    );
+}
+
+/// Paints the solid voxels within `radius` of `center`, choosing `inner_type` with a probability
+/// that falls off linearly from the center to the edge, and `outer_type` otherwise. The choice is
+/// a hash of the voxel's position, so holding the brush in place doesn't make the mix flicker.
+fn blend_paint(
+    center: Point3i,
+    radius: u32,
+    inner_type: VoxelType,
+    outer_type: VoxelType,
     map_reader: &VoxelChunkReader,
     voxel_backbuffer: &mut EditedChunksBackBuffer,
 ) {
     let fradius = radius as f32;
-    let sign = match operation {
-        SetVoxelOperation::MakeSolid => -1,
-        SetVoxelOperation::RemoveSolid => 1,
-    };
     voxel_backbuffer.edit_voxels_out_of_place(
         map_reader,
         &centered_extent(center, radius),
         |p: Point3i, v: &mut Voxel| {
-            let dist = (p - center).norm();
-
-            // Change the SDF faster closer to the center.
-            let sdf_delta = sign
-                * (SDF_GROWTH_FACTOR * (1.0 - dist / fradius))
-                    .max(0.0)
-                    .round() as i16;
-            let new_dist = v.distance.0 as i16 + sdf_delta;
-
-            v.distance.0 = new_dist.max(std::i8::MIN as i16).min(std::i8::MAX as i16) as i8;
-
-            if sdf_delta < 0 && v.distance.0 < 0 {
-                // Only set to the brush type if the voxel is solid.
-                v.voxel_type = voxel_type;
-            } else if sdf_delta > 0 && v.distance.0 >= 0 {
-                v.voxel_type = EMPTY_VOXEL.voxel_type;
+            if v.distance.0 >= 0 {
+                return;
+            }
+            let t = (p - center).norm() / fradius;
+            if t > 1.0 {
+                return;
             }
+            v.voxel_type = if point_hash_unit(p) > t {
+                inner_type
+            } else {
+                outer_type
+            };
         },
     );
 }
 
-fn key_number(code: VirtualKeyCode) -> u32 {
-    (code as u32 + 1) % 10
-}
+/// A cheap, stable pseudorandom number in `[0, 1)` for each point.
+fn point_hash_unit(p: Point3i) -> f32 {
+    let mut h = (p.x() as u32).wrapping_mul(73_856_093)
+        ^ (p.y() as u32).wrapping_mul(19_349_663)
+        ^ (p.z() as u32).wrapping_mul(83_492_791);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1_e995);
+    h ^= h >> 15;
 
-fn key_is_number(code: VirtualKeyCode) -> bool {
-    code as u32 + 1 <= 10
+    (h & 0x00ff_ffff) as f32 / 0x0100_0000 as f32
 }
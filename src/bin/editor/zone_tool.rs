@@ -0,0 +1,104 @@
+use crate::{
+    bindings::{ActionBinding, GameBindings},
+    control::hover_3d::ObjectsUnderCursor,
+    selection::Selection,
+};
+
+use voxel_mapper::voxel::zones::MapZones;
+
+use amethyst::{
+    core::{ecs::prelude::*, math::Point3},
+    derive::SystemDesc,
+    input::InputEvent,
+    renderer::{debug_drawing::DebugLinesComponent, palette::Srgba},
+    shrev::EventChannel,
+};
+use building_blocks::prelude::*;
+
+#[derive(Default)]
+pub struct ZoneHintTag;
+
+impl Component for ZoneHintTag {
+    type Storage = NullStorage<Self>;
+}
+
+pub fn make_zone_hint_lines(world: &mut World) {
+    world
+        .create_entity()
+        .with(ZoneHintTag)
+        .with(DebugLinesComponent::new())
+        .build();
+}
+
+/// Turns the selection into a new zone, removes the zone under the cursor, and outlines every
+/// zone. The zone under the cursor is highlighted.
+#[derive(SystemDesc)]
+#[system_desc(name(ZoneToolSystemDesc))]
+pub struct ZoneToolSystem {
+    #[system_desc(event_channel_reader)]
+    reader_id: ReaderId<InputEvent<GameBindings>>,
+}
+
+impl ZoneToolSystem {
+    pub fn new(reader_id: ReaderId<InputEvent<GameBindings>>) -> Self {
+        ZoneToolSystem { reader_id }
+    }
+}
+
+impl<'a> System<'a> for ZoneToolSystem {
+    type SystemData = (
+        Read<'a, EventChannel<InputEvent<GameBindings>>>,
+        Read<'a, ObjectsUnderCursor>,
+        Read<'a, Selection>,
+        Write<'a, MapZones>,
+        ReadStorage<'a, ZoneHintTag>,
+        WriteStorage<'a, DebugLinesComponent>,
+    );
+
+    fn run(
+        &mut self,
+        (input_events, objects, selection, mut zones, is_hint, mut debug_lines): Self::SystemData,
+    ) {
+        for input_event in input_events.read(&mut self.reader_id) {
+            match input_event {
+                InputEvent::ActionPressed(ActionBinding::CreateZone) => {
+                    if let Some(extent) = selection.extent {
+                        let name = zones.add_zone(extent);
+                        log::info!("Created {:?} from the selection", name);
+                    }
+                }
+                InputEvent::ActionPressed(ActionBinding::RemoveZone) => {
+                    if let Some(v) = &objects.voxel {
+                        if let Some(removed) = zones.remove_at(v.point()) {
+                            log::info!("Removed {:?}", removed.name);
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        let hovered_zone = objects
+            .voxel
+            .as_ref()
+            .and_then(|v| zones.zone_at(v.point()))
+            .map(|z| z.name.clone());
+
+        for (_, lines) in (&is_hint, &mut debug_lines).join() {
+            lines.clear();
+            for zone in zones.iter() {
+                let color = if Some(&zone.name) == hovered_zone.as_ref() {
+                    Srgba::new(1.0, 1.0, 1.0, 1.0)
+                } else {
+                    Srgba::new(1.0, 0.5, 0.0, 1.0)
+                };
+                for zone_box in zone.boxes.iter() {
+                    let extent = zone_box.extent();
+                    let box_min = Point3::from(Point3f::from(extent.minimum).0);
+                    let box_max = Point3::from(Point3f::from(extent.least_upper_bound()).0);
+                    lines.add_box(box_min, box_max, color);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use voxel_mapper::voxel::{heightmap::Heightmap, map_file::read_voxels_file};
+
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+/// Writes the top surface of a voxels file as a 16-bit grayscale PNG, with one pixel per XZ column.
+/// The image can be imported again with a `Heightmap` voxels file in a map.
+#[derive(StructOpt, Debug)]
+#[structopt(name = "export-heightmap")]
+struct Opt {
+    /// A voxels file saved by the editor.
+    #[structopt(parse(from_os_str))]
+    voxels_file: PathBuf,
+    /// Where to write the PNG.
+    #[structopt(parse(from_os_str))]
+    output: PathBuf,
+    /// The height in voxels of a white pixel. Defaults to the height of the highest column.
+    #[structopt(long)]
+    vertical_scale: Option<f32>,
+}
+
+fn main() -> Result<(), String> {
+    let opt = Opt::from_args();
+
+    let chunks = read_voxels_file(&opt.voxels_file)
+        .map_err(|e| format!("Failed to read {}: {:?}", opt.voxels_file.display(), e))?;
+    let ([x, z], heightmap) =
+        Heightmap::from_voxels(&chunks).ok_or_else(|| "The voxels file is empty".to_string())?;
+    let vertical_scale = opt
+        .vertical_scale
+        .unwrap_or_else(|| heightmap.max_height().max(1.0));
+
+    heightmap
+        .to_luma16(vertical_scale)
+        .save(&opt.output)
+        .map_err(|e| format!("Failed to write {}: {}", opt.output.display(), e))?;
+    println!(
+        "Wrote a {}x{} heightmap of the columns starting at X = {}, Z = {}, with vertical_scale: {}",
+        heightmap.width(),
+        heightmap.depth(),
+        x,
+        z,
+        vertical_scale
+    );
+
+    Ok(())
+}
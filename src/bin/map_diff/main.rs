@@ -0,0 +1,88 @@
+use voxel_mapper::voxel::{
+    map_diff::{diff_chunks, merge_chunks, ChunkDiff, ConflictResolution},
+    map_file::{read_voxels_file_with_shape, write_voxels_file},
+    Voxel,
+};
+
+use building_blocks::prelude::*;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+/// Compares two voxels files chunk by chunk, or applies the changes between them onto a third.
+///
+/// To merge someone else's edits, pass the voxels file both copies started from as OLD, their file
+/// as NEW, and yours with --apply-to.
+#[derive(StructOpt, Debug)]
+#[structopt(name = "map-diff")]
+struct Opt {
+    #[structopt(parse(from_os_str))]
+    old: PathBuf,
+    #[structopt(parse(from_os_str))]
+    new: PathBuf,
+    /// Applies the changes from OLD to NEW onto this voxels file.
+    #[structopt(long, parse(from_os_str), requires = "output")]
+    apply_to: Option<PathBuf>,
+    /// Where to write the merged voxels file.
+    #[structopt(long, parse(from_os_str))]
+    output: Option<PathBuf>,
+    /// Takes NEW's voxel where --apply-to also changed it, instead of keeping the --apply-to voxel.
+    #[structopt(long)]
+    take_changes: bool,
+}
+
+fn main() -> Result<(), String> {
+    let opt = Opt::from_args();
+
+    let (_, old) = read(&opt.old)?;
+    let (_, new) = read(&opt.new)?;
+
+    if let (Some(target_path), Some(output)) = (&opt.apply_to, &opt.output) {
+        let (chunk_shape, target) = read(target_path)?;
+        let resolution = if opt.take_changes {
+            ConflictResolution::TakeChanges
+        } else {
+            ConflictResolution::KeepTarget
+        };
+        let (merged, report) = merge_chunks(&old, &new, &target, resolution);
+        if !report.conflicts.is_empty() {
+            println!("Conflicting chunks, resolved with {:?}:", resolution);
+            print_diffs(&report.conflicts);
+        }
+        println!(
+            "Applied {} voxels, with conflicts in {} chunks",
+            report.num_applied,
+            report.conflicts.len()
+        );
+        write_voxels_file(output, chunk_shape, merged)
+            .map_err(|e| format!("Failed to write {}: {:?}", output.display(), e))?;
+    } else {
+        let diffs = diff_chunks(&old, &new);
+        print_diffs(&diffs);
+        println!(
+            "{} voxels changed in {} chunks",
+            diffs.iter().map(|d| d.num_changed).sum::<usize>(),
+            diffs.len()
+        );
+    }
+
+    Ok(())
+}
+
+/// Returns the chunk shape of the file along with its chunks.
+#[allow(clippy::type_complexity)]
+fn read(path: &Path) -> Result<(Point3i, Vec<(Point3i, Array3x1<Voxel>)>), String> {
+    read_voxels_file_with_shape(path)
+        .map_err(|e| format!("Failed to read {}: {:?}", path.display(), e))
+}
+
+fn print_diffs(diffs: &[ChunkDiff]) {
+    for diff in diffs.iter() {
+        println!(
+            "  chunk {:?}: {} voxels in min {:?}, max {:?}",
+            diff.chunk_min.0,
+            diff.num_changed,
+            diff.changed_extent.minimum.0,
+            diff.changed_extent.max().0
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use voxel_mapper::voxel::{
+    map_file::{load_voxel_map, snapshot_chunks},
+    map_stats::map_stats,
+};
+
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+/// Prints voxel counts per type, the solid fraction, the solid extent, the chunk count and sizes,
+/// and a histogram of solid voxels per Y layer for a map. Useful for checking what a generator or
+/// an import produced.
+#[derive(StructOpt, Debug)]
+#[structopt(name = "map-stats")]
+struct Opt {
+    /// A map file, like "assets/maps/example_map.ron".
+    #[structopt(parse(from_os_str))]
+    map_file: PathBuf,
+    /// The width in characters of the longest histogram bar.
+    #[structopt(long, default_value = "60")]
+    histogram_width: usize,
+}
+
+fn main() -> Result<(), String> {
+    let opt = Opt::from_args();
+
+    let map = load_voxel_map(&opt.map_file)
+        .map_err(|e| format!("Failed to load {}: {:?}", opt.map_file.display(), e))?;
+    let stats = map_stats(&snapshot_chunks(&map))
+        .map_err(|e| format!("Failed to compress chunks: {:?}", e))?;
+
+    println!("Chunks: {}", stats.num_chunks);
+    println!(
+        "Voxels: {} ({} solid, {} empty, {:.2}% solid)",
+        stats.num_voxels,
+        stats.num_solid,
+        stats.num_voxels - stats.num_solid,
+        100.0 * stats.solid_fraction()
+    );
+    match stats.solid_extent {
+        Some(extent) => println!(
+            "Solid extent: min {:?}, max {:?}, shape {:?}",
+            extent.minimum.0,
+            extent.max().0,
+            extent.shape.0
+        ),
+        None => println!("Solid extent: none"),
+    }
+    println!(
+        "Size: {} bytes uncompressed, {} bytes compressed ({:.1}x)",
+        stats.uncompressed_bytes,
+        stats.compressed_bytes,
+        stats.uncompressed_bytes as f32 / stats.compressed_bytes.max(1) as f32
+    );
+
+    println!("\nVoxels per type:");
+    let num_palette_types = map.palette.infos.len();
+    for (voxel_type, count) in stats.type_counts.iter().enumerate() {
+        if *count == 0 {
+            continue;
+        }
+        let note = match map.palette.infos.get(voxel_type) {
+            Some(info) if info.flags.is_empty => "empty",
+            Some(_) => "",
+            None => "not in palette",
+        };
+        println!("  {:>3}: {:>12} {}", voxel_type, count, note);
+    }
+    if num_palette_types == 0 {
+        println!("  (the palette is empty)");
+    }
+
+    println!("\nSolid voxels per Y:");
+    let max_count = stats.solid_per_y.values().copied().max().unwrap_or(0);
+    // Print from the top down, so the histogram reads like a cross section.
+    for (y, count) in stats.solid_per_y.iter().rev() {
+        let bar_len = (*count * opt.histogram_width + max_count - 1) / max_count;
+        println!("  {:>5}: {:>10} {}", y, count, "#".repeat(bar_len));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use voxel_mapper::{
+    rendering::tiles::{render_map, write_xyz_tiles, OrthoView},
+    voxel::{map_file::load_voxel_map, minimap::MinimapConfig},
+};
+
+use amethyst::config::Config;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+/// Renders a map into a directory of XYZ tiles ("{z}/{x}/{y}.png") for web map viewers. Zoom level
+/// 0 is a single tile of the whole map, and each level after it doubles the resolution, up to the
+/// first level with at least `--pixels-per-voxel`.
+#[derive(StructOpt, Debug)]
+#[structopt(name = "render-tiles")]
+struct Opt {
+    /// A map file, like "assets/maps/example_map.ron".
+    #[structopt(parse(from_os_str))]
+    map_file: PathBuf,
+    /// The directory to write the tiles in.
+    #[structopt(parse(from_os_str))]
+    output_dir: PathBuf,
+    /// The width of one voxel at the most detailed zoom level.
+    #[structopt(long, default_value = "4")]
+    pixels_per_voxel: f32,
+    /// The width and height of each tile.
+    #[structopt(long, default_value = "256")]
+    tile_size: u32,
+    /// Look at the map from the side at an isometric angle instead of straight down.
+    #[structopt(long)]
+    isometric: bool,
+    /// A minimap config with the colors of the voxel types.
+    #[structopt(long, parse(from_os_str), default_value = "assets/config/minimap.ron")]
+    colors: PathBuf,
+}
+
+fn main() -> Result<(), String> {
+    let opt = Opt::from_args();
+
+    let map = load_voxel_map(&opt.map_file)
+        .map_err(|e| format!("Failed to load {}: {:?}", opt.map_file.display(), e))?;
+    let colors = MinimapConfig::load(&opt.colors)
+        .map_err(|e| format!("Failed to load {}: {}", opt.colors.display(), e))?;
+    let view = if opt.isometric {
+        OrthoView::isometric()
+    } else {
+        OrthoView::top_down()
+    };
+
+    let rendered = render_map(&map, &view, opt.pixels_per_voxel, opt.tile_size, |t| {
+        colors.color(t)
+    })
+    .ok_or_else(|| "The map has no surfaces to render".to_string())?;
+    let num_tiles = write_xyz_tiles(&rendered, &opt.output_dir)
+        .map_err(|e| format!("Failed to write tiles: {}", e))?;
+    println!(
+        "Wrote {} tiles for zoom levels 0 to {} in {}",
+        num_tiles,
+        rendered.max_zoom,
+        opt.output_dir.display()
+    );
+
+    Ok(())
+}
@@ -1,25 +1,36 @@
-use crate::voxel::{LocalVoxelCache, VoxelMap};
+use crate::voxel::{morton::morton_ordered_chunk_mins, VoxelMap};
 
+pub mod chunk_collider;
 pub mod floor_translation;
+pub mod sweep;
 
 use building_blocks::{prelude::*, search::OctreeDbvt, storage::OctreeSet};
+use rayon::prelude::*;
 
 pub type VoxelBVT = OctreeDbvt<Point3i>;
 
-pub fn insert_all_chunk_bvts(
-    bvt: &mut VoxelBVT,
-    voxel_map: &VoxelMap,
-    chunk_cache: &LocalVoxelCache,
-) {
-    let reader = voxel_map.voxels.reader(chunk_cache);
-    for &chunk_key in voxel_map.voxels.storage().chunk_keys() {
-        let chunk = reader.get_chunk(chunk_key).unwrap();
-        let chunk_infos = TransformMap::new(chunk, voxel_map.voxel_info_transform());
-        let octree = OctreeSet::from_array3(&chunk_infos, *chunk_infos.extent());
+/// Builds the octrees for every chunk in the map in parallel and inserts them into `bvt`.
+pub fn insert_all_chunk_bvts(bvt: &mut VoxelBVT, voxel_map: &VoxelMap) {
+    let octrees: Vec<(Point3i, OctreeSet)> = morton_ordered_chunk_mins(voxel_map)
+        .into_par_iter()
+        .filter_map(|chunk_min| {
+            let local_chunk_cache = LocalChunkCache3::new();
+            let reader = voxel_map.voxels.reader(&local_chunk_cache);
+            let chunk = reader.get_chunk(ChunkKey::new(0, chunk_min))?;
+            let chunk_infos = TransformMap::new(chunk, voxel_map.voxel_info_transform());
+
+            Some((
+                chunk_min,
+                OctreeSet::from_array3(&chunk_infos, *chunk_infos.extent()),
+            ))
+        })
+        .collect();
+
+    for (chunk_min, octree) in octrees.into_iter() {
         if octree.is_empty() {
-            bvt.remove(&chunk_key.minimum);
+            bvt.remove(&chunk_min);
         } else {
-            bvt.insert(chunk_key.minimum, octree);
+            bvt.insert(chunk_min, octree);
         }
     }
 }
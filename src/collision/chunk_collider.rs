@@ -0,0 +1,108 @@
+use crate::voxel::{morton::morton_ordered_chunk_mins, VoxelMap};
+
+use building_blocks::{
+    prelude::*,
+    search::ncollide3d::{
+        na::{Isometry3, Vector3},
+        shape::{Compound, Cuboid, ShapeHandle},
+    },
+    storage::{Octant, OctreeSet, VisitStatus},
+};
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+/// A compound of boxes, one for each full octant of a chunk's `OctreeSet` (the same octrees that
+/// are inserted into the `VoxelBVT`). Uses the ncollide3d version from building-blocks, so it can
+/// be handed straight to a physics engine built on the newer nalgebra.
+pub type ChunkCollider = Compound<f32>;
+
+/// Returns `None` if the octree is empty. The boxes are in world units, scaled by `world_scale`,
+/// the map's `VoxelMap::world_scale`.
+pub fn chunk_collider(octree: &OctreeSet, world_scale: f32) -> Option<ChunkCollider> {
+    let voxel_size = world_scale;
+    let mut boxes = Vec::new();
+    octree.visit(&mut |octant: Octant, is_leaf: bool| {
+        if is_leaf {
+            let half_extent = octant.edge_length() as f32 / 2.0 * voxel_size;
+            let center = Point3f::from(octant.minimum()) * voxel_size + PointN([half_extent; 3]);
+            boxes.push((
+                Isometry3::translation(center.x(), center.y(), center.z()),
+                ShapeHandle::new(Cuboid::new(Vector3::repeat(half_extent))),
+            ));
+        }
+
+        VisitStatus::Continue
+    });
+
+    if boxes.is_empty() {
+        None
+    } else {
+        Some(Compound::new(boxes))
+    }
+}
+
+/// The collider for each non-empty chunk, kept in sync with edits by the
+/// `VoxelChunkProcessorSystem`. Colliders are only built while `enabled` is set, since the editor
+/// itself only needs the `VoxelBVT`.
+#[derive(Default)]
+pub struct ChunkColliders {
+    pub enabled: bool,
+    colliders: HashMap<Point3i, ChunkCollider>,
+    changed: HashSet<Point3i>,
+}
+
+impl ChunkColliders {
+    pub fn new_enabled() -> Self {
+        Self {
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn get(&self, chunk_min: &Point3i) -> Option<&ChunkCollider> {
+        self.colliders.get(chunk_min)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Point3i, &ChunkCollider)> {
+        self.colliders.iter()
+    }
+
+    pub fn set_collider(&mut self, chunk_min: Point3i, collider: Option<ChunkCollider>) {
+        let changed = match collider {
+            Some(c) => {
+                self.colliders.insert(chunk_min, c);
+
+                true
+            }
+            None => self.colliders.remove(&chunk_min).is_some(),
+        };
+        if changed {
+            self.changed.insert(chunk_min);
+        }
+    }
+
+    /// The chunks whose colliders were added, replaced or removed since the last call.
+    pub fn take_changed(&mut self) -> Vec<Point3i> {
+        self.changed.drain().collect()
+    }
+}
+
+/// Builds the colliders for every chunk in the map in parallel and inserts them into `colliders`.
+pub fn insert_all_chunk_colliders(colliders: &mut ChunkColliders, voxel_map: &VoxelMap) {
+    let chunk_colliders: Vec<(Point3i, ChunkCollider)> = morton_ordered_chunk_mins(voxel_map)
+        .into_par_iter()
+        .filter_map(|chunk_min| {
+            let local_chunk_cache = LocalChunkCache3::new();
+            let reader = voxel_map.voxels.reader(&local_chunk_cache);
+            let chunk = reader.get_chunk(ChunkKey::new(0, chunk_min))?;
+            let chunk_infos = TransformMap::new(chunk, voxel_map.voxel_info_transform());
+            let octree = OctreeSet::from_array3(&chunk_infos, *chunk_infos.extent());
+
+            chunk_collider(&octree, voxel_map.world_scale).map(|c| (chunk_min, c))
+        })
+        .collect();
+
+    for (chunk_min, collider) in chunk_colliders.into_iter() {
+        colliders.set_collider(chunk_min, Some(collider));
+    }
+}
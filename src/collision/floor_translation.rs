@@ -1,6 +1,6 @@
 use crate::{
     geometry::UP,
-    voxel::{voxel_containing_point, IsFloor},
+    voxel::{world_to_voxel_space, IsFloor},
 };
 
 use amethyst::core::math::{Point3, Vector3};
@@ -57,6 +57,11 @@ fn integer_points_on_line_segment_3d(
     solutions
 }
 
+/// The voxel containing `p`, which is already in voxel units.
+fn containing_voxel(p: Point3<f32>) -> Point3i {
+    PointN([p.x.floor() as i32, p.y.floor() as i32, p.z.floor() as i32])
+}
+
 /// True if the voxel at `p` is not a floor voxel AND the voxel directly under `p` is a floor voxel.
 fn voxel_is_on_top_of_floor<V, T>(p: Point3i, voxels: &V) -> bool
 where
@@ -102,7 +107,31 @@ where
 ///   1. Encountering a tall column of solid voxels
 ///   2. TODO: Tightly enclosed spaces that make it hard for camera collisions
 ///
+/// `world_scale` is the edge length of a voxel, the map's `VoxelMap::world_scale`.
 pub fn translate_over_floor<V, T>(
+    start: &Point3<f32>,
+    velocity: &Vector3<f32>,
+    voxels: &V,
+    world_scale: f32,
+    blocking_collisions: bool,
+) -> Point3<f32>
+where
+    V: Get<Point3i, Item = T>,
+    T: IsFloor,
+{
+    // The boundary crossings and probes are all in whole voxels, so do them in voxel units.
+    let voxel_size = world_scale;
+    let end = translate_over_floor_in_voxels(
+        &world_to_voxel_space(*start, world_scale),
+        &(velocity / voxel_size),
+        voxels,
+        blocking_collisions,
+    );
+
+    end * voxel_size
+}
+
+fn translate_over_floor_in_voxels<V, T>(
     start: &Point3<f32>,
     velocity: &Vector3<f32>,
     voxels: &V,
@@ -115,7 +144,7 @@ where
     let up = Vector3::from(UP);
 
     let mut start = *start;
-    let start_voxel = voxel_containing_point(start);
+    let start_voxel = containing_voxel(start);
 
     // Sometimes geometry gets created on top of the camera feet, so just probe out of it.
     if voxels.get(start_voxel).is_floor() {
@@ -147,7 +176,7 @@ where
         // entering, but we know the midpoint between boundaries will fall into the voxel we are
         // entering.
         let midpoint = (p1 + p2.coords) / 2.0;
-        let midpoint_voxel = voxel_containing_point(midpoint);
+        let midpoint_voxel = containing_voxel(midpoint);
 
         let voxel_p = midpoint_voxel + PointN([0, height_delta, 0]);
 
@@ -246,8 +275,8 @@ mod tests {
         }
     }
 
-    fn empty_voxels() -> ChunkedLatticeMap<TestVoxel> {
-        ChunkedLatticeMap::new(VOXEL_CHUNK_SHAPE)
+    fn empty_voxels(chunk_shape: Point3i) -> ChunkedLatticeMap<TestVoxel> {
+        ChunkedLatticeMap::new(chunk_shape)
     }
 
     fn make_floor_strip(voxels: &mut ChunkedLatticeMap<TestVoxel>) {
@@ -277,69 +306,83 @@ mod tests {
 
     #[test]
     fn test_translate_over_floor_flat() {
-        let mut voxels = empty_voxels();
+        let mut voxels = empty_voxels(VOXEL_CHUNK_SHAPE);
         make_floor_strip(&mut voxels);
 
         let start = Point3::new(0.5, 1.5, 0.5);
         let velocity = Vector3::new(2.0, 0.0, 0.0);
         assert_relative_eq_point3(
-            &translate_over_floor(&start, &velocity, &voxels, true),
+            &translate_over_floor(&start, &velocity, &voxels, 1.0, true),
             &(start + velocity),
         );
     }
 
     #[test]
     fn test_translate_over_floor_up_step() {
-        let mut voxels = empty_voxels();
+        let mut voxels = empty_voxels(VOXEL_CHUNK_SHAPE);
+        make_floor_strip(&mut voxels);
+        make_bump(&mut voxels);
+
+        let start = Point3::new(0.5, 1.5, 0.5);
+        let velocity = Vector3::new(1.0, 0.0, 0.0);
+        assert_relative_eq_point3(
+            &translate_over_floor(&start, &velocity, &voxels, 1.0, true),
+            &(start + velocity + Vector3::from(UP)),
+        );
+    }
+
+    #[test]
+    fn test_translate_over_floor_up_step_with_large_chunks() {
+        let mut voxels = empty_voxels(PointN([32; 3]));
         make_floor_strip(&mut voxels);
         make_bump(&mut voxels);
 
         let start = Point3::new(0.5, 1.5, 0.5);
         let velocity = Vector3::new(1.0, 0.0, 0.0);
         assert_relative_eq_point3(
-            &translate_over_floor(&start, &velocity, &voxels, true),
+            &translate_over_floor(&start, &velocity, &voxels, 1.0, true),
             &(start + velocity + Vector3::from(UP)),
         );
     }
 
     #[test]
     fn test_translate_over_floor_up_step_negative_velocity() {
-        let mut voxels = empty_voxels();
+        let mut voxels = empty_voxels(VOXEL_CHUNK_SHAPE);
         make_floor_strip(&mut voxels);
         make_bump(&mut voxels);
 
         let start = Point3::new(2.5, 1.5, 0.5);
         let velocity = Vector3::new(-1.0, 0.0, 0.0);
         assert_relative_eq_point3(
-            &translate_over_floor(&start, &velocity, &voxels, true),
+            &translate_over_floor(&start, &velocity, &voxels, 1.0, true),
             &(start + velocity + Vector3::from(UP)),
         );
     }
 
     #[test]
     fn test_translate_over_floor_down_step() {
-        let mut voxels = empty_voxels();
+        let mut voxels = empty_voxels(VOXEL_CHUNK_SHAPE);
         make_floor_strip(&mut voxels);
         make_bump(&mut voxels);
 
         let start = Point3::new(1.5, 2.5, 0.5);
         let velocity = Vector3::new(1.0, 0.0, 0.0);
         assert_relative_eq_point3(
-            &translate_over_floor(&start, &velocity, &voxels, true),
+            &translate_over_floor(&start, &velocity, &voxels, 1.0, true),
             &(start + velocity - Vector3::from(UP)),
         );
     }
 
     #[test]
     fn test_translate_over_floor_down_step_negative_velocity() {
-        let mut voxels = empty_voxels();
+        let mut voxels = empty_voxels(VOXEL_CHUNK_SHAPE);
         make_floor_strip(&mut voxels);
         make_bump(&mut voxels);
 
         let start = Point3::new(1.5, 2.5, 0.5);
         let velocity = Vector3::new(-1.0, 0.0, 0.0);
         assert_relative_eq_point3(
-            &translate_over_floor(&start, &velocity, &voxels, true),
+            &translate_over_floor(&start, &velocity, &voxels, 1.0, true),
             &(start + velocity - Vector3::from(UP)),
         );
     }
@@ -0,0 +1,178 @@
+//! Sweeps of convex shapes against voxels, for character controllers. Unlike the BVT casts in
+//! building-blocks, these only need a way to tell if a voxel is empty, so they work on any voxel
+//! map, and they use the same nalgebra as amethyst.
+//!
+//! Every voxel in the swept bounds is tested, so these are meant for short sweeps, like one frame
+//! of movement.
+
+use crate::voxel::voxel_center;
+
+use amethyst::core::math::{Isometry3, Point3, Vector3};
+use building_blocks::prelude::*;
+use ncollide3d::{
+    query::time_of_impact,
+    shape::{Capsule, Cuboid, Shape},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SweepHit {
+    /// The voxel that was hit.
+    pub point: Point3i,
+    /// The fraction of the velocity that the shape can move before touching the voxel.
+    pub toi: f32,
+    /// The outward normal of the voxel surface that was hit.
+    pub normal: Vector3<f32>,
+}
+
+/// Moves `shape` from `start` along `velocity` for up to `max_toi` and returns the first non-empty
+/// voxel that it touches. Voxels have an edge length of `world_scale`, the map's
+/// `VoxelMap::world_scale`.
+pub fn sweep_shape_at_voxels(
+    shape: &dyn Shape<f32>,
+    start: &Isometry3<f32>,
+    velocity: &Vector3<f32>,
+    max_toi: f32,
+    world_scale: f32,
+    voxel_is_empty_fn: impl Fn(&Point3i) -> bool,
+) -> Option<SweepHit> {
+    let start_aabb = shape.aabb(start);
+    let end = Isometry3::from_parts(
+        (start.translation.vector + velocity * max_toi).into(),
+        start.rotation,
+    );
+    let end_aabb = shape.aabb(&end);
+    let voxel_size = world_scale;
+    let swept_min = start_aabb.mins().inf(end_aabb.mins()) / voxel_size;
+    let swept_max = start_aabb.maxs().sup(end_aabb.maxs()) / voxel_size;
+    let extent = Extent3i::from_min_and_max(
+        PointN([
+            swept_min.x.floor() as i32,
+            swept_min.y.floor() as i32,
+            swept_min.z.floor() as i32,
+        ]),
+        PointN([
+            swept_max.x.floor() as i32,
+            swept_max.y.floor() as i32,
+            swept_max.z.floor() as i32,
+        ]),
+    );
+
+    let voxel_shape = Cuboid::new(Vector3::repeat(0.5 * voxel_size));
+    let mut closest: Option<SweepHit> = None;
+    for p in extent.iter_points() {
+        if voxel_is_empty_fn(&p) {
+            continue;
+        }
+        let center = voxel_center(p, world_scale);
+        let voxel_tfm = Isometry3::translation(center.x, center.y, center.z);
+        let toi = time_of_impact(
+            start,
+            velocity,
+            shape,
+            &voxel_tfm,
+            &Vector3::zeros(),
+            &voxel_shape,
+            max_toi,
+            0.0,
+        );
+        if let Some(toi) = toi {
+            if closest.map_or(true, |c| toi.toi < c.toi) {
+                closest = Some(SweepHit {
+                    point: p,
+                    toi: toi.toi,
+                    normal: voxel_tfm.rotation * toi.normal2.into_inner(),
+                });
+            }
+        }
+    }
+
+    closest
+}
+
+/// Sweeps an upright capsule whose center starts at `start`. The capsule's total height is
+/// `2 * (half_height + radius)`.
+pub fn sweep_capsule_at_voxels(
+    half_height: f32,
+    radius: f32,
+    start: Point3<f32>,
+    velocity: &Vector3<f32>,
+    max_toi: f32,
+    world_scale: f32,
+    voxel_is_empty_fn: impl Fn(&Point3i) -> bool,
+) -> Option<SweepHit> {
+    sweep_shape_at_voxels(
+        &Capsule::new(half_height, radius),
+        &Isometry3::translation(start.x, start.y, start.z),
+        velocity,
+        max_toi,
+        world_scale,
+        voxel_is_empty_fn,
+    )
+}
+
+/// Sweeps an axis-aligned box whose center starts at `start`.
+pub fn sweep_aabb_at_voxels(
+    half_extents: Vector3<f32>,
+    start: Point3<f32>,
+    velocity: &Vector3<f32>,
+    max_toi: f32,
+    world_scale: f32,
+    voxel_is_empty_fn: impl Fn(&Point3i) -> bool,
+) -> Option<SweepHit> {
+    sweep_shape_at_voxels(
+        &Cuboid::new(half_extents),
+        &Isometry3::translation(start.x, start.y, start.z),
+        velocity,
+        max_toi,
+        world_scale,
+        voxel_is_empty_fn,
+    )
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use amethyst::core::approx::assert_relative_eq;
+
+    #[test]
+    fn test_falling_capsule_lands_on_floor() {
+        // Solid floor below y = 0.
+        let is_empty = |p: &Point3i| p.y() >= 0;
+
+        // The bottom of the capsule starts 2 voxels above the floor.
+        let start = Point3::new(0.5, 3.5, 0.5);
+        let hit = sweep_capsule_at_voxels(
+            1.0,
+            0.5,
+            start,
+            &Vector3::new(0.0, -4.0, 0.0),
+            1.0,
+            1.0,
+            is_empty,
+        )
+        .unwrap();
+
+        assert_eq!(hit.point.y(), -1);
+        assert_relative_eq!(hit.toi, 0.5, epsilon = 1e-3);
+        assert_relative_eq!(hit.normal, Vector3::new(0.0, 1.0, 0.0), epsilon = 1e-3);
+
+        // Moving sideways along the floor doesn't hit anything.
+        let miss = sweep_aabb_at_voxels(
+            Vector3::new(0.4, 0.4, 0.4),
+            Point3::new(0.5, 0.5, 0.5),
+            &Vector3::new(5.0, 0.0, 0.0),
+            1.0,
+            1.0,
+            is_empty,
+        );
+        assert!(miss.is_none());
+    }
+}
@@ -2,7 +2,7 @@ use amethyst::{
     core::{
         alga::general::RealField,
         approx::relative_eq,
-        math::{Point2, Point3, Rotation3, Unit, Vector3},
+        math::{Matrix4, Point2, Point3, Rotation3, Unit, Vector3, Vector4},
         num::Zero,
         Transform,
     },
@@ -56,6 +56,23 @@ pub fn line_plane_intersection(l: &Line, p: &Plane) -> LinePlaneIntersection {
     }
 }
 
+/// Returns the parameters `(s, t)` of the closest points `a.p + s * a.v` and `b.p + t * b.v` on two
+/// lines, or `None` if the lines are parallel.
+pub fn closest_points_on_lines(a: &Line, b: &Line) -> Option<(f32, f32)> {
+    let w0 = a.p - b.p;
+    let aa = a.v.dot(&a.v);
+    let ab = a.v.dot(&b.v);
+    let bb = b.v.dot(&b.v);
+    let d = a.v.dot(&w0);
+    let e = b.v.dot(&w0);
+    let denom = aa * bb - ab * ab;
+    if relative_eq!(denom, f32::zero()) {
+        return None;
+    }
+
+    Some(((ab * e - bb * d) / denom, (aa * e - ab * d) / denom))
+}
+
 pub fn project_point_onto_line(p: &Point3<f32>, line: &Line) -> Point3<f32> {
     let p_v = p - line.p;
     let line_v_unit = line.v.normalize();
@@ -124,6 +141,99 @@ pub fn ritter_sphere_bounding_positions(positions: &[Position]) -> Sphere {
     ritter_sphere_bounding_points(&points)
 }
 
+/// An axis-aligned bounding box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    /// The corner farthest along `n`, used for testing against planes.
+    fn positive_vertex(&self, n: &Vector3<f32>) -> Point3<f32> {
+        Point3::new(
+            if n.x >= 0.0 { self.max.x } else { self.min.x },
+            if n.y >= 0.0 { self.max.y } else { self.min.y },
+            if n.z >= 0.0 { self.max.z } else { self.min.z },
+        )
+    }
+
+    /// Conservatively tests whether the box is on the inside of all `planes`, where each plane
+    /// `(a, b, c, d)` has the inside `a*x + b*y + c*z + d >= 0`.
+    pub fn intersects_planes(&self, planes: &[Vector4<f32>]) -> bool {
+        planes.iter().all(|plane| {
+            let n = plane.xyz();
+
+            n.dot(&self.positive_vertex(&n).coords) + plane.w >= 0.0
+        })
+    }
+}
+
+pub fn aabb_bounding_positions(positions: &[Position]) -> Aabb {
+    let mut min = Point3::from(positions[0].0);
+    let mut max = min;
+    for Position(p) in positions.iter() {
+        for i in 0..3 {
+            min[i] = min[i].min(p[i]);
+            max[i] = max[i].max(p[i]);
+        }
+    }
+
+    Aabb { min, max }
+}
+
+/// Extracts the left, right, bottom, and top clipping planes from a view-projection matrix, in the
+/// space that the matrix transforms from. The near and far planes are left out, since they depend
+/// on the depth convention of the projection.
+pub fn frustum_side_planes(view_proj: &Matrix4<f32>) -> [Vector4<f32>; 4] {
+    let row = |i: usize| view_proj.row(i).transpose();
+
+    [
+        row(3) + row(0),
+        row(3) - row(0),
+        row(3) + row(1),
+        row(3) - row(1),
+    ]
+}
+
+fn sign_not_zero(x: f32) -> f32 {
+    if x >= 0.0 {
+        1.0
+    } else {
+        -1.0
+    }
+}
+
+/// Maps a unit vector onto the octahedron and unfolds it into the square [-1, 1]^2, so it can be
+/// stored in 2 components.
+pub fn octahedral_encode(n: &Vector3<f32>) -> [f32; 2] {
+    let l1 = n.x.abs() + n.y.abs() + n.z.abs();
+    let (x, y) = (n.x / l1, n.y / l1);
+    if n.z < 0.0 {
+        [
+            (1.0 - y.abs()) * sign_not_zero(x),
+            (1.0 - x.abs()) * sign_not_zero(y),
+        ]
+    } else {
+        [x, y]
+    }
+}
+
+/// The inverse of `octahedral_encode`. This must match the decoding in the "pos_packed" shader.
+pub fn octahedral_decode(e: [f32; 2]) -> Vector3<f32> {
+    let z = 1.0 - e[0].abs() - e[1].abs();
+    let (x, y) = if z < 0.0 {
+        (
+            (1.0 - e[1].abs()) * e[0].signum(),
+            (1.0 - e[0].abs()) * e[1].signum(),
+        )
+    } else {
+        (e[0], e[1])
+    };
+
+    Vector3::new(x, y, z).normalize()
+}
+
 /// Returns pitch and yaw angles that rotates z unit vector to v. The yaw is applied first to z
 /// about the y axis to get z'. Then the pitch is applied about some axis orthogonal to z' in the
 /// XZ plane to get v.
@@ -249,6 +359,54 @@ mod tests {
 
     use amethyst::core::approx::assert_relative_eq;
 
+    #[test]
+    fn test_closest_points_on_skew_lines() {
+        let a = Line {
+            p: Point3::new(0.0, 0.0, 0.0),
+            v: Vector3::new(1.0, 0.0, 0.0),
+        };
+        let b = Line {
+            p: Point3::new(2.0, 1.0, -3.0),
+            v: Vector3::new(0.0, 0.0, 2.0),
+        };
+        let (s, t) = closest_points_on_lines(&a, &b).unwrap();
+
+        assert_relative_eq!(s, 2.0);
+        assert_relative_eq!(t, 1.5);
+    }
+
+    #[test]
+    fn test_aabb_outside_orthographic_frustum() {
+        // Orthographic projection of the cube [-1, 1]^3.
+        let planes = frustum_side_planes(&Matrix4::identity());
+        let inside = Aabb {
+            min: Point3::new(0.5, 0.5, 0.0),
+            max: Point3::new(2.0, 2.0, 1.0),
+        };
+        let outside = Aabb {
+            min: Point3::new(1.5, -0.5, 0.0),
+            max: Point3::new(2.0, 0.5, 1.0),
+        };
+
+        assert!(inside.intersects_planes(&planes));
+        assert!(!outside.intersects_planes(&planes));
+    }
+
+    #[test]
+    fn test_octahedral_round_trip() {
+        for n in [
+            Vector3::new(0.0, 0.0, 1.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            Vector3::new(1.0, -2.0, -3.0).normalize(),
+            Vector3::new(-0.3, 0.5, 0.2).normalize(),
+        ]
+        .iter()
+        {
+            let decoded = octahedral_decode(octahedral_encode(n));
+            assert_relative_eq!(decoded, *n, epsilon = 1e-5);
+        }
+    }
+
     #[test]
     fn test_yaw_and_pitch_identity() {
         let v = Vector3::new(0.0, 0.0, 1.0);
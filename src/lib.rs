@@ -1,6 +1,8 @@
 pub mod assets;
 pub mod collision;
 pub mod geometry;
+#[cfg(feature = "physics")]
+pub mod physics;
 pub mod rendering;
 pub mod voxel;
 
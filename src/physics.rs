@@ -0,0 +1,185 @@
+//! An nphysics world where the chunks of the `VoxelMap` are static colliders, so dynamic props can
+//! roll around on the terrain while it's being edited. Only built with the "physics" feature.
+
+use crate::collision::chunk_collider::ChunkColliders;
+
+use amethyst::core::{ecs::prelude::*, SystemBundle, Time, Transform};
+use building_blocks::{prelude::*, search::ncollide3d::shape::ShapeHandle};
+use nphysics3d::{
+    force_generator::DefaultForceGeneratorSet,
+    joint::DefaultJointConstraintSet,
+    math::Vector,
+    object::{
+        BodyPartHandle, ColliderDesc, DefaultBodyHandle, DefaultBodySet, DefaultColliderHandle,
+        DefaultColliderSet, Ground, RigidBody,
+    },
+    world::{DefaultGeometricalWorld, DefaultMechanicalWorld},
+};
+use std::collections::HashMap;
+
+/// Simulation steps longer than this are clamped, so a hitch doesn't launch everything through
+/// the terrain.
+const MAX_TIMESTEP_SECS: f32 = 1.0 / 20.0;
+
+pub struct PhysicsWorld {
+    pub mechanical: DefaultMechanicalWorld<f32>,
+    pub geometrical: DefaultGeometricalWorld<f32>,
+    pub bodies: DefaultBodySet<f32>,
+    pub colliders: DefaultColliderSet<f32>,
+    pub joints: DefaultJointConstraintSet<f32>,
+    pub forces: DefaultForceGeneratorSet<f32>,
+    /// The static body that all of the chunk colliders are attached to.
+    ground: DefaultBodyHandle,
+    chunk_colliders: HashMap<Point3i, DefaultColliderHandle>,
+}
+
+impl PhysicsWorld {
+    pub fn new(gravity: [f32; 3]) -> Self {
+        let mut bodies = DefaultBodySet::new();
+        let ground = bodies.insert(Ground::new());
+
+        Self {
+            mechanical: DefaultMechanicalWorld::new(Vector::new(
+                gravity[0], gravity[1], gravity[2],
+            )),
+            geometrical: DefaultGeometricalWorld::new(),
+            bodies,
+            colliders: DefaultColliderSet::new(),
+            joints: DefaultJointConstraintSet::new(),
+            forces: DefaultForceGeneratorSet::new(),
+            ground,
+            chunk_colliders: HashMap::new(),
+        }
+    }
+
+    /// Adds a body with a single collider. Attach the returned handle to an entity as a
+    /// `PhysicsBody` to have its `Transform` follow the body.
+    pub fn add_rigid_body(
+        &mut self,
+        body: RigidBody<f32>,
+        collider: ColliderDesc<f32>,
+    ) -> DefaultBodyHandle {
+        let handle = self.bodies.insert(body);
+        self.colliders
+            .insert(collider.build(BodyPartHandle(handle, 0)));
+
+        handle
+    }
+
+    fn update_chunk_collider(&mut self, chunk_min: Point3i, colliders: &ChunkColliders) {
+        if let Some(old) = self.chunk_colliders.remove(&chunk_min) {
+            self.colliders.remove(old);
+        }
+        if let Some(collider) = colliders.get(&chunk_min) {
+            let collider = ColliderDesc::new(ShapeHandle::new(collider.clone()))
+                .build(BodyPartHandle(self.ground, 0));
+            self.chunk_colliders
+                .insert(chunk_min, self.colliders.insert(collider));
+        }
+    }
+
+    fn step(&mut self, dt: f32) {
+        self.mechanical.set_timestep(dt.min(MAX_TIMESTEP_SECS));
+        self.mechanical.step(
+            &mut self.geometrical,
+            &mut self.bodies,
+            &mut self.colliders,
+            &mut self.joints,
+            &mut self.forces,
+        );
+    }
+}
+
+/// The rigid body that drives an entity's `Transform`.
+pub struct PhysicsBody(pub DefaultBodyHandle);
+
+impl Component for PhysicsBody {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Swaps the chunk colliders that changed into the `PhysicsWorld`, then steps the simulation.
+pub struct PhysicsStepSystem;
+
+impl<'a> System<'a> for PhysicsStepSystem {
+    type SystemData = (
+        Read<'a, Time>,
+        Write<'a, ChunkColliders>,
+        WriteExpect<'a, PhysicsWorld>,
+    );
+
+    fn run(&mut self, (time, mut chunk_colliders, mut physics): Self::SystemData) {
+        for chunk_min in chunk_colliders.take_changed().into_iter() {
+            physics.update_chunk_collider(chunk_min, &chunk_colliders);
+        }
+
+        let dt = time.delta_seconds();
+        if dt > 0.0 {
+            physics.step(dt);
+        }
+    }
+}
+
+/// Copies the position of each `PhysicsBody` into its `Transform`.
+pub struct PhysicsTransformSystem;
+
+impl<'a> System<'a> for PhysicsTransformSystem {
+    type SystemData = (
+        ReadExpect<'a, PhysicsWorld>,
+        ReadStorage<'a, PhysicsBody>,
+        WriteStorage<'a, Transform>,
+    );
+
+    fn run(&mut self, (physics, bodies, mut transforms): Self::SystemData) {
+        for (PhysicsBody(handle), tfm) in (&bodies, &mut transforms).join() {
+            let body = match physics.bodies.rigid_body(*handle) {
+                Some(b) => b,
+                None => continue,
+            };
+            // nphysics and amethyst share nalgebra 0.19, so the isometry can be copied as is.
+            *tfm.isometry_mut() = *body.position();
+        }
+    }
+}
+
+/// Inserts a `PhysicsWorld` and enables the `ChunkColliders`, whose changes are applied to the
+/// world every frame. Must be added after the `VoxelSystemBundle`. After loading the map, call
+/// `insert_all_chunk_colliders` so the existing chunks become part of the world too.
+pub struct PhysicsBundle {
+    pub gravity: [f32; 3],
+}
+
+impl Default for PhysicsBundle {
+    fn default() -> Self {
+        Self {
+            gravity: [0.0, -9.81, 0.0],
+        }
+    }
+}
+
+impl<'a, 'b> SystemBundle<'a, 'b> for PhysicsBundle {
+    fn build(
+        self,
+        world: &mut World,
+        dispatcher: &mut DispatcherBuilder<'a, 'b>,
+    ) -> Result<(), amethyst::Error> {
+        world.register::<PhysicsBody>();
+        world.insert(PhysicsWorld::new(self.gravity));
+        world
+            .entry::<ChunkColliders>()
+            .or_insert_with(Default::default)
+            .enabled = true;
+
+        dispatcher.add(
+            PhysicsStepSystem,
+            "physics_step",
+            &["voxel_chunk_processor"],
+        );
+        dispatcher.add(
+            PhysicsTransformSystem,
+            "physics_transform",
+            &["physics_step"],
+        );
+
+        Ok(())
+    }
+}
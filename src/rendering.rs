@@ -1 +1,14 @@
+pub mod aabb_culling;
+pub mod atlas;
+pub mod blocky_pbr_pass;
+pub mod chunk_culling;
+pub mod day_night;
+pub mod frame_capture;
+pub mod glow_shell_pass;
+pub mod merged_chunk_meshes;
+pub mod multisampling;
+pub mod range_allocator;
+pub mod shadow_pass;
+pub mod shadows;
 pub mod splatted_triplanar_pbr_pass;
+pub mod tiles;
@@ -0,0 +1,84 @@
+use crate::geometry::{frustum_side_planes, Aabb};
+
+use amethyst::{
+    core::{ecs::prelude::*, Transform},
+    renderer::{
+        camera::{ActiveCamera, Camera},
+        visibility::Visibility,
+    },
+};
+
+/// An axis-aligned bounding box in model space. Entities with one are culled more tightly than
+/// with their `BoundingSphere` alone.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingBox(pub Aabb);
+
+impl Component for BoundingBox {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Refines the `Visibility` computed by Amethyst's sphere-based "visibility_system" by removing
+/// any entities whose `BoundingBox` is outside of the camera frustum. Must run after
+/// "visibility_system".
+pub struct AabbCullingSystem;
+
+impl<'a> System<'a> for AabbCullingSystem {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, ActiveCamera>,
+        ReadStorage<'a, Camera>,
+        ReadStorage<'a, Transform>,
+        ReadStorage<'a, BoundingBox>,
+        Write<'a, Visibility>,
+    );
+
+    fn run(
+        &mut self,
+        (entities, active_camera, cameras, transforms, boxes, mut visibility): Self::SystemData,
+    ) {
+        let camera = active_camera
+            .entity
+            .and_then(|e| Some((cameras.get(e)?, transforms.get(e)?)))
+            .or_else(|| (&cameras, &transforms).join().next());
+        let (camera, camera_tfm) = match camera {
+            Some(c) => c,
+            None => return,
+        };
+        let view = match camera_tfm.global_matrix().try_inverse() {
+            Some(v) => v,
+            None => return,
+        };
+        let view_proj = camera.projection().as_matrix() * view;
+
+        let mut culled = BitSet::new();
+        for (e, bounding_box, tfm, _) in (
+            &entities,
+            &boxes,
+            &transforms,
+            &visibility.visible_unordered,
+        )
+            .join()
+        {
+            let planes = frustum_side_planes(&(view_proj * tfm.global_matrix()));
+            if !bounding_box.0.intersects_planes(&planes) {
+                culled.add(e.id());
+            }
+        }
+        // Transparent entities are only in the ordered list.
+        for e in visibility.visible_ordered.iter() {
+            if let (Some(bounding_box), Some(tfm)) = (boxes.get(*e), transforms.get(*e)) {
+                let planes = frustum_side_planes(&(view_proj * tfm.global_matrix()));
+                if !bounding_box.0.intersects_planes(&planes) {
+                    culled.add(e.id());
+                }
+            }
+        }
+
+        for id in (&culled).join() {
+            visibility.visible_unordered.remove(id);
+        }
+        visibility
+            .visible_ordered
+            .retain(|e| !culled.contains(e.id()));
+    }
+}
@@ -0,0 +1,297 @@
+//! Support for backends that lack robust texture arrays. The layers of each array material image
+//! are packed into a 2x2 atlas while loading, and the atlas variant of the splatted triplanar pass
+//! picks the tile for each material.
+
+use amethyst::{
+    assets::{AssetLoaderSystemData, Handle, Prefab},
+    core::ecs::prelude::*,
+    renderer::{
+        formats::{mtl::MaterialPrefab, texture::TexturePrefab},
+        rendy::{
+            hal::image::{Filter, Kind, SamplerInfo, ViewKind, WrapMode},
+            texture::{
+                pixel::{Rgba8Srgb, Rgba8Unorm},
+                TextureBuilder,
+            },
+        },
+        types::TextureData,
+        Material, MaterialDefaults, Texture,
+    },
+};
+use image::RgbaImage;
+use std::path::Path;
+
+/// The number of layers that fit in an atlas, matching the 2x2 grid in the atlas shader.
+pub const ATLAS_LAYERS: u32 = 4;
+
+/// Packs the layers of an array texture image into a 2x2 atlas. Array material images have their
+/// layers stacked vertically, each as wide as it is tall.
+pub fn pack_layers_into_atlas(image: &RgbaImage) -> RgbaImage {
+    let tile = image.width();
+    let num_layers = image.height() / tile;
+    if num_layers > ATLAS_LAYERS {
+        log::warn!(
+            "Image has {} layers, but only {} fit in an atlas",
+            num_layers,
+            ATLAS_LAYERS
+        );
+    }
+
+    let mut atlas = RgbaImage::new(2 * tile, 2 * tile);
+    for layer in 0..num_layers.min(ATLAS_LAYERS) {
+        let (tile_x, tile_y) = (layer % 2, layer / 2);
+        for y in 0..tile {
+            for x in 0..tile {
+                atlas.put_pixel(
+                    tile_x * tile + x,
+                    tile_y * tile + y,
+                    *image.get_pixel(x, layer * tile + y),
+                );
+            }
+        }
+    }
+
+    atlas
+}
+
+/// Creates texture data from RGBA pixels, with `layers` images of `width` x `height` stored one
+/// after another. Textures with multiple layers are array textures.
+pub fn rgba8_texture(
+    width: u32,
+    height: u32,
+    layers: u32,
+    pixels: Vec<[u8; 4]>,
+    srgb: bool,
+    filter: Filter,
+) -> TextureData {
+    let builder = TextureBuilder::new()
+        .with_kind(Kind::D2(width, height, layers as u16, 1))
+        .with_view_kind(if layers > 1 {
+            ViewKind::D2Array
+        } else {
+            ViewKind::D2
+        })
+        .with_data_width(width)
+        .with_data_height(height)
+        .with_sampler_info(SamplerInfo::new(filter, WrapMode::Tile));
+    let builder = if srgb {
+        builder.with_data(
+            pixels
+                .into_iter()
+                .map(|repr| Rgba8Srgb { repr })
+                .collect::<Vec<_>>(),
+        )
+    } else {
+        builder.with_data(
+            pixels
+                .into_iter()
+                .map(|repr| Rgba8Unorm { repr })
+                .collect::<Vec<_>>(),
+        )
+    };
+
+    builder.into()
+}
+
+/// The file paths of the textures in a material prefab, e.g. `albedo: Some(File("path", ...))`.
+/// Textures that aren't loaded from a file have no path.
+#[derive(Debug)]
+struct MaterialTexturePaths {
+    albedo: Option<String>,
+    emission: Option<String>,
+    normal: Option<String>,
+    metallic_roughness: Option<String>,
+    ambient_occlusion: Option<String>,
+    cavity: Option<String>,
+}
+
+/// Parses a material prefab file the way the prefab loader would, and returns the texture paths of
+/// the material of its first entity that has one.
+fn material_texture_paths(prefab: &str) -> Result<Option<MaterialTexturePaths>, ron::de::Error> {
+    let prefab: Prefab<MaterialPrefab> = ron::de::from_str(prefab)?;
+
+    Ok(prefab
+        .entities()
+        .find_map(|e| e.data())
+        .map(|material| MaterialTexturePaths {
+            albedo: texture_file_path(&material.albedo),
+            emission: texture_file_path(&material.emission),
+            normal: texture_file_path(&material.normal),
+            metallic_roughness: texture_file_path(&material.metallic_roughness),
+            ambient_occlusion: texture_file_path(&material.ambient_occlusion),
+            cavity: texture_file_path(&material.cavity),
+        }))
+}
+
+fn texture_file_path(texture: &Option<TexturePrefab>) -> Option<String> {
+    match texture {
+        Some(TexturePrefab::File(path, _)) => Some(path.clone()),
+        _ => None,
+    }
+}
+
+/// Loads array material prefabs as atlas textures.
+#[derive(SystemData)]
+pub struct AtlasMaterialLoader<'a> {
+    texture_loader: AssetLoaderSystemData<'a, Texture>,
+    material_loader: AssetLoaderSystemData<'a, Material>,
+    material_defaults: ReadExpect<'a, MaterialDefaults>,
+}
+
+impl<'a> AtlasMaterialLoader<'a> {
+    /// Reads the texture paths from the array material prefab at `prefab_path` and packs each
+    /// texture into an atlas. Texture paths are relative to `assets_dir`. Textures missing from
+    /// the prefab get neutral values, but the albedo is required. On failure, returns the paths
+    /// that couldn't be loaded.
+    pub fn load(
+        &self,
+        prefab_path: &Path,
+        assets_dir: &Path,
+    ) -> Result<Handle<Material>, Vec<String>> {
+        let prefab_name = || prefab_path.to_string_lossy().to_string();
+        let prefab = std::fs::read_to_string(prefab_path).map_err(|_| vec![prefab_name()])?;
+        let paths = match material_texture_paths(&prefab) {
+            Ok(Some(paths)) => paths,
+            Ok(None) => return Err(vec![format!("{} (material)", prefab_name())]),
+            Err(e) => {
+                log::error!("Failed to parse {}: {}", prefab_name(), e);
+
+                return Err(vec![prefab_name()]);
+            }
+        };
+
+        let mut missing = Vec::new();
+        let mut load = |path: Option<String>, srgb: bool, default: Option<[u8; 4]>| {
+            let texture = match path {
+                Some(path) => match image::open(assets_dir.join(&path)) {
+                    Ok(image) => {
+                        let atlas = pack_layers_into_atlas(&image.to_rgba());
+                        let (width, height) = atlas.dimensions();
+                        let pixels = atlas.pixels().map(|p| p.0).collect();
+
+                        Some(rgba8_texture(
+                            width,
+                            height,
+                            1,
+                            pixels,
+                            srgb,
+                            Filter::Linear,
+                        ))
+                    }
+                    Err(e) => {
+                        log::error!("Failed to load {}: {}", path, e);
+                        missing.push(path);
+
+                        None
+                    }
+                },
+                None => {
+                    default.map(|pixel| rgba8_texture(1, 1, 1, vec![pixel], srgb, Filter::Linear))
+                }
+            };
+
+            texture.map(|data| self.texture_loader.load_from_data(data, ()))
+        };
+
+        let albedo = load(paths.albedo, true, None);
+        let emission = load(paths.emission, true, Some([0, 0, 0, 0]));
+        let normal = load(paths.normal, false, Some([128, 128, 255, 255]));
+        let metallic_roughness = load(paths.metallic_roughness, false, Some([0, 255, 0, 255]));
+        let ambient_occlusion = load(paths.ambient_occlusion, false, Some([255; 4]));
+        let cavity = load(paths.cavity, false, Some([255; 4]));
+
+        match (
+            albedo,
+            emission,
+            normal,
+            metallic_roughness,
+            ambient_occlusion,
+            cavity,
+        ) {
+            (
+                Some(albedo),
+                Some(emission),
+                Some(normal),
+                Some(metallic_roughness),
+                Some(ambient_occlusion),
+                Some(cavity),
+            ) if missing.is_empty() => Ok(self.material_loader.load_from_data(
+                Material {
+                    albedo,
+                    emission,
+                    normal,
+                    metallic_roughness,
+                    ambient_occlusion,
+                    cavity,
+                    ..self.material_defaults.0.clone()
+                },
+                (),
+            )),
+            _ => {
+                if missing.is_empty() {
+                    missing.push(format!("{} (albedo)", prefab_name()));
+                }
+
+                Err(missing)
+            }
+        }
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::Rgba;
+
+    #[test]
+    fn test_pack_layers_into_atlas() {
+        let mut image = RgbaImage::new(2, 6);
+        for layer in 0..3 {
+            for y in 0..2 {
+                for x in 0..2 {
+                    image.put_pixel(x, 2 * layer + y, Rgba([layer as u8, 0, 0, 255]));
+                }
+            }
+        }
+
+        let atlas = pack_layers_into_atlas(&image);
+
+        assert_eq!(atlas.dimensions(), (4, 4));
+        assert_eq!(atlas.get_pixel(1, 1).0[0], 0);
+        assert_eq!(atlas.get_pixel(3, 0).0[0], 1);
+        assert_eq!(atlas.get_pixel(0, 3).0[0], 2);
+        assert_eq!(atlas.get_pixel(3, 3).0, [0; 4]);
+    }
+
+    #[test]
+    fn test_material_prefab_texture_paths() {
+        let prefab = r#"
+            #![enable(implicit_some)]
+            Prefab(
+                entities: [
+                    PrefabEntity(
+                        data: (
+                            // "normal: File(...)" in a comment isn't a texture.
+                            albedo: File("array_materials/grass/albedo.png", ("IMAGE", ())),
+                        ),
+                    ),
+                ],
+            )
+        "#;
+        let paths = material_texture_paths(prefab).unwrap().unwrap();
+
+        assert_eq!(
+            paths.albedo.as_deref(),
+            Some("array_materials/grass/albedo.png")
+        );
+        assert_eq!(paths.normal, None);
+    }
+}
@@ -0,0 +1,92 @@
+use super::splatted_triplanar_pbr_pass::{ArrayTextures, AtlasTextures, Emission};
+
+use amethyst::renderer::{mtl::FullTextureSet, pass::Base3DPassDef, RenderBase3D};
+use rendy::{
+    hal::{format::Format, pso::ShaderStageFlags},
+    mesh::{AsAttribute, AsVertex, VertexFormat},
+    shader::SpirvShader,
+    util::types::vertex::{Normal, Position, TexCoord},
+};
+use std::fmt::Debug;
+use std::marker::PhantomData;
+
+lazy_static::lazy_static! {
+    static ref POS_TEX_LAYER_NORM_VERTEX: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("shaders/pos_tex_layer_norm.spv"),
+        ShaderStageFlags::VERTEX,
+        "main",
+    ).unwrap();
+    static ref BLOCKY_PBR_FRAGMENT: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("shaders/blocky_pbr.spv"),
+        ShaderStageFlags::FRAGMENT,
+        "main",
+    ).unwrap();
+    static ref BLOCKY_PBR_ATLAS_FRAGMENT: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("shaders/blocky_pbr_atlas.spv"),
+        ShaderStageFlags::FRAGMENT,
+        "main",
+    ).unwrap();
+}
+
+/// Selects how the blocky fragment shader samples the material textures.
+pub trait BlockyTextureVariant: 'static + Debug + Send + Sync {
+    fn fragment_shader() -> &'static SpirvShader;
+}
+
+impl BlockyTextureVariant for ArrayTextures {
+    fn fragment_shader() -> &'static SpirvShader {
+        &BLOCKY_PBR_FRAGMENT
+    }
+}
+
+impl BlockyTextureVariant for AtlasTextures {
+    fn fragment_shader() -> &'static SpirvShader {
+        &BLOCKY_PBR_ATLAS_FRAGMENT
+    }
+}
+
+#[derive(Debug)]
+pub struct BlockyPbrPassDef<T = ArrayTextures>(PhantomData<T>);
+
+impl<T: BlockyTextureVariant> Base3DPassDef for BlockyPbrPassDef<T> {
+    const NAME: &'static str = "BlockyPbr";
+    type TextureSet = FullTextureSet;
+    fn vertex_shader() -> &'static SpirvShader {
+        &POS_TEX_LAYER_NORM_VERTEX
+    }
+    fn vertex_skinned_shader() -> &'static SpirvShader {
+        unimplemented!("Don't need skinning for this pass")
+    }
+    fn fragment_shader() -> &'static SpirvShader {
+        T::fragment_shader()
+    }
+    fn base_format() -> Vec<VertexFormat> {
+        vec![
+            Position::vertex(),
+            TexCoord::vertex(),
+            MaterialLayer::vertex(),
+            Normal::vertex(),
+            Emission::vertex(),
+        ]
+    }
+    fn skinned_format() -> Vec<VertexFormat> {
+        vec![]
+    }
+}
+
+/// A render pass for the meshes of the `GreedyQuads` mesh mode. Requires a vertex format of (vec3
+/// position, vec2 tex coord, u32 material layer, vec3 normal, vec3 emission). Each quad samples a
+/// single layer of the bound array material with UVs that tile once per voxel, instead of
+/// splatting with triplanar mapping. Meshes without texture coordinates are skipped, so this pass
+/// can be added alongside the splatted triplanar pass.
+pub type RenderBlockyPbr = RenderBase3D<BlockyPbrPassDef>;
+
+/// The array layer sampled by a blocky quad.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MaterialLayer(pub u32);
+
+impl AsAttribute for MaterialLayer {
+    const NAME: &'static str = "material_layer";
+    const FORMAT: Format = Format::R32Uint;
+}
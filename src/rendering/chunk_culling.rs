@@ -0,0 +1,253 @@
+use super::splatted_triplanar_pbr_pass::VoxelRenderConfig;
+
+use crate::{
+    geometry::{frustum_side_planes, Aabb},
+    voxel::{
+        meshing::VoxelMeshEntities, morton::morton_ordered_chunk_mins, Voxel, VoxelMap,
+        VoxelPalette,
+    },
+};
+
+use amethyst::{
+    core::{
+        ecs::prelude::*,
+        math::{Point3, Vector3},
+        Transform,
+    },
+    renderer::{
+        camera::{ActiveCamera, Camera},
+        visibility::Visibility,
+    },
+};
+use building_blocks::prelude::*;
+use rayon::prelude::*;
+use std::collections::HashSet;
+
+/// The chunks that are completely filled with solid, opaque voxels. Nothing behind them can be
+/// seen, so they're used by the `ChunkCullingSystem` to test for occlusion. Kept up to date by the
+/// `VoxelChunkProcessorSystem`.
+#[derive(Debug, Default)]
+pub struct OccluderChunks {
+    pub chunks: HashSet<Point3i>,
+}
+
+impl OccluderChunks {
+    pub fn set_occluder(&mut self, chunk_min: Point3i, is_occluder: bool) {
+        if is_occluder {
+            self.chunks.insert(chunk_min);
+        } else {
+            self.chunks.remove(&chunk_min);
+        }
+    }
+}
+
+pub fn is_occluder_chunk(palette: &VoxelPalette, chunk: &Array3x1<Voxel>) -> bool {
+    let mut all_opaque = true;
+    chunk.for_each(chunk.extent(), |_p: Point3i, v: Voxel| {
+        let flags = &palette.get_voxel_type_info(v.voxel_type).flags;
+        all_opaque &= v.distance.0 < 0 && !flags.is_empty && !flags.is_transparent;
+    });
+
+    all_opaque
+}
+
+/// Finds the occluders among every chunk in the map in parallel and inserts them into `occluders`.
+pub fn insert_all_occluder_chunks(occluders: &mut OccluderChunks, voxel_map: &VoxelMap) {
+    let occluder_mins: Vec<Point3i> = morton_ordered_chunk_mins(voxel_map)
+        .into_par_iter()
+        .filter(|chunk_min| {
+            let local_chunk_cache = LocalChunkCache3::new();
+            let reader = voxel_map.voxels.reader(&local_chunk_cache);
+
+            reader
+                .get_chunk(ChunkKey::new(0, *chunk_min))
+                .map_or(false, |chunk| is_occluder_chunk(&voxel_map.palette, chunk))
+        })
+        .collect();
+
+    occluders.chunks.extend(occluder_mins);
+}
+
+/// Hides the mesh entities of chunks whose extent is outside of the camera frustum. If
+/// `VoxelRenderConfig::occlusion_culling` is set, chunks that are hidden behind `OccluderChunks`
+/// are hidden as well. Must run after "visibility_system".
+pub struct ChunkCullingSystem;
+
+impl<'a> System<'a> for ChunkCullingSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        Read<'a, VoxelRenderConfig>,
+        Read<'a, ActiveCamera>,
+        ReadStorage<'a, Camera>,
+        ReadStorage<'a, Transform>,
+        Read<'a, VoxelMeshEntities>,
+        Read<'a, OccluderChunks>,
+        ReadExpect<'a, VoxelMap>,
+        Write<'a, Visibility>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            render_config,
+            active_camera,
+            cameras,
+            transforms,
+            mesh_entities,
+            occluders,
+            voxel_map,
+            mut visibility,
+        ): Self::SystemData,
+    ) {
+        let camera = active_camera
+            .entity
+            .and_then(|e| Some((cameras.get(e)?, transforms.get(e)?)))
+            .or_else(|| (&cameras, &transforms).join().next());
+        let (camera, camera_tfm) = match camera {
+            Some(c) => c,
+            None => return,
+        };
+        let view = match camera_tfm.global_matrix().try_inverse() {
+            Some(v) => v,
+            None => return,
+        };
+        // Chunk meshes are in world coordinates.
+        let planes = frustum_side_planes(&(camera.projection().as_matrix() * view));
+        let eye = camera_tfm
+            .global_matrix()
+            .transform_point(&Point3::origin());
+
+        let indexer = &voxel_map.voxels.indexer;
+        let mut culled = BitSet::new();
+        for (chunk_min, entities) in mesh_entities.chunk_entities.iter() {
+            if entities.is_empty() {
+                continue;
+            }
+            let extent = indexer.extent_for_chunk_with_min(*chunk_min);
+            let aabb = extent_aabb(&extent);
+            let is_hidden = !aabb.intersects_planes(&planes)
+                || (render_config.occlusion_culling
+                    && is_chunk_occluded(&eye, &extent, &occluders, indexer.chunk_shape()));
+            if is_hidden {
+                for e in entities.iter() {
+                    culled.add(e.id());
+                }
+            }
+        }
+
+        for id in (&culled).join() {
+            visibility.visible_unordered.remove(id);
+        }
+        visibility
+            .visible_ordered
+            .retain(|e| !culled.contains(e.id()));
+    }
+}
+
+fn extent_aabb(extent: &Extent3i) -> Aabb {
+    let min = Point3f::from(extent.minimum).0;
+    let max = Point3f::from(extent.minimum + extent.shape).0;
+
+    Aabb {
+        min: Point3::new(min[0], min[1], min[2]),
+        max: Point3::new(max[0], max[1], max[2]),
+    }
+}
+
+/// A coarse occlusion test: the chunk is occluded if the lines from the eye to its center and to
+/// each of its corners all pass through an occluder chunk. Small gaps between occluders can be
+/// missed, which only means fewer chunks get culled.
+fn is_chunk_occluded(
+    eye: &Point3<f32>,
+    extent: &Extent3i,
+    occluders: &OccluderChunks,
+    chunk_shape: Point3i,
+) -> bool {
+    if occluders.chunks.is_empty() {
+        return false;
+    }
+
+    let aabb = extent_aabb(extent);
+    let center = aabb.min + (aabb.max - aabb.min) / 2.0;
+    // Pull the corners in a little, so the lines end inside of this chunk instead of grazing its
+    // neighbors.
+    let inset = Vector3::new(0.01, 0.01, 0.01);
+    let (min, max) = (aabb.min + inset, aabb.max - inset);
+    let corners = [
+        Point3::new(min.x, min.y, min.z),
+        Point3::new(max.x, min.y, min.z),
+        Point3::new(min.x, max.y, min.z),
+        Point3::new(max.x, max.y, min.z),
+        Point3::new(min.x, min.y, max.z),
+        Point3::new(max.x, min.y, max.z),
+        Point3::new(min.x, max.y, max.z),
+        Point3::new(max.x, max.y, max.z),
+    ];
+
+    std::iter::once(&center)
+        .chain(corners.iter())
+        .all(|target| line_hits_occluder(eye, target, extent.minimum, occluders, chunk_shape))
+}
+
+/// Samples the line from `from` to `to` every half chunk, looking for an occluder other than the
+/// chunk at `to` (`target_min`) or the chunk containing `from`.
+fn line_hits_occluder(
+    from: &Point3<f32>,
+    to: &Point3<f32>,
+    target_min: Point3i,
+    occluders: &OccluderChunks,
+    chunk_shape: Point3i,
+) -> bool {
+    let chunk_min_containing = |p: Point3<f32>| {
+        PointN([
+            (p.x / chunk_shape.x() as f32).floor() as i32 * chunk_shape.x(),
+            (p.y / chunk_shape.y() as f32).floor() as i32 * chunk_shape.y(),
+            (p.z / chunk_shape.z() as f32).floor() as i32 * chunk_shape.z(),
+        ])
+    };
+    let eye_min = chunk_min_containing(*from);
+
+    let step = chunk_shape.x().min(chunk_shape.y()).min(chunk_shape.z()) as f32 / 2.0;
+    let length = (to - from).norm();
+    let num_steps = (length / step).ceil() as usize;
+    (1..num_steps).any(|i| {
+        let p = from + (to - from) * (i as f32 / num_steps as f32);
+        let chunk_min = chunk_min_containing(p);
+
+        chunk_min != eye_min && chunk_min != target_min && occluders.chunks.contains(&chunk_min)
+    })
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_behind_wall_of_occluders_is_occluded() {
+        let shape = PointN([16; 3]);
+        let mut occluders = OccluderChunks::default();
+        // A 3x3 wall of solid chunks at z = 16, in front of the chunk at z = 32.
+        for x in -1..=1 {
+            for y in -1..=1 {
+                occluders.set_occluder(PointN([16 * x, 16 * y, 16]), true);
+            }
+        }
+        let eye = Point3::new(8.0, 8.0, 8.0);
+        let behind = Extent3i::from_min_and_shape(PointN([0, 0, 32]), shape);
+        assert!(is_chunk_occluded(&eye, &behind, &occluders, shape));
+
+        // The chunk to the side can be seen around the wall.
+        let beside = Extent3i::from_min_and_shape(PointN([64, 0, 32]), shape);
+        assert!(!is_chunk_occluded(&eye, &beside, &occluders, shape));
+
+        occluders.set_occluder(PointN([0, 0, 16]), false);
+        assert!(!is_chunk_occluded(&eye, &behind, &occluders, shape));
+    }
+}
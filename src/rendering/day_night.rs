@@ -0,0 +1,219 @@
+use amethyst::{
+    core::{ecs::prelude::*, math::Vector3, Time},
+    renderer::{
+        light::{DirectionalLight, Light},
+        palette::Srgb,
+        pass::SkyboxSettings,
+    },
+};
+use serde::{Deserialize, Serialize};
+
+/// The gradient drawn by `RenderSkybox`, in sRGB.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SkyColors {
+    pub zenith: [f32; 3],
+    pub nadir: [f32; 3],
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DayNightConfig {
+    /// Real seconds for a full 24 hour day. Time stands still if this is zero.
+    pub day_length_secs: f32,
+    /// The time of day when the map is opened, in hours.
+    pub start_hour: f32,
+    /// How far the sun's path is tilted from straight overhead, towards +Z, in degrees.
+    pub sun_tilt_degrees: f32,
+    pub sun_intensity: f32,
+    /// sRGB color of the sun at noon.
+    pub sun_color: [f32; 3],
+    /// sRGB color of the sun when it's at the horizon.
+    pub horizon_sun_color: [f32; 3],
+    pub day_sky: SkyColors,
+    pub night_sky: SkyColors,
+}
+
+impl Default for DayNightConfig {
+    fn default() -> Self {
+        Self {
+            day_length_secs: 600.0,
+            start_hour: 10.0,
+            sun_tilt_degrees: 30.0,
+            sun_intensity: 3.0,
+            sun_color: [1.0, 0.98, 0.92],
+            horizon_sun_color: [1.0, 0.5, 0.25],
+            day_sky: SkyColors {
+                zenith: [0.18, 0.11, 0.85],
+                nadir: [0.82, 0.51, 0.50],
+            },
+            night_sky: SkyColors {
+                zenith: [0.0, 0.0, 0.02],
+                nadir: [0.03, 0.03, 0.08],
+            },
+        }
+    }
+}
+
+/// The time of day in hours, from 0 (midnight) up to 24. Updated by the `DayNightSystem`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimeOfDay {
+    pub hours: f32,
+}
+
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        Self { hours: 12.0 }
+    }
+}
+
+impl TimeOfDay {
+    pub fn new(hours: f32) -> Self {
+        Self {
+            hours: hours.rem_euclid(24.0),
+        }
+    }
+
+    pub fn advance(&mut self, hours: f32) {
+        self.hours = (self.hours + hours).rem_euclid(24.0);
+    }
+
+    /// The angle of the sun along its path, zero at sunrise (6:00) and `PI` at sunset (18:00).
+    fn sun_angle(&self) -> f32 {
+        (self.hours - 6.0) / 24.0 * 2.0 * std::f32::consts::PI
+    }
+
+    /// The unit vector pointing towards the sun. It rises in +X and sets in -X.
+    pub fn sun_position(&self, tilt_degrees: f32) -> Vector3<f32> {
+        let angle = self.sun_angle();
+        let tilt = tilt_degrees.to_radians();
+        let up = angle.sin();
+
+        Vector3::new(angle.cos(), up * tilt.cos(), up * tilt.sin())
+    }
+
+    /// How much of the daytime lighting is used, from 0 at night to 1 during the day. Fades over
+    /// the hour or so around sunrise and sunset.
+    pub fn daylight(&self) -> f32 {
+        let elevation = self.sun_angle().sin();
+        let t = ((elevation + 0.1) / 0.3).max(0.0).min(1.0);
+
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+/// Marks the `DirectionalLight` that the `DayNightSystem` moves along with the time of day.
+#[derive(Default)]
+pub struct SunTag;
+
+impl Component for SunTag {
+    type Storage = NullStorage<Self>;
+}
+
+/// Inserts the resources used by the `DayNightSystem` and creates the sun.
+pub fn start_day_night_cycle(config: DayNightConfig, world: &mut World) {
+    world.register::<SunTag>();
+
+    let time = TimeOfDay::new(config.start_hour);
+    let (sun, sky) = sun_and_sky(&time, &config);
+    world
+        .create_entity()
+        .with(Light::from(sun))
+        .with(SunTag)
+        .build();
+    world.insert(sky);
+    world.insert(time);
+    world.insert(config);
+}
+
+/// Advances the `TimeOfDay` and updates the sun and sky to match. Expects the resources inserted by
+/// `start_day_night_cycle`.
+pub struct DayNightSystem;
+
+impl<'a> System<'a> for DayNightSystem {
+    type SystemData = (
+        Read<'a, Time>,
+        ReadExpect<'a, DayNightConfig>,
+        WriteExpect<'a, TimeOfDay>,
+        WriteExpect<'a, SkyboxSettings>,
+        ReadStorage<'a, SunTag>,
+        WriteStorage<'a, Light>,
+    );
+
+    fn run(
+        &mut self,
+        (time, config, mut time_of_day, mut sky, suns, mut lights): Self::SystemData,
+    ) {
+        if config.day_length_secs > 0.0 {
+            time_of_day.advance(time.delta_seconds() * 24.0 / config.day_length_secs);
+        }
+
+        let (new_sun, new_sky) = sun_and_sky(&time_of_day, &config);
+        for (_, light) in (&suns, &mut lights).join() {
+            if let Light::Directional(sun) = light {
+                *sun = new_sun.clone();
+            }
+        }
+        *sky = new_sky;
+    }
+}
+
+fn sun_and_sky(time: &TimeOfDay, config: &DayNightConfig) -> (DirectionalLight, SkyboxSettings) {
+    let daylight = time.daylight();
+    let sun_position = time.sun_position(config.sun_tilt_degrees);
+    // The sun is reddest at the horizon.
+    let noon = sun_position.y.max(0.0).sqrt();
+    let sun_color = lerp3(config.horizon_sun_color, config.sun_color, noon);
+    let sun = DirectionalLight {
+        color: srgb(sun_color),
+        intensity: daylight * config.sun_intensity,
+        direction: -sun_position,
+    };
+
+    let zenith = lerp3(config.night_sky.zenith, config.day_sky.zenith, daylight);
+    let nadir = lerp3(config.night_sky.nadir, config.day_sky.nadir, daylight);
+    let sky = SkyboxSettings {
+        zenith_color: srgb(zenith),
+        nadir_color: srgb(nadir),
+    };
+
+    (sun, sky)
+}
+
+fn lerp3(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+    [
+        a[0] + t * (b[0] - a[0]),
+        a[1] + t * (b[1] - a[1]),
+        a[2] + t * (b[2] - a[2]),
+    ]
+}
+
+fn srgb([r, g, b]: [f32; 3]) -> Srgb {
+    Srgb::new(r, g, b)
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sun_is_up_during_the_day() {
+        let noon = TimeOfDay::new(12.0);
+        assert!(noon.sun_position(30.0).y > 0.8);
+        assert_eq!(noon.daylight(), 1.0);
+
+        let mut midnight = TimeOfDay::new(18.0);
+        midnight.advance(6.0);
+        assert_eq!(midnight.hours, 0.0);
+        assert!(midnight.sun_position(30.0).y < -0.8);
+        assert_eq!(midnight.daylight(), 0.0);
+
+        let dusk = TimeOfDay::new(18.0).daylight();
+        assert!(dusk > 0.0 && dusk < 1.0);
+    }
+}
@@ -0,0 +1,175 @@
+//! Saving rendered frames as PNG images, e.g. for sharing previews of a map, and the camera path of
+//! a turntable render that orbits a point and saves one frame per step.
+//!
+//! TODO: read the frames back from the GPU. The window's color image only exists inside the render
+//! graph that `RenderingBundle` builds, and it's handed straight to the present node, so getting at
+//! it needs a graph node of our own that copies it into a host-visible buffer after the last pass
+//! and maps the buffer once its fence signals. Until that node exists, capture requests are only
+//! queued in `FrameCaptureRequests`, and nothing fills the `CapturedFrames`.
+
+use amethyst::core::{
+    ecs::prelude::*,
+    math::{Point3, Vector3},
+};
+use image::{ImageBuffer, ImageResult, Rgba};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FrameCaptureConfig {
+    /// The directory that screenshots and turntable frames are saved in.
+    pub output_dir: String,
+    pub turntable: TurntableConfig,
+}
+
+impl Default for FrameCaptureConfig {
+    fn default() -> Self {
+        Self {
+            output_dir: "captures".to_string(),
+            turntable: TurntableConfig::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct TurntableConfig {
+    /// The number of frames in one full orbit.
+    pub num_frames: u32,
+    /// How far above the horizon the camera looks down from.
+    pub pitch_degrees: f32,
+}
+
+impl Default for TurntableConfig {
+    fn default() -> Self {
+        Self {
+            num_frames: 120,
+            pitch_degrees: 30.0,
+        }
+    }
+}
+
+impl TurntableConfig {
+    /// Where the camera is for `frame` of an orbit around `target` at `radius`. The first frame
+    /// looks along -Z, and the camera circles counterclockwise when seen from above.
+    pub fn eye(&self, target: Point3<f32>, radius: f32, frame: u32) -> Point3<f32> {
+        let yaw = frame as f32 / self.num_frames.max(1) as f32 * std::f32::consts::PI * 2.0;
+        let pitch = self.pitch_degrees.to_radians();
+        let horizontal = radius * pitch.cos();
+
+        target
+            + Vector3::new(
+                horizontal * yaw.sin(),
+                radius * pitch.sin(),
+                horizontal * yaw.cos(),
+            )
+    }
+}
+
+/// The paths of the frames to save, in the order they were requested. Each request is for the next
+/// frame that's rendered.
+#[derive(Debug, Default)]
+pub struct FrameCaptureRequests {
+    paths: Vec<PathBuf>,
+}
+
+impl FrameCaptureRequests {
+    pub fn request(&mut self, path: PathBuf) {
+        self.paths.push(path);
+    }
+
+    pub fn take(&mut self) -> Vec<PathBuf> {
+        std::mem::replace(&mut self.paths, Vec::new())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+}
+
+/// The pixels of one rendered frame.
+#[derive(Clone, Debug)]
+pub struct CapturedFrame {
+    pub width: u32,
+    pub height: u32,
+    /// RGBA in sRGB, row by row from the top of the frame.
+    pub pixels: Vec<[u8; 4]>,
+}
+
+impl CapturedFrame {
+    pub fn save_png(&self, path: impl AsRef<Path>) -> ImageResult<()> {
+        assert_eq!(self.pixels.len(), (self.width * self.height) as usize);
+
+        let image = ImageBuffer::from_fn(self.width, self.height, |x, y| {
+            Rgba(self.pixels[(y * self.width + x) as usize])
+        });
+
+        image.save(path)
+    }
+}
+
+/// Frames that were read back for a request, waiting to be saved by the `FrameCaptureSystem`.
+#[derive(Debug, Default)]
+pub struct CapturedFrames {
+    pub frames: Vec<(PathBuf, CapturedFrame)>,
+}
+
+/// The path of frame number `frame` in a sequence, like "turntable_0007.png", so the frames sort in
+/// order.
+pub fn numbered_frame_path(dir: impl AsRef<Path>, prefix: &str, frame: u32) -> PathBuf {
+    dir.as_ref().join(format!("{}_{:04}.png", prefix, frame))
+}
+
+/// Saves the `CapturedFrames` as PNG files, creating their directories as needed.
+pub struct FrameCaptureSystem;
+
+impl<'a> System<'a> for FrameCaptureSystem {
+    type SystemData = Write<'a, CapturedFrames>;
+
+    fn run(&mut self, mut captured: Self::SystemData) {
+        for (path, frame) in captured.frames.drain(..) {
+            if let Some(dir) = path.parent() {
+                if let Err(e) = std::fs::create_dir_all(dir) {
+                    log::error!("Failed to create {}: {}", dir.display(), e);
+                    continue;
+                }
+            }
+            match frame.save_png(&path) {
+                Ok(()) => log::info!("Saved frame to {}", path.display()),
+                Err(e) => log::error!("Failed to save frame to {}: {}", path.display(), e),
+            }
+        }
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_turntable_orbits_at_radius() {
+        let config = TurntableConfig {
+            num_frames: 4,
+            pitch_degrees: 0.0,
+        };
+        let target = Point3::new(1.0, 2.0, 3.0);
+
+        let first = config.eye(target, 10.0, 0);
+        assert!((first - Point3::new(1.0, 2.0, 13.0)).norm() < 1e-4);
+        let quarter = config.eye(target, 10.0, 1);
+        assert!((quarter - Point3::new(11.0, 2.0, 3.0)).norm() < 1e-4);
+        // An orbit ends just before it gets back to the first frame, so the sequence loops.
+        assert!((config.eye(target, 10.0, 4) - first).norm() < 1e-4);
+
+        assert_eq!(
+            numbered_frame_path("captures", "turntable", 7),
+            PathBuf::from("captures/turntable_0007.png")
+        );
+    }
+}
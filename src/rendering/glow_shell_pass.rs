@@ -0,0 +1,61 @@
+use amethyst::renderer::{mtl::FullTextureSet, pass::Base3DPassDef, RenderBase3D};
+use rendy::{
+    hal::{format::Format, pso::ShaderStageFlags},
+    mesh::{AsAttribute, AsVertex, VertexFormat},
+    shader::SpirvShader,
+    util::types::vertex::{Normal, Position},
+};
+
+lazy_static::lazy_static! {
+    static ref GLOW_VERTEX: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("shaders/glow_shell.spv"),
+        ShaderStageFlags::VERTEX,
+        "main",
+    ).unwrap();
+    static ref GLOW_FRAGMENT: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("shaders/glow.spv"),
+        ShaderStageFlags::FRAGMENT,
+        "main",
+    ).unwrap();
+}
+
+#[derive(Debug)]
+pub struct GlowShellPassDef;
+
+impl Base3DPassDef for GlowShellPassDef {
+    const NAME: &'static str = "GlowShell";
+    type TextureSet = FullTextureSet;
+    fn vertex_shader() -> &'static SpirvShader {
+        &GLOW_VERTEX
+    }
+    fn vertex_skinned_shader() -> &'static SpirvShader {
+        unimplemented!("Don't need skinning for this pass")
+    }
+    fn fragment_shader() -> &'static SpirvShader {
+        &GLOW_FRAGMENT
+    }
+    fn base_format() -> Vec<VertexFormat> {
+        vec![Position::vertex(), Normal::vertex(), GlowEmission::vertex()]
+    }
+    fn skinned_format() -> Vec<VertexFormat> {
+        vec![]
+    }
+}
+
+/// Draws a glowing shell around emissive voxels. This isn't bloom, since nothing blurs the bright
+/// parts of the rendered frame. Instead, the emissive triangles of each chunk get a "glow" mesh
+/// (with the `Transparent` component), which this pass draws as a halo around the surface, alpha
+/// blended over everything else. Requires a vertex format of (vec3 position, vec3 normal, vec3 glow
+/// emission).
+pub type RenderGlowShells = RenderBase3D<GlowShellPassDef>;
+
+/// The linear RGB emission of a glow mesh vertex. It has a different name than the `Emission` of
+/// the chunk meshes, so the passes don't draw each other's meshes.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GlowEmission(pub [f32; 3]);
+
+impl AsAttribute for GlowEmission {
+    const NAME: &'static str = "glow_emission";
+    const FORMAT: Format = Format::Rgb32Sfloat;
+}
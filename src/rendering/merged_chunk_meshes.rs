@@ -0,0 +1,293 @@
+//! Merges the opaque chunk meshes into one mesh per region of chunks, so a map needs far fewer
+//! draw calls and `Mesh` assets than it has chunks.
+//!
+//! Each region keeps its vertices and indices on the CPU, with a range of each buffer allocated for
+//! every chunk in the region. Re-meshing a chunk only rewrites its own ranges. The whole region
+//! mesh is still uploaded again, because `RenderBase3D` draws every index of a `Mesh` and can't
+//! write into part of an existing buffer, but a region only changes once per frame, no matter how
+//! many of its chunks were re-meshed.
+
+use super::range_allocator::RangeAllocator;
+
+use crate::{
+    assets::{IndexedPosColorNormVertices, PosColorNormVertices},
+    voxel::VOXEL_CHUNK_SHAPE,
+};
+
+use building_blocks::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+/// The number of chunks along each axis of a region.
+pub const REGION_CHUNKS: i32 = 4;
+
+/// Regions smaller than this are never compacted, since their holes don't cost much.
+const MIN_COMPACT_VERTICES: u32 = 1 << 14;
+
+/// The minimum of the region that contains the chunk at `chunk_min`, for a map with chunks of
+/// `chunk_shape`.
+pub fn region_min_for_chunk(chunk_min: Point3i, chunk_shape: Point3i) -> Point3i {
+    let region_shape = chunk_shape * REGION_CHUNKS;
+
+    chunk_min.vector_div_floor(&region_shape) * region_shape
+}
+
+#[derive(Clone, Debug)]
+struct ChunkRanges {
+    vertices: Range<u32>,
+    indices: Range<u32>,
+}
+
+#[derive(Default)]
+struct MergedRegion {
+    /// Freed vertices are left in place and aren't referenced by any index.
+    vertices: PosColorNormVertices,
+    /// Freed indices are zeroed, so they only make degenerate triangles.
+    indices: Vec<u32>,
+    vertex_alloc: RangeAllocator,
+    index_alloc: RangeAllocator,
+    chunks: HashMap<Point3i, ChunkRanges>,
+}
+
+impl MergedRegion {
+    fn set_chunk(&mut self, chunk_min: Point3i, ivs: Option<IndexedPosColorNormVertices>) {
+        if let Some(old) = self.chunks.remove(&chunk_min) {
+            self.free_chunk(old);
+        }
+
+        if let Some(IndexedPosColorNormVertices { indices, vertices }) = ivs {
+            let vertex_range = self.vertex_alloc.allocate(vertices.positions.len() as u32);
+            let index_range = self.index_alloc.allocate(indices.len() as u32);
+
+            let v = vertex_range.start as usize;
+            write_range(&mut self.vertices.positions, v, &vertices.positions);
+            write_range(&mut self.vertices.materials, v, &vertices.materials);
+            write_range(&mut self.vertices.normals, v, &vertices.normals);
+            write_range(&mut self.vertices.emissions, v, &vertices.emissions);
+            write_range(&mut self.vertices.tex_coords, v, &vertices.tex_coords);
+            let offset_indices: Vec<u32> = indices.iter().map(|i| i + vertex_range.start).collect();
+            write_range(
+                &mut self.indices,
+                index_range.start as usize,
+                &offset_indices,
+            );
+
+            self.chunks.insert(
+                chunk_min,
+                ChunkRanges {
+                    vertices: vertex_range,
+                    indices: index_range,
+                },
+            );
+        }
+
+        let num_vertices = self.vertex_alloc.len();
+        if num_vertices >= MIN_COMPACT_VERTICES && 2 * self.vertex_alloc.num_free() > num_vertices {
+            self.compact();
+        }
+    }
+
+    fn free_chunk(&mut self, ranges: ChunkRanges) {
+        for i in ranges.indices.clone() {
+            self.indices[i as usize] = 0;
+        }
+        self.vertex_alloc.free(ranges.vertices);
+        self.index_alloc.free(ranges.indices);
+
+        // The allocators shrink when the end of a buffer is freed.
+        let num_vertices = self.vertex_alloc.len() as usize;
+        self.vertices.positions.truncate(num_vertices);
+        self.vertices.materials.truncate(num_vertices);
+        self.vertices.normals.truncate(num_vertices);
+        self.vertices.emissions.truncate(num_vertices);
+        self.vertices.tex_coords.truncate(num_vertices);
+        self.indices.truncate(self.index_alloc.len() as usize);
+    }
+
+    fn chunk_vertices(&self, ranges: &ChunkRanges) -> IndexedPosColorNormVertices {
+        let v = ranges.vertices.start as usize..ranges.vertices.end as usize;
+        let vertices = PosColorNormVertices {
+            positions: read_range(&self.vertices.positions, &v),
+            materials: read_range(&self.vertices.materials, &v),
+            normals: read_range(&self.vertices.normals, &v),
+            emissions: read_range(&self.vertices.emissions, &v),
+            tex_coords: read_range(&self.vertices.tex_coords, &v),
+        };
+        let indices = self.indices[ranges.indices.start as usize..ranges.indices.end as usize]
+            .iter()
+            .map(|i| i - ranges.vertices.start)
+            .collect();
+
+        IndexedPosColorNormVertices { indices, vertices }
+    }
+
+    /// Rewrites the buffers without any of the freed ranges.
+    fn compact(&mut self) {
+        let mut chunk_mins: Vec<Point3i> = self.chunks.keys().cloned().collect();
+        chunk_mins.sort_by_key(|p| (p.z(), p.y(), p.x()));
+
+        let mut compacted = MergedRegion::default();
+        for chunk_min in chunk_mins.into_iter() {
+            let ivs = self.chunk_vertices(&self.chunks[&chunk_min]);
+            compacted.set_chunk(chunk_min, Some(ivs));
+        }
+
+        *self = compacted;
+    }
+
+    fn vertices(&self) -> IndexedPosColorNormVertices {
+        IndexedPosColorNormVertices {
+            indices: self.indices.clone(),
+            vertices: self.vertices.clone(),
+        }
+    }
+}
+
+/// `items` must either fit inside of `buffer` or start at its end, like the ranges given out by a
+/// `RangeAllocator`. Empty attributes (like the tex coords of smooth meshes) stay empty.
+fn write_range<T: Copy>(buffer: &mut Vec<T>, start: usize, items: &[T]) {
+    if items.is_empty() {
+        return;
+    }
+    if start == buffer.len() {
+        buffer.extend_from_slice(items);
+    } else {
+        buffer[start..start + items.len()].copy_from_slice(items);
+    }
+}
+
+fn read_range<T: Copy>(buffer: &[T], range: &Range<usize>) -> Vec<T> {
+    if buffer.is_empty() {
+        return Vec::new();
+    }
+
+    buffer[range.clone()].to_vec()
+}
+
+/// The opaque vertices of every chunk, merged by region. Only used when
+/// `VoxelRenderConfig::merge_chunk_meshes` is set.
+pub struct MergedChunkMeshes {
+    /// The shape of the map's chunks.
+    chunk_shape: Point3i,
+    regions: HashMap<Point3i, MergedRegion>,
+    dirty_regions: HashSet<Point3i>,
+}
+
+impl Default for MergedChunkMeshes {
+    fn default() -> Self {
+        Self::with_chunk_shape(VOXEL_CHUNK_SHAPE)
+    }
+}
+
+impl MergedChunkMeshes {
+    pub fn with_chunk_shape(chunk_shape: Point3i) -> Self {
+        Self {
+            chunk_shape,
+            regions: HashMap::new(),
+            dirty_regions: HashSet::new(),
+        }
+    }
+
+    /// Replaces the vertices of the chunk at `chunk_min`, or removes them if `ivs` is `None`.
+    pub fn set_chunk(&mut self, chunk_min: Point3i, ivs: Option<IndexedPosColorNormVertices>) {
+        let region_min = region_min_for_chunk(chunk_min, self.chunk_shape);
+        let has_chunk = self
+            .regions
+            .get(&region_min)
+            .map_or(false, |r| r.chunks.contains_key(&chunk_min));
+        if ivs.is_none() && !has_chunk {
+            return;
+        }
+
+        self.regions
+            .entry(region_min)
+            .or_insert_with(MergedRegion::default)
+            .set_chunk(chunk_min, ivs);
+        self.dirty_regions.insert(region_min);
+    }
+
+    /// The merged vertices of every region that changed since the last call, or `None` for the
+    /// regions that are now empty.
+    pub fn take_dirty_regions(&mut self) -> Vec<(Point3i, Option<IndexedPosColorNormVertices>)> {
+        let mut dirty = Vec::with_capacity(self.dirty_regions.len());
+        for region_min in self.dirty_regions.drain() {
+            let is_empty = self
+                .regions
+                .get(&region_min)
+                .map_or(true, |r| r.chunks.is_empty() || r.index_alloc.is_empty());
+            if is_empty {
+                self.regions.remove(&region_min);
+                dirty.push((region_min, None));
+            } else {
+                dirty.push((region_min, Some(self.regions[&region_min].vertices())));
+            }
+        }
+
+        dirty
+    }
+
+    pub fn clear(&mut self) {
+        self.regions.clear();
+        self.dirty_regions.clear();
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use amethyst::renderer::rendy::mesh::Position;
+
+    fn triangle(z: f32) -> IndexedPosColorNormVertices {
+        let mut vertices = PosColorNormVertices::default();
+        for &(x, y) in [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)].iter() {
+            vertices.positions.push(Position([x, y, z]));
+            vertices.materials.push(Default::default());
+            vertices.normals.push(Default::default());
+            vertices.emissions.push(Default::default());
+        }
+
+        IndexedPosColorNormVertices {
+            indices: vec![0, 1, 2],
+            vertices,
+        }
+    }
+
+    #[test]
+    fn test_rewritten_chunk_reuses_freed_ranges() {
+        let mut merged = MergedChunkMeshes::default();
+        let a = PointN([0, 0, 0]);
+        let b = PointN([16, 0, 0]);
+        merged.set_chunk(a, Some(triangle(0.0)));
+        merged.set_chunk(b, Some(triangle(1.0)));
+
+        let dirty = merged.take_dirty_regions();
+        assert_eq!(dirty.len(), 1);
+        let region = dirty[0].1.as_ref().unwrap();
+        assert_eq!(region.indices, vec![0, 1, 2, 3, 4, 5]);
+
+        // Removing the first chunk leaves a hole of degenerate triangles, which the next chunk
+        // fills.
+        merged.set_chunk(a, None);
+        let region = merged.take_dirty_regions().pop().unwrap().1.unwrap();
+        assert_eq!(region.indices, vec![0, 0, 0, 3, 4, 5]);
+
+        merged.set_chunk(a, Some(triangle(2.0)));
+        let region = merged.take_dirty_regions().pop().unwrap().1.unwrap();
+        assert_eq!(region.indices, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(region.vertices.positions[0], Position([0.0, 0.0, 2.0]));
+
+        merged.set_chunk(a, None);
+        merged.set_chunk(b, None);
+        let dirty = merged.take_dirty_regions();
+        assert!(dirty[0].1.is_none());
+        assert!(merged.take_dirty_regions().is_empty());
+    }
+}
@@ -0,0 +1,414 @@
+//! Multisample anti-aliasing for the splatted triplanar pass, which smooths the thin edges of voxel
+//! meshes.
+//!
+//! The window's target isn't multisampled, and the pipelines of `RenderBase3D` and the other
+//! plugins that draw into it are built for one sample per pixel. So the opaque group of the
+//! splatted pass draws into its own multisampled target, which is resolved into the main target
+//! before anything else is drawn there, depth included. The transparent meshes, the greedy quads of
+//! the blocky pass, the skybox and the rest are drawn after that with one sample, depth tested
+//! against the resolved depth.
+
+use super::{
+    shadow_pass::{add_opaque_splatted, plan_shadow_cascades},
+    shadows::{ShadowCascadeSystem, ShadowConfig},
+    splatted_triplanar_pbr_pass::{
+        SplattedTextureVariant, SplattedTriplanarPbrPassDef, SplattedVertexVariant,
+    },
+};
+
+use amethyst::{
+    core::ecs::{prelude::*, DispatcherBuilder},
+    renderer::{
+        bundle::{
+            ImageOptions, RenderOrder, RenderPlan, RenderPlugin, Target, TargetImage,
+            TargetPlanOutputs,
+        },
+        pass::DrawBase3DTransparentDesc,
+        pipeline::{PipelineDescBuilder, PipelinesBuilder},
+        types::Backend,
+        util,
+    },
+    window::ScreenDimensions,
+    Error,
+};
+use rendy::{
+    command::{QueueId, RenderPassEncoder},
+    factory::Factory,
+    graph::{
+        render::{PrepareResult, RenderGroup, RenderGroupDesc},
+        GraphContext, ImageAccess, NodeBuffer, NodeImage,
+    },
+    hal::{
+        self,
+        command::{ClearColor, ClearDepthStencil, ClearValue},
+        device::Device,
+        format::{Aspects, Format, Swizzle},
+        image::{self, Filter, Kind, SamplerInfo, SubresourceRange, ViewKind, WrapMode},
+        pso::{self, ShaderStageFlags},
+    },
+    resource::{
+        DescriptorSet, DescriptorSetLayout, Escape, Handle as RendyHandle, ImageView,
+        ImageViewInfo, Sampler,
+    },
+    shader::{Shader, SpirvShader},
+};
+use std::marker::PhantomData;
+
+lazy_static::lazy_static! {
+    static ref FULLSCREEN_TRIANGLE_VERTEX: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("shaders/fullscreen_triangle.spv"),
+        ShaderStageFlags::VERTEX,
+        "main",
+    ).unwrap();
+    static ref RESOLVE_FRAGMENT: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("shaders/resolve.spv"),
+        ShaderStageFlags::FRAGMENT,
+        "main",
+    ).unwrap();
+}
+
+const MULTISAMPLED_TARGET: Target = Target::Custom("splatted_multisampled");
+
+/// Linear color, like the splatted fragment shader writes, with enough precision that the resolve
+/// doesn't band.
+const MULTISAMPLED_COLOR_FORMAT: Format = Format::Rgba16Sfloat;
+const MULTISAMPLED_DEPTH_FORMAT: Format = Format::D32Sfloat;
+
+/// Draws `SplattedTriplanarPbrPassDef<V, T>` with `samples` per pixel, and with shadows from the
+/// sun if they're enabled. Used in place of `RenderBase3D` or `RenderShadowedSplattedTriplanarPbr`
+/// when `VoxelRenderConfig::msaa_samples` is more than 1.
+#[derive(Debug)]
+pub struct RenderMultisampledSplattedTriplanarPbr<V, T> {
+    shadows: ShadowConfig,
+    samples: u8,
+    /// The size of the multisampled target, which has to match the window.
+    dimensions: Option<ScreenDimensions>,
+    marker: PhantomData<(V, T)>,
+}
+
+impl<V, T> RenderMultisampledSplattedTriplanarPbr<V, T> {
+    /// `samples` must be a sample count that the GPU supports for both color and depth images,
+    /// e.g. 2, 4 or 8.
+    pub fn new(shadows: ShadowConfig, samples: u8) -> Self {
+        Self {
+            shadows,
+            samples,
+            dimensions: None,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<B, V, T> RenderPlugin<B> for RenderMultisampledSplattedTriplanarPbr<V, T>
+where
+    B: Backend,
+    V: SplattedVertexVariant,
+    T: SplattedTextureVariant,
+{
+    fn on_build<'a, 'b>(
+        &mut self,
+        _world: &mut World,
+        builder: &mut DispatcherBuilder<'a, 'b>,
+    ) -> Result<(), Error> {
+        if self.shadows.enabled {
+            builder.add(ShadowCascadeSystem, "shadow_cascades", &[]);
+        }
+
+        Ok(())
+    }
+
+    fn should_rebuild(&mut self, world: &World) -> bool {
+        let dimensions = world.read_resource::<ScreenDimensions>();
+        if self.dimensions.as_ref() != Some(&*dimensions) {
+            self.dimensions = Some((*dimensions).clone());
+
+            return true;
+        }
+
+        false
+    }
+
+    fn on_plan(
+        &mut self,
+        plan: &mut RenderPlan<B>,
+        _factory: &mut Factory<B>,
+        world: &World,
+    ) -> Result<(), Error> {
+        let num_cascades = if self.shadows.enabled {
+            plan_shadow_cascades(plan, &self.shadows)?
+        } else {
+            0
+        };
+
+        let samples = self.samples;
+        let dimensions = world.read_resource::<ScreenDimensions>();
+        let kind = Kind::D2(
+            dimensions.width() as u32,
+            dimensions.height() as u32,
+            1,
+            samples,
+        );
+        plan.define_pass(
+            MULTISAMPLED_TARGET,
+            TargetPlanOutputs {
+                colors: vec![ImageOptions {
+                    kind,
+                    levels: 1,
+                    format: MULTISAMPLED_COLOR_FORMAT,
+                    clear: Some(ClearValue {
+                        color: ClearColor { float32: [0.0; 4] },
+                    }),
+                }],
+                depth: Some(ImageOptions {
+                    kind,
+                    levels: 1,
+                    format: MULTISAMPLED_DEPTH_FORMAT,
+                    clear: Some(ClearValue {
+                        depth_stencil: ClearDepthStencil {
+                            depth: 1.0,
+                            stencil: 0,
+                        },
+                    }),
+                }),
+            },
+        )?;
+        plan.extend_target(MULTISAMPLED_TARGET, move |ctx| {
+            add_opaque_splatted::<B, V, T>(ctx, num_cascades, samples)
+        });
+
+        plan.extend_target(Target::Main, move |ctx| {
+            let resolve = DrawResolveDesc
+                .builder()
+                .with_image(ctx.get_image(TargetImage::Color(MULTISAMPLED_TARGET, 0))?)
+                .with_image(ctx.get_image(TargetImage::Depth(MULTISAMPLED_TARGET))?);
+            ctx.add(RenderOrder::BeforeOpaque, resolve)?;
+            ctx.add(
+                RenderOrder::Transparent,
+                DrawBase3DTransparentDesc::<B, SplattedTriplanarPbrPassDef<V, T>>::new().builder(),
+            )?;
+            Ok(())
+        });
+
+        Ok(())
+    }
+}
+
+/// Draws the multisampled color and depth, which are the images of this group, into the main
+/// target with `shaders/resolve.frag`.
+#[derive(Clone, Debug)]
+struct DrawResolveDesc;
+
+impl<B: Backend> RenderGroupDesc<B, World> for DrawResolveDesc {
+    fn images(&self) -> Vec<ImageAccess> {
+        vec![
+            ImageAccess {
+                access: image::Access::SHADER_READ,
+                usage: image::Usage::SAMPLED,
+                layout: image::Layout::ShaderReadOnlyOptimal,
+                stages: pso::PipelineStage::FRAGMENT_SHADER,
+            };
+            2
+        ]
+    }
+
+    fn build(
+        self,
+        ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        _queue: QueueId,
+        _world: &World,
+        framebuffer_width: u32,
+        framebuffer_height: u32,
+        subpass: hal::pass::Subpass<'_, B>,
+        _buffers: Vec<NodeBuffer>,
+        images: Vec<NodeImage>,
+    ) -> Result<Box<dyn RenderGroup<B, World>>, failure::Error> {
+        let samples = MultisampledImages::new(ctx, factory, &images)?;
+
+        let pipeline_layout = unsafe {
+            factory
+                .device()
+                .create_pipeline_layout(vec![samples.layout.raw()], None as Option<(_, _)>)
+        }?;
+        let shader_vertex = unsafe { FULLSCREEN_TRIANGLE_VERTEX.module(factory).unwrap() };
+        let shader_fragment = unsafe { RESOLVE_FRAGMENT.module(factory).unwrap() };
+        let pipes = PipelinesBuilder::new()
+            .with_pipeline(
+                PipelineDescBuilder::new()
+                    .with_vertex_desc(&[])
+                    .with_shaders(util::simple_shader_set(
+                        &shader_vertex,
+                        Some(&shader_fragment),
+                    ))
+                    .with_layout(&pipeline_layout)
+                    .with_subpass(subpass)
+                    .with_framebuffer_size(framebuffer_width, framebuffer_height)
+                    .with_face_culling(pso::Face::NONE)
+                    .with_depth_test(pso::DepthTest {
+                        fun: pso::Comparison::Always,
+                        write: true,
+                    })
+                    .with_blend_targets(vec![pso::ColorBlendDesc {
+                        mask: pso::ColorMask::ALL,
+                        blend: Some(pso::BlendState::PREMULTIPLIED_ALPHA),
+                    }]),
+            )
+            .build(factory, None);
+        unsafe {
+            factory.destroy_shader_module(shader_vertex);
+            factory.destroy_shader_module(shader_fragment);
+        }
+
+        match pipes {
+            Err(e) => {
+                unsafe {
+                    factory.device().destroy_pipeline_layout(pipeline_layout);
+                }
+                Err(e)
+            }
+            Ok(mut pipes) => Ok(Box::new(DrawResolve {
+                pipeline: pipes.remove(0),
+                pipeline_layout,
+                samples,
+            })),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct DrawResolve<B: Backend> {
+    pipeline: B::GraphicsPipeline,
+    pipeline_layout: B::PipelineLayout,
+    samples: MultisampledImages<B>,
+}
+
+impl<B: Backend> RenderGroup<B, World> for DrawResolve<B> {
+    fn prepare(
+        &mut self,
+        _factory: &Factory<B>,
+        _queue: QueueId,
+        _index: usize,
+        _subpass: hal::pass::Subpass<'_, B>,
+        _world: &World,
+    ) -> PrepareResult {
+        PrepareResult::DrawReuse
+    }
+
+    fn draw_inline(
+        &mut self,
+        mut encoder: RenderPassEncoder<'_, B>,
+        _index: usize,
+        _subpass: hal::pass::Subpass<'_, B>,
+        _world: &World,
+    ) {
+        encoder.bind_graphics_pipeline(&self.pipeline);
+        unsafe {
+            encoder.bind_graphics_descriptor_sets(
+                &self.pipeline_layout,
+                0,
+                Some(self.samples.set.raw()),
+                std::iter::empty(),
+            );
+            encoder.draw(0..3, 0..1);
+        }
+    }
+
+    fn dispose(self: Box<Self>, factory: &mut Factory<B>, _world: &World) {
+        unsafe {
+            factory.device().destroy_graphics_pipeline(self.pipeline);
+            factory
+                .device()
+                .destroy_pipeline_layout(self.pipeline_layout);
+        }
+    }
+}
+
+/// The descriptor set of the multisampled color and depth, as `sampler2DMS color` (binding 0) and
+/// `sampler2DMS depth` (binding 1).
+#[derive(Debug)]
+struct MultisampledImages<B: Backend> {
+    layout: RendyHandle<DescriptorSetLayout<B>>,
+    set: Escape<DescriptorSet<B>>,
+    // Kept alive for as long as the set refers to them.
+    _views: Vec<Escape<ImageView<B>>>,
+    _sampler: RendyHandle<Sampler<B>>,
+}
+
+impl<B: Backend> MultisampledImages<B> {
+    fn new(
+        ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        images: &[NodeImage],
+    ) -> Result<Self, failure::Error> {
+        let formats = [
+            (MULTISAMPLED_COLOR_FORMAT, Aspects::COLOR),
+            (MULTISAMPLED_DEPTH_FORMAT, Aspects::DEPTH),
+        ];
+        let layout: RendyHandle<DescriptorSetLayout<B>> = factory
+            .create_descriptor_set_layout(
+                (0..formats.len() as u32)
+                    .map(|binding| pso::DescriptorSetLayoutBinding {
+                        binding,
+                        ty: pso::DescriptorType::CombinedImageSampler,
+                        count: 1,
+                        stage_flags: ShaderStageFlags::FRAGMENT,
+                        immutable_samplers: false,
+                    })
+                    .collect(),
+            )?
+            .into();
+        let set = factory.create_descriptor_set(layout.clone())?;
+        // Multisampled images are only read with `texelFetch`, so the filter doesn't matter.
+        let sampler = factory.get_sampler(SamplerInfo::new(Filter::Nearest, WrapMode::Clamp))?;
+
+        let mut views = Vec::with_capacity(formats.len());
+        for (node_image, &(format, aspects)) in images.iter().zip(formats.iter()) {
+            let image = ctx
+                .get_image(node_image.id)
+                .ok_or_else(|| failure::format_err!("Missing multisampled image"))?;
+            let view = factory
+                .create_image_view(
+                    image.clone(),
+                    ImageViewInfo {
+                        view_kind: ViewKind::D2,
+                        format,
+                        swizzle: Swizzle::NO,
+                        range: SubresourceRange {
+                            aspects,
+                            levels: 0..1,
+                            layers: 0..1,
+                        },
+                    },
+                )
+                .map_err(|e| failure::format_err!("Failed to create multisampled view: {:?}", e))?;
+            views.push(view);
+        }
+        if views.len() != formats.len() {
+            failure::bail!("Missing multisampled images");
+        }
+
+        unsafe {
+            factory
+                .device()
+                .write_descriptor_sets(views.iter().enumerate().map(|(binding, view)| {
+                    pso::DescriptorSetWrite {
+                        set: set.raw(),
+                        binding: binding as u32,
+                        array_offset: 0,
+                        descriptors: Some(pso::Descriptor::CombinedImageSampler(
+                            view.raw(),
+                            image::Layout::ShaderReadOnlyOptimal,
+                            sampler.raw(),
+                        )),
+                    }
+                }));
+        }
+
+        Ok(Self {
+            layout,
+            set,
+            _views: views,
+            _sampler: sampler,
+        })
+    }
+}
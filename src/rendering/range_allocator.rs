@@ -0,0 +1,110 @@
+use std::ops::Range;
+
+/// Hands out ranges of a buffer that grows at the end, reusing freed ranges first-fit. Freed ranges
+/// are merged with their free neighbors, and the buffer shrinks when its last range is freed.
+#[derive(Clone, Debug, Default)]
+pub struct RangeAllocator {
+    /// Sorted and never adjacent to each other or to `end`.
+    free: Vec<Range<u32>>,
+    end: u32,
+}
+
+impl RangeAllocator {
+    /// The range always fits inside of `0..self.len()`, and it either fits inside of the old length
+    /// or starts exactly at it.
+    pub fn allocate(&mut self, size: u32) -> Range<u32> {
+        if let Some(i) = self.free.iter().position(|r| r.end - r.start >= size) {
+            let start = self.free[i].start;
+            if self.free[i].end - start == size {
+                self.free.remove(i);
+            } else {
+                self.free[i].start += size;
+            }
+
+            return start..start + size;
+        }
+
+        let start = self.end;
+        self.end += size;
+
+        start..self.end
+    }
+
+    pub fn free(&mut self, range: Range<u32>) {
+        if range.start == range.end {
+            return;
+        }
+
+        let i = self.free.partition_point(|r| r.start < range.start);
+        let mut merged = range;
+        if i < self.free.len() && self.free[i].start == merged.end {
+            merged.end = self.free.remove(i).end;
+        }
+        if i > 0 && self.free[i - 1].end == merged.start {
+            merged.start = self.free.remove(i - 1).start;
+        }
+
+        if merged.end == self.end {
+            self.end = merged.start;
+        } else {
+            let i = self.free.partition_point(|r| r.start < merged.start);
+            self.free.insert(i, merged);
+        }
+    }
+
+    /// The size of the buffer, including free ranges.
+    pub fn len(&self) -> u32 {
+        self.end
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.end == 0
+    }
+
+    pub fn num_free(&self) -> u32 {
+        self.free.iter().map(|r| r.end - r.start).sum()
+    }
+
+    pub fn free_ranges(&self) -> &[Range<u32>] {
+        &self.free
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reuses_and_merges_free_ranges() {
+        let mut alloc = RangeAllocator::default();
+        let a = alloc.allocate(10);
+        let b = alloc.allocate(5);
+        let c = alloc.allocate(10);
+        assert_eq!((a.clone(), b.clone(), c.clone()), (0..10, 10..15, 15..25));
+
+        alloc.free(a);
+        assert_eq!(alloc.allocate(4), 0..4);
+        assert_eq!(alloc.free_ranges(), &[4..10]);
+
+        // Merges with the free range on its left.
+        alloc.free(b);
+        assert_eq!(alloc.free_ranges(), &[4..15]);
+        assert_eq!(alloc.num_free(), 11);
+
+        // Too big for the free range, so the buffer grows.
+        assert_eq!(alloc.allocate(12), 25..37);
+
+        // Freeing the end shrinks the buffer, along with any free range before it.
+        alloc.free(25..37);
+        alloc.free(c);
+        assert_eq!(alloc.len(), 4);
+        assert!(alloc.free_ranges().is_empty());
+    }
+}
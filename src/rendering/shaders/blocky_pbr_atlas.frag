@@ -0,0 +1,289 @@
+#version 450
+
+// Copied from amethyst_rendy, sampling one layer of the array material with the UVs of each face
+
+const float PI = 3.14159265359;
+
+struct UvOffset {
+    vec2 u_offset;
+    vec2 v_offset;
+};
+
+float tex_coord(float coord, vec2 offset) {
+    return offset.x + coord * (offset.y - offset.x);
+}
+
+vec2 tex_coords(vec2 coord, UvOffset offset) {
+    return vec2(tex_coord(coord.x, offset.u_offset), tex_coord(coord.y, offset.v_offset));
+}
+
+vec3 schlick_fresnel(float HdotV, vec3 fresnel_base) {
+    return fresnel_base + (1.0 - fresnel_base) * pow(1.0 - HdotV, 5.0);
+}
+
+float ggx_normal_distribution(vec3 N, vec3 H, float a) {
+    float a2 = a * a;
+    float NdotH = max(dot(N, H), 0.0);
+    float NdotH2 = NdotH*NdotH;
+
+    float denom = (NdotH2 * (a2 - 1.0) + 1.0);
+    denom = PI * denom * denom;
+
+    return (a2 + 0.0000001) / denom;
+}
+
+float ggx_geometry(float NdotV, float NdotL, float r2) {
+    float a1 = r2 + 1.0;
+    float k = a1 * a1 / 8.0;
+    float denom = NdotV * (1.0 - k) + k;
+    float ggx1 = NdotV / denom;
+    denom = NdotL * (1.0 - k) + k;
+    float ggx2 = NdotL / denom;
+    return ggx1 * ggx2;
+}
+
+float s_curve (float x) {
+		x = x * 2.0 - 1.0;
+		return -x * abs(x) * 0.5 + x + 0.5;
+}
+
+struct PointLight {
+    vec3 position;
+    vec3 color;
+    float intensity;
+};
+
+struct DirectionalLight {
+    vec3 color;
+    float intensity;
+    vec3 direction;
+};
+
+struct SpotLight {
+    vec3 position;
+    vec3 color;
+    vec3 direction;
+    float angle;
+    float intensity;
+    float range;
+    float smoothness;
+};
+
+layout(std140, set = 0, binding = 1) uniform Environment {
+    vec3 ambient_color;
+    vec3 camera_position;
+    int point_light_count;
+    int directional_light_count;
+    int spot_light_count;
+};
+
+layout(std140, set = 0, binding = 2) uniform PointLights {
+    PointLight plight[128];
+};
+
+layout(std140, set = 0, binding = 3) uniform DirectionalLights {
+    DirectionalLight dlight[16];
+};
+
+layout(std140, set = 0, binding = 4) uniform SpotLights {
+    SpotLight slight[128];
+};
+
+layout(std140, set = 1, binding = 0) uniform Material {
+    UvOffset uv_offset;
+    float alpha_cutoff;
+};
+
+layout(set = 1, binding = 1) uniform sampler2D albedo_samp;
+layout(set = 1, binding = 2) uniform sampler2D emission_samp;
+layout(set = 1, binding = 3) uniform sampler2D normal_samp;
+layout(set = 1, binding = 4) uniform sampler2D metallic_roughness_samp;
+layout(set = 1, binding = 5) uniform sampler2D ambient_occlusion_samp;
+layout(set = 1, binding = 6) uniform sampler2D cavity_samp;
+
+layout(location = 0) in VertexData {
+    vec3 position;
+    vec3 normal;
+    vec2 tex_coord;
+    vec4 color;
+    flat uint material_layer;
+    vec3 emission;
+} vertex;
+
+layout(location = 0) out vec4 out_color;
+
+
+vec3 fresnel(float HdotV, vec3 fresnel_base) {
+    return fresnel_base + (1.0 - fresnel_base) * pow(1.0 - HdotV, 5.0);
+}
+
+vec3 compute_light(vec3 attenuation,
+                   vec3 light_color,
+                   vec3 view_direction,
+                   vec3 light_direction,
+                   vec3 albedo,
+                   vec3 normal,
+                   float roughness2,
+                   float metallic,
+                   vec3 fresnel_base) {
+
+    vec3 halfway = normalize(view_direction + light_direction);
+    float normal_distribution = ggx_normal_distribution(normal, halfway, roughness2);
+
+    float NdotV = max(dot(normal, view_direction), 0.0);
+    float NdotL = max(dot(normal, light_direction), 0.0);
+    float HdotV = max(dot(halfway, view_direction), 0.0);
+    float geometry = ggx_geometry(NdotV, NdotL, roughness2);
+
+
+    vec3 fresnel = fresnel(HdotV, fresnel_base);
+    vec3 diffuse = vec3(1.0) - fresnel;
+    diffuse *= 1.0 - metallic;
+
+    vec3 nominator = normal_distribution * geometry * fresnel;
+    float denominator = 4 * NdotV * NdotL + 0.0001;
+    vec3 specular = nominator / denominator;
+
+    vec3 resulting_light = (diffuse * albedo / PI + specular) * light_color * attenuation * NdotL;
+    return resulting_light;
+}
+
+// The layers of each material are packed into a 2x2 atlas, in row-major order.
+vec4 layer_texture(sampler2D samp, vec2 uv, float layer) {
+    vec2 tile = vec2(mod(layer, 2.0), floor(layer * 0.5));
+    vec2 atlas_uv = (fract(uv) + tile) * 0.5;
+    // Use the gradients of the continuous UVs to avoid seams where fract wraps.
+    return textureGrad(samp, atlas_uv, dFdx(uv) * 0.5, dFdy(uv) * 0.5);
+}
+
+// Builds the tangent frame from screen space derivatives, so the mesh doesn't need tangents.
+vec3 normal_to_world(vec3 tangent_normal, vec3 surf_normal, vec3 position, vec2 uv) {
+    vec3 dp1 = dFdx(position);
+    vec3 dp2 = dFdy(position);
+    vec2 duv1 = dFdx(uv);
+    vec2 duv2 = dFdy(uv);
+
+    vec3 dp2perp = cross(dp2, surf_normal);
+    vec3 dp1perp = cross(surf_normal, dp1);
+    vec3 tangent = dp2perp * duv1.x + dp1perp * duv2.x;
+    vec3 bitangent = dp2perp * duv1.y + dp1perp * duv2.y;
+    float inv_max = inversesqrt(max(dot(tangent, tangent), dot(bitangent, bitangent)));
+
+    return normalize(mat3(tangent * inv_max, bitangent * inv_max, surf_normal) * tangent_normal);
+}
+
+void main() {
+    vec2 uv = tex_coords(vertex.tex_coord, uv_offset);
+    float layer = float(vertex.material_layer);
+
+    vec4 albedo_alpha       = layer_texture(albedo_samp, uv, layer);
+    float alpha             = albedo_alpha.a;
+    if(alpha < alpha_cutoff) discard;
+
+    vec3 albedo             = albedo_alpha.rgb;
+    vec3 emission           = layer_texture(emission_samp, uv, layer).rgb + vertex.emission;
+    // Important that the texture is loaded as Unorm.
+    vec3 tangent_normal     = 2.0 * layer_texture(normal_samp, uv, layer).rgb - 1.0;
+    vec3 normal             = normal_to_world(tangent_normal, normalize(vertex.normal), vertex.position, uv);
+    vec2 metallic_roughness = layer_texture(metallic_roughness_samp, uv, layer).bg;
+    float ambient_occlusion = layer_texture(ambient_occlusion_samp, uv, layer).r;
+    // TODO: Use cavity
+    float metallic          = metallic_roughness.r;
+    float roughness         = metallic_roughness.g;
+
+    float roughness2 = roughness * roughness;
+    vec3 fresnel_base = mix(vec3(0.04), albedo, metallic);
+
+    vec3 view_direction = normalize(camera_position - vertex.position);
+    vec3 lighted = vec3(0.0);
+    for (int i = 0; i < point_light_count; i++) {
+        vec3 light_direction = normalize(plight[i].position - vertex.position);
+        float attenuation = plight[i].intensity / dot(light_direction, light_direction);
+
+        vec3 light = compute_light(vec3(attenuation),
+                                   plight[i].color,
+                                   view_direction,
+                                   light_direction,
+                                   albedo,
+                                   normal,
+                                   roughness2,
+                                   metallic,
+                                   fresnel_base);
+
+        lighted += light;
+    }
+
+    for (int i = 0; i < directional_light_count; i++) {
+        vec3 light_direction = -normalize(dlight[i].direction);
+        float attenuation = dlight[i].intensity;
+
+        vec3 light = compute_light(vec3(attenuation),
+                                   dlight[i].color,
+                                   view_direction,
+                                   light_direction,
+                                   albedo,
+                                   normal,
+                                   roughness2,
+                                   metallic,
+                                   fresnel_base);
+
+        lighted += light;
+    }
+
+    for (int i = 0; i < spot_light_count; i++) {
+        vec3 light_vec = slight[i].position - vertex.position;
+        vec3 normalized_light_vec = normalize(light_vec);
+
+        // The distance between the current fragment and the "core" of the light
+        float light_length = length(light_vec);
+
+        // The allowed "length", everything after this won't be lit.
+        // Later on we are dividing by this range, so it can't be 0
+        float range = max(slight[i].range, 0.00001);
+
+        // get normalized range, so everything 0..1 could be lit, everything else can't.
+        float normalized_range = light_length / max(0.00001, range);
+
+        // The attenuation for the "range". If we would only consider this, we'd have a
+        // point light instead, so we need to also check for the spot angle and direction.
+        float range_attenuation = max(0.0, 1.0 - normalized_range);
+
+        // this is actually the cosine of the angle, so it can be compared with the
+        // "dotted" frag_angle below a lot cheaper.
+        float spot_angle = max(slight[i].angle, 0.00001);
+        vec3 spot_direction = normalize(slight[i].direction);
+        float smoothness = 1.0 - slight[i].smoothness;
+
+        // Here we check if the current fragment is within the "ring" of the spotlight.
+        float frag_angle = dot(spot_direction, -normalized_light_vec);
+
+        // so that the ring_attenuation won't be > 1
+        frag_angle = max(frag_angle, spot_angle);
+
+        // How much is this outside of the ring? (let's call it "rim")
+        // Also smooth this out.
+        float rim_attenuation = pow(max((1.0 - frag_angle) / (1.0 - spot_angle), 0.00001), smoothness);
+
+        // How much is this inside the "ring"?
+        float ring_attenuation = 1.0 - rim_attenuation;
+
+        // combine the attenuations and intensity
+        float attenuation = range_attenuation * ring_attenuation * slight[i].intensity;
+
+        vec3 light = compute_light(vec3(attenuation),
+                                   slight[i].color,
+                                   view_direction,
+                                   normalize(light_vec),
+                                   albedo,
+                                   normal,
+                                   roughness2,
+                                   metallic,
+                                   fresnel_base);
+        lighted += light;
+    }
+
+    vec3 ambient = ambient_color * albedo * ambient_occlusion;
+    vec3 color = ambient + lighted + emission;
+
+    out_color = vec4(color, alpha) * vertex.color;
+}
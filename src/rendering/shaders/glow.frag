@@ -0,0 +1,31 @@
+#version 450
+
+// Fades the glow shell out toward its silhouette, so it looks like light bleeding around the
+// emissive surface.
+
+layout(std140, set = 0, binding = 1) uniform Environment {
+    vec3 ambient_color;
+    vec3 camera_position;
+    int point_light_count;
+    int directional_light_count;
+    int spot_light_count;
+};
+
+layout(location = 0) in VertexData {
+    vec3 position;
+    vec3 normal;
+    vec3 emission;
+} vertex;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    vec3 view_direction = normalize(camera_position - vertex.position);
+    float facing = abs(dot(normalize(vertex.normal), view_direction));
+    float brightness = max(vertex.emission.r, max(vertex.emission.g, vertex.emission.b));
+    if (brightness <= 0.0) discard;
+
+    // Brighter emission makes a more opaque halo, up to a point.
+    float alpha = facing * facing * clamp(0.5 * brightness, 0.0, 0.8);
+    out_color = vec4(vertex.emission / max(brightness, 1.0), alpha);
+}
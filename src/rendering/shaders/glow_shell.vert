@@ -0,0 +1,34 @@
+#version 450
+
+// Pushes the emissive surfaces out along their normals, making a shell that the fragment shader
+// fades into a halo.
+
+// How far the halo reaches past the surface, in voxels.
+const float GLOW_RADIUS = 0.4;
+
+layout(std140, set = 0, binding = 0) uniform Projview {
+    mat4 proj;
+    mat4 view;
+    mat4 proj_view;
+};
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec3 glow_emission;
+layout(location = 3) in mat4 model; // instance rate
+layout(location = 7) in vec4 tint; // instance rate
+
+layout(location = 0) out VertexData {
+    vec3 position;
+    vec3 normal;
+    vec3 emission;
+} vertex;
+
+void main() {
+    vec3 world_normal = normalize(mat3(model) * normal);
+    vec4 vertex_position = model * vec4(position, 1.0) + vec4(GLOW_RADIUS * world_normal, 0.0);
+    vertex.position = vertex_position.xyz;
+    vertex.normal = world_normal;
+    vertex.emission = glow_emission;
+    gl_Position = proj_view * vertex_position;
+}
@@ -11,14 +11,16 @@ layout(std140, set = 0, binding = 0) uniform Projview {
 layout(location = 0) in vec3 position;
 layout(location = 1) in vec4 material_weights;
 layout(location = 2) in vec3 normal;
-layout(location = 3) in mat4 model; // instance rate
-layout(location = 7) in vec4 tint; // instance rate
+layout(location = 3) in vec3 emission;
+layout(location = 4) in mat4 model; // instance rate
+layout(location = 8) in vec4 tint; // instance rate
 
 layout(location = 0) out VertexData {
     vec3 position;
     vec3 normal;
     vec4 color;
     vec4 material_weights;
+    vec3 emission;
 } vertex;
 
 void main() {
@@ -26,6 +28,7 @@ void main() {
     vertex.position = vertex_position.xyz;
     vertex.normal = normalize(mat3(model) * normal);
     vertex.color = tint;
+    vertex.emission = emission;
     vertex.material_weights = material_weights / dot(material_weights, vec4(1.0));
     gl_Position = proj_view * vertex_position;
 }
@@ -0,0 +1,42 @@
+#version 450
+
+// Same as pos_packed.vert, but each vertex also has the indices of the array layers that its
+// material weights refer to. Every vertex of a triangle has the same indices, so they don't need to
+// be interpolated.
+
+// Matches `MAX_PACKED_EMISSION` in splatted_triplanar_pbr_pass.rs.
+const float MAX_PACKED_EMISSION = 16.0;
+
+layout(std140, set = 0, binding = 0) uniform Projview {
+    mat4 proj;
+    mat4 view;
+    mat4 proj_view;
+};
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in uvec4 material_indices;
+layout(location = 2) in vec4 material_weights;
+layout(location = 3) in vec3 normal;
+layout(location = 4) in vec4 emission; // RGBM, see MAX_PACKED_EMISSION
+layout(location = 5) in mat4 model; // instance rate
+layout(location = 9) in vec4 tint; // instance rate
+
+layout(location = 0) out VertexData {
+    vec3 position;
+    vec3 normal;
+    vec4 color;
+    vec4 material_weights;
+    flat uvec4 material_indices;
+    vec3 emission;
+} vertex;
+
+void main() {
+    vec4 vertex_position = model * vec4(position, 1.0);
+    vertex.position = vertex_position.xyz;
+    vertex.normal = normalize(mat3(model) * normal);
+    vertex.color = tint;
+    vertex.emission = emission.rgb * emission.a * MAX_PACKED_EMISSION;
+    vertex.material_weights = material_weights / dot(material_weights, vec4(1.0));
+    vertex.material_indices = material_indices;
+    gl_Position = proj_view * vertex_position;
+}
@@ -0,0 +1,46 @@
+#version 450
+
+// Same as pos_color_norm.vert, but with the packed vertex format. The material weights are 8-bit
+// unorm and the normal is octahedral-encoded as 16-bit snorm.
+
+// Matches `MAX_PACKED_EMISSION` in splatted_triplanar_pbr_pass.rs.
+const float MAX_PACKED_EMISSION = 16.0;
+
+layout(std140, set = 0, binding = 0) uniform Projview {
+    mat4 proj;
+    mat4 view;
+    mat4 proj_view;
+};
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec4 material_weights;
+layout(location = 2) in vec2 normal;
+layout(location = 3) in vec4 emission; // RGBM, see MAX_PACKED_EMISSION
+layout(location = 4) in mat4 model; // instance rate
+layout(location = 8) in vec4 tint; // instance rate
+
+layout(location = 0) out VertexData {
+    vec3 position;
+    vec3 normal;
+    vec4 color;
+    vec4 material_weights;
+    vec3 emission;
+} vertex;
+
+vec3 octahedral_decode(vec2 e) {
+    vec3 n = vec3(e.x, e.y, 1.0 - abs(e.x) - abs(e.y));
+    if (n.z < 0.0) {
+        n.xy = (1.0 - abs(n.yx)) * sign(n.xy);
+    }
+    return n;
+}
+
+void main() {
+    vec4 vertex_position = model * vec4(position, 1.0);
+    vertex.position = vertex_position.xyz;
+    vertex.normal = normalize(mat3(model) * octahedral_decode(normal));
+    vertex.color = tint;
+    vertex.emission = emission.rgb * emission.a * MAX_PACKED_EMISSION;
+    vertex.material_weights = material_weights / dot(material_weights, vec4(1.0));
+    gl_Position = proj_view * vertex_position;
+}
@@ -0,0 +1,37 @@
+#version 450
+
+// Vertices of blocky (greedy quads) meshes. Each quad has a single material layer and tiling UVs.
+
+layout(std140, set = 0, binding = 0) uniform Projview {
+    mat4 proj;
+    mat4 view;
+    mat4 proj_view;
+};
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec2 tex_coord;
+layout(location = 2) in uint material_layer;
+layout(location = 3) in vec3 normal;
+layout(location = 4) in vec3 emission;
+layout(location = 5) in mat4 model; // instance rate
+layout(location = 9) in vec4 tint; // instance rate
+
+layout(location = 0) out VertexData {
+    vec3 position;
+    vec3 normal;
+    vec2 tex_coord;
+    vec4 color;
+    flat uint material_layer;
+    vec3 emission;
+} vertex;
+
+void main() {
+    vec4 vertex_position = model * vec4(position, 1.0);
+    vertex.position = vertex_position.xyz;
+    vertex.normal = normalize(mat3(model) * normal);
+    vertex.tex_coord = tex_coord;
+    vertex.color = tint;
+    vertex.emission = emission;
+    vertex.material_layer = material_layer;
+    gl_Position = proj_view * vertex_position;
+}
@@ -0,0 +1,29 @@
+#version 450
+
+// Resolves the multisampled target of the splatted pass into the main target. The target is
+// cleared to transparent black, so the average color is premultiplied by how much of the pixel the
+// meshes cover. The depth is the nearest of the samples, and pixels that no mesh covers are left
+// alone.
+
+layout(set = 0, binding = 0) uniform sampler2DMS color;
+layout(set = 0, binding = 1) uniform sampler2DMS depth;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    ivec2 p = ivec2(gl_FragCoord.xy);
+    int samples = textureSamples(color);
+
+    vec4 sum = vec4(0.0);
+    float nearest = 1.0;
+    for (int i = 0; i < samples; i++) {
+        sum += texelFetch(color, p, i);
+        nearest = min(nearest, texelFetch(depth, p, i).r);
+    }
+    if (nearest >= 1.0) {
+        discard;
+    }
+
+    out_color = sum / float(samples);
+    gl_FragDepth = nearest;
+}
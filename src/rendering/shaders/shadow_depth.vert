@@ -0,0 +1,15 @@
+#version 450
+
+// Depth-only pass from the sun, drawing one shadow cascade.
+
+layout(std140, set = 0, binding = 0) uniform ShadowView {
+    mat4 view_proj;
+};
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in mat4 model; // instance rate
+layout(location = 5) in vec4 tint; // instance rate
+
+void main() {
+    gl_Position = view_proj * model * vec4(position, 1.0);
+}
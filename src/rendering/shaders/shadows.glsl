@@ -0,0 +1,50 @@
+// Sampling the cascaded shadow maps of the sun. Included by the splatted fragment shaders when
+// they're compiled with -DSHADOWS.
+
+const int MAX_SHADOW_CASCADES = 4;
+// How far to move the surface towards the sun before looking it up, in voxels, so that it doesn't
+// shadow itself.
+const float SHADOW_NORMAL_OFFSET = 0.15;
+const float SHADOW_DEPTH_BIAS = 0.0005;
+
+layout(std140, set = 2, binding = 0) uniform Shadows {
+    mat4 cascade_view_proj[MAX_SHADOW_CASCADES];
+    vec3 sun_direction;
+    int cascade_count;
+};
+
+// Slots past `cascade_count` repeat the first cascade.
+layout(set = 3, binding = 0) uniform sampler2D shadow_maps[MAX_SHADOW_CASCADES];
+
+float cascade_depth(int cascade, vec2 uv) {
+    // Arrays of samplers can only be indexed by constants.
+    if (cascade == 0) return texture(shadow_maps[0], uv).r;
+    if (cascade == 1) return texture(shadow_maps[1], uv).r;
+    if (cascade == 2) return texture(shadow_maps[2], uv).r;
+    return texture(shadow_maps[3], uv).r;
+}
+
+// The fraction of the sunlight that reaches `position`, from the nearest cascade that covers it,
+// with 3x3 percentage-closer filtering. Beyond the last cascade, everything is lit.
+float sun_visibility(vec3 position, vec3 normal) {
+    vec4 offset_position = vec4(position + normal * SHADOW_NORMAL_OFFSET, 1.0);
+    vec2 texel = 1.0 / vec2(textureSize(shadow_maps[0], 0));
+    for (int i = 0; i < cascade_count; i++) {
+        // The projection is orthographic, so w is 1.
+        vec3 p = (cascade_view_proj[i] * offset_position).xyz;
+        vec2 uv = p.xy * 0.5 + 0.5;
+        if (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0))) || p.z > 1.0) {
+            continue;
+        }
+
+        float lit = 0.0;
+        for (int x = -1; x <= 1; x++) {
+            for (int y = -1; y <= 1; y++) {
+                float depth = cascade_depth(i, uv + vec2(x, y) * texel);
+                lit += p.z - SHADOW_DEPTH_BIAS <= depth ? 1.0 : 0.0;
+            }
+        }
+        return lit / 9.0;
+    }
+    return 1.0;
+}
@@ -1,4 +1,5 @@
 #version 450
+#extension GL_GOOGLE_include_directive : require
 
 // Copied from amethyst_rendy, augmented for triplanar mapping
 
@@ -106,10 +107,15 @@ layout(location = 0) in VertexData {
     vec3 normal;
     vec4 color;
     vec4 material_weights;
+    vec3 emission;
 } vertex;
 
 layout(location = 0) out vec4 out_color;
 
+#ifdef SHADOWS
+#include "shadows.glsl"
+#endif
+
 
 vec3 fresnel(float HdotV, vec3 fresnel_base) {
     return fresnel_base + (1.0 - fresnel_base) * pow(1.0 - HdotV, 5.0);
@@ -214,7 +220,7 @@ void main() {
     if(alpha < alpha_cutoff) discard;
 
     vec3 albedo             = albedo_alpha.rgb;
-    vec3 emission           = triplanar_texture_splatted(emission_samp, vertex.material_weights, blend, uv_x, uv_y, uv_z).rgb;
+    vec3 emission           = triplanar_texture_splatted(emission_samp, vertex.material_weights, blend, uv_x, uv_y, uv_z).rgb + vertex.emission;
     vec3 normal             = triplanar_normal_to_world_splatted(normal_samp, vertex.material_weights, blend, uv_x, uv_y, uv_z, vertex.normal);
     vec2 metallic_roughness = triplanar_texture_splatted(metallic_roughness_samp, vertex.material_weights, blend, uv_x, uv_y, uv_z).bg;
     float ambient_occlusion = triplanar_texture_splatted(ambient_occlusion_samp, vertex.material_weights, blend, uv_x, uv_y, uv_z).r;
@@ -245,9 +251,17 @@ void main() {
         lighted += light;
     }
 
+#ifdef SHADOWS
+    float sun_light = sun_visibility(vertex.position, vertex.normal);
+#endif
     for (int i = 0; i < directional_light_count; i++) {
         vec3 light_direction = -normalize(dlight[i].direction);
         float attenuation = dlight[i].intensity;
+#ifdef SHADOWS
+        if (dot(-light_direction, sun_direction) > 0.9999) {
+            attenuation *= sun_light;
+        }
+#endif
 
         vec3 light = compute_light(vec3(attenuation),
                                    dlight[i].color,
@@ -0,0 +1,836 @@
+//! The render passes that draw the `ShadowCascades`: a depth-only pass over the opaque meshes for
+//! each cascade, and the opaque group of the splatted triplanar pass, with a shadow sampler for the
+//! sun.
+//!
+//! `RenderBase3D` builds the pipeline layout of its groups itself, with no set for the shadow maps,
+//! so the opaque group is our own, drawing the same batches as `DrawBase3D` would. The
+//! `multisampling` module uses the same group, with or without shadows. Transparent
+//! meshes neither cast nor receive shadows, and they're still drawn by the transparent group of
+//! `RenderBase3D`. Only the splatted pass receives shadows; the greedy quads of the blocky pass
+//! cast them, but aren't darkened by them.
+
+use super::{
+    shadows::{ShadowCascadeSystem, ShadowCascades, ShadowConfig, MAX_SHADOW_CASCADES},
+    splatted_triplanar_pbr_pass::{
+        SplattedTextureVariant, SplattedTriplanarPbrPassDef, SplattedVertexVariant,
+    },
+};
+
+use amethyst::{
+    assets::{AssetStorage, Handle},
+    core::{
+        ecs::{prelude::*, DispatcherBuilder},
+        math::Matrix4,
+        Hidden, HiddenPropagate, Transform,
+    },
+    renderer::{
+        bundle::{
+            ImageOptions, RenderOrder, RenderPlan, RenderPlugin, Target, TargetImage,
+            TargetPlanContext, TargetPlanOutputs,
+        },
+        mtl::{FullTextureSet, Material},
+        pass::DrawBase3DTransparentDesc,
+        pipeline::{PipelineDescBuilder, PipelinesBuilder},
+        pod::VertexArgs,
+        resources::Tint,
+        submodules::{
+            DynamicUniform, DynamicVertexBuffer, EnvironmentSub, MaterialId, MaterialSub,
+        },
+        transparent::Transparent,
+        types::{Backend, Mesh},
+        util,
+        visibility::Visibility,
+    },
+    Error,
+};
+use fnv::FnvHashMap;
+use glsl_layout::{int, mat4, vec3, AsStd140};
+use rendy::{
+    command::{QueueId, RenderPassEncoder},
+    factory::Factory,
+    graph::{
+        render::{PrepareResult, RenderGroup, RenderGroupDesc},
+        GraphContext, ImageAccess, NodeBuffer, NodeImage,
+    },
+    hal::{
+        self,
+        command::{ClearDepthStencil, ClearValue},
+        device::Device,
+        format::{Aspects, Format, Swizzle},
+        image::{self, Filter, Kind, SamplerInfo, SubresourceRange, ViewKind, WrapMode},
+        pso::{self, ShaderStageFlags},
+    },
+    mesh::{AsVertex, VertexFormat},
+    resource::{
+        DescriptorSet, DescriptorSetLayout, Escape, Handle as RendyHandle, ImageView,
+        ImageViewInfo, Sampler,
+    },
+    shader::{Shader, SpirvShader},
+    util::types::vertex::Position,
+};
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::ops::Range;
+
+lazy_static::lazy_static! {
+    static ref SHADOW_DEPTH_VERTEX: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("shaders/shadow_depth.spv"),
+        ShaderStageFlags::VERTEX,
+        "main",
+    ).unwrap();
+}
+
+const SHADOW_MAP_FORMAT: Format = Format::D32Sfloat;
+
+/// The render targets of the cascades, each with only a depth image.
+const CASCADE_TARGETS: [Target; MAX_SHADOW_CASCADES] = [
+    Target::Custom("shadow_cascade_0"),
+    Target::Custom("shadow_cascade_1"),
+    Target::Custom("shadow_cascade_2"),
+    Target::Custom("shadow_cascade_3"),
+];
+
+/// Draws the opaque meshes of `SplattedTriplanarPbrPassDef<V, T>` with shadows from the sun, along
+/// with the shadow maps themselves. Used in place of `RenderBase3D` when shadows are enabled.
+#[derive(Debug)]
+pub struct RenderShadowedSplattedTriplanarPbr<V, T> {
+    config: ShadowConfig,
+    marker: PhantomData<(V, T)>,
+}
+
+impl<V, T> RenderShadowedSplattedTriplanarPbr<V, T> {
+    pub fn new(config: ShadowConfig) -> Self {
+        Self {
+            config,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<B, V, T> RenderPlugin<B> for RenderShadowedSplattedTriplanarPbr<V, T>
+where
+    B: Backend,
+    V: SplattedVertexVariant,
+    T: SplattedTextureVariant,
+{
+    fn on_build<'a, 'b>(
+        &mut self,
+        _world: &mut World,
+        builder: &mut DispatcherBuilder<'a, 'b>,
+    ) -> Result<(), Error> {
+        builder.add(ShadowCascadeSystem, "shadow_cascades", &[]);
+
+        Ok(())
+    }
+
+    fn on_plan(
+        &mut self,
+        plan: &mut RenderPlan<B>,
+        _factory: &mut Factory<B>,
+        _world: &World,
+    ) -> Result<(), Error> {
+        let num_cascades = plan_shadow_cascades(plan, &self.config)?;
+        plan.extend_target(Target::Main, move |ctx| {
+            add_opaque_splatted::<B, V, T>(ctx, num_cascades, 1)?;
+            ctx.add(
+                RenderOrder::Transparent,
+                DrawBase3DTransparentDesc::<B, SplattedTriplanarPbrPassDef<V, T>>::new().builder(),
+            )?;
+            Ok(())
+        });
+
+        Ok(())
+    }
+}
+
+/// Defines the depth-only pass of each shadow cascade, and returns the number of cascades.
+pub(super) fn plan_shadow_cascades<B: Backend>(
+    plan: &mut RenderPlan<B>,
+    config: &ShadowConfig,
+) -> Result<usize, Error> {
+    let num_cascades = config.num_cascades.max(1).min(MAX_SHADOW_CASCADES);
+    let resolution = config.resolution;
+    for (cascade, &target) in CASCADE_TARGETS[..num_cascades].iter().enumerate() {
+        plan.define_pass(
+            target,
+            TargetPlanOutputs {
+                colors: vec![],
+                depth: Some(ImageOptions {
+                    kind: Kind::D2(resolution, resolution, 1, 1),
+                    levels: 1,
+                    format: SHADOW_MAP_FORMAT,
+                    clear: Some(ClearValue {
+                        depth_stencil: ClearDepthStencil {
+                            depth: 1.0,
+                            stencil: 0,
+                        },
+                    }),
+                }),
+            },
+        )?;
+        plan.extend_target(target, move |ctx| {
+            ctx.add(
+                RenderOrder::Opaque,
+                DrawShadowDepthDesc { cascade }.builder(),
+            )?;
+            Ok(())
+        });
+    }
+
+    Ok(num_cascades)
+}
+
+/// Adds the opaque group of `SplattedTriplanarPbrPassDef<V, T>` to a target with `samples` per
+/// pixel. It's shadowed by the first `num_cascades` cascades of `plan_shadow_cascades`, if any.
+pub(super) fn add_opaque_splatted<B, V, T>(
+    ctx: &mut TargetPlanContext<'_, B>,
+    num_cascades: usize,
+    samples: u8,
+) -> Result<(), Error>
+where
+    B: Backend,
+    V: SplattedVertexVariant,
+    T: SplattedTextureVariant,
+{
+    let mut opaque = DrawOpaqueSplattedDesc::<V, T>::new(num_cascades, samples).builder();
+    for &target in CASCADE_TARGETS[..num_cascades].iter() {
+        opaque = opaque.with_image(ctx.get_image(TargetImage::Depth(target))?);
+    }
+
+    ctx.add(RenderOrder::Opaque, opaque)
+}
+
+/// The `ShadowView` uniform block of `shaders/shadow_depth.vert`.
+#[derive(Clone, Copy, Debug, AsStd140)]
+#[repr(C, align(16))]
+struct ShadowViewArgs {
+    view_proj: mat4,
+}
+
+/// The `Shadows` uniform block of `shaders/shadows.glsl`.
+#[derive(Clone, Copy, Debug, AsStd140)]
+#[repr(C, align(16))]
+struct ShadowArgs {
+    cascade_view_proj: [mat4; MAX_SHADOW_CASCADES],
+    sun_direction: vec3,
+    cascade_count: int,
+}
+
+fn to_mat4(m: &Matrix4<f32>) -> mat4 {
+    let m: [[f32; 4]; 4] = (*m).into();
+
+    m.into()
+}
+
+/// Draws all of the opaque meshes, visible or not, into the depth target of one cascade.
+#[derive(Clone, Debug)]
+struct DrawShadowDepthDesc {
+    cascade: usize,
+}
+
+impl<B: Backend> RenderGroupDesc<B, World> for DrawShadowDepthDesc {
+    fn colors(&self) -> usize {
+        0
+    }
+
+    fn build(
+        self,
+        _ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        _queue: QueueId,
+        _world: &World,
+        framebuffer_width: u32,
+        framebuffer_height: u32,
+        subpass: hal::pass::Subpass<'_, B>,
+        _buffers: Vec<NodeBuffer>,
+        _images: Vec<NodeImage>,
+    ) -> Result<Box<dyn RenderGroup<B, World>>, failure::Error> {
+        let view = DynamicUniform::new(factory, ShaderStageFlags::VERTEX)?;
+        let vertex_format = vec![Position::vertex()];
+        let (pipeline, pipeline_layout) = build_pipeline(
+            factory,
+            subpass,
+            framebuffer_width,
+            framebuffer_height,
+            &SHADOW_DEPTH_VERTEX,
+            None,
+            &vertex_format,
+            vec![view.raw_layout()],
+            1,
+        )?;
+
+        Ok(Box::new(DrawShadowDepth {
+            cascade: self.cascade,
+            pipeline,
+            pipeline_layout,
+            vertex_format,
+            view,
+            models: DynamicVertexBuffer::new(),
+            draws: Vec::new(),
+            instances: Vec::new(),
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct DrawShadowDepth<B: Backend> {
+    cascade: usize,
+    pipeline: B::GraphicsPipeline,
+    pipeline_layout: B::PipelineLayout,
+    vertex_format: Vec<VertexFormat>,
+    view: DynamicUniform<B, ShadowViewArgs>,
+    models: DynamicVertexBuffer<B, VertexArgs>,
+    /// Mesh IDs and their ranges of `instances`.
+    draws: Vec<(u32, Range<u32>)>,
+    instances: Vec<VertexArgs>,
+}
+
+impl<B: Backend> RenderGroup<B, World> for DrawShadowDepth<B> {
+    fn prepare(
+        &mut self,
+        factory: &Factory<B>,
+        _queue: QueueId,
+        index: usize,
+        _subpass: hal::pass::Subpass<'_, B>,
+        world: &World,
+    ) -> PrepareResult {
+        let (mesh_storage, shadows, meshes, transforms, transparent, hidden, hidden_propagate) =
+            <(
+                Read<'_, AssetStorage<Mesh>>,
+                Read<'_, ShadowCascades>,
+                ReadStorage<'_, Handle<Mesh>>,
+                ReadStorage<'_, Transform>,
+                ReadStorage<'_, Transparent>,
+                ReadStorage<'_, Hidden>,
+                ReadStorage<'_, HiddenPropagate>,
+            )>::fetch(world);
+
+        self.draws.clear();
+        self.instances.clear();
+        // Without a sun, the cleared depth leaves everything lit.
+        if let Some(cascade) = shadows.cascades.get(self.cascade) {
+            self.view.write(
+                factory,
+                index,
+                ShadowViewArgs {
+                    view_proj: to_mat4(&cascade.view_proj),
+                }
+                .std140(),
+            );
+            // Casters outside of the camera frustum still shadow what's inside, so this doesn't
+            // use the `Visibility`.
+            let opaque = (
+                &meshes,
+                &transforms,
+                !&transparent,
+                !&hidden,
+                !&hidden_propagate,
+            )
+                .join()
+                .filter(|(mesh, ..)| mesh_storage.contains_id(mesh.id()))
+                .map(|(mesh, tfm, ..)| (mesh.id(), VertexArgs::from_object_data(tfm, None)));
+            batch_instances(opaque, &mut self.draws, &mut self.instances);
+        }
+        self.models.write(
+            factory,
+            index,
+            self.instances.len() as u64,
+            Some(&self.instances),
+        );
+
+        PrepareResult::DrawRecord
+    }
+
+    fn draw_inline(
+        &mut self,
+        mut encoder: RenderPassEncoder<'_, B>,
+        index: usize,
+        _subpass: hal::pass::Subpass<'_, B>,
+        world: &World,
+    ) {
+        if self.draws.is_empty() {
+            return;
+        }
+        let mesh_storage = <Read<'_, AssetStorage<Mesh>>>::fetch(world);
+
+        encoder.bind_graphics_pipeline(&self.pipeline);
+        self.view
+            .bind(index, &self.pipeline_layout, 0, &mut encoder);
+        if !self
+            .models
+            .bind(index, self.vertex_format.len() as u32, 0, &mut encoder)
+        {
+            return;
+        }
+        for (mesh_id, instances) in self.draws.iter() {
+            debug_assert!(mesh_storage.contains_id(*mesh_id));
+            let mesh = unsafe { mesh_storage.get_by_id_unchecked(*mesh_id) };
+            if let Some(mesh) = B::unwrap_mesh(mesh) {
+                // Every mesh has positions.
+                let _ = mesh.bind_and_draw(0, &self.vertex_format, instances.clone(), &mut encoder);
+            }
+        }
+    }
+
+    fn dispose(self: Box<Self>, factory: &mut Factory<B>, _world: &World) {
+        unsafe {
+            factory.device().destroy_graphics_pipeline(self.pipeline);
+            factory
+                .device()
+                .destroy_pipeline_layout(self.pipeline_layout);
+        }
+    }
+}
+
+/// The opaque group of `SplattedTriplanarPbrPassDef<V, T>`. With shadows, it also binds the
+/// `ShadowArgs` (set 2) and the shadow map of each cascade (set 3), which are the images of this
+/// group. The pipeline is built for a target with `samples` per pixel.
+#[derive(Debug)]
+struct DrawOpaqueSplattedDesc<V, T> {
+    num_cascades: usize,
+    samples: u8,
+    marker: PhantomData<(V, T)>,
+}
+
+impl<V, T> DrawOpaqueSplattedDesc<V, T> {
+    fn new(num_cascades: usize, samples: u8) -> Self {
+        Self {
+            num_cascades,
+            samples,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<B, V, T> RenderGroupDesc<B, World> for DrawOpaqueSplattedDesc<V, T>
+where
+    B: Backend,
+    V: SplattedVertexVariant,
+    T: SplattedTextureVariant,
+{
+    fn images(&self) -> Vec<ImageAccess> {
+        (0..self.num_cascades)
+            .map(|_| ImageAccess {
+                access: image::Access::SHADER_READ,
+                usage: image::Usage::SAMPLED,
+                layout: image::Layout::ShaderReadOnlyOptimal,
+                stages: pso::PipelineStage::FRAGMENT_SHADER,
+            })
+            .collect()
+    }
+
+    fn build(
+        self,
+        ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        _queue: QueueId,
+        _world: &World,
+        framebuffer_width: u32,
+        framebuffer_height: u32,
+        subpass: hal::pass::Subpass<'_, B>,
+        _buffers: Vec<NodeBuffer>,
+        images: Vec<NodeImage>,
+    ) -> Result<Box<dyn RenderGroup<B, World>>, failure::Error> {
+        let env = EnvironmentSub::new(
+            factory,
+            [ShaderStageFlags::VERTEX, ShaderStageFlags::FRAGMENT],
+        )?;
+        let materials = MaterialSub::new(factory)?;
+        let shadows = if self.num_cascades > 0 {
+            Some((
+                DynamicUniform::new(factory, ShaderStageFlags::FRAGMENT)?,
+                ShadowMaps::new(ctx, factory, &images)?,
+            ))
+        } else {
+            None
+        };
+        let (fragment_shader, layouts) = match shadows.as_ref() {
+            Some((args, maps)) => (
+                T::shadowed_fragment_shader(),
+                vec![
+                    env.raw_layout(),
+                    materials.raw_layout(),
+                    args.raw_layout(),
+                    maps.layout.raw(),
+                ],
+            ),
+            None => (
+                T::fragment_shader(),
+                vec![env.raw_layout(), materials.raw_layout()],
+            ),
+        };
+        let vertex_format = V::base_format();
+        let (pipeline, pipeline_layout) = build_pipeline(
+            factory,
+            subpass,
+            framebuffer_width,
+            framebuffer_height,
+            V::vertex_shader(),
+            Some(fragment_shader),
+            &vertex_format,
+            layouts,
+            self.samples,
+        )?;
+
+        Ok(Box::new(DrawOpaqueSplatted::<B, V, T> {
+            pipeline,
+            pipeline_layout,
+            vertex_format,
+            env,
+            materials,
+            shadows,
+            models: DynamicVertexBuffer::new(),
+            draws: Vec::new(),
+            instances: Vec::new(),
+            marker: PhantomData,
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct DrawOpaqueSplatted<B: Backend, V, T> {
+    pipeline: B::GraphicsPipeline,
+    pipeline_layout: B::PipelineLayout,
+    vertex_format: Vec<VertexFormat>,
+    env: EnvironmentSub<B>,
+    materials: MaterialSub<B, FullTextureSet>,
+    shadows: Option<(DynamicUniform<B, ShadowArgs>, ShadowMaps<B>)>,
+    models: DynamicVertexBuffer<B, VertexArgs>,
+    /// Materials, mesh IDs and their ranges of `instances`.
+    draws: Vec<((MaterialId, u32), Range<u32>)>,
+    instances: Vec<VertexArgs>,
+    marker: PhantomData<(V, T)>,
+}
+
+impl<B, V, T> RenderGroup<B, World> for DrawOpaqueSplatted<B, V, T>
+where
+    B: Backend,
+    V: SplattedVertexVariant,
+    T: SplattedTextureVariant,
+{
+    fn prepare(
+        &mut self,
+        factory: &Factory<B>,
+        _queue: QueueId,
+        index: usize,
+        _subpass: hal::pass::Subpass<'_, B>,
+        world: &World,
+    ) -> PrepareResult {
+        let (mesh_storage, visibility, cascades, meshes, materials, transforms, tints) =
+            <(
+                Read<'_, AssetStorage<Mesh>>,
+                ReadExpect<'_, Visibility>,
+                Read<'_, ShadowCascades>,
+                ReadStorage<'_, Handle<Mesh>>,
+                ReadStorage<'_, Handle<Material>>,
+                ReadStorage<'_, Transform>,
+                ReadStorage<'_, Tint>,
+            )>::fetch(world);
+
+        self.env.process(factory, index, world);
+        self.materials.maintain();
+
+        if let Some((shadow_args, _)) = self.shadows.as_mut() {
+            let mut cascade_view_proj = [to_mat4(&Matrix4::identity()); MAX_SHADOW_CASCADES];
+            for (view_proj, cascade) in cascade_view_proj.iter_mut().zip(cascades.cascades.iter()) {
+                *view_proj = to_mat4(&cascade.view_proj);
+            }
+            let sun_direction: [f32; 3] = cascades.sun_direction.into();
+            shadow_args.write(
+                factory,
+                index,
+                ShadowArgs {
+                    cascade_view_proj,
+                    sun_direction: sun_direction.into(),
+                    cascade_count: cascades.cascades.len().min(MAX_SHADOW_CASCADES) as int,
+                }
+                .std140(),
+            );
+        }
+
+        let materials_ref = &mut self.materials;
+        let visible = (
+            &materials,
+            &meshes,
+            &transforms,
+            tints.maybe(),
+            &visibility.visible_unordered,
+        )
+            .join()
+            .filter(|(_, mesh, ..)| mesh_storage.contains_id(mesh.id()))
+            .filter_map(|(material, mesh, tfm, tint, _)| {
+                let (material_id, _) = materials_ref.insert(factory, world, material)?;
+
+                Some((
+                    (material_id, mesh.id()),
+                    VertexArgs::from_object_data(tfm, tint),
+                ))
+            });
+        batch_instances(visible, &mut self.draws, &mut self.instances);
+        self.models.write(
+            factory,
+            index,
+            self.instances.len() as u64,
+            Some(&self.instances),
+        );
+
+        PrepareResult::DrawRecord
+    }
+
+    fn draw_inline(
+        &mut self,
+        mut encoder: RenderPassEncoder<'_, B>,
+        index: usize,
+        _subpass: hal::pass::Subpass<'_, B>,
+        world: &World,
+    ) {
+        if self.draws.is_empty() {
+            return;
+        }
+        let mesh_storage = <Read<'_, AssetStorage<Mesh>>>::fetch(world);
+
+        encoder.bind_graphics_pipeline(&self.pipeline);
+        self.env.bind(index, &self.pipeline_layout, 0, &mut encoder);
+        if let Some((shadow_args, shadow_maps)) = self.shadows.as_ref() {
+            shadow_args.bind(index, &self.pipeline_layout, 2, &mut encoder);
+            unsafe {
+                encoder.bind_graphics_descriptor_sets(
+                    &self.pipeline_layout,
+                    3,
+                    Some(shadow_maps.set.raw()),
+                    std::iter::empty(),
+                );
+            }
+        }
+        if !self
+            .models
+            .bind(index, self.vertex_format.len() as u32, 0, &mut encoder)
+        {
+            return;
+        }
+        for ((material_id, mesh_id), instances) in self.draws.iter() {
+            if !self.materials.loaded(*material_id) {
+                continue;
+            }
+            self.materials
+                .bind(&self.pipeline_layout, 1, *material_id, &mut encoder);
+            debug_assert!(mesh_storage.contains_id(*mesh_id));
+            let mesh = unsafe { mesh_storage.get_by_id_unchecked(*mesh_id) };
+            if let Some(mesh) = B::unwrap_mesh(mesh) {
+                // The meshes of the other passes, like the greedy quads of the blocky pass, don't
+                // have our vertex format, so they're skipped.
+                let _ = mesh.bind_and_draw(0, &self.vertex_format, instances.clone(), &mut encoder);
+            }
+        }
+    }
+
+    fn dispose(self: Box<Self>, factory: &mut Factory<B>, _world: &World) {
+        unsafe {
+            factory.device().destroy_graphics_pipeline(self.pipeline);
+            factory
+                .device()
+                .destroy_pipeline_layout(self.pipeline_layout);
+        }
+    }
+}
+
+/// The descriptor set of the cascades' depth images, as `sampler2D shadow_maps[4]`. Slots without a
+/// cascade repeat the first one, so that every slot is valid.
+#[derive(Debug)]
+struct ShadowMaps<B: Backend> {
+    layout: RendyHandle<DescriptorSetLayout<B>>,
+    set: Escape<DescriptorSet<B>>,
+    // Kept alive for as long as the set refers to them.
+    _views: Vec<Escape<ImageView<B>>>,
+    _sampler: RendyHandle<Sampler<B>>,
+}
+
+impl<B: Backend> ShadowMaps<B> {
+    fn new(
+        ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        images: &[NodeImage],
+    ) -> Result<Self, failure::Error> {
+        let layout: RendyHandle<DescriptorSetLayout<B>> = factory
+            .create_descriptor_set_layout(vec![pso::DescriptorSetLayoutBinding {
+                binding: 0,
+                ty: pso::DescriptorType::CombinedImageSampler,
+                count: MAX_SHADOW_CASCADES,
+                stage_flags: ShaderStageFlags::FRAGMENT,
+                immutable_samplers: false,
+            }])?
+            .into();
+        let set = factory.create_descriptor_set(layout.clone())?;
+        let sampler = factory.get_sampler(SamplerInfo::new(Filter::Nearest, WrapMode::Clamp))?;
+
+        let mut views = Vec::with_capacity(images.len());
+        for node_image in images.iter() {
+            let image = ctx
+                .get_image(node_image.id)
+                .ok_or_else(|| failure::format_err!("Missing shadow map image"))?;
+            let view = factory
+                .create_image_view(
+                    image.clone(),
+                    ImageViewInfo {
+                        view_kind: ViewKind::D2,
+                        format: SHADOW_MAP_FORMAT,
+                        swizzle: Swizzle::NO,
+                        range: SubresourceRange {
+                            aspects: Aspects::DEPTH,
+                            levels: 0..1,
+                            layers: 0..1,
+                        },
+                    },
+                )
+                .map_err(|e| failure::format_err!("Failed to create shadow map view: {:?}", e))?;
+            views.push(view);
+        }
+        if views.is_empty() {
+            failure::bail!("No shadow cascades to sample");
+        }
+
+        unsafe {
+            factory
+                .device()
+                .write_descriptor_sets((0..MAX_SHADOW_CASCADES).map(|slot| {
+                    let view = &views[slot.min(views.len() - 1)];
+                    pso::DescriptorSetWrite {
+                        set: set.raw(),
+                        binding: 0,
+                        array_offset: slot,
+                        descriptors: Some(pso::Descriptor::CombinedImageSampler(
+                            view.raw(),
+                            image::Layout::ShaderReadOnlyOptimal,
+                            sampler.raw(),
+                        )),
+                    }
+                }));
+        }
+
+        Ok(Self {
+            layout,
+            set,
+            _views: views,
+            _sampler: sampler,
+        })
+    }
+}
+
+/// The multisample state of a pipeline that draws into a target with `samples` per pixel.
+pub(super) fn multisampling(samples: u8) -> Option<pso::Multisampling> {
+    if samples <= 1 {
+        return None;
+    }
+
+    Some(pso::Multisampling {
+        rasterization_samples: samples,
+        sample_shading: None,
+        sample_mask: !0,
+        alpha_coverage: false,
+        alpha_to_one: false,
+    })
+}
+
+/// Groups the instances by `key` into `draws`, each with its range of `instances`.
+fn batch_instances<K: Copy + Eq + Hash>(
+    items: impl Iterator<Item = (K, VertexArgs)>,
+    draws: &mut Vec<(K, Range<u32>)>,
+    instances: &mut Vec<VertexArgs>,
+) {
+    let mut batches: FnvHashMap<K, Vec<VertexArgs>> = FnvHashMap::default();
+    for (key, args) in items {
+        batches.entry(key).or_insert_with(Vec::new).push(args);
+    }
+
+    draws.clear();
+    instances.clear();
+    for (key, batch) in batches.into_iter() {
+        let start = instances.len() as u32;
+        instances.extend(batch);
+        draws.push((key, start..instances.len() as u32));
+    }
+}
+
+/// Builds a pipeline for the vertex format of the meshes followed by the `VertexArgs` of each
+/// instance, like `DrawBase3D` does. Without a fragment shader, only depth is written. `samples`
+/// must match the target that the subpass draws into.
+#[allow(clippy::too_many_arguments)]
+fn build_pipeline<B: Backend>(
+    factory: &Factory<B>,
+    subpass: hal::pass::Subpass<'_, B>,
+    framebuffer_width: u32,
+    framebuffer_height: u32,
+    vertex_shader: &SpirvShader,
+    fragment_shader: Option<&SpirvShader>,
+    vertex_format: &[VertexFormat],
+    layouts: Vec<&B::DescriptorSetLayout>,
+    samples: u8,
+) -> Result<(B::GraphicsPipeline, B::PipelineLayout), failure::Error> {
+    let pipeline_layout = unsafe {
+        factory
+            .device()
+            .create_pipeline_layout(layouts, None as Option<(_, _)>)
+    }?;
+
+    let vertex_desc: Vec<_> = vertex_format
+        .iter()
+        .map(|f| (f.clone(), pso::VertexInputRate::Vertex))
+        .chain(Some((
+            VertexArgs::vertex(),
+            pso::VertexInputRate::Instance(1),
+        )))
+        .collect();
+    let shader_vertex = unsafe { vertex_shader.module(factory).unwrap() };
+    let shader_fragment = fragment_shader.map(|s| unsafe { s.module(factory).unwrap() });
+    let (blend_targets, face_culling) = if shader_fragment.is_some() {
+        (
+            vec![pso::ColorBlendDesc {
+                mask: pso::ColorMask::ALL,
+                blend: None,
+            }],
+            pso::Face::BACK,
+        )
+    } else {
+        // The cascades' projections don't flip Y like the camera's, so the winding of the
+        // triangles is reversed. Drawing both sides also keeps thin walls from leaking light.
+        (vec![], pso::Face::NONE)
+    };
+
+    let pipes = PipelinesBuilder::new()
+        .with_pipeline(
+            PipelineDescBuilder::new()
+                .with_vertex_desc(&vertex_desc)
+                .with_shaders(util::simple_shader_set(
+                    &shader_vertex,
+                    shader_fragment.as_ref(),
+                ))
+                .with_layout(&pipeline_layout)
+                .with_subpass(subpass)
+                .with_framebuffer_size(framebuffer_width, framebuffer_height)
+                .with_face_culling(face_culling)
+                .with_depth_test(pso::DepthTest {
+                    fun: pso::Comparison::Less,
+                    write: true,
+                })
+                .with_blend_targets(blend_targets)
+                .with_multisampling(multisampling(samples)),
+        )
+        .build(factory, None);
+
+    unsafe {
+        factory.destroy_shader_module(shader_vertex);
+        if let Some(shader_fragment) = shader_fragment {
+            factory.destroy_shader_module(shader_fragment);
+        }
+    }
+
+    match pipes {
+        Err(e) => {
+            unsafe {
+                factory.device().destroy_pipeline_layout(pipeline_layout);
+            }
+            Err(e)
+        }
+        Ok(mut pipes) => Ok((pipes.remove(0), pipeline_layout)),
+    }
+}
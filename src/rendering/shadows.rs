@@ -0,0 +1,258 @@
+//! Cascaded shadow maps for the sun.
+//!
+//! The camera frustum is split into `num_cascades` slices along the view direction, and each slice
+//! gets an orthographic projection from the sun that covers it. Nearby slices are small, so they
+//! get more shadow map texels per voxel than the distant ones.
+//!
+//! The `RenderShadowedSplattedTriplanarPbr` plugin draws the opaque meshes into one depth target
+//! per cascade and samples them in the splatted pass.
+
+use super::{day_night::SunTag, splatted_triplanar_pbr_pass::VoxelRenderConfig};
+
+use amethyst::{
+    core::{
+        ecs::prelude::*,
+        math::{Matrix4, Point3, Vector3},
+        Transform,
+    },
+    renderer::{
+        camera::{ActiveCamera, Camera},
+        light::Light,
+    },
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ShadowConfig {
+    pub enabled: bool,
+    /// Between 1 and `MAX_SHADOW_CASCADES`.
+    pub num_cascades: usize,
+    /// Width and height of each cascade's shadow map, in texels.
+    pub resolution: u32,
+    /// Nothing casts shadows beyond this distance from the camera.
+    pub max_distance: f32,
+    /// Blends between uniform (0) and logarithmic (1) split distances. Logarithmic splits put more
+    /// resolution close to the camera.
+    pub split_lambda: f32,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            num_cascades: 3,
+            resolution: 2048,
+            max_distance: 200.0,
+            split_lambda: 0.75,
+        }
+    }
+}
+
+pub const MAX_SHADOW_CASCADES: usize = 4;
+
+/// How far behind a cascade (towards the sun) to look for shadow casters, in voxels.
+const SHADOW_CASTER_MARGIN: f32 = 100.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShadowCascade {
+    /// The distance from the camera where this cascade ends and the next begins.
+    pub split_far: f32,
+    /// Transforms world space into the cascade's clip space.
+    pub view_proj: Matrix4<f32>,
+}
+
+/// The shadow cascades for the current frame, nearest first. Empty when shadows are disabled or
+/// there's no sun.
+#[derive(Debug)]
+pub struct ShadowCascades {
+    pub cascades: Vec<ShadowCascade>,
+    /// The direction of the sunlight that the cascades were fitted to.
+    pub sun_direction: Vector3<f32>,
+}
+
+impl Default for ShadowCascades {
+    fn default() -> Self {
+        Self {
+            cascades: Vec::new(),
+            sun_direction: -Vector3::y(),
+        }
+    }
+}
+
+/// The far distance of each cascade, for a view frustum from `near` to `far`.
+pub fn cascade_split_distances(config: &ShadowConfig, near: f32, far: f32) -> Vec<f32> {
+    let far = far.min(config.max_distance);
+    let n = config.num_cascades.max(1).min(MAX_SHADOW_CASCADES);
+
+    (1..=n)
+        .map(|i| {
+            let t = i as f32 / n as f32;
+            let log = near * (far / near).powf(t);
+            let uniform = near + (far - near) * t;
+
+            config.split_lambda * log + (1.0 - config.split_lambda) * uniform
+        })
+        .collect()
+}
+
+/// The bounding sphere of the slice of a perspective frustum between view distances `near` and
+/// `far`, as (distance of the center along the view direction, radius). A sphere keeps the same
+/// size while the camera turns, so the shadows don't shimmer.
+fn frustum_slice_sphere(near: f32, far: f32, fovy: f32, aspect: f32) -> (f32, f32) {
+    let tan_y = (fovy / 2.0).tan();
+    let tan_x = tan_y * aspect;
+    // Lateral distance of the slice's corners per unit of view distance.
+    let k_sq = tan_x * tan_x + tan_y * tan_y;
+    let center = ((far + near) * (1.0 + k_sq) / 2.0).min(far);
+    let radius = ((far - center).powi(2) + far * far * k_sq).sqrt();
+
+    (center, radius)
+}
+
+/// An orthographic view of the sphere from the sun, snapped to whole texels.
+fn cascade_view_proj(
+    center: Point3<f32>,
+    radius: f32,
+    sun_direction: &Vector3<f32>,
+    resolution: u32,
+) -> Matrix4<f32> {
+    let up = if sun_direction.y.abs() > 0.99 {
+        Vector3::z()
+    } else {
+        Vector3::y()
+    };
+    let eye = center - sun_direction * (radius + SHADOW_CASTER_MARGIN);
+    let mut view = Matrix4::look_at_rh(&eye, &center, &up);
+
+    // Only move the cascade by whole texels, or the shadow edges crawl as the camera moves.
+    let texel_size = 2.0 * radius / resolution as f32;
+    let origin = view.transform_point(&Point3::origin());
+    view.append_translation_mut(&Vector3::new(
+        (origin.x / texel_size).round() * texel_size - origin.x,
+        (origin.y / texel_size).round() * texel_size - origin.y,
+        0.0,
+    ));
+
+    let depth = 2.0 * radius + SHADOW_CASTER_MARGIN;
+    let proj = Matrix4::new_orthographic(-radius, radius, -radius, radius, 0.0, depth);
+    // nalgebra maps depth to [-1, 1] like OpenGL, but Vulkan clips it to [0, 1].
+    let depth_range = Matrix4::new_nonuniform_scaling(&Vector3::new(1.0, 1.0, 0.5))
+        .append_translation(&Vector3::new(0.0, 0.0, 0.5));
+
+    depth_range * proj * view
+}
+
+/// Fits the `ShadowCascades` to the active camera and the sun (the directional light with a
+/// `SunTag`) every frame.
+pub struct ShadowCascadeSystem;
+
+impl<'a> System<'a> for ShadowCascadeSystem {
+    type SystemData = (
+        Read<'a, VoxelRenderConfig>,
+        Read<'a, ActiveCamera>,
+        ReadStorage<'a, Camera>,
+        ReadStorage<'a, Transform>,
+        ReadStorage<'a, SunTag>,
+        ReadStorage<'a, Light>,
+        Write<'a, ShadowCascades>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            render_config,
+            active_camera,
+            cameras,
+            transforms,
+            suns,
+            lights,
+            mut shadows,
+        ): Self::SystemData,
+    ) {
+        shadows.cascades.clear();
+
+        let config = &render_config.shadows;
+        if !config.enabled {
+            return;
+        }
+        let sun_direction = (&suns, &lights).join().find_map(|(_, light)| match light {
+            Light::Directional(sun) if sun.intensity > 0.0 => Some(sun.direction.normalize()),
+            _ => None,
+        });
+        let sun_direction = match sun_direction {
+            Some(d) => d,
+            None => return,
+        };
+        let camera = active_camera
+            .entity
+            .and_then(|e| Some((cameras.get(e)?, transforms.get(e)?)))
+            .or_else(|| (&cameras, &transforms).join().next());
+        let (camera, camera_tfm) = match camera {
+            Some(c) => c,
+            None => return,
+        };
+        let perspective = match camera.projection().as_perspective() {
+            Some(p) => p,
+            None => return,
+        };
+
+        let camera_matrix = camera_tfm.global_matrix();
+        let eye = camera_matrix.transform_point(&Point3::origin());
+        // Cameras look down their -Z axis.
+        let forward = camera_matrix.transform_vector(&-Vector3::z()).normalize();
+        shadows.sun_direction = sun_direction;
+        let mut near = perspective.near();
+        for split_far in cascade_split_distances(config, near, perspective.far()) {
+            let (center, radius) =
+                frustum_slice_sphere(near, split_far, perspective.fovy(), perspective.aspect());
+            shadows.cascades.push(ShadowCascade {
+                split_far,
+                view_proj: cascade_view_proj(
+                    eye + forward * center,
+                    radius,
+                    &sun_direction,
+                    config.resolution,
+                ),
+            });
+            near = split_far;
+        }
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cascade_splits_end_at_max_distance() {
+        let config = ShadowConfig {
+            num_cascades: 3,
+            max_distance: 100.0,
+            ..Default::default()
+        };
+        let splits = cascade_split_distances(&config, 0.1, 1000.0);
+        assert_eq!(splits.len(), 3);
+        assert!(splits[0] < splits[1] && splits[1] < splits[2]);
+        assert!((splits[2] - 100.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_slice_sphere_contains_slice_corners() {
+        let (fovy, aspect) = (1.0f32, 16.0 / 9.0);
+        let (center, radius) = frustum_slice_sphere(10.0, 50.0, fovy, aspect);
+        let tan_y = (fovy / 2.0).tan();
+        let tan_x = tan_y * aspect;
+        for &d in [10.0f32, 50.0].iter() {
+            let corner = Vector3::new(d * tan_x, d * tan_y, d);
+            let dist = (corner - Vector3::new(0.0, 0.0, center)).norm();
+            assert!(dist <= radius + 1e-3);
+        }
+    }
+}
@@ -1,42 +1,205 @@
-use amethyst::renderer::{mtl::FullTextureSet, pass::Base3DPassDef, RenderBase3D};
+use super::{
+    blocky_pbr_pass::{BlockyPbrPassDef, RenderBlockyPbr},
+    glow_shell_pass::RenderGlowShells,
+    multisampling::RenderMultisampledSplattedTriplanarPbr,
+    shadow_pass::RenderShadowedSplattedTriplanarPbr,
+    shadows::ShadowConfig,
+};
+
+use amethyst::renderer::{
+    mtl::FullTextureSet, pass::Base3DPassDef, types::Backend, RenderBase3D, RenderingBundle,
+};
 use rendy::{
-    hal::pso::ShaderStageFlags,
-    mesh::{AsVertex, VertexFormat},
+    hal::{format::Format, pso::ShaderStageFlags},
+    mesh::{AsAttribute, AsVertex, VertexFormat},
     shader::SpirvShader,
     util::types::vertex::{Color, Normal, Position},
 };
 use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::marker::PhantomData;
 
+// The SPIR-V is compiled from the GLSL sources next to it, e.g.
+// `glslc shaders/pos_indexed.vert -o shaders/pos_indexed.spv`. The "_shadowed" fragment shaders are
+// compiled from the same sources with `-DSHADOWS`.
 lazy_static::lazy_static! {
     static ref POS_COLOR_NORM_VERTEX: SpirvShader = SpirvShader::from_bytes(
         include_bytes!("shaders/pos_color_norm.spv"),
         ShaderStageFlags::VERTEX,
         "main",
     ).unwrap();
+    static ref POS_PACKED_VERTEX: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("shaders/pos_packed.spv"),
+        ShaderStageFlags::VERTEX,
+        "main",
+    ).unwrap();
+    static ref POS_INDEXED_VERTEX: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("shaders/pos_indexed.spv"),
+        ShaderStageFlags::VERTEX,
+        "main",
+    ).unwrap();
     static ref SPLATTED_TRIPLANAR_PBR_FRAGMENT: SpirvShader = SpirvShader::from_bytes(
         include_bytes!("shaders/splatted_triplanar_pbr.spv"),
         ShaderStageFlags::FRAGMENT,
         "main",
     ).unwrap();
+    static ref SPLATTED_TRIPLANAR_PBR_ATLAS_FRAGMENT: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("shaders/splatted_triplanar_pbr_atlas.spv"),
+        ShaderStageFlags::FRAGMENT,
+        "main",
+    ).unwrap();
+    static ref SPLATTED_TRIPLANAR_PBR_INDEXED_FRAGMENT: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("shaders/splatted_triplanar_pbr_indexed.spv"),
+        ShaderStageFlags::FRAGMENT,
+        "main",
+    ).unwrap();
+    static ref SPLATTED_TRIPLANAR_PBR_SHADOWED_FRAGMENT: SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("shaders/splatted_triplanar_pbr_shadowed.spv"),
+        ShaderStageFlags::FRAGMENT,
+        "main",
+    ).unwrap();
+    static ref SPLATTED_TRIPLANAR_PBR_ATLAS_SHADOWED_FRAGMENT: SpirvShader =
+        SpirvShader::from_bytes(
+            include_bytes!("shaders/splatted_triplanar_pbr_atlas_shadowed.spv"),
+            ShaderStageFlags::FRAGMENT,
+            "main",
+        ).unwrap();
+    static ref SPLATTED_TRIPLANAR_PBR_INDEXED_SHADOWED_FRAGMENT: SpirvShader =
+        SpirvShader::from_bytes(
+            include_bytes!("shaders/splatted_triplanar_pbr_indexed_shadowed.spv"),
+            ShaderStageFlags::FRAGMENT,
+            "main",
+        ).unwrap();
+}
+
+/// Selects the vertex shader and vertex format of the splatted triplanar pass.
+pub trait SplattedVertexVariant: 'static + Debug + Send + Sync {
+    fn vertex_shader() -> &'static SpirvShader;
+    fn base_format() -> Vec<VertexFormat>;
+}
+
+/// Selects how the fragment shader samples the material textures.
+pub trait SplattedTextureVariant: 'static + Debug + Send + Sync {
+    fn fragment_shader() -> &'static SpirvShader;
+    /// The same shader, but also sampling the sun's shadow maps.
+    fn shadowed_fragment_shader() -> &'static SpirvShader;
+}
+
+/// (vec3 position, vec4 material weights, vec3 normal, vec3 emission)
+#[derive(Debug)]
+pub struct FullVertices;
+
+impl SplattedVertexVariant for FullVertices {
+    fn vertex_shader() -> &'static SpirvShader {
+        &POS_COLOR_NORM_VERTEX
+    }
+    fn base_format() -> Vec<VertexFormat> {
+        vec![
+            Position::vertex(),
+            Color::vertex(),
+            Normal::vertex(),
+            Emission::vertex(),
+        ]
+    }
+}
+
+/// (vec3 position, 8-bit material weights, octahedral 16-bit normal, RGBM emission)
+#[derive(Debug)]
+pub struct PackedVertices;
+
+impl SplattedVertexVariant for PackedVertices {
+    fn vertex_shader() -> &'static SpirvShader {
+        &POS_PACKED_VERTEX
+    }
+    fn base_format() -> Vec<VertexFormat> {
+        vec![
+            Position::vertex(),
+            PackedMaterialWeights::vertex(),
+            PackedNormal::vertex(),
+            PackedEmission::vertex(),
+        ]
+    }
+}
+
+/// (vec3 position, 4 material indices, 8-bit material weights, vec3 normal, RGBM emission)
+#[derive(Debug)]
+pub struct IndexedVertices;
+
+impl SplattedVertexVariant for IndexedVertices {
+    fn vertex_shader() -> &'static SpirvShader {
+        &POS_INDEXED_VERTEX
+    }
+    fn base_format() -> Vec<VertexFormat> {
+        vec![
+            Position::vertex(),
+            MaterialIndices::vertex(),
+            PackedMaterialWeights::vertex(),
+            Normal::vertex(),
+            PackedEmission::vertex(),
+        ]
+    }
+}
+
+/// Each material texture is an array texture with one layer per material.
+#[derive(Debug)]
+pub struct ArrayTextures;
+
+impl SplattedTextureVariant for ArrayTextures {
+    fn fragment_shader() -> &'static SpirvShader {
+        &SPLATTED_TRIPLANAR_PBR_FRAGMENT
+    }
+    fn shadowed_fragment_shader() -> &'static SpirvShader {
+        &SPLATTED_TRIPLANAR_PBR_SHADOWED_FRAGMENT
+    }
 }
 
+/// Each material texture is a 2D atlas with the layers packed into a 2x2 grid, for backends
+/// without robust support for texture arrays.
 #[derive(Debug)]
-pub struct SplattedTriplanarPbrPassDef;
+pub struct AtlasTextures;
 
-impl Base3DPassDef for SplattedTriplanarPbrPassDef {
+impl SplattedTextureVariant for AtlasTextures {
+    fn fragment_shader() -> &'static SpirvShader {
+        &SPLATTED_TRIPLANAR_PBR_ATLAS_FRAGMENT
+    }
+    fn shadowed_fragment_shader() -> &'static SpirvShader {
+        &SPLATTED_TRIPLANAR_PBR_ATLAS_SHADOWED_FRAGMENT
+    }
+}
+
+/// Array textures with up to 256 layers, blending the 4 layers chosen by the material indices of
+/// `IndexedVertices`.
+#[derive(Debug)]
+pub struct IndexedArrayTextures;
+
+impl SplattedTextureVariant for IndexedArrayTextures {
+    fn fragment_shader() -> &'static SpirvShader {
+        &SPLATTED_TRIPLANAR_PBR_INDEXED_FRAGMENT
+    }
+    fn shadowed_fragment_shader() -> &'static SpirvShader {
+        &SPLATTED_TRIPLANAR_PBR_INDEXED_SHADOWED_FRAGMENT
+    }
+}
+
+#[derive(Debug)]
+pub struct SplattedTriplanarPbrPassDef<V = FullVertices, T = ArrayTextures>(PhantomData<(V, T)>);
+
+impl<V: SplattedVertexVariant, T: SplattedTextureVariant> Base3DPassDef
+    for SplattedTriplanarPbrPassDef<V, T>
+{
     const NAME: &'static str = "SplattedTriplanarPbr";
     type TextureSet = FullTextureSet;
     fn vertex_shader() -> &'static SpirvShader {
-        &POS_COLOR_NORM_VERTEX
+        V::vertex_shader()
     }
     fn vertex_skinned_shader() -> &'static SpirvShader {
         unimplemented!("Don't need skinning for this pass")
     }
     fn fragment_shader() -> &'static SpirvShader {
-        &SPLATTED_TRIPLANAR_PBR_FRAGMENT
+        T::fragment_shader()
     }
     fn base_format() -> Vec<VertexFormat> {
-        vec![Position::vertex(), Color::vertex(), Normal::vertex()]
+        V::base_format()
     }
     fn skinned_format() -> Vec<VertexFormat> {
         vec![]
@@ -44,11 +207,211 @@ impl Base3DPassDef for SplattedTriplanarPbrPassDef {
 }
 
 /// A render pass that does triplanar texturing and splatting of PBR materials. Requires a vertex
-/// format of (vec3 position, vec4 color, vec3 normal). The "color" attribute is really a vector of
-/// 4 material weights, summing to one, determining how to blend the 4 materials present in the
-/// bound array texture. This means at most 4 materials can be blended in one draw call.
+/// format of (vec3 position, vec4 color, vec3 normal, vec3 emission). The "color" attribute is
+/// really a vector of 4 material weights, summing to one, determining how to blend the 4 materials
+/// present in the bound array texture. This means at most 4 materials can be blended in one draw
+/// call. The emission of the voxel types is added to the emission of the materials.
+///
+/// Like any `RenderBase3D` plugin, entities with the `Transparent` component (the meshes of
+/// transparent voxels) are drawn after the opaque ones, sorted back to front and alpha blended.
 pub type RenderSplattedTriplanarPbr = RenderBase3D<SplattedTriplanarPbrPassDef>;
 
+/// The same as `RenderSplattedTriplanarPbr`, but for meshes loaded with the
+/// `ChunkVertexFormat::Packed` vertex format.
+pub type RenderPackedSplattedTriplanarPbr =
+    RenderBase3D<SplattedTriplanarPbrPassDef<PackedVertices>>;
+
+/// The same as `RenderSplattedTriplanarPbr`, but for meshes loaded with the
+/// `ChunkVertexFormat::Indexed` vertex format, so a chunk isn't limited to the first 4 layers.
+pub type RenderIndexedSplattedTriplanarPbr =
+    RenderBase3D<SplattedTriplanarPbrPassDef<IndexedVertices, IndexedArrayTextures>>;
+
+/// Chooses the variant of the splatted triplanar pass, along with the matching formats for chunk
+/// meshes and materials. Insert it as a resource before loading the `VoxelAssets`, and add the
+/// render plugin with `with_voxel_render_plugin`.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub struct VoxelRenderConfig {
+    #[serde(default)]
+    pub vertex_format: ChunkVertexFormat,
+    #[serde(default)]
+    pub material_textures: MaterialTextureMode,
+    /// Whether emissive voxels get glow meshes, drawn by `RenderGlowShells`.
+    #[serde(default)]
+    pub glow_shells: bool,
+    /// Cascaded shadow maps for the sun. When enabled, the splatted pass is drawn by
+    /// `RenderShadowedSplattedTriplanarPbr` instead of `RenderBase3D`.
+    #[serde(default)]
+    pub shadows: ShadowConfig,
+    /// Whether the `ChunkCullingSystem` also hides chunks behind fully solid chunks.
+    #[serde(default)]
+    pub occlusion_culling: bool,
+    /// Whether the opaque chunk meshes are merged into one mesh per region of chunks, with the
+    /// `MergedChunkMeshes`. Fewer, larger meshes mean fewer draw calls, but re-meshing a chunk
+    /// uploads its whole region again.
+    #[serde(default)]
+    pub merge_chunk_meshes: bool,
+    /// The number of samples per pixel of the splatted pass, which smooths the thin edges of voxel
+    /// meshes. With more than 1, the opaque smooth meshes are drawn by
+    /// `RenderMultisampledSplattedTriplanarPbr` into a multisampled target, which is resolved into
+    /// the window's target. The GPU must support the count for color and depth, e.g. 2, 4 or 8.
+    #[serde(default)]
+    pub msaa_samples: u8,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum MaterialTextureMode {
+    /// Array materials are loaded from their prefabs as array textures.
+    Array,
+    /// The layers of the array material images are packed into 2D atlas textures while loading.
+    Atlas,
+}
+
+impl Default for MaterialTextureMode {
+    fn default() -> Self {
+        MaterialTextureMode::Array
+    }
+}
+
+pub fn with_voxel_render_plugin<B: Backend>(
+    bundle: RenderingBundle<B>,
+    config: &VoxelRenderConfig,
+) -> RenderingBundle<B> {
+    // Greedy quads meshes have their own vertex format, so they're drawn by the blocky pass no
+    // matter which splatted pass is used for the smooth meshes.
+    let bundle = match config.material_textures {
+        MaterialTextureMode::Array => bundle.with_plugin(RenderBlockyPbr::default()),
+        MaterialTextureMode::Atlas => {
+            bundle.with_plugin(RenderBase3D::<BlockyPbrPassDef<AtlasTextures>>::default())
+        }
+    };
+
+    let bundle = if config.glow_shells {
+        bundle.with_plugin(RenderGlowShells::default())
+    } else {
+        bundle
+    };
+
+    match (config.vertex_format, config.material_textures) {
+        (ChunkVertexFormat::Full, MaterialTextureMode::Array) => {
+            with_splatted_plugin::<B, FullVertices, ArrayTextures>(bundle, config)
+        }
+        (ChunkVertexFormat::Packed, MaterialTextureMode::Array) => {
+            with_splatted_plugin::<B, PackedVertices, ArrayTextures>(bundle, config)
+        }
+        (ChunkVertexFormat::Full, MaterialTextureMode::Atlas) => {
+            with_splatted_plugin::<B, FullVertices, AtlasTextures>(bundle, config)
+        }
+        (ChunkVertexFormat::Packed, MaterialTextureMode::Atlas) => {
+            with_splatted_plugin::<B, PackedVertices, AtlasTextures>(bundle, config)
+        }
+        (ChunkVertexFormat::Indexed, MaterialTextureMode::Array) => {
+            with_splatted_plugin::<B, IndexedVertices, IndexedArrayTextures>(bundle, config)
+        }
+        (ChunkVertexFormat::Indexed, MaterialTextureMode::Atlas) => {
+            // An atlas only holds 4 layers.
+            panic!("The Indexed vertex format requires Array material textures")
+        }
+    }
+}
+
+fn with_splatted_plugin<B, V, T>(
+    bundle: RenderingBundle<B>,
+    config: &VoxelRenderConfig,
+) -> RenderingBundle<B>
+where
+    B: Backend,
+    V: SplattedVertexVariant,
+    T: SplattedTextureVariant,
+{
+    if config.msaa_samples > 1 {
+        bundle.with_plugin(RenderMultisampledSplattedTriplanarPbr::<V, T>::new(
+            config.shadows,
+            config.msaa_samples,
+        ))
+    } else if config.shadows.enabled {
+        bundle.with_plugin(RenderShadowedSplattedTriplanarPbr::<V, T>::new(
+            config.shadows,
+        ))
+    } else {
+        bundle.with_plugin(RenderBase3D::<SplattedTriplanarPbrPassDef<V, T>>::default())
+    }
+}
+
+/// Material weights quantized to 8 bits each.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PackedMaterialWeights(pub [u8; 4]);
+
+impl AsAttribute for PackedMaterialWeights {
+    const NAME: &'static str = "material_weights";
+    const FORMAT: Format = Format::Rgba8Unorm;
+}
+
+/// The array layers that the material weights of a vertex refer to.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MaterialIndices(pub [ArrayMaterialIndexInt; 4]);
+
+impl AsAttribute for MaterialIndices {
+    const NAME: &'static str = "material_indices";
+    const FORMAT: Format = Format::Rgba8Uint;
+}
+
+/// An octahedral-encoded unit normal.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PackedNormal(pub [i16; 2]);
+
+impl AsAttribute for PackedNormal {
+    const NAME: &'static str = "packed_normal";
+    const FORMAT: Format = Format::Rg16Snorm;
+}
+
+/// The linear RGB emission of a vertex, added to the emission of the materials.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Emission(pub [f32; 3]);
+
+impl AsAttribute for Emission {
+    const NAME: &'static str = "emission";
+    const FORMAT: Format = Format::Rgb32Sfloat;
+}
+
+/// The largest emission that `PackedEmission` can encode in each channel.
+pub const MAX_PACKED_EMISSION: f32 = 16.0;
+
+/// Emission encoded as RGBM: the RGB is scaled by the alpha times `MAX_PACKED_EMISSION`.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PackedEmission(pub [u8; 4]);
+
+impl AsAttribute for PackedEmission {
+    const NAME: &'static str = "emission";
+    const FORMAT: Format = Format::Rgba8Unorm;
+}
+
+/// Which vertex format the chunk meshes are loaded with.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ChunkVertexFormat {
+    /// (vec3 position, vec4 material weights, vec3 normal, vec3 emission), 52 bytes per vertex.
+    Full,
+    /// (vec3 position, 8-bit material weights, octahedral 16-bit normal, RGBM emission), 24 bytes
+    /// per vertex.
+    Packed,
+    /// (vec3 position, 4 material indices, 8-bit material weights, vec3 normal, RGBM emission), 36
+    /// bytes per vertex. Each triangle blends the 4 materials that are most common at its corners,
+    /// from any of the 256 layers of the array material, instead of only the first 4 layers.
+    /// Triangles don't share vertices, so meshes have about 3 times as many vertices. Requires
+    /// `MaterialTextureMode::Array`.
+    Indexed,
+}
+
+impl Default for ChunkVertexFormat {
+    fn default() -> Self {
+        ChunkVertexFormat::Full
+    }
+}
+
 /// Identifier for one of the arrays of materials. Each mesh can only have one array material bound
 /// for the draw call.
 #[derive(
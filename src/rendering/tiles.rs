@@ -0,0 +1,360 @@
+//! Offline rendering of a map into XYZ tiles for web map viewers, like Leaflet or OpenLayers. The
+//! chunks are meshed with the same surface nets meshing as the editor, and the meshes are drawn by
+//! a small software rasterizer with an orthographic view, so no window or GPU is needed.
+//!
+//! Only the opaque layer is drawn, and each triangle gets the flat color of the voxel under it.
+
+use crate::voxel::{
+    meshing::{generate_mesh_vertices_with_surface_nets, MeshLayer},
+    morton::morton_ordered_chunk_mins,
+    LocalVoxelCache, VoxelMap, VoxelType,
+};
+
+use amethyst::core::math::{Point3, Vector3};
+use building_blocks::prelude::*;
+use image::{ImageBuffer, ImageResult, Rgba};
+use rayon::prelude::*;
+use std::path::Path;
+
+/// The light is the same for every tile, coming from the upper left of a top-down view.
+const LIGHT_DIRECTION: [f32; 3] = [-0.4, 1.0, -0.3];
+const AMBIENT_LIGHT: f32 = 0.35;
+
+/// The directions of an orthographic camera. Screen X goes along `right` and screen Y along
+/// `-up`, so the images are the right way up.
+#[derive(Clone, Copy, Debug)]
+pub struct OrthoView {
+    right: Vector3<f32>,
+    up: Vector3<f32>,
+    forward: Vector3<f32>,
+}
+
+impl OrthoView {
+    /// A camera turned `yaw_degrees` about the Y axis, looking `pitch_degrees` below the horizon.
+    pub fn new(yaw_degrees: f32, pitch_degrees: f32) -> Self {
+        let (yaw, pitch) = (yaw_degrees.to_radians(), pitch_degrees.to_radians());
+        let forward = Vector3::new(
+            -pitch.cos() * yaw.sin(),
+            -pitch.sin(),
+            -pitch.cos() * yaw.cos(),
+        );
+        let right = Vector3::new(yaw.cos(), 0.0, -yaw.sin());
+
+        Self {
+            right,
+            up: right.cross(&forward),
+            forward,
+        }
+    }
+
+    /// Looks straight down, with +X to the right and +Z down the image.
+    pub fn top_down() -> Self {
+        Self::new(0.0, 90.0)
+    }
+
+    /// The 2:1 dimetric view of pixel art games, looking down the diagonal between -X and -Z.
+    pub fn isometric() -> Self {
+        Self::new(45.0, 0.5f32.atan().to_degrees())
+    }
+
+    /// Screen X, screen Y and depth of `p`, in voxels. Larger depths are farther away.
+    fn project(&self, p: Point3<f32>) -> [f32; 3] {
+        let v = p.coords;
+
+        [v.dot(&self.right), -v.dot(&self.up), v.dot(&self.forward)]
+    }
+}
+
+/// An RGBA image with a depth buffer.
+pub struct Canvas {
+    pub width: u32,
+    pub height: u32,
+    /// Row by row from the top. Pixels that nothing was drawn on are transparent.
+    pub pixels: Vec<[u8; 4]>,
+    depths: Vec<f32>,
+}
+
+impl Canvas {
+    pub fn new(width: u32, height: u32) -> Self {
+        let num_pixels = (width * height) as usize;
+
+        Self {
+            width,
+            height,
+            pixels: vec![[0; 4]; num_pixels],
+            depths: vec![std::f32::INFINITY; num_pixels],
+        }
+    }
+
+    /// Fills the pixels whose centers are inside the triangle, where it's nearer than whatever was
+    /// drawn there before. The vertices are (X, Y, depth) in pixels.
+    pub fn draw_triangle(&mut self, [a, b, c]: [[f32; 3]; 3], color: [u8; 4]) {
+        let area = edge(a, b, c);
+        if area.abs() < std::f32::EPSILON {
+            return;
+        }
+
+        let min_x = a[0].min(b[0]).min(c[0]).floor().max(0.0) as u32;
+        let min_y = a[1].min(b[1]).min(c[1]).floor().max(0.0) as u32;
+        let max_x = (a[0].max(b[0]).max(c[0]).ceil().max(0.0) as u32).min(self.width);
+        let max_y = (a[1].max(b[1]).max(c[1]).ceil().max(0.0) as u32).min(self.height);
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let p = [x as f32 + 0.5, y as f32 + 0.5, 0.0];
+                // Barycentric weights, which are all positive inside the triangle for either
+                // winding.
+                let wa = edge(b, c, p) / area;
+                let wb = edge(c, a, p) / area;
+                let wc = edge(a, b, p) / area;
+                if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+                    continue;
+                }
+                let depth = wa * a[2] + wb * b[2] + wc * c[2];
+                let i = (y * self.width + x) as usize;
+                if depth < self.depths[i] {
+                    self.depths[i] = depth;
+                    self.pixels[i] = color;
+                }
+            }
+        }
+    }
+
+    /// Halves the width and height, averaging each 2x2 block of pixels by their alpha.
+    fn downsample(&self) -> Self {
+        let (width, height) = ((self.width / 2).max(1), (self.height / 2).max(1));
+        let mut half = Self::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = [0u32; 4];
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)].iter() {
+                    let (sx, sy) = (
+                        (2 * x + dx).min(self.width - 1),
+                        (2 * y + dy).min(self.height - 1),
+                    );
+                    let p = self.pixels[(sy * self.width + sx) as usize];
+                    let alpha = p[3] as u32;
+                    for (s, c) in sum.iter_mut().zip(p.iter()).take(3) {
+                        *s += *c as u32 * alpha;
+                    }
+                    sum[3] += alpha;
+                }
+                if sum[3] > 0 {
+                    half.pixels[(y * width + x) as usize] = [
+                        (sum[0] / sum[3]) as u8,
+                        (sum[1] / sum[3]) as u8,
+                        (sum[2] / sum[3]) as u8,
+                        (sum[3] / 4) as u8,
+                    ];
+                }
+            }
+        }
+
+        half
+    }
+
+    fn tile_is_empty(&self, min_x: u32, min_y: u32, size: u32) -> bool {
+        (min_y..min_y + size).all(|y| {
+            (min_x..min_x + size).all(|x| self.pixels[(y * self.width + x) as usize][3] == 0)
+        })
+    }
+}
+
+fn edge(a: [f32; 3], b: [f32; 3], p: [f32; 3]) -> f32 {
+    (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0])
+}
+
+/// A map drawn at the resolution of the most detailed zoom level.
+pub struct RenderedMap {
+    pub canvas: Canvas,
+    /// The most detailed zoom level, which has 2^`max_zoom` tiles on each side.
+    pub max_zoom: u32,
+    pub tile_size: u32,
+}
+
+struct ShadedTriangle {
+    /// Projected positions in voxels.
+    vertices: [[f32; 3]; 3],
+    color: [u8; 4],
+}
+
+/// Meshes every chunk of `voxel_map` and draws the meshes from `view`, with `pixels_per_voxel` at
+/// the most detailed zoom level. The image is square and made of whole tiles, with the map in the
+/// top left. Returns `None` if the map has no surfaces.
+pub fn render_map(
+    voxel_map: &VoxelMap,
+    view: &OrthoView,
+    pixels_per_voxel: f32,
+    tile_size: u32,
+    color: impl Fn(VoxelType) -> [u8; 4] + Sync,
+) -> Option<RenderedMap> {
+    let light = Vector3::from(LIGHT_DIRECTION).normalize();
+    let triangles: Vec<ShadedTriangle> = morton_ordered_chunk_mins(voxel_map)
+        .into_par_iter()
+        .flat_map(|chunk_min| {
+            let local_cache = LocalVoxelCache::new();
+            let chunk_extent = voxel_map
+                .voxels
+                .indexer
+                .extent_for_chunk_with_min(chunk_min);
+            let mesh = match generate_mesh_vertices_with_surface_nets(
+                voxel_map,
+                &chunk_extent,
+                &local_cache,
+                MeshLayer::Opaque,
+            ) {
+                Some(m) => m,
+                None => return Vec::new(),
+            };
+            let reader = voxel_map.voxels.reader(&local_cache);
+            let voxels = reader.lod_view(0);
+
+            let positions = &mesh.vertices.positions;
+            let normals = &mesh.vertices.normals;
+            // Tiles are drawn in voxels, whatever the world scale of the meshes.
+            let voxel_size = voxel_map.world_scale;
+            mesh.indices
+                .chunks(3)
+                .map(|tri| {
+                    let corner = |i: u32| Point3::from(positions[i as usize].0) / voxel_size;
+                    let corners = [corner(tri[0]), corner(tri[1]), corner(tri[2])];
+                    let normal = tri
+                        .iter()
+                        .map(|i| Vector3::from(normals[*i as usize].0))
+                        .fold(Vector3::zeros(), |sum, n| sum + n)
+                        .normalize();
+                    let centroid = Point3::from(
+                        (corners[0].coords + corners[1].coords + corners[2].coords) / 3.0,
+                    );
+
+                    // The surface passes between voxel samples, so look a little way under it for a
+                    // solid voxel to take the color from.
+                    let voxel_type = [0.5, 1.5]
+                        .iter()
+                        .map(|depth| {
+                            let p = centroid - normal * *depth;
+                            voxels.get(PointN([
+                                p.x.round() as i32,
+                                p.y.round() as i32,
+                                p.z.round() as i32,
+                            ]))
+                        })
+                        .find(|v| v.distance.0 < 0)
+                        .map(|v| v.voxel_type);
+                    let shade = AMBIENT_LIGHT + (1.0 - AMBIENT_LIGHT) * normal.dot(&light).max(0.0);
+                    let [r, g, b, a] = voxel_type.map_or([128, 128, 128, 255], &color);
+                    let shaded = |c: u8| (c as f32 * shade).min(255.0) as u8;
+
+                    ShadedTriangle {
+                        vertices: [
+                            view.project(corners[0]),
+                            view.project(corners[1]),
+                            view.project(corners[2]),
+                        ],
+                        color: [shaded(r), shaded(g), shaded(b), a],
+                    }
+                })
+                .collect()
+        })
+        .collect();
+
+    if triangles.is_empty() {
+        return None;
+    }
+    let (mut min, mut max) = ([std::f32::MAX; 2], [std::f32::MIN; 2]);
+    for v in triangles.iter().flat_map(|t| t.vertices.iter()) {
+        for axis in 0..2 {
+            min[axis] = min[axis].min(v[axis]);
+            max[axis] = max[axis].max(v[axis]);
+        }
+    }
+
+    let size_pixels = ((max[0] - min[0]).max(max[1] - min[1]) * pixels_per_voxel).ceil() as u32;
+    let mut max_zoom = 0;
+    while tile_size << max_zoom < size_pixels {
+        max_zoom += 1;
+    }
+    let canvas_size = tile_size << max_zoom;
+    let mut canvas = Canvas::new(canvas_size, canvas_size);
+    let to_pixels = |v: [f32; 3]| {
+        [
+            (v[0] - min[0]) * pixels_per_voxel,
+            (v[1] - min[1]) * pixels_per_voxel,
+            v[2],
+        ]
+    };
+    for tri in triangles.iter() {
+        let [a, b, c] = tri.vertices;
+        canvas.draw_triangle([to_pixels(a), to_pixels(b), to_pixels(c)], tri.color);
+    }
+
+    Some(RenderedMap {
+        canvas,
+        max_zoom,
+        tile_size,
+    })
+}
+
+/// Writes the tiles of every zoom level as "{z}/{x}/{y}.png" under `dir`, skipping tiles that
+/// nothing was drawn on. Returns the number of tiles written.
+pub fn write_xyz_tiles(rendered: &RenderedMap, dir: impl AsRef<Path>) -> ImageResult<usize> {
+    let tile_size = rendered.tile_size;
+    let mut num_written = 0;
+    let mut level = None;
+    for zoom in (0..=rendered.max_zoom).rev() {
+        let canvas = level.as_ref().unwrap_or(&rendered.canvas);
+        let tiles_per_side = 1 << zoom;
+        for tile_y in 0..tiles_per_side {
+            for tile_x in 0..tiles_per_side {
+                let (min_x, min_y) = (tile_x * tile_size, tile_y * tile_size);
+                if canvas.tile_is_empty(min_x, min_y, tile_size) {
+                    continue;
+                }
+                let tile = ImageBuffer::from_fn(tile_size, tile_size, |x, y| {
+                    Rgba(canvas.pixels[((min_y + y) * canvas.width + min_x + x) as usize])
+                });
+                let tile_dir = dir.as_ref().join(zoom.to_string()).join(tile_x.to_string());
+                std::fs::create_dir_all(&tile_dir)?;
+                tile.save(tile_dir.join(format!("{}.png", tile_y)))?;
+                num_written += 1;
+            }
+        }
+        level = Some(canvas.downsample());
+    }
+
+    Ok(num_written)
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nearer_triangle_covers_farther_one() {
+        let mut canvas = Canvas::new(8, 8);
+        let near = [255, 0, 0, 255];
+        let far = [0, 0, 255, 255];
+        // Opposite windings, drawn nearest first.
+        canvas.draw_triangle([[0.0, 0.0, 1.0], [8.0, 0.0, 1.0], [0.0, 8.0, 1.0]], near);
+        canvas.draw_triangle([[0.0, 0.0, 2.0], [0.0, 8.0, 2.0], [8.0, 8.0, 2.0]], far);
+
+        assert_eq!(canvas.pixels[0], near);
+        assert_eq!(canvas.pixels[7 * 8], far);
+        assert_eq!(canvas.pixels[7 * 8 + 7], [0; 4]);
+
+        let half = canvas.downsample();
+        assert_eq!((half.width, half.height), (4, 4));
+        assert_eq!(half.pixels[0], near);
+        assert!(half.tile_is_empty(3, 0, 1));
+
+        let top_down = OrthoView::top_down();
+        let [x, y, depth] = top_down.project(Point3::new(1.0, 2.0, 3.0));
+        assert!((x - 1.0).abs() < 1e-5 && (y - 3.0).abs() < 1e-5 && (depth + 2.0).abs() < 1e-5);
+    }
+}
@@ -1,11 +1,35 @@
-use amethyst::core::{
-    approx::assert_relative_eq,
-    math::{Point3, Vector3},
+use crate::{
+    rendering::{aabb_culling::BoundingBox, atlas::rgba8_texture},
+    voxel::{
+        bench_map::bench_palette,
+        bundle::VoxelSystemBundle,
+        chunk_processor::ChunkMeshJobs,
+        double_buffer::{EditSourceId, EditStamp, EditedChunksBackBuffer},
+        meshing::VoxelMeshEntities,
+        ArrayMaterialHandle, ArrayMaterialId, Voxel, VoxelAssets, VoxelMap, VoxelPalette,
+    },
 };
+
+use amethyst::{
+    assets::{AssetStorage, Handle, Loader},
+    core::{
+        approx::assert_relative_eq,
+        ecs::prelude::*,
+        math::{Point3, Vector3},
+        rayon::ThreadPoolBuilder,
+        ArcThreadPool, SystemBundle, Transform,
+    },
+    renderer::{
+        mtl::TextureOffset, rendy::hal::image::Filter, visibility::BoundingSphere, Material,
+        MaterialDefaults, Mesh, Texture,
+    },
+};
+use building_blocks::{prelude::*, search::OctreeDbvt};
 use std::collections::HashSet;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::iter::FromIterator;
+use std::sync::Arc;
 
 #[allow(unused)]
 pub fn assert_elements_eq<T: Clone + Debug + Eq + Hash>(v1: &Vec<T>, v2: &Vec<T>) {
@@ -30,3 +54,138 @@ pub fn assert_relative_eq_vector3(v1: &Vector3<f32>, v2: &Vector3<f32>) {
 pub fn assert_relative_eq_point3(p1: &Point3<f32>, p2: &Point3<f32>) {
     assert_relative_eq_vector3(&p1.coords, &p2.coords);
 }
+
+/// Voxel type 0 is empty and type 1 is solid. This is the benchmark palette with a single solid
+/// type, so tests and benchmarks build their palettes the same way.
+pub fn test_palette() -> VoxelPalette {
+    bench_palette(1)
+}
+
+/// A `World` running the `VoxelSystemBundle` without a renderer, for testing the voxel pipeline
+/// end to end. Mesh and material assets are loaded but never processed, so tests can only check
+/// which chunks have meshes, not what the meshes look like.
+pub struct VoxelPipelineHarness {
+    pub world: World,
+    dispatcher: Dispatcher<'static, 'static>,
+}
+
+impl VoxelPipelineHarness {
+    pub fn new(map: VoxelMap) -> Self {
+        let pool: ArcThreadPool =
+            Arc::new(ThreadPoolBuilder::new().num_threads(2).build().unwrap());
+
+        let mut world = World::new();
+        world.insert(pool.clone());
+        world.insert(Loader::new(".", pool.clone()));
+
+        let mut builder = DispatcherBuilder::new().with_pool(pool);
+        VoxelSystemBundle.build(&mut world, &mut builder).unwrap();
+        let mut dispatcher = builder.build();
+        dispatcher.setup(&mut world);
+
+        // Components of the chunk mesh entities, which are normally registered by the renderer.
+        world.register::<Handle<Mesh>>();
+        world.register::<Handle<Material>>();
+        world.register::<Transform>();
+        world.register::<BoundingSphere>();
+        world.register::<BoundingBox>();
+
+        let material = placeholder_material(&world);
+        let material_handle = world.read_resource::<Loader>().load_from_data(
+            material.clone(),
+            (),
+            &world.read_resource::<AssetStorage<Material>>(),
+        );
+        world.insert(MaterialDefaults(material));
+        let mut assets = VoxelAssets::default();
+        assets.array_materials.insert(
+            ArrayMaterialId(1),
+            ArrayMaterialHandle::Fallback(material_handle),
+        );
+        world.insert(assets);
+        world.insert(map);
+
+        Self { world, dispatcher }
+    }
+
+    /// Runs one frame of the pipeline. Edits are merged into the map on the frame they're applied,
+    /// and meshing of the affected chunks starts on the next frame.
+    pub fn step(&mut self) {
+        self.dispatcher.dispatch(&self.world);
+        self.world.maintain();
+    }
+
+    /// Runs frames until every chunk mesh that's been started is swapped in. Panics if that takes
+    /// unreasonably long.
+    pub fn step_until_meshed(&mut self) {
+        for _ in 0..1000 {
+            self.step();
+            if !self.world.read_resource::<ChunkMeshJobs>().is_busy() {
+                return;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        panic!("Chunk meshing did not finish");
+    }
+
+    /// Queues an edit of every voxel in `extent`, like a brush would.
+    pub fn queue_edit(
+        &mut self,
+        extent: Extent3i,
+        edit: impl Fn(Point3i, &mut Voxel) + Send + Sync + 'static,
+    ) -> EditStamp {
+        self.world
+            .write_resource::<EditedChunksBackBuffer>()
+            .queue_edit(EditSourceId::LOCAL, extent, |_| true, edit)
+    }
+
+    pub fn voxel(&self, p: Point3i) -> Voxel {
+        let map = self.world.read_resource::<VoxelMap>();
+        let local_cache = LocalChunkCache3::new();
+        let reader = map.voxels.reader(&local_cache);
+
+        reader.lod_view(0).get(p)
+    }
+
+    pub fn chunk_has_mesh(&self, chunk_min: Point3i) -> bool {
+        self.world
+            .read_resource::<VoxelAssets>()
+            .meshes
+            .chunk_meshes
+            .contains_key(&chunk_min)
+    }
+
+    pub fn num_chunk_mesh_entities(&self, chunk_min: Point3i) -> usize {
+        self.world
+            .read_resource::<VoxelMeshEntities>()
+            .chunk_entities
+            .get(&chunk_min)
+            .map_or(0, |entities| entities.len())
+    }
+
+    pub fn chunk_has_bvt(&self, chunk_min: Point3i) -> bool {
+        self.world
+            .read_resource::<OctreeDbvt<Point3i>>()
+            .contains_key(&chunk_min)
+    }
+}
+
+/// Every texture is a single white pixel.
+fn placeholder_material(world: &World) -> Material {
+    let texture: Handle<Texture> = world.read_resource::<Loader>().load_from_data(
+        rgba8_texture(1, 1, 1, vec![[255; 4]], false, Filter::Nearest),
+        (),
+        &world.read_resource::<AssetStorage<Texture>>(),
+    );
+
+    Material {
+        alpha_cutoff: 0.01,
+        albedo: texture.clone(),
+        emission: texture.clone(),
+        normal: texture.clone(),
+        metallic_roughness: texture.clone(),
+        ambient_occlusion: texture.clone(),
+        cavity: texture,
+        uv_offset: TextureOffset::default(),
+    }
+}
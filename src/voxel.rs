@@ -1,21 +1,68 @@
 use crate::rendering::splatted_triplanar_pbr_pass::{ArrayMaterialId, ArrayMaterialIndex};
 
 pub mod asset_loader;
+pub mod background_save;
+pub mod bench_map;
+pub mod block_out;
 pub mod bundle;
+pub mod cave_brush;
 pub mod chunk_cache_compressor;
 pub mod chunk_cache_flusher;
+pub mod chunk_cache_stats;
+pub mod chunk_compression;
+pub mod chunk_lock;
+pub mod chunk_octree;
 pub mod chunk_processor;
+pub mod chunk_streaming;
+pub mod clipboard;
+pub mod crater;
+pub mod csg;
 pub mod double_buffer;
+pub mod edit_history;
+pub mod edit_journal;
+pub mod edit_limits;
+pub mod erosion;
+pub mod extent_ops;
+pub mod falling;
+pub mod flood_fill;
+pub mod fluid;
+pub mod generation;
+pub mod heightmap;
+pub mod lights;
+pub mod map_diff;
 pub mod map_file;
-//pub mod map_generators;
+pub mod map_generators;
+pub mod map_stats;
+pub mod markers;
+pub mod material_fallback;
 pub mod meshing;
+pub mod metadata;
+pub mod minimap;
+pub mod morton;
+pub mod network;
+pub mod palette_audit;
+pub mod props;
+pub mod raycast;
+pub mod region_extract;
+#[cfg(feature = "scripting")]
+pub mod scripting;
+pub mod sdf_primitives;
 pub mod search;
-
+pub mod session_recording;
+pub mod sphere_brush;
+pub mod spline;
+pub mod stamps;
+pub mod validation;
+pub mod vox;
+pub mod zones;
+
+use chunk_compression::ChunkCodec;
+use material_fallback::PendingArrayMaterial;
 use meshing::loader::VoxelMeshes;
 
 use amethyst::{
     assets::{Handle, Prefab},
-    renderer::formats::mtl::MaterialPrefab,
+    renderer::{formats::mtl::MaterialPrefab, Material},
 };
 use building_blocks::{
     core::bytemuck::{Pod, Zeroable},
@@ -26,20 +73,77 @@ use nalgebra as na;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+#[cfg(feature = "sd16")]
+pub use building_blocks::storage::Sd16 as VoxelDistance;
+/// The quantized signed distance stored in each voxel. Both sizes cover distances from -1 to 1
+/// voxel, but 8 bits leave visible stair steps on large, smooth shapes. Building with the "sd16"
+/// feature stores 16 bits instead, which makes the voxels half again as large. Voxels files are
+/// converted to whichever size is built when they're loaded.
+#[cfg(not(feature = "sd16"))]
+pub use building_blocks::storage::Sd8 as VoxelDistance;
+
+/// The integer inside of a `VoxelDistance`.
+#[cfg(not(feature = "sd16"))]
+pub type VoxelDistanceInt = i8;
+#[cfg(feature = "sd16")]
+pub type VoxelDistanceInt = i16;
+
+/// The number of `VoxelDistance` steps in one step of an 8-bit distance, for code like the sphere
+/// brush that was tuned in 8-bit steps.
+#[cfg(not(feature = "sd16"))]
+pub const DISTANCE_STEPS_PER_SD8: VoxelDistanceInt = 1;
+#[cfg(feature = "sd16")]
+pub const DISTANCE_STEPS_PER_SD8: VoxelDistanceInt = std::i16::MAX / std::i8::MAX as i16;
+
 /// The global source of truth for voxels in the current map.
 pub struct VoxelMap {
     pub voxels: VoxelChunkMap,
     pub palette: VoxelPalette,
+    /// How chunks are compressed when they leave the cache.
+    pub codec: ChunkCodec,
+    /// The edge length of a voxel in world units, set from the `VoxelMapFile`. Maps can use smaller
+    /// voxels than 1 unit; meshes, colliders and conversions like `voxel_center` are scaled by it.
+    pub world_scale: f32,
 }
 
 impl VoxelMap {
     pub fn new(palette: VoxelPalette) -> Self {
+        Self::with_codec(palette, ChunkCodec::default())
+    }
+
+    pub fn with_codec(palette: VoxelPalette, codec: ChunkCodec) -> Self {
+        Self::with_chunk_shape(palette, codec, VOXEL_CHUNK_SHAPE)
+    }
+
+    /// Each dimension of `chunk_shape` must be a power of 2.
+    pub fn with_chunk_shape(
+        palette: VoxelPalette,
+        codec: ChunkCodec,
+        chunk_shape: Point3i,
+    ) -> Self {
         Self {
-            voxels: empty_compressible_chunk_map(),
+            voxels: empty_compressible_chunk_map(codec, chunk_shape),
             palette,
+            codec,
+            world_scale: 1.0,
         }
     }
 
+    /// The shape of every chunk in the map, `VOXEL_CHUNK_SHAPE` unless the map file sets another.
+    pub fn chunk_shape(&self) -> Point3i {
+        self.voxels.indexer.chunk_shape()
+    }
+
+    /// Looks up the gameplay metadata of the voxel at `p`. This decompresses the chunk into a
+    /// throwaway cache, so it's meant for occasional queries like footsteps, not bulk reads.
+    pub fn voxel_gameplay_at(&self, p: Point3i) -> &VoxelGameplay {
+        let local_cache = LocalChunkCache3::new();
+        let reader = self.voxels.reader(&local_cache);
+        let voxel = reader.lod_view(0).get(p);
+
+        self.palette.get_voxel_type_gameplay(voxel.voxel_type)
+    }
+
     pub fn voxel_info_transform<'a>(&'a self) -> impl Fn(Voxel) -> &'a VoxelInfo {
         move |v: Voxel| self.palette.get_voxel_type_info(v.voxel_type)
     }
@@ -47,9 +151,11 @@ impl VoxelMap {
 
 /// The data actually stored in each point of the voxel map.
 #[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+// A 16-bit distance would otherwise leave a padding byte, which `Pod` doesn't allow.
+#[cfg_attr(feature = "sd16", repr(C, packed))]
 pub struct Voxel {
     pub voxel_type: VoxelType,
-    pub distance: Sd8,
+    pub distance: VoxelDistance,
 }
 
 unsafe impl Zeroable for Voxel {}
@@ -73,7 +179,7 @@ impl From<Voxel> for f32 {
 
 pub const EMPTY_VOXEL: Voxel = Voxel {
     voxel_type: VoxelType(0),
-    distance: Sd8(50),
+    distance: VoxelDistance(50 * DISTANCE_STEPS_PER_SD8),
 };
 
 /// A full static description of the `VoxelInfo`s to be loaded for one map.
@@ -90,14 +196,37 @@ impl VoxelPalette {
     pub fn get_voxel_type_info(&self, voxel_type: VoxelType) -> &VoxelInfo {
         &self.infos[voxel_type.0 as usize]
     }
+
+    pub fn get_voxel_type_gameplay(&self, voxel_type: VoxelType) -> &VoxelGameplay {
+        &self.get_voxel_type_info(voxel_type).gameplay
+    }
+
+    pub fn get_voxel_type_info_mut(&mut self, voxel_type: VoxelType) -> &mut VoxelInfo {
+        &mut self.infos[voxel_type.0 as usize]
+    }
+
+    /// Appends a new entry to the palette. Returns `None` if the palette is full, since voxel types
+    /// are only 8 bits.
+    pub fn add_voxel_type(&mut self, info: VoxelInfo) -> Option<VoxelType> {
+        if self.infos.len() > u8::MAX as usize {
+            return None;
+        }
+        self.infos.push(info);
+
+        Some(VoxelType((self.infos.len() - 1) as u8))
+    }
 }
 
 /// Fully describes a voxel model in a serializable format. Can be aliased by a `Voxel` for
 /// instancing inside the map.
-#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct VoxelInfo {
     pub flags: VoxelFlags,
     pub material_index: ArrayMaterialIndex,
+    #[serde(default)]
+    pub gameplay: VoxelGameplay,
+    #[serde(default)]
+    pub emission: VoxelEmission,
 }
 
 impl IsEmpty for &VoxelInfo {
@@ -122,7 +251,7 @@ impl MergeVoxel for &VoxelInfo {
 
 impl IsOpaque for &VoxelInfo {
     fn is_opaque(&self) -> bool {
-        true
+        !self.flags.is_transparent
     }
 }
 
@@ -132,6 +261,53 @@ pub struct VoxelFlags {
     pub is_floor: bool,
     /// Whether a bounding box (AABB) should be created for this voxel.
     pub is_empty: bool,
+    /// Whether the voxel is meshed separately and alpha blended, e.g. for water or glass. The
+    /// opacity comes from the alpha channel of the material's albedo texture.
+    #[serde(default)]
+    pub is_transparent: bool,
+    /// Whether unsupported voxels of this type fall, like sand or gravel; see the `falling` module.
+    #[serde(default)]
+    pub is_gravity_affected: bool,
+}
+
+/// Game-facing properties of a voxel type. The editor doesn't use any of these; they're carried with
+/// the palette so games don't need to keep their own table in sync with it.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct VoxelGameplay {
+    /// Identifies the sound to play when walking on this voxel type.
+    #[serde(default)]
+    pub footstep_sound: Option<String>,
+    /// How hard the voxel type is to dig through, in whatever units the game likes.
+    #[serde(default)]
+    pub hardness: Option<f32>,
+    #[serde(default)]
+    pub friction: Option<f32>,
+    /// Whether the game should allow this voxel type to be destroyed.
+    #[serde(default)]
+    pub destructible: Option<bool>,
+}
+
+/// Light given off by a voxel type, e.g. for lava or crystals. It's added to the emission of the
+/// material, so it shows even when the material has no emission texture, and it can be drawn as a
+/// glow shell around the voxels.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct VoxelEmission {
+    /// Linear RGB.
+    pub color: [f32; 3],
+    /// Can be more than 1 for a stronger glow.
+    pub intensity: f32,
+}
+
+impl VoxelEmission {
+    pub fn is_emissive(&self) -> bool {
+        self.intensity > 0.0 && self.color.iter().any(|c| *c > 0.0)
+    }
+
+    pub fn radiance(&self) -> [f32; 3] {
+        let [r, g, b] = self.color;
+
+        [r * self.intensity, g * self.intensity, b * self.intensity]
+    }
 }
 
 pub trait IsFloor {
@@ -144,27 +320,55 @@ pub struct VoxelPaletteAssets {
     pub array_materials: HashMap<usize, String>,
 }
 
+/// The default chunk shape. Maps can use another with `VoxelMap::with_chunk_shape`.
 pub const VOXEL_CHUNK_SHAPE: Point3i = PointN([16; 3]);
 
 #[derive(Default)]
 pub struct VoxelAssets {
     /// Although these are just `Material`s, each `Texture` can have multiple layers for the purpose
     /// of splatting (blending between layers).
-    pub array_materials: HashMap<ArrayMaterialId, Handle<Prefab<MaterialPrefab>>>,
+    pub array_materials: HashMap<ArrayMaterialId, ArrayMaterialHandle>,
+    /// Array materials that are still loading. Checked by the `ArrayMaterialFallbackSystem`.
+    pub pending_materials: Vec<PendingArrayMaterial>,
     /// Generated at runtime, the asset handles are stored here.
     pub meshes: VoxelMeshes,
 }
 
-pub fn voxel_center_offset() -> na::Vector3<f32> {
-    na::Vector3::new(0.5, 0.5, 0.5)
+/// An array material prefab, or the built-in fallback material if the prefab failed to load.
+#[derive(Clone)]
+pub enum ArrayMaterialHandle {
+    Prefab(Handle<Prefab<MaterialPrefab>>),
+    /// Loaded for `MaterialTextureMode::Atlas`.
+    Atlas(Handle<Material>),
+    Fallback(Handle<Material>),
+}
+
+/// Converts a point from world units to voxel units. `world_scale` is the map's
+/// `VoxelMap::world_scale`.
+pub fn world_to_voxel_space(p: na::Point3<f32>, world_scale: f32) -> na::Point3<f32> {
+    p / world_scale
+}
+
+/// Converts a point from voxel units to world units.
+pub fn voxel_to_world_space(p: na::Point3<f32>, world_scale: f32) -> na::Point3<f32> {
+    p * world_scale
+}
+
+pub fn voxel_center_offset(world_scale: f32) -> na::Vector3<f32> {
+    na::Vector3::new(0.5, 0.5, 0.5) * world_scale
 }
 
-pub fn voxel_center(p: Point3i) -> na::Point3<f32> {
-    na::Point3::<f32>::from(mint::Point3::<f32>::from(Point3f::from(p))) + voxel_center_offset()
+pub fn voxel_center(p: Point3i, world_scale: f32) -> na::Point3<f32> {
+    voxel_to_world_space(
+        na::Point3::<f32>::from(mint::Point3::<f32>::from(
+            Point3f::from(p) + PointN([0.5; 3]),
+        )),
+        world_scale,
+    )
 }
 
-pub fn voxel_containing_point(p: na::Point3<f32>) -> Point3i {
-    let p: mint::Point3<f32> = p.into();
+pub fn voxel_containing_point(p: na::Point3<f32>, world_scale: f32) -> Point3i {
+    let p: mint::Point3<f32> = world_to_voxel_space(p, world_scale).into();
 
     Point3f::from(p).in_voxel()
 }
@@ -177,24 +381,24 @@ pub fn centered_extent(center: Point3i, radius: u32) -> Extent3i {
     Extent3i::from_min_and_shape(min, shape)
 }
 
-pub fn empty_compressible_chunk_map() -> VoxelChunkMap {
-    let builder = ChunkMapBuilder3x1::new(VOXEL_CHUNK_SHAPE, EMPTY_VOXEL);
+pub fn empty_compressible_chunk_map(codec: ChunkCodec, chunk_shape: Point3i) -> VoxelChunkMap {
+    let builder = ChunkMapBuilder3x1::new(chunk_shape, EMPTY_VOXEL);
 
     builder.build_with_write_storage(FastCompressibleChunkStorageNx1::with_bytes_compression(
-        Lz4 { level: 10 },
+        codec,
     ))
 }
 
-pub fn empty_chunk_hash_map() -> VoxelChunkHashMap {
-    ChunkMapBuilder3x1::new(VOXEL_CHUNK_SHAPE, EMPTY_VOXEL).build_with_hash_map_storage()
+pub fn empty_chunk_hash_map(chunk_shape: Point3i) -> VoxelChunkHashMap {
+    ChunkMapBuilder3x1::new(chunk_shape, EMPTY_VOXEL).build_with_hash_map_storage()
 }
 
 pub fn empty_array(extent: Extent3i) -> Array3x1<Voxel> {
     Array3x1::fill(extent, EMPTY_VOXEL)
 }
 
-pub type VoxelChunkMap = CompressibleChunkMap3x1<Lz4, Voxel>;
+pub type VoxelChunkMap = CompressibleChunkMap3x1<ChunkCodec, Voxel>;
 pub type VoxelChunkHashMap = ChunkHashMap3x1<Voxel>;
 
 pub type LocalVoxelCache = LocalChunkCache3<Array3x1<Voxel>>;
-pub type VoxelChunkReader<'a> = CompressibleChunkMapReader3x1<'a, Lz4, Voxel>;
+pub type VoxelChunkReader<'a> = CompressibleChunkMapReader3x1<'a, ChunkCodec, Voxel>;
@@ -1,11 +1,18 @@
 use super::{
-    meshing::loader::VoxelMeshLoader, ArrayMaterialId, LocalVoxelCache, VoxelAssets, VoxelMap,
+    material_fallback::{FallbackMaterialLoader, MissingAssetsEvent, PendingArrayMaterial},
+    meshing::loader::VoxelMeshLoader,
+    ArrayMaterialHandle, ArrayMaterialId, VoxelAssets, VoxelMap,
+};
+use crate::rendering::{
+    atlas::AtlasMaterialLoader,
+    splatted_triplanar_pbr_pass::{MaterialTextureMode, VoxelRenderConfig},
 };
 
 use amethyst::{
-    assets::{Handle, Prefab, PrefabLoader, ProgressCounter, RonFormat},
+    assets::{PrefabLoader, ProgressCounter, RonFormat},
     core::ecs::prelude::*,
     renderer::formats::mtl::MaterialPrefab,
+    shrev::EventChannel,
     utils::application_dir,
 };
 use std::collections::HashMap;
@@ -13,51 +20,118 @@ use std::collections::HashMap;
 #[derive(SystemData)]
 pub struct VoxelAssetLoader<'a> {
     material_loader: PrefabLoader<'a, MaterialPrefab>,
+    atlas_material_loader: AtlasMaterialLoader<'a>,
+    fallback_material_loader: FallbackMaterialLoader<'a>,
+    missing_asset_events: Write<'a, EventChannel<MissingAssetsEvent>>,
+    render_config: Read<'a, VoxelRenderConfig>,
     mesh_loader: VoxelMeshLoader<'a>,
 }
 
 impl<'a> VoxelAssetLoader<'a> {
-    pub fn start_loading(
-        &mut self,
-        map: &VoxelMap,
-        chunk_cache: &LocalVoxelCache,
-        progress: &mut ProgressCounter,
-    ) -> VoxelAssets {
-        let array_materials =
-            self.start_loading_materials(&map.palette.assets.array_materials, &mut *progress);
+    pub fn start_loading(&mut self, map: &VoxelMap, progress: &mut ProgressCounter) -> VoxelAssets {
+        let (array_materials, pending_materials) = match self.render_config.material_textures {
+            MaterialTextureMode::Array => {
+                let pending_materials =
+                    self.start_loading_materials(&map.palette.assets.array_materials);
+                let array_materials = pending_materials
+                    .iter()
+                    .map(|pending| {
+                        (
+                            pending.id,
+                            ArrayMaterialHandle::Prefab(pending.handle.clone()),
+                        )
+                    })
+                    .collect();
+
+                (array_materials, pending_materials)
+            }
+            MaterialTextureMode::Atlas => (
+                self.load_atlas_materials(&map.palette.assets.array_materials),
+                Vec::new(),
+            ),
+        };
         let meshes = self
             .mesh_loader
-            .start_loading_all_chunks(map, chunk_cache, &mut *progress);
+            .start_loading_all_chunks(map, &mut *progress);
 
         VoxelAssets {
             array_materials,
+            pending_materials,
             meshes,
         }
     }
 
+    /// Loads the array material prefabs as atlas textures. Any that fail are replaced with the
+    /// fallback material right away.
+    fn load_atlas_materials(
+        &mut self,
+        material_array_set: &HashMap<usize, String>,
+    ) -> HashMap<ArrayMaterialId, ArrayMaterialHandle> {
+        let assets_dir = application_dir("assets").expect("Failed to get assets dir.");
+        let array_materials_dir = assets_dir.join("array_materials");
+
+        let mut missing_paths = Vec::new();
+        let array_materials = material_array_set
+            .iter()
+            .map(|(array_id, mtl_array_name)| {
+                let prefab_path = array_materials_dir.join(mtl_array_name).join("prefab.ron");
+                let handle = match self.atlas_material_loader.load(&prefab_path, &assets_dir) {
+                    Ok(material) => ArrayMaterialHandle::Atlas(material),
+                    Err(paths) => {
+                        missing_paths.extend(paths);
+
+                        ArrayMaterialHandle::Fallback(
+                            self.fallback_material_loader.load_checker_material(),
+                        )
+                    }
+                };
+
+                (ArrayMaterialId(*array_id), handle)
+            })
+            .collect();
+
+        if !missing_paths.is_empty() {
+            log::error!(
+                "Failed to load atlas materials, using fallback material instead. Missing assets: \
+                {:?}",
+                missing_paths
+            );
+            self.missing_asset_events.single_write(MissingAssetsEvent {
+                asset_paths: missing_paths,
+            });
+        }
+
+        array_materials
+    }
+
+    /// Each material gets its own progress counter so the `ArrayMaterialFallbackSystem` can tell
+    /// which ones failed.
     fn start_loading_materials(
         &mut self,
         material_array_set: &HashMap<usize, String>,
-        progress: &mut ProgressCounter,
-    ) -> HashMap<ArrayMaterialId, Handle<Prefab<MaterialPrefab>>> {
+    ) -> Vec<PendingArrayMaterial> {
         let array_materials_dir =
             application_dir("assets/array_materials").expect("Failed to get array_materials dir.");
 
         material_array_set
             .iter()
             .map(|(array_id, mtl_array_name)| {
-                (
-                    ArrayMaterialId(*array_id),
-                    self.material_loader.load(
-                        array_materials_dir
-                            .join(mtl_array_name)
-                            .join("prefab.ron")
-                            .to_str()
-                            .unwrap(),
-                        RonFormat,
-                        &mut *progress,
-                    ),
-                )
+                let mut progress = ProgressCounter::new();
+                let handle = self.material_loader.load(
+                    array_materials_dir
+                        .join(mtl_array_name)
+                        .join("prefab.ron")
+                        .to_str()
+                        .unwrap(),
+                    RonFormat,
+                    &mut progress,
+                );
+
+                PendingArrayMaterial {
+                    id: ArrayMaterialId(*array_id),
+                    handle,
+                    progress,
+                }
             })
             .collect()
     }
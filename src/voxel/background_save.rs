@@ -0,0 +1,154 @@
+use crate::voxel::{
+    chunk_streaming::StoredChunks,
+    generation::GeneratedChunks,
+    map_file::{
+        decompress_snapshot, snapshot_maybe_compressed_chunks, write_voxels_file_with_compressed,
+    },
+    VoxelMap,
+};
+
+use amethyst::{core::ecs::prelude::*, shrev::EventChannel};
+use crossbeam::{channel::TryRecvError, Receiver};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::PathBuf;
+
+/// Sent by the `BackgroundSaveSystem` when a save started with `BackgroundSaves::start` finishes.
+#[derive(Clone, Debug)]
+pub struct SaveCompleted {
+    pub path: PathBuf,
+    /// The error message if the save failed.
+    pub result: Result<(), String>,
+}
+
+/// Saves the `VoxelMap` on background threads, so serializing and compressing a large map doesn't
+/// freeze the app. Only copying the chunks happens on the calling thread; chunks that are
+/// compressed in the map's cache are copied compressed, and decompressed on the background thread.
+#[derive(Default)]
+pub struct BackgroundSaves {
+    /// Each save has its own channel, so a save thread that dies without reporting is noticed when
+    /// its sender is dropped.
+    in_progress: Vec<(PathBuf, Receiver<SaveCompleted>)>,
+}
+
+impl BackgroundSaves {
+    /// Snapshots the chunks of `map`, along with the `stored` chunks that aren't loaded, and writes
+    /// them to `path` on a new thread. Pristine `generated` chunks are left out, since they're
+    /// generated again when the map is loaded. Returns false without saving if another save is still
+    /// in progress.
+    pub fn start(
+        &mut self,
+        map: &VoxelMap,
+        stored: &StoredChunks,
+        generated: &GeneratedChunks,
+        path: PathBuf,
+    ) -> bool {
+        if self.is_saving() {
+            return false;
+        }
+
+        let snapshot =
+            snapshot_maybe_compressed_chunks(map, |chunk_min| generated.is_pristine(chunk_min));
+        let (unloaded_chunks, compressed_chunks) = stored.snapshot_unloaded(map);
+        let chunk_shape = map.chunk_shape();
+        let (tx, rx) = crossbeam::channel::bounded(1);
+        let thread_path = path.clone();
+        std::thread::spawn(move || {
+            // A panic is reported like any other failure, so the save doesn't stay in progress.
+            let result = catch_unwind(AssertUnwindSafe(|| {
+                let mut chunks = decompress_snapshot(snapshot);
+                chunks.extend(unloaded_chunks);
+                write_voxels_file_with_compressed(
+                    &thread_path,
+                    chunk_shape,
+                    chunks,
+                    compressed_chunks,
+                )
+                .map_err(|e| format!("{:?}", e))
+            }))
+            .unwrap_or_else(|_| Err("The save thread panicked".to_string()));
+            // The receiver only goes away on exit.
+            let _ = tx.send(SaveCompleted {
+                path: thread_path,
+                result,
+            });
+        });
+        self.in_progress.push((path, rx));
+
+        true
+    }
+
+    pub fn is_saving(&self) -> bool {
+        !self.in_progress.is_empty()
+    }
+
+    /// Blocks until every save in progress is finished. Call this before exiting.
+    pub fn wait_for_all(&mut self) -> Vec<SaveCompleted> {
+        self.in_progress
+            .drain(..)
+            .map(|(path, rx)| rx.recv().unwrap_or_else(|_| exited_without_reporting(path)))
+            .collect()
+    }
+
+    fn try_completed(&mut self) -> Vec<SaveCompleted> {
+        let mut completed = Vec::new();
+        self.in_progress.retain(|(path, rx)| match rx.try_recv() {
+            Ok(c) => {
+                completed.push(c);
+                false
+            }
+            Err(TryRecvError::Empty) => true,
+            Err(TryRecvError::Disconnected) => {
+                completed.push(exited_without_reporting(path.clone()));
+                false
+            }
+        });
+
+        completed
+    }
+}
+
+fn exited_without_reporting(path: PathBuf) -> SaveCompleted {
+    SaveCompleted {
+        path,
+        result: Err("The save thread exited without reporting".to_string()),
+    }
+}
+
+/// Sends a `SaveCompleted` event for each background save that finished since the last frame.
+pub struct BackgroundSaveSystem;
+
+impl<'a> System<'a> for BackgroundSaveSystem {
+    type SystemData = (
+        Write<'a, BackgroundSaves>,
+        Write<'a, EventChannel<SaveCompleted>>,
+    );
+
+    fn run(&mut self, (mut saves, mut completed_events): Self::SystemData) {
+        completed_events.iter_write(saves.try_completed());
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_thread_that_never_reports_is_a_failure() {
+        let mut saves = BackgroundSaves::default();
+        let (tx, rx) = crossbeam::channel::bounded::<SaveCompleted>(1);
+        saves.in_progress.push((PathBuf::from("lost.bin"), rx));
+        drop(tx);
+
+        let completed = saves.wait_for_all();
+        assert_eq!(completed.len(), 1);
+        assert!(completed[0].result.is_err());
+        assert!(!saves.is_saving());
+    }
+}
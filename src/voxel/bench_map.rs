@@ -0,0 +1,118 @@
+//! Synthetic maps for benchmarking the meshing and editing pipeline. They're generated from a
+//! seed, so every run of a benchmark sees exactly the same voxels, and no map files need to be
+//! checked in.
+
+use crate::{
+    rendering::splatted_triplanar_pbr_pass::ArrayMaterialIndex,
+    voxel::{
+        map_generators::{generate_noise_terrain, MaterialLayer, NoiseTerrainConfig},
+        VoxelFlags, VoxelInfo, VoxelMap, VoxelPalette, VoxelType, VOXEL_CHUNK_SHAPE,
+    },
+};
+
+use building_blocks::prelude::*;
+
+#[derive(Clone, Copy, Debug)]
+pub struct BenchMapSpec {
+    pub seed: u32,
+    /// The terrain covers this many chunks along X and Z, starting at the origin.
+    pub size_chunks: [i32; 2],
+    /// The number of solid voxel types, layered from the surface down. Not counting type 0, which
+    /// is empty.
+    pub num_solid_types: u8,
+}
+
+impl Default for BenchMapSpec {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            size_chunks: [8, 8],
+            num_solid_types: 3,
+        }
+    }
+}
+
+/// Voxel type 0 is empty and the rest are solid, each with its own material.
+pub fn bench_palette(num_solid_types: u8) -> VoxelPalette {
+    let info = |i: u8| VoxelInfo {
+        flags: VoxelFlags {
+            is_empty: i == 0,
+            is_floor: i != 0,
+            is_transparent: false,
+            is_gravity_affected: false,
+        },
+        material_index: ArrayMaterialIndex(i.saturating_sub(1)),
+        gameplay: Default::default(),
+        emission: Default::default(),
+    };
+
+    VoxelPalette {
+        assets: Default::default(),
+        infos: (0..=num_solid_types).map(info).collect(),
+    }
+}
+
+/// Hilly noise terrain, a few chunks tall, with a thin layer of each voxel type so that chunks near
+/// the surface have several materials to blend.
+pub fn generate_bench_map(spec: &BenchMapSpec) -> VoxelMap {
+    let config = NoiseTerrainConfig {
+        seed: spec.seed,
+        size: [
+            spec.size_chunks[0] * VOXEL_CHUNK_SHAPE.x(),
+            spec.size_chunks[1] * VOXEL_CHUNK_SHAPE.z(),
+        ],
+        base_height: 2 * VOXEL_CHUNK_SHAPE.y(),
+        amplitude: 20.0,
+        frequency: 0.01,
+        octaves: 4,
+        lacunarity: 2.0,
+        persistence: 0.5,
+        layers: (1..=spec.num_solid_types)
+            .map(|i| MaterialLayer {
+                thickness: 3,
+                voxel_type: VoxelType(i),
+            })
+            .collect(),
+    };
+
+    let mut map = VoxelMap::new(bench_palette(spec.num_solid_types));
+    let chunk_shape = map.chunk_shape();
+    for (chunk_min, chunk) in generate_noise_terrain(&config, chunk_shape).into_iter() {
+        map.voxels.write_chunk(ChunkKey::new(0, chunk_min), chunk);
+    }
+
+    map
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::voxel::morton::morton_ordered_chunk_mins;
+
+    #[test]
+    fn test_bench_map_is_deterministic_and_in_bounds() {
+        let spec = BenchMapSpec {
+            size_chunks: [2, 3],
+            ..Default::default()
+        };
+
+        let chunk_mins = morton_ordered_chunk_mins(&generate_bench_map(&spec));
+        assert!(!chunk_mins.is_empty());
+        for chunk_min in chunk_mins.iter() {
+            assert!((0..2 * VOXEL_CHUNK_SHAPE.x()).contains(&chunk_min.x()));
+            assert!((0..3 * VOXEL_CHUNK_SHAPE.z()).contains(&chunk_min.z()));
+        }
+        assert_eq!(
+            chunk_mins,
+            morton_ordered_chunk_mins(&generate_bench_map(&spec))
+        );
+    }
+}
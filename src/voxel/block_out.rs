@@ -0,0 +1,167 @@
+use crate::voxel::{
+    double_buffer::EditedChunksBackBuffer, Voxel, VoxelChunkReader, VoxelDistance, VoxelType,
+    EMPTY_VOXEL,
+};
+
+use building_blocks::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A quick architectural block-out: a set of rectangular rooms, each with walls, a floor, an
+/// optional flat roof and any number of doorways. Usually loaded from RON.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct BlockOutSpec {
+    pub rooms: Vec<RoomSpec>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RoomSpec {
+    /// Minimum corner of the interior, relative to the block-out origin.
+    pub min: [i32; 3],
+    /// Size of the empty interior.
+    pub shape: [i32; 3],
+    pub wall_thickness: i32,
+    pub wall_voxel_type: VoxelType,
+    pub floor_voxel_type: VoxelType,
+    pub has_roof: bool,
+    #[serde(default)]
+    pub doorways: Vec<DoorwaySpec>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum WallSide {
+    NegX,
+    PosX,
+    NegZ,
+    PosZ,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DoorwaySpec {
+    pub wall: WallSide,
+    /// Distance along the wall from the interior's minimum corner to the side of the doorway.
+    pub offset: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl RoomSpec {
+    fn interior(&self, origin: Point3i) -> Extent3i {
+        Extent3i::from_min_and_shape(origin + PointN(self.min), PointN(self.shape))
+    }
+
+    fn exterior(&self, origin: Point3i) -> Extent3i {
+        let t = self.wall_thickness;
+        let interior = self.interior(origin);
+        let roof = if self.has_roof { t } else { 0 };
+
+        Extent3i::from_min_and_max(
+            interior.minimum - PointN([t, t, t]),
+            interior.max() + PointN([t, roof, t]),
+        )
+    }
+
+    /// The space carved out of the shell. Without a roof, the interior is open to the sky.
+    fn carved(&self, origin: Point3i) -> Extent3i {
+        let interior = self.interior(origin);
+        if self.has_roof {
+            interior
+        } else {
+            Extent3i::from_min_and_max(interior.minimum, interior.max() + PointN([0, 1, 0]))
+        }
+    }
+
+    fn doorway(&self, origin: Point3i, door: &DoorwaySpec) -> Extent3i {
+        let interior = self.interior(origin);
+        let t = self.wall_thickness;
+        let min = interior.minimum;
+        let max = interior.max();
+        let (door_min, door_shape) = match door.wall {
+            WallSide::NegX => (
+                PointN([min.x() - t, min.y(), min.z() + door.offset]),
+                PointN([t, door.height, door.width]),
+            ),
+            WallSide::PosX => (
+                PointN([max.x() + 1, min.y(), min.z() + door.offset]),
+                PointN([t, door.height, door.width]),
+            ),
+            WallSide::NegZ => (
+                PointN([min.x() + door.offset, min.y(), min.z() - t]),
+                PointN([door.width, door.height, t]),
+            ),
+            WallSide::PosZ => (
+                PointN([min.x() + door.offset, min.y(), max.z() + 1]),
+                PointN([door.width, door.height, t]),
+            ),
+        };
+
+        Extent3i::from_min_and_shape(door_min, door_shape)
+    }
+}
+
+/// Signed distance from `p` to the surface of the box covering all voxels in `extent`.
+pub fn extent_signed_distance(extent: &Extent3i, p: Point3i) -> f32 {
+    let min = extent.minimum;
+    let max = extent.max();
+    let mut outside_sq = 0.0;
+    let mut inside = std::f32::MIN;
+    for i in 0..3 {
+        let q = ((min.0[i] - p.0[i]).max(p.0[i] - max.0[i])) as f32 - 0.5;
+        if q > 0.0 {
+            outside_sq += q * q;
+        }
+        inside = inside.max(q);
+    }
+
+    if outside_sq > 0.0 {
+        outside_sq.sqrt()
+    } else {
+        inside
+    }
+}
+
+/// Writes all rooms in `spec` into the map with their interiors placed relative to `origin`. Walls
+/// and roofs are added on top of whatever is already there, and interiors and doorways are always
+/// cleared, so rooms can be dropped into existing terrain.
+pub fn write_block_out(
+    map_reader: &VoxelChunkReader,
+    spec: &BlockOutSpec,
+    origin: Point3i,
+    backbuffer: &mut EditedChunksBackBuffer,
+) {
+    for room in spec.rooms.iter() {
+        let exterior = room.exterior(origin);
+        let carved = room.carved(origin);
+        let doorways: Vec<Extent3i> = room
+            .doorways
+            .iter()
+            .map(|d| room.doorway(origin, d))
+            .collect();
+        let floor_top = room.interior(origin).minimum.y() - 1;
+
+        backbuffer.edit_voxels_out_of_place(
+            map_reader,
+            &exterior.padded(1),
+            |p: Point3i, v: &mut Voxel| {
+                let shell = extent_signed_distance(&exterior, p);
+                let hole = doorways
+                    .iter()
+                    .map(|d| extent_signed_distance(d, p))
+                    .fold(extent_signed_distance(&carved, p), f32::min);
+
+                let old_dist: f32 = v.distance.into();
+                let new_dist = old_dist.min(shell).max(-hole);
+                v.distance = VoxelDistance::from(new_dist);
+
+                if v.distance.0 >= 0 {
+                    v.voxel_type = EMPTY_VOXEL.voxel_type;
+                } else if shell < old_dist {
+                    v.voxel_type = if p.y() <= floor_top {
+                        room.floor_voxel_type
+                    } else {
+                        room.wall_voxel_type
+                    };
+                }
+            },
+        );
+    }
+}
@@ -1,8 +1,19 @@
 use super::{
+    background_save::BackgroundSaveSystem,
     chunk_cache_compressor::ChunkCacheCompressorSystem,
     chunk_cache_flusher::{ChunkCacheFlusher, ChunkCacheFlusherSystem, ChunkCacheReceiver},
-    chunk_processor::{MeshMode, VoxelChunkProcessorSystem},
+    chunk_cache_stats::ChunkCacheStatsSystem,
+    chunk_processor::{ChunkMeshJobs, MeshMode, MeshingConfig, VoxelChunkProcessorSystem},
+    chunk_streaming::ChunkStreamingSystem,
     double_buffer::{EditedChunksBackBuffer, VoxelDoubleBufferingSystem},
+    edit_journal::EditReplaySystem,
+    falling::FallingVoxelsSystem,
+    fluid::{FluidMeshSystem, FluidSimulationSystem},
+    generation::ChunkGenerationSystem,
+    material_fallback::ArrayMaterialFallbackSystem,
+    network::NetworkEditSystem,
+    session_recording::SessionPlaybackSystem,
+    VoxelMap,
 };
 
 use amethyst::core::{ecs::prelude::*, SystemBundle};
@@ -15,6 +26,32 @@ use building_blocks::{core::Point3i, search::OctreeDbvt};
 ///
 /// In order for edits to be considered by the pipeline of systems, they must be written to the
 /// `EditedChunksBackBuffer`. Editing the `VoxelMap` directly will not work.
+///
+/// To edit the map together with other editors, insert `Some(EditSession)`; see the `network`
+/// module.
+///
+/// If a `VoxelSource` is registered with the `EditedChunksBackBuffer`, any extents written to the
+/// `ChunkGenerationRequests` resource will be generated on demand.
+///
+/// Maps loaded with `VoxelMapFile::load_streamed_voxel_map` should also insert the returned
+/// `StoredChunks`, whose chunks are streamed in and out around the centers requested from
+/// `ChunkGenerationRequests`.
+///
+/// The size of the chunk cache can be tuned by inserting a `ChunkCacheConfig` resource, or capped in
+/// bytes with a `ChunkMemoryBudget`. The `ChunkBudgetDiagnostics` resource shows whether the cache
+/// is keeping up with the budget.
+///
+/// Voxels of gravity-affected types fall at the rate set by the `FallingVoxelsConfig`; see the
+/// `falling` module.
+///
+/// Every chunk merged into the map can be recorded by starting the `SessionRecorder` resource, and
+/// a recording is played back by inserting a `SessionPlayback`; see the `session_recording` module.
+///
+/// Fluid flows out of the points in the `FluidSources` resource at the rate set by the
+/// `FluidConfig`; see the `fluid` module.
+///
+/// Chunk meshes are generated on a background thread pool and swapped in over the following
+/// frames. The pool and the per-frame budget can be tuned by inserting a `MeshingConfig` resource.
 pub struct VoxelSystemBundle;
 
 impl<'a, 'b> SystemBundle<'a, 'b> for VoxelSystemBundle {
@@ -25,23 +62,302 @@ impl<'a, 'b> SystemBundle<'a, 'b> for VoxelSystemBundle {
     ) -> Result<(), amethyst::Error> {
         world.insert(OctreeDbvt::<Point3i>::default());
         world.insert(MeshMode::SurfaceNets);
-        world.insert(EditedChunksBackBuffer::new());
+        // The map's chunk shape can only be known if it was inserted before the bundle.
+        let backbuffer = world
+            .try_fetch::<VoxelMap>()
+            .map(|map| EditedChunksBackBuffer::with_chunk_shape(map.chunk_shape()))
+            .unwrap_or_else(EditedChunksBackBuffer::new);
+        world.insert(backbuffer);
+        let meshing_config = world
+            .entry::<MeshingConfig>()
+            .or_insert_with(Default::default)
+            .clone();
+        world.insert(ChunkMeshJobs::new(&meshing_config));
 
         // Chunk cache maintenance.
         let (tx, rx) = crossbeam::channel::unbounded();
         world.insert(ChunkCacheFlusher::new(tx));
         world.insert(ChunkCacheReceiver::new(rx));
         dispatcher.add(ChunkCacheFlusherSystem, "chunk_cache_flusher", &[]);
-        dispatcher.add(ChunkCacheCompressorSystem, "chunk_cache_compressor", &[]);
+        dispatcher.add(
+            ChunkCacheCompressorSystem::default(),
+            "chunk_cache_compressor",
+            &["chunk_cache_flusher"],
+        );
+        dispatcher.add(
+            ChunkCacheStatsSystem::default(),
+            "chunk_cache_stats",
+            &["chunk_cache_flusher", "chunk_cache_compressor"],
+        );
 
         // Voxel editing.
+        dispatcher.add(ChunkStreamingSystem, "chunk_streaming", &[]);
+        // Generation consumes the requested centers, so streaming needs to see them first.
+        dispatcher.add(
+            ChunkGenerationSystem,
+            "chunk_generation",
+            &["chunk_streaming"],
+        );
         dispatcher.add(VoxelChunkProcessorSystem, "voxel_chunk_processor", &[]);
+        dispatcher.add(EditReplaySystem, "edit_replay", &[]);
+        dispatcher.add(NetworkEditSystem, "network_edits", &[]);
+        dispatcher.add(SessionPlaybackSystem, "session_playback", &[]);
         dispatcher.add(
             VoxelDoubleBufferingSystem,
             "voxel_double_buffering",
-            &["voxel_chunk_processor"],
+            &[
+                "voxel_chunk_processor",
+                "edit_replay",
+                "network_edits",
+                "session_playback",
+                "chunk_streaming",
+            ],
+        );
+
+        // Falling voxels see the chunks dirtied by this frame's merge, and their moves are merged
+        // on the next frame.
+        dispatcher.add(
+            FallingVoxelsSystem::default(),
+            "falling_voxels",
+            &["voxel_double_buffering"],
+        );
+
+        // Fluids.
+        dispatcher.add(
+            FluidSimulationSystem::default(),
+            "fluid_simulation",
+            &["voxel_double_buffering"],
+        );
+        dispatcher.add(FluidMeshSystem, "fluid_meshing", &["fluid_simulation"]);
+
+        // Saving.
+        dispatcher.add(BackgroundSaveSystem, "background_save", &[]);
+
+        // Asset loading.
+        dispatcher.add(
+            ArrayMaterialFallbackSystem::default(),
+            "array_material_fallback",
+            &[],
         );
 
         Ok(())
     }
 }
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        test_util::{test_palette, VoxelPipelineHarness},
+        voxel::{
+            centered_extent,
+            chunk_lock::{LockedChunkEditEvent, LockedChunks},
+            double_buffer::{EditSourceId, EditedChunksBackBuffer},
+            edit_history::EditHistory,
+            edit_limits::{EditLimits, RejectedEditEvent},
+            Voxel, VoxelDistance, VoxelMap, VoxelType, EMPTY_VOXEL,
+        },
+    };
+
+    use amethyst::{core::ecs::prelude::*, shrev::EventChannel};
+    use building_blocks::prelude::*;
+
+    fn solid_ball(center: Point3i, radius: f32) -> impl Fn(Point3i, &mut Voxel) + Send + Sync {
+        move |p: Point3i, v: &mut Voxel| {
+            let d = (p - center).norm() - radius;
+            v.distance = VoxelDistance::from(d.min(v.distance.into()));
+            if v.distance.0 < 0 {
+                v.voxel_type = VoxelType(1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_edit_is_meshed_after_it_is_merged() {
+        let mut harness = VoxelPipelineHarness::new(VoxelMap::new(test_palette()));
+        let chunk_min = PointN([0; 3]);
+        let center = PointN([8; 3]);
+        harness.queue_edit(centered_extent(center, 4), solid_ball(center, 3.0));
+
+        // The double buffer merges the edit at the end of the first frame.
+        harness.step();
+        assert_eq!(harness.voxel(center).voxel_type, VoxelType(1));
+        assert!(!harness.chunk_has_mesh(chunk_min));
+
+        harness.step_until_meshed();
+        assert!(harness.chunk_has_mesh(chunk_min));
+        assert_eq!(harness.num_chunk_mesh_entities(chunk_min), 1);
+        assert!(harness.chunk_has_bvt(chunk_min));
+    }
+
+    #[test]
+    fn test_edit_on_chunk_boundary_dirties_neighbor() {
+        let mut harness = VoxelPipelineHarness::new(VoxelMap::new(test_palette()));
+        let center = PointN([16, 8, 8]);
+        harness.queue_edit(centered_extent(center, 4), solid_ball(center, 3.0));
+        harness.step();
+        harness.step_until_meshed();
+
+        for chunk_min in [PointN([0; 3]), PointN([16, 0, 0])].iter() {
+            assert!(harness.chunk_has_mesh(*chunk_min));
+            assert!(harness.chunk_has_bvt(*chunk_min));
+        }
+        assert!(!harness.chunk_has_mesh(PointN([0, 16, 0])));
+    }
+
+    #[test]
+    fn test_emptied_chunk_loses_mesh_and_bvt() {
+        let mut harness = VoxelPipelineHarness::new(VoxelMap::new(test_palette()));
+        let chunk_min = PointN([0; 3]);
+        let center = PointN([8; 3]);
+        harness.queue_edit(centered_extent(center, 4), solid_ball(center, 3.0));
+        harness.step();
+        harness.step_until_meshed();
+        assert!(harness.chunk_has_mesh(chunk_min));
+
+        harness.queue_edit(centered_extent(center, 4), |_p, v: &mut Voxel| {
+            *v = EMPTY_VOXEL
+        });
+        harness.step();
+        harness.step_until_meshed();
+        assert!(!harness.chunk_has_mesh(chunk_min));
+        assert_eq!(harness.num_chunk_mesh_entities(chunk_min), 0);
+        assert!(!harness.chunk_has_bvt(chunk_min));
+    }
+
+    #[test]
+    fn test_large_edit_is_spread_over_frames_in_one_transaction() {
+        let mut harness = VoxelPipelineHarness::new(VoxelMap::new(test_palette()));
+        harness.world.insert(EditLimits {
+            max_chunks_per_frame: 3,
+            ..Default::default()
+        });
+        // Covers 2x2x2 chunks.
+        let extent = Extent3i::from_min_and_shape(PointN([0; 3]), PointN([32; 3]));
+        harness.queue_edit(extent, |_p, v: &mut Voxel| {
+            v.distance = VoxelDistance::from(-1.0);
+            v.voxel_type = VoxelType(1);
+        });
+
+        harness.step();
+        assert_eq!(harness.voxel(PointN([0; 3])).voxel_type, VoxelType(1));
+        assert_eq!(harness.voxel(PointN([31; 3])).voxel_type, VoxelType(0));
+
+        harness.step();
+        harness.step();
+        assert_eq!(harness.voxel(PointN([31; 3])).voxel_type, VoxelType(1));
+
+        // Undoing the edit restores all of its chunks at once.
+        let mut history = harness.world.write_resource::<EditHistory>();
+        assert_eq!(history.undo().map(|chunks| chunks.len()), Some(8));
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn test_edits_to_locked_chunks_are_discarded() {
+        let mut harness = VoxelPipelineHarness::new(VoxelMap::new(test_palette()));
+        let locked_min = PointN([0; 3]);
+        harness.world.insert(LockedChunks::new(vec![locked_min]));
+        harness.step();
+        let mut locked_reader = harness
+            .world
+            .write_resource::<EventChannel<LockedChunkEditEvent>>()
+            .register_reader();
+
+        // Straddles the locked chunk and its unlocked neighbor.
+        let center = PointN([16, 8, 8]);
+        harness.queue_edit(centered_extent(center, 4), solid_ball(center, 3.0));
+        harness.step();
+
+        assert_eq!(harness.voxel(PointN([15, 8, 8])), EMPTY_VOXEL);
+        assert_eq!(harness.voxel(PointN([17, 8, 8])).voxel_type, VoxelType(1));
+        let events: Vec<LockedChunkEditEvent> = harness
+            .world
+            .read_resource::<EventChannel<LockedChunkEditEvent>>()
+            .read(&mut locked_reader)
+            .cloned()
+            .collect();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].chunk_mins, vec![locked_min]);
+    }
+
+    #[test]
+    fn test_deterministic_edits_apply_in_stamp_order() {
+        let extent = centered_extent(PointN([8; 3]), 2);
+        let set_type = |voxel_type: VoxelType| {
+            move |_p: Point3i, v: &mut Voxel| {
+                v.distance = VoxelDistance::from(-1.0);
+                v.voxel_type = voxel_type;
+            }
+        };
+
+        // Each source's edit is queued first on one of the machines.
+        let mut results = Vec::new();
+        for sources in [[0, 1], [1, 0]].iter() {
+            let mut harness = VoxelPipelineHarness::new(VoxelMap::new(test_palette()));
+            {
+                let mut backbuffer = harness.world.write_resource::<EditedChunksBackBuffer>();
+                backbuffer.set_deterministic(true);
+                for source in sources.iter() {
+                    let voxel_type = VoxelType(*source as u8 + 1);
+                    backbuffer.queue_edit(
+                        EditSourceId(*source),
+                        extent,
+                        |_| true,
+                        set_type(voxel_type),
+                    );
+                }
+            }
+            harness.step();
+            results.push(harness.voxel(PointN([8; 3])));
+        }
+
+        assert_eq!(results[0], results[1]);
+        assert_eq!(results[0].voxel_type, VoxelType(2));
+    }
+
+    #[test]
+    fn test_direct_edit_beyond_the_limits_is_rejected() {
+        let mut harness = VoxelPipelineHarness::new(VoxelMap::new(test_palette()));
+        harness.world.insert(EditLimits {
+            max_distance_from_origin: 100,
+            ..Default::default()
+        });
+        // The backbuffer picks up the limits when it's merged.
+        harness.step();
+        let mut rejected_reader = harness
+            .world
+            .write_resource::<EventChannel<RejectedEditEvent>>()
+            .register_reader();
+
+        let far = PointN([200, 0, 0]);
+        harness.world.exec(
+            |(map, mut backbuffer): (ReadExpect<VoxelMap>, WriteExpect<EditedChunksBackBuffer>)| {
+                let local_cache = LocalChunkCache3::new();
+                let reader = map.voxels.reader(&local_cache);
+                backbuffer.edit_voxels_out_of_place(
+                    &reader,
+                    &centered_extent(far, 4),
+                    solid_ball(far, 3.0),
+                );
+            },
+        );
+        harness.step();
+
+        assert_eq!(harness.voxel(far), EMPTY_VOXEL);
+        let rejected: Vec<RejectedEditEvent> = harness
+            .world
+            .read_resource::<EventChannel<RejectedEditEvent>>()
+            .read(&mut rejected_reader)
+            .cloned()
+            .collect();
+        assert_eq!(rejected.len(), 1);
+        assert!(rejected[0].stamp.is_none());
+    }
+}
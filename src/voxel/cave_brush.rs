@@ -0,0 +1,65 @@
+use crate::voxel::{
+    centered_extent, double_buffer::EditedChunksBackBuffer, Voxel, VoxelChunkReader, VoxelDistance,
+    EMPTY_VOXEL,
+};
+
+use building_blocks::prelude::*;
+use noise::{NoiseFn, OpenSimplex, Seedable};
+use serde::{Deserialize, Serialize};
+
+/// The 3D noise that the cave brush carves with.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CaveNoiseParams {
+    pub seed: u32,
+    /// In cycles per voxel. Lower frequencies make larger caverns.
+    pub frequency: f32,
+    /// Space is carved where the noise (in [-1, 1]) is above this. Higher thresholds make smaller,
+    /// sparser pockets.
+    pub threshold: f32,
+}
+
+impl Default for CaveNoiseParams {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            frequency: 0.08,
+            threshold: 0.2,
+        }
+    }
+}
+
+/// Carves out the parts of the sphere of `radius` around `center` where the noise is above the
+/// threshold. The noise is sampled in world space, so overlapping strokes extend the same network
+/// of caves, and applying a stroke again doesn't change anything.
+pub fn carve_caves(
+    map_reader: &VoxelChunkReader,
+    center: Point3i,
+    radius: u32,
+    params: &CaveNoiseParams,
+    backbuffer: &mut EditedChunksBackBuffer,
+) {
+    let noise = OpenSimplex::new().set_seed(params.seed);
+    let fradius = radius as f32;
+    backbuffer.edit_voxels_out_of_place(
+        map_reader,
+        &centered_extent(center, radius),
+        |p: Point3i, v: &mut Voxel| {
+            let sample = noise.get([
+                (p.x() as f32 * params.frequency) as f64,
+                (p.y() as f32 * params.frequency) as f64,
+                (p.z() as f32 * params.frequency) as f64,
+            ]) as f32;
+            // The noise changes by roughly one unit per wavelength, so this approximates the
+            // distance to the cave walls in voxels.
+            let cave_dist = (params.threshold - sample) / params.frequency;
+            let sphere_dist = (p - center).norm() - fradius;
+            let carve_dist = cave_dist.max(sphere_dist);
+
+            let old_dist: f32 = v.distance.into();
+            v.distance = VoxelDistance::from(old_dist.max(-carve_dist));
+            if v.distance.0 >= 0 {
+                v.voxel_type = EMPTY_VOXEL.voxel_type;
+            }
+        },
+    );
+}
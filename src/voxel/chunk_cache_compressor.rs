@@ -1,33 +1,150 @@
-use crate::voxel::VoxelMap;
+use crate::voxel::{
+    chunk_cache_stats::{chunk_bytes, ChunkCacheStats},
+    chunk_compression::{install_compressed_chunk, ChunkCompressionThread},
+    VoxelMap,
+};
 
 use amethyst::core::ecs::prelude::*;
+use building_blocks::core::Point3i;
+use serde::{Deserialize, Serialize};
 
-/// A system that evicts and compresses the least recently used voxel chunks when the cache gets too
-/// big.
-#[derive(Default)]
-pub struct ChunkCacheCompressorSystem;
+/// Limits on the decompressed chunks kept in the `VoxelMap`'s cache.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ChunkCacheConfig {
+    /// The `ChunkCacheCompressorSystem` compresses the least recently used chunks beyond this
+    /// count.
+    pub max_cached_chunks: usize,
+    /// Avoids high latency from copying too many chunks for compression in one frame.
+    pub max_compressed_per_frame: usize,
+    /// The most chunks waiting for the compression thread at once.
+    pub compression_queue_capacity: usize,
+    /// The most chunks that the `ChunkCacheFlusherSystem` will let local caches thaw into the
+    /// central cache in one frame. Beyond this, the least recently used chunks are compressed
+    /// right away, which bounds the memory spike from a big brush stroke.
+    pub max_thawed_per_frame: usize,
+}
 
-// These constants should be correlated with the size of a chunk, which is currently 16^3 * 2 bytes.
+// These defaults should be correlated with the size of a chunk, which is currently 16^3 * 2 bytes.
 
-/// We'll reserve a little under a gigabyte for the cache.
-const MAX_CACHED_CHUNKS: usize = 1000000;
-// Avoid high latency from compressing too many chunks in one frame. 8192-byte chunk compression
-// latency is around 0.1 ms.
-const MAX_COMPRESSED_PER_FRAME_PER_CORE: usize = 50;
+impl Default for ChunkCacheConfig {
+    fn default() -> Self {
+        Self {
+            // We'll reserve a little under a gigabyte for the cache.
+            max_cached_chunks: 1000000,
+            // 8192-byte chunk compression latency is around 0.1 ms.
+            max_compressed_per_frame: 50,
+            compression_queue_capacity: 256,
+            max_thawed_per_frame: 4096,
+        }
+    }
+}
+
+/// A memory budget for the decompressed chunks in the `VoxelMap`'s cache. It's a tighter limit
+/// than `ChunkCacheConfig::max_cached_chunks` for apps that would rather think in bytes.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ChunkMemoryBudget {
+    pub max_resident_bytes: usize,
+}
+
+impl Default for ChunkMemoryBudget {
+    fn default() -> Self {
+        Self {
+            max_resident_bytes: 1 << 30,
+        }
+    }
+}
+
+impl ChunkMemoryBudget {
+    pub fn max_resident_chunks(&self, chunk_shape: Point3i) -> usize {
+        self.max_resident_bytes / chunk_bytes(chunk_shape)
+    }
+}
+
+/// How the cache is doing against the `ChunkMemoryBudget`, updated by the
+/// `ChunkCacheCompressorSystem` every frame.
+#[derive(Clone, Debug, Default)]
+pub struct ChunkBudgetDiagnostics {
+    /// The most chunks the cache may hold, from the budget and the `ChunkCacheConfig`.
+    pub max_resident_chunks: usize,
+    /// Chunks over the limit at the end of this frame. Some of them may already be on the
+    /// compression thread.
+    pub chunks_over_budget: usize,
+    /// Chunks waiting on the compression thread.
+    pub chunks_in_flight: usize,
+    /// Consecutive frames that ended over the limit. If this keeps growing, the compression rate
+    /// can't keep up with the edits.
+    pub frames_over_budget: u32,
+    /// Chunks compressed to stay under the limit since the app started.
+    pub total_compressed: u64,
+}
+
+/// A system that compresses the least recently used voxel chunks when the cache gets too big. The
+/// compression itself is done by a `ChunkCompressionThread`, using the `VoxelMap`'s codec, and the
+/// compressed chunks are swapped in on later frames.
+#[derive(Default)]
+pub struct ChunkCacheCompressorSystem {
+    thread: Option<ChunkCompressionThread>,
+}
 
 impl<'a> System<'a> for ChunkCacheCompressorSystem {
-    type SystemData = WriteExpect<'a, VoxelMap>;
-
-    fn run(&mut self, mut voxel_map: Self::SystemData) {
-        // PERF: compression could happen in parallel, but we'd need to add some CompressibleMap
-        // APIs
-
-        let overgrowth = voxel_map.voxels.storage().len_cached() as i64 - MAX_CACHED_CHUNKS as i64;
-        for _ in 0..overgrowth
-            .max(0)
-            .min(MAX_COMPRESSED_PER_FRAME_PER_CORE as i64)
-        {
-            voxel_map.voxels.storage_mut().compress_lru();
+    type SystemData = (
+        Read<'a, ChunkCacheConfig>,
+        Read<'a, ChunkMemoryBudget>,
+        WriteExpect<'a, VoxelMap>,
+        Write<'a, ChunkCacheStats>,
+        Write<'a, ChunkBudgetDiagnostics>,
+    );
+
+    fn run(
+        &mut self,
+        (config, budget, mut voxel_map, mut stats, mut diagnostics): Self::SystemData,
+    ) {
+        let codec = voxel_map.codec;
+        let thread = self.thread.get_or_insert_with(|| {
+            ChunkCompressionThread::spawn(codec, config.compression_queue_capacity)
+        });
+
+        let mut num_compressed = 0;
+        while let Some(chunk) = thread.try_recv() {
+            if install_compressed_chunk(&mut voxel_map.voxels, chunk) {
+                num_compressed += 1;
+            }
+        }
+        stats.compressed_this_frame += num_compressed;
+
+        let max_resident_chunks = config
+            .max_cached_chunks
+            .min(budget.max_resident_chunks(voxel_map.chunk_shape()));
+        let overgrowth = voxel_map
+            .voxels
+            .storage()
+            .len_cached()
+            .saturating_sub(max_resident_chunks);
+        // The chunks that are already on the thread will bring the cache down when they're back.
+        let num_to_send = overgrowth
+            .saturating_sub(thread.in_flight())
+            .min(config.max_compressed_per_frame);
+        for _ in 0..num_to_send {
+            let (key, chunk) = match voxel_map.voxels.storage_mut().remove_lru() {
+                Some(lru) => lru,
+                None => break,
+            };
+            // Putting the chunk back moves it to the end of the LRU order, so the next iteration
+            // gets a different chunk. It stays readable until its compressed copy is swapped in.
+            voxel_map.voxels.write_chunk(key, chunk.clone());
+            if !thread.try_send(key, chunk) {
+                break;
+            }
+        }
+
+        diagnostics.max_resident_chunks = max_resident_chunks;
+        diagnostics.chunks_over_budget = overgrowth;
+        diagnostics.chunks_in_flight = thread.in_flight();
+        if overgrowth > 0 {
+            diagnostics.frames_over_budget += 1;
+        } else {
+            diagnostics.frames_over_budget = 0;
         }
+        diagnostics.total_compressed += num_compressed as u64;
     }
 }
@@ -1,4 +1,7 @@
-use crate::voxel::{LocalVoxelCache, VoxelMap};
+use crate::voxel::{
+    chunk_cache_compressor::ChunkCacheConfig, chunk_cache_stats::ChunkCacheStats, LocalVoxelCache,
+    VoxelMap,
+};
 
 use amethyst::core::ecs::prelude::*;
 use crossbeam::{Receiver, Sender};
@@ -36,19 +39,32 @@ impl ChunkCacheReceiver {
 // Right now this is just unlikely because of the size of the cache and rate of compression
 
 /// A system that flushes system-local `LocalVoxelCache`s. Just send your cache using the
-/// `ChunkCacheFlusher`.
+/// `ChunkCacheFlusher`. If more than `ChunkCacheConfig::max_thawed_per_frame` chunks are flushed in
+/// one frame, the excess is compressed again, least recently used first.
 #[derive(Default)]
 pub struct ChunkCacheFlusherSystem;
 
 impl<'a> System<'a> for ChunkCacheFlusherSystem {
     type SystemData = (
+        Read<'a, ChunkCacheConfig>,
         ReadExpect<'a, ChunkCacheReceiver>,
         WriteExpect<'a, VoxelMap>,
+        Write<'a, ChunkCacheStats>,
     );
 
-    fn run(&mut self, (cache_rx, mut voxel_map): Self::SystemData) {
+    fn run(&mut self, (config, cache_rx, mut voxel_map, mut stats): Self::SystemData) {
+        stats.decompressed_this_frame = 0;
         for cache in cache_rx.rx.try_iter() {
+            stats.decompressed_this_frame += cache.len();
             voxel_map.voxels.storage_mut().flush_local_cache(cache);
         }
+
+        let excess = stats
+            .decompressed_this_frame
+            .saturating_sub(config.max_thawed_per_frame);
+        for _ in 0..excess {
+            voxel_map.voxels.storage_mut().compress_lru();
+        }
+        stats.compressed_this_frame = excess;
     }
 }
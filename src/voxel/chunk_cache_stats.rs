@@ -0,0 +1,68 @@
+use crate::voxel::{Voxel, VoxelMap};
+
+use amethyst::core::ecs::prelude::*;
+use building_blocks::{prelude::*, storage::MaybeCompressed};
+
+/// A snapshot of the `VoxelMap`'s chunk cache, refreshed every frame by the
+/// `ChunkCacheStatsSystem`. Useful for tuning the cache size and compression rate.
+#[derive(Clone, Debug, Default)]
+pub struct ChunkCacheStats {
+    /// Chunks held decompressed in the cache.
+    pub resident_chunks: usize,
+    pub compressed_chunks: usize,
+    pub resident_bytes: usize,
+    /// Only measured every `COMPRESSED_BYTES_INTERVAL` frames, since it visits every compressed
+    /// chunk.
+    pub compressed_bytes: usize,
+    /// Chunks compressed out of the cache by the `ChunkCacheFlusherSystem` and
+    /// `ChunkCacheCompressorSystem` this frame.
+    pub compressed_this_frame: usize,
+    /// Chunks decompressed into local caches and flushed back by the `ChunkCacheFlusherSystem` this
+    /// frame.
+    pub decompressed_this_frame: usize,
+    /// Log the stats at debug level every this many frames.
+    pub log_interval: Option<u32>,
+}
+
+pub fn chunk_bytes(chunk_shape: Point3i) -> usize {
+    chunk_shape.volume() as usize * std::mem::size_of::<Voxel>()
+}
+
+const COMPRESSED_BYTES_INTERVAL: u32 = 60;
+
+/// Fills in the `ChunkCacheStats` after the cache maintenance systems have run.
+#[derive(Default)]
+pub struct ChunkCacheStatsSystem {
+    frame: u32,
+}
+
+impl<'a> System<'a> for ChunkCacheStatsSystem {
+    type SystemData = (ReadExpect<'a, VoxelMap>, Write<'a, ChunkCacheStats>);
+
+    fn run(&mut self, (voxel_map, mut stats): Self::SystemData) {
+        let storage = voxel_map.voxels.storage();
+        stats.resident_chunks = storage.len_cached();
+        stats.compressed_chunks = storage.len_compressed();
+        stats.resident_bytes = stats.resident_chunks * chunk_bytes(voxel_map.chunk_shape());
+
+        if self.frame % COMPRESSED_BYTES_INTERVAL == 0 {
+            stats.compressed_bytes = storage
+                .iter_maybe_compressed()
+                .map(|(_, chunk)| match chunk {
+                    MaybeCompressed::Compressed(compressed_chunk) => {
+                        compressed_chunk.compressed_bytes.len()
+                    }
+                    _ => 0,
+                })
+                .sum();
+        }
+
+        if let Some(interval) = stats.log_interval {
+            if self.frame % interval.max(1) == 0 {
+                log::debug!("{:?}", *stats);
+            }
+        }
+
+        self.frame = self.frame.wrapping_add(1);
+    }
+}
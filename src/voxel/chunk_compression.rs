@@ -0,0 +1,228 @@
+//! How chunks are compressed in the `VoxelMap`'s cache, and a thread that does the compression so
+//! it doesn't hold up the main dispatcher.
+
+use crate::voxel::{Voxel, VoxelChunkMap};
+
+use building_blocks::{
+    prelude::*,
+    storage::{
+        BytesCompression, Compressed, Compression, FastArrayCompressionNx1, MaybeCompressed,
+    },
+};
+use crossbeam::channel::{self, Receiver, Sender, TrySendError};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+
+/// The compression used for chunks that leave the cache. This only affects memory use and how long
+/// it takes to compress and decompress chunks, not the voxels files.
+///
+/// Every compressed chunk starts with a byte naming its codec, so chunks compressed with different
+/// codecs can live in the same map.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ChunkCodec {
+    /// Smaller, but slower to compress at higher levels.
+    Lz4 { level: u32 },
+    /// Faster to compress, but larger.
+    Snappy,
+}
+
+impl Default for ChunkCodec {
+    fn default() -> Self {
+        ChunkCodec::Lz4 { level: 10 }
+    }
+}
+
+const LZ4_TAG: u8 = 0;
+const SNAPPY_TAG: u8 = 1;
+
+impl ChunkCodec {
+    fn try_compress_bytes(
+        &self,
+        mut bytes: impl Read,
+        mut compressed_bytes: impl Write,
+    ) -> io::Result<()> {
+        match *self {
+            ChunkCodec::Lz4 { level } => {
+                compressed_bytes.write_all(&[LZ4_TAG])?;
+                let mut encoder = lz4::EncoderBuilder::new()
+                    .level(level)
+                    .build(compressed_bytes)?;
+                io::copy(&mut bytes, &mut encoder)?;
+                let (_, result) = encoder.finish();
+
+                result
+            }
+            ChunkCodec::Snappy => {
+                compressed_bytes.write_all(&[SNAPPY_TAG])?;
+                let mut encoder = snap::write::FrameEncoder::new(compressed_bytes);
+                io::copy(&mut bytes, &mut encoder)?;
+
+                encoder.flush()
+            }
+        }
+    }
+
+    fn try_decompress_bytes(
+        mut compressed_bytes: impl Read,
+        bytes: &mut impl Write,
+    ) -> io::Result<()> {
+        let mut tag = [0];
+        compressed_bytes.read_exact(&mut tag)?;
+        match tag[0] {
+            LZ4_TAG => {
+                io::copy(&mut lz4::Decoder::new(compressed_bytes)?, bytes)?;
+            }
+            SNAPPY_TAG => {
+                io::copy(&mut snap::read::FrameDecoder::new(compressed_bytes), bytes)?;
+            }
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unknown chunk codec tag {}", other),
+                ))
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl BytesCompression for ChunkCodec {
+    fn compress_bytes(&self, bytes: impl Read, compressed_bytes: impl Write) {
+        // Like the building-blocks codecs, this only fails on a broken in-memory buffer.
+        self.try_compress_bytes(bytes, compressed_bytes)
+            .expect("Failed to compress chunk");
+    }
+
+    fn decompress_bytes(compressed_bytes: impl Read, bytes: &mut impl Write) {
+        Self::try_decompress_bytes(compressed_bytes, bytes).expect("Failed to decompress chunk");
+    }
+}
+
+pub type ChunkCompression = FastArrayCompressionNx1<[i32; 3], ChunkCodec, Voxel>;
+
+/// A chunk as it's stored in the `VoxelMap`, which may or may not be compressed.
+pub type MaybeCompressedChunk = MaybeCompressed<Array3x1<Voxel>, Compressed<ChunkCompression>>;
+
+/// A chunk compressed on the `ChunkCompressionThread`, with the voxels it was compressed from.
+pub struct CompressedChunk {
+    pub key: ChunkKey<[i32; 3]>,
+    pub original: Array3x1<Voxel>,
+    pub compressed: Compressed<ChunkCompression>,
+}
+
+/// A dedicated thread that compresses copies of chunks. The job queue is bounded, so a burst of
+/// evictions can't pile up uncompressed copies faster than the thread can get through them.
+pub struct ChunkCompressionThread {
+    tx: Sender<(ChunkKey<[i32; 3]>, Array3x1<Voxel>)>,
+    rx: Receiver<CompressedChunk>,
+    in_flight: usize,
+}
+
+impl ChunkCompressionThread {
+    pub fn spawn(codec: ChunkCodec, queue_capacity: usize) -> Self {
+        let (tx, job_rx) =
+            channel::bounded::<(ChunkKey<[i32; 3]>, Array3x1<Voxel>)>(queue_capacity);
+        let (result_tx, rx) = channel::unbounded();
+        std::thread::Builder::new()
+            .name("chunk_compression".to_string())
+            .spawn(move || {
+                let compression = ChunkCompression::from_bytes_compression(codec);
+                // Exits when the sender is dropped.
+                for (key, original) in job_rx.iter() {
+                    let compressed = compression.compress(&original);
+                    let result = CompressedChunk {
+                        key,
+                        original,
+                        compressed,
+                    };
+                    if result_tx.send(result).is_err() {
+                        break;
+                    }
+                }
+            })
+            .expect("Failed to spawn chunk compression thread");
+
+        Self {
+            tx,
+            rx,
+            in_flight: 0,
+        }
+    }
+
+    /// Chunks sent to the thread that haven't been received back yet.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    /// Queues a copy of `chunk` for compression. Returns false if the queue is full.
+    pub fn try_send(&mut self, key: ChunkKey<[i32; 3]>, chunk: Array3x1<Voxel>) -> bool {
+        match self.tx.try_send((key, chunk)) {
+            Ok(()) => {
+                self.in_flight += 1;
+
+                true
+            }
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+
+    pub fn try_recv(&mut self) -> Option<CompressedChunk> {
+        let result = self.rx.try_recv().ok()?;
+        self.in_flight -= 1;
+
+        Some(result)
+    }
+}
+
+/// Replaces the cached chunk with its compressed copy, unless the chunk was changed after the copy
+/// was made. Returns true if the chunk was replaced.
+pub fn install_compressed_chunk(voxels: &mut VoxelChunkMap, chunk: CompressedChunk) -> bool {
+    let CompressedChunk {
+        key,
+        original,
+        compressed,
+    } = chunk;
+
+    let unchanged = {
+        let local_cache = LocalChunkCache3::new();
+        let reader = voxels.reader(&local_cache);
+        let view = reader.lod_view(0);
+        original
+            .extent()
+            .iter_points()
+            .all(|p| view.get(p) == original.get(p))
+    };
+    if unchanged {
+        voxels.storage_mut().insert_compressed(key, compressed);
+    }
+
+    unchanged
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codecs_round_trip() {
+        let bytes: Vec<u8> = (0..8192).map(|i| (i % 7) as u8).collect();
+        for codec in [ChunkCodec::Lz4 { level: 4 }, ChunkCodec::Snappy].iter() {
+            let mut compressed = Vec::new();
+            codec.compress_bytes(bytes.as_slice(), &mut compressed);
+            assert!(compressed.len() < bytes.len());
+
+            // The tag picks the decoder, whichever codec the map is configured with.
+            let mut decompressed = Vec::new();
+            ChunkCodec::decompress_bytes(compressed.as_slice(), &mut decompressed);
+            assert_eq!(decompressed, bytes);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use building_blocks::prelude::*;
+use std::collections::HashSet;
+
+/// Chunks that are protected from edits, e.g. because that part of the map is finished. The
+/// `VoxelDoubleBufferingSystem` discards any edits to locked chunks and sends a
+/// `LockedChunkEditEvent` instead.
+#[derive(Debug, Default)]
+pub struct LockedChunks {
+    chunks: HashSet<Point3i>,
+    /// Set whenever the locks change, so they can be saved with the map.
+    changed: bool,
+}
+
+impl LockedChunks {
+    pub fn new(chunks: impl IntoIterator<Item = Point3i>) -> Self {
+        Self {
+            chunks: chunks.into_iter().collect(),
+            changed: false,
+        }
+    }
+
+    pub fn is_locked(&self, chunk_min: &Point3i) -> bool {
+        self.chunks.contains(chunk_min)
+    }
+
+    /// Returns whether the chunk is locked after toggling.
+    pub fn toggle(&mut self, chunk_min: Point3i) -> bool {
+        self.changed = true;
+        if self.chunks.remove(&chunk_min) {
+            false
+        } else {
+            self.chunks.insert(chunk_min);
+
+            true
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Point3i> {
+        self.chunks.iter()
+    }
+
+    pub fn has_changed(&self) -> bool {
+        self.changed
+    }
+}
+
+/// Sent when edits to locked chunks were discarded.
+#[derive(Clone, Debug)]
+pub struct LockedChunkEditEvent {
+    pub chunk_mins: Vec<Point3i>,
+}
+
+/// The minimum of the chunk of shape `chunk_shape` that contains `p`.
+pub fn chunk_min_containing_point(p: Point3i, chunk_shape: Point3i) -> Point3i {
+    let s = chunk_shape;
+
+    PointN([
+        p.x().div_euclid(s.x()) * s.x(),
+        p.y().div_euclid(s.y()) * s.y(),
+        p.z().div_euclid(s.z()) * s.z(),
+    ])
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_toggle_locks_and_unlocks() {
+        let chunk_min = PointN([16, 0, -16]);
+        let mut locks = LockedChunks::new(vec![PointN([0; 3])]);
+        assert!(!locks.has_changed());
+
+        assert!(locks.toggle(chunk_min));
+        assert!(locks.is_locked(&chunk_min));
+        assert!(locks.is_locked(&PointN([0; 3])));
+        assert!(locks.has_changed());
+
+        assert!(!locks.toggle(chunk_min));
+        assert!(!locks.is_locked(&chunk_min));
+        assert_eq!(locks.iter().count(), 1);
+    }
+
+    #[test]
+    fn test_chunk_min_containing_negative_point() {
+        let shape = PointN([16, 8, 16]);
+
+        assert_eq!(
+            chunk_min_containing_point(PointN([-1, 8, 15]), shape),
+            PointN([-16, 8, 0])
+        );
+        assert_eq!(
+            chunk_min_containing_point(PointN([-16, -9, 16]), shape),
+            PointN([-16, -16, 16])
+        );
+    }
+}
@@ -0,0 +1,191 @@
+//! Keeps the `OctreeSet` of each chunk up to date without rebuilding it after every edit. Most
+//! edits either don't change which voxels are empty at all (painting, smoothing, or the neighbors
+//! of an edited chunk that were only marked dirty for meshing), or only change a few of them.
+
+use crate::voxel::{Voxel, VoxelPalette};
+
+use building_blocks::{prelude::*, storage::OctreeSet};
+use std::sync::Arc;
+
+/// Which voxels of a chunk are non-empty, one bit per voxel in `Extent3i::iter_points` order.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ChunkOccupancy {
+    extent: Extent3i,
+    bits: Vec<u64>,
+}
+
+impl ChunkOccupancy {
+    pub fn from_chunk(chunk: &Array3x1<Voxel>, palette: &VoxelPalette) -> Self {
+        let extent = *chunk.extent();
+        let mut bits = vec![0; (extent.num_points() + 63) / 64];
+        for (i, p) in extent.iter_points().enumerate() {
+            if !palette
+                .get_voxel_type_info(chunk.get(p).voxel_type)
+                .flags
+                .is_empty
+            {
+                bits[i / 64] |= 1 << (i % 64);
+            }
+        }
+
+        Self { extent, bits }
+    }
+
+    /// The points that are occupied in `other` but not in `self` (`true`), or the other way around
+    /// (`false`). Returns `None` if there are more than `max_changes` of them, or the extents
+    /// differ.
+    pub fn changes(&self, other: &Self, max_changes: usize) -> Option<Vec<(Point3i, bool)>> {
+        if self.extent != other.extent {
+            return None;
+        }
+
+        let num_changes: u32 = self
+            .bits
+            .iter()
+            .zip(other.bits.iter())
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum();
+        if num_changes as usize > max_changes {
+            return None;
+        }
+        if num_changes == 0 {
+            return Some(Vec::new());
+        }
+
+        let mut changes = Vec::with_capacity(num_changes as usize);
+        for (i, p) in self.extent.iter_points().enumerate() {
+            let mask = 1 << (i % 64);
+            let (was, is) = (self.bits[i / 64] & mask, other.bits[i / 64] & mask);
+            if was != is {
+                changes.push((p, is != 0));
+            }
+        }
+
+        Some(changes)
+    }
+}
+
+/// The octree of a chunk, along with the occupancy it was built from.
+pub struct ChunkOctree {
+    pub occupancy: ChunkOccupancy,
+    pub octree: OctreeSet,
+}
+
+impl ChunkOctree {
+    pub fn build(chunk: &Array3x1<Voxel>, palette: &VoxelPalette) -> Self {
+        let is_empty_map =
+            TransformMap::new(chunk, |v: Voxel| palette.get_voxel_type_info(v.voxel_type));
+
+        Self {
+            occupancy: ChunkOccupancy::from_chunk(chunk, palette),
+            octree: OctreeSet::from_array3(&is_empty_map, *chunk.extent()),
+        }
+    }
+}
+
+pub enum OctreeUpdate {
+    /// The same voxels are occupied as before, so the old octree is still correct.
+    Unchanged,
+    /// The old octree with the changed voxels added or removed.
+    Patched(Arc<ChunkOctree>),
+    Rebuilt(Arc<ChunkOctree>),
+    /// The chunk no longer exists.
+    Removed,
+}
+
+/// Works out the new octree for `chunk`, given the octree of the `previous` version of the chunk.
+/// The previous octree is patched if at most `max_patched_voxels` voxels changed occupancy, and
+/// otherwise a new one is built from scratch.
+pub fn update_chunk_octree(
+    previous: Option<&ChunkOctree>,
+    chunk: Option<&Array3x1<Voxel>>,
+    palette: &VoxelPalette,
+    max_patched_voxels: usize,
+) -> OctreeUpdate {
+    let chunk = match chunk {
+        Some(c) => c,
+        None => return OctreeUpdate::Removed,
+    };
+    let previous = match previous {
+        Some(p) => p,
+        None => return OctreeUpdate::Rebuilt(Arc::new(ChunkOctree::build(chunk, palette))),
+    };
+
+    let occupancy = ChunkOccupancy::from_chunk(chunk, palette);
+    let changes = match previous.occupancy.changes(&occupancy, max_patched_voxels) {
+        Some(changes) => changes,
+        None => return OctreeUpdate::Rebuilt(Arc::new(ChunkOctree::build(chunk, palette))),
+    };
+    if changes.is_empty() {
+        return OctreeUpdate::Unchanged;
+    }
+
+    let mut octree = previous.octree.clone();
+    for (p, is_occupied) in changes.into_iter() {
+        let voxel = Extent3i::from_min_and_shape(p, PointN([1; 3]));
+        if is_occupied {
+            octree.add_extent(&voxel);
+        } else {
+            octree.subtract_extent(&voxel);
+        }
+    }
+
+    OctreeUpdate::Patched(Arc::new(ChunkOctree { occupancy, octree }))
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        test_util::test_palette,
+        voxel::{empty_array, VoxelDistance, VoxelType, VOXEL_CHUNK_SHAPE},
+    };
+
+    fn set_solid(chunk: &mut Array3x1<Voxel>, p: Point3i) {
+        *chunk.get_mut(p) = Voxel {
+            voxel_type: VoxelType(1),
+            distance: VoxelDistance::from(-1.0),
+        };
+    }
+
+    #[test]
+    fn test_small_changes_patch_and_large_changes_rebuild() {
+        let palette = test_palette();
+        let extent = Extent3i::from_min_and_shape(PointN([0; 3]), VOXEL_CHUNK_SHAPE);
+        let mut chunk = empty_array(extent);
+        set_solid(&mut chunk, PointN([1, 1, 1]));
+        let previous = ChunkOctree::build(&chunk, &palette);
+
+        // Only the distance changed.
+        chunk.get_mut(PointN([1, 1, 1])).distance = VoxelDistance::from(-2.0);
+        assert!(matches!(
+            update_chunk_octree(Some(&previous), Some(&chunk), &palette, 4),
+            OctreeUpdate::Unchanged
+        ));
+
+        set_solid(&mut chunk, PointN([2, 1, 1]));
+        match update_chunk_octree(Some(&previous), Some(&chunk), &palette, 4) {
+            OctreeUpdate::Patched(patched) => {
+                let rebuilt = ChunkOctree::build(&chunk, &palette);
+                assert_eq!(patched.occupancy, rebuilt.occupancy);
+            }
+            _ => panic!("Expected a patch"),
+        }
+
+        for x in 3..10 {
+            set_solid(&mut chunk, PointN([x, 1, 1]));
+        }
+        assert!(matches!(
+            update_chunk_octree(Some(&previous), Some(&chunk), &palette, 4),
+            OctreeUpdate::Rebuilt(_)
+        ));
+    }
+}
@@ -1,28 +1,236 @@
 use crate::{
     assets::IndexedPosColorNormVertices,
+    collision::chunk_collider::{chunk_collider, ChunkCollider, ChunkColliders},
+    rendering::chunk_culling::{is_occluder_chunk, OccluderChunks},
     voxel::{
         chunk_cache_flusher::ChunkCacheFlusher,
+        chunk_octree::{update_chunk_octree, ChunkOctree, OctreeUpdate},
         double_buffer::DirtyChunks,
         meshing::{
-            generate_mesh_vertices_with_greedy_quads, generate_mesh_vertices_with_surface_nets,
-            loader::VoxelMeshLoader, manager::VoxelMeshManager,
+            buffer_pool::recycle_voxel_array, copy_mesh_voxels, greedy_quads_vertices,
+            loader::VoxelMeshLoader, manager::VoxelMeshManager, surface_nets_vertices, MeshLayer,
         },
-        VoxelAssets, VoxelMap,
+        morton::sort_chunk_mins_morton,
+        voxel_to_world_space, Voxel, VoxelAssets, VoxelMap, VoxelPalette,
     },
 };
 
-use amethyst::{assets::ProgressCounter, core::ecs::prelude::*};
-use building_blocks::{prelude::*, search::OctreeDbvt, storage::OctreeSet};
+use amethyst::{
+    assets::ProgressCounter,
+    core::{
+        ecs::prelude::*,
+        math::{Point3, Vector3},
+        Transform,
+    },
+    renderer::camera::{ActiveCamera, Camera},
+};
+use building_blocks::{
+    mesh::{padded_greedy_quads_chunk_extent, padded_surface_nets_chunk_extent},
+    prelude::*,
+    search::OctreeDbvt,
+};
+use crossbeam::channel::{Receiver, Sender};
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 #[cfg(feature = "profiler")]
 use thread_profiler::profile_scope;
 
+#[derive(Clone, Copy)]
 pub enum MeshMode {
     SurfaceNets,
     GreedyQuads,
 }
 
+/// Controls the background meshing done for the `VoxelChunkProcessorSystem`. Insert it before
+/// adding the `VoxelSystemBundle`, since the thread pool is created by the bundle.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MeshingConfig {
+    /// Threads in the meshing pool. 0 means one per CPU.
+    pub num_threads: usize,
+    /// The most finished meshes to upload and swap in per frame. The rest wait for later frames,
+    /// so a huge stroke is spread over several frames instead of causing a hitch.
+    pub max_meshes_per_frame: usize,
+    /// A chunk's octree is patched if at most this many of its voxels became empty or non-empty,
+    /// and rebuilt otherwise.
+    #[serde(default = "default_max_patched_voxels")]
+    pub max_patched_voxels: usize,
+    /// Roughly how many milliseconds per frame to spend copying dirty chunks out of the map for
+    /// meshing. Chunks closest to the camera are started first, and the rest wait for later
+    /// frames. At least one batch of chunks is started every frame, however small the budget.
+    #[serde(default = "default_frame_budget_ms")]
+    pub frame_budget_ms: f32,
+}
+
+fn default_max_patched_voxels() -> usize {
+    64
+}
+
+fn default_frame_budget_ms() -> f32 {
+    4.0
+}
+
+impl MeshingConfig {
+    fn frame_budget(&self) -> Duration {
+        Duration::from_secs_f32(self.frame_budget_ms.max(0.0) / 1000.0)
+    }
+}
+
+impl Default for MeshingConfig {
+    fn default() -> Self {
+        Self {
+            num_threads: 0,
+            max_meshes_per_frame: 64,
+            max_patched_voxels: default_max_patched_voxels(),
+            frame_budget_ms: default_frame_budget_ms(),
+        }
+    }
+}
+
+struct MeshJobResult {
+    chunk_min: Point3i,
+    version: u64,
+    octree: OctreeUpdate,
+    /// Whether the chunk is completely solid and opaque.
+    is_occluder: bool,
+    /// Only built when `ChunkColliders::enabled` is set.
+    collider: Option<ChunkCollider>,
+    vertices: Option<IndexedPosColorNormVertices>,
+    transparent_vertices: Option<IndexedPosColorNormVertices>,
+}
+
+/// Meshes dirty chunks on a background thread pool. Jobs work on copies of the voxels, so the map
+/// can keep changing while they run; results that were superseded by a newer job for the same
+/// chunk are dropped.
+pub struct ChunkMeshJobs {
+    pool: rayon::ThreadPool,
+    tx: Sender<MeshJobResult>,
+    rx: Receiver<MeshJobResult>,
+    /// Dirty chunks that are waiting for a job to be started.
+    pending: HashSet<Point3i>,
+    /// The version of the newest job for each chunk that hasn't been drained yet.
+    latest_versions: HashMap<Point3i, u64>,
+    next_version: u64,
+    /// The octree currently in the `OctreeDbvt` for each chunk, which new jobs patch if they can.
+    octrees: HashMap<Point3i, Arc<ChunkOctree>>,
+    max_patched_voxels: usize,
+}
+
+impl ChunkMeshJobs {
+    pub fn new(config: &MeshingConfig) -> Self {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(config.num_threads)
+            .thread_name(|i| format!("chunk_meshing_{}", i))
+            .build()
+            .expect("Failed to build the meshing thread pool");
+        let (tx, rx) = crossbeam::channel::unbounded();
+
+        Self {
+            pool,
+            tx,
+            rx,
+            pending: HashSet::new(),
+            latest_versions: HashMap::new(),
+            next_version: 0,
+            octrees: HashMap::new(),
+            max_patched_voxels: config.max_patched_voxels,
+        }
+    }
+
+    /// Whether any jobs are still waiting to start, running or waiting to be drained.
+    pub fn is_busy(&self) -> bool {
+        !self.pending.is_empty() || !self.latest_versions.is_empty()
+    }
+
+    /// Dirty chunks that haven't been handed to the meshing pool yet.
+    pub fn num_pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn spawn(
+        &mut self,
+        chunk_min: Point3i,
+        mesh_mode: MeshMode,
+        palette: Arc<VoxelPalette>,
+        world_scale: f32,
+        chunk: Option<Array3x1<Voxel>>,
+        mesh_voxels: Array3x1<Voxel>,
+        build_collider: bool,
+    ) {
+        let version = self.next_version;
+        self.next_version += 1;
+        self.latest_versions.insert(chunk_min, version);
+
+        let previous_octree = self.octrees.get(&chunk_min).cloned();
+        let max_patched_voxels = self.max_patched_voxels;
+        let tx = self.tx.clone();
+        self.pool.spawn(move || {
+            let mesh_layer = |layer| match mesh_mode {
+                MeshMode::SurfaceNets => {
+                    surface_nets_vertices(&palette, &mesh_voxels, layer, world_scale)
+                }
+                MeshMode::GreedyQuads => {
+                    greedy_quads_vertices(&palette, &mesh_voxels, layer, world_scale)
+                }
+            };
+            let vertices = mesh_layer(MeshLayer::Opaque);
+            let transparent_vertices = mesh_layer(MeshLayer::Transparent);
+            recycle_voxel_array(mesh_voxels);
+            let is_occluder = chunk
+                .as_ref()
+                .map_or(false, |chunk| is_occluder_chunk(&palette, chunk));
+            let octree = update_chunk_octree(
+                previous_octree.as_deref(),
+                chunk.as_ref(),
+                &palette,
+                max_patched_voxels,
+            );
+            // An unchanged octree keeps its old collider.
+            let collider = match &octree {
+                OctreeUpdate::Patched(o) | OctreeUpdate::Rebuilt(o) if build_collider => {
+                    chunk_collider(&o.octree, world_scale)
+                }
+                _ => None,
+            };
+
+            // The receiver only goes away with the whole `ChunkMeshJobs`.
+            let _ = tx.send(MeshJobResult {
+                chunk_min,
+                version,
+                octree,
+                is_occluder,
+                collider,
+                vertices,
+                transparent_vertices,
+            });
+        });
+    }
+
+    /// Takes up to `max_results` finished jobs, skipping any that are out of date.
+    fn drain(&mut self, max_results: usize) -> Vec<MeshJobResult> {
+        let mut results = Vec::new();
+        while results.len() < max_results {
+            let result = match self.rx.try_recv() {
+                Ok(r) => r,
+                Err(_) => break,
+            };
+            if self.latest_versions.get(&result.chunk_min) == Some(&result.version) {
+                self.latest_versions.remove(&result.chunk_min);
+                results.push(result);
+            }
+        }
+
+        results
+    }
+}
+
+/// Starts meshing jobs for the dirty chunks, nearest to the camera first and within the
+/// `MeshingConfig::frame_budget_ms`, and swaps in the meshes, octrees and entities of the jobs that
+/// finished since last frame.
 pub struct VoxelChunkProcessorSystem;
 
 impl<'a> System<'a> for VoxelChunkProcessorSystem {
@@ -31,7 +239,14 @@ impl<'a> System<'a> for VoxelChunkProcessorSystem {
         ReadExpect<'a, VoxelMap>,
         ReadExpect<'a, MeshMode>,
         ReadExpect<'a, ChunkCacheFlusher>,
+        Read<'a, MeshingConfig>,
+        Read<'a, ActiveCamera>,
+        ReadStorage<'a, Camera>,
+        ReadStorage<'a, Transform>,
         Write<'a, Option<DirtyChunks>>,
+        Write<'a, OccluderChunks>,
+        Write<'a, ChunkColliders>,
+        WriteExpect<'a, ChunkMeshJobs>,
         WriteExpect<'a, VoxelAssets>,
         WriteExpect<'a, OctreeDbvt<Point3i>>,
         VoxelMeshLoader<'a>,
@@ -44,20 +259,40 @@ impl<'a> System<'a> for VoxelChunkProcessorSystem {
             voxel_map,
             mesh_mode,
             cache_flusher,
+            config,
+            active_camera,
+            cameras,
+            transforms,
             mut dirty_chunks,
+            mut occluders,
+            mut colliders,
+            mut jobs,
             mut voxel_assets,
             mut voxel_bvt,
-            loader,
+            mut loader,
             mut manager,
         ): Self::SystemData,
     ) {
         #[cfg(feature = "profiler")]
         profile_scope!("voxel_chunk_processor");
 
-        let chunks_to_generate = match dirty_chunks.take() {
-            Some(c) => c.chunks,
-            None => return,
-        };
+        if let Some(dirty_chunks) = dirty_chunks.take() {
+            jobs.pending.extend(dirty_chunks.chunks.keys().cloned());
+        }
+        let eye = active_camera
+            .entity
+            .and_then(|e| Some((cameras.get(e)?, transforms.get(e)?)))
+            .or_else(|| (&cameras, &transforms).join().next())
+            .map(|(_, tfm)| tfm.global_matrix().transform_point(&Point3::origin()));
+        start_mesh_jobs(
+            &voxel_map,
+            *mesh_mode,
+            &cache_flusher,
+            colliders.enabled,
+            eye,
+            config.frame_budget(),
+            &mut jobs,
+        );
 
         let VoxelAssets {
             array_materials,
@@ -65,70 +300,184 @@ impl<'a> System<'a> for VoxelChunkProcessorSystem {
             ..
         } = &mut *voxel_assets;
 
-        // Do parallel processing of dirty chunks.
-        let generated_chunks: Vec<(Point3i, OctreeSet, Option<IndexedPosColorNormVertices>)> =
-            chunks_to_generate
-                .into_par_iter()
-                .filter_map(|chunk_min| {
-                    let chunk_key = ChunkKey::new(0, chunk_min);
-
-                    let local_chunk_cache = LocalChunkCache3::new();
-                    let reader = voxel_map.voxels.reader(&local_chunk_cache);
-
-                    let chunk_extent = reader.indexer.extent_for_chunk_with_min(chunk_min);
-
-                    let vertices = match *mesh_mode {
-                        MeshMode::SurfaceNets => generate_mesh_vertices_with_surface_nets(
-                            &voxel_map,
-                            &chunk_extent,
-                            &local_chunk_cache,
-                        ),
-                        MeshMode::GreedyQuads => generate_mesh_vertices_with_greedy_quads(
-                            &voxel_map,
-                            &chunk_extent,
-                            &local_chunk_cache,
-                        ),
-                    };
-
-                    let maybe_processed_chunk = reader.get_chunk(chunk_key).map(|chunk| {
-                        let is_empty_map =
-                            TransformMap::new(chunk, voxel_map.voxel_info_transform());
-                        let new_octree = OctreeSet::from_array3(&is_empty_map, chunk_extent);
-
-                        (chunk_min, new_octree, vertices)
-                    });
-
-                    cache_flusher.flush(local_chunk_cache);
-
-                    maybe_processed_chunk
-                })
-                .collect();
-
-        // Collect the generated results.
-        for (chunk_min, octree, vertices) in generated_chunks.into_iter() {
-            // Load the mesh.
-            let mesh = {
+        for MeshJobResult {
+            chunk_min,
+            octree,
+            is_occluder,
+            collider,
+            vertices,
+            transparent_vertices,
+            ..
+        } in jobs.drain(config.max_meshes_per_frame).into_iter()
+        {
+            // Load the meshes.
+            let (mesh, transparent_mesh) = {
                 #[cfg(feature = "profiler")]
                 profile_scope!("load_chunk_mesh");
 
                 let mut _unused_progress = ProgressCounter::new();
-                vertices.map(|v| loader.start_loading_chunk(v, &mut _unused_progress))
+                (
+                    loader.start_loading_opaque_chunk(chunk_min, vertices, &mut _unused_progress),
+                    transparent_vertices
+                        .map(|v| loader.start_loading_chunk(v, &mut _unused_progress)),
+                )
             };
 
-            // Replace the chunk BVT.
-            if octree.is_empty() {
-                voxel_bvt.remove(&chunk_min);
-            } else {
-                voxel_bvt.insert(chunk_min, octree);
+            // Replace the chunk BVT, unless no voxels became empty or non-empty.
+            match octree {
+                OctreeUpdate::Unchanged => (),
+                OctreeUpdate::Patched(octree) | OctreeUpdate::Rebuilt(octree) => {
+                    if octree.octree.is_empty() {
+                        voxel_bvt.remove(&chunk_min);
+                    } else {
+                        voxel_bvt.insert(chunk_min, octree.octree.clone());
+                    }
+                    jobs.octrees.insert(chunk_min, octree);
+                    if colliders.enabled {
+                        colliders.set_collider(chunk_min, collider);
+                    }
+                }
+                OctreeUpdate::Removed => {
+                    voxel_bvt.remove(&chunk_min);
+                    jobs.octrees.remove(&chunk_min);
+                    if colliders.enabled {
+                        colliders.set_collider(chunk_min, None);
+                    }
+                }
             }
+            occluders.set_occluder(chunk_min, is_occluder);
 
             // Update entities and drop old assets.
-            manager.update_chunk_mesh_entities(chunk_min, mesh.clone(), array_materials);
+            manager.update_chunk_mesh_entities(
+                chunk_min,
+                mesh.clone(),
+                transparent_mesh.clone(),
+                array_materials,
+            );
             if let Some(new_mesh) = mesh {
                 let _drop_old_chunk_meshes = meshes.chunk_meshes.insert(chunk_min, new_mesh);
             } else {
                 meshes.chunk_meshes.remove(&chunk_min);
             }
+            if let Some(new_mesh) = transparent_mesh {
+                let _drop_old_chunk_meshes =
+                    meshes.transparent_chunk_meshes.insert(chunk_min, new_mesh);
+            } else {
+                meshes.transparent_chunk_meshes.remove(&chunk_min);
+            }
+        }
+
+        // Each merged region is only loaded once, after all of its chunks have been updated.
+        let mut _unused_progress = ProgressCounter::new();
+        for (region_min, mesh) in loader.start_loading_dirty_regions(&mut _unused_progress) {
+            manager.update_region_mesh_entities(region_min, mesh.clone(), array_materials);
+            if let Some(new_mesh) = mesh {
+                let _drop_old_region_meshes = meshes.region_meshes.insert(region_min, new_mesh);
+            } else {
+                meshes.region_meshes.remove(&region_min);
+            }
+        }
+    }
+}
+
+/// The number of chunks copied in parallel between checks of the frame budget.
+const MESH_JOB_BATCH_SIZE: usize = 32;
+
+/// Copies the voxels of the pending chunks (in parallel, since decompression can be slow) and hands
+/// them off to the meshing pool, starting with the chunks nearest to `eye`. Stops once `budget` has
+/// been used up, leaving the remaining chunks pending.
+fn start_mesh_jobs(
+    voxel_map: &VoxelMap,
+    mesh_mode: MeshMode,
+    cache_flusher: &ChunkCacheFlusher,
+    build_colliders: bool,
+    eye: Option<Point3<f32>>,
+    budget: Duration,
+    jobs: &mut ChunkMeshJobs,
+) {
+    if jobs.pending.is_empty() {
+        return;
+    }
+    let start = Instant::now();
+
+    let mut chunk_mins: Vec<Point3i> = jobs.pending.drain().collect();
+    match eye {
+        Some(eye) => {
+            let half_chunk = Vector3::from(Point3f::from(voxel_map.chunk_shape()).0) / 2.0;
+            let distance_sq = |chunk_min: &Point3i| {
+                let center = Point3::from(Point3f::from(*chunk_min).0) + half_chunk;
+                (voxel_to_world_space(center, voxel_map.world_scale) - eye).norm_squared()
+            };
+            // Farthest first, so the nearest chunks can be popped off the end.
+            chunk_mins.sort_by(|a, b| distance_sq(b).partial_cmp(&distance_sq(a)).unwrap());
+        }
+        None => {
+            sort_chunk_mins_morton(&mut chunk_mins, voxel_map.chunk_shape());
+            chunk_mins.reverse();
+        }
+    }
+
+    let palette = Arc::new(voxel_map.palette.clone());
+    while !chunk_mins.is_empty() {
+        let batch_start = chunk_mins.len().saturating_sub(MESH_JOB_BATCH_SIZE);
+        let mut batch = chunk_mins.split_off(batch_start);
+        // Rayon splits the list into contiguous runs, so Morton order gives each thread a compact
+        // region of chunks whose boundary reads overlap.
+        sort_chunk_mins_morton(&mut batch, voxel_map.chunk_shape());
+        start_mesh_job_batch(
+            batch,
+            voxel_map,
+            mesh_mode,
+            cache_flusher,
+            build_colliders,
+            &palette,
+            jobs,
+        );
+        if start.elapsed() >= budget {
+            break;
         }
     }
+    jobs.pending.extend(chunk_mins);
+}
+
+fn start_mesh_job_batch(
+    chunk_mins: Vec<Point3i>,
+    voxel_map: &VoxelMap,
+    mesh_mode: MeshMode,
+    cache_flusher: &ChunkCacheFlusher,
+    build_colliders: bool,
+    palette: &Arc<VoxelPalette>,
+    jobs: &mut ChunkMeshJobs,
+) {
+    #[allow(clippy::type_complexity)]
+    let copies: Vec<(Point3i, Option<Array3x1<Voxel>>, Array3x1<Voxel>)> = chunk_mins
+        .into_par_iter()
+        .map(|chunk_min| {
+            let local_chunk_cache = LocalChunkCache3::new();
+            let reader = voxel_map.voxels.reader(&local_chunk_cache);
+            let chunk_extent = reader.indexer.extent_for_chunk_with_min(chunk_min);
+            let mesh_extent = match mesh_mode {
+                MeshMode::SurfaceNets => padded_surface_nets_chunk_extent(&chunk_extent),
+                MeshMode::GreedyQuads => padded_greedy_quads_chunk_extent(&chunk_extent),
+            };
+
+            let chunk = reader.get_chunk(ChunkKey::new(0, chunk_min)).cloned();
+            let mesh_voxels = copy_mesh_voxels(voxel_map, &mesh_extent, &local_chunk_cache);
+            cache_flusher.flush(local_chunk_cache);
+
+            (chunk_min, chunk, mesh_voxels)
+        })
+        .collect();
+
+    for (chunk_min, chunk, mesh_voxels) in copies.into_iter() {
+        jobs.spawn(
+            chunk_min,
+            mesh_mode,
+            palette.clone(),
+            voxel_map.world_scale,
+            chunk,
+            mesh_voxels,
+            build_colliders,
+        );
+    }
 }
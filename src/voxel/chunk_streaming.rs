@@ -0,0 +1,515 @@
+use crate::{
+    assets::BincodeFileError,
+    voxel::{
+        centered_extent,
+        chunk_cache_flusher::ChunkCacheFlusher,
+        double_buffer::EditedChunksBackBuffer,
+        generation::{ChunkGenerationRequests, GeneratedChunks, StreamingConfig},
+        map_file::{compress_chunk, decompress_chunk},
+        Voxel, VoxelMap, VOXEL_CHUNK_SHAPE,
+    },
+};
+
+use amethyst::core::ecs::prelude::*;
+use building_blocks::prelude::*;
+use crossbeam::channel::{Receiver, Sender};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+enum StreamingJobResult {
+    Decompressed {
+        chunk_min: Point3i,
+        result: Result<Array3x1<Voxel>, BincodeFileError>,
+    },
+    Compressed {
+        chunk_min: Point3i,
+        version: u64,
+        result: Result<Vec<u8>, BincodeFileError>,
+    },
+}
+
+/// The chunks of a streamed map that don't need to be loaded into the `VoxelMap`, kept compressed
+/// the same way as in the voxels file. Evicted chunks are compressed back into the store, so they
+/// are included the next time the map is saved.
+///
+/// A chunk that's loaded in the map always takes precedence over its stored copy, which may be out
+/// of date.
+pub struct StoredChunks {
+    /// The shape of the map's chunks.
+    chunk_shape: Point3i,
+    /// Shared with the `EditedChunksBackBuffer` through a `StoredChunkReader`.
+    copies: Arc<RwLock<StoredCopies>>,
+    /// Chunks being decompressed, or waiting to be written into the backbuffer.
+    loading: HashSet<Point3i>,
+    /// Chunks that were still waiting for compression when they were requested again.
+    ready: Vec<(Point3i, Array3x1<Voxel>)>,
+    next_version: u64,
+    tx: Sender<StreamingJobResult>,
+    rx: Receiver<StreamingJobResult>,
+}
+
+#[derive(Default)]
+struct StoredCopies {
+    compressed: HashMap<Point3i, Vec<u8>>,
+    /// Evicted chunks that are still being compressed, with the version of the newest job.
+    compressing: HashMap<Point3i, (u64, Array3x1<Voxel>)>,
+}
+
+impl StoredCopies {
+    fn contains(&self, chunk_min: &Point3i) -> bool {
+        self.compressed.contains_key(chunk_min) || self.compressing.contains_key(chunk_min)
+    }
+}
+
+/// Reads the stored copies of chunks that aren't loaded in the map, so edits to those chunks start
+/// from their stored voxels. Registered with `EditedChunksBackBuffer::set_stored_chunks`.
+#[derive(Clone)]
+pub struct StoredChunkReader {
+    chunk_shape: Point3i,
+    copies: Arc<RwLock<StoredCopies>>,
+}
+
+impl StoredChunkReader {
+    /// The newest stored copy of the chunk at `chunk_min`, decompressed on the calling thread.
+    pub fn read_chunk(&self, chunk_min: Point3i) -> Option<Array3x1<Voxel>> {
+        let bytes = {
+            let copies = self.copies.read().unwrap();
+            if let Some((_, chunk)) = copies.compressing.get(&chunk_min) {
+                return Some(chunk.clone());
+            }
+            copies.compressed.get(&chunk_min)?.clone()
+        };
+
+        match decompress_chunk(chunk_min, self.chunk_shape, &bytes) {
+            Ok(chunk) => Some(chunk),
+            Err(e) => {
+                log::error!("Failed to load chunk {:?}: {:?}", chunk_min, e);
+
+                None
+            }
+        }
+    }
+}
+
+impl Default for StoredChunks {
+    fn default() -> Self {
+        Self::new(VOXEL_CHUNK_SHAPE, Vec::new())
+    }
+}
+
+impl StoredChunks {
+    /// `compressed` chunks must come from `compress_chunk`, and have the map's `chunk_shape`.
+    pub fn new(chunk_shape: Point3i, compressed: Vec<(Point3i, Vec<u8>)>) -> Self {
+        let (tx, rx) = crossbeam::channel::unbounded();
+
+        Self {
+            chunk_shape,
+            copies: Arc::new(RwLock::new(StoredCopies {
+                compressed: compressed.into_iter().collect(),
+                compressing: HashMap::new(),
+            })),
+            loading: HashSet::new(),
+            ready: Vec::new(),
+            next_version: 0,
+            tx,
+            rx,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        let copies = self.copies.read().unwrap();
+
+        copies.compressed.is_empty()
+            && copies.compressing.is_empty()
+            && self.loading.is_empty()
+            && self.ready.is_empty()
+    }
+
+    pub fn contains(&self, chunk_min: &Point3i) -> bool {
+        self.copies.read().unwrap().contains(chunk_min)
+    }
+
+    pub fn reader(&self) -> StoredChunkReader {
+        StoredChunkReader {
+            chunk_shape: self.chunk_shape,
+            copies: self.copies.clone(),
+        }
+    }
+
+    /// Copies the stored chunks that aren't loaded in `map`, for saving with
+    /// `write_voxels_file_with_compressed`. Chunks that are still being compressed are returned
+    /// uncompressed.
+    #[allow(clippy::type_complexity)]
+    pub fn snapshot_unloaded(
+        &self,
+        map: &VoxelMap,
+    ) -> (Vec<(Point3i, Array3x1<Voxel>)>, Vec<(Point3i, Vec<u8>)>) {
+        let loaded: HashSet<Point3i> = map
+            .voxels
+            .storage()
+            .chunk_keys()
+            .map(|chunk_key| chunk_key.minimum)
+            .collect();
+        let is_loaded = |chunk_min: &Point3i| loaded.contains(chunk_min);
+
+        let copies = self.copies.read().unwrap();
+        let uncompressed = copies
+            .compressing
+            .iter()
+            .filter(|(chunk_min, _)| !is_loaded(chunk_min))
+            .map(|(chunk_min, (_, chunk))| (*chunk_min, chunk.clone()))
+            .collect();
+        let compressed = copies
+            .compressed
+            .iter()
+            .filter(|(chunk_min, _)| {
+                !is_loaded(chunk_min) && !copies.compressing.contains_key(chunk_min)
+            })
+            .map(|(chunk_min, bytes)| (*chunk_min, bytes.clone()))
+            .collect();
+
+        (uncompressed, compressed)
+    }
+
+    /// Starts loading the stored chunk at `chunk_min` on a background thread. Returns false if
+    /// there is no such chunk, or it's already loading.
+    fn start_loading(&mut self, chunk_min: Point3i) -> bool {
+        if self.loading.contains(&chunk_min) {
+            return false;
+        }
+
+        // The newest copy is the one waiting to be compressed.
+        let copies = self.copies.read().unwrap();
+        if let Some((_, chunk)) = copies.compressing.get(&chunk_min) {
+            self.ready.push((chunk_min, chunk.clone()));
+        } else if let Some(bytes) = copies.compressed.get(&chunk_min) {
+            let bytes = bytes.clone();
+            let chunk_shape = self.chunk_shape;
+            let tx = self.tx.clone();
+            rayon::spawn(move || {
+                // The receiver only goes away with the whole `StoredChunks`.
+                let _ = tx.send(StreamingJobResult::Decompressed {
+                    chunk_min,
+                    result: decompress_chunk(chunk_min, chunk_shape, &bytes),
+                });
+            });
+        } else {
+            return false;
+        }
+        self.loading.insert(chunk_min);
+
+        true
+    }
+
+    /// Stores a copy of an evicted chunk, compressing it on a background thread.
+    fn start_storing(&mut self, chunk_min: Point3i, chunk: Array3x1<Voxel>) {
+        let version = self.next_version;
+        self.next_version += 1;
+
+        let tx = self.tx.clone();
+        let job_chunk = chunk.clone();
+        rayon::spawn(move || {
+            let _ = tx.send(StreamingJobResult::Compressed {
+                chunk_min,
+                version,
+                result: compress_chunk(&job_chunk, None),
+            });
+        });
+        self.copies
+            .write()
+            .unwrap()
+            .compressing
+            .insert(chunk_min, (version, chunk));
+    }
+
+    /// Stores the chunks that finished compressing, and returns the chunks that finished loading.
+    fn finish_jobs(&mut self) -> Vec<(Point3i, Array3x1<Voxel>)> {
+        let mut loaded = std::mem::replace(&mut self.ready, Vec::new());
+        let mut copies = self.copies.write().unwrap();
+        for result in self.rx.try_iter() {
+            match result {
+                StreamingJobResult::Decompressed { chunk_min, result } => match result {
+                    Ok(chunk) => loaded.push((chunk_min, chunk)),
+                    Err(e) => log::error!("Failed to load chunk {:?}: {:?}", chunk_min, e),
+                },
+                StreamingJobResult::Compressed {
+                    chunk_min,
+                    version,
+                    result,
+                } => {
+                    // A newer copy of the chunk may have been evicted in the meantime.
+                    if copies.compressing.get(&chunk_min).map(|(v, _)| *v) != Some(version) {
+                        continue;
+                    }
+                    match result {
+                        Ok(bytes) => {
+                            copies.compressing.remove(&chunk_min);
+                            copies.compressed.insert(chunk_min, bytes);
+                        }
+                        // Keep the uncompressed copy so the chunk isn't lost.
+                        Err(e) => log::error!("Failed to store chunk {:?}: {:?}", chunk_min, e),
+                    }
+                }
+            }
+        }
+        for (chunk_min, _) in loaded.iter() {
+            self.loading.remove(chunk_min);
+        }
+
+        loaded
+    }
+}
+
+/// Streams the chunks in `StoredChunks` in and out of the `VoxelMap` around the centers passed to
+/// `ChunkGenerationRequests::request_around`, using the same radii as generated chunks. Chunks are
+/// loaded through the `EditedChunksBackBuffer`, so their meshes and BVT entries follow along.
+///
+/// Edited chunks beyond the `StreamingConfig::evict_radius` are stored and then evicted from the
+/// map. Pristine generated chunks are left to the `ChunkGenerationSystem`, since they don't need to
+/// be stored.
+///
+/// The `StoredChunks` are registered with the backbuffer, so edits to stored chunks that haven't
+/// been loaded yet start from their stored copy instead of empty space.
+pub struct ChunkStreamingSystem;
+
+impl<'a> System<'a> for ChunkStreamingSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        Read<'a, StreamingConfig>,
+        Read<'a, ChunkGenerationRequests>,
+        Read<'a, GeneratedChunks>,
+        ReadExpect<'a, VoxelMap>,
+        ReadExpect<'a, ChunkCacheFlusher>,
+        Write<'a, StoredChunks>,
+        WriteExpect<'a, EditedChunksBackBuffer>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            config,
+            requests,
+            generated,
+            voxel_map,
+            cache_flusher,
+            mut stored,
+            mut backbuffer,
+        ): Self::SystemData,
+    ) {
+        #[cfg(feature = "profiler")]
+        profile_scope!("chunk_streaming");
+
+        // A `StoredChunks` that wasn't inserted along with the map has the default chunk shape.
+        let chunk_shape = voxel_map.chunk_shape();
+        if stored.chunk_shape != chunk_shape && stored.is_empty() {
+            *stored = StoredChunks::new(chunk_shape, Vec::new());
+        }
+        debug_assert_eq!(stored.chunk_shape, chunk_shape);
+        backbuffer.set_stored_chunks(Some(stored.reader()));
+
+        let local_cache = LocalChunkCache3::new();
+        let reader = voxel_map.voxels.reader(&local_cache);
+
+        let loaded: Vec<_> = stored
+            .finish_jobs()
+            .into_iter()
+            .filter(|(chunk_min, _)| reader.get_chunk(ChunkKey::new(0, *chunk_min)).is_none())
+            .collect();
+        backbuffer.load_chunks(&reader, loaded);
+
+        let centers = requests.centers();
+        if centers.is_empty() {
+            cache_flusher.flush(local_cache);
+            return;
+        }
+
+        // Store and evict the edited chunks that are far from every center.
+        let keep_extents: Vec<Extent3i> = centers
+            .iter()
+            .map(|c| centered_extent(*c, config.evict_radius))
+            .collect();
+        let evicted: Vec<Point3i> = voxel_map
+            .voxels
+            .storage()
+            .chunk_keys()
+            .map(|chunk_key| chunk_key.minimum)
+            .filter(|chunk_min| {
+                let chunk_extent = reader.indexer.extent_for_chunk_with_min(*chunk_min);
+
+                !generated.is_pristine(chunk_min)
+                    && keep_extents
+                        .iter()
+                        .all(|keep| keep.intersection(&chunk_extent).is_empty())
+            })
+            .collect();
+        for chunk_min in evicted.iter() {
+            if let Some(chunk) = reader.get_chunk(ChunkKey::new(0, *chunk_min)) {
+                stored.start_storing(*chunk_min, chunk.clone());
+            }
+        }
+        backbuffer.unload_chunks(&reader, evicted);
+
+        // Load the stored chunks near any center.
+        let mut num_loads = 0;
+        'outer: for center in centers.iter() {
+            let load_extent = centered_extent(*center, config.load_radius);
+            for chunk_min in reader.indexer.chunk_mins_for_extent(&load_extent) {
+                if num_loads >= config.max_chunk_loads_per_frame {
+                    break 'outer;
+                }
+                if reader.get_chunk(ChunkKey::new(0, chunk_min)).is_none()
+                    && stored.start_loading(chunk_min)
+                {
+                    num_loads += 1;
+                }
+            }
+        }
+
+        cache_flusher.flush(local_cache);
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        test_util::{test_palette, VoxelPipelineHarness},
+        voxel::{
+            chunk_lock::{LockedChunkEditEvent, LockedChunks},
+            VoxelDistance, VoxelType, EMPTY_VOXEL,
+        },
+    };
+
+    use amethyst::shrev::EventChannel;
+
+    const SOLID: Voxel = Voxel {
+        voxel_type: VoxelType(1),
+        distance: VoxelDistance(-1),
+    };
+
+    /// A harness whose map has a single solid chunk at the origin in its `StoredChunks`.
+    fn harness_with_stored_chunk() -> VoxelPipelineHarness {
+        let map = VoxelMap::new(test_palette());
+        let chunk_shape = map.chunk_shape();
+        let chunk_min = PointN([0; 3]);
+        let chunk = Array3x1::fill(Extent3i::from_min_and_shape(chunk_min, chunk_shape), SOLID);
+
+        let harness = VoxelPipelineHarness::new(map);
+        harness.world.insert(StoredChunks::new(
+            chunk_shape,
+            vec![(chunk_min, compress_chunk(&chunk, None).unwrap())],
+        ));
+
+        harness
+    }
+
+    #[test]
+    fn test_stored_chunk_streams_in_and_out() {
+        let chunk_min = PointN([0; 3]);
+        let mut harness = harness_with_stored_chunk();
+
+        let step_around = |harness: &mut VoxelPipelineHarness, center: Point3i| {
+            harness
+                .world
+                .write_resource::<ChunkGenerationRequests>()
+                .request_around(center);
+            harness.step();
+        };
+
+        // Loading takes one frame to decompress and another to merge.
+        for _ in 0..100 {
+            step_around(&mut harness, PointN([8; 3]));
+            if harness.voxel(PointN([8; 3])) == SOLID {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert_eq!(harness.voxel(PointN([8; 3])), SOLID);
+
+        // Edit the chunk, then walk far enough away to evict it.
+        harness.queue_edit(
+            Extent3i::from_min_and_shape(PointN([8; 3]), PointN([1; 3])),
+            |_p, v| *v = EMPTY_VOXEL,
+        );
+        harness.step();
+        step_around(&mut harness, PointN([10_000, 0, 0]));
+        assert_eq!(harness.voxel(PointN([9; 3])), EMPTY_VOXEL);
+        assert!(harness
+            .world
+            .read_resource::<StoredChunks>()
+            .contains(&chunk_min));
+
+        // The edit survives the round trip.
+        for _ in 0..100 {
+            step_around(&mut harness, PointN([8; 3]));
+            if harness.voxel(PointN([9; 3])) == SOLID {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert_eq!(harness.voxel(PointN([9; 3])), SOLID);
+        assert_eq!(harness.voxel(PointN([8; 3])), EMPTY_VOXEL);
+    }
+
+    #[test]
+    fn test_edit_to_unloaded_stored_chunk_keeps_its_voxels() {
+        let mut harness = harness_with_stored_chunk();
+
+        // No centers are requested, so the chunk is never streamed in.
+        harness.queue_edit(
+            Extent3i::from_min_and_shape(PointN([8; 3]), PointN([1; 3])),
+            |_p, v| *v = EMPTY_VOXEL,
+        );
+        harness.step();
+
+        assert_eq!(harness.voxel(PointN([8; 3])), EMPTY_VOXEL);
+        assert_eq!(harness.voxel(PointN([9; 3])), SOLID);
+        assert_eq!(harness.voxel(PointN([0; 3])), SOLID);
+    }
+
+    #[test]
+    fn test_locked_stored_chunk_streams_in() {
+        let mut harness = harness_with_stored_chunk();
+        harness
+            .world
+            .insert(LockedChunks::new(vec![PointN([0; 3])]));
+        harness.step();
+        let mut locked_reader = harness
+            .world
+            .write_resource::<EventChannel<LockedChunkEditEvent>>()
+            .register_reader();
+
+        for _ in 0..100 {
+            harness
+                .world
+                .write_resource::<ChunkGenerationRequests>()
+                .request_around(PointN([8; 3]));
+            harness.step();
+            if harness.voxel(PointN([8; 3])) == SOLID {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        assert_eq!(harness.voxel(PointN([8; 3])), SOLID);
+        assert_eq!(
+            harness
+                .world
+                .read_resource::<EventChannel<LockedChunkEditEvent>>()
+                .read(&mut locked_reader)
+                .count(),
+            0
+        );
+    }
+}
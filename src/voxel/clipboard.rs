@@ -0,0 +1,270 @@
+use crate::{
+    assets::{write_bincode_file, BincodeFileError},
+    voxel::{
+        double_buffer::EditedChunksBackBuffer, empty_array, map_file::deserialize_voxels, Voxel,
+        VoxelChunkReader, EMPTY_VOXEL,
+    },
+};
+
+use building_blocks::prelude::*;
+use serde::Serialize;
+use std::io;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    fn index(self) -> usize {
+        match self {
+            Axis::X => 0,
+            Axis::Y => 1,
+            Axis::Z => 2,
+        }
+    }
+}
+
+/// A dense copy of some voxels from the map. The array always has its minimum at the origin, so
+/// it can be pasted at any offset.
+#[derive(Clone)]
+pub struct VoxelClipboard {
+    pub voxels: Array3x1<Voxel>,
+}
+
+impl VoxelClipboard {
+    pub fn copy_from_map(map_reader: &VoxelChunkReader, extent: &Extent3i) -> Self {
+        let mut voxels = empty_array(*extent);
+        copy_extent(extent, &map_reader.lod_view(0), &mut voxels);
+        voxels.set_minimum(PointN([0; 3]));
+
+        Self { voxels }
+    }
+
+    pub fn shape(&self) -> Point3i {
+        self.voxels.extent().shape
+    }
+
+    /// Writes the clipboard to its own file so it can be reused in other maps.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), BincodeFileError> {
+        let extent = *self.voxels.extent();
+        let mut voxels = Vec::with_capacity(extent.num_points());
+        self.voxels
+            .for_each(&extent, |_p: Point3i, v: Voxel| voxels.push(v));
+
+        write_bincode_file(
+            path,
+            ClipboardFile {
+                shape: extent.shape.0,
+                voxels,
+            },
+        )
+    }
+
+    /// Loads a clipboard saved with either distance precision; see `map_file::deserialize_voxels`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, BincodeFileError> {
+        let bytes = std::fs::read(path)?;
+        // The shape comes first, as 3 fixed-size integers.
+        let shape: [i32; 3] = bincode::deserialize(&bytes)?;
+        let file_voxels = deserialize_voxels(&bytes[12..])?;
+        let extent = Extent3i::from_min_and_shape(PointN([0; 3]), PointN(shape));
+        if shape.iter().any(|&d| d < 0) || file_voxels.len() != extent.num_points() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Clipboard of shape {:?} has {} voxels",
+                    shape,
+                    file_voxels.len()
+                ),
+            )
+            .into());
+        }
+        let mut voxels = empty_array(extent);
+        let mut file_voxels = file_voxels.into_iter();
+        voxels.for_each_mut(&extent, |_p: Point3i, v: &mut Voxel| {
+            *v = file_voxels.next().unwrap();
+        });
+
+        Ok(Self { voxels })
+    }
+
+    /// Rotates the contents by 90 degrees about the Y axis. The X and Z dimensions of the shape are
+    /// swapped.
+    pub fn rotate_y_90(&mut self) {
+        let shape = self.shape();
+        let new_extent =
+            Extent3i::from_min_and_shape(PointN([0; 3]), PointN([shape.z(), shape.y(), shape.x()]));
+        let mut rotated = Array3x1::fill(new_extent, EMPTY_VOXEL);
+        let extent = *self.voxels.extent();
+        self.voxels.for_each(&extent, |p: Point3i, v: Voxel| {
+            *rotated.get_mut(PointN([shape.z() - 1 - p.z(), p.y(), p.x()])) = v;
+        });
+        self.voxels = rotated;
+    }
+
+    /// Mirrors the contents across the plane perpendicular to `axis` through the center.
+    pub fn flip(&mut self, axis: Axis) {
+        let i = axis.index();
+        let shape = self.shape();
+        let extent = *self.voxels.extent();
+        let mut flipped = Array3x1::fill(extent, EMPTY_VOXEL);
+        self.voxels.for_each(&extent, |p: Point3i, v: Voxel| {
+            let mut q = p;
+            q.0[i] = shape.0[i] - 1 - p.0[i];
+            *flipped.get_mut(q) = v;
+        });
+        self.voxels = flipped;
+    }
+
+    /// Writes the contents into the map with the clipboard's minimum at `min`. The contents are
+    /// unioned with the existing voxels, so empty space in the clipboard doesn't carve anything.
+    pub fn paste(
+        &self,
+        map_reader: &VoxelChunkReader,
+        min: Point3i,
+        backbuffer: &mut EditedChunksBackBuffer,
+    ) {
+        let dst_extent = Extent3i::from_min_and_shape(min, self.shape());
+        backbuffer.edit_voxels_out_of_place(
+            map_reader,
+            &dst_extent,
+            |p: Point3i, v: &mut Voxel| {
+                let src = self.voxels.get(p - min);
+                if src.distance.0 < v.distance.0 {
+                    v.distance = src.distance;
+                    if src.distance.0 < 0 {
+                        v.voxel_type = src.voxel_type;
+                    }
+                }
+            },
+        );
+    }
+}
+
+#[derive(Serialize)]
+struct ClipboardFile {
+    shape: [i32; 3],
+    voxels: Vec<Voxel>,
+}
+
+/// Moves the voxels in `src` so their minimum is at `dst_min`, leaving empty space behind. This is
+/// done with a single edit of the bounding extent, so it works even if the source and destination
+/// overlap.
+pub fn move_extent(
+    map_reader: &VoxelChunkReader,
+    src: &Extent3i,
+    dst_min: Point3i,
+    backbuffer: &mut EditedChunksBackBuffer,
+) {
+    let moved = VoxelClipboard::copy_from_map(map_reader, src);
+    let dst = Extent3i::from_min_and_shape(dst_min, src.shape);
+    let bounds = Extent3i::from_min_and_max(
+        PointN([
+            src.minimum.x().min(dst.minimum.x()),
+            src.minimum.y().min(dst.minimum.y()),
+            src.minimum.z().min(dst.minimum.z()),
+        ]),
+        PointN([
+            src.max().x().max(dst.max().x()),
+            src.max().y().max(dst.max().y()),
+            src.max().z().max(dst.max().z()),
+        ]),
+    );
+
+    backbuffer.edit_voxels_out_of_place(map_reader, &bounds, |p: Point3i, v: &mut Voxel| {
+        if dst.contains(p) {
+            *v = moved.voxels.get(p - dst_min);
+        } else if src.contains(p) {
+            *v = EMPTY_VOXEL;
+        }
+    });
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::voxel::{VoxelDistance, VoxelType};
+
+    fn clipboard_with_marker(shape: Point3i, marker: Point3i) -> VoxelClipboard {
+        let mut voxels = empty_array(Extent3i::from_min_and_shape(PointN([0; 3]), shape));
+        *voxels.get_mut(marker) = Voxel {
+            voxel_type: VoxelType(1),
+            distance: VoxelDistance::from(-1.0),
+        };
+
+        VoxelClipboard { voxels }
+    }
+
+    #[test]
+    fn test_rotate_y_90_moves_marker_and_swaps_shape() {
+        let mut clipboard = clipboard_with_marker(PointN([4, 2, 3]), PointN([1, 0, 0]));
+        clipboard.rotate_y_90();
+
+        assert_eq!(clipboard.shape(), PointN([3, 2, 4]));
+        assert_eq!(
+            clipboard.voxels.get(PointN([2, 0, 1])).voxel_type,
+            VoxelType(1)
+        );
+    }
+
+    #[test]
+    fn test_four_rotations_is_identity() {
+        let original = clipboard_with_marker(PointN([4, 2, 3]), PointN([3, 1, 2]));
+        let mut clipboard = original.clone();
+        for _ in 0..4 {
+            clipboard.rotate_y_90();
+        }
+
+        assert_eq!(clipboard.shape(), original.shape());
+        let extent = *original.voxels.extent();
+        original.voxels.for_each(&extent, |p: Point3i, v: Voxel| {
+            assert_eq!(clipboard.voxels.get(p), v);
+        });
+    }
+
+    #[test]
+    fn test_loading_a_clipboard_with_too_few_voxels_fails() {
+        let clipboard = clipboard_with_marker(PointN([4, 2, 3]), PointN([0, 1, 2]));
+        let path = std::env::temp_dir().join(format!("short_clipboard_{}.bin", std::process::id()));
+
+        clipboard.save(&path).unwrap();
+        let loaded = VoxelClipboard::load(&path).unwrap();
+        assert_eq!(loaded.shape(), clipboard.shape());
+
+        let mut voxels = Vec::new();
+        let extent = *clipboard.voxels.extent();
+        clipboard
+            .voxels
+            .for_each(&extent, |_p: Point3i, v: Voxel| voxels.push(v));
+        let file = ClipboardFile {
+            shape: [5, 2, 3],
+            voxels,
+        };
+        write_bincode_file(&path, file).unwrap();
+        assert!(VoxelClipboard::load(&path).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_flip_x() {
+        let mut clipboard = clipboard_with_marker(PointN([4, 2, 3]), PointN([0, 1, 2]));
+        clipboard.flip(Axis::X);
+
+        assert_eq!(
+            clipboard.voxels.get(PointN([3, 1, 2])).voxel_type,
+            VoxelType(1)
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use crate::voxel::{
+    centered_extent, double_buffer::EditedChunksBackBuffer, Voxel, VoxelChunkReader, VoxelDistance,
+    VoxelType, EMPTY_VOXEL,
+};
+
+use building_blocks::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// The shape of a crater, in voxel units.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct CraterParams {
+    /// Horizontal radius of the bowl.
+    pub radius: f32,
+    /// How far below the impact point the bowl reaches.
+    pub depth: f32,
+    /// How high the rim rises above the impact point.
+    pub rim_height: f32,
+    /// Horizontal thickness of the rim.
+    pub rim_width: f32,
+}
+
+impl CraterParams {
+    /// A crater with rim proportions that look reasonable for most radii.
+    pub fn with_radius_and_depth(radius: f32, depth: f32) -> Self {
+        Self {
+            radius,
+            depth,
+            rim_height: 0.2 * radius,
+            rim_width: 0.3 * radius,
+        }
+    }
+
+    /// Approximate signed distance to the ellipsoidal bowl that gets subtracted.
+    fn bowl_distance(&self, offset: [f32; 3]) -> f32 {
+        let radii = [self.radius, self.depth, self.radius];
+        let scaled = (0..3)
+            .map(|i| (offset[i] / radii[i]).powi(2))
+            .sum::<f32>()
+            .sqrt();
+
+        (scaled - 1.0) * self.radius.min(self.depth)
+    }
+
+    /// Approximate signed distance to the squashed torus that forms the raised rim.
+    fn rim_distance(&self, offset: [f32; 3]) -> f32 {
+        let horizontal = (offset[0] * offset[0] + offset[2] * offset[2]).sqrt() - self.radius;
+        let vertical = offset[1] * self.rim_width / self.rim_height.max(std::f32::EPSILON);
+
+        (horizontal * horizontal + vertical * vertical).sqrt() - self.rim_width
+    }
+
+    fn edit_radius(&self) -> u32 {
+        (self.radius + self.rim_width).max(self.depth).ceil() as u32 + 1
+    }
+}
+
+/// Blasts a crater into the map at `center`: an ellipsoidal bowl is subtracted and a ring of ejecta
+/// is added around its edge. The rim is filled with `rim_voxel_type`, or the type of the voxel at
+/// `center` if none is given.
+///
+/// This is the same operation used by the editor's crater brush, but it's just as useful for
+/// explosions in destructible-terrain games.
+pub fn apply_crater(
+    map_reader: &VoxelChunkReader,
+    center: Point3i,
+    params: &CraterParams,
+    rim_voxel_type: Option<VoxelType>,
+    backbuffer: &mut EditedChunksBackBuffer,
+) {
+    let rim_voxel_type = rim_voxel_type.unwrap_or_else(|| {
+        let center_voxel = map_reader.lod_view(0).get(center);
+        if center_voxel.distance.0 < 0 {
+            center_voxel.voxel_type
+        } else {
+            // The impact point is in the air, so take whatever is just below it.
+            map_reader
+                .lod_view(0)
+                .get(center - PointN([0, 1, 0]))
+                .voxel_type
+        }
+    });
+
+    backbuffer.edit_voxels_out_of_place(
+        map_reader,
+        &centered_extent(center, params.edit_radius()),
+        |p: Point3i, v: &mut Voxel| {
+            let d = p - center;
+            let offset = [d.x() as f32, d.y() as f32, d.z() as f32];
+
+            let old_dist: f32 = v.distance.into();
+            let bowl = params.bowl_distance(offset);
+            let carved = old_dist.max(-bowl);
+            // Don't let the rim spill back into the bowl.
+            let rim = params.rim_distance(offset).max(-bowl);
+            let new_dist = carved.min(rim);
+
+            v.distance = VoxelDistance::from(new_dist);
+            if v.distance.0 >= 0 {
+                v.voxel_type = EMPTY_VOXEL.voxel_type;
+            } else if rim < carved {
+                v.voxel_type = rim_voxel_type;
+            }
+        },
+    );
+}
@@ -0,0 +1,165 @@
+//! Constructive solid geometry between two boxes of voxels, like the map and the clipboard. Since
+//! both sides are signed distance fields, the operations are just the min or max of the distances,
+//! and each voxel takes the material of whichever side its distance came from.
+
+use crate::voxel::{
+    double_buffer::EditedChunksBackBuffer, Voxel, VoxelChunkReader, VoxelDistance, EMPTY_VOXEL,
+};
+
+use building_blocks::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum CsgOperation {
+    /// Solid where either side is solid.
+    Union,
+    /// Solid where the first side is solid and the second is not.
+    Subtraction,
+    /// Solid where both sides are solid.
+    Intersection,
+}
+
+impl CsgOperation {
+    /// Combines voxel `a` with voxel `b`, in that order for subtraction.
+    pub fn combine(self, a: Voxel, b: Voxel) -> Voxel {
+        let (distance, voxel_type) = match self {
+            CsgOperation::Union => {
+                if b.distance.0 < a.distance.0 {
+                    (b.distance, b.voxel_type)
+                } else {
+                    (a.distance, a.voxel_type)
+                }
+            }
+            CsgOperation::Subtraction => {
+                // Saturate so the most negative distance doesn't overflow.
+                let negated_b = b.distance.0.saturating_neg();
+                (VoxelDistance(a.distance.0.max(negated_b)), a.voxel_type)
+            }
+            CsgOperation::Intersection => {
+                if b.distance.0 > a.distance.0 {
+                    (b.distance, b.voxel_type)
+                } else {
+                    (a.distance, a.voxel_type)
+                }
+            }
+        };
+
+        if distance.0 < 0 {
+            Voxel {
+                distance,
+                voxel_type,
+            }
+        } else {
+            Voxel {
+                distance,
+                voxel_type: EMPTY_VOXEL.voxel_type,
+            }
+        }
+    }
+}
+
+/// Combines `a` with `b` over the extent of `a`. Points outside of `b`'s extent are treated as
+/// empty.
+pub fn combine_arrays(
+    a: &Array3x1<Voxel>,
+    b: &Array3x1<Voxel>,
+    operation: CsgOperation,
+) -> Array3x1<Voxel> {
+    let mut combined = a.clone();
+    for p in a.extent().iter_points() {
+        let b_voxel = if b.extent().contains(p) {
+            b.get(p)
+        } else {
+            EMPTY_VOXEL
+        };
+        *combined.get_mut(p) = operation.combine(a.get(p), b_voxel);
+    }
+
+    combined
+}
+
+/// Combines the map voxels in `extent` with `operand`, which is moved so its minimum is at
+/// `operand_min`. The map is the first side of the operation, so subtraction carves the operand out
+/// of the map. Points in `extent` that the operand doesn't cover are treated as empty in the
+/// operand, so an intersection clears them.
+pub fn combine_with_map(
+    map_reader: &VoxelChunkReader,
+    extent: &Extent3i,
+    operand: &Array3x1<Voxel>,
+    operand_min: Point3i,
+    operation: CsgOperation,
+    backbuffer: &mut EditedChunksBackBuffer,
+) {
+    let operand_extent = Extent3i::from_min_and_shape(operand_min, operand.extent().shape);
+    let to_operand = operand.extent().minimum - operand_min;
+    backbuffer.edit_voxels_out_of_place(map_reader, extent, |p: Point3i, v: &mut Voxel| {
+        let operand_voxel = if operand_extent.contains(p) {
+            operand.get(p + to_operand)
+        } else {
+            EMPTY_VOXEL
+        };
+        *v = operation.combine(*v, operand_voxel);
+    });
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::voxel::{empty_array, VoxelType};
+
+    /// A row of 4 voxels along X, solid with `voxel_type` from `solid_min_x` on.
+    fn half_solid_row(solid_min_x: i32, voxel_type: VoxelType) -> Array3x1<Voxel> {
+        let mut voxels = empty_array(Extent3i::from_min_and_shape(
+            PointN([0; 3]),
+            PointN([4, 1, 1]),
+        ));
+        for x in solid_min_x..4 {
+            *voxels.get_mut(PointN([x, 0, 0])) = Voxel {
+                voxel_type,
+                distance: VoxelDistance::from(-1.0),
+            };
+        }
+
+        voxels
+    }
+
+    #[test]
+    fn test_operations_combine_distances_and_types() {
+        let a = half_solid_row(1, VoxelType(1));
+        let b = half_solid_row(3, VoxelType(2));
+        let solid_types = |voxels: &Array3x1<Voxel>| -> Vec<VoxelType> {
+            (0..4)
+                .map(|x| voxels.get(PointN([x, 0, 0])))
+                .map(|v| {
+                    if v.distance.0 < 0 {
+                        v.voxel_type
+                    } else {
+                        EMPTY_VOXEL.voxel_type
+                    }
+                })
+                .collect()
+        };
+        let empty = EMPTY_VOXEL.voxel_type;
+
+        assert_eq!(
+            solid_types(&combine_arrays(&a, &b, CsgOperation::Union)),
+            vec![empty, VoxelType(1), VoxelType(1), VoxelType(1)]
+        );
+        assert_eq!(
+            solid_types(&combine_arrays(&a, &b, CsgOperation::Subtraction)),
+            vec![empty, VoxelType(1), VoxelType(1), empty]
+        );
+        assert_eq!(
+            solid_types(&combine_arrays(&a, &b, CsgOperation::Intersection)),
+            vec![empty, empty, empty, VoxelType(1)]
+        );
+    }
+}
@@ -1,73 +1,676 @@
 use crate::voxel::{
-    empty_array, empty_chunk_hash_map, Voxel, VoxelChunkHashMap, VoxelMap, VOXEL_CHUNK_SHAPE,
+    chunk_lock::{LockedChunkEditEvent, LockedChunks},
+    chunk_streaming::StoredChunkReader,
+    edit_history::{ChunkRestore, EditHistory, TransactionId, TransactionState},
+    edit_limits::{EditLimits, RejectedEditEvent},
+    empty_array, empty_chunk_hash_map,
+    generation::{GeneratedChunks, VoxelSource},
+    network::{ChunkDelta, NetworkChunkEdits},
+    session_recording::SessionRecorder,
+    Voxel, VoxelChunkHashMap, VoxelChunkReader, VoxelMap, VOXEL_CHUNK_SHAPE,
 };
 
-use amethyst::core::ecs::prelude::*;
+use amethyst::{
+    core::{ecs::prelude::*, Time},
+    shrev::EventChannel,
+};
 use building_blocks::prelude::*;
-use std::collections::HashSet;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
 #[cfg(feature = "profiler")]
 use thread_profiler::profile_scope;
 
+/// Identifies where queued edits come from, e.g. the local editor or a remote peer.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct EditSourceId(pub u32);
+
+impl EditSourceId {
+    pub const LOCAL: Self = EditSourceId(0);
+}
+
+/// Orders queued edits. Sequence numbers count up separately for each source.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct EditStamp {
+    pub source: EditSourceId,
+    pub sequence: u64,
+}
+
+/// Chunk meshes are built from their chunk plus this many voxels on every side, so edits this close
+/// to a chunk boundary change the neighboring chunks' meshes too.
+const MESH_PADDING: i32 = 1;
+
+pub type ChunkFilter = Box<dyn Fn(&Extent3i) -> bool + Send + Sync>;
+pub type VoxelEditFn = Box<dyn Fn(Point3i, &mut Voxel) + Send + Sync>;
+
+/// An edit that doesn't touch any voxels until the `VoxelDoubleBufferingSystem` runs.
+pub struct QueuedEdit {
+    pub stamp: EditStamp,
+    pub extent: Extent3i,
+    pub chunk_filter: ChunkFilter,
+    pub edit: VoxelEditFn,
+}
+
+/// A queued edit that overlaps too many chunks to apply in one frame. See
+/// `EditLimits::max_chunks_per_frame`.
+struct PendingEdit {
+    edit: QueuedEdit,
+    /// The chunks that haven't been edited yet, in order.
+    chunk_mins: Vec<Point3i>,
+    /// The transaction that every piece of the edit is recorded in, once the edit has been spread
+    /// over more than one frame.
+    transaction: Option<TransactionId>,
+}
+
 /// For the sake of pipelining, all voxels edits are first written out of place here. They get
 /// merged into the `VoxelMap` by the `VoxelDoubleBufferingSystem` at the end of a frame.
 pub struct EditedChunksBackBuffer {
     edited_voxels: VoxelChunkHashMap,
-    // Includes the edited chunks as well as their neighbors, all of which need to be re-meshed.
-    dirty_chunk_keys: HashSet<Point3i>,
+    // The part of each chunk that needs to be re-meshed. Includes the neighbors of edited chunks
+    // when the edits came within `MESH_PADDING` of their boundary.
+    dirty_extents: HashMap<Point3i, Extent3i>,
+    // Chunks that were produced by the `VoxelSource` and haven't been edited yet.
+    generated_chunk_keys: HashSet<Point3i>,
+    // Used in place of empty space for chunks that don't exist in the map yet.
+    source: Option<Arc<dyn VoxelSource>>,
+    // Stored copies of chunks that haven't been streamed into the map yet, which take precedence
+    // over the `source`.
+    stored: Option<StoredChunkReader>,
+    transactions: TransactionState,
+    // Chunks written by undo or redo, which shouldn't be recorded in the edit history.
+    untracked_chunk_keys: HashSet<Point3i>,
+    // Pristine chunks to remove from the map.
+    evicted_chunk_keys: HashSet<Point3i>,
+    // Stored chunks to remove from the map.
+    unloaded_chunk_keys: HashSet<Point3i>,
+    // Chunks streamed in from storage, which aren't edits.
+    loaded_chunk_keys: HashSet<Point3i>,
+    // Chunks received from an `EditSession`, which aren't shared again.
+    remote_chunk_keys: HashSet<Point3i>,
+    queued_edits: Vec<QueuedEdit>,
+    // The queued edit that's partway applied, which has to finish before the next one starts.
+    pending_edit: Option<PendingEdit>,
+    // A transaction that was opened to keep the pieces of a pending edit together, which is closed
+    // again once the edit is done.
+    resumed_transaction: Option<TransactionId>,
+    next_sequence: HashMap<EditSourceId, u64>,
+    deterministic: bool,
+    // The `EditLimits` as of the last merge, which direct edits are checked against.
+    limits: EditLimits,
+    // Direct edits that violated the `limits`, to be sent as events when the backbuffer is merged.
+    rejected_edits: Vec<RejectedEditEvent>,
 }
 
 impl EditedChunksBackBuffer {
     pub fn new() -> Self {
+        Self::with_chunk_shape(VOXEL_CHUNK_SHAPE)
+    }
+
+    /// A backbuffer for a map whose chunks have `chunk_shape`; see `VoxelMap::chunk_shape`.
+    pub fn with_chunk_shape(chunk_shape: Point3i) -> Self {
         Self {
-            edited_voxels: empty_chunk_hash_map(),
-            dirty_chunk_keys: Default::default(),
+            edited_voxels: empty_chunk_hash_map(chunk_shape),
+            dirty_extents: Default::default(),
+            generated_chunk_keys: Default::default(),
+            source: None,
+            stored: None,
+            transactions: Default::default(),
+            untracked_chunk_keys: Default::default(),
+            evicted_chunk_keys: Default::default(),
+            unloaded_chunk_keys: Default::default(),
+            loaded_chunk_keys: Default::default(),
+            remote_chunk_keys: Default::default(),
+            queued_edits: Vec::new(),
+            pending_edit: None,
+            resumed_transaction: None,
+            next_sequence: HashMap::new(),
+            deterministic: false,
+            limits: EditLimits::default(),
+            rejected_edits: Vec::new(),
+        }
+    }
+
+    /// In deterministic mode, queued edits are applied in order of their `EditStamp`s, rather than
+    /// the order they were queued in. As long as every machine queues the same stamped edits, they
+    /// will all end up with identical maps, regardless of the order systems ran in. Edits made
+    /// directly, e.g. with `edit_voxels_out_of_place`, aren't stamped, so they're still applied in
+    /// the order they were made, before any queued edits.
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    /// Queues an edit of `extent` from `source`, stamped with the source's next sequence number.
+    /// Queued edits are applied with `edit_chunks_in_parallel` when the backbuffer is merged, after
+    /// any edits made directly this frame. Edits that overlap more than
+    /// `EditLimits::max_chunks_per_frame` chunks are applied over several frames, in a single
+    /// transaction, and the edits queued after them wait until they're done.
+    pub fn queue_edit(
+        &mut self,
+        source: EditSourceId,
+        extent: Extent3i,
+        chunk_filter: impl Fn(&Extent3i) -> bool + Send + Sync + 'static,
+        edit: impl Fn(Point3i, &mut Voxel) + Send + Sync + 'static,
+    ) -> EditStamp {
+        let stamp = self.next_stamp(source);
+        self.queue_stamped_edit(QueuedEdit {
+            stamp,
+            extent,
+            chunk_filter: Box::new(chunk_filter),
+            edit: Box::new(edit),
+        });
+
+        stamp
+    }
+
+    /// Reserves the next sequence number for `source`.
+    pub fn next_stamp(&mut self, source: EditSourceId) -> EditStamp {
+        let next_sequence = self.next_sequence.entry(source).or_insert(0);
+        let stamp = EditStamp {
+            source,
+            sequence: *next_sequence,
+        };
+        *next_sequence += 1;
+
+        stamp
+    }
+
+    /// Queues an edit that was already stamped, e.g. by a remote peer or a recording.
+    pub fn queue_stamped_edit(&mut self, edit: QueuedEdit) {
+        let next_sequence = self.next_sequence.entry(edit.stamp.source).or_insert(0);
+        *next_sequence = (*next_sequence).max(edit.stamp.sequence + 1);
+        self.queued_edits.push(edit);
+    }
+
+    /// The number of chunks that the edit being spread over several frames still has to edit.
+    pub fn num_pending_chunks(&self) -> usize {
+        self.pending_edit.as_ref().map_or(0, |p| p.chunk_mins.len())
+    }
+
+    /// Applies queued edits until `limits.max_chunks_per_frame` chunks have been edited, leaving
+    /// the rest for the next frame. Edits that violate the `limits` are skipped and returned as
+    /// events.
+    fn apply_queued_edits(
+        &mut self,
+        reader: &VoxelChunkReader,
+        limits: &EditLimits,
+    ) -> Vec<RejectedEditEvent> {
+        // The last piece of a pending edit was merged on the previous frame.
+        if let Some(id) = self.resumed_transaction.take() {
+            if self.transactions.open_transaction() == Some(id) {
+                self.transactions.end();
+            }
+        }
+
+        let mut queued_edits = std::mem::replace(&mut self.queued_edits, Vec::new());
+        if self.deterministic {
+            queued_edits.sort_by_key(|e| e.stamp);
+        }
+        let mut queued_edits = queued_edits.into_iter();
+        let mut chunk_budget = limits.max_chunks_per_frame.max(1);
+        let mut rejected = Vec::new();
+        while chunk_budget > 0 {
+            let mut pending = match self.pending_edit.take() {
+                Some(pending) => pending,
+                None => match queued_edits.next() {
+                    Some(edit) => {
+                        if let Err(violation) = limits.check_extent(&edit.extent) {
+                            rejected.push(RejectedEditEvent {
+                                stamp: Some(edit.stamp),
+                                violation,
+                            });
+                            continue;
+                        }
+                        PendingEdit {
+                            chunk_mins: reader
+                                .indexer
+                                .chunk_mins_for_extent(&edit.extent)
+                                .collect(),
+                            edit,
+                            transaction: None,
+                        }
+                    }
+                    None => break,
+                },
+            };
+
+            let num_chunks = chunk_budget.min(pending.chunk_mins.len());
+            let chunk_mins: Vec<Point3i> = pending.chunk_mins.drain(..num_chunks).collect();
+            chunk_budget -= num_chunks;
+            self.edit_chunk_mins_in_parallel(
+                reader,
+                &pending.edit.extent,
+                chunk_mins,
+                &pending.edit.chunk_filter,
+                &pending.edit.edit,
+            );
+
+            // Keep every piece of the edit in the same transaction, even if the one it started in
+            // was ended in the meantime, e.g. by releasing the brush.
+            if pending.transaction.is_some() || !pending.chunk_mins.is_empty() {
+                if self.transactions.open_transaction().is_none() {
+                    let id = match pending.transaction {
+                        Some(id) => {
+                            self.transactions.resume(id);
+
+                            id
+                        }
+                        None => self.transactions.begin(),
+                    };
+                    self.resumed_transaction = Some(id);
+                }
+                pending.transaction = pending
+                    .transaction
+                    .or_else(|| self.transactions.open_transaction());
+            }
+            if !pending.chunk_mins.is_empty() {
+                self.pending_edit = Some(pending);
+            }
+        }
+        self.queued_edits = queued_edits.collect();
+
+        rejected
+    }
+
+    /// Starts grouping all edits into a single transaction until `end_transaction` is called, e.g.
+    /// for the duration of a brush stroke. Otherwise, each frame's edits are their own transaction.
+    pub fn begin_transaction(&mut self) -> TransactionId {
+        self.transactions.begin()
+    }
+
+    pub fn end_transaction(&mut self) {
+        self.transactions.end()
+    }
+
+    pub fn open_transaction(&self) -> Option<TransactionId> {
+        self.transactions.open_transaction()
+    }
+
+    /// Overwrites whole chunks, e.g. to undo or redo a transaction. Chunks that should no longer
+    /// exist are replaced with generated or empty chunks. These writes aren't recorded in the
+    /// `EditHistory`.
+    pub fn restore_chunks(&mut self, reader: &VoxelChunkReader, chunks: ChunkRestore) {
+        for (chunk_min, chunk) in chunks.into_iter() {
+            let chunk_extent = reader.indexer.extent_for_chunk_with_min(chunk_min);
+            let chunk = match chunk {
+                Some(chunk) => {
+                    self.generated_chunk_keys.remove(&chunk_min);
+                    chunk
+                }
+                None => match self
+                    .source
+                    .as_ref()
+                    .and_then(|s| s.generate_chunk(&chunk_extent))
+                {
+                    Some(chunk) => {
+                        self.generated_chunk_keys.insert(chunk_min);
+                        chunk
+                    }
+                    None => empty_array(chunk_extent),
+                },
+            };
+            self.edited_voxels
+                .write_chunk(ChunkKey::new(0, chunk_min), chunk);
+            self.untracked_chunk_keys.insert(chunk_min);
+            self.loaded_chunk_keys.remove(&chunk_min);
+            self.remote_chunk_keys.remove(&chunk_min);
+            self.mark_extent_dirty(reader, &chunk_extent);
+        }
+    }
+
+    /// Removes generated chunks from the map, e.g. because they're far from the camera. They will be
+    /// generated again if they're requested later. Chunks that are edited in the same frame are
+    /// kept.
+    pub fn evict_chunks(&mut self, reader: &VoxelChunkReader, chunk_mins: Vec<Point3i>) {
+        for chunk_min in chunk_mins.into_iter() {
+            let chunk_extent = reader.indexer.extent_for_chunk_with_min(chunk_min);
+            self.evicted_chunk_keys.insert(chunk_min);
+            self.mark_extent_dirty(reader, &chunk_extent);
+        }
+    }
+
+    /// Writes chunks that were streamed in from `StoredChunks`. Like generation, loading isn't an
+    /// edit that can be undone. Chunks that were already edited this frame are skipped, since their
+    /// edits started from the same stored copy.
+    pub fn load_chunks(
+        &mut self,
+        reader: &VoxelChunkReader,
+        chunks: Vec<(Point3i, Array3x1<Voxel>)>,
+    ) {
+        for (chunk_min, chunk) in chunks.into_iter() {
+            let chunk_key = ChunkKey::new(0, chunk_min);
+            if self.edited_voxels.get_chunk(chunk_key).is_some() {
+                continue;
+            }
+            let extent = *chunk.extent();
+            self.edited_voxels.write_chunk(chunk_key, chunk);
+            self.untracked_chunk_keys.insert(chunk_min);
+            self.loaded_chunk_keys.insert(chunk_min);
+            self.mark_extent_dirty(reader, &extent);
+        }
+    }
+
+    /// Writes chunks that were received from the other editors in an `EditSession`, over any edits
+    /// made to the same chunks this frame. They aren't recorded in the `EditHistory`, and they
+    /// aren't shared with the session again, except by the host; see the `network` module.
+    pub fn receive_chunks(
+        &mut self,
+        reader: &VoxelChunkReader,
+        chunks: Vec<(Point3i, Array3x1<Voxel>)>,
+    ) {
+        for (chunk_min, chunk) in chunks.into_iter() {
+            let extent = *chunk.extent();
+            self.edited_voxels
+                .write_chunk(ChunkKey::new(0, chunk_min), chunk);
+            self.generated_chunk_keys.remove(&chunk_min);
+            self.loaded_chunk_keys.remove(&chunk_min);
+            self.untracked_chunk_keys.insert(chunk_min);
+            self.remote_chunk_keys.insert(chunk_min);
+            self.mark_extent_dirty(reader, &extent);
+        }
+    }
+
+    /// Writes the voxels that were changed by the other editors in an `EditSession`, on top of any
+    /// edits made to the same chunks this frame, so edits to different voxels of a chunk are all
+    /// kept. Chunks that weren't already edited this frame aren't recorded in the `EditHistory`,
+    /// and like `receive_chunks`, they're only shared with the session again by the host.
+    pub fn receive_voxels(&mut self, reader: &VoxelChunkReader, deltas: Vec<ChunkDelta>) {
+        for delta in deltas.into_iter() {
+            let chunk_min = delta.chunk_min;
+            let chunk_key = ChunkKey::new(0, chunk_min);
+            let is_tracked = self.edited_voxels.get_chunk(chunk_key).is_some()
+                && !self.untracked_chunk_keys.contains(&chunk_min);
+            let (source, stored) = (&self.source, &self.stored);
+            let chunk = self
+                .edited_voxels
+                .get_mut_chunk_or_insert_with(chunk_key, || {
+                    read_chunk_to_edit(reader, source, stored, chunk_min)
+                });
+            let mut changed_extent: Option<Extent3i> = None;
+            for (p, voxel) in delta.voxels.into_iter() {
+                *chunk.get_mut(p) = voxel;
+                let voxel_extent = Extent3i::from_min_and_shape(p, PointN([1; 3]));
+                changed_extent = Some(match changed_extent {
+                    Some(extent) => bounding_extent(&extent, &voxel_extent),
+                    None => voxel_extent,
+                });
+            }
+
+            self.generated_chunk_keys.remove(&chunk_min);
+            self.loaded_chunk_keys.remove(&chunk_min);
+            if !is_tracked {
+                self.untracked_chunk_keys.insert(chunk_min);
+                self.remote_chunk_keys.insert(chunk_min);
+            }
+            if let Some(extent) = changed_extent {
+                self.mark_extent_dirty(reader, &extent);
+            }
+        }
+    }
+
+    /// Removes chunks from the map after they've been copied into `StoredChunks`. Chunks that are
+    /// edited in the same frame are kept.
+    pub fn unload_chunks(&mut self, reader: &VoxelChunkReader, chunk_mins: Vec<Point3i>) {
+        for chunk_min in chunk_mins.into_iter() {
+            let chunk_extent = reader.indexer.extent_for_chunk_with_min(chunk_min);
+            self.unloaded_chunk_keys.insert(chunk_min);
+            self.mark_extent_dirty(reader, &chunk_extent);
+        }
+    }
+
+    /// Registers a generator for chunks that are missing from the map. Edits to missing chunks will
+    /// start from the generated voxels instead of empty space.
+    pub fn set_voxel_source(&mut self, source: Option<Arc<dyn VoxelSource>>) {
+        self.source = source;
+    }
+
+    pub fn voxel_source(&self) -> Option<&Arc<dyn VoxelSource>> {
+        self.source.as_ref()
+    }
+
+    /// Registers the stored chunks of a streamed map. Edits to stored chunks that haven't been
+    /// loaded into the map yet start from their stored copy. The `ChunkStreamingSystem` does this
+    /// for the `StoredChunks` resource.
+    pub fn set_stored_chunks(&mut self, stored: Option<StoredChunkReader>) {
+        self.stored = stored;
+    }
+
+    /// Re-meshes the chunks at `chunk_mins` without editing them, e.g. after the palette changes.
+    pub fn mark_chunks_dirty(&mut self, chunk_mins: impl IntoIterator<Item = Point3i>) {
+        for chunk_min in chunk_mins.into_iter() {
+            let chunk_extent = self
+                .edited_voxels
+                .indexer
+                .extent_for_chunk_with_min(chunk_min);
+            self.add_dirty_extent(chunk_min, chunk_extent);
+        }
+    }
+
+    /// Marks the voxels in `extent` as changed. Every chunk whose mesh reads any of them is
+    /// dirtied, so neighboring chunks are only dirtied if `extent` comes within `MESH_PADDING` of
+    /// them.
+    fn mark_extent_dirty(&mut self, reader: &VoxelChunkReader, extent: &Extent3i) {
+        let padded_extent = extent.padded(MESH_PADDING);
+        for chunk_min in reader.indexer.chunk_mins_for_extent(&padded_extent) {
+            let chunk_extent = reader.indexer.extent_for_chunk_with_min(chunk_min);
+            self.add_dirty_extent(chunk_min, padded_extent.intersection(&chunk_extent));
+        }
+    }
+
+    fn add_dirty_extent(&mut self, chunk_min: Point3i, extent: Extent3i) {
+        self.dirty_extents
+            .entry(chunk_min)
+            .and_modify(|dirty| *dirty = bounding_extent(dirty, &extent))
+            .or_insert(extent);
+    }
+
+    /// Generates the chunks at `chunk_mins` with the registered `VoxelSource` (in parallel) and
+    /// writes them into the backbuffer. The caller is responsible for making sure the chunks don't
+    /// already exist in the map.
+    pub fn generate_missing_chunks(&mut self, reader: &VoxelChunkReader, chunk_mins: Vec<Point3i>) {
+        let source = match self.source.as_ref() {
+            Some(s) => s.clone(),
+            None => return,
+        };
+
+        let generated: Vec<(Point3i, Array3x1<Voxel>)> = chunk_mins
+            .into_par_iter()
+            .filter_map(|chunk_min| {
+                let extent = reader.indexer.extent_for_chunk_with_min(chunk_min);
+
+                source
+                    .generate_chunk(&extent)
+                    .map(|chunk| (chunk_min, chunk))
+            })
+            .collect();
+
+        for (chunk_min, chunk) in generated.into_iter() {
+            let extent = *chunk.extent();
+            self.edited_voxels
+                .write_chunk(ChunkKey::new(0, chunk_min), chunk);
+            self.generated_chunk_keys.insert(chunk_min);
+            // Generation isn't an edit that can be undone.
+            self.untracked_chunk_keys.insert(chunk_min);
+            self.mark_extent_dirty(reader, &extent);
+        }
+    }
+
+    /// Checks a direct edit of `extent` against the `EditLimits` that were in place when the
+    /// backbuffer was last merged. Rejected edits are sent as `RejectedEditEvent`s at the next
+    /// merge.
+    fn check_direct_edit(&mut self, extent: &Extent3i) -> bool {
+        match self.limits.check_extent(extent) {
+            Ok(()) => true,
+            Err(violation) => {
+                log::warn!("Rejected edit of {:?}: {:?}", extent, violation);
+                self.rejected_edits.push(RejectedEditEvent {
+                    stamp: None,
+                    violation,
+                });
+
+                false
+            }
         }
     }
 
     /// This function does read-modify-write of the voxels in `extent`, reading from `reader` and
     /// writing into the backbuffer. This enables parallelism between voxel editors and the chunk
-    /// processor. All edited chunks and their neighbors will be marked as dirty.
+    /// processor. The edited chunks are marked dirty, along with any neighbors that `extent` comes
+    /// within `MESH_PADDING` of. Edits that violate the `EditLimits` are skipped.
     pub fn edit_voxels_out_of_place(
         &mut self,
-        reader: &CompressibleChunkMapReader3x1<Lz4, Voxel>,
+        reader: &VoxelChunkReader,
         extent: &Extent3i,
         edit_func: impl Fn(Point3i, &mut Voxel),
     ) {
+        if !self.check_direct_edit(extent) {
+            return;
+        }
+
         // Copy any of the overlapping chunks that don't already exist in the backbuffer, i.e. those
         // chunks which haven't been modified by this function yet.
+        let (source, stored) = (&self.source, &self.stored);
         for chunk_min in reader.indexer.chunk_mins_for_extent(extent) {
             let chunk_key = ChunkKey::new(0, chunk_min);
             self.edited_voxels
                 .get_mut_chunk_or_insert_with(chunk_key, || {
-                    reader.get_chunk(chunk_key).cloned().unwrap_or(empty_array(
-                        reader.indexer.extent_for_chunk_with_min(chunk_min),
-                    ))
+                    read_chunk_to_edit(reader, source, stored, chunk_min)
                 });
+            // The chunk is no longer pristine, so it needs to be persisted.
+            self.generated_chunk_keys.remove(&chunk_min);
+            self.untracked_chunk_keys.remove(&chunk_min);
+            self.loaded_chunk_keys.remove(&chunk_min);
+            self.remote_chunk_keys.remove(&chunk_min);
         }
 
-        // Mark the chunks and their neighbors as dirty.
-        let extent_with_neighbor_chunks = Extent3i::from_min_and_max(
-            extent.minimum - VOXEL_CHUNK_SHAPE,
-            extent.max() + VOXEL_CHUNK_SHAPE,
-        );
-        for chunk_key in reader
-            .indexer
-            .chunk_mins_for_extent(&extent_with_neighbor_chunks)
-        {
-            self.dirty_chunk_keys.insert(chunk_key);
-        }
+        // Mark the edited chunks dirty, and any neighbors whose meshes overlap the edit.
+        self.mark_extent_dirty(reader, extent);
 
         // Edit the backbuffer.
         self.edited_voxels
             .lod_view_mut(0)
             .for_each_mut(extent, edit_func);
     }
+
+    /// Like `edit_voxels_out_of_place`, but meant for very large edits. Each chunk is edited on its
+    /// own thread, chunks for which `chunk_filter` returns false are skipped entirely, and chunks
+    /// whose voxels don't actually change are neither written to the backbuffer nor marked dirty.
+    /// Edits that violate the `EditLimits` are skipped.
+    pub fn edit_chunks_in_parallel(
+        &mut self,
+        reader: &VoxelChunkReader,
+        extent: &Extent3i,
+        chunk_filter: impl Fn(&Extent3i) -> bool,
+        edit_func: impl Fn(Point3i, &mut Voxel) + Sync,
+    ) {
+        #[cfg(feature = "profiler")]
+        profile_scope!("edit_chunks_in_parallel");
+
+        if !self.check_direct_edit(extent) {
+            return;
+        }
+
+        let chunk_mins: Vec<Point3i> = reader.indexer.chunk_mins_for_extent(extent).collect();
+        self.edit_chunk_mins_in_parallel(reader, extent, chunk_mins, chunk_filter, edit_func);
+    }
+
+    /// Like `edit_chunks_in_parallel`, but only edits the part of `extent` in the chunks at
+    /// `chunk_mins`.
+    fn edit_chunk_mins_in_parallel(
+        &mut self,
+        reader: &VoxelChunkReader,
+        extent: &Extent3i,
+        chunk_mins: Vec<Point3i>,
+        chunk_filter: impl Fn(&Extent3i) -> bool,
+        edit_func: impl Fn(Point3i, &mut Voxel) + Sync,
+    ) {
+        // Gather the chunks up front, since the reader can't be shared between threads.
+        let mut chunks = Vec::new();
+        for chunk_min in chunk_mins.into_iter() {
+            let chunk_extent = reader.indexer.extent_for_chunk_with_min(chunk_min);
+            let edit_extent = extent.intersection(&chunk_extent);
+            if !chunk_filter(&edit_extent) {
+                continue;
+            }
+
+            let chunk_key = ChunkKey::new(0, chunk_min);
+            let (chunk, already_edited) = match self.edited_voxels.pop_chunk(chunk_key) {
+                Some(chunk) => (chunk, true),
+                None => (
+                    read_chunk_to_edit(reader, &self.source, &self.stored, chunk_min),
+                    false,
+                ),
+            };
+            chunks.push((chunk_min, edit_extent, chunk, already_edited));
+        }
+
+        let edited: Vec<(Point3i, Extent3i, Array3x1<Voxel>, bool, bool)> = chunks
+            .into_par_iter()
+            .map(|(chunk_min, edit_extent, mut chunk, already_edited)| {
+                let mut changed = false;
+                chunk.for_each_mut(&edit_extent, |p: Point3i, v: &mut Voxel| {
+                    let old = *v;
+                    edit_func(p, v);
+                    changed |= *v != old;
+                });
+
+                (chunk_min, edit_extent, chunk, already_edited, changed)
+            })
+            .collect();
+
+        for (chunk_min, edit_extent, chunk, already_edited, changed) in edited.into_iter() {
+            if changed {
+                // The chunk is no longer pristine, so it needs to be persisted.
+                self.generated_chunk_keys.remove(&chunk_min);
+                self.untracked_chunk_keys.remove(&chunk_min);
+                self.loaded_chunk_keys.remove(&chunk_min);
+                self.remote_chunk_keys.remove(&chunk_min);
+                self.mark_extent_dirty(reader, &edit_extent);
+            }
+            if changed || already_edited {
+                self.edited_voxels
+                    .write_chunk(ChunkKey::new(0, chunk_min), chunk);
+            }
+        }
+    }
+}
+
+/// The voxels that an edit of the chunk at `chunk_min` starts from: the chunk in the map, its
+/// stored copy if it hasn't been streamed in yet, or else the generated or empty chunk.
+fn read_chunk_to_edit(
+    reader: &VoxelChunkReader,
+    source: &Option<Arc<dyn VoxelSource>>,
+    stored: &Option<StoredChunkReader>,
+    chunk_min: Point3i,
+) -> Array3x1<Voxel> {
+    if let Some(chunk) = reader.get_chunk(ChunkKey::new(0, chunk_min)) {
+        return chunk.clone();
+    }
+    if let Some(chunk) = stored.as_ref().and_then(|s| s.read_chunk(chunk_min)) {
+        return chunk;
+    }
+    let chunk_extent = reader.indexer.extent_for_chunk_with_min(chunk_min);
+
+    source
+        .as_ref()
+        .and_then(|s| s.generate_chunk(&chunk_extent))
+        .unwrap_or_else(|| empty_array(chunk_extent))
+}
+
+fn bounding_extent(a: &Extent3i, b: &Extent3i) -> Extent3i {
+    Extent3i::from_min_and_max(a.minimum.meet(b.minimum), a.max().join(b.max()))
 }
 
 #[derive(Default)]
 pub struct DirtyChunks {
-    pub chunks: HashSet<Point3i>,
+    /// The bounding extent of the voxels that changed in or around each chunk since it was last
+    /// meshed.
+    pub chunks: HashMap<Point3i, Extent3i>,
 }
 
 /// The system responsible for merging the `EditedChunksBackBuffer` into the `VoxelMap`. This allows
@@ -76,24 +679,185 @@ pub struct DirtyChunks {
 pub struct VoxelDoubleBufferingSystem;
 
 impl<'a> System<'a> for VoxelDoubleBufferingSystem {
+    #[allow(clippy::type_complexity)]
     type SystemData = (
         Write<'a, Option<DirtyChunks>>,
+        Write<'a, GeneratedChunks>,
+        Write<'a, EditHistory>,
+        Read<'a, LockedChunks>,
+        Write<'a, EventChannel<LockedChunkEditEvent>>,
+        Read<'a, EditLimits>,
+        Write<'a, EventChannel<RejectedEditEvent>>,
+        Write<'a, SessionRecorder>,
+        Write<'a, NetworkChunkEdits>,
+        Read<'a, Time>,
         WriteExpect<'a, EditedChunksBackBuffer>,
         WriteExpect<'a, VoxelMap>,
     );
 
-    fn run(&mut self, (mut dirty_chunks, mut edits, mut map): Self::SystemData) {
+    fn run(
+        &mut self,
+        (
+            mut dirty_chunks,
+            mut generated,
+            mut history,
+            locked_chunks,
+            mut locked_edit_events,
+            edit_limits,
+            mut rejected_edit_events,
+            mut recorder,
+            mut network,
+            time,
+            mut edits,
+            mut map,
+        ): Self::SystemData,
+    ) {
         #[cfg(feature = "profiler")]
         profile_scope!("voxel_double_buffering");
 
-        // Create a new backbuffer.
+        // Apply the queued edits on top of this frame's other edits.
+        let local_cache = LocalChunkCache3::new();
+        let rejected = edits.apply_queued_edits(&map.voxels.reader(&local_cache), &edit_limits);
+        rejected_edit_events.iter_write(rejected);
+        map.voxels.storage_mut().flush_local_cache(local_cache);
+
+        // Create a new backbuffer, keeping the same voxel source, transaction state and edit
+        // sequence numbers.
+        let mut new_edits = EditedChunksBackBuffer::with_chunk_shape(map.chunk_shape());
+        new_edits.set_voxel_source(edits.source.clone());
+        new_edits.set_stored_chunks(edits.stored.clone());
+        new_edits.transactions = edits.transactions.clone();
+        new_edits.next_sequence = edits.next_sequence.clone();
+        new_edits.deterministic = edits.deterministic;
+        new_edits.limits = edit_limits.clone();
+        // Edits that didn't fit in this frame's budget carry over to the next frame.
+        new_edits.queued_edits = std::mem::replace(&mut edits.queued_edits, Vec::new());
+        new_edits.pending_edit = edits.pending_edit.take();
+        new_edits.resumed_transaction = edits.resumed_transaction.take();
         let EditedChunksBackBuffer {
             edited_voxels,
-            dirty_chunk_keys,
-        } = std::mem::replace(&mut *edits, EditedChunksBackBuffer::new());
+            dirty_extents,
+            generated_chunk_keys,
+            untracked_chunk_keys,
+            evicted_chunk_keys,
+            unloaded_chunk_keys,
+            loaded_chunk_keys,
+            remote_chunk_keys,
+            rejected_edits,
+            ..
+        } = std::mem::replace(&mut *edits, new_edits);
+        rejected_edit_events.iter_write(rejected_edits);
+
+        // Discard any edits to locked chunks. Generated, loaded and other untracked chunks aren't
+        // edits, so they always stream in. The chunks a host receives from its clients are edits,
+        // but a session client can't refuse the host's chunks.
+        let is_edit = |chunk_min: &Point3i| {
+            if remote_chunk_keys.contains(chunk_min) {
+                !network.is_client()
+            } else {
+                !generated_chunk_keys.contains(chunk_min)
+                    && !loaded_chunk_keys.contains(chunk_min)
+                    && !untracked_chunk_keys.contains(chunk_min)
+            }
+        };
+        let (locked_edits, edited_chunks): (Vec<_>, Vec<_>) = edited_voxels
+            .take_storage()
+            .into_iter()
+            .partition(|(key, _)| locked_chunks.is_locked(&key.minimum) && is_edit(&key.minimum));
+        if !locked_edits.is_empty() {
+            locked_edit_events.single_write(LockedChunkEditEvent {
+                chunk_mins: locked_edits
+                    .into_iter()
+                    .map(|(key, _)| key.minimum)
+                    .collect(),
+            });
+        }
+
+        // Record the state of the chunks before and after this frame's edits.
+        if edited_chunks
+            .iter()
+            .any(|(key, _)| !untracked_chunk_keys.contains(&key.minimum))
+        {
+            let transaction = edits.transactions.current_or_new();
+            let local_cache = LocalChunkCache3::new();
+            let reader = map.voxels.reader(&local_cache);
+            for (chunk_key, chunk) in edited_chunks.iter() {
+                if untracked_chunk_keys.contains(&chunk_key.minimum) {
+                    continue;
+                }
+                history.record(
+                    transaction,
+                    chunk_key.minimum,
+                    reader.get_chunk(*chunk_key).cloned(),
+                    chunk.clone(),
+                );
+            }
+        }
+
+        // Chunks that were actually edited here are shared with the rest of the `EditSession`. A
+        // client merges its own edits right away, and sends the host the voxels they changed.
+        let is_shared = |chunk_min: &Point3i| {
+            !generated_chunk_keys.contains(chunk_min)
+                && !loaded_chunk_keys.contains(chunk_min)
+                && !remote_chunk_keys.contains(chunk_min)
+        };
+        if network.is_client() {
+            let local_cache = LocalChunkCache3::new();
+            let reader = map.voxels.reader(&local_cache);
+            let deltas: Vec<ChunkDelta> = edited_chunks
+                .iter()
+                .filter(|(key, _)| is_shared(&key.minimum))
+                .filter_map(|(key, chunk)| {
+                    let base =
+                        read_chunk_to_edit(&reader, &edits.source, &edits.stored, key.minimum);
+
+                    ChunkDelta::between(&base, chunk)
+                })
+                .collect();
+            network.outgoing_deltas.extend(deltas);
+        }
+
+        // Evict chunks that weren't edited in the meantime.
+        let edited_chunk_keys: HashSet<Point3i> =
+            edited_chunks.iter().map(|(key, _)| key.minimum).collect();
+        for chunk_min in evicted_chunk_keys.into_iter() {
+            if edited_chunk_keys.contains(&chunk_min) || !generated.is_pristine(&chunk_min) {
+                continue;
+            }
+            map.voxels.pop_chunk(ChunkKey::new(0, chunk_min));
+            generated.forget(&chunk_min);
+        }
+        for chunk_min in unloaded_chunk_keys.into_iter() {
+            if !edited_chunk_keys.contains(&chunk_min) {
+                map.voxels.pop_chunk(ChunkKey::new(0, chunk_min));
+            }
+        }
+
+        if recorder.is_recording() {
+            let chunks = edited_chunks
+                .iter()
+                .map(|(key, chunk)| (key.minimum, chunk))
+                .collect();
+            if let Err(e) =
+                recorder.record(time.frame_number(), time.absolute_time_seconds(), chunks)
+            {
+                log::error!("Failed to record merged chunks: {:?}", e);
+            }
+        }
 
         // Merge the edits into the map.
-        for (chunk_key, chunk) in edited_voxels.take_storage().into_iter() {
+        for (chunk_key, chunk) in edited_chunks.into_iter() {
+            // The host sends every change to the map, including the ones clients asked for.
+            if network.is_host()
+                && (is_shared(&chunk_key.minimum) || remote_chunk_keys.contains(&chunk_key.minimum))
+            {
+                network.outgoing.push((chunk_key.minimum, chunk.clone()));
+            }
+            if generated_chunk_keys.contains(&chunk_key.minimum) {
+                generated.mark_generated(chunk_key.minimum);
+            } else {
+                generated.mark_edited(&chunk_key.minimum);
+            }
             map.voxels.write_chunk(chunk_key, chunk);
         }
 
@@ -101,7 +865,7 @@ impl<'a> System<'a> for VoxelDoubleBufferingSystem {
         // frame.
         assert!(dirty_chunks.is_none());
         *dirty_chunks = Some(DirtyChunks {
-            chunks: dirty_chunk_keys,
+            chunks: dirty_extents,
         });
     }
 }
@@ -0,0 +1,239 @@
+use crate::voxel::Voxel;
+
+use building_blocks::prelude::*;
+use std::collections::{hash_map::Entry, HashMap, VecDeque};
+
+/// Identifies a group of edits that should be treated as one logical operation, like a whole brush
+/// stroke from press to release.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct TransactionId(pub u64);
+
+/// Hands out transaction IDs and tracks which transaction is open. Edits made while no transaction
+/// is open are grouped by frame.
+#[derive(Clone, Debug, Default)]
+pub struct TransactionState {
+    next_id: u64,
+    open: Option<TransactionId>,
+}
+
+impl TransactionState {
+    fn allocate(&mut self) -> TransactionId {
+        let id = TransactionId(self.next_id);
+        self.next_id += 1;
+
+        id
+    }
+
+    pub fn begin(&mut self) -> TransactionId {
+        let id = self.allocate();
+        self.open = Some(id);
+
+        id
+    }
+
+    pub fn end(&mut self) {
+        self.open = None;
+    }
+
+    pub fn open_transaction(&self) -> Option<TransactionId> {
+        self.open
+    }
+
+    /// Opens a transaction that was already begun, so more edits can be added to it.
+    pub fn resume(&mut self, id: TransactionId) {
+        self.open = Some(id);
+    }
+
+    /// The transaction that edits merged this frame belong to.
+    pub fn current_or_new(&mut self) -> TransactionId {
+        match self.open {
+            Some(id) => id,
+            None => self.allocate(),
+        }
+    }
+}
+
+/// The contents of a chunk before and after a transaction. `before` is `None` if the chunk didn't
+/// exist in the map yet.
+pub struct ChunkDelta {
+    pub before: Option<Array3x1<Voxel>>,
+    pub after: Array3x1<Voxel>,
+}
+
+pub struct EditTransaction {
+    pub id: TransactionId,
+    pub chunks: HashMap<Point3i, ChunkDelta>,
+}
+
+impl EditTransaction {
+    fn new(id: TransactionId) -> Self {
+        Self {
+            id,
+            chunks: HashMap::new(),
+        }
+    }
+
+    fn record(
+        &mut self,
+        chunk_min: Point3i,
+        before: Option<Array3x1<Voxel>>,
+        after: Array3x1<Voxel>,
+    ) {
+        match self.chunks.entry(chunk_min) {
+            // Keep the state from before the transaction started.
+            Entry::Occupied(mut entry) => entry.get_mut().after = after,
+            Entry::Vacant(entry) => {
+                entry.insert(ChunkDelta { before, after });
+            }
+        }
+    }
+}
+
+/// Chunks to write back into the map in order to undo or redo a transaction. `None` means the
+/// chunk should go back to not existing.
+pub type ChunkRestore = Vec<(Point3i, Option<Array3x1<Voxel>>)>;
+
+const DEFAULT_MAX_UNDO_DEPTH: usize = 64;
+
+/// Undo and redo stacks of whole transactions, recorded by the `VoxelDoubleBufferingSystem` as
+/// edits are merged into the map.
+pub struct EditHistory {
+    undo_stack: VecDeque<EditTransaction>,
+    redo_stack: Vec<EditTransaction>,
+    max_undo_depth: usize,
+}
+
+impl Default for EditHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_UNDO_DEPTH)
+    }
+}
+
+impl EditHistory {
+    pub fn new(max_undo_depth: usize) -> Self {
+        Self {
+            undo_stack: VecDeque::new(),
+            redo_stack: Vec::new(),
+            max_undo_depth,
+        }
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        id: TransactionId,
+        chunk_min: Point3i,
+        before: Option<Array3x1<Voxel>>,
+        after: Array3x1<Voxel>,
+    ) {
+        // Any new edit invalidates the redo stack.
+        self.redo_stack.clear();
+
+        if self.undo_stack.back().map(|t| t.id) != Some(id) {
+            self.undo_stack.push_back(EditTransaction::new(id));
+            if self.undo_stack.len() > self.max_undo_depth {
+                self.undo_stack.pop_front();
+            }
+        }
+        self.undo_stack
+            .back_mut()
+            .unwrap()
+            .record(chunk_min, before, after);
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    /// Pops the last transaction and returns the chunks needed to revert it.
+    pub fn undo(&mut self) -> Option<ChunkRestore> {
+        let transaction = self.undo_stack.pop_back()?;
+        let restore = transaction
+            .chunks
+            .iter()
+            .map(|(chunk_min, delta)| (*chunk_min, delta.before.clone()))
+            .collect();
+        self.redo_stack.push(transaction);
+
+        Some(restore)
+    }
+
+    /// Pops the last undone transaction and returns the chunks needed to reapply it.
+    pub fn redo(&mut self) -> Option<ChunkRestore> {
+        let transaction = self.redo_stack.pop()?;
+        let restore = transaction
+            .chunks
+            .iter()
+            .map(|(chunk_min, delta)| (*chunk_min, Some(delta.after.clone())))
+            .collect();
+        self.undo_stack.push_back(transaction);
+
+        Some(restore)
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::voxel::{VoxelDistance, VoxelDistanceInt, EMPTY_VOXEL};
+
+    fn chunk(distance: VoxelDistanceInt) -> Array3x1<Voxel> {
+        Array3x1::fill(
+            Extent3i::from_min_and_shape(PointN([0; 3]), PointN([2; 3])),
+            Voxel {
+                distance: VoxelDistance(distance),
+                ..EMPTY_VOXEL
+            },
+        )
+    }
+
+    #[test]
+    fn test_edits_in_one_transaction_undo_together() {
+        let mut history = EditHistory::default();
+        let id = TransactionId(0);
+        let chunk_min = PointN([0; 3]);
+        history.record(id, chunk_min, Some(chunk(1)), chunk(2));
+        history.record(id, chunk_min, Some(chunk(2)), chunk(3));
+
+        let restore = history.undo().unwrap();
+        assert_eq!(restore.len(), 1);
+        assert_eq!(
+            restore[0].1.as_ref().unwrap().get(PointN([0; 3])).distance,
+            VoxelDistance(1)
+        );
+        assert!(!history.can_undo());
+
+        let restore = history.redo().unwrap();
+        assert_eq!(
+            restore[0].1.as_ref().unwrap().get(PointN([0; 3])).distance,
+            VoxelDistance(3)
+        );
+    }
+
+    #[test]
+    fn test_new_edit_clears_redo() {
+        let mut history = EditHistory::default();
+        history.record(TransactionId(0), PointN([0; 3]), None, chunk(1));
+        history.undo();
+        assert!(history.can_redo());
+
+        history.record(
+            TransactionId(1),
+            PointN([0; 3]),
+            None,
+            chunk(EMPTY_VOXEL.distance.0),
+        );
+        assert!(!history.can_redo());
+    }
+}
@@ -0,0 +1,193 @@
+use crate::{
+    assets::{read_bincode_file, write_bincode_file, BincodeFileError},
+    voxel::{
+        double_buffer::{EditStamp, EditedChunksBackBuffer, QueuedEdit},
+        sphere_brush::SphereStroke,
+    },
+};
+
+use amethyst::core::ecs::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// The kinds of edits that can be recorded. Only edits that are described entirely by plain data
+/// can be replayed. They don't hold any voxel distances, so journals can be replayed whether or not
+/// the `sd16` feature matches the build that recorded them.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum JournaledEdit {
+    Sphere(SphereStroke),
+}
+
+impl JournaledEdit {
+    pub fn queued_edit(&self, stamp: EditStamp) -> QueuedEdit {
+        match self {
+            JournaledEdit::Sphere(stroke) => stroke.queued_edit(stamp),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct JournalEntry {
+    /// The frame on which the edit was queued.
+    pub frame: u64,
+    pub stamp: EditStamp,
+    pub edit: JournaledEdit,
+}
+
+/// A recording of queued edits, in the order they were made.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct EditJournal {
+    pub entries: Vec<JournalEntry>,
+    /// Set when the journal will be saved. Tools whose edits can't be journaled should refuse to
+    /// edit while this is set, so a replay doesn't silently miss their edits.
+    #[serde(skip)]
+    pub is_recording: bool,
+}
+
+impl EditJournal {
+    /// An empty journal that's being recorded.
+    pub fn recording() -> Self {
+        Self {
+            entries: Vec::new(),
+            is_recording: true,
+        }
+    }
+
+    pub fn record(&mut self, frame: u64, stamp: EditStamp, edit: JournaledEdit) {
+        self.entries.push(JournalEntry { frame, stamp, edit });
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), BincodeFileError> {
+        write_bincode_file(path, self)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, BincodeFileError> {
+        read_bincode_file(path)
+    }
+}
+
+/// Plays back an `EditJournal` with the `EditReplaySystem`.
+#[derive(Default)]
+pub struct EditReplay {
+    journal: EditJournal,
+    /// Journal frames to advance per app frame. 2.0 plays twice as fast as the recording.
+    speed: f64,
+    /// How far into the journal playback has gotten, in journal frames since the first entry.
+    elapsed_frames: f64,
+    next_entry: usize,
+}
+
+impl EditReplay {
+    pub fn new(journal: EditJournal, speed: f64) -> Self {
+        Self {
+            journal,
+            speed,
+            elapsed_frames: 0.0,
+            next_entry: 0,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next_entry >= self.journal.entries.len()
+    }
+
+    /// Returns the entries that are due after advancing by one app frame.
+    fn advance(&mut self) -> &[JournalEntry] {
+        let entries = &self.journal.entries;
+        let first_frame = match entries.first() {
+            Some(e) => e.frame,
+            None => return &[],
+        };
+        self.elapsed_frames += self.speed;
+
+        let start = self.next_entry;
+        while self.next_entry < entries.len()
+            && (entries[self.next_entry].frame - first_frame) as f64 <= self.elapsed_frames
+        {
+            self.next_entry += 1;
+        }
+
+        &entries[start..self.next_entry]
+    }
+}
+
+/// Queues the edits from the `EditReplay` resource as their recorded frames come up. Edits keep
+/// their recorded stamps, so in deterministic mode a replay produces the same map as the original
+/// session.
+pub struct EditReplaySystem;
+
+impl<'a> System<'a> for EditReplaySystem {
+    type SystemData = (
+        Write<'a, EditReplay>,
+        WriteExpect<'a, EditedChunksBackBuffer>,
+    );
+
+    fn run(&mut self, (mut replay, mut backbuffer): Self::SystemData) {
+        if replay.is_finished() {
+            return;
+        }
+
+        for entry in replay.advance() {
+            backbuffer.queue_stamped_edit(entry.edit.queued_edit(entry.stamp));
+        }
+
+        if replay.is_finished() {
+            log::info!("Finished replaying edits");
+        }
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::voxel::{
+        double_buffer::EditSourceId,
+        sphere_brush::{BrushFalloff, SetVoxelOperation},
+        VoxelType,
+    };
+
+    use building_blocks::prelude::*;
+
+    fn journal_with_frames(frames: &[u64]) -> EditJournal {
+        let mut journal = EditJournal::default();
+        for (i, &frame) in frames.iter().enumerate() {
+            journal.record(
+                frame,
+                EditStamp {
+                    source: EditSourceId::LOCAL,
+                    sequence: i as u64,
+                },
+                JournaledEdit::Sphere(SphereStroke {
+                    operation: SetVoxelOperation::MakeSolid,
+                    center: PointN([0; 3]),
+                    radius: 4,
+                    voxel_type: VoxelType(1),
+                    shell_cutoff: 0.8,
+                    sdf_growth_factor: 5.0,
+                    falloff: BrushFalloff::Linear,
+                }),
+            );
+        }
+
+        journal
+    }
+
+    #[test]
+    fn test_replay_at_double_speed() {
+        let mut replay = EditReplay::new(journal_with_frames(&[10, 11, 12, 16]), 2.0);
+
+        // Each app frame covers two recorded frames.
+        assert_eq!(replay.advance().len(), 3);
+        assert_eq!(replay.advance().len(), 0);
+        assert_eq!(replay.advance().len(), 1);
+        assert!(replay.is_finished());
+    }
+}
@@ -0,0 +1,136 @@
+use crate::voxel::double_buffer::EditStamp;
+
+use building_blocks::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Upper bounds on the size and location of a single edit. A stray brush far from the camera or a
+/// typo in a radius could otherwise dirty thousands of chunks in one frame.
+///
+/// The `EditedChunksBackBuffer` checks every edit against them: queued edits when they're applied,
+/// and direct edits as they're made, against the limits as of the last merge.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EditLimits {
+    pub max_brush_radius: u32,
+    /// The number of voxels in the edit's bounding extent.
+    pub max_voxels_per_edit: usize,
+    /// Edits must lie within this many voxels of the origin along every axis.
+    pub max_distance_from_origin: i32,
+    /// Queued edits that overlap more chunks than this are applied a piece at a time over several
+    /// frames, so a huge brush stroke doesn't stall the editor. This isn't a limit on the size of
+    /// an edit.
+    #[serde(default = "default_max_chunks_per_frame")]
+    pub max_chunks_per_frame: usize,
+}
+
+fn default_max_chunks_per_frame() -> usize {
+    64
+}
+
+impl Default for EditLimits {
+    fn default() -> Self {
+        Self {
+            max_brush_radius: 64,
+            max_voxels_per_edit: 4_000_000,
+            max_distance_from_origin: 100_000,
+            max_chunks_per_frame: default_max_chunks_per_frame(),
+        }
+    }
+}
+
+impl EditLimits {
+    pub fn check_radius(&self, radius: u32) -> Result<(), EditLimitViolation> {
+        if radius > self.max_brush_radius {
+            return Err(EditLimitViolation::RadiusTooLarge {
+                radius,
+                max: self.max_brush_radius,
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn check_extent(&self, extent: &Extent3i) -> Result<(), EditLimitViolation> {
+        let num_voxels = extent.num_points();
+        if num_voxels > self.max_voxels_per_edit {
+            return Err(EditLimitViolation::TooManyVoxels {
+                num_voxels,
+                max: self.max_voxels_per_edit,
+            });
+        }
+
+        let distance = extent
+            .minimum
+            .0
+            .iter()
+            .chain(extent.max().0.iter())
+            .map(|c| c.abs())
+            .max()
+            .unwrap();
+        if distance > self.max_distance_from_origin {
+            return Err(EditLimitViolation::TooFarFromOrigin {
+                distance,
+                max: self.max_distance_from_origin,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EditLimitViolation {
+    RadiusTooLarge { radius: u32, max: u32 },
+    TooManyVoxels { num_voxels: usize, max: usize },
+    TooFarFromOrigin { distance: i32, max: i32 },
+}
+
+/// Sent instead of applying an edit that violates the `EditLimits`.
+#[derive(Clone, Debug)]
+pub struct RejectedEditEvent {
+    /// Only queued edits have a stamp.
+    pub stamp: Option<EditStamp>,
+    pub violation: EditLimitViolation,
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_extent() {
+        let limits = EditLimits {
+            max_brush_radius: 8,
+            max_voxels_per_edit: 1000,
+            max_distance_from_origin: 100,
+            max_chunks_per_frame: 8,
+        };
+
+        let small = Extent3i::from_min_and_shape(PointN([-5; 3]), PointN([10; 3]));
+        assert_eq!(limits.check_extent(&small), Ok(()));
+
+        let big = Extent3i::from_min_and_shape(PointN([0; 3]), PointN([11, 10, 10]));
+        assert_eq!(
+            limits.check_extent(&big),
+            Err(EditLimitViolation::TooManyVoxels {
+                num_voxels: 1100,
+                max: 1000
+            })
+        );
+
+        let far = Extent3i::from_min_and_shape(PointN([0, -105, 0]), PointN([2; 3]));
+        assert_eq!(
+            limits.check_extent(&far),
+            Err(EditLimitViolation::TooFarFromOrigin {
+                distance: 105,
+                max: 100
+            })
+        );
+    }
+}
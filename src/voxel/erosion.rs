@@ -0,0 +1,393 @@
+use crate::voxel::{
+    double_buffer::EditedChunksBackBuffer, VoxelChunkReader, VoxelDistance, EMPTY_VOXEL,
+};
+
+use building_blocks::prelude::*;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+/// Parameters for the hydraulic and thermal erosion passes run by `erode_extent`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ErosionConfig {
+    pub seed: u64,
+    /// Number of rain droplets simulated by the hydraulic pass.
+    pub num_droplets: usize,
+    /// The maximum number of steps a single droplet can take before it evaporates.
+    pub droplet_lifetime: usize,
+    /// How much a droplet keeps moving in its previous direction, in `[0, 1]`.
+    pub inertia: f32,
+    /// Scales the amount of sediment a droplet can carry.
+    pub sediment_capacity: f32,
+    /// Lower bound on the capacity so droplets on flat ground still erode a little.
+    pub min_sediment_capacity: f32,
+    /// Fraction of excess sediment deposited per step.
+    pub deposit_rate: f32,
+    /// Fraction of remaining capacity eroded per step.
+    pub erode_rate: f32,
+    /// Fraction of water lost per step.
+    pub evaporation_rate: f32,
+    pub gravity: f32,
+    /// Number of thermal relaxation passes over the height field.
+    pub thermal_iterations: usize,
+    /// The height difference between neighboring columns that material can rest at without
+    /// sliding. Effectively the tangent of the angle of repose.
+    pub talus_height: f32,
+    /// Fraction of the excess height moved downhill per thermal iteration.
+    pub thermal_rate: f32,
+}
+
+impl Default for ErosionConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            num_droplets: 5000,
+            droplet_lifetime: 30,
+            inertia: 0.05,
+            sediment_capacity: 4.0,
+            min_sediment_capacity: 0.01,
+            deposit_rate: 0.3,
+            erode_rate: 0.3,
+            evaporation_rate: 0.01,
+            gravity: 4.0,
+            thermal_iterations: 50,
+            talus_height: 1.0,
+            thermal_rate: 0.5,
+        }
+    }
+}
+
+/// The height of the top surface of each XZ column in some extent. Heights are in voxel
+/// coordinates, so a column whose surface lies halfway between the voxels at y = 3 and y = 4 has a
+/// height of 3.5.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeightField {
+    pub min_x: i32,
+    pub min_z: i32,
+    pub size_x: usize,
+    pub size_z: usize,
+    pub heights: Vec<f32>,
+}
+
+impl HeightField {
+    pub fn fill(min_x: i32, min_z: i32, size_x: usize, size_z: usize, height: f32) -> Self {
+        Self {
+            min_x,
+            min_z,
+            size_x,
+            size_z,
+            heights: vec![height; size_x * size_z],
+        }
+    }
+
+    /// Finds the top surface of every column in `extent`. Columns without any solid voxels get a
+    /// height just below the extent.
+    pub fn from_voxels<V>(voxels: &V, extent: &Extent3i) -> Self
+    where
+        V: Get<Point3i, Item = crate::voxel::Voxel>,
+    {
+        let min = extent.minimum;
+        let max = extent.max();
+        let size_x = extent.shape.x() as usize;
+        let size_z = extent.shape.z() as usize;
+        let mut field = Self::fill(min.x(), min.z(), size_x, size_z, (min.y() - 1) as f32);
+
+        for z in min.z()..=max.z() {
+            for x in min.x()..=max.x() {
+                let mut above: f32 = voxels.get(PointN([x, max.y(), z])).distance.into();
+                if above < 0.0 {
+                    // The column is solid all the way to the top of the extent.
+                    field.set(x, z, max.y() as f32);
+                    continue;
+                }
+                for y in (min.y()..max.y()).rev() {
+                    let d: f32 = voxels.get(PointN([x, y, z])).distance.into();
+                    if d < 0.0 {
+                        // Find the zero crossing between this voxel and the one above it.
+                        let t = d / (d - above);
+                        field.set(x, z, y as f32 + t);
+                        break;
+                    }
+                    above = d;
+                }
+            }
+        }
+
+        field
+    }
+
+    fn index(&self, x: i32, z: i32) -> usize {
+        (z - self.min_z) as usize * self.size_x + (x - self.min_x) as usize
+    }
+
+    pub fn get(&self, x: i32, z: i32) -> f32 {
+        self.heights[self.index(x, z)]
+    }
+
+    pub fn set(&mut self, x: i32, z: i32, h: f32) {
+        let i = self.index(x, z);
+        self.heights[i] = h;
+    }
+
+    /// Returns the bilinearly interpolated height and gradient at local coordinates `(u, v)`.
+    fn height_and_gradient(&self, u: f32, v: f32) -> (f32, [f32; 2]) {
+        let (i, j) = (u as usize, v as usize);
+        let (fu, fv) = (u - i as f32, v - j as f32);
+        let h = |i: usize, j: usize| self.heights[j * self.size_x + i];
+        let h00 = h(i, j);
+        let h10 = h(i + 1, j);
+        let h01 = h(i, j + 1);
+        let h11 = h(i + 1, j + 1);
+
+        let gx = (h10 - h00) * (1.0 - fv) + (h11 - h01) * fv;
+        let gz = (h01 - h00) * (1.0 - fu) + (h11 - h10) * fu;
+        let height = h00 * (1.0 - fu) * (1.0 - fv)
+            + h10 * fu * (1.0 - fv)
+            + h01 * (1.0 - fu) * fv
+            + h11 * fu * fv;
+
+        (height, [gx, gz])
+    }
+
+    /// Distributes `amount` over the 4 cells surrounding `(u, v)`, weighted bilinearly.
+    fn add_bilinear(&mut self, u: f32, v: f32, amount: f32) {
+        let (i, j) = (u as usize, v as usize);
+        let (fu, fv) = (u - i as f32, v - j as f32);
+        let w = self.size_x;
+        self.heights[j * w + i] += amount * (1.0 - fu) * (1.0 - fv);
+        self.heights[j * w + i + 1] += amount * fu * (1.0 - fv);
+        self.heights[(j + 1) * w + i] += amount * (1.0 - fu) * fv;
+        self.heights[(j + 1) * w + i + 1] += amount * fu * fv;
+    }
+}
+
+/// Simulates rain droplets flowing downhill, picking up sediment on steep slopes and depositing it
+/// where they slow down. This carves gullies into the height field.
+pub fn hydraulic_erosion(field: &mut HeightField, config: &ErosionConfig) {
+    #[cfg(feature = "profiler")]
+    profile_scope!("hydraulic_erosion");
+
+    if field.size_x < 2 || field.size_z < 2 {
+        return;
+    }
+
+    let max_u = (field.size_x - 1) as f32;
+    let max_v = (field.size_z - 1) as f32;
+    let mut rng = SmallRng::seed_from_u64(config.seed);
+
+    for _ in 0..config.num_droplets {
+        let mut pos = [rng.gen_range(0.0, max_u), rng.gen_range(0.0, max_v)];
+        let mut dir = [0.0f32, 0.0];
+        let mut speed = 1.0;
+        let mut water = 1.0;
+        let mut sediment = 0.0;
+
+        for _ in 0..config.droplet_lifetime {
+            let (height, grad) = field.height_and_gradient(pos[0], pos[1]);
+
+            dir[0] = dir[0] * config.inertia - grad[0] * (1.0 - config.inertia);
+            dir[1] = dir[1] * config.inertia - grad[1] * (1.0 - config.inertia);
+            let len = (dir[0] * dir[0] + dir[1] * dir[1]).sqrt();
+            if len < std::f32::EPSILON {
+                break;
+            }
+            dir[0] /= len;
+            dir[1] /= len;
+
+            let old_pos = pos;
+            pos[0] += dir[0];
+            pos[1] += dir[1];
+            if pos[0] < 0.0 || pos[0] >= max_u || pos[1] < 0.0 || pos[1] >= max_v {
+                break;
+            }
+
+            let (new_height, _) = field.height_and_gradient(pos[0], pos[1]);
+            let delta_h = new_height - height;
+
+            let capacity = (-delta_h * speed * water * config.sediment_capacity)
+                .max(config.min_sediment_capacity);
+
+            if sediment > capacity || delta_h > 0.0 {
+                // Fill the pit we're moving into, or drop whatever we can't carry.
+                let amount = if delta_h > 0.0 {
+                    delta_h.min(sediment)
+                } else {
+                    (sediment - capacity) * config.deposit_rate
+                };
+                sediment -= amount;
+                field.add_bilinear(old_pos[0], old_pos[1], amount);
+            } else {
+                // Never erode deeper than the slope we just went down, or we dig holes.
+                let amount = ((capacity - sediment) * config.erode_rate).min(-delta_h);
+                sediment += amount;
+                field.add_bilinear(old_pos[0], old_pos[1], -amount);
+            }
+
+            speed = (speed * speed + delta_h.abs() * config.gravity).sqrt();
+            water *= 1.0 - config.evaporation_rate;
+        }
+    }
+}
+
+/// Moves material from each column to its lower neighbors wherever the height difference exceeds
+/// the talus height, which settles cliffs into slopes.
+pub fn thermal_erosion(field: &mut HeightField, config: &ErosionConfig) {
+    #[cfg(feature = "profiler")]
+    profile_scope!("thermal_erosion");
+
+    let (w, h) = (field.size_x, field.size_z);
+    let mut deltas = vec![0.0; field.heights.len()];
+
+    for _ in 0..config.thermal_iterations {
+        for d in deltas.iter_mut() {
+            *d = 0.0;
+        }
+
+        for j in 0..h {
+            for i in 0..w {
+                let here = j * w + i;
+                let mut neighbors = [None; 4];
+                if i > 0 {
+                    neighbors[0] = Some(here - 1);
+                }
+                if i + 1 < w {
+                    neighbors[1] = Some(here + 1);
+                }
+                if j > 0 {
+                    neighbors[2] = Some(here - w);
+                }
+                if j + 1 < h {
+                    neighbors[3] = Some(here + w);
+                }
+
+                // Share the excess between all lower neighbors so the result doesn't depend on
+                // iteration order.
+                let excess: Vec<(usize, f32)> = neighbors
+                    .iter()
+                    .filter_map(|n| *n)
+                    .map(|n| {
+                        (
+                            n,
+                            field.heights[here] - field.heights[n] - config.talus_height,
+                        )
+                    })
+                    .filter(|(_, e)| *e > 0.0)
+                    .collect();
+                let total_excess: f32 = excess.iter().map(|(_, e)| e).sum();
+                if total_excess <= 0.0 {
+                    continue;
+                }
+                let max_excess = excess.iter().map(|(_, e)| *e).fold(0.0, f32::max);
+                let moved = 0.5 * config.thermal_rate * max_excess;
+                for (n, e) in excess.into_iter() {
+                    let share = moved * e / total_excess;
+                    deltas[n] += share;
+                    deltas[here] -= share;
+                }
+            }
+        }
+
+        for (height, d) in field.heights.iter_mut().zip(deltas.iter()) {
+            *height += d;
+        }
+    }
+}
+
+/// Runs hydraulic then thermal erosion on the top surface of `extent` and writes the difference
+/// back into `backbuffer` as SDF edits. Only voxels near the old and new surface of each column are
+/// rewritten, so overhangs and caves below the surface are left alone.
+pub fn erode_extent(
+    map_reader: &VoxelChunkReader,
+    extent: &Extent3i,
+    config: &ErosionConfig,
+    backbuffer: &mut EditedChunksBackBuffer,
+) {
+    #[cfg(feature = "profiler")]
+    profile_scope!("erode_extent");
+
+    let lod0 = map_reader.lod_view(0);
+    let old_field = HeightField::from_voxels(&lod0, extent);
+    let mut new_field = old_field.clone();
+    hydraulic_erosion(&mut new_field, config);
+    thermal_erosion(&mut new_field, config);
+
+    backbuffer.edit_voxels_out_of_place(map_reader, extent, |p: Point3i, v| {
+        let old_h = old_field.get(p.x(), p.z());
+        let new_h = new_field.get(p.x(), p.z());
+        let y = p.y() as f32;
+
+        // Leave voxels that are far from both surfaces untouched.
+        if y < old_h.min(new_h) - 2.0 || y > old_h.max(new_h) + 2.0 {
+            return;
+        }
+
+        let was_solid = v.distance.0 < 0;
+        v.distance = VoxelDistance::from(y - new_h);
+        if v.distance.0 < 0 {
+            if !was_solid {
+                // Deposited material takes the type of the surface it landed on.
+                let below = PointN([p.x(), old_h.floor() as i32, p.z()]);
+                v.voxel_type = lod0.get(below).voxel_type;
+            }
+        } else {
+            v.voxel_type = EMPTY_VOXEL.voxel_type;
+        }
+    });
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spike_field() -> HeightField {
+        let mut field = HeightField::fill(0, 0, 9, 9, 0.0);
+        field.set(4, 4, 20.0);
+
+        field
+    }
+
+    #[test]
+    fn test_thermal_erosion_conserves_material() {
+        let mut field = spike_field();
+        let before: f32 = field.heights.iter().sum();
+
+        thermal_erosion(&mut field, &ErosionConfig::default());
+
+        let after: f32 = field.heights.iter().sum();
+        assert!((before - after).abs() < 1e-3, "{} != {}", before, after);
+    }
+
+    #[test]
+    fn test_thermal_erosion_flattens_spike() {
+        let mut field = spike_field();
+        let config = ErosionConfig {
+            thermal_iterations: 500,
+            ..Default::default()
+        };
+
+        thermal_erosion(&mut field, &config);
+
+        assert!(field.get(4, 4) < 10.0, "spike = {}", field.get(4, 4));
+        assert!(field.get(3, 4) > 0.0);
+    }
+
+    #[test]
+    fn test_hydraulic_erosion_leaves_flat_field_flat() {
+        let mut field = HeightField::fill(0, 0, 16, 16, 5.0);
+
+        hydraulic_erosion(&mut field, &ErosionConfig::default());
+
+        for h in field.heights.iter() {
+            assert!((h - 5.0).abs() < 1e-3);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! Bulk operations that only affect the voxels inside of an extent, e.g. an editor selection. Each
+//! operation is written to the backbuffer in a single edit, so it's merged into the map at once.
+
+use crate::voxel::{
+    double_buffer::EditedChunksBackBuffer, empty_array, Voxel, VoxelChunkReader, VoxelDistance,
+    VoxelType, EMPTY_VOXEL,
+};
+
+use building_blocks::prelude::*;
+use noise::{NoiseFn, OpenSimplex, Seedable};
+
+/// Makes every voxel in `extent` solid with `voxel_type`.
+pub fn fill_extent(
+    map_reader: &VoxelChunkReader,
+    extent: &Extent3i,
+    voxel_type: VoxelType,
+    backbuffer: &mut EditedChunksBackBuffer,
+) {
+    backbuffer.edit_voxels_out_of_place(map_reader, extent, |p: Point3i, v: &mut Voxel| {
+        // Keep a smooth surface on the boundary of the extent.
+        let boundary_dist = boundary_distance(extent, p);
+        v.distance = VoxelDistance::from(-boundary_dist);
+        v.voxel_type = voxel_type;
+    });
+}
+
+/// Makes every voxel in `extent` empty.
+pub fn clear_extent(
+    map_reader: &VoxelChunkReader,
+    extent: &Extent3i,
+    backbuffer: &mut EditedChunksBackBuffer,
+) {
+    backbuffer.edit_voxels_out_of_place(map_reader, extent, |_p: Point3i, v: &mut Voxel| {
+        *v = EMPTY_VOXEL;
+    });
+}
+
+/// Changes the type of every solid voxel of type `from` in `extent` to `to`.
+pub fn replace_voxel_type(
+    map_reader: &VoxelChunkReader,
+    extent: &Extent3i,
+    from: VoxelType,
+    to: VoxelType,
+    backbuffer: &mut EditedChunksBackBuffer,
+) {
+    backbuffer.edit_voxels_out_of_place(map_reader, extent, |_p: Point3i, v: &mut Voxel| {
+        if v.voxel_type == from && v.distance.0 < 0 {
+            v.voxel_type = to;
+        }
+    });
+}
+
+/// Blurs the signed distance field in `extent` with a 3x3x3 box filter, `iterations` times. This
+/// rounds off sharp edges and removes small bumps.
+pub fn smooth_extent(
+    map_reader: &VoxelChunkReader,
+    extent: &Extent3i,
+    iterations: usize,
+    backbuffer: &mut EditedChunksBackBuffer,
+) {
+    let read_extent = extent.padded(1);
+    let mut src = empty_array(read_extent);
+    copy_extent(&read_extent, &map_reader.lod_view(0), &mut src);
+
+    let mut dst = src.clone();
+    for _ in 0..iterations {
+        src.for_each(extent, |p: Point3i, _v: Voxel| {
+            let mut sum = 0.0;
+            for dz in -1..=1 {
+                for dy in -1..=1 {
+                    for dx in -1..=1 {
+                        let d: f32 = src.get(p + PointN([dx, dy, dz])).distance.into();
+                        sum += d;
+                    }
+                }
+            }
+            dst.get_mut(p).distance = VoxelDistance::from(sum / 27.0);
+        });
+        std::mem::swap(&mut src, &mut dst);
+    }
+
+    let smoothed = src;
+    backbuffer.edit_voxels_out_of_place(map_reader, extent, |p: Point3i, v: &mut Voxel| {
+        let was_solid = v.distance.0 < 0;
+        v.distance = smoothed.get(p).distance;
+        if v.distance.0 >= 0 {
+            v.voxel_type = EMPTY_VOXEL.voxel_type;
+        } else if !was_solid {
+            v.voxel_type = nearest_solid_type(&smoothed, p).unwrap_or(v.voxel_type);
+        }
+    });
+}
+
+/// Perturbs the signed distance field in `extent` with smooth 3D noise. `amplitude` is in voxels
+/// and `frequency` is in cycles per voxel.
+pub fn add_noise_to_extent(
+    map_reader: &VoxelChunkReader,
+    extent: &Extent3i,
+    seed: u32,
+    amplitude: f32,
+    frequency: f32,
+    backbuffer: &mut EditedChunksBackBuffer,
+) {
+    let noise = OpenSimplex::new().set_seed(seed);
+    let read_extent = extent.padded(1);
+    let mut original = empty_array(read_extent);
+    copy_extent(&read_extent, &map_reader.lod_view(0), &mut original);
+
+    backbuffer.edit_voxels_out_of_place(map_reader, extent, |p: Point3i, v: &mut Voxel| {
+        let sample = noise.get([
+            (p.x() as f32 * frequency) as f64,
+            (p.y() as f32 * frequency) as f64,
+            (p.z() as f32 * frequency) as f64,
+        ]) as f32;
+        let was_solid = v.distance.0 < 0;
+        let d: f32 = v.distance.into();
+        v.distance = VoxelDistance::from(d + amplitude * sample);
+        if v.distance.0 >= 0 {
+            v.voxel_type = EMPTY_VOXEL.voxel_type;
+        } else if !was_solid {
+            v.voxel_type = nearest_solid_type(&original, p).unwrap_or(v.voxel_type);
+        }
+    });
+}
+
+/// Distance from `p` to the nearest face of `extent`, in voxels. Always positive inside.
+fn boundary_distance(extent: &Extent3i, p: Point3i) -> f32 {
+    let min = extent.minimum;
+    let max = extent.max();
+    (0..3)
+        .map(|i| (p.0[i] - min.0[i]).min(max.0[i] - p.0[i]))
+        .min()
+        .unwrap() as f32
+        + 0.5
+}
+
+/// Voxels that become solid need a type, so borrow one from an adjacent solid voxel.
+fn nearest_solid_type(voxels: &Array3x1<Voxel>, p: Point3i) -> Option<VoxelType> {
+    for offset in Point3i::von_neumann_offsets().iter() {
+        let q = p + *offset;
+        if voxels.extent().contains(q) {
+            let neighbor = voxels.get(q);
+            if neighbor.distance.0 < 0 {
+                return Some(neighbor.voxel_type);
+            }
+        }
+    }
+
+    None
+}
@@ -0,0 +1,275 @@
+//! Voxel types with the `is_gravity_affected` flag, like sand and gravel, fall when there's nothing
+//! underneath them. Only the voxels around edited chunks are checked, and each tick moves every
+//! unsupported voxel down by one, so a column of sand falls together and piles up where it lands.
+
+use crate::voxel::{
+    chunk_cache_flusher::ChunkCacheFlusher,
+    chunk_lock::chunk_min_containing_point,
+    double_buffer::{DirtyChunks, EditedChunksBackBuffer},
+    Voxel, VoxelMap, VoxelType,
+};
+
+use amethyst::core::{ecs::prelude::*, Time};
+use building_blocks::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FallingVoxelsConfig {
+    /// Seconds between steps, i.e. how long a voxel takes to fall by one.
+    pub tick_seconds: f32,
+    /// The most voxels that move in one tick. Any others wait for the next tick.
+    pub max_moves_per_tick: usize,
+}
+
+impl Default for FallingVoxelsConfig {
+    fn default() -> Self {
+        Self {
+            tick_seconds: 0.05,
+            max_moves_per_tick: 10000,
+        }
+    }
+}
+
+/// The voxels changed by one step of `fall_step`.
+#[derive(Debug, Default)]
+pub struct FallingStep {
+    pub changes: HashMap<Point3i, Voxel>,
+    pub num_moves: usize,
+    /// Whether some voxels didn't get to move because of `max_moves`.
+    pub hit_limit: bool,
+}
+
+/// Moves the gravity-affected voxels in `extents` that aren't supported. A voxel falls into the
+/// empty voxel below it, or otherwise slides into an empty voxel diagonally below it, so piles
+/// settle at 45 degrees. Moving swaps the voxel with the empty one it moves into.
+///
+/// `get` returns `None` outside of the stored chunks, which voxels never move into. Voxels are
+/// moved from the bottom up, so a voxel can fall into the space left by the one below it.
+pub fn fall_step(
+    extents: impl Iterator<Item = Extent3i>,
+    get: impl Fn(Point3i) -> Option<Voxel>,
+    is_gravity_affected: impl Fn(VoxelType) -> bool,
+    max_moves: usize,
+) -> FallingStep {
+    let is_falling = |v: &Voxel| v.distance.0 < 0 && is_gravity_affected(v.voxel_type);
+
+    let mut candidates = HashSet::new();
+    for extent in extents {
+        for p in extent.iter_points() {
+            if get(p).map_or(false, |v| is_falling(&v)) {
+                candidates.insert(p);
+            }
+        }
+    }
+    let mut candidates: Vec<Point3i> = candidates.into_iter().collect();
+    candidates.sort_by_key(|p| (p.y(), p.x(), p.z()));
+
+    let below = PointN([0, -1, 0]);
+    let sides = [
+        PointN([1, 0, 0]),
+        PointN([-1, 0, 0]),
+        PointN([0, 0, 1]),
+        PointN([0, 0, -1]),
+    ];
+    let mut step = FallingStep::default();
+    for p in candidates.into_iter() {
+        let voxel_at = |q: Point3i| step.changes.get(&q).cloned().or_else(|| get(q));
+        let is_open = |q: Point3i| voxel_at(q).map_or(false, |v| v.distance.0 >= 0);
+
+        let v = match voxel_at(p) {
+            Some(v) if is_falling(&v) => v,
+            _ => continue,
+        };
+        let target = if is_open(p + below) {
+            Some(p + below)
+        } else {
+            sides
+                .iter()
+                .map(|side| p + *side)
+                .find(|q| is_open(*q) && is_open(*q + below))
+                .map(|q| q + below)
+        };
+        let target = match target {
+            Some(t) => t,
+            None => continue,
+        };
+
+        let displaced = voxel_at(target).unwrap();
+        if step.num_moves == max_moves {
+            step.hit_limit = true;
+            break;
+        }
+        step.changes.insert(target, v);
+        step.changes.insert(p, displaced);
+        step.num_moves += 1;
+    }
+
+    step
+}
+
+/// Steps the falling voxels every `FallingVoxelsConfig::tick_seconds`. The chunks to check come
+/// from the `DirtyChunks`, so voxels start falling when the terrain under them is edited, and keep
+/// falling because their own moves dirty the chunks again.
+#[derive(Default)]
+pub struct FallingVoxelsSystem {
+    seconds_since_tick: f32,
+    // The dirty extents gathered since the last tick.
+    active_extents: HashMap<Point3i, Extent3i>,
+}
+
+impl<'a> System<'a> for FallingVoxelsSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        Read<'a, Time>,
+        Read<'a, FallingVoxelsConfig>,
+        Read<'a, Option<DirtyChunks>>,
+        ReadExpect<'a, VoxelMap>,
+        ReadExpect<'a, ChunkCacheFlusher>,
+        WriteExpect<'a, EditedChunksBackBuffer>,
+    );
+
+    fn run(
+        &mut self,
+        (time, config, dirty_chunks, voxel_map, cache_flusher, mut backbuffer): Self::SystemData,
+    ) {
+        #[cfg(feature = "profiler")]
+        profile_scope!("falling_voxels");
+
+        let palette = &voxel_map.palette;
+        if !palette.infos.iter().any(|i| i.flags.is_gravity_affected) {
+            self.active_extents.clear();
+            return;
+        }
+
+        if let Some(dirty_chunks) = dirty_chunks.as_ref() {
+            for (chunk_min, extent) in dirty_chunks.chunks.iter() {
+                self.active_extents
+                    .entry(*chunk_min)
+                    .and_modify(|active| {
+                        *active = Extent3i::from_min_and_max(
+                            active.minimum.meet(extent.minimum),
+                            active.max().join(extent.max()),
+                        )
+                    })
+                    .or_insert(*extent);
+            }
+        }
+
+        self.seconds_since_tick += time.delta_seconds();
+        if self.seconds_since_tick < config.tick_seconds || self.active_extents.is_empty() {
+            return;
+        }
+        // Only one tick per frame, so a slow frame doesn't make the next one slower.
+        self.seconds_since_tick = 0.0;
+
+        let local_cache = LocalChunkCache3::new();
+        let reader = voxel_map.voxels.reader(&local_cache);
+        let chunk_shape = voxel_map.chunk_shape();
+        let step = fall_step(
+            self.active_extents.values().cloned(),
+            |p| {
+                reader
+                    .get_chunk(ChunkKey::new(0, chunk_min_containing_point(p, chunk_shape)))
+                    .map(|chunk| chunk.get(p))
+            },
+            |t| palette.get_voxel_type_info(t).flags.is_gravity_affected,
+            config.max_moves_per_tick,
+        );
+        // The voxels that moved dirty their chunks again, but the ones that didn't get a turn
+        // need to be checked on the next tick too.
+        if !step.hit_limit {
+            self.active_extents.clear();
+        }
+
+        // Write the moves with one edit per chunk.
+        let mut chunk_changes: HashMap<Point3i, Vec<Point3i>> = HashMap::new();
+        for p in step.changes.keys() {
+            chunk_changes
+                .entry(chunk_min_containing_point(*p, chunk_shape))
+                .or_default()
+                .push(*p);
+        }
+        for points in chunk_changes.values() {
+            let (min, max) = points.iter().fold((points[0], points[0]), |(min, max), p| {
+                (min.meet(*p), max.join(*p))
+            });
+            backbuffer.edit_voxels_out_of_place(
+                &reader,
+                &Extent3i::from_min_and_max(min, max),
+                |p: Point3i, v: &mut Voxel| {
+                    if let Some(new_v) = step.changes.get(&p) {
+                        *v = *new_v;
+                    }
+                },
+            );
+        }
+        cache_flusher.flush(local_cache);
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::voxel::{VoxelDistance, EMPTY_VOXEL};
+
+    const SAND: VoxelType = VoxelType(2);
+
+    #[test]
+    fn test_sand_column_falls_and_piles_up() {
+        // A floor of rock at y = 0 under a column of sand in midair.
+        let mut voxels: HashMap<Point3i, Voxel> = HashMap::new();
+        for y in 5..8 {
+            voxels.insert(
+                PointN([0, y, 0]),
+                Voxel {
+                    voxel_type: SAND,
+                    distance: VoxelDistance::from(-1.0),
+                },
+            );
+        }
+        let get = |voxels: &HashMap<Point3i, Voxel>, p: Point3i| {
+            if p.y() < 0 || p.x().abs() > 8 || p.z().abs() > 8 {
+                None
+            } else if p.y() == 0 {
+                Some(Voxel {
+                    voxel_type: VoxelType(1),
+                    distance: VoxelDistance::from(-1.0),
+                })
+            } else {
+                Some(voxels.get(&p).cloned().unwrap_or(EMPTY_VOXEL))
+            }
+        };
+        let extent = Extent3i::from_min_and_max(PointN([-8, 0, -8]), PointN([8, 8, 8]));
+
+        for _ in 0..20 {
+            let step = fall_step(
+                std::iter::once(extent),
+                |p| get(&voxels, p),
+                |t| t == SAND,
+                usize::MAX,
+            );
+            voxels.extend(step.changes);
+        }
+
+        let sand: Vec<Point3i> = voxels
+            .iter()
+            .filter(|(_, v)| v.voxel_type == SAND)
+            .map(|(p, _)| *p)
+            .collect();
+        assert_eq!(sand.len(), 3);
+        // The column spread out into a pile on the floor.
+        assert!(sand.iter().all(|p| p.y() == 1));
+    }
+}
@@ -0,0 +1,124 @@
+//! Repaints connected regions of a single material, like the paint bucket of an image editor.
+
+use crate::voxel::{double_buffer::EditedChunksBackBuffer, Voxel, VoxelChunkReader, VoxelType};
+
+use building_blocks::prelude::*;
+use std::collections::{HashSet, VecDeque};
+
+/// Finds the solid voxels of the same type as `start` that are connected to it through their faces,
+/// without leaving `bounds`. The result is empty if `start` isn't solid or is outside of `bounds`.
+pub fn connected_voxels_of_same_type<V>(
+    voxels: &V,
+    start: Point3i,
+    bounds: &Extent3i,
+) -> HashSet<Point3i>
+where
+    V: Get<Point3i, Item = Voxel>,
+{
+    let mut connected = HashSet::new();
+    let start_voxel = voxels.get(start);
+    if start_voxel.distance.0 >= 0 || !bounds.contains(start) {
+        return connected;
+    }
+
+    let mut queue = VecDeque::new();
+    connected.insert(start);
+    queue.push_back(start);
+    while let Some(p) = queue.pop_front() {
+        for offset in Point3i::von_neumann_offsets().iter() {
+            let q = p + *offset;
+            if !bounds.contains(q) || connected.contains(&q) {
+                continue;
+            }
+            let v = voxels.get(q);
+            if v.distance.0 < 0 && v.voxel_type == start_voxel.voxel_type {
+                connected.insert(q);
+                queue.push_back(q);
+            }
+        }
+    }
+
+    connected
+}
+
+/// Changes the type of the voxels found by `connected_voxels_of_same_type` to `to`, in a single
+/// edit. Returns the number of voxels that were changed.
+pub fn flood_fill_voxel_type(
+    map_reader: &VoxelChunkReader,
+    start: Point3i,
+    bounds: &Extent3i,
+    to: VoxelType,
+    backbuffer: &mut EditedChunksBackBuffer,
+) -> usize {
+    let view = map_reader.lod_view(0);
+    if view.get(start).voxel_type == to {
+        return 0;
+    }
+    let filled = connected_voxels_of_same_type(&view, start, bounds);
+    if filled.is_empty() {
+        return 0;
+    }
+
+    let mut min = start.0;
+    let mut max = start.0;
+    for p in filled.iter() {
+        for i in 0..3 {
+            min[i] = min[i].min(p.0[i]);
+            max[i] = max[i].max(p.0[i]);
+        }
+    }
+    let filled_extent = Extent3i::from_min_and_max(PointN(min), PointN(max));
+    let num_filled = filled.len();
+    backbuffer.edit_voxels_out_of_place(
+        map_reader,
+        &filled_extent,
+        move |p: Point3i, v: &mut Voxel| {
+            if filled.contains(&p) {
+                v.voxel_type = to;
+            }
+        },
+    );
+
+    num_filled
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::voxel::{empty_array, VoxelDistance, EMPTY_VOXEL};
+
+    #[test]
+    fn test_connected_voxels_stop_at_other_types_and_bounds() {
+        // A row of 8 solid voxels along X: types 1, 1, 1, 2, 1, 1, 1, 1.
+        let mut voxels = empty_array(Extent3i::from_min_and_shape(
+            PointN([-1; 3]),
+            PointN([10, 3, 3]),
+        ));
+        for x in 0..8 {
+            *voxels.get_mut(PointN([x, 0, 0])) = Voxel {
+                voxel_type: VoxelType(if x == 3 { 2 } else { 1 }),
+                distance: VoxelDistance::from(-1.0),
+            };
+        }
+        let bounds = Extent3i::from_min_and_shape(PointN([0; 3]), PointN([7, 1, 1]));
+
+        let left = connected_voxels_of_same_type(&voxels, PointN([1, 0, 0]), &bounds);
+        assert_eq!(left.len(), 3);
+
+        // The last voxel is outside of the bounds.
+        let right = connected_voxels_of_same_type(&voxels, PointN([4, 0, 0]), &bounds);
+        assert_eq!(right.len(), 3);
+        assert!(!right.contains(&PointN([7, 0, 0])));
+
+        assert_eq!(voxels.get(PointN([0, 1, 0])), EMPTY_VOXEL);
+        assert!(connected_voxels_of_same_type(&voxels, PointN([0, 1, 0]), &bounds).is_empty());
+    }
+}
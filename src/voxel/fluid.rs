@@ -0,0 +1,477 @@
+//! Water and other fluids. Fluid levels are kept in their own chunks next to the `VoxelMap`, so
+//! fluid can flow through empty voxels without being written into the map. A cellular automaton
+//! moves the fluid at a fixed tick, and the fluid surface of each chunk is meshed separately and
+//! drawn with the transparent meshes.
+
+use crate::voxel::{
+    chunk_cache_flusher::ChunkCacheFlusher,
+    chunk_lock::chunk_min_containing_point,
+    meshing::{
+        loader::{ChunkMesh, VoxelMeshLoader},
+        manager::VoxelMeshManager,
+        surface_nets_vertices, MeshLayer,
+    },
+    Voxel, VoxelAssets, VoxelDistance, VoxelMap, VoxelType, EMPTY_VOXEL, VOXEL_CHUNK_SHAPE,
+};
+
+use amethyst::{
+    assets::ProgressCounter,
+    core::{ecs::prelude::*, Time},
+};
+use building_blocks::{mesh::padded_surface_nets_chunk_extent, prelude::*};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+/// The level of a voxel that's full of fluid.
+pub const MAX_FLUID_LEVEL: u8 = 8;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FluidConfig {
+    /// Seconds between steps of the simulation.
+    pub tick_seconds: f32,
+    /// The palette entry whose material the fluid surface is drawn with, e.g. a transparent water
+    /// type.
+    pub voxel_type: VoxelType,
+    /// The most chunks whose fluid surface is meshed per frame.
+    pub max_meshes_per_frame: usize,
+}
+
+impl Default for FluidConfig {
+    fn default() -> Self {
+        Self {
+            tick_seconds: 0.1,
+            voxel_type: VoxelType(1),
+            max_meshes_per_frame: 16,
+        }
+    }
+}
+
+/// Points that are refilled with fluid on every tick, like springs. Sources are stored in the map
+/// file; the fluid itself is not, it flows out of the sources again when the map is loaded.
+#[derive(Debug, Default)]
+pub struct FluidSources {
+    sources: Vec<Point3i>,
+    /// Set whenever the sources change, so they can be saved with the map.
+    changed: bool,
+}
+
+impl FluidSources {
+    pub fn new(sources: Vec<Point3i>) -> Self {
+        Self {
+            sources,
+            changed: false,
+        }
+    }
+
+    /// Adds a source at `p`, or removes the one that's already there. Returns whether a source was
+    /// added.
+    pub fn toggle(&mut self, p: Point3i) -> bool {
+        self.changed = true;
+        if let Some(i) = self.sources.iter().position(|s| *s == p) {
+            self.sources.remove(i);
+
+            false
+        } else {
+            self.sources.push(p);
+
+            true
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Point3i> {
+        self.sources.iter()
+    }
+
+    pub fn has_changed(&self) -> bool {
+        self.changed
+    }
+}
+
+/// What a voxel is to the fluid flowing into it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FluidCell {
+    Open,
+    Solid,
+    /// Outside of the stored chunks of the map. Fluid that flows here is removed, so it doesn't
+    /// fall forever.
+    Drain,
+}
+
+/// The fluid level of every voxel, in chunks of the map's chunk shape.
+pub struct FluidField {
+    chunk_shape: Point3i,
+    chunks: HashMap<Point3i, Array3x1<u8>>,
+    /// Chunks whose fluid surface needs to be meshed again.
+    dirty_chunks: HashSet<Point3i>,
+}
+
+impl Default for FluidField {
+    fn default() -> Self {
+        Self::with_chunk_shape(VOXEL_CHUNK_SHAPE)
+    }
+}
+
+impl FluidField {
+    pub fn with_chunk_shape(chunk_shape: Point3i) -> Self {
+        Self {
+            chunk_shape,
+            chunks: HashMap::new(),
+            dirty_chunks: HashSet::new(),
+        }
+    }
+
+    pub fn chunk_shape(&self) -> Point3i {
+        self.chunk_shape
+    }
+
+    pub fn level(&self, p: Point3i) -> u8 {
+        self.chunks
+            .get(&chunk_min_containing_point(p, self.chunk_shape))
+            .map_or(0, |chunk| chunk.get(p))
+    }
+
+    pub fn set_level(&mut self, p: Point3i, level: u8) {
+        let chunk_shape = self.chunk_shape;
+        let chunk_min = chunk_min_containing_point(p, chunk_shape);
+        if level == 0 && !self.chunks.contains_key(&chunk_min) {
+            return;
+        }
+        let chunk = self.chunks.entry(chunk_min).or_insert_with(|| {
+            Array3x1::fill(Extent3i::from_min_and_shape(chunk_min, chunk_shape), 0)
+        });
+        *chunk.get_mut(p) = level.min(MAX_FLUID_LEVEL);
+    }
+
+    /// The total amount of fluid, in units of `1 / MAX_FLUID_LEVEL` voxels.
+    pub fn total_level(&self) -> u64 {
+        let mut total = 0;
+        for chunk in self.chunks.values() {
+            chunk.for_each(chunk.extent(), |_p: Point3i, level: u8| {
+                total += level as u64
+            });
+        }
+
+        total
+    }
+
+    /// Removes all of the fluid.
+    pub fn clear(&mut self) {
+        self.dirty_chunks
+            .extend(self.chunks.drain().map(|(chunk_min, _)| chunk_min));
+    }
+
+    /// Takes the chunks whose fluid changed since the last call, so their surface can be meshed.
+    pub fn take_dirty_chunks(&mut self, max_chunks: usize) -> Vec<Point3i> {
+        let chunks: Vec<Point3i> = self.dirty_chunks.iter().take(max_chunks).cloned().collect();
+        for chunk_min in chunks.iter() {
+            self.dirty_chunks.remove(chunk_min);
+        }
+
+        chunks
+    }
+
+    /// Runs one tick of the cellular automaton. Sources are refilled, then fluid falls into the
+    /// open voxel below it, and whatever can't fall spreads into the horizontal neighbors that are
+    /// at least 2 levels lower. Voxels that became solid lose their fluid.
+    ///
+    /// Voxels are visited from the bottom up, so a column of fluid falls together, and the
+    /// horizontal flow is decided from the levels at the start of the tick, so it doesn't depend on
+    /// the order within a layer.
+    pub fn step<'a>(
+        &mut self,
+        sources: impl Iterator<Item = &'a Point3i>,
+        cell: impl Fn(Point3i) -> FluidCell,
+    ) {
+        let mut touched = HashSet::new();
+        for p in sources {
+            if cell(*p) == FluidCell::Open && self.level(*p) != MAX_FLUID_LEVEL {
+                self.set_level(*p, MAX_FLUID_LEVEL);
+                touched.insert(*p);
+            }
+        }
+
+        let old = self.chunks.clone();
+        let chunk_shape = self.chunk_shape;
+        let old_level = |p: Point3i| {
+            old.get(&chunk_min_containing_point(p, chunk_shape))
+                .map_or(0, |chunk: &Array3x1<u8>| chunk.get(p))
+        };
+        let mut wet = Vec::new();
+        for chunk in old.values() {
+            chunk.for_each(chunk.extent(), |p: Point3i, level: u8| {
+                if level > 0 {
+                    wet.push(p);
+                }
+            });
+        }
+        wet.sort_by_key(|p| (p.y(), p.x(), p.z()));
+
+        let below = PointN([0, -1, 0]);
+        let horizontal = [
+            PointN([1, 0, 0]),
+            PointN([-1, 0, 0]),
+            PointN([0, 0, 1]),
+            PointN([0, 0, -1]),
+        ];
+        for p in wet.into_iter() {
+            let mut amount = self.level(p);
+            if amount == 0 {
+                continue;
+            }
+            if cell(p) == FluidCell::Solid {
+                self.set_level(p, 0);
+                touched.insert(p);
+                continue;
+            }
+
+            // Fall.
+            let q = p + below;
+            match cell(q) {
+                FluidCell::Open => {
+                    let flow = amount.min(MAX_FLUID_LEVEL - self.level(q));
+                    if flow > 0 {
+                        self.set_level(q, self.level(q) + flow);
+                        amount -= flow;
+                        touched.insert(q);
+                    }
+                }
+                FluidCell::Drain => amount = 0,
+                FluidCell::Solid => (),
+            }
+
+            // Spread.
+            let start_amount = amount;
+            for offset in horizontal.iter() {
+                let q = p + *offset;
+                let neighbor_level = match cell(q) {
+                    FluidCell::Open => old_level(q),
+                    FluidCell::Drain => 0,
+                    FluidCell::Solid => continue,
+                };
+                if neighbor_level + 1 >= start_amount {
+                    continue;
+                }
+                let flow = ((start_amount - neighbor_level) / 5)
+                    .max(1)
+                    .min(amount)
+                    .min(MAX_FLUID_LEVEL - self.level(q));
+                if flow == 0 {
+                    continue;
+                }
+                if cell(q) == FluidCell::Open {
+                    self.set_level(q, self.level(q) + flow);
+                    touched.insert(q);
+                }
+                amount -= flow;
+            }
+
+            if amount != self.level(p) {
+                self.set_level(p, amount);
+                touched.insert(p);
+            }
+        }
+
+        for p in touched.into_iter() {
+            self.mark_dirty(p);
+        }
+        self.chunks.retain(|_, chunk| {
+            let mut any_fluid = false;
+            chunk.for_each(chunk.extent(), |_p: Point3i, level: u8| {
+                any_fluid |= level > 0
+            });
+
+            any_fluid
+        });
+    }
+
+    /// Dirties every chunk whose padded meshing extent contains `p`.
+    fn mark_dirty(&mut self, p: Point3i) {
+        let extent = Extent3i::from_min_and_max(p - PointN([1; 3]), p + PointN([2; 3]));
+        let indexer = ChunkIndexer::new(self.chunk_shape);
+        self.dirty_chunks
+            .extend(indexer.chunk_mins_for_extent(&extent));
+    }
+
+    /// Voxels of `voxel_type` whose distance comes from the fluid level, for meshing the fluid
+    /// surface with surface nets. Returns `None` if there's no fluid in `mesh_extent`.
+    pub fn mesh_voxels(
+        &self,
+        mesh_extent: &Extent3i,
+        voxel_type: VoxelType,
+    ) -> Option<Array3x1<Voxel>> {
+        let mut voxels = Array3x1::fill(*mesh_extent, EMPTY_VOXEL);
+        let mut any_fluid = false;
+        voxels.for_each_mut(mesh_extent, |p: Point3i, v: &mut Voxel| {
+            let level = self.level(p);
+            // Surface nets puts the surface halfway between a full voxel and an empty one.
+            v.distance = VoxelDistance::from(0.5 - level as f32 / MAX_FLUID_LEVEL as f32);
+            if level > 0 {
+                v.voxel_type = voxel_type;
+                any_fluid = true;
+            }
+        });
+
+        if any_fluid {
+            Some(voxels)
+        } else {
+            None
+        }
+    }
+}
+
+/// Steps the `FluidField` every `FluidConfig::tick_seconds`.
+#[derive(Default)]
+pub struct FluidSimulationSystem {
+    seconds_since_tick: f32,
+}
+
+impl<'a> System<'a> for FluidSimulationSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        Read<'a, Time>,
+        Read<'a, FluidConfig>,
+        Read<'a, FluidSources>,
+        ReadExpect<'a, VoxelMap>,
+        ReadExpect<'a, ChunkCacheFlusher>,
+        Write<'a, FluidField>,
+    );
+
+    fn run(
+        &mut self,
+        (time, config, sources, voxel_map, cache_flusher, mut field): Self::SystemData,
+    ) {
+        #[cfg(feature = "profiler")]
+        profile_scope!("fluid_simulation");
+
+        self.seconds_since_tick += time.delta_seconds();
+        if self.seconds_since_tick < config.tick_seconds {
+            return;
+        }
+        // Only one tick per frame, so a slow frame doesn't make the next one slower.
+        self.seconds_since_tick = 0.0;
+
+        let local_cache = LocalChunkCache3::new();
+        let reader = voxel_map.voxels.reader(&local_cache);
+        let map_chunk_shape = voxel_map.chunk_shape();
+        let stored_chunks: std::cell::RefCell<HashMap<Point3i, bool>> = Default::default();
+        let cell = |p: Point3i| {
+            let chunk_min = chunk_min_containing_point(p, map_chunk_shape);
+            let is_stored = *stored_chunks
+                .borrow_mut()
+                .entry(chunk_min)
+                .or_insert_with(|| reader.get_chunk(ChunkKey::new(0, chunk_min)).is_some());
+            if !is_stored {
+                FluidCell::Drain
+            } else if reader.lod_view(0).get(p).distance.0 < 0 {
+                FluidCell::Solid
+            } else {
+                FluidCell::Open
+            }
+        };
+        field.step(sources.iter(), cell);
+        cache_flusher.flush(local_cache);
+    }
+}
+
+/// Meshes the fluid surface of the chunks dirtied by the `FluidSimulationSystem`.
+pub struct FluidMeshSystem;
+
+impl<'a> System<'a> for FluidMeshSystem {
+    type SystemData = (
+        Read<'a, FluidConfig>,
+        Write<'a, FluidField>,
+        ReadExpect<'a, VoxelMap>,
+        WriteExpect<'a, VoxelAssets>,
+        VoxelMeshLoader<'a>,
+        VoxelMeshManager<'a>,
+    );
+
+    fn run(
+        &mut self,
+        (config, mut field, voxel_map, mut voxel_assets, loader, mut manager): Self::SystemData,
+    ) {
+        #[cfg(feature = "profiler")]
+        profile_scope!("fluid_meshing");
+
+        let info = match voxel_map.palette.infos.get(config.voxel_type.0 as usize) {
+            Some(info) => info,
+            None => return,
+        };
+        let layer = if info.flags.is_transparent {
+            MeshLayer::Transparent
+        } else {
+            MeshLayer::Opaque
+        };
+
+        let VoxelAssets {
+            array_materials,
+            meshes,
+            ..
+        } = &mut *voxel_assets;
+        let mut _unused_progress = ProgressCounter::new();
+        for chunk_min in field.take_dirty_chunks(config.max_meshes_per_frame) {
+            let chunk_extent = Extent3i::from_min_and_shape(chunk_min, field.chunk_shape());
+            let mesh: Option<ChunkMesh> = field
+                .mesh_voxels(
+                    &padded_surface_nets_chunk_extent(&chunk_extent),
+                    config.voxel_type,
+                )
+                .and_then(|voxels| {
+                    surface_nets_vertices(&voxel_map.palette, &voxels, layer, voxel_map.world_scale)
+                })
+                .map(|vertices| loader.start_loading_chunk(vertices, &mut _unused_progress));
+
+            manager.update_fluid_mesh_entities(chunk_min, mesh.clone(), array_materials);
+            if let Some(new_mesh) = mesh {
+                let _drop_old_fluid_meshes = meshes.fluid_chunk_meshes.insert(chunk_min, new_mesh);
+            } else {
+                meshes.fluid_chunk_meshes.remove(&chunk_min);
+            }
+        }
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fluid_falls_and_spreads_on_the_floor() {
+        // A floor at y = 0, inside a box of stored chunks.
+        let cell = |p: Point3i| {
+            if p.x().abs() > 20 || p.z().abs() > 20 || p.y() > 20 {
+                FluidCell::Drain
+            } else if p.y() <= 0 {
+                FluidCell::Solid
+            } else {
+                FluidCell::Open
+            }
+        };
+        let mut field = FluidField::default();
+        field.set_level(PointN([0, 5, 0]), MAX_FLUID_LEVEL);
+
+        for _ in 0..4 {
+            field.step(std::iter::empty(), cell);
+        }
+        assert_eq!(field.level(PointN([0, 5, 0])), 0);
+        assert!(field.level(PointN([0, 1, 0])) > 0);
+
+        for _ in 0..20 {
+            field.step(std::iter::empty(), cell);
+        }
+        assert!(field.level(PointN([0, 1, 0])) < MAX_FLUID_LEVEL);
+        // Nothing drained, so the fluid that left the column spread out over the floor.
+        assert_eq!(field.total_level(), MAX_FLUID_LEVEL as u64);
+        assert!(!field.take_dirty_chunks(100).is_empty());
+    }
+}
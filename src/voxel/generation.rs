@@ -0,0 +1,326 @@
+use crate::voxel::{
+    centered_extent, chunk_cache_flusher::ChunkCacheFlusher, chunk_streaming::StoredChunks,
+    double_buffer::EditedChunksBackBuffer, Voxel, VoxelDistance, VoxelMap, VoxelType, EMPTY_VOXEL,
+};
+
+use amethyst::core::ecs::prelude::*;
+use building_blocks::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+/// Synthesizes voxels for chunks that aren't stored in the map. When a source is registered with
+/// the `EditedChunksBackBuffer`, missing chunks are generated lazily instead of being treated as
+/// empty space, which allows for effectively unbounded procedural worlds.
+pub trait VoxelSource: Send + Sync {
+    /// Returns `None` if the chunk would be entirely ambient (empty) space.
+    fn generate_chunk(&self, chunk_extent: &Extent3i) -> Option<Array3x1<Voxel>>;
+}
+
+/// A serializable description of a `VoxelSource`, as found in a map file.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum VoxelSourceSpec {
+    /// An infinite flat plane with its surface at `height`.
+    Flat { height: i32, voxel_type: VoxelType },
+}
+
+impl VoxelSourceSpec {
+    pub fn build(&self) -> Arc<dyn VoxelSource> {
+        match self {
+            VoxelSourceSpec::Flat { height, voxel_type } => Arc::new(FlatSource {
+                height: *height,
+                voxel_type: *voxel_type,
+            }),
+        }
+    }
+}
+
+pub struct FlatSource {
+    pub height: i32,
+    pub voxel_type: VoxelType,
+}
+
+impl VoxelSource for FlatSource {
+    fn generate_chunk(&self, chunk_extent: &Extent3i) -> Option<Array3x1<Voxel>> {
+        if chunk_extent.minimum.y() > self.height + 1 {
+            return None;
+        }
+
+        let mut chunk = Array3x1::fill(*chunk_extent, EMPTY_VOXEL);
+        chunk.for_each_mut(chunk_extent, |p: Point3i, v: &mut Voxel| {
+            v.distance = VoxelDistance::from((p.y() - self.height) as f32);
+            if v.distance.0 < 0 {
+                v.voxel_type = self.voxel_type;
+            }
+        });
+
+        Some(chunk)
+    }
+}
+
+/// Tracks which chunks have been generated on demand. Chunks stay "pristine" until they are
+/// edited, so they don't need to be persisted; they can always be generated again. Updated by the
+/// `VoxelDoubleBufferingSystem` as chunks are merged into the map.
+#[derive(Default)]
+pub struct GeneratedChunks {
+    /// Every chunk that was already checked for generation, whether or not it needed it.
+    visited: HashSet<Point3i>,
+    pristine: HashSet<Point3i>,
+}
+
+impl GeneratedChunks {
+    pub fn is_pristine(&self, chunk_min: &Point3i) -> bool {
+        self.pristine.contains(chunk_min)
+    }
+
+    pub fn pristine_chunks(&self) -> impl Iterator<Item = &Point3i> {
+        self.pristine.iter()
+    }
+
+    pub(crate) fn mark_generated(&mut self, chunk_min: Point3i) {
+        self.pristine.insert(chunk_min);
+    }
+
+    pub(crate) fn mark_edited(&mut self, chunk_min: &Point3i) {
+        self.pristine.remove(chunk_min);
+    }
+
+    /// Called when a pristine chunk is evicted, so it can be generated again.
+    pub(crate) fn forget(&mut self, chunk_min: &Point3i) {
+        self.visited.remove(chunk_min);
+        self.pristine.remove(chunk_min);
+    }
+}
+
+/// Controls how chunks are streamed in and out around the centers passed to
+/// `ChunkGenerationRequests::request_around`. Larger radii mean less pop-in at the cost of memory.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StreamingConfig {
+    /// Chunks within this many voxels of a center are generated or loaded from `StoredChunks`.
+    pub load_radius: u32,
+    /// Chunks farther than this many voxels from every center are evicted from the map. Pristine
+    /// chunks can always be generated again, and the rest are stored first. Should be larger than
+    /// `load_radius` to avoid thrashing.
+    pub evict_radius: u32,
+    /// Avoid long frames when a large region is requested at once. The remaining chunks will be
+    /// generated on later frames as long as they're still requested.
+    pub max_chunk_loads_per_frame: usize,
+}
+
+impl Default for StreamingConfig {
+    fn default() -> Self {
+        Self {
+            load_radius: 64,
+            evict_radius: 128,
+            max_chunk_loads_per_frame: 32,
+        }
+    }
+}
+
+/// Extents that some consumer (e.g. a camera) wants to be populated by the `VoxelSource`.
+#[derive(Default)]
+pub struct ChunkGenerationRequests {
+    extents: Vec<Extent3i>,
+    centers: Vec<Point3i>,
+}
+
+impl ChunkGenerationRequests {
+    pub fn request_extent(&mut self, extent: Extent3i) {
+        self.extents.push(extent);
+    }
+
+    /// Requests the chunks within `StreamingConfig::load_radius` of `center`. Unlike
+    /// `request_extent`, this also keeps chunks near `center` from being evicted, so it should be
+    /// requested every frame.
+    pub fn request_around(&mut self, center: Point3i) {
+        self.centers.push(center);
+    }
+
+    /// The centers requested so far this frame.
+    pub fn centers(&self) -> &[Point3i] {
+        &self.centers
+    }
+}
+
+/// Generates any requested chunks that are missing from both the map and the `StoredChunks`, using
+/// the `VoxelSource` registered with the `EditedChunksBackBuffer`. The generated chunks go through
+/// the backbuffer like any other edit so they get meshed and inserted into the BVT.
+///
+/// If any centers were requested, pristine chunks outside of the `StreamingConfig::evict_radius` of
+/// all of them are evicted.
+pub struct ChunkGenerationSystem;
+
+impl<'a> System<'a> for ChunkGenerationSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        Read<'a, StreamingConfig>,
+        ReadExpect<'a, VoxelMap>,
+        ReadExpect<'a, ChunkCacheFlusher>,
+        Read<'a, StoredChunks>,
+        Write<'a, ChunkGenerationRequests>,
+        Write<'a, GeneratedChunks>,
+        WriteExpect<'a, EditedChunksBackBuffer>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            config,
+            voxel_map,
+            cache_flusher,
+            stored,
+            mut requests,
+            mut generated,
+            mut backbuffer,
+        ): Self::SystemData,
+    ) {
+        #[cfg(feature = "profiler")]
+        profile_scope!("chunk_generation");
+
+        let centers: Vec<Point3i> = requests.centers.drain(..).collect();
+        let mut extents: Vec<Extent3i> = requests.extents.drain(..).collect();
+        extents.extend(
+            centers
+                .iter()
+                .map(|c| centered_extent(*c, config.load_radius)),
+        );
+        if extents.is_empty() || backbuffer.voxel_source().is_none() {
+            return;
+        }
+
+        let local_cache = LocalChunkCache3::new();
+        let reader = voxel_map.voxels.reader(&local_cache);
+
+        if !centers.is_empty() {
+            let keep_extents: Vec<Extent3i> = centers
+                .iter()
+                .map(|c| centered_extent(*c, config.evict_radius))
+                .collect();
+            let evicted: Vec<Point3i> = generated
+                .pristine_chunks()
+                .filter(|chunk_min| {
+                    let chunk_extent = reader.indexer.extent_for_chunk_with_min(**chunk_min);
+
+                    keep_extents
+                        .iter()
+                        .all(|keep| keep.intersection(&chunk_extent).is_empty())
+                })
+                .cloned()
+                .collect();
+            backbuffer.evict_chunks(&reader, evicted);
+        }
+
+        let mut missing = Vec::new();
+        'outer: for extent in extents.iter() {
+            for chunk_min in reader.indexer.chunk_mins_for_extent(extent) {
+                if missing.len() >= config.max_chunk_loads_per_frame {
+                    break 'outer;
+                }
+                if !generated.visited.insert(chunk_min) {
+                    continue;
+                }
+                if reader.get_chunk(ChunkKey::new(0, chunk_min)).is_none()
+                    && !stored.contains(&chunk_min)
+                {
+                    missing.push(chunk_min);
+                }
+            }
+        }
+
+        backbuffer.generate_missing_chunks(&reader, missing);
+
+        cache_flusher.flush(local_cache);
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::{test_palette, VoxelPipelineHarness};
+
+    fn flat_harness() -> VoxelPipelineHarness {
+        let mut harness = VoxelPipelineHarness::new(VoxelMap::new(test_palette()));
+        harness
+            .world
+            .write_resource::<EditedChunksBackBuffer>()
+            .set_voxel_source(Some(Arc::new(FlatSource {
+                height: 0,
+                voxel_type: VoxelType(1),
+            })));
+        harness.world.insert(StreamingConfig {
+            load_radius: 16,
+            evict_radius: 48,
+            max_chunk_loads_per_frame: 1000,
+        });
+
+        harness
+    }
+
+    /// Generation and merging can happen in either order within a frame, so this takes two.
+    fn step_around(harness: &mut VoxelPipelineHarness, center: Point3i) {
+        for _ in 0..2 {
+            harness
+                .world
+                .write_resource::<ChunkGenerationRequests>()
+                .request_around(center);
+            harness.step();
+        }
+    }
+
+    fn map_has_chunk(harness: &VoxelPipelineHarness, chunk_min: Point3i) -> bool {
+        harness
+            .world
+            .read_resource::<VoxelMap>()
+            .voxels
+            .storage()
+            .chunk_keys()
+            .any(|key| key.minimum == chunk_min)
+    }
+
+    #[test]
+    fn test_request_around_generates_nearby_chunks() {
+        let mut harness = flat_harness();
+
+        step_around(&mut harness, PointN([0; 3]));
+
+        assert_eq!(harness.voxel(PointN([0, -1, 0])).voxel_type, VoxelType(1));
+        let near_chunk = PointN([0, -16, 0]);
+        assert!(map_has_chunk(&harness, near_chunk));
+        assert!(harness
+            .world
+            .read_resource::<GeneratedChunks>()
+            .is_pristine(&near_chunk));
+        assert!(!map_has_chunk(&harness, PointN([1024, -16, 0])));
+    }
+
+    #[test]
+    fn test_pristine_chunks_are_evicted_and_generated_again() {
+        let mut harness = flat_harness();
+        let chunk_min = PointN([0, -16, 0]);
+
+        step_around(&mut harness, PointN([0; 3]));
+        assert!(map_has_chunk(&harness, chunk_min));
+
+        step_around(&mut harness, PointN([10_000, 0, 0]));
+        assert!(!map_has_chunk(&harness, chunk_min));
+        assert!(!harness
+            .world
+            .read_resource::<GeneratedChunks>()
+            .is_pristine(&chunk_min));
+
+        step_around(&mut harness, PointN([0; 3]));
+        assert!(map_has_chunk(&harness, chunk_min));
+        assert_eq!(harness.voxel(PointN([0, -1, 0])).voxel_type, VoxelType(1));
+    }
+}
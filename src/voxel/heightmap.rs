@@ -0,0 +1,279 @@
+//! Import of grayscale heightmaps, so maps can be bootstrapped from real-world or generated
+//! terrain, and export of a map's surface for external terrain analysis.
+
+use crate::voxel::{Voxel, VoxelDistance, VoxelType, EMPTY_VOXEL};
+
+use building_blocks::prelude::*;
+use image::{DynamicImage, ImageBuffer, ImageResult, Luma};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// How a heightmap image becomes voxels. Each pixel is one column of voxels, with the image's X
+/// axis along X and its Y axis along Z. The terrain rests on Y = 0.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct HeightmapConfig {
+    /// The height in voxels of a white pixel. Black pixels have height 0.
+    pub vertical_scale: f32,
+    /// Solid voxels get the type of the highest band that starts at or below their altitude.
+    pub bands: Vec<AltitudeBand>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct AltitudeBand {
+    pub min_altitude: i32,
+    pub voxel_type: VoxelType,
+}
+
+impl HeightmapConfig {
+    fn voxel_type_at_altitude(&self, y: i32) -> VoxelType {
+        self.bands
+            .iter()
+            .filter(|b| b.min_altitude <= y)
+            .max_by_key(|b| b.min_altitude)
+            .or_else(|| self.bands.iter().min_by_key(|b| b.min_altitude))
+            .map(|b| b.voxel_type)
+            .unwrap_or(VoxelType(1))
+    }
+}
+
+pub struct Heightmap {
+    width: i32,
+    depth: i32,
+    /// In voxels, row by row along Z.
+    heights: Vec<f32>,
+}
+
+impl Heightmap {
+    /// Reads a grayscale image in any format supported by the `image` crate. 16-bit images keep
+    /// their full precision; anything else is converted to 8-bit luma.
+    pub fn read(path: impl AsRef<Path>, vertical_scale: f32) -> ImageResult<Self> {
+        let (width, depth, intensities): (u32, u32, Vec<f32>) = match image::open(path)? {
+            DynamicImage::ImageLuma16(img) => (
+                img.width(),
+                img.height(),
+                img.pixels()
+                    .map(|p| p.0[0] as f32 / std::u16::MAX as f32)
+                    .collect(),
+            ),
+            other => {
+                let img = other.to_luma();
+
+                (
+                    img.width(),
+                    img.height(),
+                    img.pixels()
+                        .map(|p| p.0[0] as f32 / std::u8::MAX as f32)
+                        .collect(),
+                )
+            }
+        };
+
+        Ok(Self::from_heights(
+            width as i32,
+            depth as i32,
+            intensities
+                .into_iter()
+                .map(|i| i * vertical_scale)
+                .collect(),
+        ))
+    }
+
+    pub fn from_heights(width: i32, depth: i32, heights: Vec<f32>) -> Self {
+        assert_eq!(heights.len(), (width * depth) as usize);
+
+        Self {
+            width,
+            depth,
+            heights,
+        }
+    }
+
+    /// The height of the top surface of each column of `chunks`, interpolated between the highest
+    /// solid voxel and the one above it. Returns the heightmap along with the (X, Z) of its first
+    /// column, or `None` if there are no chunks. Columns without any solid voxels have height 0, and
+    /// so do columns whose surface is below 0. The chunks must all have the same shape.
+    pub fn from_voxels(chunks: &[(Point3i, Array3x1<Voxel>)]) -> Option<([i32; 2], Self)> {
+        let chunk_shape = chunks.first()?.1.extent().shape;
+        let min_x = chunks.iter().map(|(chunk_min, _)| chunk_min.x()).min()?;
+        let min_z = chunks.iter().map(|(chunk_min, _)| chunk_min.z()).min()?;
+        let max_x = chunks.iter().map(|(chunk_min, _)| chunk_min.x()).max()?;
+        let max_z = chunks.iter().map(|(chunk_min, _)| chunk_min.z()).max()?;
+        let width = max_x + chunk_shape.x() - min_x;
+        let depth = max_z + chunk_shape.z() - min_z;
+
+        let mut map =
+            ChunkMapBuilder3x1::new(chunk_shape, EMPTY_VOXEL).build_with_hash_map_storage();
+        let mut top_solid_y: Vec<Option<i32>> = vec![None; (width * depth) as usize];
+        for (chunk_min, chunk) in chunks.iter() {
+            let extent = *chunk.extent();
+            chunk.for_each(&extent, |p: Point3i, v: Voxel| {
+                if v.distance.0 < 0 {
+                    let top = &mut top_solid_y[((p.z() - min_z) * width + p.x() - min_x) as usize];
+                    *top = Some(top.map_or(p.y(), |y| y.max(p.y())));
+                }
+            });
+            map.write_chunk(ChunkKey::new(0, *chunk_min), chunk.clone());
+        }
+
+        let view = map.lod_view(0);
+        let heights = top_solid_y
+            .into_iter()
+            .enumerate()
+            .map(|(i, top)| {
+                top.map_or(0.0, |y| {
+                    let (x, z) = (min_x + i as i32 % width, min_z + i as i32 / width);
+                    let below: f32 = view.get(PointN([x, y, z])).distance.into();
+                    let above: f32 = view.get(PointN([x, y + 1, z])).distance.into();
+
+                    (y as f32 + below / (below - above)).max(0.0)
+                })
+            })
+            .collect();
+
+        Some(([min_x, min_z], Self::from_heights(width, depth, heights)))
+    }
+
+    /// Inverse of `read` for 16-bit images.
+    pub fn to_luma16(&self, vertical_scale: f32) -> ImageBuffer<Luma<u16>, Vec<u16>> {
+        ImageBuffer::from_fn(self.width as u32, self.depth as u32, |x, z| {
+            let intensity = self.height(x as i32, z as i32) / vertical_scale;
+
+            Luma([(intensity.max(0.0).min(1.0) * std::u16::MAX as f32).round() as u16])
+        })
+    }
+
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn depth(&self) -> i32 {
+        self.depth
+    }
+
+    /// Columns outside of the image have the height of the nearest edge.
+    fn height(&self, x: i32, z: i32) -> f32 {
+        let x = x.max(0).min(self.width - 1);
+        let z = z.max(0).min(self.depth - 1);
+
+        self.heights[(z * self.width + x) as usize]
+    }
+
+    /// Dividing the vertical distance to the surface by this gives the distance along the surface
+    /// normal, so steep slopes don't get stretched distance fields.
+    fn slope_factor(&self, x: i32, z: i32) -> f32 {
+        let dx = (self.height(x + 1, z) - self.height(x - 1, z)) / 2.0;
+        let dz = (self.height(x, z + 1) - self.height(x, z - 1)) / 2.0;
+
+        (1.0 + dx * dx + dz * dz).sqrt()
+    }
+
+    pub fn max_height(&self) -> f32 {
+        self.heights.iter().cloned().fold(0.0, f32::max)
+    }
+
+    /// The voxel extent covering the terrain, with a voxel of padding above the highest point.
+    pub fn extent(&self) -> Extent3i {
+        Extent3i::from_min_and_shape(
+            PointN([0; 3]),
+            PointN([self.width, self.max_height().ceil() as i32 + 2, self.depth]),
+        )
+    }
+}
+
+/// Extrudes the heightmap into signed distance voxels in chunks of `chunk_shape`, returning every
+/// chunk that contains some of the terrain or the space just above it.
+pub fn heightmap_chunks(
+    heightmap: &Heightmap,
+    config: &HeightmapConfig,
+    chunk_shape: Point3i,
+) -> Vec<(Point3i, Array3x1<Voxel>)> {
+    let extent = heightmap.extent();
+    let chunk_mins: Vec<Point3i> = ChunkIndexer::new(chunk_shape)
+        .chunk_mins_for_extent(&extent)
+        .collect();
+
+    chunk_mins
+        .into_par_iter()
+        .filter_map(|chunk_min| {
+            let chunk_extent = Extent3i::from_min_and_shape(chunk_min, chunk_shape);
+            let mut chunk = Array3x1::fill(chunk_extent, EMPTY_VOXEL);
+            let mut any_near_surface = false;
+            chunk.for_each_mut(
+                &extent.intersection(&chunk_extent),
+                |p: Point3i, v: &mut Voxel| {
+                    let distance = (p.y() as f32 - heightmap.height(p.x(), p.z()))
+                        / heightmap.slope_factor(p.x(), p.z());
+                    v.distance = VoxelDistance::from(distance);
+                    if distance < 0.0 {
+                        v.voxel_type = config.voxel_type_at_altitude(p.y());
+                    }
+                    any_near_surface |= distance < 1.0;
+                },
+            );
+
+            if any_near_surface {
+                Some((chunk_min, chunk))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::voxel::VOXEL_CHUNK_SHAPE;
+
+    #[test]
+    fn test_heightmap_is_extruded_with_altitude_bands() {
+        // A 2x2 plateau of height 20 on ground of height 5.
+        let mut heights = vec![5.0; 16];
+        for &i in [5, 6, 9, 10].iter() {
+            heights[i] = 20.0;
+        }
+        let heightmap = Heightmap::from_heights(4, 4, heights);
+        let config = HeightmapConfig {
+            vertical_scale: 1.0,
+            bands: vec![
+                AltitudeBand {
+                    min_altitude: 0,
+                    voxel_type: VoxelType(1),
+                },
+                AltitudeBand {
+                    min_altitude: 16,
+                    voxel_type: VoxelType(2),
+                },
+            ],
+        };
+
+        let mut map =
+            ChunkMapBuilder3x1::new(VOXEL_CHUNK_SHAPE, EMPTY_VOXEL).build_with_hash_map_storage();
+        for (chunk_min, chunk) in
+            heightmap_chunks(&heightmap, &config, VOXEL_CHUNK_SHAPE).into_iter()
+        {
+            map.write_chunk(ChunkKey::new(0, chunk_min), chunk);
+        }
+        let view = map.lod_view(0);
+
+        assert_eq!(view.get(PointN([0, 2, 0])).voxel_type, VoxelType(1));
+        assert!(view.get(PointN([0, 6, 0])).distance.0 > 0);
+        assert_eq!(view.get(PointN([1, 18, 1])).voxel_type, VoxelType(2));
+        assert!(view.get(PointN([1, 21, 1])).distance.0 > 0);
+
+        let chunks = heightmap_chunks(&heightmap, &config, VOXEL_CHUNK_SHAPE);
+        let (origin, exported) = Heightmap::from_voxels(&chunks).unwrap();
+        assert_eq!(origin, [0, 0]);
+        assert!((exported.height(0, 0) - 5.0).abs() < 0.5);
+        assert!((exported.height(2, 2) - 20.0).abs() < 0.5);
+    }
+}
@@ -0,0 +1,118 @@
+use building_blocks::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A point light anchored to the center of a voxel. Lights are stored in the map file and become
+/// `PointLight` entities when the map is loaded.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct VoxelLight {
+    pub position: [i32; 3],
+    /// Linear RGB.
+    #[serde(default = "default_light_color")]
+    pub color: [f32; 3],
+    #[serde(default = "default_light_intensity")]
+    pub intensity: f32,
+    /// The distance in voxels where the light fades out.
+    #[serde(default = "default_light_radius")]
+    pub radius: f32,
+}
+
+fn default_light_color() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn default_light_intensity() -> f32 {
+    10.0
+}
+
+fn default_light_radius() -> f32 {
+    10.0
+}
+
+impl VoxelLight {
+    pub fn new(position: Point3i) -> Self {
+        Self {
+            position: position.0,
+            color: default_light_color(),
+            intensity: default_light_intensity(),
+            radius: default_light_radius(),
+        }
+    }
+
+    pub fn position(&self) -> Point3i {
+        PointN(self.position)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MapLights {
+    lights: Vec<VoxelLight>,
+    /// Set whenever the lights change, so they can be saved with the map.
+    changed: bool,
+}
+
+impl MapLights {
+    pub fn new(lights: Vec<VoxelLight>) -> Self {
+        Self {
+            lights,
+            changed: false,
+        }
+    }
+
+    pub fn add(&mut self, light: VoxelLight) {
+        self.lights.push(light);
+        self.changed = true;
+    }
+
+    /// Removes the light closest to `p`, as long as it's within `radius` voxels.
+    pub fn remove_near(&mut self, p: Point3i, radius: f32) -> Option<VoxelLight> {
+        let (i, dist) = self
+            .lights
+            .iter()
+            .enumerate()
+            .map(|(i, l)| (i, (l.position() - p).norm()))
+            .min_by(|(_, d1), (_, d2)| d1.partial_cmp(d2).unwrap())?;
+        if dist > radius {
+            return None;
+        }
+        self.changed = true;
+
+        Some(self.lights.remove(i))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &VoxelLight> {
+        self.lights.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    pub fn has_changed(&self) -> bool {
+        self.changed
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remove_nearest_light() {
+        let mut lights = MapLights::default();
+        lights.add(VoxelLight::new(PointN([0, 0, 0])));
+        lights.add(VoxelLight::new(PointN([10, 0, 0])));
+        assert!(lights.has_changed());
+
+        assert_eq!(lights.remove_near(PointN([5, 0, 0]), 1.5), None);
+        let removed = lights.remove_near(PointN([9, 0, 0]), 1.5).unwrap();
+        assert_eq!(removed.position(), PointN([10, 0, 0]));
+        assert_eq!(lights.iter().count(), 1);
+    }
+}
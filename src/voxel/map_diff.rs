@@ -0,0 +1,256 @@
+//! Compares and merges the chunks of voxels files, e.g. to combine edits made to copies of the same
+//! map in version control. Chunks missing from a file are ambient space, so they compare equal to
+//! chunks full of `EMPTY_VOXEL`.
+
+use crate::voxel::{empty_array, morton::morton_key, Voxel, EMPTY_VOXEL, VOXEL_CHUNK_SHAPE};
+
+use building_blocks::prelude::*;
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+/// The voxels that differ in one chunk.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ChunkDiff {
+    pub chunk_min: Point3i,
+    /// The smallest extent containing every changed voxel.
+    pub changed_extent: Extent3i,
+    pub num_changed: usize,
+}
+
+/// What to do with a voxel that was changed both by the diff and in the target.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConflictResolution {
+    KeepTarget,
+    TakeChanges,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MergeReport {
+    pub num_applied: usize,
+    /// The voxels that the target changed differently, per chunk in morton order.
+    pub conflicts: Vec<ChunkDiff>,
+}
+
+type Chunks = [(Point3i, Array3x1<Voxel>)];
+
+fn index_chunks(chunks: &Chunks) -> HashMap<Point3i, &Array3x1<Voxel>> {
+    chunks.iter().map(|(min, chunk)| (*min, chunk)).collect()
+}
+
+fn voxel_at(chunk: Option<&&Array3x1<Voxel>>, p: Point3i) -> Voxel {
+    chunk.map_or(EMPTY_VOXEL, |c| c.get(p))
+}
+
+/// The shape shared by all of the chunks, which must be the same in every file.
+fn chunk_shape(chunk_sets: &[&Chunks]) -> Point3i {
+    let mut shapes = chunk_sets
+        .iter()
+        .flat_map(|chunks| chunks.iter())
+        .map(|(_, chunk)| chunk.extent().shape);
+    let shape = shapes.next().unwrap_or(VOXEL_CHUNK_SHAPE);
+    assert!(
+        shapes.all(|s| s == shape),
+        "All voxels files must have the same chunk shape"
+    );
+
+    shape
+}
+
+fn sorted_chunk_mins(
+    maps: &[&HashMap<Point3i, &Array3x1<Voxel>>],
+    chunk_shape: Point3i,
+) -> Vec<Point3i> {
+    let mins: HashSet<Point3i> = maps.iter().flat_map(|m| m.keys().cloned()).collect();
+    let mut mins: Vec<Point3i> = mins.into_iter().collect();
+    mins.sort_by_key(|min| morton_key(*min, chunk_shape));
+
+    mins
+}
+
+/// Accumulates changed points into a `ChunkDiff`.
+struct DiffBuilder {
+    chunk_min: Point3i,
+    min: Point3i,
+    max: Point3i,
+    num_changed: usize,
+}
+
+impl DiffBuilder {
+    fn new(chunk_min: Point3i) -> Self {
+        Self {
+            chunk_min,
+            min: PointN([i32::MAX; 3]),
+            max: PointN([i32::MIN; 3]),
+            num_changed: 0,
+        }
+    }
+
+    fn add(&mut self, p: Point3i) {
+        self.min = self.min.meet(p);
+        self.max = self.max.join(p);
+        self.num_changed += 1;
+    }
+
+    fn build(self) -> Option<ChunkDiff> {
+        if self.num_changed == 0 {
+            return None;
+        }
+
+        Some(ChunkDiff {
+            chunk_min: self.chunk_min,
+            changed_extent: Extent3i::from_min_and_max(self.min, self.max),
+            num_changed: self.num_changed,
+        })
+    }
+}
+
+/// Finds the chunks that differ between `old` and `new`, in morton order.
+pub fn diff_chunks(old: &Chunks, new: &Chunks) -> Vec<ChunkDiff> {
+    let chunk_shape = chunk_shape(&[old, new]);
+    let old = index_chunks(old);
+    let new = index_chunks(new);
+
+    sorted_chunk_mins(&[&old, &new], chunk_shape)
+        .into_par_iter()
+        .filter_map(|chunk_min| {
+            let (old_chunk, new_chunk) = (old.get(&chunk_min), new.get(&chunk_min));
+            let mut diff = DiffBuilder::new(chunk_min);
+            let extent = Extent3i::from_min_and_shape(chunk_min, chunk_shape);
+            for p in extent.iter_points() {
+                if voxel_at(old_chunk, p) != voxel_at(new_chunk, p) {
+                    diff.add(p);
+                }
+            }
+
+            diff.build()
+        })
+        .collect()
+}
+
+/// Applies the changes from `base` to `changed` onto `target`, i.e. a three-way merge. Voxels that
+/// `target` also changed, to something else, are resolved with `resolution`. Returns the merged
+/// chunks in morton order, including any chunks of `target` that weren't touched.
+pub fn merge_chunks(
+    base: &Chunks,
+    changed: &Chunks,
+    target: &Chunks,
+    resolution: ConflictResolution,
+) -> (Vec<(Point3i, Array3x1<Voxel>)>, MergeReport) {
+    let chunk_shape = chunk_shape(&[base, changed, target]);
+    let base = index_chunks(base);
+    let changed = index_chunks(changed);
+    let target = index_chunks(target);
+
+    let merged: Vec<_> = sorted_chunk_mins(&[&base, &changed, &target], chunk_shape)
+        .into_par_iter()
+        .filter_map(|chunk_min| {
+            let (base_chunk, changed_chunk, target_chunk) = (
+                base.get(&chunk_min),
+                changed.get(&chunk_min),
+                target.get(&chunk_min),
+            );
+            let extent = Extent3i::from_min_and_shape(chunk_min, chunk_shape);
+            let mut merged_chunk =
+                target_chunk.map_or_else(|| empty_array(extent), |c| (*c).clone());
+            let mut num_applied = 0;
+            let mut conflicts = DiffBuilder::new(chunk_min);
+            for p in extent.iter_points() {
+                let base_voxel = voxel_at(base_chunk, p);
+                let changed_voxel = voxel_at(changed_chunk, p);
+                let target_voxel = voxel_at(target_chunk, p);
+                if base_voxel == changed_voxel || target_voxel == changed_voxel {
+                    continue;
+                }
+                if target_voxel != base_voxel {
+                    conflicts.add(p);
+                    if resolution == ConflictResolution::KeepTarget {
+                        continue;
+                    }
+                }
+                *merged_chunk.get_mut(p) = changed_voxel;
+                num_applied += 1;
+            }
+
+            // Don't store new chunks that would only hold ambient space.
+            if target_chunk.is_none() && num_applied == 0 {
+                return None;
+            }
+
+            Some(((chunk_min, merged_chunk), num_applied, conflicts.build()))
+        })
+        .collect();
+
+    let mut report = MergeReport::default();
+    let mut chunks = Vec::with_capacity(merged.len());
+    for (chunk, num_applied, conflict) in merged.into_iter() {
+        chunks.push(chunk);
+        report.num_applied += num_applied;
+        report.conflicts.extend(conflict);
+    }
+
+    (chunks, report)
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::voxel::{VoxelDistance, VoxelType};
+
+    fn solid(t: u8) -> Voxel {
+        Voxel {
+            voxel_type: VoxelType(t),
+            distance: VoxelDistance::from(-1.0),
+        }
+    }
+
+    fn chunk_with(points: &[(Point3i, Voxel)]) -> (Point3i, Array3x1<Voxel>) {
+        let mut chunk = empty_array(Extent3i::from_min_and_shape(
+            PointN([0; 3]),
+            VOXEL_CHUNK_SHAPE,
+        ));
+        for (p, v) in points.iter() {
+            *chunk.get_mut(*p) = *v;
+        }
+
+        (PointN([0; 3]), chunk)
+    }
+
+    #[test]
+    fn test_diff_and_merge() {
+        let base = vec![chunk_with(&[(PointN([1, 1, 1]), solid(1))])];
+        let changed = vec![chunk_with(&[
+            (PointN([1, 1, 1]), solid(2)),
+            (PointN([3, 4, 5]), solid(2)),
+        ])];
+        // The target changed one of the same voxels differently.
+        let target = vec![chunk_with(&[
+            (PointN([1, 1, 1]), solid(1)),
+            (PointN([3, 4, 5]), solid(3)),
+        ])];
+
+        assert_eq!(
+            diff_chunks(&base, &changed),
+            vec![ChunkDiff {
+                chunk_min: PointN([0; 3]),
+                changed_extent: Extent3i::from_min_and_max(PointN([1, 1, 1]), PointN([3, 4, 5])),
+                num_changed: 2,
+            }]
+        );
+
+        let (merged, report) =
+            merge_chunks(&base, &changed, &target, ConflictResolution::KeepTarget);
+        assert_eq!(report.num_applied, 1);
+        assert_eq!(report.conflicts.len(), 1);
+        assert_eq!(report.conflicts[0].num_changed, 1);
+        assert_eq!(merged[0].1.get(PointN([1, 1, 1])), solid(2));
+        assert_eq!(merged[0].1.get(PointN([3, 4, 5])), solid(3));
+    }
+}
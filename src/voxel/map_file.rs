@@ -1,90 +1,1005 @@
 use crate::{
     assets::{read_bincode_file, write_bincode_file, BincodeFileError},
-    voxel::{VoxelMap, VoxelPalette, EMPTY_VOXEL, VOXEL_CHUNK_SHAPE},
+    voxel::{
+        chunk_compression::{ChunkCodec, MaybeCompressedChunk},
+        chunk_lock::LockedChunks,
+        chunk_streaming::StoredChunks,
+        empty_array,
+        fluid::FluidSources,
+        generation::{VoxelSource, VoxelSourceSpec},
+        heightmap::{heightmap_chunks, Heightmap, HeightmapConfig},
+        lights::{MapLights, VoxelLight},
+        map_generators::{
+            generate_dungeon, generate_noise_terrain, DungeonMapSpec, NoiseTerrainConfig,
+        },
+        markers::{MapMarkers, Marker},
+        metadata::{VoxelMetadata, UNTAGGED},
+        morton::{morton_key, morton_ordered_chunk_mins},
+        props::{MapProps, Prop},
+        zones::{MapZones, Zone},
+        Voxel, VoxelDistance, VoxelMap, VoxelPalette, VoxelType, EMPTY_VOXEL, VOXEL_CHUNK_SHAPE,
+    },
 };
 
-use amethyst::config::Config;
-use building_blocks::{prelude::*, storage::MaybeCompressed};
+use amethyst::config::{Config, ConfigError};
+use building_blocks::prelude::*;
+use building_blocks::storage::{MaybeCompressed, Sd16, Sd8};
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 #[derive(Deserialize, Serialize)]
 pub struct VoxelMapFile {
     palette: VoxelPalette,
     voxels_file_path: Option<(VoxelsFileType, String)>,
+    /// Generates any chunks that aren't stored in the voxels file.
+    #[serde(default)]
+    generator: Option<VoxelSourceSpec>,
+    /// Minimums of chunks that are protected from edits.
+    #[serde(default)]
+    locked_chunks: Vec<[i32; 3]>,
+    /// Named points of interest, like spawn points.
+    #[serde(default)]
+    markers: Vec<Marker>,
+    /// Named regions, like music areas.
+    #[serde(default)]
+    zones: Vec<Zone>,
+    /// Point lights anchored to voxels.
+    #[serde(default)]
+    lights: Vec<VoxelLight>,
+    /// Points that fluid flows out of.
+    #[serde(default)]
+    fluid_sources: Vec<[i32; 3]>,
+    /// Prefab entities placed in the map.
+    #[serde(default)]
+    props: Vec<Prop>,
+    /// A bincode file with the per-voxel gameplay tags; see `VoxelMetadata`.
+    #[serde(default)]
+    metadata_file_path: Option<String>,
+    /// How chunks are compressed in memory when they leave the cache.
+    #[serde(default)]
+    codec: ChunkCodec,
+    /// The edge length of a voxel in world units; see `VoxelMap::world_scale`.
+    #[serde(default = "default_world_scale")]
+    world_scale: f32,
+    /// The shape of the map's chunks. Each dimension must be a power of 2.
+    #[serde(default = "default_chunk_shape")]
+    chunk_shape: [i32; 3],
+    /// The changes made in the editor, which are saved to the edits file instead of the map file.
+    #[serde(skip)]
+    edits: MapEditsFile,
+}
+
+/// The things the editor changed in a map. They're saved next to the map file, e.g.
+/// "example_map.editor.ron", so the hand-written map file and its comments are left alone. Each
+/// field that's present replaces the map file's when the map is loaded with
+/// `VoxelMapFile::load_with_edits`.
+#[derive(Clone, Default, Deserialize, Serialize)]
+struct MapEditsFile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    palette: Option<VoxelPalette>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    locked_chunks: Option<Vec<[i32; 3]>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    markers: Option<Vec<Marker>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    zones: Option<Vec<Zone>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lights: Option<Vec<VoxelLight>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    fluid_sources: Option<Vec<[i32; 3]>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    props: Option<Vec<Prop>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metadata_file_path: Option<String>,
+}
+
+/// Where the editor's changes to the map at `map_path` are saved, e.g. "example_map.editor.ron"
+/// for "example_map.ron".
+pub fn map_edits_path(map_path: impl AsRef<Path>) -> PathBuf {
+    map_path.as_ref().with_extension("editor.ron")
+}
+
+fn default_world_scale() -> f32 {
+    1.0
+}
+
+fn default_chunk_shape() -> [i32; 3] {
+    VOXEL_CHUNK_SHAPE.0
+}
+
+/// An error loading or saving a map: either in the map file itself, or in one of the files it
+/// refers to.
+#[derive(Debug)]
+pub enum MapFileError {
+    ConfigError(ConfigError),
+    BincodeFileError(BincodeFileError),
+}
+
+impl From<ConfigError> for MapFileError {
+    fn from(other: ConfigError) -> Self {
+        MapFileError::ConfigError(other)
+    }
+}
+
+impl From<BincodeFileError> for MapFileError {
+    fn from(other: BincodeFileError) -> Self {
+        MapFileError::BincodeFileError(other)
+    }
+}
+
+impl From<io::Error> for MapFileError {
+    fn from(other: io::Error) -> Self {
+        MapFileError::BincodeFileError(other.into())
+    }
+}
+
+impl std::fmt::Display for MapFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MapFileError::ConfigError(e) => write!(f, "{}", e),
+            MapFileError::BincodeFileError(e) => write!(f, "{:?}", e),
+        }
+    }
+}
+
+impl std::error::Error for MapFileError {}
+
+/// A map file is parsed once with `VoxelMapFile::load_with_edits`, and everything in the map is
+/// loaded from the result. The editor's changes are saved by setting them and writing the edits
+/// file with `write_edits`.
+impl VoxelMapFile {
+    /// Loads the map file at `map_path`, with the changes from its edits file applied if it has
+    /// one; see `map_edits_path`.
+    pub fn load_with_edits(map_path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let map_path = map_path.as_ref();
+        let mut spec = Self::load(map_path)?;
+        let edits_path = map_edits_path(map_path);
+        if edits_path.exists() {
+            spec.apply_edits(MapEditsFile::load(edits_path)?);
+        }
+
+        Ok(spec)
+    }
+
+    fn apply_edits(&mut self, edits: MapEditsFile) {
+        if let Some(palette) = &edits.palette {
+            self.palette = palette.clone();
+        }
+        if let Some(locked_chunks) = &edits.locked_chunks {
+            self.locked_chunks = locked_chunks.clone();
+        }
+        if let Some(markers) = &edits.markers {
+            self.markers = markers.clone();
+        }
+        if let Some(zones) = &edits.zones {
+            self.zones = zones.clone();
+        }
+        if let Some(lights) = &edits.lights {
+            self.lights = lights.clone();
+        }
+        if let Some(fluid_sources) = &edits.fluid_sources {
+            self.fluid_sources = fluid_sources.clone();
+        }
+        if let Some(props) = &edits.props {
+            self.props = props.clone();
+        }
+        if let Some(metadata_file_path) = &edits.metadata_file_path {
+            self.metadata_file_path = Some(metadata_file_path.clone());
+        }
+        self.edits = edits;
+    }
+
+    /// Writes everything that was set since the map was loaded, along with the earlier edits, to
+    /// the edits file of the map at `map_path`. The map file itself isn't written.
+    pub fn write_edits(&self, map_path: impl AsRef<Path>) -> Result<(), ConfigError> {
+        self.edits.write(map_edits_path(map_path))
+    }
+
+    /// Creates the empty map, with the file's chunk shape and world scale.
+    fn empty_map(&self) -> Result<VoxelMap, ConfigError> {
+        if !self.chunk_shape.iter().all(|&d| d > 0 && d & (d - 1) == 0) {
+            return Err(ConfigError::File(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Chunk shape {:?} isn't made of powers of 2",
+                    self.chunk_shape
+                ),
+            )));
+        }
+        if self.world_scale.is_nan() || self.world_scale <= 0.0 {
+            return Err(ConfigError::File(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("World scale must be positive, not {}", self.world_scale),
+            )));
+        }
+
+        let mut map =
+            VoxelMap::with_chunk_shape(self.palette.clone(), self.codec, PointN(self.chunk_shape));
+        map.world_scale = self.world_scale;
+
+        Ok(map)
+    }
+
+    /// Loads all of the map's voxels into a new map.
+    pub fn load_voxel_map(&self) -> Result<VoxelMap, MapFileError> {
+        let mut map = self.empty_map()?;
+        match &self.voxels_file_path {
+            Some((VoxelsFileType::Bincode, voxels_path)) => {
+                write_chunks(&mut map, read_voxels_file(voxels_path)?);
+            }
+            Some((VoxelsFileType::ProcGenDungeon, spec_path)) => {
+                load_dungeon(&mut map, spec_path)?;
+            }
+            Some((VoxelsFileType::ProcGenNoise, config_path)) => {
+                load_noise_terrain(&mut map, config_path)?;
+            }
+            Some((VoxelsFileType::Heightmap(config), image_path)) => {
+                load_heightmap(&mut map, image_path, config)?;
+            }
+            None => (),
+        }
+
+        Ok(map)
+    }
+
+    /// Like `load_voxel_map`, but the chunks of the voxels file are left compressed in the returned
+    /// `StoredChunks` instead of being loaded into the map. The `ChunkStreamingSystem` loads them
+    /// as they're needed.
+    pub fn load_streamed_voxel_map(&self) -> Result<(VoxelMap, StoredChunks), MapFileError> {
+        let mut map = self.empty_map()?;
+        let chunk_shape = map.chunk_shape();
+        let stored = match &self.voxels_file_path {
+            Some((VoxelsFileType::Bincode, voxels_path)) => {
+                let (file_chunk_shape, chunks) = read_compressed_voxels_file(voxels_path)?;
+                if file_chunk_shape == chunk_shape {
+                    StoredChunks::new(chunk_shape, chunks)
+                } else {
+                    // Stored chunks go straight into the map, so chunks of another shape are all
+                    // converted up front. The map is saved with its own chunk shape.
+                    write_chunks(&mut map, decompress_chunks(file_chunk_shape, chunks)?);
+
+                    StoredChunks::new(chunk_shape, Vec::new())
+                }
+            }
+            // Generated dungeons, terrain and heightmaps are small enough to create up front.
+            // Their chunks are stored once they're evicted.
+            Some((VoxelsFileType::ProcGenDungeon, spec_path)) => {
+                load_dungeon(&mut map, spec_path)?;
+
+                StoredChunks::new(chunk_shape, Vec::new())
+            }
+            Some((VoxelsFileType::ProcGenNoise, config_path)) => {
+                load_noise_terrain(&mut map, config_path)?;
+
+                StoredChunks::new(chunk_shape, Vec::new())
+            }
+            Some((VoxelsFileType::Heightmap(config), image_path)) => {
+                load_heightmap(&mut map, image_path, config)?;
+
+                StoredChunks::new(chunk_shape, Vec::new())
+            }
+            None => StoredChunks::new(chunk_shape, Vec::new()),
+        };
+
+        Ok((map, stored))
+    }
+
+    /// Where the map's voxels should be saved: its bincode voxels file if it has one, otherwise
+    /// "saved_voxels.bin".
+    pub fn voxels_save_path(&self) -> PathBuf {
+        match &self.voxels_file_path {
+            Some((VoxelsFileType::Bincode, voxels_path)) => PathBuf::from(voxels_path),
+            _ => PathBuf::from(DEFAULT_VOXELS_FILE),
+        }
+    }
+
+    /// Builds the `VoxelSource` described by the map file, if any. It should be registered with
+    /// the `EditedChunksBackBuffer` so that missing chunks are generated on demand.
+    pub fn voxel_source(&self) -> Option<Arc<dyn VoxelSource>> {
+        self.generator.as_ref().map(|g| g.build())
+    }
+
+    /// The voxel types that the map's generators place, which have to stay in the palette even if
+    /// no stored voxel uses them yet.
+    pub fn generated_voxel_types(&self) -> Result<Vec<VoxelType>, MapFileError> {
+        let mut types = Vec::new();
+        match &self.generator {
+            Some(VoxelSourceSpec::Flat { voxel_type, .. }) => types.push(*voxel_type),
+            None => (),
+        }
+        match &self.voxels_file_path {
+            Some((VoxelsFileType::ProcGenDungeon, spec_path)) => {
+                let spec = DungeonMapSpec::load(spec_path)?;
+                types.push(spec.voxel_types.empty);
+                types.push(spec.voxel_types.solid);
+            }
+            Some((VoxelsFileType::ProcGenNoise, config_path)) => {
+                let config = NoiseTerrainConfig::load(config_path)?;
+                types.extend(config.layers.iter().map(|layer| layer.voxel_type));
+            }
+            Some((VoxelsFileType::Heightmap(config), _)) => {
+                types.extend(config.bands.iter().map(|band| band.voxel_type));
+            }
+            Some((VoxelsFileType::Bincode, _)) | None => (),
+        }
+
+        Ok(types)
+    }
+
+    /// Replaces the palette, e.g. after `remap_palette`.
+    pub fn set_palette(&mut self, palette: &VoxelPalette) {
+        self.palette = palette.clone();
+        self.edits.palette = Some(palette.clone());
+    }
+
+    pub fn locked_chunks(&self) -> LockedChunks {
+        LockedChunks::new(self.locked_chunks.iter().cloned().map(PointN))
+    }
+
+    pub fn set_locked_chunks(&mut self, locked_chunks: &LockedChunks) {
+        self.locked_chunks = locked_chunks.iter().map(|p| p.0).collect();
+        self.locked_chunks.sort();
+        self.edits.locked_chunks = Some(self.locked_chunks.clone());
+    }
+
+    pub fn markers(&self) -> MapMarkers {
+        MapMarkers::new(self.markers.clone())
+    }
+
+    pub fn set_markers(&mut self, markers: &MapMarkers) {
+        self.markers = markers.iter().cloned().collect();
+        self.edits.markers = Some(self.markers.clone());
+    }
+
+    pub fn zones(&self) -> MapZones {
+        MapZones::new(self.zones.clone())
+    }
+
+    pub fn set_zones(&mut self, zones: &MapZones) {
+        self.zones = zones.iter().cloned().collect();
+        self.edits.zones = Some(self.zones.clone());
+    }
+
+    pub fn lights(&self) -> MapLights {
+        MapLights::new(self.lights.clone())
+    }
+
+    pub fn set_lights(&mut self, lights: &MapLights) {
+        self.lights = lights.iter().cloned().collect();
+        self.edits.lights = Some(self.lights.clone());
+    }
+
+    pub fn fluid_sources(&self) -> FluidSources {
+        FluidSources::new(self.fluid_sources.iter().cloned().map(PointN).collect())
+    }
+
+    pub fn set_fluid_sources(&mut self, sources: &FluidSources) {
+        self.fluid_sources = sources.iter().map(|p| p.0).collect();
+        self.edits.fluid_sources = Some(self.fluid_sources.clone());
+    }
+
+    pub fn props(&self) -> MapProps {
+        MapProps::new(self.props.clone())
+    }
+
+    pub fn set_props(&mut self, props: &MapProps) {
+        self.props = props.iter().cloned().collect();
+        self.edits.props = Some(self.props.clone());
+    }
 }
 
 #[derive(Deserialize, Serialize)]
 pub enum VoxelsFileType {
     Bincode,
+    /// A RON file with a `DungeonMapSpec`. The dungeon is generated when the map is loaded, and
+    /// saved to "saved_voxels.bin".
     ProcGenDungeon,
+    /// A RON file with a `NoiseTerrainConfig`. The terrain is generated when the map is loaded, and
+    /// saved to "saved_voxels.bin".
+    ProcGenNoise,
+    /// A grayscale image, extruded into terrain when the map is loaded. The map is saved to
+    /// "saved_voxels.bin", since the image can't hold edits.
+    Heightmap(HeightmapConfig),
+}
+
+/// Loads the voxels of the map file at `path`; see `VoxelMapFile::load_voxel_map`.
+pub fn load_voxel_map(path: impl AsRef<Path>) -> Result<VoxelMap, MapFileError> {
+    VoxelMapFile::load(path)?.load_voxel_map()
+}
+
+fn load_heightmap(
+    map: &mut VoxelMap,
+    image_path: impl AsRef<Path>,
+    config: &HeightmapConfig,
+) -> Result<(), BincodeFileError> {
+    let heightmap = Heightmap::read(image_path, config.vertical_scale)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    write_chunks(map, heightmap_chunks(&heightmap, config, map.chunk_shape()));
+
+    Ok(())
+}
+
+fn load_dungeon(map: &mut VoxelMap, spec_path: impl AsRef<Path>) -> Result<(), BincodeFileError> {
+    let spec = DungeonMapSpec::load(spec_path)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    write_chunks(map, generate_dungeon(&spec, map.chunk_shape()));
+
+    Ok(())
+}
+
+fn load_noise_terrain(
+    map: &mut VoxelMap,
+    config_path: impl AsRef<Path>,
+) -> Result<(), BincodeFileError> {
+    let config = NoiseTerrainConfig::load(config_path)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    write_chunks(map, generate_noise_terrain(&config, map.chunk_shape()));
+
+    Ok(())
+}
+
+/// Writes `chunks` into the map. Chunks that don't have the map's shape, like the generators' and
+/// those of voxels files saved before the map's chunk shape changed, are split up and merged into
+/// chunks of the map's shape.
+fn write_chunks(map: &mut VoxelMap, chunks: Vec<(Point3i, Array3x1<Voxel>)>) {
+    let indexer = ChunkIndexer::new(map.chunk_shape());
+    let mut rechunked: HashMap<Point3i, Array3x1<Voxel>> = HashMap::new();
+    for (chunk_min, chunk) in chunks.into_iter() {
+        if chunk.extent().shape == indexer.chunk_shape() {
+            map.voxels.write_chunk(ChunkKey::new(0, chunk_min), chunk);
+            continue;
+        }
+        for dst_min in indexer.chunk_mins_for_extent(chunk.extent()) {
+            let dst = rechunked
+                .entry(dst_min)
+                .or_insert_with(|| empty_array(indexer.extent_for_chunk_with_min(dst_min)));
+            let overlap = chunk.extent().intersection(dst.extent());
+            copy_extent(&overlap, &chunk, dst);
+        }
+    }
+    for (chunk_min, chunk) in rechunked.into_iter() {
+        map.voxels.write_chunk(ChunkKey::new(0, chunk_min), chunk);
+    }
+}
+
+const DEFAULT_VOXELS_FILE: &str = "saved_voxels.bin";
+
+#[derive(Deserialize, Serialize)]
+struct VoxelsFile {
+    chunk_shape: [i32; 3],
+    /// In Morton order, so spatial regions can be read back with mostly sequential reads.
+    chunks: Vec<SavedChunk>,
 }
 
-pub fn load_voxel_map(path: impl AsRef<Path>) -> Result<VoxelMap, BincodeFileError> {
-    // TODO: gosh I guess we should have another error type
-    let spec: VoxelMapFile = Config::load(path).unwrap();
-
-    // let voxels = match spec.voxels_file_path {
-    // TODO: add support for ChunkDb from building-blocks
-    // Some((VoxelsFileType::Bincode, path)) => {
-    //     let serializable_map: SerializableChunkMap3<_> = read_bincode_file(path)?;
-
-    //     let map = futures::executor::block_on(ChunkMap3::from_serializable(
-    //         &serializable_map,
-    //         Lz4 { level: 10 },
-    //     ));
-
-    //     let mut sum_bytes = 0;
-    //     for (_, chunk) in map.chunks.iter_maybe_compressed() {
-    //         match chunk {
-    //             MaybeCompressed::Compressed(compressed_chunk) => {
-    //                 sum_bytes += compressed_chunk.compressed_array.compressed_bytes.len();
-    //             }
-    //             _ => (),
-    //         }
-    //     }
-    //     let num_chunks = serializable_map.compressed_chunks.len();
-    //     log::debug!(
-    //         "# chunks = {}; avg compressed size = {} bytes",
-    //         num_chunks,
-    //         sum_bytes / num_chunks
-    //     );
-
-    //     map
-    // }
-    // TODO: return support for ProcGenDungeon map type; this was removed temporarily while
-    // porting from ilattice3 to building-blocks, because ilattice3-procgen will take some more
-    // effort to port
-    // Some((VoxelsFileType::ProcGenDungeon, path)) => {
-    //     // TODO: don't hardcode this
-    //     let voxel_type_map = [0, 2];
-
-    //     generate_dungeon(path, voxel_type_map).unwrap()
-    // }
-    // _ => {
-    //     let ambient_value = EMPTY_VOXEL;
-
-    //     VoxelMap::new(VOXEL_CHUNK_SHAPE, ambient_value, (), Lz4 { level: 10 })
-    // }
-    // };
-
-    Ok(VoxelMap::new(spec.palette))
-}
-
-// pub fn save_voxel_map(path: impl AsRef<Path>, map: &VoxelMap) -> Result<(), BincodeFileError> {
-//     let serializable_map =
-//         futures::executor::block_on(map.voxels.to_serializable(BincodeLz4 { level: 16 }));
-
-//     let mut sum_bytes = 0;
-//     for chunk in serializable_map.compressed_chunks.values() {
-//         sum_bytes += chunk.compressed_bytes.len();
-//     }
-//     let num_chunks = serializable_map.compressed_chunks.len();
-//     log::debug!(
-//         "# chunks = {}; avg compressed size = {} bytes",
-//         num_chunks,
-//         sum_bytes / num_chunks
-//     );
-
-//     write_bincode_file(path, &serializable_map)
-// }
+#[derive(Deserialize, Serialize)]
+struct SavedChunk {
+    minimum: [i32; 3],
+    /// LZ4-compressed bincode of the chunk's voxels in array order.
+    lz4_voxels: Vec<u8>,
+}
+
+/// Copies every chunk out of the map, so they can be serialized without holding on to the map.
+/// Compressed chunks are decompressed one at a time into a throwaway cache, so this doesn't disturb
+/// the map's own cache.
+pub fn snapshot_chunks(map: &VoxelMap) -> Vec<(Point3i, Array3x1<Voxel>)> {
+    morton_ordered_chunk_mins(map)
+        .into_iter()
+        .filter_map(|chunk_min| {
+            let local_cache = LocalChunkCache3::new();
+            let reader = map.voxels.reader(&local_cache);
+
+            reader
+                .get_chunk(ChunkKey::new(0, chunk_min))
+                .cloned()
+                .map(|chunk| (chunk_min, chunk))
+        })
+        .collect()
+}
+
+/// Like `snapshot_chunks`, but chunks that are compressed in the map's cache are copied without
+/// being decompressed, so the decompression can happen off of the calling thread with
+/// `decompress_snapshot`. Chunks for which `skip` returns true are left out.
+pub fn snapshot_maybe_compressed_chunks(
+    map: &VoxelMap,
+    skip: impl Fn(&Point3i) -> bool,
+) -> Vec<(Point3i, MaybeCompressedChunk)> {
+    map.voxels
+        .storage()
+        .iter_maybe_compressed()
+        .filter(|(chunk_key, _)| !skip(&chunk_key.minimum))
+        .map(|(chunk_key, chunk)| {
+            let chunk = match chunk {
+                MaybeCompressed::Decompressed(chunk) => {
+                    MaybeCompressed::Decompressed(chunk.clone())
+                }
+                MaybeCompressed::Compressed(chunk) => MaybeCompressed::Compressed(chunk.clone()),
+            };
+
+            (chunk_key.minimum, chunk)
+        })
+        .collect()
+}
+
+/// Decompresses the chunks taken with `snapshot_maybe_compressed_chunks`, in parallel.
+pub fn decompress_snapshot(
+    chunks: Vec<(Point3i, MaybeCompressedChunk)>,
+) -> Vec<(Point3i, Array3x1<Voxel>)> {
+    chunks
+        .into_par_iter()
+        .map(|(chunk_min, chunk)| match chunk {
+            MaybeCompressed::Decompressed(chunk) => (chunk_min, chunk),
+            MaybeCompressed::Compressed(chunk) => (chunk_min, chunk.decompress()),
+        })
+        .collect()
+}
+
+/// Compresses and writes chunks taken with `snapshot_chunks`. This is slow for large maps, so it
+/// should be kept off of the main thread; see `BackgroundSaves`. The chunks must all have
+/// `chunk_shape`, the map's `VoxelMap::chunk_shape`, which is written even if there are no chunks.
+pub fn write_voxels_file(
+    path: impl AsRef<Path>,
+    chunk_shape: Point3i,
+    chunks: Vec<(Point3i, Array3x1<Voxel>)>,
+) -> Result<(), BincodeFileError> {
+    write_voxels_file_with_compressed(path, chunk_shape, chunks, Vec::new())
+}
+
+/// Like `write_voxels_file`, but also writes chunks that were already compressed with
+/// `compress_chunk`, e.g. the unloaded chunks of a streamed map. All of the chunks must have
+/// `chunk_shape`.
+pub fn write_voxels_file_with_compressed(
+    path: impl AsRef<Path>,
+    chunk_shape: Point3i,
+    chunks: Vec<(Point3i, Array3x1<Voxel>)>,
+    compressed_chunks: Vec<(Point3i, Vec<u8>)>,
+) -> Result<(), BincodeFileError> {
+    let mut chunks = chunks
+        .into_par_iter()
+        .map(|(chunk_min, chunk)| {
+            Ok(SavedChunk {
+                minimum: chunk_min.0,
+                lz4_voxels: compress_chunk(
+                    &chunk,
+                    Some(lz4::block::CompressionMode::HIGHCOMPRESSION(10)),
+                )?,
+            })
+        })
+        .collect::<Result<Vec<_>, BincodeFileError>>()?;
+    chunks.extend(
+        compressed_chunks
+            .into_iter()
+            .map(|(chunk_min, lz4_voxels)| SavedChunk {
+                minimum: chunk_min.0,
+                lz4_voxels,
+            }),
+    );
+    chunks.sort_by_key(|saved| morton_key(PointN(saved.minimum), chunk_shape));
+
+    write_bincode_file(
+        path,
+        VoxelsFile {
+            chunk_shape: chunk_shape.0,
+            chunks,
+        },
+    )
+}
+
+/// LZ4-compresses the bincode of the chunk's voxels in array order, as stored in the voxels file.
+/// The default `mode` is fast enough to use while editing.
+pub fn compress_chunk(
+    chunk: &Array3x1<Voxel>,
+    mode: Option<lz4::block::CompressionMode>,
+) -> Result<Vec<u8>, BincodeFileError> {
+    let extent = *chunk.extent();
+    let mut voxels = Vec::with_capacity(extent.num_points());
+    chunk.for_each(&extent, |_p: Point3i, v: Voxel| voxels.push(v));
+
+    Ok(lz4::block::compress(
+        &bincode::serialize(&voxels)?,
+        mode,
+        true,
+    )?)
+}
+
+/// Inverse of `compress_chunk`, for a chunk with `chunk_shape`. It's an error if the number of
+/// voxels doesn't match the shape.
+pub fn decompress_chunk(
+    chunk_min: Point3i,
+    chunk_shape: Point3i,
+    lz4_voxels: &[u8],
+) -> Result<Array3x1<Voxel>, BincodeFileError> {
+    let voxels = deserialize_voxels(&lz4::block::decompress(lz4_voxels, None)?)?;
+    let extent = Extent3i::from_min_and_shape(chunk_min, chunk_shape);
+    if voxels.len() != extent.num_points() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Chunk at {:?} has {} voxels, but its shape {:?} has {} points",
+                chunk_min.0,
+                voxels.len(),
+                chunk_shape.0,
+                extent.num_points()
+            ),
+        )
+        .into());
+    }
+    let mut chunk = Array3x1::fill(extent, EMPTY_VOXEL);
+    let mut voxels = voxels.into_iter();
+    chunk.for_each_mut(&extent, |_p: Point3i, v: &mut Voxel| {
+        // There's exactly one voxel per point.
+        *v = voxels.next().unwrap();
+    });
+
+    Ok(chunk)
+}
+
+/// A voxel saved with either distance precision.
+#[derive(Deserialize)]
+struct SavedVoxel<D> {
+    voxel_type: VoxelType,
+    distance: D,
+}
+
+/// Deserializes voxels saved with either the `sd16` feature or without it, converting the
+/// distances if they don't match this build. The precision is told apart by the number of bytes
+/// per voxel, after the `u64` length of the `Vec`.
+pub(crate) fn deserialize_voxels(bytes: &[u8]) -> Result<Vec<Voxel>, BincodeFileError> {
+    let num_voxels: u64 = bincode::deserialize(bytes)?;
+    let num_voxel_bytes = (bytes.len() as u64).checked_sub(8).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Voxels are missing their length",
+        )
+    })?;
+    let bytes_per_voxel = num_voxel_bytes / num_voxels.max(1);
+
+    Ok(match bytes_per_voxel {
+        2 if cfg!(feature = "sd16") => convert_voxels::<Sd8>(bincode::deserialize(bytes)?),
+        3 if cfg!(not(feature = "sd16")) => convert_voxels::<Sd16>(bincode::deserialize(bytes)?),
+        _ => bincode::deserialize(bytes)?,
+    })
+}
+
+fn convert_voxels<D: Into<f32>>(saved: Vec<SavedVoxel<D>>) -> Vec<Voxel> {
+    saved
+        .into_iter()
+        .map(|v| Voxel {
+            voxel_type: v.voxel_type,
+            distance: VoxelDistance::from(v.distance.into()),
+        })
+        .collect()
+}
+
+/// Reads and decompresses every chunk of a voxels file. The chunks have the shape they were saved
+/// with.
+pub fn read_voxels_file(
+    path: impl AsRef<Path>,
+) -> Result<Vec<(Point3i, Array3x1<Voxel>)>, BincodeFileError> {
+    read_voxels_file_with_shape(path).map(|(_, chunks)| chunks)
+}
+
+/// Like `read_voxels_file`, but also returns the chunk shape of the file, e.g. to write the chunks
+/// back out with `write_voxels_file`.
+#[allow(clippy::type_complexity)]
+pub fn read_voxels_file_with_shape(
+    path: impl AsRef<Path>,
+) -> Result<(Point3i, Vec<(Point3i, Array3x1<Voxel>)>), BincodeFileError> {
+    let (chunk_shape, chunks) = read_compressed_voxels_file(path)?;
+
+    Ok((chunk_shape, decompress_chunks(chunk_shape, chunks)?))
+}
+
+fn decompress_chunks(
+    chunk_shape: Point3i,
+    chunks: Vec<(Point3i, Vec<u8>)>,
+) -> Result<Vec<(Point3i, Array3x1<Voxel>)>, BincodeFileError> {
+    // Decompression is the slow part of loading, and each chunk is independent.
+    chunks
+        .into_par_iter()
+        .map(|(chunk_min, lz4_voxels)| {
+            Ok((
+                chunk_min,
+                decompress_chunk(chunk_min, chunk_shape, &lz4_voxels)?,
+            ))
+        })
+        .collect()
+}
+
+/// Returns the chunk shape of the file along with its chunks.
+#[allow(clippy::type_complexity)]
+fn read_compressed_voxels_file(
+    path: impl AsRef<Path>,
+) -> Result<(Point3i, Vec<(Point3i, Vec<u8>)>), BincodeFileError> {
+    let file: VoxelsFile = read_bincode_file(path)?;
+    let chunks = file
+        .chunks
+        .into_iter()
+        .map(|saved| (PointN(saved.minimum), saved.lz4_voxels))
+        .collect();
+
+    Ok((PointN(file.chunk_shape), chunks))
+}
+
+/// Writes a new map file with the palette, codec, world scale and chunk shape of `map`, and the
+/// voxels from the bincode voxels file at `voxels_path`. Unlike the `save_*` functions, this
+/// doesn't read an existing map file, so the new map has no generator, locked chunks, markers,
+/// zones, lights or props.
+pub fn write_new_map_file(
+    path: impl AsRef<Path>,
+    map: &VoxelMap,
+    voxels_path: &str,
+) -> Result<(), ConfigError> {
+    let spec = VoxelMapFile {
+        palette: map.palette.clone(),
+        voxels_file_path: Some((VoxelsFileType::Bincode, voxels_path.to_string())),
+        generator: None,
+        locked_chunks: Vec::new(),
+        markers: Vec::new(),
+        zones: Vec::new(),
+        lights: Vec::new(),
+        fluid_sources: Vec::new(),
+        props: Vec::new(),
+        metadata_file_path: None,
+        codec: map.codec,
+        world_scale: map.world_scale,
+        chunk_shape: map.chunk_shape().0,
+        edits: MapEditsFile::default(),
+    };
+
+    spec.write(path)
+}
+
+#[derive(Deserialize, Serialize)]
+struct MetadataFile {
+    chunk_shape: [i32; 3],
+    /// In Morton order, like the voxels file.
+    chunks: Vec<SavedMetadataChunk>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct SavedMetadataChunk {
+    minimum: [i32; 3],
+    /// LZ4-compressed values in array order.
+    lz4_values: Vec<u8>,
+}
+
+impl VoxelMapFile {
+    /// Reads the map's metadata file, if it has one. A file saved with another chunk shape is
+    /// re-chunked to the map's.
+    pub fn load_voxel_metadata(&self) -> Result<VoxelMetadata, MapFileError> {
+        let chunk_shape = PointN(self.chunk_shape);
+        let metadata_path = match &self.metadata_file_path {
+            Some(p) => p,
+            None => return Ok(VoxelMetadata::new(chunk_shape, Vec::new())),
+        };
+
+        let file: MetadataFile = read_bincode_file(metadata_path)?;
+        let file_chunk_shape = PointN(file.chunk_shape);
+        let chunks = file
+            .chunks
+            .into_iter()
+            .map(|saved| {
+                let values = lz4::block::decompress(&saved.lz4_values, None)?;
+                let chunk_min = PointN(saved.minimum);
+                let extent = Extent3i::from_min_and_shape(chunk_min, file_chunk_shape);
+                if values.len() != extent.num_points() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "Metadata chunk at {:?} has {} values, but its shape {:?} has {}",
+                            chunk_min.0,
+                            values.len(),
+                            file_chunk_shape.0,
+                            extent.num_points()
+                        ),
+                    )
+                    .into());
+                }
+                let mut chunk = Array3x1::fill(extent, UNTAGGED);
+                let mut values = values.into_iter();
+                chunk.for_each_mut(&extent, |_p: Point3i, v: &mut u8| {
+                    *v = values.next().unwrap();
+                });
+
+                Ok((chunk_min, chunk))
+            })
+            .collect::<Result<Vec<_>, BincodeFileError>>()?;
+
+        Ok(VoxelMetadata::new(chunk_shape, chunks))
+    }
+
+    /// Writes the map's metadata file. Maps that don't have one yet get a file next to their
+    /// voxels file, e.g. "saved_voxels.meta.bin", so the edits file has to be written again
+    /// afterwards.
+    pub fn save_voxel_metadata(&mut self, metadata: &VoxelMetadata) -> Result<(), MapFileError> {
+        let metadata_path = self.metadata_file_path.clone().unwrap_or_else(|| {
+            self.voxels_save_path()
+                .with_extension("meta.bin")
+                .to_string_lossy()
+                .into_owned()
+        });
+
+        let chunks = metadata
+            .snapshot_chunks()
+            .into_iter()
+            .map(|(chunk_min, chunk)| {
+                let extent = *chunk.extent();
+                let mut values = Vec::with_capacity(extent.num_points());
+                chunk.for_each(&extent, |_p: Point3i, v: u8| values.push(v));
+
+                Ok(SavedMetadataChunk {
+                    minimum: chunk_min.0,
+                    lz4_values: lz4::block::compress(&values, None, true)?,
+                })
+            })
+            .collect::<Result<Vec<_>, BincodeFileError>>()?;
+        write_bincode_file(
+            &metadata_path,
+            MetadataFile {
+                chunk_shape: metadata.chunk_shape().0,
+                chunks,
+            },
+        )?;
+        self.edits.metadata_file_path = Some(metadata_path.clone());
+        self.metadata_file_path = Some(metadata_path);
+
+        Ok(())
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::test_palette;
+
+    #[test]
+    fn test_chunks_are_rechunked_into_map_shape() {
+        let mut map =
+            VoxelMap::with_chunk_shape(test_palette(), ChunkCodec::default(), PointN([32, 16, 32]));
+        let solid = Voxel {
+            voxel_type: VoxelType(1),
+            distance: VoxelDistance::from(-1.0),
+        };
+        let chunks: Vec<_> = [[0, 0, 0], [16, 0, 16], [0, 16, 0]]
+            .iter()
+            .map(|&min| {
+                let chunk_min = PointN(min);
+                let mut chunk =
+                    empty_array(Extent3i::from_min_and_shape(chunk_min, VOXEL_CHUNK_SHAPE));
+                *chunk.get_mut(chunk_min + PointN([1; 3])) = solid;
+
+                (chunk_min, chunk)
+            })
+            .collect();
+        write_chunks(&mut map, chunks);
+
+        let mut chunk_mins: Vec<[i32; 3]> = map
+            .voxels
+            .storage()
+            .chunk_keys()
+            .map(|key| key.minimum.0)
+            .collect();
+        chunk_mins.sort();
+        assert_eq!(chunk_mins, vec![[0, 0, 0], [0, 16, 0]]);
+
+        let local_cache = LocalChunkCache3::new();
+        let reader = map.voxels.reader(&local_cache);
+        for &p in [[1, 1, 1], [17, 1, 17], [1, 17, 1]].iter() {
+            assert_eq!(reader.lod_view(0).get(PointN(p)).voxel_type, VoxelType(1));
+        }
+        assert_eq!(
+            reader.lod_view(0).get(PointN([2, 2, 2])).voxel_type,
+            VoxelType(0)
+        );
+    }
+
+    #[test]
+    fn test_map_with_large_chunks_loads_in_its_own_shape() {
+        let dir = std::env::temp_dir().join(format!("large_chunk_map_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let voxels_path = dir.join("voxels.bin");
+        let map_path = dir.join("map.ron");
+        let chunk_shape = PointN([32; 3]);
+        let solid = Voxel {
+            voxel_type: VoxelType(1),
+            distance: VoxelDistance::from(-1.0),
+        };
+
+        // The voxels file still has the default chunk shape.
+        let chunk_min = PointN([16, 0, 0]);
+        let mut chunk = empty_array(Extent3i::from_min_and_shape(chunk_min, VOXEL_CHUNK_SHAPE));
+        *chunk.get_mut(PointN([17, 1, 1])) = solid;
+        write_voxels_file(&voxels_path, VOXEL_CHUNK_SHAPE, vec![(chunk_min, chunk)]).unwrap();
+        let mut new_map =
+            VoxelMap::with_chunk_shape(test_palette(), ChunkCodec::default(), chunk_shape);
+        new_map.world_scale = 0.5;
+        write_new_map_file(&map_path, &new_map, voxels_path.to_str().unwrap()).unwrap();
+
+        let mut map_spec = VoxelMapFile::load(&map_path).unwrap();
+        for map in vec![
+            map_spec.load_voxel_map().unwrap(),
+            map_spec.load_streamed_voxel_map().unwrap().0,
+        ] {
+            assert_eq!(map.chunk_shape(), chunk_shape);
+            assert_eq!(map.world_scale, 0.5);
+            let chunk_mins: Vec<Point3i> = map
+                .voxels
+                .storage()
+                .chunk_keys()
+                .map(|key| key.minimum)
+                .collect();
+            assert_eq!(chunk_mins, vec![PointN([0; 3])]);
+            let local_cache = LocalChunkCache3::new();
+            let reader = map.voxels.reader(&local_cache);
+            assert_eq!(reader.lod_view(0).get(PointN([17, 1, 1])), solid);
+        }
+
+        let mut metadata = map_spec.load_voxel_metadata().unwrap();
+        assert_eq!(metadata.chunk_shape(), chunk_shape);
+        metadata.set(PointN([17, 1, 1]), 3);
+        map_spec.save_voxel_metadata(&metadata).unwrap();
+        let metadata = map_spec.load_voxel_metadata().unwrap();
+        assert_eq!(metadata.get(PointN([17, 1, 1])), 3);
+        assert_eq!(metadata.snapshot_chunks().len(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_editor_changes_are_saved_next_to_the_map_file() {
+        let dir = std::env::temp_dir().join(format!("map_edits_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let map_path = dir.join("map.ron");
+        write_new_map_file(&map_path, &VoxelMap::new(test_palette()), "voxels.bin").unwrap();
+        let map_ron = std::fs::read_to_string(&map_path).unwrap();
+        let locked_min = PointN([16, 0, 0]);
+
+        let mut map_spec = VoxelMapFile::load_with_edits(&map_path).unwrap();
+        map_spec.set_locked_chunks(&LockedChunks::new(vec![locked_min]));
+        map_spec.write_edits(&map_path).unwrap();
+        assert_eq!(std::fs::read_to_string(&map_path).unwrap(), map_ron);
+        assert!(!VoxelMapFile::load(&map_path)
+            .unwrap()
+            .locked_chunks()
+            .is_locked(&locked_min));
+
+        // Edits from earlier sessions are kept when later ones are saved.
+        let mut map_spec = VoxelMapFile::load_with_edits(&map_path).unwrap();
+        assert!(map_spec.locked_chunks().is_locked(&locked_min));
+        map_spec.set_props(&MapProps::new(Vec::new()));
+        map_spec.write_edits(&map_path).unwrap();
+        let map_spec = VoxelMapFile::load_with_edits(&map_path).unwrap();
+        assert!(map_spec.locked_chunks().is_locked(&locked_min));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_decompressing_into_the_wrong_shape_fails() {
+        let chunk = empty_array(Extent3i::from_min_and_shape(
+            PointN([0; 3]),
+            PointN([16; 3]),
+        ));
+        let lz4_voxels = compress_chunk(&chunk, None).unwrap();
+
+        assert!(decompress_chunk(PointN([0; 3]), PointN([16; 3]), &lz4_voxels).is_ok());
+        assert!(decompress_chunk(PointN([0; 3]), PointN([32; 3]), &lz4_voxels).is_err());
+        assert!(decompress_chunk(PointN([0; 3]), PointN([8; 3]), &lz4_voxels).is_err());
+
+        // Too short to even hold the number of voxels.
+        assert!(deserialize_voxels(&[]).is_err());
+        assert!(deserialize_voxels(&[1, 0, 0]).is_err());
+    }
+}
@@ -1,44 +1,566 @@
-use super::{Voxel, EMPTY_VOXEL, VOXEL_CHUNK_SHAPE};
-
-use amethyst::config::Config;
-use building_blocks::prelude::*;
-use std::path::Path;
-
-/// `voxel_type_map` is used to convert from the dungeon voxel type indices to the corresponding
-/// palette addresses.
-pub fn generate_dungeon<P: AsRef<Path>>(
-    path: P,
-    voxel_type_map: [u8; 2],
-) -> amethyst::Result<ChunkMap3<Voxel>> {
-    let spec = DungeonMapSpec::load(path)?;
-
-    let mut map = ChunkMap3::new(VOXEL_CHUNK_SHAPE);
-    let mut encoder = DungeonEncoder::new(&mut map, voxel_type_map);
-
-    let mut rng = small_rng(spec.seed);
-    spec.generate(&mut rng, &mut encoder);
-
-    Ok(map)
-}
-
-pub struct DungeonEncoder<'a> {
-    map: &'a mut ChunkMap3<Voxel>,
-    voxel_type_map: [u8; 2],
-}
-
-impl<'a> DungeonEncoder<'a> {
-    pub fn new(map: &'a mut ChunkMap3<Voxel>, voxel_type_map: [u8; 2]) -> Self {
-        DungeonEncoder {
-            map,
-            voxel_type_map,
-        }
-    }
-}
-
-impl VoxelEncoder for DungeonEncoder<'_> {
-    fn encode_voxel(&mut self, point: &Point3i, data: &ProcVoxel) {
-        let (_, voxel) = self.map.get_mut_or_default(point, (), EMPTY_VOXEL);
-        voxel.distance = encode_distance(data.distance);
-        voxel.voxel_type = self.voxel_type_map[data.voxel_type as usize];
-    }
-}
+use super::{Voxel, VoxelDistance, VoxelType, EMPTY_VOXEL};
+
+use building_blocks::prelude::*;
+use noise::{Fbm, MultiFractal, NoiseFn, Seedable};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Rolling terrain made from fractal Brownian motion (layered Perlin noise), loaded from the RON
+/// file given with `VoxelsFileType::ProcGenNoise`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct NoiseTerrainConfig {
+    pub seed: u32,
+    /// The terrain covers X and Z from 0 up to (but not including) these sizes.
+    pub size: [i32; 2],
+    /// Average height of the surface.
+    pub base_height: i32,
+    /// The surface is at most this many voxels above or below `base_height`.
+    pub amplitude: f32,
+    /// Cycles per voxel of the first octave.
+    pub frequency: f64,
+    pub octaves: usize,
+    /// Frequency multiplier from one octave to the next.
+    pub lacunarity: f64,
+    /// Amplitude multiplier from one octave to the next.
+    pub persistence: f64,
+    /// From the surface down. The last layer extends all the way to the bottom of the terrain.
+    pub layers: Vec<MaterialLayer>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct MaterialLayer {
+    /// In voxels.
+    pub thickness: i32,
+    pub voxel_type: VoxelType,
+}
+
+impl NoiseTerrainConfig {
+    fn voxel_type_at_depth(&self, depth: f32) -> VoxelType {
+        let mut layer_bottom = 0.0;
+        for layer in self.layers.iter() {
+            layer_bottom += layer.thickness as f32;
+            if depth < layer_bottom {
+                return layer.voxel_type;
+            }
+        }
+
+        self.layers
+            .last()
+            .map(|l| l.voxel_type)
+            .unwrap_or(VoxelType(1))
+    }
+
+    /// The voxels that may contain terrain. The bottom is flat, just below the lowest possible
+    /// surface.
+    fn extent(&self) -> Extent3i {
+        let amplitude = self.amplitude.ceil() as i32;
+        let min = PointN([0, self.base_height - amplitude - 2, 0]);
+        let max = PointN([
+            self.size[0] - 1,
+            self.base_height + amplitude + 2,
+            self.size[1] - 1,
+        ]);
+
+        Extent3i::from_min_and_max(min, max)
+    }
+}
+
+/// Generates the chunks of the terrain described by `config`, as a signed distance field, in
+/// chunks of `chunk_shape`. Chunks that are entirely empty are skipped.
+pub fn generate_noise_terrain(
+    config: &NoiseTerrainConfig,
+    chunk_shape: Point3i,
+) -> Vec<(Point3i, Array3x1<Voxel>)> {
+    let fbm = Fbm::new()
+        .set_seed(config.seed)
+        .set_octaves(config.octaves)
+        .set_frequency(config.frequency)
+        .set_lacunarity(config.lacunarity)
+        .set_persistence(config.persistence);
+    let height = |x: i32, z: i32| {
+        config.base_height as f32 + config.amplitude * fbm.get([x as f64, z as f64]) as f32
+    };
+
+    let extent = config.extent();
+    let chunk_mins: Vec<Point3i> = ChunkIndexer::new(chunk_shape)
+        .chunk_mins_for_extent(&extent)
+        .collect();
+
+    chunk_mins
+        .into_par_iter()
+        .filter_map(|chunk_min| {
+            let chunk_extent = Extent3i::from_min_and_shape(chunk_min, chunk_shape);
+            let fill_extent = extent.intersection(&chunk_extent);
+            if fill_extent.is_empty() {
+                return None;
+            }
+
+            // Sample each column once, along with the slope, which turns the vertical distance to
+            // the surface into the distance along its normal.
+            let [min_x, _, min_z] = fill_extent.minimum.0;
+            let [width, _, depth] = fill_extent.shape.0;
+            let columns: Vec<(f32, f32)> = (0..width * depth)
+                .map(|i| {
+                    let (x, z) = (min_x + i % width, min_z + i / width);
+                    let dx = (height(x + 1, z) - height(x - 1, z)) / 2.0;
+                    let dz = (height(x, z + 1) - height(x, z - 1)) / 2.0;
+
+                    (height(x, z), (1.0 + dx * dx + dz * dz).sqrt())
+                })
+                .collect();
+
+            let mut chunk = Array3x1::fill(chunk_extent, EMPTY_VOXEL);
+            let mut any_near_surface = false;
+            chunk.for_each_mut(&fill_extent, |p: Point3i, v: &mut Voxel| {
+                let (surface, slope_factor) =
+                    columns[((p.z() - min_z) * width + p.x() - min_x) as usize];
+                let distance = (p.y() as f32 - surface) / slope_factor;
+                v.distance = VoxelDistance::from(distance);
+                if distance < 0.0 {
+                    v.voxel_type = config.voxel_type_at_depth(surface - p.y() as f32);
+                }
+                any_near_surface |= distance < 1.0;
+            });
+
+            if any_near_surface {
+                Some((chunk_min, chunk))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// A dungeon of box-shaped rooms connected by corridors, carved out of rock. Loaded from the RON
+/// file given with `VoxelsFileType::ProcGenDungeon`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DungeonMapSpec {
+    pub seed: (u32, u32, u32, u32),
+    pub room_graph: RoomGraphSpec,
+    pub room_dist: RoomDistribution,
+    pub min_room_dim: i32,
+    pub max_room_dim: i32,
+    /// Corridors have a square cross section with a side length between these dimensions.
+    pub min_door_dim: i32,
+    pub max_door_dim: i32,
+    /// Palette addresses for the carved out space and the rock around it.
+    #[serde(default)]
+    pub voxel_types: DungeonVoxelTypes,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct RoomGraphSpec {
+    pub num_rooms: usize,
+    /// The number of corridors between the entrance room and the objective room. The other rooms
+    /// branch off of this path, and the objective is always a dead end.
+    pub entrance_to_objective_path_length: usize,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct RoomDistribution {
+    /// Room centers are uniformly distributed within these (inclusive) ranges.
+    pub location: AxisRanges,
+    /// Room dimensions are normally distributed, then clamped to the min and max room dimensions.
+    pub size: AxisNormals,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct AxisRanges {
+    pub x: (i32, i32),
+    pub y: (i32, i32),
+    pub z: (i32, i32),
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct AxisNormals {
+    pub x: Normal,
+    pub y: Normal,
+    pub z: Normal,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct Normal {
+    pub mean: f32,
+    pub std_dev: f32,
+}
+
+impl Normal {
+    /// Box-Muller transform.
+    fn sample(&self, rng: &mut impl Rng) -> f32 {
+        let u1: f32 = rng.gen_range(std::f32::EPSILON, 1.0);
+        let u2: f32 = rng.gen();
+
+        self.mean + self.std_dev * (-2.0 * u1.ln()).sqrt() * (2.0 * std::f32::consts::PI * u2).cos()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct DungeonVoxelTypes {
+    pub empty: VoxelType,
+    pub solid: VoxelType,
+}
+
+impl Default for DungeonVoxelTypes {
+    fn default() -> Self {
+        Self {
+            empty: VoxelType(0),
+            solid: VoxelType(2),
+        }
+    }
+}
+
+/// Rock only extends this many voxels from the carved out space, so the dungeon is a shell instead
+/// of a solid block.
+const DUNGEON_WALL_THICKNESS: i32 = 3;
+
+impl DungeonMapSpec {
+    fn rng(&self) -> SmallRng {
+        let (a, b, c, d) = self.seed;
+
+        SmallRng::seed_from_u64((a as u64) << 48 ^ (b as u64) << 32 ^ (c as u64) << 16 ^ d as u64)
+    }
+
+    fn sample_room(&self, rng: &mut impl Rng) -> Extent3i {
+        let AxisRanges { x, y, z } = self.room_dist.location;
+        let AxisNormals {
+            x: size_x,
+            y: size_y,
+            z: size_z,
+        } = self.room_dist.size;
+        let mut min = [0; 3];
+        let mut shape = [0; 3];
+        for (i, (range, size)) in [(x, size_x), (y, size_y), (z, size_z)].iter().enumerate() {
+            let center = rng.gen_range(range.0, range.1.max(range.0) + 1);
+            shape[i] = (size.sample(rng).round() as i32)
+                .max(self.min_room_dim)
+                .min(self.max_room_dim)
+                .max(1);
+            min[i] = center - shape[i] / 2;
+        }
+
+        Extent3i::from_min_and_shape(PointN(min), PointN(shape))
+    }
+}
+
+/// Generates the chunks of the dungeon described by `spec`, as a signed distance field, in chunks
+/// of `chunk_shape`.
+pub fn generate_dungeon(
+    spec: &DungeonMapSpec,
+    chunk_shape: Point3i,
+) -> Vec<(Point3i, Array3x1<Voxel>)> {
+    let mut rng = spec.rng();
+    let rooms: Vec<Extent3i> = (0..spec.room_graph.num_rooms)
+        .map(|_| spec.sample_room(&mut rng))
+        .collect();
+    let mut spaces = rooms.clone();
+    for (a, b) in connect_rooms(&rooms, spec.room_graph.entrance_to_objective_path_length) {
+        let door_dim = rng.gen_range(
+            spec.min_door_dim,
+            spec.max_door_dim.max(spec.min_door_dim) + 1,
+        );
+        spaces.extend(corridor(&rooms[a], &rooms[b], door_dim).iter().cloned());
+    }
+
+    carve_spaces(&spaces, spec.voxel_types, chunk_shape)
+}
+
+fn room_center(room: &Extent3i) -> [f32; 3] {
+    let mut center = [0.0; 3];
+    for i in 0..3 {
+        center[i] = room.minimum.0[i] as f32 + room.shape.0[i] as f32 / 2.0;
+    }
+
+    center
+}
+
+fn room_distance_squared(a: &Extent3i, b: &Extent3i) -> f32 {
+    let (ca, cb) = (room_center(a), room_center(b));
+
+    (0..3).map(|i| (ca[i] - cb[i]) * (ca[i] - cb[i])).sum()
+}
+
+/// Returns the pairs of rooms to connect with corridors, forming a tree. Room 0 is the entrance,
+/// and the main path greedily walks to the nearest unvisited room. Every other room connects to
+/// the nearest room already in the tree, other than the objective at the end of the main path.
+fn connect_rooms(rooms: &[Extent3i], path_length: usize) -> Vec<(usize, usize)> {
+    if rooms.is_empty() {
+        return Vec::new();
+    }
+
+    let mut connected = vec![0];
+    let mut unconnected: Vec<usize> = (1..rooms.len()).collect();
+    let mut edges = Vec::new();
+
+    let mut last = 0;
+    for _ in 0..path_length.min(unconnected.len()) {
+        let (index, next) = unconnected
+            .iter()
+            .cloned()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| {
+                room_distance_squared(&rooms[last], &rooms[*a])
+                    .partial_cmp(&room_distance_squared(&rooms[last], &rooms[*b]))
+                    .unwrap()
+            })
+            .unwrap();
+        unconnected.swap_remove(index);
+        edges.push((last, next));
+        connected.push(next);
+        last = next;
+    }
+    let objective = if last != 0 { Some(last) } else { None };
+
+    while !unconnected.is_empty() {
+        let mut best: Option<(f32, usize, usize)> = None;
+        for (index, &room) in unconnected.iter().enumerate() {
+            for &other in connected.iter().filter(|&&c| Some(c) != objective) {
+                let d = room_distance_squared(&rooms[room], &rooms[other]);
+                if best.map_or(true, |(best_d, _, _)| d < best_d) {
+                    best = Some((d, index, other));
+                }
+            }
+        }
+        let (_, index, other) = best.unwrap();
+        let room = unconnected.swap_remove(index);
+        edges.push((other, room));
+        connected.push(room);
+    }
+
+    edges
+}
+
+/// A corridor that leaves `a` along X and then Z at its floor, and climbs (or drops) to the floor
+/// of `b` under its center.
+fn corridor(a: &Extent3i, b: &Extent3i, door_dim: i32) -> [Extent3i; 3] {
+    let (ca, cb) = (room_center(a), room_center(b));
+    let (ax, az) = (ca[0] as i32, ca[2] as i32);
+    let (bx, bz) = (cb[0] as i32, cb[2] as i32);
+    let (floor_a, floor_b) = (a.minimum.y(), b.minimum.y());
+    let door_dim = door_dim.min(a.shape.y()).min(b.shape.y()).max(1);
+
+    let segment = |p: [i32; 3], q: [i32; 3]| {
+        let half = door_dim / 2;
+        let min = PointN([p[0].min(q[0]) - half, p[1].min(q[1]), p[2].min(q[2]) - half]);
+        let max = PointN([
+            p[0].max(q[0]) + door_dim - half - 1,
+            p[1].max(q[1]) + door_dim - 1,
+            p[2].max(q[2]) + door_dim - half - 1,
+        ]);
+
+        Extent3i::from_min_and_max(min, max)
+    };
+
+    [
+        segment([ax, floor_a, az], [bx, floor_a, az]),
+        segment([bx, floor_a, az], [bx, floor_a, bz]),
+        segment([bx, floor_a, bz], [bx, floor_b, bz]),
+    ]
+}
+
+/// Signed distance to the surface of the voxels in `space`, negative inside.
+fn box_distance(space: &Extent3i, p: Point3i) -> f32 {
+    let mut outside_squared = 0.0;
+    let mut inside = std::f32::MIN;
+    for i in 0..3 {
+        let half = space.shape.0[i] as f32 / 2.0;
+        let center = space.minimum.0[i] as f32 + half - 0.5;
+        let q = (p.0[i] as f32 - center).abs() - half;
+        outside_squared += q.max(0.0) * q.max(0.0);
+        inside = inside.max(q);
+    }
+
+    outside_squared.sqrt() + inside.min(0.0)
+}
+
+fn padded(extent: &Extent3i, padding: i32) -> Extent3i {
+    Extent3i::from_min_and_shape(
+        extent.minimum - PointN([padding; 3]),
+        extent.shape + PointN([2 * padding; 3]),
+    )
+}
+
+/// Fills the space within `DUNGEON_WALL_THICKNESS` of `spaces` with rock, leaving `spaces` empty.
+/// Chunks without any rock are skipped.
+fn carve_spaces(
+    spaces: &[Extent3i],
+    voxel_types: DungeonVoxelTypes,
+    chunk_shape: Point3i,
+) -> Vec<(Point3i, Array3x1<Voxel>)> {
+    if spaces.is_empty() {
+        return Vec::new();
+    }
+
+    let padded_spaces: Vec<Extent3i> = spaces
+        .iter()
+        .map(|s| padded(s, DUNGEON_WALL_THICKNESS))
+        .collect();
+    let mut min = padded_spaces[0].minimum.0;
+    let mut max = padded_spaces[0].max().0;
+    for space in padded_spaces.iter() {
+        for i in 0..3 {
+            min[i] = min[i].min(space.minimum.0[i]);
+            max[i] = max[i].max(space.max().0[i]);
+        }
+    }
+    let bounds = Extent3i::from_min_and_max(PointN(min), PointN(max));
+    let chunk_mins: Vec<Point3i> = ChunkIndexer::new(chunk_shape)
+        .chunk_mins_for_extent(&bounds)
+        .collect();
+
+    chunk_mins
+        .into_par_iter()
+        .filter_map(|chunk_min| {
+            let chunk_extent = Extent3i::from_min_and_shape(chunk_min, chunk_shape);
+            let nearby: Vec<&Extent3i> = spaces
+                .iter()
+                .zip(padded_spaces.iter())
+                .filter(|(_, p)| !p.intersection(&chunk_extent).is_empty())
+                .map(|(s, _)| s)
+                .collect();
+            if nearby.is_empty() {
+                return None;
+            }
+
+            let mut chunk = Array3x1::fill(chunk_extent, EMPTY_VOXEL);
+            let mut any_rock = false;
+            chunk.for_each_mut(&chunk_extent, |p: Point3i, v: &mut Voxel| {
+                let space_distance = nearby
+                    .iter()
+                    .map(|s| box_distance(s, p))
+                    .fold(std::f32::MAX, f32::min);
+                if space_distance > DUNGEON_WALL_THICKNESS as f32 {
+                    return;
+                }
+                v.distance = VoxelDistance::from(-space_distance);
+                v.voxel_type = if space_distance > 0.0 {
+                    voxel_types.solid
+                } else {
+                    voxel_types.empty
+                };
+                any_rock |= space_distance > -1.0;
+            });
+
+            if any_rock {
+                Some((chunk_min, chunk))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::voxel::VOXEL_CHUNK_SHAPE;
+
+    #[test]
+    fn test_noise_terrain_is_layered() {
+        let config = NoiseTerrainConfig {
+            seed: 7,
+            size: [32, 32],
+            base_height: 10,
+            amplitude: 6.0,
+            frequency: 0.02,
+            octaves: 4,
+            lacunarity: 2.0,
+            persistence: 0.5,
+            layers: vec![
+                MaterialLayer {
+                    thickness: 1,
+                    voxel_type: VoxelType(1),
+                },
+                MaterialLayer {
+                    thickness: 1,
+                    voxel_type: VoxelType(2),
+                },
+            ],
+        };
+
+        let mut map =
+            ChunkMapBuilder3x1::new(VOXEL_CHUNK_SHAPE, EMPTY_VOXEL).build_with_hash_map_storage();
+        for (chunk_min, chunk) in generate_noise_terrain(&config, VOXEL_CHUNK_SHAPE).into_iter() {
+            map.write_chunk(ChunkKey::new(0, chunk_min), chunk);
+        }
+        let view = map.lod_view(0);
+
+        // Every column has a surface within the amplitude, with the top layer just below it and
+        // the bottom layer at the bottom of the terrain.
+        for &(x, z) in [(0, 0), (5, 20), (31, 31)].iter() {
+            let surface_y = (0..=20)
+                .rev()
+                .find(|&y| view.get(PointN([x, y, z])).distance.0 < 0)
+                .unwrap();
+            assert!(surface_y >= 3 && surface_y <= 17);
+            assert_eq!(view.get(PointN([x, surface_y, z])).voxel_type, VoxelType(1));
+            assert_eq!(view.get(PointN([x, 2, z])).voxel_type, VoxelType(2));
+        }
+    }
+    #[test]
+    fn test_dungeon_room_is_carved_out_of_rock() {
+        let size = Normal {
+            mean: 20.0,
+            std_dev: 0.0,
+        };
+        let spec = DungeonMapSpec {
+            seed: (1, 2, 3, 4),
+            room_graph: RoomGraphSpec {
+                num_rooms: 1,
+                entrance_to_objective_path_length: 0,
+            },
+            room_dist: RoomDistribution {
+                location: AxisRanges {
+                    x: (0, 0),
+                    y: (0, 0),
+                    z: (0, 0),
+                },
+                size: AxisNormals {
+                    x: size,
+                    y: size,
+                    z: size,
+                },
+            },
+            min_room_dim: 10,
+            max_room_dim: 30,
+            min_door_dim: 4,
+            max_door_dim: 8,
+            voxel_types: DungeonVoxelTypes {
+                empty: VoxelType(0),
+                solid: VoxelType(3),
+            },
+        };
+
+        let mut map =
+            ChunkMapBuilder3x1::new(VOXEL_CHUNK_SHAPE, EMPTY_VOXEL).build_with_hash_map_storage();
+        for (chunk_min, chunk) in generate_dungeon(&spec, VOXEL_CHUNK_SHAPE).into_iter() {
+            map.write_chunk(ChunkKey::new(0, chunk_min), chunk);
+        }
+        let view = map.lod_view(0);
+
+        // The room spans [-10, 9] on each axis, with a wall outside of it.
+        assert!(view.get(PointN([0, 0, 0])).distance.0 > 0);
+        assert!(view.get(PointN([0, 9, 0])).distance.0 > 0);
+        let wall = view.get(PointN([0, -12, 0]));
+        assert!(wall.distance.0 < 0);
+        assert_eq!(wall.voxel_type, VoxelType(3));
+        assert!(view.get(PointN([0, -20, 0])).distance.0 > 0);
+
+        // The rooms form a tree, and the objective is only connected to the room before it.
+        let rooms: Vec<Extent3i> = (0..6)
+            .map(|i| Extent3i::from_min_and_shape(PointN([i * 100, 0, 0]), PointN([10; 3])))
+            .collect();
+        let edges = connect_rooms(&rooms, 3);
+        assert_eq!(edges.len(), 5);
+        assert_eq!(&edges[..3], &[(0, 1), (1, 2), (2, 3)]);
+        assert!(edges[3..].iter().all(|&(a, b)| a != 3 && b != 3));
+    }
+}
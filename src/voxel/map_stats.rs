@@ -0,0 +1,166 @@
+//! Summarizes the voxels of a map, e.g. to sanity check a generator or an import.
+
+use crate::{
+    assets::BincodeFileError,
+    voxel::{map_file::compress_chunk, Voxel},
+};
+
+use building_blocks::prelude::*;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug)]
+pub struct MapStats {
+    pub num_chunks: usize,
+    /// Indexed by voxel type.
+    pub type_counts: Vec<usize>,
+    pub num_voxels: usize,
+    /// Voxels with negative distance.
+    pub num_solid: usize,
+    /// The smallest extent containing every solid voxel.
+    pub solid_extent: Option<Extent3i>,
+    /// Solid voxels in each Y layer that has any.
+    pub solid_per_y: BTreeMap<i32, usize>,
+    pub uncompressed_bytes: usize,
+    /// The size of the chunks as compressed in a voxels file.
+    pub compressed_bytes: usize,
+}
+
+impl Default for MapStats {
+    fn default() -> Self {
+        Self {
+            num_chunks: 0,
+            type_counts: vec![0; 256],
+            num_voxels: 0,
+            num_solid: 0,
+            solid_extent: None,
+            solid_per_y: BTreeMap::new(),
+            uncompressed_bytes: 0,
+            compressed_bytes: 0,
+        }
+    }
+}
+
+impl MapStats {
+    pub fn solid_fraction(&self) -> f32 {
+        if self.num_voxels == 0 {
+            return 0.0;
+        }
+
+        self.num_solid as f32 / self.num_voxels as f32
+    }
+
+    fn from_chunk(chunk: &Array3x1<Voxel>) -> Result<Self, BincodeFileError> {
+        let mut stats = Self {
+            num_chunks: 1,
+            compressed_bytes: compress_chunk(
+                chunk,
+                Some(lz4::block::CompressionMode::HIGHCOMPRESSION(10)),
+            )?
+            .len(),
+            ..Default::default()
+        };
+        let mut solid_min = PointN([i32::MAX; 3]);
+        let mut solid_max = PointN([i32::MIN; 3]);
+        chunk.for_each(chunk.extent(), |p: Point3i, v: Voxel| {
+            stats.num_voxels += 1;
+            stats.type_counts[v.voxel_type.0 as usize] += 1;
+            if v.distance.0 < 0 {
+                stats.num_solid += 1;
+                *stats.solid_per_y.entry(p.y()).or_insert(0) += 1;
+                solid_min = solid_min.meet(p);
+                solid_max = solid_max.join(p);
+            }
+        });
+        stats.uncompressed_bytes = stats.num_voxels * std::mem::size_of::<Voxel>();
+        if stats.num_solid > 0 {
+            stats.solid_extent = Some(Extent3i::from_min_and_max(solid_min, solid_max));
+        }
+
+        Ok(stats)
+    }
+
+    fn merge(mut self, other: Self) -> Self {
+        self.num_chunks += other.num_chunks;
+        for (count, other_count) in self.type_counts.iter_mut().zip(other.type_counts.iter()) {
+            *count += other_count;
+        }
+        self.num_voxels += other.num_voxels;
+        self.num_solid += other.num_solid;
+        self.solid_extent = match (self.solid_extent, other.solid_extent) {
+            (Some(a), Some(b)) => Some(Extent3i::from_min_and_max(
+                a.minimum.meet(b.minimum),
+                a.max().join(b.max()),
+            )),
+            (a, b) => a.or(b),
+        };
+        for (y, count) in other.solid_per_y.into_iter() {
+            *self.solid_per_y.entry(y).or_insert(0) += count;
+        }
+        self.uncompressed_bytes += other.uncompressed_bytes;
+        self.compressed_bytes += other.compressed_bytes;
+
+        self
+    }
+}
+
+/// Gathers the `MapStats` of `chunks`, e.g. from `snapshot_chunks` or `read_voxels_file`. Chunks
+/// that aren't stored are ambient space, so they aren't counted.
+pub fn map_stats(chunks: &[(Point3i, Array3x1<Voxel>)]) -> Result<MapStats, BincodeFileError> {
+    chunks
+        .par_iter()
+        .map(|(_, chunk)| MapStats::from_chunk(chunk))
+        .try_reduce(MapStats::default, |a, b| Ok(a.merge(b)))
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::voxel::{empty_array, VoxelDistance, VoxelType, VOXEL_CHUNK_SHAPE};
+
+    #[test]
+    fn test_stats_of_two_chunks() {
+        let solid = Voxel {
+            voxel_type: VoxelType(1),
+            distance: VoxelDistance::from(-1.0),
+        };
+        let mut chunks = Vec::new();
+        for (chunk_min, solid_point) in [
+            (PointN([0; 3]), PointN([1, 2, 3])),
+            (PointN([16, 0, 0]), PointN([20, 2, 5])),
+        ]
+        .iter()
+        {
+            let mut chunk =
+                empty_array(Extent3i::from_min_and_shape(*chunk_min, VOXEL_CHUNK_SHAPE));
+            *chunk.get_mut(*solid_point) = solid;
+            chunks.push((*chunk_min, chunk));
+        }
+
+        let stats = map_stats(&chunks).unwrap();
+
+        let chunk_voxels = VOXEL_CHUNK_SHAPE.volume() as usize;
+        assert_eq!(stats.num_chunks, 2);
+        assert_eq!(stats.num_voxels, 2 * chunk_voxels);
+        assert_eq!(stats.num_solid, 2);
+        assert_eq!(stats.type_counts[1], 2);
+        assert_eq!(stats.type_counts[0], 2 * chunk_voxels - 2);
+        assert_eq!(
+            stats.solid_extent,
+            Some(Extent3i::from_min_and_max(
+                PointN([1, 2, 3]),
+                PointN([20, 2, 5])
+            ))
+        );
+        assert_eq!(stats.solid_per_y.get(&2), Some(&2));
+        assert!(stats.compressed_bytes < stats.uncompressed_bytes);
+    }
+}
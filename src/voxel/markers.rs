@@ -0,0 +1,201 @@
+use amethyst::core::math::Point3;
+use building_blocks::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum MarkerKind {
+    SpawnPoint,
+    Light,
+    Item,
+    /// Anything else the game wants to find by name.
+    Other,
+    /// A saved camera view, which the editor can jump back to.
+    CameraBookmark,
+}
+
+impl MarkerKind {
+    /// The next kind of marker that can be placed on a surface. Camera bookmarks are skipped, since
+    /// they're made from the camera instead.
+    pub fn next(self) -> Self {
+        match self {
+            MarkerKind::SpawnPoint => MarkerKind::Light,
+            MarkerKind::Light => MarkerKind::Item,
+            MarkerKind::Item => MarkerKind::Other,
+            MarkerKind::Other | MarkerKind::CameraBookmark => MarkerKind::SpawnPoint,
+        }
+    }
+}
+
+/// A named point of interest in the map, e.g. where the player spawns. Markers are stored in the
+/// map file, and the editor only draws them; it's up to the game to decide what they mean.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Marker {
+    pub name: String,
+    pub kind: MarkerKind,
+    pub position: [i32; 3],
+    /// Where the camera was when a `CameraBookmark` was made. The camera looks at `position`.
+    #[serde(default)]
+    pub eye: Option<[f32; 3]>,
+}
+
+impl Marker {
+    pub fn position(&self) -> Point3i {
+        PointN(self.position)
+    }
+
+    /// The center of the bottom face of the marker's voxel, i.e. where something standing on the
+    /// surface should be placed.
+    pub fn floor_point(&self) -> Point3<f32> {
+        let p = Point3f::from(self.position());
+
+        Point3::new(p.x() + 0.5, p.y(), p.z() + 0.5)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MapMarkers {
+    markers: Vec<Marker>,
+    /// Set whenever the markers change, so they can be saved with the map.
+    changed: bool,
+}
+
+impl MapMarkers {
+    pub fn new(markers: Vec<Marker>) -> Self {
+        Self {
+            markers,
+            changed: false,
+        }
+    }
+
+    /// Adds a marker named after its kind with the smallest number that isn't taken, like
+    /// "SpawnPoint 2", and returns the name.
+    pub fn add(&mut self, kind: MarkerKind, position: Point3i) -> String {
+        self.push(kind, position, None)
+    }
+
+    /// Adds a `CameraBookmark` for a camera at `eye` looking at `target`, and returns its name.
+    pub fn add_camera_bookmark(&mut self, eye: Point3<f32>, target: Point3i) -> String {
+        self.push(MarkerKind::CameraBookmark, target, Some(eye.coords.into()))
+    }
+
+    fn push(&mut self, kind: MarkerKind, position: Point3i, eye: Option<[f32; 3]>) -> String {
+        // Counting the markers of the kind would reuse the name of the last one after an earlier
+        // one is removed.
+        let name = (1..)
+            .map(|n| format!("{:?} {}", kind, n))
+            .find(|name| self.markers.iter().all(|m| m.name != *name))
+            .unwrap();
+        self.markers.push(Marker {
+            name: name.clone(),
+            kind,
+            position: position.0,
+            eye,
+        });
+        self.changed = true;
+
+        name
+    }
+
+    /// Removes the marker closest to `p`, as long as it's within `radius` voxels.
+    pub fn remove_near(&mut self, p: Point3i, radius: f32) -> Option<Marker> {
+        let (i, dist) = self
+            .markers
+            .iter()
+            .enumerate()
+            .map(|(i, m)| (i, (m.position() - p).norm()))
+            .min_by(|(_, d1), (_, d2)| d1.partial_cmp(d2).unwrap())?;
+        if dist > radius {
+            return None;
+        }
+        self.changed = true;
+
+        Some(self.markers.remove(i))
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Marker> {
+        self.markers.iter().find(|m| m.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Marker> {
+        self.markers.iter()
+    }
+
+    pub fn iter_kind(&self, kind: MarkerKind) -> impl Iterator<Item = &Marker> {
+        self.markers.iter().filter(move |m| m.kind == kind)
+    }
+
+    /// The spawn points, in the order they were placed.
+    pub fn spawn_points(&self) -> impl Iterator<Item = &Marker> {
+        self.iter_kind(MarkerKind::SpawnPoint)
+    }
+
+    /// Looks up a spawn point by name, e.g. for a level transition that names its destination.
+    pub fn find_spawn_point(&self, name: &str) -> Option<&Marker> {
+        self.spawn_points().find(|m| m.name == name)
+    }
+
+    /// The camera bookmarks, in the order they were made.
+    pub fn camera_bookmarks(&self) -> impl Iterator<Item = &Marker> {
+        self.iter_kind(MarkerKind::CameraBookmark)
+    }
+
+    pub fn has_changed(&self) -> bool {
+        self.changed
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_and_remove_markers() {
+        let mut markers = MapMarkers::default();
+        assert_eq!(
+            markers.add(MarkerKind::SpawnPoint, PointN([0, 0, 0])),
+            "SpawnPoint 1"
+        );
+        assert_eq!(markers.add(MarkerKind::Item, PointN([10, 0, 0])), "Item 1");
+        assert_eq!(
+            markers.add(MarkerKind::SpawnPoint, PointN([20, 0, 0])),
+            "SpawnPoint 2"
+        );
+        assert!(markers.has_changed());
+
+        assert_eq!(markers.remove_near(PointN([5, 0, 0]), 1.5), None);
+        let removed = markers.remove_near(PointN([11, 0, 0]), 1.5).unwrap();
+        assert_eq!(removed.name, "Item 1");
+        assert_eq!(markers.iter_kind(MarkerKind::SpawnPoint).count(), 2);
+        assert_eq!(
+            markers.find_spawn_point("SpawnPoint 2").unwrap().position(),
+            PointN([20, 0, 0])
+        );
+
+        // Names aren't reused while their marker exists, but freed numbers are.
+        markers.remove_near(PointN([0, 0, 0]), 1.5).unwrap();
+        assert_eq!(
+            markers.add(MarkerKind::SpawnPoint, PointN([30, 0, 0])),
+            "SpawnPoint 1"
+        );
+        assert_eq!(
+            markers.add(MarkerKind::SpawnPoint, PointN([40, 0, 0])),
+            "SpawnPoint 3"
+        );
+        assert_eq!(markers.iter_kind(MarkerKind::SpawnPoint).count(), 3);
+
+        let name = markers.add_camera_bookmark(Point3::new(0.0, 10.0, 0.0), PointN([5, 0, 0]));
+        assert_eq!(name, "CameraBookmark 1");
+        assert_eq!(markers.spawn_points().count(), 3);
+        assert_eq!(
+            markers.camera_bookmarks().next().unwrap().eye,
+            Some([0.0, 10.0, 0.0])
+        );
+    }
+}
@@ -0,0 +1,212 @@
+use super::{ArrayMaterialHandle, ArrayMaterialId, VoxelAssets};
+use crate::rendering::{
+    atlas::rgba8_texture,
+    splatted_triplanar_pbr_pass::{MaterialTextureMode, VoxelRenderConfig},
+};
+
+use amethyst::{
+    assets::{AssetLoaderSystemData, AssetStorage, Completion, Handle, Prefab, ProgressCounter},
+    core::ecs::prelude::*,
+    renderer::{
+        formats::mtl::MaterialPrefab, rendy::hal::image::Filter, Material, MaterialDefaults,
+        Texture,
+    },
+    shrev::EventChannel,
+};
+
+/// An array material prefab that hasn't finished loading yet, along with the progress of the
+/// prefab file itself. The progress of its textures is tracked by the `Prefab`.
+pub struct PendingArrayMaterial {
+    pub id: ArrayMaterialId,
+    pub handle: Handle<Prefab<MaterialPrefab>>,
+    pub progress: ProgressCounter,
+}
+
+impl PendingArrayMaterial {
+    fn completion(&self, prefabs: &AssetStorage<Prefab<MaterialPrefab>>) -> Completion {
+        match self.progress.complete() {
+            Completion::Complete => match prefabs.get(&self.handle) {
+                Some(prefab) if prefab.loading() => prefab.progress().complete(),
+                _ => Completion::Loading,
+            },
+            other => other,
+        }
+    }
+
+    fn failed_asset_names(&self, prefabs: &AssetStorage<Prefab<MaterialPrefab>>) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .progress
+            .errors()
+            .into_iter()
+            .map(|e| e.asset_name)
+            .collect();
+        if let Some(prefab) = prefabs.get(&self.handle) {
+            if prefab.loading() {
+                names.extend(prefab.progress().errors().into_iter().map(|e| e.asset_name));
+            }
+        }
+
+        names
+    }
+}
+
+/// Sent when array materials fail to load and are replaced with the fallback material.
+#[derive(Clone, Debug)]
+pub struct MissingAssetsEvent {
+    pub asset_paths: Vec<String>,
+}
+
+/// Watches the array materials as they load. If any of them fail, chunks using them are switched
+/// to a built-in checker material so they still draw, and a `MissingAssetsEvent` is sent.
+#[derive(Default)]
+pub struct ArrayMaterialFallbackSystem {
+    fallback_material: Option<Handle<Material>>,
+}
+
+impl<'a> System<'a> for ArrayMaterialFallbackSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, AssetStorage<Prefab<MaterialPrefab>>>,
+        WriteExpect<'a, VoxelAssets>,
+        Write<'a, EventChannel<MissingAssetsEvent>>,
+        WriteStorage<'a, Handle<Prefab<MaterialPrefab>>>,
+        WriteStorage<'a, Handle<Material>>,
+        FallbackMaterialLoader<'a>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            prefabs,
+            mut voxel_assets,
+            mut missing_events,
+            mut prefab_handles,
+            mut material_handles,
+            fallback_loader,
+        ): Self::SystemData,
+    ) {
+        if voxel_assets.pending_materials.is_empty() {
+            return;
+        }
+
+        let mut failed = Vec::new();
+        let mut missing_paths = Vec::new();
+        voxel_assets
+            .pending_materials
+            .retain(|pending| match pending.completion(&prefabs) {
+                Completion::Loading => true,
+                Completion::Complete => false,
+                Completion::Failed => {
+                    missing_paths.extend(pending.failed_asset_names(&prefabs));
+                    failed.push((pending.id, pending.handle.clone()));
+
+                    false
+                }
+            });
+        if failed.is_empty() {
+            return;
+        }
+
+        log::error!(
+            "Failed to load array materials, using fallback material instead. Missing assets: {:?}",
+            missing_paths
+        );
+
+        let fallback = self
+            .fallback_material
+            .get_or_insert_with(|| fallback_loader.load_checker_material())
+            .clone();
+
+        for (id, failed_handle) in failed.into_iter() {
+            voxel_assets
+                .array_materials
+                .insert(id, ArrayMaterialHandle::Fallback(fallback.clone()));
+
+            // Swap the material on any chunks that were already created.
+            let failed_entities: Vec<Entity> = (&entities, &prefab_handles)
+                .join()
+                .filter(|(_, handle)| **handle == failed_handle)
+                .map(|(e, _)| e)
+                .collect();
+            for e in failed_entities.into_iter() {
+                prefab_handles.remove(e);
+                material_handles.insert(e, fallback.clone()).unwrap();
+            }
+        }
+
+        missing_events.single_write(MissingAssetsEvent {
+            asset_paths: missing_paths,
+        });
+    }
+}
+
+const CHECKER_SIZE: u32 = 8;
+/// Matches the maximum number of materials that can be splatted together.
+const CHECKER_LAYERS: u32 = 4;
+
+#[derive(SystemData)]
+pub struct FallbackMaterialLoader<'a> {
+    texture_loader: AssetLoaderSystemData<'a, Texture>,
+    material_loader: AssetLoaderSystemData<'a, Material>,
+    material_defaults: ReadExpect<'a, MaterialDefaults>,
+    render_config: Read<'a, VoxelRenderConfig>,
+}
+
+impl<'a> FallbackMaterialLoader<'a> {
+    /// A magenta and black checkerboard that's hard to mistake for a real material. The textures
+    /// are laid out to match the `MaterialTextureMode` of the splatted triplanar pass.
+    pub fn load_checker_material(&self) -> Handle<Material> {
+        let albedo = self.load_texture(
+            |x, y| {
+                if (x + y) % 2 == 0 {
+                    [255, 0, 255, 255]
+                } else {
+                    [0, 0, 0, 255]
+                }
+            },
+            true,
+        );
+        let emission = self.load_texture(|_, _| [0, 0, 0, 0], true);
+        let normal = self.load_texture(|_, _| [128, 128, 255, 255], false);
+        // Fully rough and not metallic.
+        let metallic_roughness = self.load_texture(|_, _| [0, 255, 0, 255], false);
+        let ambient_occlusion = self.load_texture(|_, _| [255; 4], false);
+        let cavity = self.load_texture(|_, _| [255; 4], false);
+
+        self.material_loader.load_from_data(
+            Material {
+                albedo,
+                emission,
+                normal,
+                metallic_roughness,
+                ambient_occlusion,
+                cavity,
+                ..self.material_defaults.0.clone()
+            },
+            (),
+        )
+    }
+
+    fn load_texture(&self, pixel: impl Fn(u32, u32) -> [u8; 4], srgb: bool) -> Handle<Texture> {
+        // An atlas has the layers in a 2x2 grid, but they're all the same here.
+        let (size, layers) = match self.render_config.material_textures {
+            MaterialTextureMode::Array => (CHECKER_SIZE, CHECKER_LAYERS),
+            MaterialTextureMode::Atlas => (2 * CHECKER_SIZE, 1),
+        };
+        let mut pixels = Vec::new();
+        for _layer in 0..layers {
+            for y in 0..size {
+                for x in 0..size {
+                    pixels.push(pixel(x, y));
+                }
+            }
+        }
+
+        self.texture_loader.load_from_data(
+            rgba8_texture(size, size, layers, pixels, srgb, Filter::Nearest),
+            (),
+        )
+    }
+}
@@ -1,15 +1,24 @@
+pub mod buffer_pool;
 pub mod loader;
 pub mod manager;
 
 use crate::{
-    assets::{IndexedPosColorNormVertices, PosColorNormVertices},
-    rendering::splatted_triplanar_pbr_pass::ArrayMaterialIndex,
-    voxel::{LocalVoxelCache, Voxel, VoxelMap, EMPTY_VOXEL},
+    assets::{IndexedPosColorNormVertices, PosColorNormVertices, VertexMaterials},
+    rendering::splatted_triplanar_pbr_pass::{ArrayMaterialIndex, ArrayMaterialIndexInt, Emission},
+    voxel::{
+        LocalVoxelCache, Voxel, VoxelDistanceInt, VoxelInfo, VoxelMap, VoxelPalette, EMPTY_VOXEL,
+    },
+};
+
+use buffer_pool::{
+    recycle_voxel_array, take_voxel_array, with_greedy_quads_buffer, with_pos_norm_mesh,
+    with_surface_nets_buffer,
 };
 
 use amethyst::core::ecs::prelude::*;
-use amethyst::renderer::rendy::mesh::{Color, Normal, Position};
+use amethyst::renderer::rendy::mesh::{Normal, Position, TexCoord};
 use building_blocks::{mesh::*, prelude::*};
+use std::borrow::Cow;
 use std::collections::HashMap;
 
 #[cfg(feature = "profiler")]
@@ -20,137 +29,300 @@ pub enum MeshMode {
     GreedyQuads,
 }
 
+/// Transparent voxels are meshed separately from the opaque ones, so they can be alpha blended over
+/// everything else.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MeshLayer {
+    Opaque,
+    Transparent,
+}
+
+impl MeshLayer {
+    pub const ALL: [MeshLayer; 2] = [MeshLayer::Opaque, MeshLayer::Transparent];
+
+    fn contains(&self, info: &VoxelInfo) -> bool {
+        info.flags.is_transparent == (*self == MeshLayer::Transparent)
+    }
+}
+
 #[derive(Default)]
 pub struct VoxelMeshEntities {
     pub chunk_entities: HashMap<Point3i, Vec<Entity>>,
+    /// The entities of the merged region meshes, keyed by region minimum. See
+    /// `MergedChunkMeshes`.
+    pub region_entities: HashMap<Point3i, Vec<Entity>>,
+    /// The entities of the fluid surface meshes, keyed by chunk minimum. See `fluid`.
+    pub fluid_entities: HashMap<Point3i, Vec<Entity>>,
 }
 
 pub fn generate_mesh_vertices_with_surface_nets(
     voxel_map: &VoxelMap,
     chunk_extent: &Extent3i,
     local_chunk_cache: &LocalVoxelCache,
+    layer: MeshLayer,
 ) -> Option<IndexedPosColorNormVertices> {
-    #[cfg(feature = "profiler")]
-    profile_scope!("generate_mesh_vertices");
+    let mesh_voxels = copy_mesh_voxels(
+        voxel_map,
+        &padded_surface_nets_chunk_extent(chunk_extent),
+        local_chunk_cache,
+    );
+    let vertices = surface_nets_vertices(
+        &voxel_map.palette,
+        &mesh_voxels,
+        layer,
+        voxel_map.world_scale,
+    );
+    recycle_voxel_array(mesh_voxels);
 
-    let mesh_extent = padded_surface_nets_chunk_extent(chunk_extent);
-    // PERF: reuse these buffers between frames
-    let mut buffer = SurfaceNetsBuffer::default();
-    let mut mesh_voxels = Array3x1::fill(mesh_extent, EMPTY_VOXEL);
-    let reader = voxel_map.voxels.reader(local_chunk_cache);
-    copy_extent(&mesh_extent, &reader.lod_view(0), &mut mesh_voxels);
+    vertices
+}
+
+pub fn generate_mesh_vertices_with_greedy_quads(
+    voxel_map: &VoxelMap,
+    chunk_extent: &Extent3i,
+    local_chunk_cache: &LocalVoxelCache,
+    layer: MeshLayer,
+) -> Option<IndexedPosColorNormVertices> {
+    let mesh_voxels = copy_mesh_voxels(
+        voxel_map,
+        &padded_greedy_quads_chunk_extent(chunk_extent),
+        local_chunk_cache,
+    );
+    let vertices = greedy_quads_vertices(
+        &voxel_map.palette,
+        &mesh_voxels,
+        layer,
+        voxel_map.world_scale,
+    );
+    recycle_voxel_array(mesh_voxels);
 
-    {
-        #[cfg(feature = "profiler")]
-        profile_scope!("surface_nets");
+    vertices
+}
 
-        surface_nets(&mesh_voxels, &mesh_extent, 1.0, &mut buffer);
-    }
+/// Copies the voxels needed to mesh a chunk out of the map, so the meshing itself can happen
+/// without access to the map, e.g. on another thread. `mesh_extent` should be the chunk extent
+/// padded with `padded_surface_nets_chunk_extent` or `padded_greedy_quads_chunk_extent`.
+///
+/// The array comes from the `buffer_pool`, so give it back with `recycle_voxel_array` when the
+/// meshing is done.
+pub fn copy_mesh_voxels(
+    voxel_map: &VoxelMap,
+    mesh_extent: &Extent3i,
+    local_chunk_cache: &LocalVoxelCache,
+) -> Array3x1<Voxel> {
+    // Every voxel gets overwritten, either from a chunk or with the ambient value.
+    let mut mesh_voxels = take_voxel_array(*mesh_extent);
+    let reader = voxel_map.voxels.reader(local_chunk_cache);
+    copy_extent(mesh_extent, &reader.lod_view(0), &mut mesh_voxels);
 
-    if buffer.mesh.is_empty() {
+    mesh_voxels
+}
+
+/// Replaces the solid voxels that aren't in `layer` with empty space, so only the surface of
+/// `layer` gets meshed. Returns `None` if there are no solid voxels in `layer`.
+fn layer_voxels<'a>(
+    palette: &VoxelPalette,
+    mesh_voxels: &'a Array3x1<Voxel>,
+    layer: MeshLayer,
+) -> Option<Cow<'a, Array3x1<Voxel>>> {
+    let mut num_in_layer = 0;
+    let mut num_masked = 0;
+    mesh_voxels.for_each(mesh_voxels.extent(), |_p: Point3i, v: Voxel| {
+        let info = palette.get_voxel_type_info(v.voxel_type);
+        if info.flags.is_empty {
+            return;
+        }
+        if layer.contains(info) {
+            num_in_layer += 1;
+        } else {
+            num_masked += 1;
+        }
+    });
+    if num_in_layer == 0 {
         return None;
     }
+    if num_masked == 0 {
+        return Some(Cow::Borrowed(mesh_voxels));
+    }
 
-    let SurfaceNetsBuffer {
-        mesh:
-            PosNormMesh {
-                positions,
-                normals,
-                indices,
-            },
-        surface_strides,
-        ..
-    } = buffer;
-
-    let transform_voxel = |v: Voxel| {
-        let info = voxel_map.palette.get_voxel_type_info(v.voxel_type);
-
-        MaterialWeightsVoxel {
-            material_index: info.material_index,
-            distance: v.distance.0,
+    let mut masked = mesh_voxels.clone();
+    let extent = *masked.extent();
+    masked.for_each_mut(&extent, |_p: Point3i, v: &mut Voxel| {
+        let info = palette.get_voxel_type_info(v.voxel_type);
+        if !info.flags.is_empty && !layer.contains(info) {
+            *v = EMPTY_VOXEL;
         }
-    };
-    let material_voxels = TransformMap::new(&mesh_voxels, &transform_voxel);
-    let vertex_material_weights = {
-        #[cfg(feature = "profiler")]
-        profile_scope!("material_weights");
-
-        material_weights(&material_voxels, &surface_strides)
-    };
-
-    let positions = positions.into_iter().map(|p| Position(p)).collect();
-    let colors = vertex_material_weights
-        .into_iter()
-        .map(|w| Color(w))
-        .collect();
-    let normals = normals.into_iter().map(|n| Normal(n)).collect();
-    let vertices = PosColorNormVertices {
-        positions,
-        colors,
-        normals,
-    };
-    let indices: Vec<_> = indices.into_iter().map(|i| i as u32).collect();
-
-    Some(IndexedPosColorNormVertices { vertices, indices })
+    });
+
+    Some(Cow::Owned(masked))
 }
 
-pub fn generate_mesh_vertices_with_greedy_quads(
-    voxel_map: &VoxelMap,
-    chunk_extent: &Extent3i,
-    local_chunk_cache: &LocalVoxelCache,
+/// Meshes the voxels in `layer`, out of voxels copied with `copy_mesh_voxels` using a surface nets
+/// padded extent. The positions are scaled by `world_scale`, the map's `VoxelMap::world_scale`.
+pub fn surface_nets_vertices(
+    palette: &VoxelPalette,
+    mesh_voxels: &Array3x1<Voxel>,
+    layer: MeshLayer,
+    world_scale: f32,
 ) -> Option<IndexedPosColorNormVertices> {
     #[cfg(feature = "profiler")]
     profile_scope!("generate_mesh_vertices");
 
-    let mesh_extent = padded_greedy_quads_chunk_extent(chunk_extent);
-    // PERF: reuse these buffers between frames
-    let mut buffer = GreedyQuadsBuffer::new(mesh_extent, RIGHT_HANDED_Y_UP_CONFIG.quad_groups());
-    let mut mesh_voxels = Array3x1::fill(mesh_extent, EMPTY_VOXEL);
-    let reader = voxel_map.voxels.reader(local_chunk_cache);
-    copy_extent(&mesh_extent, &reader.lod_view(0), &mut mesh_voxels);
-    let voxel_infos = TransformMap::new(&mesh_voxels, voxel_map.voxel_info_transform());
+    let masked_voxels = layer_voxels(palette, mesh_voxels, layer)?;
+    let mesh_voxels = &*masked_voxels;
+    let mesh_extent = *mesh_voxels.extent();
 
-    {
-        #[cfg(feature = "profiler")]
-        profile_scope!("greedy_quads");
+    with_surface_nets_buffer(|buffer| {
+        {
+            #[cfg(feature = "profiler")]
+            profile_scope!("surface_nets");
 
-        greedy_quads(&voxel_infos, &mesh_extent, &mut buffer);
-    }
+            surface_nets(mesh_voxels, &mesh_extent, world_scale, buffer);
+        }
 
-    if buffer.num_quads() == 0 {
-        return None;
-    }
+        if buffer.mesh.is_empty() {
+            return None;
+        }
 
-    // PERF: reuse these buffers between frames
-    let mut mesh = PosNormMesh::default();
-    let mut colors = Vec::with_capacity(4 * buffer.num_quads());
-    for group in buffer.quad_groups.iter() {
-        for quad in group.quads.iter() {
-            let material = voxel_infos.get(quad.minimum).material_index;
-            colors.extend(&[Color(MATERIAL_WEIGHT_TABLE[material.0 as usize]); 4]);
-            group.face.add_quad_to_pos_norm_mesh(quad, 1.0, &mut mesh);
+        let SurfaceNetsBuffer {
+            mesh:
+                PosNormMesh {
+                    positions,
+                    normals,
+                    indices,
+                },
+            surface_strides,
+            ..
+        } = &*buffer;
+
+        let transform_voxel = |v: Voxel| {
+            let info = palette.get_voxel_type_info(v.voxel_type);
+
+            MaterialWeightsVoxel {
+                material_index: info.material_index,
+                radiance: info.emission.radiance(),
+                distance: v.distance.0,
+            }
+        };
+        let material_voxels = TransformMap::new(mesh_voxels, &transform_voxel);
+        let materials = {
+            #[cfg(feature = "profiler")]
+            profile_scope!("material_weights");
+
+            material_weights(&material_voxels, surface_strides)
+        };
+        // Most palettes don't have any emissive voxels, so don't bother averaging zeros.
+        let emissions = if palette.infos.iter().any(|i| i.emission.is_emissive()) {
+            vertex_emissions(&material_voxels, surface_strides)
+        } else {
+            vec![Emission::default(); surface_strides.len()]
+        };
+
+        let positions = positions.iter().map(|p| Position(*p)).collect();
+        let normals = normals.iter().map(|n| Normal(*n)).collect();
+        let vertices = PosColorNormVertices {
+            positions,
+            materials,
+            normals,
+            emissions,
+            tex_coords: Vec::new(),
+        };
+        let indices: Vec<_> = indices.iter().map(|i| *i as u32).collect();
+
+        Some(IndexedPosColorNormVertices { vertices, indices })
+    })
+}
+
+/// Meshes the voxels in `layer`, out of voxels copied with `copy_mesh_voxels` using a greedy quads
+/// padded extent. The positions are scaled by `world_scale`, the map's `VoxelMap::world_scale`.
+pub fn greedy_quads_vertices(
+    palette: &VoxelPalette,
+    mesh_voxels: &Array3x1<Voxel>,
+    layer: MeshLayer,
+    world_scale: f32,
+) -> Option<IndexedPosColorNormVertices> {
+    #[cfg(feature = "profiler")]
+    profile_scope!("generate_mesh_vertices");
+
+    let masked_voxels = layer_voxels(palette, mesh_voxels, layer)?;
+    let mesh_voxels = &*masked_voxels;
+
+    let mesh_extent = *mesh_voxels.extent();
+    let voxel_infos = TransformMap::new(mesh_voxels, |v: Voxel| {
+        palette.get_voxel_type_info(v.voxel_type)
+    });
+
+    with_greedy_quads_buffer(&mesh_extent, |buffer| {
+        {
+            #[cfg(feature = "profiler")]
+            profile_scope!("greedy_quads");
+
+            greedy_quads(&voxel_infos, &mesh_extent, buffer);
         }
-    }
 
-    let positions = mesh.positions.into_iter().map(|p| Position(p)).collect();
-    let normals = mesh.normals.into_iter().map(|n| Normal(n)).collect();
-    let vertices = PosColorNormVertices {
-        positions,
-        colors,
-        normals,
-    };
-    let indices = mesh.indices.into_iter().map(|i| i as u32).collect();
+        if buffer.num_quads() == 0 {
+            return None;
+        }
+
+        let voxel_size = world_scale;
+        with_pos_norm_mesh(|mesh| {
+            let mut materials = Vec::with_capacity(4 * buffer.num_quads());
+            let mut emissions = Vec::with_capacity(4 * buffer.num_quads());
+            for group in buffer.quad_groups.iter() {
+                for quad in group.quads.iter() {
+                    let info = voxel_infos.get(quad.minimum);
+                    materials.extend(&[VertexMaterials::single(info.material_index); 4]);
+                    emissions.extend(&[Emission(info.emission.radiance()); 4]);
+                    group.face.add_quad_to_pos_norm_mesh(quad, voxel_size, mesh);
+                }
+            }
+
+            let tex_coords = mesh
+                .positions
+                .iter()
+                .zip(mesh.normals.iter())
+                .map(|(p, n)| TexCoord(face_tex_coord(p, n, voxel_size)))
+                .collect();
+            let positions = mesh.positions.iter().map(|p| Position(*p)).collect();
+            let normals = mesh.normals.iter().map(|n| Normal(*n)).collect();
+            let vertices = PosColorNormVertices {
+                positions,
+                materials,
+                normals,
+                emissions,
+                tex_coords,
+            };
+            let indices = mesh.indices.iter().map(|i| *i as u32).collect();
+
+            Some(IndexedPosColorNormVertices { vertices, indices })
+        })
+    })
+}
 
-    Some(IndexedPosColorNormVertices { vertices, indices })
+/// Projects a quad vertex onto the plane of its (axis-aligned) face, so textures tile once per
+/// voxel. V points down the side faces, so images aren't upside down.
+fn face_tex_coord(position: &[f32; 3], normal: &[f32; 3], voxel_size: f32) -> [f32; 2] {
+    let [x, y, z] = [
+        position[0] / voxel_size,
+        position[1] / voxel_size,
+        position[2] / voxel_size,
+    ];
+    if normal[0] != 0.0 {
+        [z, -y]
+    } else if normal[1] != 0.0 {
+        [x, z]
+    } else {
+        [x, -y]
+    }
 }
 
 /// Returns the material weights for each of the points in `surface_strides`.
 ///
 /// Uses a 2x2x2 kernel (the same shape as the Surface Nets kernel) to average the adjacent
 /// materials for each surface point. `voxels` should at least contain the extent that was used with
-/// `surface_nets` in order to generate `surface_strides`. Currently limited to 4 materials per
-/// surface chunk.
-fn material_weights<V>(voxels: &V, surface_strides: &[Stride]) -> Vec<[f32; 4]>
+/// `surface_nets` in order to generate `surface_strides`. Any number of materials can appear in a
+/// chunk, but only the 4 heaviest are kept for each vertex.
+fn material_weights<V>(voxels: &V, surface_strides: &[Stride]) -> Vec<VertexMaterials>
 where
     V: IndexedArray<[i32; 3]> + Get<Stride, Item = MaterialWeightsVoxel>,
 {
@@ -159,35 +331,64 @@ where
     let corner_offsets = Local::localize_points_slice(&Point3i::corner_offsets());
     voxels.strides_from_local_points(&corner_offsets, &mut corner_offset_strides);
 
-    let mut material_weights = vec![[0.0; 4]; surface_strides.len()];
-
-    for (i, p_stride) in surface_strides.iter().enumerate() {
-        let w = &mut material_weights[i];
+    let mut material_weights = Vec::with_capacity(surface_strides.len());
+    // At most 8 distinct materials at the corners of a cube.
+    let mut corner_weights: Vec<(ArrayMaterialIndexInt, f32)> = Vec::with_capacity(8);
+    for p_stride in surface_strides.iter() {
+        corner_weights.clear();
         for offset_stride in corner_offset_strides.iter() {
             let q_stride = *p_stride + *offset_stride;
             let voxel = voxels.get(q_stride);
             if voxel.distance < 0 {
-                let material_w = MATERIAL_WEIGHT_TABLE[voxel.material_index.0 as usize];
-                w[0] += material_w[0];
-                w[1] += material_w[1];
-                w[2] += material_w[2];
-                w[3] += material_w[3];
+                let index = voxel.material_index.0;
+                match corner_weights.iter_mut().find(|(i, _)| *i == index) {
+                    Some((_, w)) => *w += 1.0,
+                    None => corner_weights.push((index, 1.0)),
+                }
             }
         }
+        material_weights.push(VertexMaterials::from_weights(&mut corner_weights));
     }
 
     material_weights
 }
 
-// Currently limited to 4 numbers for material weights.
-const MATERIAL_WEIGHT_TABLE: [[f32; 4]; 4] = [
-    [1.0, 0.0, 0.0, 0.0],
-    [0.0, 1.0, 0.0, 0.0],
-    [0.0, 0.0, 1.0, 0.0],
-    [0.0, 0.0, 0.0, 1.0],
-];
+/// Returns the emission for each of the points in `surface_strides`, averaged over the solid voxels
+/// of the same 2x2x2 kernel as `material_weights`.
+fn vertex_emissions<V>(voxels: &V, surface_strides: &[Stride]) -> Vec<Emission>
+where
+    V: IndexedArray<[i32; 3]> + Get<Stride, Item = MaterialWeightsVoxel>,
+{
+    let mut corner_offset_strides = [Stride(0); 8];
+    let corner_offsets = Local::localize_points_slice(&Point3i::corner_offsets());
+    voxels.strides_from_local_points(&corner_offsets, &mut corner_offset_strides);
+
+    let mut emissions = Vec::with_capacity(surface_strides.len());
+    for p_stride in surface_strides.iter() {
+        let mut sum = [0.0; 3];
+        let mut num_solid = 0;
+        for offset_stride in corner_offset_strides.iter() {
+            let voxel = voxels.get(*p_stride + *offset_stride);
+            if voxel.distance < 0 {
+                for (s, r) in sum.iter_mut().zip(voxel.radiance.iter()) {
+                    *s += r;
+                }
+                num_solid += 1;
+            }
+        }
+        if num_solid > 0 {
+            for s in sum.iter_mut() {
+                *s /= num_solid as f32;
+            }
+        }
+        emissions.push(Emission(sum));
+    }
+
+    emissions
+}
 
 struct MaterialWeightsVoxel {
     material_index: ArrayMaterialIndex,
-    distance: i8,
+    radiance: [f32; 3],
+    distance: VoxelDistanceInt,
 }
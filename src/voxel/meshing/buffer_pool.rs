@@ -0,0 +1,90 @@
+//! Scratch allocations for meshing, reused between chunks so re-meshing many chunks in a frame
+//! doesn't keep going back to the allocator.
+//!
+//! Mesh buffers are kept per thread, since each meshing job runs start to finish on one thread.
+//! Voxel arrays are shared between threads, because they're copied out of the map on one thread and
+//! meshed on another.
+
+use crate::voxel::{empty_array, Voxel};
+
+use building_blocks::{mesh::*, prelude::*};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Beyond this, recycled arrays of the same shape are just dropped.
+const MAX_POOLED_ARRAYS_PER_SHAPE: usize = 64;
+
+thread_local! {
+    static SURFACE_NETS_BUFFER: RefCell<SurfaceNetsBuffer> =
+        RefCell::new(SurfaceNetsBuffer::default());
+    static GREEDY_QUADS_BUFFERS: RefCell<HashMap<Point3i, GreedyQuadsBuffer>> =
+        RefCell::new(HashMap::new());
+    static POS_NORM_MESH: RefCell<PosNormMesh> = RefCell::new(PosNormMesh::default());
+}
+
+lazy_static::lazy_static! {
+    static ref VOXEL_ARRAYS: Mutex<HashMap<Point3i, Vec<Array3x1<Voxel>>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Calls `f` with this thread's `SurfaceNetsBuffer`. `surface_nets` resets the buffer itself.
+pub fn with_surface_nets_buffer<R>(f: impl FnOnce(&mut SurfaceNetsBuffer) -> R) -> R {
+    SURFACE_NETS_BUFFER.with(|buffer| f(&mut buffer.borrow_mut()))
+}
+
+/// Calls `f` with this thread's `GreedyQuadsBuffer` for extents shaped like `extent`.
+/// `greedy_quads` resets the buffer itself.
+pub fn with_greedy_quads_buffer<R>(
+    extent: &Extent3i,
+    f: impl FnOnce(&mut GreedyQuadsBuffer) -> R,
+) -> R {
+    GREEDY_QUADS_BUFFERS.with(|buffers| {
+        let mut buffers = buffers.borrow_mut();
+        let buffer = buffers.entry(extent.shape).or_insert_with(|| {
+            GreedyQuadsBuffer::new(*extent, RIGHT_HANDED_Y_UP_CONFIG.quad_groups())
+        });
+
+        f(buffer)
+    })
+}
+
+/// Calls `f` with this thread's empty `PosNormMesh`.
+pub fn with_pos_norm_mesh<R>(f: impl FnOnce(&mut PosNormMesh) -> R) -> R {
+    POS_NORM_MESH.with(|mesh| {
+        let mut mesh = mesh.borrow_mut();
+        mesh.positions.clear();
+        mesh.normals.clear();
+        mesh.indices.clear();
+
+        f(&mut mesh)
+    })
+}
+
+/// An array covering `extent`, recycled if possible. The voxels are left over from whatever the
+/// array was last used for, so the caller must write all of them.
+pub fn take_voxel_array(extent: Extent3i) -> Array3x1<Voxel> {
+    let recycled = VOXEL_ARRAYS
+        .lock()
+        .unwrap()
+        .get_mut(&extent.shape)
+        .and_then(|arrays| arrays.pop());
+
+    match recycled {
+        Some(mut array) => {
+            array.set_minimum(extent.minimum);
+
+            array
+        }
+        None => empty_array(extent),
+    }
+}
+
+/// Returns an array to the pool for `take_voxel_array`.
+pub fn recycle_voxel_array(array: Array3x1<Voxel>) {
+    let mut pool = VOXEL_ARRAYS.lock().unwrap();
+    let arrays = pool.entry(array.extent().shape).or_insert_with(Vec::new);
+    if arrays.len() < MAX_POOLED_ARRAYS_PER_SHAPE {
+        arrays.push(array);
+    }
+}
@@ -1,55 +1,135 @@
-use super::generate_mesh_vertices_with_surface_nets;
+use super::{generate_mesh_vertices_with_surface_nets, MeshLayer};
 
 use crate::{
     assets::{BoundedMesh, IndexedPosColorNormVertices, MeshLoader},
-    voxel::{ArrayMaterialId, LocalVoxelCache, VoxelMap},
+    rendering::{
+        merged_chunk_meshes::MergedChunkMeshes, splatted_triplanar_pbr_pass::VoxelRenderConfig,
+    },
+    voxel::{morton::morton_ordered_chunk_mins, ArrayMaterialId, VoxelMap},
 };
 
 use amethyst::{assets::ProgressCounter, core::ecs::prelude::*};
 use building_blocks::prelude::*;
+use rayon::prelude::*;
 use std::collections::HashMap;
 
 /// Loads the vertices for chunks into `ChunkMesh` objects.
 #[derive(SystemData)]
 pub struct VoxelMeshLoader<'a> {
     pub mesh_loader: MeshLoader<'a>,
+    render_config: Read<'a, VoxelRenderConfig>,
+    merged_meshes: Write<'a, MergedChunkMeshes>,
 }
 
 #[derive(Clone)]
 pub struct ChunkMesh {
     pub material_array_id: ArrayMaterialId,
     pub mesh: BoundedMesh,
+    /// The emissive triangles of `mesh`, drawn by the glow shell pass when it's enabled.
+    pub glow: Option<BoundedMesh>,
 }
 
 #[derive(Default)]
 pub struct VoxelMeshes {
     pub chunk_meshes: HashMap<Point3i, ChunkMesh>,
+    /// Meshes of the `MeshLayer::Transparent` voxels, for the chunks that have any.
+    pub transparent_chunk_meshes: HashMap<Point3i, ChunkMesh>,
+    /// Meshes of the merged opaque chunks, keyed by region minimum. Only used when
+    /// `VoxelRenderConfig::merge_chunk_meshes` is set, in place of `chunk_meshes`.
+    pub region_meshes: HashMap<Point3i, ChunkMesh>,
+    /// Meshes of the fluid surfaces, made by the `FluidMeshSystem`.
+    pub fluid_chunk_meshes: HashMap<Point3i, ChunkMesh>,
 }
 
 impl<'a> VoxelMeshLoader<'a> {
+    /// Generates the vertices for every chunk in parallel, then starts loading the meshes.
     pub fn start_loading_all_chunks(
         &mut self,
         voxel_map: &VoxelMap,
-        chunk_cache: &LocalVoxelCache,
         progress: &mut ProgressCounter,
     ) -> VoxelMeshes {
-        let chunk_meshes = voxel_map
-            .voxels
-            .storage()
-            .chunk_keys()
-            .filter_map(|chunk_key| {
-                let chunk_extent = voxel_map
-                    .voxels
-                    .indexer
-                    .extent_for_chunk_with_min(chunk_key.minimum);
-                let vertices =
-                    generate_mesh_vertices_with_surface_nets(voxel_map, &chunk_extent, chunk_cache);
+        // Morton order gives each thread a compact region of chunks, so the neighboring chunks read
+        // by surface nets are likely to be warm in the cache.
+        #[allow(clippy::type_complexity)]
+        let chunk_vertices: Vec<(Point3i, MeshLayer, IndexedPosColorNormVertices)> =
+            morton_ordered_chunk_mins(voxel_map)
+                .into_par_iter()
+                .flat_map(|chunk_min| {
+                    let local_chunk_cache = LocalChunkCache3::new();
+                    let chunk_extent = voxel_map
+                        .voxels
+                        .indexer
+                        .extent_for_chunk_with_min(chunk_min);
 
-                vertices.map(|v| (chunk_key.minimum, self.start_loading_chunk(v, progress)))
-            })
-            .collect();
+                    MeshLayer::ALL
+                        .iter()
+                        .filter_map(|&layer| {
+                            generate_mesh_vertices_with_surface_nets(
+                                voxel_map,
+                                &chunk_extent,
+                                &local_chunk_cache,
+                                layer,
+                            )
+                            .map(|v| (chunk_min, layer, v))
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect();
 
-        VoxelMeshes { chunk_meshes }
+        let mut meshes = VoxelMeshes::default();
+        for (chunk_min, layer, v) in chunk_vertices.into_iter() {
+            match layer {
+                MeshLayer::Opaque => {
+                    if let Some(mesh) =
+                        self.start_loading_opaque_chunk(chunk_min, Some(v), progress)
+                    {
+                        meshes.chunk_meshes.insert(chunk_min, mesh);
+                    }
+                }
+                MeshLayer::Transparent => {
+                    let mesh = self.start_loading_chunk(v, progress);
+                    meshes.transparent_chunk_meshes.insert(chunk_min, mesh);
+                }
+            };
+        }
+        for (region_min, mesh) in self.start_loading_dirty_regions(progress).into_iter() {
+            if let Some(mesh) = mesh {
+                meshes.region_meshes.insert(region_min, mesh);
+            }
+        }
+
+        meshes
+    }
+
+    /// Starts loading the opaque mesh of a chunk, unless chunk meshes are merged, in which case
+    /// the vertices are moved into the `MergedChunkMeshes` and `None` is returned. The merged
+    /// regions are loaded later by `start_loading_dirty_regions`.
+    pub fn start_loading_opaque_chunk(
+        &mut self,
+        chunk_min: Point3i,
+        vertices: Option<IndexedPosColorNormVertices>,
+        progress: &mut ProgressCounter,
+    ) -> Option<ChunkMesh> {
+        if self.render_config.merge_chunk_meshes {
+            self.merged_meshes.set_chunk(chunk_min, vertices);
+
+            return None;
+        }
+
+        vertices.map(|v| self.start_loading_chunk(v, progress))
+    }
+
+    /// Starts loading a mesh for each merged region that changed since the last call, or returns
+    /// `None` for the regions that are now empty.
+    pub fn start_loading_dirty_regions(
+        &mut self,
+        progress: &mut ProgressCounter,
+    ) -> Vec<(Point3i, Option<ChunkMesh>)> {
+        self.merged_meshes
+            .take_dirty_regions()
+            .into_iter()
+            .map(|(region_min, v)| (region_min, v.map(|v| self.start_loading_chunk(v, progress))))
+            .collect()
     }
 
     pub fn start_loading_chunk(
@@ -57,6 +137,9 @@ impl<'a> VoxelMeshLoader<'a> {
         vertices: IndexedPosColorNormVertices,
         progress: &mut ProgressCounter,
     ) -> ChunkMesh {
+        let glow = self
+            .mesh_loader
+            .start_loading_glow_mesh(&vertices, &mut *progress);
         let mesh = self
             .mesh_loader
             .start_loading_pos_color_norm_mesh(vertices, &mut *progress);
@@ -65,6 +148,7 @@ impl<'a> VoxelMeshLoader<'a> {
             // TODO: support multiple array materials
             material_array_id: ArrayMaterialId(1),
             mesh,
+            glow,
         }
     }
 }
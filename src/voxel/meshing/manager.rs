@@ -1,13 +1,15 @@
 use super::loader::ChunkMesh;
 use crate::{
     assets::BoundedMesh,
-    voxel::{meshing::VoxelMeshEntities, ArrayMaterialId, VoxelAssets, VoxelMap},
+    voxel::{
+        meshing::VoxelMeshEntities, morton::morton_ordered_chunk_mins, ArrayMaterialHandle,
+        ArrayMaterialId, VoxelAssets, VoxelMap,
+    },
 };
 
 use amethyst::{
-    assets::{Handle, Prefab},
     core::{ecs::prelude::*, Transform},
-    renderer::formats::mtl::MaterialPrefab,
+    renderer::transparent::Transparent,
 };
 use building_blocks::prelude::*;
 use std::collections::HashMap;
@@ -29,35 +31,30 @@ impl<'a> VoxelMeshManager<'a> {
             ..
         } = assets;
 
-        for chunk_key in voxel_map.voxels.storage().chunk_keys() {
-            if let Some(chunk_mesh) = meshes.chunk_meshes.get(&chunk_key.minimum) {
-                self.update_chunk_mesh_entities(
-                    chunk_key.minimum,
-                    Some(chunk_mesh.clone()),
-                    array_materials,
-                );
+        for chunk_min in morton_ordered_chunk_mins(voxel_map) {
+            let mesh = meshes.chunk_meshes.get(&chunk_min).cloned();
+            let transparent_mesh = meshes.transparent_chunk_meshes.get(&chunk_min).cloned();
+            if mesh.is_some() || transparent_mesh.is_some() {
+                self.update_chunk_mesh_entities(chunk_min, mesh, transparent_mesh, array_materials);
             }
         }
+        for (region_min, mesh) in meshes.region_meshes.iter() {
+            self.update_region_mesh_entities(*region_min, Some(mesh.clone()), array_materials);
+        }
     }
 
     pub fn update_chunk_mesh_entities(
         &mut self,
         chunk_key: Point3i,
         mesh: Option<ChunkMesh>,
-        array_materials: &HashMap<ArrayMaterialId, Handle<Prefab<MaterialPrefab>>>,
+        transparent_mesh: Option<ChunkMesh>,
+        array_materials: &HashMap<ArrayMaterialId, ArrayMaterialHandle>,
     ) {
-        // Make new entities.
-        let mut new_entities = Vec::new();
-
-        if let Some(ChunkMesh {
-            material_array_id,
-            mesh,
-        }) = mesh
-        {
-            let material_array = array_materials[&material_array_id].clone();
-            let entity = self.make_voxel_mesh_entity(mesh, material_array);
-            new_entities.push(entity);
-        }
+        let layers = mesh
+            .into_iter()
+            .map(|m| (m, false))
+            .chain(transparent_mesh.into_iter().map(|m| (m, true)));
+        let new_entities = self.make_chunk_mesh_entities(layers, array_materials);
 
         // Replace the entities.
         let mesh_entities = self
@@ -71,26 +68,125 @@ impl<'a> VoxelMeshManager<'a> {
         *mesh_entities = new_entities;
     }
 
+    /// Like `update_chunk_mesh_entities`, but for the opaque mesh of a merged region.
+    pub fn update_region_mesh_entities(
+        &mut self,
+        region_min: Point3i,
+        mesh: Option<ChunkMesh>,
+        array_materials: &HashMap<ArrayMaterialId, ArrayMaterialHandle>,
+    ) {
+        let new_entities =
+            self.make_chunk_mesh_entities(mesh.into_iter().map(|m| (m, false)), array_materials);
+
+        let old_entities = if new_entities.is_empty() {
+            self.mesh_entities.region_entities.remove(&region_min)
+        } else {
+            self.mesh_entities
+                .region_entities
+                .insert(region_min, new_entities)
+        };
+        for e in old_entities.into_iter().flatten() {
+            self.entities.delete(e).unwrap();
+        }
+    }
+
+    /// Like `update_chunk_mesh_entities`, but for the fluid surface of a chunk, which is always
+    /// drawn as transparent.
+    pub fn update_fluid_mesh_entities(
+        &mut self,
+        chunk_key: Point3i,
+        mesh: Option<ChunkMesh>,
+        array_materials: &HashMap<ArrayMaterialId, ArrayMaterialHandle>,
+    ) {
+        let new_entities =
+            self.make_chunk_mesh_entities(mesh.into_iter().map(|m| (m, true)), array_materials);
+
+        let old_entities = if new_entities.is_empty() {
+            self.mesh_entities.fluid_entities.remove(&chunk_key)
+        } else {
+            self.mesh_entities
+                .fluid_entities
+                .insert(chunk_key, new_entities)
+        };
+        for e in old_entities.into_iter().flatten() {
+            self.entities.delete(e).unwrap();
+        }
+    }
+
+    /// Makes an entity for each (mesh, is transparent) layer, plus one for its glow.
+    fn make_chunk_mesh_entities(
+        &self,
+        layers: impl Iterator<Item = (ChunkMesh, bool)>,
+        array_materials: &HashMap<ArrayMaterialId, ArrayMaterialHandle>,
+    ) -> Vec<Entity> {
+        let mut new_entities = Vec::new();
+        for (
+            ChunkMesh {
+                material_array_id,
+                mesh,
+                glow,
+            },
+            transparent,
+        ) in layers
+        {
+            let material_array = &array_materials[&material_array_id];
+            let entity = self.make_voxel_mesh_entity(mesh, material_array.clone(), transparent);
+            new_entities.push(entity);
+            // The glow is blended over the surface it surrounds, so it's always transparent.
+            if let Some(glow) = glow {
+                let entity = self.make_voxel_mesh_entity(glow, material_array.clone(), true);
+                new_entities.push(entity);
+            }
+        }
+
+        new_entities
+    }
+
     /// Creates a new entity with the given mesh and material. Expects the mesh vertices to already
-    /// be in world coordinates, so the model transform can be the identity.
+    /// be in world coordinates, so the model transform can be the identity. Transparent entities
+    /// are drawn by the transparent group of the render pass, sorted back to front and alpha
+    /// blended.
     fn make_voxel_mesh_entity(
         &self,
         mesh: BoundedMesh,
-        material_array: Handle<Prefab<MaterialPrefab>>,
+        material_array: ArrayMaterialHandle,
+        transparent: bool,
     ) -> Entity {
-        let BoundedMesh { mesh, sphere } = mesh;
+        let BoundedMesh { mesh, sphere, aabb } = mesh;
 
-        self.lazy
+        let builder = self
+            .lazy
             .create_entity(&self.entities)
-            .with(material_array)
             .with(mesh)
             .with(Transform::default())
             .with(sphere)
-            .build()
+            .with(aabb);
+        let builder = if transparent {
+            builder.with(Transparent)
+        } else {
+            builder
+        };
+        let builder = match material_array {
+            ArrayMaterialHandle::Prefab(handle) => builder.with(handle),
+            ArrayMaterialHandle::Atlas(handle) | ArrayMaterialHandle::Fallback(handle) => {
+                builder.with(handle)
+            }
+        };
+
+        builder.build()
     }
 
     pub fn destroy(&mut self) {
-        for (_chunk_key, entities) in self.mesh_entities.chunk_entities.drain() {
+        let VoxelMeshEntities {
+            chunk_entities,
+            region_entities,
+            fluid_entities,
+        } = &mut *self.mesh_entities;
+        for (_key, entities) in chunk_entities
+            .drain()
+            .chain(region_entities.drain())
+            .chain(fluid_entities.drain())
+        {
             for e in entities.into_iter() {
                 self.entities.delete(e).unwrap();
             }
@@ -0,0 +1,179 @@
+//! A second channel of per-voxel data: an 8-bit value for gameplay tags, like spawn zones,
+//! triggers or hints for navigation. 0 means untagged, and what the other values mean is up to the
+//! game.
+//!
+//! The values are kept in their own chunks, lined up with the chunks of the `VoxelMap`, rather than
+//! interleaved with the `Voxel`s, so meshing, chunk compression and the voxels file are unaffected
+//! by them. Chunks without any tags aren't stored at all.
+
+use crate::voxel::{chunk_lock::chunk_min_containing_point, morton::morton_key, VOXEL_CHUNK_SHAPE};
+
+use building_blocks::prelude::*;
+use std::collections::HashMap;
+
+pub const UNTAGGED: u8 = 0;
+
+pub struct VoxelMetadata {
+    /// The same as the map's chunk shape.
+    chunk_shape: Point3i,
+    chunks: HashMap<Point3i, Array3x1<u8>>,
+    /// Set whenever a value changes, so the metadata can be saved with the map.
+    changed: bool,
+}
+
+impl Default for VoxelMetadata {
+    fn default() -> Self {
+        Self::new(VOXEL_CHUNK_SHAPE, Vec::new())
+    }
+}
+
+impl VoxelMetadata {
+    /// Metadata for a map whose chunks have `chunk_shape`. Any `chunks` with a different shape,
+    /// e.g. from before the map's chunk shape changed, are split up or merged to match.
+    pub fn new(chunk_shape: Point3i, chunks: Vec<(Point3i, Array3x1<u8>)>) -> Self {
+        let mut metadata = Self {
+            chunk_shape,
+            chunks: HashMap::new(),
+            changed: false,
+        };
+        for (chunk_min, chunk) in chunks.into_iter() {
+            if chunk.extent().shape == chunk_shape {
+                metadata.chunks.insert(chunk_min, chunk);
+            } else {
+                chunk.for_each(chunk.extent(), |p: Point3i, v: u8| {
+                    if v != UNTAGGED {
+                        metadata.set(p, v);
+                    }
+                });
+            }
+        }
+        metadata.changed = false;
+
+        metadata
+    }
+
+    pub fn chunk_shape(&self) -> Point3i {
+        self.chunk_shape
+    }
+
+    pub fn get(&self, p: Point3i) -> u8 {
+        self.chunks
+            .get(&chunk_min_containing_point(p, self.chunk_shape))
+            .map_or(UNTAGGED, |chunk| chunk.get(p))
+    }
+
+    pub fn set(&mut self, p: Point3i, value: u8) {
+        self.paint(
+            &Extent3i::from_min_and_shape(p, PointN([1; 3])),
+            |_| true,
+            value,
+        );
+    }
+
+    /// Sets the value of the voxels in `extent` for which `filter` returns true. Returns how many
+    /// of them changed.
+    pub fn paint(
+        &mut self,
+        extent: &Extent3i,
+        filter: impl Fn(Point3i) -> bool,
+        value: u8,
+    ) -> usize {
+        let mut num_changed = 0;
+        let indexer = ChunkIndexer::new(self.chunk_shape);
+        for chunk_min in indexer.chunk_mins_for_extent(extent) {
+            if value == UNTAGGED && !self.chunks.contains_key(&chunk_min) {
+                continue;
+            }
+            let chunk_extent = Extent3i::from_min_and_shape(chunk_min, self.chunk_shape);
+            let chunk = self
+                .chunks
+                .entry(chunk_min)
+                .or_insert_with(|| Array3x1::fill(chunk_extent, UNTAGGED));
+            chunk.for_each_mut(
+                &extent.intersection(&chunk_extent),
+                |p: Point3i, v: &mut u8| {
+                    if *v != value && filter(p) {
+                        *v = value;
+                        num_changed += 1;
+                    }
+                },
+            );
+
+            let mut any_tagged = false;
+            chunk.for_each(&chunk_extent, |_p: Point3i, v: u8| {
+                any_tagged |= v != UNTAGGED
+            });
+            if !any_tagged {
+                self.chunks.remove(&chunk_min);
+            }
+        }
+        self.changed |= num_changed > 0;
+
+        num_changed
+    }
+
+    /// The tagged voxels in `extent`.
+    pub fn tagged_in_extent(&self, extent: &Extent3i) -> Vec<(Point3i, u8)> {
+        let mut tagged = Vec::new();
+        let indexer = ChunkIndexer::new(self.chunk_shape);
+        for chunk_min in indexer.chunk_mins_for_extent(extent) {
+            if let Some(chunk) = self.chunks.get(&chunk_min) {
+                chunk.for_each(&extent.intersection(chunk.extent()), |p: Point3i, v: u8| {
+                    if v != UNTAGGED {
+                        tagged.push((p, v));
+                    }
+                });
+            }
+        }
+
+        tagged
+    }
+
+    /// Copies out the stored chunks in Morton order, for saving.
+    pub fn snapshot_chunks(&self) -> Vec<(Point3i, Array3x1<u8>)> {
+        let mut chunks: Vec<_> = self
+            .chunks
+            .iter()
+            .map(|(chunk_min, chunk)| (*chunk_min, chunk.clone()))
+            .collect();
+        chunks.sort_by_key(|(chunk_min, _)| morton_key(*chunk_min, self.chunk_shape));
+
+        chunks
+    }
+
+    pub fn has_changed(&self) -> bool {
+        self.changed
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paint_and_erase_across_chunks() {
+        let mut metadata = VoxelMetadata::default();
+        let extent = Extent3i::from_min_and_shape(PointN([14, 0, 0]), PointN([4, 1, 1]));
+        assert_eq!(metadata.paint(&extent, |p| p.x() != 15, 3), 3);
+        assert!(metadata.has_changed());
+        assert_eq!(metadata.get(PointN([14, 0, 0])), 3);
+        assert_eq!(metadata.get(PointN([15, 0, 0])), UNTAGGED);
+        assert_eq!(metadata.get(PointN([17, 0, 0])), 3);
+        assert_eq!(metadata.snapshot_chunks().len(), 2);
+
+        // Erasing the only tags in a chunk drops it.
+        metadata.paint(&extent, |p| p.x() >= 16, UNTAGGED);
+        assert_eq!(
+            metadata.tagged_in_extent(&extent),
+            vec![(PointN([14, 0, 0]), 3)]
+        );
+        assert_eq!(metadata.snapshot_chunks().len(), 1);
+    }
+}
@@ -0,0 +1,327 @@
+//! A coarse top-down overview of the map. Each pixel covers a square of voxel columns and shows the
+//! voxel type that most of the solid voxels in those columns have. Chunks are summarized as they
+//! change, so drawing the minimap never has to read the voxels.
+
+use crate::voxel::{
+    chunk_cache_flusher::ChunkCacheFlusher, double_buffer::DirtyChunks,
+    morton::morton_ordered_chunk_mins, Voxel, VoxelMap, VoxelType, VOXEL_CHUNK_SHAPE,
+};
+
+use amethyst::core::ecs::prelude::*;
+use building_blocks::prelude::*;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MinimapConfig {
+    /// The width of the square of voxel columns that each pixel covers. Must divide the chunk
+    /// width.
+    pub voxels_per_pixel: i32,
+    /// The width and height of the minimap in pixels.
+    pub size_pixels: u32,
+    /// Linear RGB colors of the voxel types. Other types get a color made up from their number.
+    #[serde(default)]
+    pub colors: HashMap<VoxelType, [f32; 3]>,
+}
+
+impl Default for MinimapConfig {
+    fn default() -> Self {
+        Self {
+            voxels_per_pixel: 4,
+            size_pixels: 128,
+            colors: HashMap::new(),
+        }
+    }
+}
+
+impl MinimapConfig {
+    pub fn color(&self, voxel_type: VoxelType) -> [u8; 4] {
+        let [r, g, b] = self.colors.get(&voxel_type).cloned().unwrap_or_else(|| {
+            // Spread the types around the color wheel, so neighbors are easy to tell apart.
+            let hue = (voxel_type.0 as f32 * 0.618).fract() * std::f32::consts::PI * 2.0;
+
+            [
+                0.5 + 0.4 * hue.cos(),
+                0.5 + 0.4 * (hue + 2.1).cos(),
+                0.5 + 0.4 * (hue + 4.2).cos(),
+            ]
+        });
+        let to_u8 = |c: f32| (c.max(0.0).min(1.0) * 255.0) as u8;
+
+        [to_u8(r), to_u8(g), to_u8(b), 255]
+    }
+}
+
+/// The solid voxels of one pixel's columns within one chunk.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MinimapCell {
+    /// The most common voxel type.
+    pub voxel_type: VoxelType,
+    /// How many voxels have `voxel_type`.
+    pub count: u16,
+    /// The Y of the highest solid voxel.
+    pub top: i32,
+}
+
+pub struct Minimap {
+    voxels_per_pixel: i32,
+    /// The shape of the summarized chunks, which is taken from the map.
+    chunk_shape: Point3i,
+    /// The cells of each chunk, by the (X, Z) of the chunk's minimum and then its Y. Cells are in
+    /// row-major order, with X varying fastest.
+    columns: HashMap<[i32; 2], HashMap<i32, Vec<Option<MinimapCell>>>>,
+    /// Counts the changes, so the minimap is only drawn again when something changed.
+    revision: u64,
+}
+
+impl Minimap {
+    pub fn new(voxels_per_pixel: i32) -> Self {
+        assert!(voxels_per_pixel > 0);
+
+        Self {
+            voxels_per_pixel,
+            chunk_shape: VOXEL_CHUNK_SHAPE,
+            columns: HashMap::new(),
+            revision: 0,
+        }
+    }
+
+    pub fn voxels_per_pixel(&self) -> i32 {
+        self.voxels_per_pixel
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Replaces the summary of the chunk at `chunk_min`. `None` means the chunk doesn't exist.
+    pub fn update_chunk(&mut self, chunk_min: Point3i, chunk: Option<&Array3x1<Voxel>>) {
+        if let Some(chunk) = chunk {
+            self.chunk_shape = chunk.extent().shape;
+        }
+        let cells = chunk.map(|c| summarize_chunk(c, self.voxels_per_pixel));
+        self.set_chunk_cells(chunk_min, cells);
+    }
+
+    fn set_chunk_cells(&mut self, chunk_min: Point3i, cells: Option<Vec<Option<MinimapCell>>>) {
+        let column_key = [chunk_min.x(), chunk_min.z()];
+        match cells {
+            Some(cells) => {
+                self.columns
+                    .entry(column_key)
+                    .or_default()
+                    .insert(chunk_min.y(), cells);
+            }
+            None => {
+                if let Some(column) = self.columns.get_mut(&column_key) {
+                    column.remove(&chunk_min.y());
+                    if column.is_empty() {
+                        self.columns.remove(&column_key);
+                    }
+                }
+            }
+        }
+        self.revision += 1;
+    }
+
+    /// The combined cell of the pixel at `pixel`, in units of `voxels_per_pixel` from the origin.
+    /// The voxel type is the one with the most voxels in any single chunk, which is close enough to
+    /// the most common type of the whole column for an overview.
+    pub fn cell(&self, pixel: [i32; 2]) -> Option<MinimapCell> {
+        let chunk_shape = self.chunk_shape;
+        let x = pixel[0] * self.voxels_per_pixel;
+        let z = pixel[1] * self.voxels_per_pixel;
+        let chunk_x = x.div_euclid(chunk_shape.x()) * chunk_shape.x();
+        let chunk_z = z.div_euclid(chunk_shape.z()) * chunk_shape.z();
+        let cells_per_row = chunk_shape.x() / self.voxels_per_pixel;
+        let i = ((z - chunk_z) / self.voxels_per_pixel * cells_per_row
+            + (x - chunk_x) / self.voxels_per_pixel) as usize;
+
+        let column = self.columns.get(&[chunk_x, chunk_z])?;
+        let dominant = column
+            .values()
+            .filter_map(|cells| cells[i])
+            .max_by_key(|cell| cell.count)?;
+        let top = column
+            .values()
+            .filter_map(|cells| cells[i])
+            .map(|cell| cell.top)
+            .max()?;
+
+        Some(MinimapCell { top, ..dominant })
+    }
+
+    /// The pixel that the voxel column at (`x`, `z`) is in.
+    pub fn pixel_containing(&self, x: i32, z: i32) -> [i32; 2] {
+        [
+            x.div_euclid(self.voxels_per_pixel),
+            z.div_euclid(self.voxels_per_pixel),
+        ]
+    }
+
+    /// Draws a `size` x `size` image whose first pixel is `min_pixel`. Rows go along +Z, so -Z is
+    /// at the top. Columns without any solid voxels are transparent.
+    pub fn draw(
+        &self,
+        min_pixel: [i32; 2],
+        size: u32,
+        color: impl Fn(VoxelType) -> [u8; 4],
+    ) -> Vec<[u8; 4]> {
+        let size = size as i32;
+        let mut pixels = Vec::with_capacity((size * size) as usize);
+        for row in 0..size {
+            for col in 0..size {
+                let pixel = [min_pixel[0] + col, min_pixel[1] + row];
+                pixels.push(
+                    self.cell(pixel)
+                        .map_or([0, 0, 0, 0], |cell| color(cell.voxel_type)),
+                );
+            }
+        }
+
+        pixels
+    }
+}
+
+fn summarize_chunk(chunk: &Array3x1<Voxel>, voxels_per_pixel: i32) -> Vec<Option<MinimapCell>> {
+    let extent = *chunk.extent();
+    let cells_per_row = extent.shape.x() / voxels_per_pixel;
+    let num_cells = (cells_per_row * (extent.shape.z() / voxels_per_pixel)) as usize;
+
+    let mut counts: Vec<HashMap<VoxelType, u16>> = vec![HashMap::new(); num_cells];
+    let mut tops: Vec<Option<i32>> = vec![None; num_cells];
+    chunk.for_each(&extent, |p: Point3i, v: Voxel| {
+        if v.distance.0 >= 0 {
+            return;
+        }
+        let local = p - extent.minimum;
+        let i =
+            (local.z() / voxels_per_pixel * cells_per_row + local.x() / voxels_per_pixel) as usize;
+        *counts[i].entry(v.voxel_type).or_insert(0) += 1;
+        tops[i] = Some(tops[i].map_or(p.y(), |y| y.max(p.y())));
+    });
+
+    counts
+        .into_iter()
+        .zip(tops.into_iter())
+        .map(|(counts, top)| {
+            // Break ties by type, so the result doesn't depend on the hash order.
+            let (voxel_type, count) = counts.into_iter().max_by_key(|(t, n)| (*n, t.0))?;
+
+            Some(MinimapCell {
+                voxel_type,
+                count,
+                top: top?,
+            })
+        })
+        .collect()
+}
+
+/// Summarizes all of the chunks in `voxel_map`, e.g. when a map is loaded.
+pub fn insert_all_minimap_chunks(minimap: &mut Minimap, voxel_map: &VoxelMap) {
+    minimap.chunk_shape = voxel_map.chunk_shape();
+    let voxels_per_pixel = minimap.voxels_per_pixel;
+    let summaries: Vec<(Point3i, Vec<Option<MinimapCell>>)> = morton_ordered_chunk_mins(voxel_map)
+        .into_par_iter()
+        .filter_map(|chunk_min| {
+            let local_chunk_cache = LocalChunkCache3::new();
+            let reader = voxel_map.voxels.reader(&local_chunk_cache);
+            let chunk = reader.get_chunk(ChunkKey::new(0, chunk_min))?;
+
+            Some((chunk_min, summarize_chunk(chunk, voxels_per_pixel)))
+        })
+        .collect();
+
+    for (chunk_min, cells) in summaries.into_iter() {
+        minimap.set_chunk_cells(chunk_min, Some(cells));
+    }
+}
+
+/// Keeps the `Minimap` up to date with the `DirtyChunks`. Chunks that are evicted from memory stay
+/// on the minimap, since it's an overview of the whole map.
+pub struct MinimapSystem;
+
+impl<'a> System<'a> for MinimapSystem {
+    type SystemData = (
+        Read<'a, Option<DirtyChunks>>,
+        ReadExpect<'a, VoxelMap>,
+        ReadExpect<'a, ChunkCacheFlusher>,
+        WriteExpect<'a, Minimap>,
+    );
+
+    fn run(&mut self, (dirty_chunks, voxel_map, cache_flusher, mut minimap): Self::SystemData) {
+        #[cfg(feature = "profiler")]
+        profile_scope!("minimap");
+
+        let dirty_chunks = match dirty_chunks.as_ref() {
+            Some(d) => d,
+            None => return,
+        };
+
+        let local_cache = LocalChunkCache3::new();
+        let reader = voxel_map.voxels.reader(&local_cache);
+        for chunk_min in dirty_chunks.chunks.keys() {
+            if let Some(chunk) = reader.get_chunk(ChunkKey::new(0, *chunk_min)) {
+                minimap.update_chunk(*chunk_min, Some(chunk));
+            }
+        }
+        cache_flusher.flush(local_cache);
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::voxel::{empty_array, VoxelDistance};
+
+    #[test]
+    fn test_minimap_shows_dominant_type_of_column() {
+        let solid = |t: u8| Voxel {
+            voxel_type: VoxelType(t),
+            distance: VoxelDistance(-10),
+        };
+
+        // A floor of type 1 with a thinner layer of type 2 on top, only in the first pixel.
+        let lower_min = PointN([0, 0, 0]);
+        let mut lower = empty_array(Extent3i::from_min_and_shape(lower_min, VOXEL_CHUNK_SHAPE));
+        lower.for_each_mut(
+            &Extent3i::from_min_and_shape(lower_min, PointN([16, 4, 16])),
+            |_p: Point3i, v: &mut Voxel| *v = solid(1),
+        );
+        let upper_min = PointN([0, 16, 0]);
+        let mut upper = empty_array(Extent3i::from_min_and_shape(upper_min, VOXEL_CHUNK_SHAPE));
+        upper.for_each_mut(
+            &Extent3i::from_min_and_shape(upper_min, PointN([4, 2, 4])),
+            |_p: Point3i, v: &mut Voxel| *v = solid(2),
+        );
+
+        let mut minimap = Minimap::new(4);
+        minimap.update_chunk(lower_min, Some(&lower));
+        minimap.update_chunk(upper_min, Some(&upper));
+
+        let first = minimap.cell([0, 0]).unwrap();
+        assert_eq!(first.voxel_type, VoxelType(1));
+        assert_eq!(first.top, 17);
+        assert_eq!(minimap.cell([1, 0]).unwrap().top, 3);
+        assert_eq!(minimap.cell([4, 0]), None);
+        assert_eq!(minimap.pixel_containing(-1, 5), [-1, 1]);
+
+        let revision = minimap.revision();
+        minimap.update_chunk(lower_min, None);
+        assert!(minimap.revision() > revision);
+        assert_eq!(minimap.cell([0, 0]).unwrap().voxel_type, VoxelType(2));
+        assert_eq!(minimap.cell([1, 0]), None);
+    }
+}
@@ -0,0 +1,115 @@
+//! Morton (Z-order) keys for chunks. Visiting chunks in Morton order keeps neighboring chunks close
+//! together, so work that reads across chunk boundaries (like meshing) tends to hit chunks that were
+//! just touched, and spatial regions map to mostly contiguous ranges of keys.
+
+use crate::voxel::VoxelMap;
+
+use building_blocks::prelude::*;
+
+/// Each axis gets 21 bits of the key.
+const AXIS_BITS: u32 = 21;
+/// Shifts signed chunk coordinates into the unsigned range before interleaving.
+const AXIS_BIAS: i32 = 1 << (AXIS_BITS - 1);
+
+/// The Morton key of the chunk of shape `chunk_shape` with minimum `chunk_min`. Chunk coordinates
+/// must fit in 21 bits, i.e. be within about a million chunks of the origin.
+pub fn morton_key(chunk_min: Point3i, chunk_shape: Point3i) -> u64 {
+    let chunk_coords = chunk_min.vector_div_floor(&chunk_shape);
+
+    let mut key = 0;
+    for (axis, &c) in chunk_coords.0.iter().enumerate() {
+        key |= spread_bits((c + AXIS_BIAS) as u32) << axis;
+    }
+
+    key
+}
+
+/// Inverse of `morton_key`.
+pub fn chunk_min_from_morton_key(key: u64, chunk_shape: Point3i) -> Point3i {
+    let mut chunk_coords = PointN([0; 3]);
+    for (axis, c) in chunk_coords.0.iter_mut().enumerate() {
+        *c = compact_bits(key >> axis) as i32 - AXIS_BIAS;
+    }
+
+    chunk_coords * chunk_shape
+}
+
+pub fn sort_chunk_mins_morton(chunk_mins: &mut [Point3i], chunk_shape: Point3i) {
+    chunk_mins.sort_by_key(|&p| morton_key(p, chunk_shape));
+}
+
+/// The minimums of all chunks stored in `map`, in Morton order.
+pub fn morton_ordered_chunk_mins(map: &VoxelMap) -> Vec<Point3i> {
+    let mut chunk_mins: Vec<Point3i> = map
+        .voxels
+        .storage()
+        .chunk_keys()
+        .map(|chunk_key| chunk_key.minimum)
+        .collect();
+    sort_chunk_mins_morton(&mut chunk_mins, map.chunk_shape());
+
+    chunk_mins
+}
+
+/// Inserts two zero bits between each of the low 21 bits of `x`.
+fn spread_bits(x: u32) -> u64 {
+    let mut x = x as u64 & 0x1f_ffff;
+    x = (x | x << 32) & 0x1f_0000_0000_ffff;
+    x = (x | x << 16) & 0x1f_0000_ff00_00ff;
+    x = (x | x << 8) & 0x100f_00f0_0f00_f00f;
+    x = (x | x << 4) & 0x10c3_0c30_c30c_30c3;
+    x = (x | x << 2) & 0x1249_2492_4924_9249;
+
+    x
+}
+
+/// Inverse of `spread_bits`.
+fn compact_bits(x: u64) -> u32 {
+    let mut x = x & 0x1249_2492_4924_9249;
+    x = (x | x >> 2) & 0x10c3_0c30_c30c_30c3;
+    x = (x | x >> 4) & 0x100f_00f0_0f00_f00f;
+    x = (x | x >> 8) & 0x1f_0000_ff00_00ff;
+    x = (x | x >> 16) & 0x1f_0000_0000_ffff;
+    x = (x | x >> 32) & 0x1f_ffff;
+
+    x as u32
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::voxel::VOXEL_CHUNK_SHAPE;
+
+    #[test]
+    fn test_morton_key_round_trip_and_order() {
+        for &coords in [[0, 0, 0], [-1, 2, -3], [1000, -1000, 7]].iter() {
+            let chunk_min = PointN(coords) * VOXEL_CHUNK_SHAPE;
+            assert_eq!(
+                chunk_min_from_morton_key(
+                    morton_key(chunk_min, VOXEL_CHUNK_SHAPE),
+                    VOXEL_CHUNK_SHAPE
+                ),
+                chunk_min
+            );
+        }
+
+        let mut chunk_mins: Vec<Point3i> = [[1, 1, 0], [0, 0, 1], [1, 0, 0], [0, 0, 0]]
+            .iter()
+            .map(|&c| PointN(c) * VOXEL_CHUNK_SHAPE)
+            .collect();
+        sort_chunk_mins_morton(&mut chunk_mins, VOXEL_CHUNK_SHAPE);
+        let coords: Vec<[i32; 3]> = chunk_mins
+            .iter()
+            .map(|p| p.vector_div_floor(&VOXEL_CHUNK_SHAPE).0)
+            .collect();
+        assert_eq!(coords, vec![[0, 0, 0], [1, 0, 0], [1, 1, 0], [0, 0, 1]]);
+    }
+}
@@ -0,0 +1,920 @@
+//! Lets several editors work on the same map over TCP. One editor hosts the session and owns the
+//! map; the others connect as clients.
+//!
+//! - When a client connects, the host sends it every chunk of its map, which replaces the client's
+//!   map.
+//! - Edits are taken from the `EditedChunksBackBuffer` when it's merged, so every kind of edit is
+//!   shared, whether it came from a brush, the clipboard, a script or a simulation. Generated and
+//!   streamed chunks aren't edits, so they aren't shared.
+//! - A client merges its own edits right away, and sends the voxels they changed to the host as
+//!   `ChunkDelta`s. Until the host sends them back, stamped with the client's `EditSourceId` and
+//!   the request's sequence number, they're written again over any chunks the host sends.
+//! - The host writes the voxels it receives over its own chunks, like any other edit, and sends
+//!   every chunk that changed in its map to all of the clients.
+//!
+//! Since the host's map is the only one that clients take chunks from, all of the maps end up the
+//! same. When two editors change the same voxel at once, the change that reaches the host last
+//! wins, but changes to different voxels of the same chunk are all kept. All peers must use the
+//! same palette, chunk shape and distance precision, and chunks that a streamed map has unloaded
+//! aren't sent to new clients.
+
+use crate::voxel::{
+    double_buffer::{EditSourceId, EditStamp, EditedChunksBackBuffer},
+    map_file::{compress_chunk, decompress_chunk, snapshot_chunks},
+    morton::morton_ordered_chunk_mins,
+    Voxel, VoxelMap,
+};
+
+use amethyst::core::ecs::prelude::*;
+use bincode::Options;
+use building_blocks::prelude::*;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::{self, Read as IoRead, Write as IoWrite};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+
+/// Messages are prefixed with their length in bytes, as a little-endian u32.
+const LENGTH_PREFIX_BYTES: usize = 4;
+
+/// Messages any longer than this are refused, so a bad length prefix can't make us buffer or
+/// allocate without bound.
+const MAX_MESSAGE_BYTES: u64 = 64 << 20;
+
+/// The map sent to a new client is split into messages of about this many bytes.
+const WELCOME_BATCH_BYTES: usize = 16 << 20;
+
+/// The lz4 blocks made by `compress_chunk` start with their decompressed size, as a little-endian
+/// i32.
+const LZ4_SIZE_PREFIX_BYTES: usize = 4;
+
+/// Chunks compressed with `compress_chunk`.
+type CompressedChunks = Vec<(Point3i, Vec<u8>)>;
+
+/// The voxels that an edit changed in the chunk at `chunk_min`. Clients send these instead of whole
+/// chunks, so edits to different voxels of the same chunk don't overwrite each other.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ChunkDelta {
+    pub chunk_min: Point3i,
+    pub voxels: Vec<(Point3i, Voxel)>,
+}
+
+impl ChunkDelta {
+    /// The voxels of `chunk` that differ from `base`, or `None` if they're all the same.
+    pub fn between(base: &Array3x1<Voxel>, chunk: &Array3x1<Voxel>) -> Option<Self> {
+        let extent = *chunk.extent();
+        let mut voxels = Vec::new();
+        chunk.for_each(&extent, |p: Point3i, v: Voxel| {
+            if base.get(p) != v {
+                voxels.push((p, v));
+            }
+        });
+        if voxels.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            chunk_min: extent.minimum,
+            voxels,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+enum NetMessage {
+    /// Sent by the host to each new client, followed by the host's map in `MapChunks` messages
+    /// and then `MapComplete`.
+    Welcome {
+        source: EditSourceId,
+    },
+    MapChunks(CompressedChunks),
+    MapComplete,
+    /// Sent by a client with the voxels its edits changed. The host stamps the request with the
+    /// client's `EditSourceId` and `sequence`.
+    RequestEdits {
+        sequence: u64,
+        deltas: Vec<ChunkDelta>,
+    },
+    /// Sent by the host to every client after each merge that changed its map. `stamps` has the
+    /// stamps of the client requests that were merged.
+    Chunks {
+        stamps: Vec<EditStamp>,
+        chunks: CompressedChunks,
+    },
+}
+
+fn message_options() -> impl Options {
+    bincode::options().with_limit(MAX_MESSAGE_BYTES)
+}
+
+fn encode_message(message: &NetMessage, buffer: &mut Vec<u8>) -> bincode::Result<()> {
+    let bytes = message_options().serialize(message)?;
+    buffer.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(&bytes);
+
+    Ok(())
+}
+
+/// Removes the first whole message from the front of `buffer`, if it has arrived yet. A message
+/// that's longer than `MAX_MESSAGE_BYTES`, or that fails `check_message` for chunks with
+/// `chunk_shape`, is an error, since the stream can't be trusted after it.
+fn decode_message(
+    buffer: &mut Vec<u8>,
+    chunk_shape: Point3i,
+) -> Option<bincode::Result<NetMessage>> {
+    if buffer.len() < LENGTH_PREFIX_BYTES {
+        return None;
+    }
+    let mut length = [0; LENGTH_PREFIX_BYTES];
+    length.copy_from_slice(&buffer[..LENGTH_PREFIX_BYTES]);
+    let length = u32::from_le_bytes(length) as u64;
+    if length > MAX_MESSAGE_BYTES {
+        return Some(Err(Box::new(bincode::ErrorKind::SizeLimit)));
+    }
+    let end = LENGTH_PREFIX_BYTES + length as usize;
+    if buffer.len() < end {
+        return None;
+    }
+    let message = message_options()
+        .deserialize(&buffer[LENGTH_PREFIX_BYTES..end])
+        .and_then(|message| {
+            check_message(&message, chunk_shape)
+                .map(|()| message)
+                .map_err(|reason| Box::new(bincode::ErrorKind::Custom(reason)))
+        });
+    buffer.drain(..end);
+
+    Some(message)
+}
+
+/// Peers can't be trusted to send chunks that fit in the map, so every chunk must be aligned to
+/// `chunk_shape`, every changed voxel must be inside its chunk, and every compressed chunk must say
+/// that it decompresses to exactly one chunk of voxels. Otherwise lz4 would allocate as much as the
+/// size at the front of the block asks for.
+fn check_message(message: &NetMessage, chunk_shape: Point3i) -> Result<(), String> {
+    match message {
+        NetMessage::MapChunks(chunks) | NetMessage::Chunks { chunks, .. } => {
+            chunks.iter().try_for_each(|(chunk_min, lz4_voxels)| {
+                check_compressed_chunk(*chunk_min, chunk_shape, lz4_voxels)
+            })
+        }
+        NetMessage::RequestEdits { deltas, .. } => deltas
+            .iter()
+            .try_for_each(|delta| check_delta(delta, chunk_shape)),
+        NetMessage::Welcome { .. } | NetMessage::MapComplete => Ok(()),
+    }
+}
+
+fn check_chunk_min(chunk_min: Point3i, chunk_shape: Point3i) -> Result<(), String> {
+    let is_aligned = chunk_min
+        .0
+        .iter()
+        .zip(chunk_shape.0.iter())
+        .all(|(c, s)| c.rem_euclid(*s) == 0);
+    if !is_aligned {
+        return Err(format!(
+            "Chunk at {:?} isn't aligned to the chunk shape {:?}",
+            chunk_min.0, chunk_shape.0
+        ));
+    }
+
+    Ok(())
+}
+
+fn check_compressed_chunk(
+    chunk_min: Point3i,
+    chunk_shape: Point3i,
+    lz4_voxels: &[u8],
+) -> Result<(), String> {
+    check_chunk_min(chunk_min, chunk_shape)?;
+
+    // `compress_chunk` serializes a `Vec`, which starts with its length as a u64.
+    let num_points = Extent3i::from_min_and_shape(chunk_min, chunk_shape).num_points();
+    let expected_size = 8 + num_points * std::mem::size_of::<Voxel>();
+    if lz4_voxels.len() < LZ4_SIZE_PREFIX_BYTES {
+        return Err(format!("Chunk at {:?} is missing its size", chunk_min.0));
+    }
+    let mut size = [0; LZ4_SIZE_PREFIX_BYTES];
+    size.copy_from_slice(&lz4_voxels[..LZ4_SIZE_PREFIX_BYTES]);
+    let size = i32::from_le_bytes(size);
+    if size as i64 != expected_size as i64 {
+        return Err(format!(
+            "Chunk at {:?} decompresses to {} bytes, but a chunk of shape {:?} has {}",
+            chunk_min.0, size, chunk_shape.0, expected_size
+        ));
+    }
+
+    Ok(())
+}
+
+fn check_delta(delta: &ChunkDelta, chunk_shape: Point3i) -> Result<(), String> {
+    check_chunk_min(delta.chunk_min, chunk_shape)?;
+
+    let extent = Extent3i::from_min_and_shape(delta.chunk_min, chunk_shape);
+    if delta.voxels.len() > extent.num_points() {
+        return Err(format!(
+            "Chunk at {:?} has {} changed voxels, but only {} points",
+            delta.chunk_min.0,
+            delta.voxels.len(),
+            extent.num_points()
+        ));
+    }
+    if let Some((p, _)) = delta.voxels.iter().find(|(p, _)| !extent.contains(*p)) {
+        return Err(format!(
+            "Voxel at {:?} is outside of the chunk at {:?}",
+            p.0, delta.chunk_min.0
+        ));
+    }
+
+    Ok(())
+}
+
+fn compress_chunks(chunks: Vec<(Point3i, Array3x1<Voxel>)>) -> io::Result<CompressedChunks> {
+    chunks
+        .into_par_iter()
+        .map(|(chunk_min, chunk)| {
+            compress_chunk(&chunk, None)
+                .map(|lz4_voxels| (chunk_min, lz4_voxels))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))
+        })
+        .collect()
+}
+
+/// Chunks that fail `check_compressed_chunk` or fail to decompress are logged and skipped.
+fn decompress_chunks(
+    chunks: CompressedChunks,
+    chunk_shape: Point3i,
+) -> Vec<(Point3i, Array3x1<Voxel>)> {
+    chunks
+        .into_par_iter()
+        .filter_map(|(chunk_min, lz4_voxels)| {
+            if let Err(e) = check_compressed_chunk(chunk_min, chunk_shape, &lz4_voxels) {
+                log::error!("Refused chunk {:?}: {}", chunk_min, e);
+
+                return None;
+            }
+            match decompress_chunk(chunk_min, chunk_shape, &lz4_voxels) {
+                Ok(chunk) => Some((chunk_min, chunk)),
+                Err(e) => {
+                    log::error!("Failed to decompress chunk {:?}: {:?}", chunk_min, e);
+
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// The chunks exchanged with an `EditSession`. The `VoxelDoubleBufferingSystem` collects them when
+/// it merges the backbuffer, and the `NetworkEditSystem` sends them on the next frame.
+#[derive(Default)]
+pub struct NetworkChunkEdits {
+    role: Option<NetworkRole>,
+    /// On the host, every chunk that changed in the map.
+    pub(crate) outgoing: Vec<(Point3i, Array3x1<Voxel>)>,
+    /// On a client, the voxels changed by local edits, which were merged but haven't been sent to
+    /// the host yet.
+    pub(crate) outgoing_deltas: Vec<ChunkDelta>,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum NetworkRole {
+    Host,
+    Client,
+}
+
+impl NetworkChunkEdits {
+    pub fn is_host(&self) -> bool {
+        self.role == Some(NetworkRole::Host)
+    }
+
+    pub fn is_client(&self) -> bool {
+        self.role == Some(NetworkRole::Client)
+    }
+}
+
+/// A non-blocking stream with buffers for partially sent and received messages.
+struct Connection {
+    stream: TcpStream,
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+
+        Ok(Self {
+            stream,
+            incoming: Vec::new(),
+            outgoing: Vec::new(),
+        })
+    }
+
+    fn send(&mut self, message: &NetMessage) -> io::Result<()> {
+        encode_message(message, &mut self.outgoing)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Writes as much of the outgoing buffer as the socket will take.
+    fn flush(&mut self) -> io::Result<()> {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.outgoing.drain(..n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads everything that has arrived and returns the whole messages, which are checked against
+    /// the `chunk_shape` of the map.
+    fn receive(&mut self, chunk_shape: Point3i) -> io::Result<Vec<NetMessage>> {
+        let mut read_buffer = [0; 1 << 16];
+        loop {
+            match self.stream.read(&mut read_buffer) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => self.incoming.extend_from_slice(&read_buffer[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut messages = Vec::new();
+        while let Some(message) = decode_message(&mut self.incoming, chunk_shape) {
+            messages.push(message.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?);
+        }
+
+        Ok(messages)
+    }
+}
+
+struct Peer {
+    source: EditSourceId,
+    connection: Connection,
+}
+
+pub struct EditHost {
+    listener: TcpListener,
+    peers: Vec<Peer>,
+    next_source: u32,
+    /// The stamps of the client requests that were written to the backbuffer, which are sent once
+    /// they've been merged.
+    merging_stamps: Vec<EditStamp>,
+}
+
+pub struct EditClient {
+    connection: Connection,
+    /// Assigned by the host once it has welcomed us.
+    source: Option<EditSourceId>,
+    /// The host's map, while it's still arriving.
+    welcome_chunks: Option<CompressedChunks>,
+    next_sequence: u64,
+    /// The voxels changed by each request that the host hasn't sent back yet, by sequence number.
+    /// They're written again over the chunks that the host sends in the meantime, so they aren't
+    /// undone before the host has merged them.
+    unconfirmed: Vec<(u64, Vec<ChunkDelta>)>,
+}
+
+pub enum EditSessionRole {
+    Host(EditHost),
+    Client(EditClient),
+}
+
+/// A shared editing session. Insert `Some(session)` as a resource to start sharing edits with the
+/// `NetworkEditSystem`.
+pub struct EditSession {
+    role: EditSessionRole,
+}
+
+impl EditSession {
+    /// Listens for clients on `address`.
+    pub fn host(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        log::info!("Hosting edit session on {}", listener.local_addr()?);
+
+        Ok(Self {
+            role: EditSessionRole::Host(EditHost {
+                listener,
+                peers: Vec::new(),
+                // Source 0 is the host's own local edits.
+                next_source: 1,
+                merging_stamps: Vec::new(),
+            }),
+        })
+    }
+
+    /// Joins the session hosted at `address`. The local map is replaced by the host's once the host
+    /// welcomes us.
+    pub fn connect(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let connection = Connection::new(TcpStream::connect(address)?)?;
+        log::info!("Joined edit session at {}", connection.stream.peer_addr()?);
+
+        Ok(Self {
+            role: EditSessionRole::Client(EditClient {
+                connection,
+                source: None,
+                welcome_chunks: None,
+                next_sequence: 0,
+                unconfirmed: Vec::new(),
+            }),
+        })
+    }
+
+    pub fn role(&self) -> &EditSessionRole {
+        &self.role
+    }
+}
+
+impl EditHost {
+    pub fn num_clients(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    fn update(
+        &mut self,
+        merged_chunks: Vec<(Point3i, Array3x1<Voxel>)>,
+        map: &VoxelMap,
+        backbuffer: &mut EditedChunksBackBuffer,
+    ) {
+        let mut disconnected = HashSet::new();
+
+        // Send the chunks that changed in the last merge, including the ones requested by clients.
+        let stamps = std::mem::replace(&mut self.merging_stamps, Vec::new());
+        if !self.peers.is_empty() && (!merged_chunks.is_empty() || !stamps.is_empty()) {
+            match compress_chunks(merged_chunks) {
+                Ok(chunks) => {
+                    let message = NetMessage::Chunks { stamps, chunks };
+                    for peer in self.peers.iter_mut() {
+                        if let Err(e) = peer.connection.send(&message) {
+                            log::warn!("Failed to send chunks to {:?}: {:?}", peer.source, e);
+                            disconnected.insert(peer.source);
+                        }
+                    }
+                }
+                Err(e) => log::error!("Failed to compress merged chunks: {:?}", e),
+            }
+        }
+
+        // New clients get the map as of the last merge, which every peer has now been sent.
+        loop {
+            match self.listener.accept() {
+                Ok((stream, address)) => match self.welcome(stream, map) {
+                    Ok(peer) => {
+                        log::info!("{} joined the edit session as {:?}", address, peer.source);
+                        self.peers.push(peer);
+                    }
+                    Err(e) => log::warn!("Failed to welcome {}: {:?}", address, e),
+                },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::warn!("Failed to accept edit session client: {:?}", e);
+                    break;
+                }
+            }
+        }
+
+        let local_cache = LocalChunkCache3::new();
+        let reader = map.voxels.reader(&local_cache);
+        for peer in self.peers.iter_mut() {
+            let messages = match peer.connection.receive(map.chunk_shape()) {
+                Ok(m) => m,
+                Err(e) => {
+                    log::warn!("{:?} left the edit session: {:?}", peer.source, e);
+                    disconnected.insert(peer.source);
+                    continue;
+                }
+            };
+            for message in messages.into_iter() {
+                match message {
+                    NetMessage::RequestEdits { sequence, deltas } => {
+                        self.merging_stamps.push(EditStamp {
+                            source: peer.source,
+                            sequence,
+                        });
+                        backbuffer.receive_voxels(&reader, deltas);
+                    }
+                    other => log::warn!("Unexpected message from {:?}: {:?}", peer.source, other),
+                }
+            }
+            if let Err(e) = peer.connection.flush() {
+                log::warn!("{:?} left the edit session: {:?}", peer.source, e);
+                disconnected.insert(peer.source);
+            }
+        }
+        self.peers.retain(|p| !disconnected.contains(&p.source));
+    }
+
+    fn welcome(&mut self, stream: TcpStream, map: &VoxelMap) -> io::Result<Peer> {
+        let source = EditSourceId(self.next_source);
+        self.next_source += 1;
+
+        let mut connection = Connection::new(stream)?;
+        connection.send(&NetMessage::Welcome { source })?;
+        let mut batch = Vec::new();
+        let mut batch_bytes = 0;
+        for (chunk_min, lz4_voxels) in compress_chunks(snapshot_chunks(map))?.into_iter() {
+            batch_bytes += lz4_voxels.len();
+            batch.push((chunk_min, lz4_voxels));
+            if batch_bytes >= WELCOME_BATCH_BYTES {
+                connection.send(&NetMessage::MapChunks(std::mem::replace(
+                    &mut batch,
+                    Vec::new(),
+                )))?;
+                batch_bytes = 0;
+            }
+        }
+        connection.send(&NetMessage::MapChunks(batch))?;
+        connection.send(&NetMessage::MapComplete)?;
+        connection.flush()?;
+
+        Ok(Peer { source, connection })
+    }
+}
+
+impl EditClient {
+    pub fn source(&self) -> Option<EditSourceId> {
+        self.source
+    }
+
+    /// The number of edit requests that the host hasn't merged and sent back yet.
+    pub fn num_unconfirmed_edits(&self) -> usize {
+        self.unconfirmed.len()
+    }
+
+    /// The unconfirmed voxels in the chunks at `chunk_mins`, in the order they were edited.
+    fn unconfirmed_deltas(&self, chunk_mins: &HashSet<Point3i>) -> Vec<ChunkDelta> {
+        self.unconfirmed
+            .iter()
+            .flat_map(|(_, deltas)| deltas.iter())
+            .filter(|delta| chunk_mins.contains(&delta.chunk_min))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns false once the host has gone away.
+    fn update(
+        &mut self,
+        edited_voxels: Vec<ChunkDelta>,
+        map: &VoxelMap,
+        backbuffer: &mut EditedChunksBackBuffer,
+    ) -> bool {
+        let messages = match self.connection.receive(map.chunk_shape()) {
+            Ok(m) => m,
+            Err(e) => {
+                log::error!("Lost connection to the edit session host: {:?}", e);
+                return false;
+            }
+        };
+        for message in messages.into_iter() {
+            match message {
+                NetMessage::Welcome { source } => {
+                    self.source = Some(source);
+                    self.welcome_chunks = Some(Vec::new());
+                }
+                NetMessage::MapChunks(chunks) => match self.welcome_chunks.as_mut() {
+                    Some(welcome_chunks) => welcome_chunks.extend(chunks),
+                    None => log::warn!("Received map chunks before being welcomed"),
+                },
+                NetMessage::MapComplete => {
+                    let chunks = self.welcome_chunks.take().unwrap_or_default();
+                    log::info!(
+                        "Welcomed to the edit session as {:?} with {} chunks",
+                        self.source,
+                        chunks.len()
+                    );
+                    let chunks = decompress_chunks(chunks, map.chunk_shape());
+                    let chunk_mins = chunks.iter().map(|(chunk_min, _)| *chunk_min).collect();
+                    replace_map_chunks(chunks, map, backbuffer);
+                    let local_cache = LocalChunkCache3::new();
+                    backbuffer.receive_voxels(
+                        &map.voxels.reader(&local_cache),
+                        self.unconfirmed_deltas(&chunk_mins),
+                    );
+                }
+                NetMessage::Chunks { stamps, chunks } => {
+                    let source = self.source;
+                    self.unconfirmed.retain(|(sequence, _)| {
+                        !stamps.iter().any(|stamp| {
+                            Some(stamp.source) == source && stamp.sequence == *sequence
+                        })
+                    });
+                    let chunks = decompress_chunks(chunks, map.chunk_shape());
+                    let chunk_mins = chunks.iter().map(|(chunk_min, _)| *chunk_min).collect();
+                    let local_cache = LocalChunkCache3::new();
+                    let reader = map.voxels.reader(&local_cache);
+                    backbuffer.receive_chunks(&reader, chunks);
+                    backbuffer.receive_voxels(&reader, self.unconfirmed_deltas(&chunk_mins));
+                }
+                other => log::warn!("Unexpected message from the host: {:?}", other),
+            }
+        }
+
+        if !edited_voxels.is_empty() {
+            let sequence = self.next_sequence;
+            let message = NetMessage::RequestEdits {
+                sequence,
+                deltas: edited_voxels.clone(),
+            };
+            match self.connection.send(&message) {
+                Ok(()) => {
+                    self.unconfirmed.push((sequence, edited_voxels));
+                    self.next_sequence += 1;
+                }
+                Err(e) => log::warn!("Failed to send edited voxels to the host: {:?}", e),
+            }
+        }
+        if let Err(e) = self.connection.flush() {
+            log::error!("Lost connection to the edit session host: {:?}", e);
+            return false;
+        }
+
+        true
+    }
+}
+
+/// Loads the host's chunks and unloads any of ours that the host doesn't have.
+fn replace_map_chunks(
+    chunks: Vec<(Point3i, Array3x1<Voxel>)>,
+    map: &VoxelMap,
+    backbuffer: &mut EditedChunksBackBuffer,
+) {
+    let host_chunk_mins: HashSet<Point3i> = chunks.iter().map(|(min, _)| *min).collect();
+    let stale_chunk_mins = morton_ordered_chunk_mins(map)
+        .into_iter()
+        .filter(|chunk_min| !host_chunk_mins.contains(chunk_min))
+        .collect();
+
+    let local_cache = LocalChunkCache3::new();
+    let reader = map.voxels.reader(&local_cache);
+    backbuffer.unload_chunks(&reader, stale_chunk_mins);
+    backbuffer.receive_chunks(&reader, chunks);
+}
+
+/// Shares edits with the rest of the `EditSession`, if there is one. Chunks from the other editors
+/// are written to the `EditedChunksBackBuffer`, so they go through the same pipeline as local
+/// edits.
+pub struct NetworkEditSystem;
+
+impl<'a> System<'a> for NetworkEditSystem {
+    type SystemData = (
+        Write<'a, Option<EditSession>>,
+        Write<'a, NetworkChunkEdits>,
+        ReadExpect<'a, VoxelMap>,
+        WriteExpect<'a, EditedChunksBackBuffer>,
+    );
+
+    fn run(&mut self, (mut session, mut network, map, mut backbuffer): Self::SystemData) {
+        let outgoing = std::mem::replace(&mut network.outgoing, Vec::new());
+        let outgoing_deltas = std::mem::replace(&mut network.outgoing_deltas, Vec::new());
+        let connected = match session.as_mut() {
+            Some(session) => match &mut session.role {
+                EditSessionRole::Host(host) => {
+                    network.role = Some(NetworkRole::Host);
+                    host.update(outgoing, &map, &mut backbuffer);
+
+                    true
+                }
+                EditSessionRole::Client(client) => {
+                    network.role = Some(NetworkRole::Client);
+                    client.update(outgoing_deltas, &map, &mut backbuffer)
+                }
+            },
+            None => {
+                network.role = None;
+
+                return;
+            }
+        };
+        if !connected {
+            *session = None;
+            network.role = None;
+        }
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        test_util::{test_palette, VoxelPipelineHarness},
+        voxel::{VoxelDistance, VoxelType, EMPTY_VOXEL, VOXEL_CHUNK_SHAPE},
+    };
+
+    const SOLID: Voxel = Voxel {
+        voxel_type: VoxelType(1),
+        distance: VoxelDistance(-1),
+    };
+
+    fn empty_chunk(chunk_min: Point3i) -> Array3x1<Voxel> {
+        Array3x1::fill(
+            Extent3i::from_min_and_shape(chunk_min, VOXEL_CHUNK_SHAPE),
+            EMPTY_VOXEL,
+        )
+    }
+
+    fn decode_all(bytes: &[u8]) -> Vec<bincode::Result<NetMessage>> {
+        let mut received = bytes.to_vec();
+        let mut messages = Vec::new();
+        while let Some(message) = decode_message(&mut received, VOXEL_CHUNK_SHAPE) {
+            messages.push(message);
+        }
+
+        messages
+    }
+
+    #[test]
+    fn test_messages_are_decoded_once_fully_received() {
+        let chunk_min = PointN([0, 16, 0]);
+        let chunks = NetMessage::Chunks {
+            stamps: vec![EditStamp {
+                source: EditSourceId(2),
+                sequence: 7,
+            }],
+            chunks: vec![(
+                chunk_min,
+                compress_chunk(&empty_chunk(chunk_min), None).unwrap(),
+            )],
+        };
+        let request = NetMessage::RequestEdits {
+            sequence: 3,
+            deltas: vec![ChunkDelta {
+                chunk_min: PointN([0; 3]),
+                voxels: vec![(PointN([1, 2, 3]), SOLID)],
+            }],
+        };
+        let mut bytes = Vec::new();
+        encode_message(&chunks, &mut bytes).unwrap();
+        encode_message(&request, &mut bytes).unwrap();
+
+        // Deliver the bytes in two pieces, splitting the first message.
+        let mut received = bytes[..6].to_vec();
+        assert!(decode_message(&mut received, VOXEL_CHUNK_SHAPE).is_none());
+        received.extend_from_slice(&bytes[6..]);
+        assert_eq!(
+            decode_message(&mut received, VOXEL_CHUNK_SHAPE)
+                .unwrap()
+                .unwrap(),
+            chunks
+        );
+        assert_eq!(
+            decode_message(&mut received, VOXEL_CHUNK_SHAPE)
+                .unwrap()
+                .unwrap(),
+            request
+        );
+        assert!(received.is_empty());
+        assert!(decode_message(&mut received, VOXEL_CHUNK_SHAPE).is_none());
+    }
+
+    #[test]
+    fn test_oversized_messages_are_refused_before_they_arrive() {
+        let mut received = ((MAX_MESSAGE_BYTES + 1) as u32).to_le_bytes().to_vec();
+        assert!(matches!(
+            decode_message(&mut received, VOXEL_CHUNK_SHAPE),
+            Some(Err(_))
+        ));
+    }
+
+    #[test]
+    fn test_malformed_messages_are_refused() {
+        let aligned_min = PointN([0, 16, 0]);
+        let unaligned_min = PointN([0, 15, 0]);
+        let chunk = compress_chunk(&empty_chunk(aligned_min), None).unwrap();
+        let small_chunk = compress_chunk(
+            &Array3x1::fill(
+                Extent3i::from_min_and_shape(aligned_min, PointN([8; 3])),
+                EMPTY_VOXEL,
+            ),
+            None,
+        )
+        .unwrap();
+        // Claims to decompress to 2 GiB.
+        let mut huge_chunk = chunk.clone();
+        huge_chunk[..LZ4_SIZE_PREFIX_BYTES].copy_from_slice(&i32::MAX.to_le_bytes());
+
+        let mut bytes = Vec::new();
+        for message in vec![
+            NetMessage::MapChunks(vec![(unaligned_min, chunk.clone())]),
+            NetMessage::MapChunks(vec![(aligned_min, small_chunk)]),
+            NetMessage::Chunks {
+                stamps: Vec::new(),
+                chunks: vec![(aligned_min, huge_chunk)],
+            },
+            NetMessage::MapChunks(vec![(aligned_min, vec![1, 2])]),
+            NetMessage::RequestEdits {
+                sequence: 0,
+                deltas: vec![ChunkDelta {
+                    chunk_min: unaligned_min,
+                    voxels: Vec::new(),
+                }],
+            },
+            NetMessage::RequestEdits {
+                sequence: 0,
+                deltas: vec![ChunkDelta {
+                    chunk_min: aligned_min,
+                    voxels: vec![(PointN([0; 3]), SOLID)],
+                }],
+            },
+        ]
+        .iter()
+        {
+            encode_message(message, &mut bytes).unwrap();
+        }
+        let messages = decode_all(&bytes);
+        assert_eq!(messages.len(), 6);
+        assert!(messages.iter().all(|m| m.is_err()));
+
+        // The same chunks are also skipped by `decompress_chunks`.
+        let decompressed = decompress_chunks(
+            vec![(unaligned_min, chunk.clone()), (aligned_min, chunk)],
+            VOXEL_CHUNK_SHAPE,
+        );
+        assert_eq!(decompressed.len(), 1);
+        assert_eq!(decompressed[0].0, aligned_min);
+    }
+
+    #[test]
+    fn test_concurrent_edits_to_the_same_chunk_are_all_kept() {
+        let mut host = VoxelPipelineHarness::new(VoxelMap::new(test_palette()));
+        let session = EditSession::host("127.0.0.1:0").unwrap();
+        let address = match session.role() {
+            EditSessionRole::Host(host) => host.local_addr().unwrap(),
+            EditSessionRole::Client(_) => unreachable!(),
+        };
+        *host.world.write_resource::<Option<EditSession>>() = Some(session);
+
+        let mut clients = Vec::new();
+        for _ in 0..2 {
+            let mut client = VoxelPipelineHarness::new(VoxelMap::new(test_palette()));
+            *client.world.write_resource::<Option<EditSession>>() =
+                Some(EditSession::connect(address).unwrap());
+            clients.push(client);
+        }
+        let is_welcomed = |client: &VoxelPipelineHarness| match client
+            .world
+            .read_resource::<Option<EditSession>>()
+            .as_ref()
+        {
+            Some(EditSession {
+                role: EditSessionRole::Client(client),
+            }) => client.source().is_some(),
+            _ => false,
+        };
+        for _ in 0..1000 {
+            host.step();
+            for client in clients.iter_mut() {
+                client.step();
+            }
+            if clients.iter().all(is_welcomed) {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert!(clients.iter().all(is_welcomed));
+
+        // Each client edits a different voxel of the same chunk on the same frame.
+        let edited = [PointN([2; 3]), PointN([12; 3])];
+        for (client, p) in clients.iter_mut().zip(edited.iter()) {
+            client.queue_edit(Extent3i::from_min_and_shape(*p, PointN([1; 3])), |_p, v| {
+                *v = SOLID
+            });
+        }
+        let has_both_edits =
+            |harness: &VoxelPipelineHarness| edited.iter().all(|p| harness.voxel(*p) == SOLID);
+        for _ in 0..1000 {
+            host.step();
+            for client in clients.iter_mut() {
+                client.step();
+            }
+            if has_both_edits(&host) && clients.iter().all(has_both_edits) {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        assert!(has_both_edits(&host));
+        for client in clients.iter() {
+            assert!(has_both_edits(client));
+        }
+    }
+}
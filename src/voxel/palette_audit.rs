@@ -0,0 +1,251 @@
+//! Keeps long-lived palettes tidy: count how many voxels use each palette entry, and drop unused
+//! entries by renumbering the voxel types that remain. Entries are also used by things other than
+//! the stored voxels, like generators and the editor's hotbar, so those references are added to the
+//! `PaletteUsage` before anything is dropped.
+
+use crate::voxel::{morton::morton_ordered_chunk_mins, Voxel, VoxelMap, VoxelType, EMPTY_VOXEL};
+
+use building_blocks::prelude::*;
+use rayon::prelude::*;
+
+/// How many voxels in the map use each palette entry.
+#[derive(Clone, Debug, Default)]
+pub struct PaletteUsage {
+    /// Indexed by voxel type. Has one entry per palette entry.
+    pub counts: Vec<usize>,
+    /// Voxels whose type doesn't index into the palette.
+    pub out_of_range: usize,
+    /// Entries that are referenced by something other than the stored voxels; see
+    /// `add_references`.
+    pub referenced: Vec<VoxelType>,
+}
+
+impl PaletteUsage {
+    /// Palette entries that no voxel uses and nothing references. The empty type is never reported,
+    /// since it's always needed for ambient space.
+    pub fn unused(&self) -> Vec<VoxelType> {
+        self.counts
+            .iter()
+            .enumerate()
+            .map(|(i, &count)| (VoxelType(i as u8), count))
+            .filter(|(voxel_type, count)| {
+                *voxel_type != EMPTY_VOXEL.voxel_type
+                    && *count == 0
+                    && !self.referenced.contains(voxel_type)
+            })
+            .map(|(voxel_type, _)| voxel_type)
+            .collect()
+    }
+
+    /// Keeps `voxel_types` in the palette, e.g. the types placed by the map's generators or
+    /// selected by the hotbar, even if no stored voxel uses them.
+    pub fn add_references(&mut self, voxel_types: impl IntoIterator<Item = VoxelType>) {
+        for voxel_type in voxel_types {
+            if !self.referenced.contains(&voxel_type) {
+                self.referenced.push(voxel_type);
+            }
+        }
+    }
+
+    fn add_chunk(&mut self, chunk_counts: &[usize; 256]) {
+        for (i, &count) in chunk_counts.iter().enumerate() {
+            if let Some(c) = self.counts.get_mut(i) {
+                *c += count;
+            } else {
+                self.out_of_range += count;
+            }
+        }
+    }
+}
+
+fn count_chunk(chunk: &Array3x1<Voxel>) -> [usize; 256] {
+    let mut counts = [0; 256];
+    chunk.for_each(chunk.extent(), |_p: Point3i, v: Voxel| {
+        counts[v.voxel_type.0 as usize] += 1;
+    });
+
+    counts
+}
+
+/// Counts palette usage over every stored chunk. Chunks that aren't stored are ambient space, so
+/// they only use the empty type, and they aren't counted.
+pub fn palette_usage(map: &VoxelMap) -> PaletteUsage {
+    let chunk_counts: Vec<[usize; 256]> = morton_ordered_chunk_mins(map)
+        .into_par_iter()
+        .filter_map(|chunk_min| {
+            let local_cache = LocalChunkCache3::new();
+            let reader = map.voxels.reader(&local_cache);
+
+            reader
+                .get_chunk(ChunkKey::new(0, chunk_min))
+                .map(count_chunk)
+        })
+        .collect();
+
+    let mut usage = PaletteUsage {
+        counts: vec![0; map.palette.infos.len()],
+        out_of_range: 0,
+        referenced: Vec::new(),
+    };
+    for counts in chunk_counts.iter() {
+        usage.add_chunk(counts);
+    }
+
+    usage
+}
+
+/// Maps old voxel types to their new positions in the palette.
+#[derive(Clone, Debug)]
+pub struct PaletteRemap {
+    /// Indexed by old voxel type. `None` means the entry is removed.
+    new_types: Vec<Option<VoxelType>>,
+}
+
+impl PaletteRemap {
+    /// Leaves all `num_entries` entries where they are.
+    pub fn identity(num_entries: usize) -> Self {
+        Self {
+            new_types: (0..num_entries).map(|i| Some(VoxelType(i as u8))).collect(),
+        }
+    }
+
+    /// Removes every unused entry, keeping the remaining entries in their original order. The empty
+    /// type always stays at index 0.
+    ///
+    /// Returns `None` if any voxel has a type outside of the palette. Those voxels can't be
+    /// renumbered, and they would point at other entries once the palette shrinks, so they need to
+    /// be fixed first; see `validation`.
+    pub fn compacting(usage: &PaletteUsage) -> Option<Self> {
+        if usage.out_of_range > 0 {
+            return None;
+        }
+
+        let unused = usage.unused();
+        let mut next_type = 0;
+        let new_types = (0..usage.counts.len())
+            .map(|i| {
+                let old_type = VoxelType(i as u8);
+                if unused.contains(&old_type) {
+                    None
+                } else {
+                    next_type += 1;
+                    Some(VoxelType(next_type - 1))
+                }
+            })
+            .collect();
+
+        Some(Self { new_types })
+    }
+
+    pub fn get(&self, old_type: VoxelType) -> Option<VoxelType> {
+        self.new_types.get(old_type.0 as usize).copied().flatten()
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.iter_changed().next().is_none()
+    }
+
+    /// The entries that move or get removed, as (old, new) pairs.
+    pub fn iter_changed(&self) -> impl Iterator<Item = (VoxelType, Option<VoxelType>)> + '_ {
+        self.new_types
+            .iter()
+            .enumerate()
+            .map(|(i, &new_type)| (VoxelType(i as u8), new_type))
+            .filter(|(old_type, new_type)| *new_type != Some(*old_type))
+    }
+
+    /// Voxels whose entry is removed become empty space, keeping their distance if they were
+    /// already empty. Returns whether any voxel changed.
+    fn remap_chunk(&self, chunk: &mut Array3x1<Voxel>) -> bool {
+        let mut changed = false;
+        let extent = *chunk.extent();
+        chunk.for_each_mut(&extent, |_p: Point3i, v: &mut Voxel| {
+            let old = *v;
+            match self.get(v.voxel_type) {
+                Some(new_type) => v.voxel_type = new_type,
+                None if v.distance.0 >= 0 => v.voxel_type = EMPTY_VOXEL.voxel_type,
+                None => *v = EMPTY_VOXEL,
+            }
+            changed |= *v != old;
+        });
+
+        changed
+    }
+}
+
+/// Rewrites every stored voxel and the palette itself according to `remap`.
+pub fn remap_palette(map: &mut VoxelMap, remap: &PaletteRemap) {
+    let remapped_chunks: Vec<(Point3i, Array3x1<Voxel>)> = morton_ordered_chunk_mins(map)
+        .into_par_iter()
+        .filter_map(|chunk_min| {
+            let local_cache = LocalChunkCache3::new();
+            let reader = map.voxels.reader(&local_cache);
+            let mut chunk = reader.get_chunk(ChunkKey::new(0, chunk_min))?.clone();
+
+            if remap.remap_chunk(&mut chunk) {
+                Some((chunk_min, chunk))
+            } else {
+                None
+            }
+        })
+        .collect();
+    for (chunk_min, chunk) in remapped_chunks.into_iter() {
+        map.voxels.write_chunk(ChunkKey::new(0, chunk_min), chunk);
+    }
+
+    let old_infos = std::mem::take(&mut map.palette.infos);
+    map.palette.infos = old_infos
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| remap.get(VoxelType(*i as u8)).is_some())
+        .map(|(_, info)| info)
+        .collect();
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::voxel::VoxelDistance;
+
+    #[test]
+    fn test_compact_unused_entries() {
+        let extent = Extent3i::from_min_and_shape(PointN([0; 3]), PointN([2; 3]));
+        let mut chunk = Array3x1::fill(extent, EMPTY_VOXEL);
+        let solid = |t| Voxel {
+            voxel_type: VoxelType(t),
+            distance: VoxelDistance(-5),
+        };
+        *chunk.get_mut(PointN([0, 0, 0])) = solid(2);
+        *chunk.get_mut(PointN([1, 0, 0])) = solid(4);
+
+        let mut usage = PaletteUsage {
+            counts: vec![0; 5],
+            out_of_range: 0,
+            referenced: Vec::new(),
+        };
+        usage.add_chunk(&count_chunk(&chunk));
+        assert_eq!(usage.counts, vec![6, 0, 1, 0, 1]);
+        assert_eq!(usage.unused(), vec![VoxelType(1), VoxelType(3)]);
+        usage.add_references(vec![VoxelType(3)]);
+        assert_eq!(usage.unused(), vec![VoxelType(1)]);
+        usage.referenced.clear();
+
+        let remap = PaletteRemap::compacting(&usage).unwrap();
+        assert!(remap.remap_chunk(&mut chunk));
+        assert_eq!(chunk.get(PointN([0, 0, 0])), solid(1));
+        assert_eq!(chunk.get(PointN([1, 0, 0])), solid(2));
+        assert_eq!(chunk.get(PointN([0, 1, 0])), EMPTY_VOXEL);
+
+        // Voxels outside of the palette would end up pointing at other entries.
+        usage.out_of_range = 1;
+        assert!(PaletteRemap::compacting(&usage).is_none());
+    }
+}
@@ -0,0 +1,255 @@
+//! Prefab entities placed in the map, like trees, crates or doors. Props are stored in the map file
+//! by the name of their prefab, and the `PropSpawnSystem` turns them into entities from the
+//! prefabs in "assets/props".
+
+use amethyst::{
+    assets::{Handle, Prefab, PrefabLoader, RonFormat},
+    core::{ecs::prelude::*, math::Vector3, Transform},
+    utils::application_dir,
+};
+use building_blocks::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Prop {
+    /// The file stem of the prefab in "assets/props".
+    pub prefab: String,
+    /// The voxel that the prop stands in. It's placed at the center of the voxel's bottom face.
+    pub position: [i32; 3],
+    /// Rotation about the Y axis.
+    #[serde(default)]
+    pub yaw_degrees: f32,
+}
+
+impl Prop {
+    pub fn new(prefab: String, position: Point3i) -> Self {
+        Self {
+            prefab,
+            position: position.0,
+            yaw_degrees: 0.0,
+        }
+    }
+
+    pub fn position(&self) -> Point3i {
+        PointN(self.position)
+    }
+
+    pub fn transform(&self) -> Transform {
+        let mut tfm = Transform::default();
+        let base = Point3f::from(self.position());
+        *tfm.translation_mut() = Vector3::new(base.x() + 0.5, base.y(), base.z() + 0.5);
+        tfm.set_rotation_y_axis(self.yaw_degrees.to_radians());
+
+        tfm
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MapProps {
+    props: Vec<Prop>,
+    /// Set whenever the props change, so they can be saved with the map.
+    changed: bool,
+    /// Counts the props being added or removed, so their entities can be spawned again.
+    revision: u64,
+    /// Counts the props being moved or rotated, so their entities can follow.
+    pose_revision: u64,
+}
+
+impl MapProps {
+    pub fn new(props: Vec<Prop>) -> Self {
+        Self {
+            props,
+            changed: false,
+            revision: 0,
+            pose_revision: 0,
+        }
+    }
+
+    pub fn add(&mut self, prop: Prop) {
+        self.props.push(prop);
+        self.changed = true;
+        self.revision += 1;
+    }
+
+    pub fn remove(&mut self, index: usize) -> Prop {
+        self.changed = true;
+        self.revision += 1;
+
+        self.props.remove(index)
+    }
+
+    /// The index of the prop closest to `p`, as long as it's within `radius` voxels.
+    pub fn nearest(&self, p: Point3i, radius: f32) -> Option<usize> {
+        let (i, dist) = self
+            .props
+            .iter()
+            .enumerate()
+            .map(|(i, prop)| (i, (prop.position() - p).norm()))
+            .min_by(|(_, d1), (_, d2)| d1.partial_cmp(d2).unwrap())?;
+
+        if dist <= radius {
+            Some(i)
+        } else {
+            None
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Prop> {
+        self.props.get(index)
+    }
+
+    pub fn set_position(&mut self, index: usize, p: Point3i) {
+        if let Some(prop) = self.props.get_mut(index) {
+            if prop.position != p.0 {
+                prop.position = p.0;
+                self.changed = true;
+                self.pose_revision += 1;
+            }
+        }
+    }
+
+    pub fn rotate(&mut self, index: usize, degrees: f32) {
+        if let Some(prop) = self.props.get_mut(index) {
+            prop.yaw_degrees = (prop.yaw_degrees + degrees).rem_euclid(360.0);
+            self.changed = true;
+            self.pose_revision += 1;
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Prop> {
+        self.props.iter()
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn pose_revision(&self) -> u64 {
+        self.pose_revision
+    }
+
+    pub fn has_changed(&self) -> bool {
+        self.changed
+    }
+}
+
+/// The index in `MapProps` of the prop that an entity was spawned for.
+pub struct PropInstance(pub usize);
+
+impl Component for PropInstance {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Spawns an entity with a `Handle<Prefab<T>>` for each of the `MapProps`, and resets their
+/// `Transform`s when props are moved. Whenever props are added or removed, all of the prop entities
+/// are spawned again. The `PrefabLoaderSystem` for `T` needs to run as well.
+pub struct PropSpawnSystem<T> {
+    spawned_revision: Option<u64>,
+    synced_pose_revision: u64,
+    handles: HashMap<String, Handle<Prefab<T>>>,
+    marker: PhantomData<T>,
+}
+
+impl<T> Default for PropSpawnSystem<T> {
+    fn default() -> Self {
+        Self {
+            spawned_revision: None,
+            synced_pose_revision: 0,
+            handles: HashMap::new(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, T> System<'a> for PropSpawnSystem<T>
+where
+    T: for<'de> Deserialize<'de> + Send + Sync + 'static,
+{
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        Read<'a, MapProps>,
+        Entities<'a>,
+        PrefabLoader<'a, T>,
+        WriteStorage<'a, Handle<Prefab<T>>>,
+        WriteStorage<'a, Transform>,
+        WriteStorage<'a, PropInstance>,
+    );
+
+    fn run(
+        &mut self,
+        (props, entities, loader, mut prefabs, mut transforms, mut instances): Self::SystemData,
+    ) {
+        if self.spawned_revision != Some(props.revision()) {
+            self.spawned_revision = Some(props.revision());
+            self.synced_pose_revision = props.pose_revision();
+
+            for (e, _) in (&entities, &instances).join() {
+                entities.delete(e).unwrap();
+            }
+            let props_dir = application_dir("assets/props").expect("Failed to get props dir.");
+            for (i, prop) in props.iter().enumerate() {
+                let handle = self
+                    .handles
+                    .entry(prop.prefab.clone())
+                    .or_insert_with(|| {
+                        let path = props_dir.join(&prop.prefab).with_extension("ron");
+                        loader.load(path.to_str().unwrap(), RonFormat, ())
+                    })
+                    .clone();
+                entities
+                    .build_entity()
+                    .with(handle, &mut prefabs)
+                    .with(prop.transform(), &mut transforms)
+                    .with(PropInstance(i), &mut instances)
+                    .build();
+            }
+
+            return;
+        }
+
+        // Leave the transforms alone unless a prop was moved, so games can move the entities too.
+        if self.synced_pose_revision == props.pose_revision() {
+            return;
+        }
+        self.synced_pose_revision = props.pose_revision();
+        for (instance, tfm) in (&instances, &mut transforms).join() {
+            if let Some(prop) = props.get(instance.0) {
+                *tfm = prop.transform();
+            }
+        }
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_props_only_respawn_when_added_or_removed() {
+        let mut props = MapProps::default();
+        props.add(Prop::new("crate".to_string(), PointN([0, 1, 0])));
+        props.add(Prop::new("tree".to_string(), PointN([10, 1, 0])));
+        let revision = props.revision();
+
+        let i = props.nearest(PointN([9, 1, 0]), 1.5).unwrap();
+        props.set_position(i, PointN([9, 2, 0]));
+        props.rotate(i, -90.0);
+        assert_eq!(props.revision(), revision);
+        assert_eq!(props.pose_revision(), 2);
+        assert_eq!(props.get(i).unwrap().yaw_degrees, 270.0);
+        assert_eq!(props.nearest(PointN([5, 1, 0]), 1.5), None);
+
+        assert_eq!(props.remove(i).position(), PointN([9, 2, 0]));
+        assert!(props.revision() > revision);
+        assert!(props.has_changed());
+    }
+}
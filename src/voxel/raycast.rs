@@ -0,0 +1,154 @@
+use super::{world_to_voxel_space, VoxelMap};
+
+use building_blocks::prelude::*;
+use ncollide3d::query::Ray;
+
+/// The first solid voxel hit by a ray cast with `VoxelMap::cast_ray`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VoxelRayHit {
+    pub point: Point3i,
+    /// Where the ray enters the voxel, in multiples of the ray direction.
+    pub toi: f32,
+    /// The normal of the face that the ray entered through, or zero if the ray started inside of
+    /// the voxel.
+    pub normal: Point3i,
+}
+
+impl VoxelMap {
+    /// Finds the first solid voxel along `ray` by stepping through the voxels of the map directly,
+    /// so it works without a `VoxelBVT`. Each step reads a voxel, so keep `max_toi` small.
+    pub fn cast_ray(&self, ray: &Ray<f32>, max_toi: f32) -> Option<VoxelRayHit> {
+        let ray = voxel_space_ray(ray, self.world_scale);
+        let local_cache = LocalChunkCache3::new();
+        let reader = self.voxels.reader(&local_cache);
+        let view = reader.lod_view(0);
+
+        traverse_voxels_on_ray(
+            [ray.origin.x, ray.origin.y, ray.origin.z],
+            [ray.dir.x, ray.dir.y, ray.dir.z],
+            max_toi,
+            |p| {
+                !self
+                    .palette
+                    .get_voxel_type_info(view.get(p).voxel_type)
+                    .flags
+                    .is_empty
+            },
+        )
+    }
+}
+
+/// Converts a ray in world units to voxel units, e.g. to cast it at a `VoxelBVT`. The ray's
+/// direction is scaled along with its origin, so the time of impact doesn't change. `world_scale`
+/// is the map's `VoxelMap::world_scale`.
+pub fn voxel_space_ray(ray: &Ray<f32>, world_scale: f32) -> Ray<f32> {
+    Ray::new(
+        world_to_voxel_space(ray.origin, world_scale),
+        ray.dir / world_scale,
+    )
+}
+
+/// Visits the voxels that the ray passes through in order, until `is_hit` returns true or the ray
+/// goes past `max_toi`. This is the traversal from "A Fast Voxel Traversal Algorithm for Ray
+/// Tracing" by Amanatides and Woo.
+pub fn traverse_voxels_on_ray(
+    origin: [f32; 3],
+    dir: [f32; 3],
+    max_toi: f32,
+    mut is_hit: impl FnMut(Point3i) -> bool,
+) -> Option<VoxelRayHit> {
+    let mut voxel = [0; 3];
+    let mut step = [0; 3];
+    // The toi where the ray crosses the next voxel boundary on each axis.
+    let mut t_max = [std::f32::INFINITY; 3];
+    // The toi between voxel boundaries on each axis.
+    let mut t_delta = [std::f32::INFINITY; 3];
+    for i in 0..3 {
+        voxel[i] = origin[i].floor() as i32;
+        if dir[i] > 0.0 {
+            step[i] = 1;
+            t_delta[i] = 1.0 / dir[i];
+            t_max[i] = (voxel[i] as f32 + 1.0 - origin[i]) / dir[i];
+        } else if dir[i] < 0.0 {
+            step[i] = -1;
+            t_delta[i] = -1.0 / dir[i];
+            t_max[i] = (voxel[i] as f32 - origin[i]) / dir[i];
+        }
+    }
+
+    let mut toi = 0.0;
+    let mut normal = [0; 3];
+    loop {
+        let point = PointN(voxel);
+        if is_hit(point) {
+            return Some(VoxelRayHit {
+                point,
+                toi,
+                normal: PointN(normal),
+            });
+        }
+
+        let axis = if t_max[0] < t_max[1] {
+            if t_max[0] < t_max[2] {
+                0
+            } else {
+                2
+            }
+        } else if t_max[1] < t_max[2] {
+            1
+        } else {
+            2
+        };
+        toi = t_max[axis];
+        if toi > max_toi {
+            return None;
+        }
+        voxel[axis] += step[axis];
+        t_max[axis] += t_delta[axis];
+        normal = [0; 3];
+        normal[axis] = -step[axis];
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diagonal_ray_hits_face_of_voxel() {
+        let target = PointN([3, 1, 0]);
+        let mut visited = Vec::new();
+        let hit = traverse_voxels_on_ray([0.5, 0.5, 0.5], [1.0, 0.25, 0.0], 10.0, |p| {
+            visited.push(p);
+
+            p == target
+        })
+        .unwrap();
+
+        assert_eq!(
+            visited,
+            vec![
+                PointN([0, 0, 0]),
+                PointN([1, 0, 0]),
+                PointN([2, 0, 0]),
+                PointN([2, 1, 0]),
+                PointN([3, 1, 0]),
+            ]
+        );
+        assert_eq!(hit.normal, PointN([-1, 0, 0]));
+        assert!((hit.toi - 2.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_ray_stops_at_max_toi() {
+        let hit = traverse_voxels_on_ray([0.5, 0.5, 0.5], [0.0, -1.0, 0.0], 5.0, |p| p.y() < -10);
+        assert!(hit.is_none());
+    }
+}
@@ -0,0 +1,117 @@
+//! Copies a region of a map into a new map of its own, e.g. to split a large map into tiles or to
+//! share a sample of it.
+
+use crate::voxel::{
+    empty_array,
+    palette_audit::{palette_usage, remap_palette, PaletteRemap},
+    Voxel, VoxelMap, EMPTY_VOXEL,
+};
+
+use building_blocks::prelude::*;
+use rayon::prelude::*;
+
+/// Copies the voxels of `map` in `extent` into a new map, translated by `offset`. Everything
+/// outside of the region is ambient space. The palette is compacted to the entries that the region
+/// uses; the returned remap says how the voxel types were renumbered.
+pub fn extract_region(
+    map: &VoxelMap,
+    extent: &Extent3i,
+    offset: Point3i,
+) -> (VoxelMap, PaletteRemap) {
+    let local_cache = LocalChunkCache3::new();
+    let reader = map.voxels.reader(&local_cache);
+    let mut region = empty_array(*extent);
+    copy_extent(extent, &reader.lod_view(0), &mut region);
+
+    let dst_extent = Extent3i::from_min_and_shape(extent.minimum + offset, extent.shape);
+    let indexer = ChunkIndexer::new(map.chunk_shape());
+    let chunk_mins: Vec<Point3i> = indexer.chunk_mins_for_extent(&dst_extent).collect();
+    let chunks: Vec<(Point3i, Array3x1<Voxel>)> = chunk_mins
+        .into_par_iter()
+        .filter_map(|chunk_min| {
+            let chunk_extent = indexer.extent_for_chunk_with_min(chunk_min);
+            let mut chunk = empty_array(chunk_extent);
+            let mut any_stored = false;
+            chunk.for_each_mut(
+                &dst_extent.intersection(&chunk_extent),
+                |p: Point3i, v: &mut Voxel| {
+                    *v = region.get(p - offset);
+                    any_stored |= *v != EMPTY_VOXEL;
+                },
+            );
+
+            // Chunks of ambient space don't need to be stored.
+            if any_stored {
+                Some((chunk_min, chunk))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    let mut region_map =
+        VoxelMap::with_chunk_shape(map.palette.clone(), map.codec, map.chunk_shape());
+    region_map.world_scale = map.world_scale;
+    for (chunk_min, chunk) in chunks.into_iter() {
+        region_map
+            .voxels
+            .write_chunk(ChunkKey::new(0, chunk_min), chunk);
+    }
+
+    let remap = PaletteRemap::compacting(&palette_usage(&region_map)).unwrap_or_else(|| {
+        log::warn!("The region has voxel types outside of the palette, so it isn't compacted");
+
+        PaletteRemap::identity(region_map.palette.infos.len())
+    });
+    remap_palette(&mut region_map, &remap);
+
+    (region_map, remap)
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        test_util::test_palette,
+        voxel::{VoxelDistance, VoxelType, VOXEL_CHUNK_SHAPE},
+    };
+
+    #[test]
+    fn test_extract_region_compacts_palette_and_translates() {
+        // A second solid type.
+        let mut palette = test_palette();
+        palette.infos.push(palette.infos[1].clone());
+        let mut map = VoxelMap::new(palette);
+        let solid = |t| Voxel {
+            voxel_type: VoxelType(t),
+            distance: VoxelDistance::from(-1.0),
+        };
+        let mut chunk = empty_array(Extent3i::from_min_and_shape(
+            PointN([0; 3]),
+            VOXEL_CHUNK_SHAPE,
+        ));
+        // Only the type 2 voxel is inside the region.
+        *chunk.get_mut(PointN([1, 1, 1])) = solid(1);
+        *chunk.get_mut(PointN([5, 5, 5])) = solid(2);
+        map.voxels
+            .write_chunk(ChunkKey::new(0, PointN([0; 3])), chunk);
+
+        let extent = Extent3i::from_min_and_shape(PointN([4; 3]), PointN([4; 3]));
+        let (region_map, remap) = extract_region(&map, &extent, PointN([-4; 3]));
+
+        assert_eq!(region_map.palette.infos.len(), 2);
+        assert_eq!(remap.get(VoxelType(2)), Some(VoxelType(1)));
+        let local_cache = LocalChunkCache3::new();
+        let reader = region_map.voxels.reader(&local_cache);
+        assert_eq!(reader.lod_view(0).get(PointN([1, 1, 1])), solid(1));
+        assert_eq!(reader.lod_view(0).get(PointN([5, 5, 5])), EMPTY_VOXEL);
+    }
+}
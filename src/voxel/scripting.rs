@@ -0,0 +1,276 @@
+//! Runs [Rhai](https://rhai.rs) scripts that read and edit the `VoxelMap`, e.g. to generate towers,
+//! roads or fractals. Only built with the "scripting" feature.
+//!
+//! Scripts can call these functions, with integer voxel coordinates:
+//!
+//! - `voxel_type(x, y, z)`: the type of the voxel, including any writes the script already made
+//! - `is_solid(x, y, z)`
+//! - `set_voxel(x, y, z, voxel_type)`: makes a single solid voxel
+//! - `clear_voxel(x, y, z)`: makes a voxel empty
+//! - `sphere(x, y, z, radius, voxel_type)`: adds a smooth solid ball
+//!
+//! The point that the script was run at is in the `origin_x`, `origin_y` and `origin_z` variables.
+//! All of the writes are queued in the `EditedChunksBackBuffer` as a single edit once the script
+//! finishes, so a script that fails doesn't change the map at all.
+
+use crate::voxel::{
+    double_buffer::EditedChunksBackBuffer, LocalVoxelCache, Voxel, VoxelDistance, VoxelMap,
+    VoxelType, EMPTY_VOXEL,
+};
+
+use building_blocks::prelude::*;
+use rhai::{Engine, EvalAltResult, Scope, INT};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+/// Keeps runaway loops from freezing the editor.
+const MAX_SCRIPT_OPERATIONS: u64 = 100_000_000;
+
+#[derive(Debug)]
+pub enum ScriptError {
+    IoError(io::Error),
+    EvalError(Box<EvalAltResult>),
+    InvalidVoxelType(INT),
+}
+
+impl From<io::Error> for ScriptError {
+    fn from(other: io::Error) -> Self {
+        ScriptError::IoError(other)
+    }
+}
+
+impl From<Box<EvalAltResult>> for ScriptError {
+    fn from(other: Box<EvalAltResult>) -> Self {
+        ScriptError::EvalError(other)
+    }
+}
+
+struct ScriptState {
+    map: VoxelMap,
+    local_cache: LocalVoxelCache,
+    writes: HashMap<Point3i, Voxel>,
+    invalid_type: Option<INT>,
+}
+
+impl ScriptState {
+    fn get(&self, p: Point3i) -> Voxel {
+        if let Some(v) = self.writes.get(&p) {
+            return *v;
+        }
+
+        self.map.voxels.reader(&self.local_cache).lod_view(0).get(p)
+    }
+
+    fn voxel_type(&mut self, t: INT) -> Option<VoxelType> {
+        if t < 0 || t as usize >= self.map.palette.infos.len() {
+            self.invalid_type.get_or_insert(t);
+
+            return None;
+        }
+
+        Some(VoxelType(t as u8))
+    }
+}
+
+fn point(x: INT, y: INT, z: INT) -> Point3i {
+    PointN([x as i32, y as i32, z as i32])
+}
+
+fn make_engine(state: &Rc<RefCell<ScriptState>>) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_SCRIPT_OPERATIONS);
+
+    let s = state.clone();
+    engine.register_fn("voxel_type", move |x: INT, y: INT, z: INT| {
+        s.borrow().get(point(x, y, z)).voxel_type.0 as INT
+    });
+    let s = state.clone();
+    engine.register_fn("is_solid", move |x: INT, y: INT, z: INT| {
+        s.borrow().get(point(x, y, z)).distance.0 < 0
+    });
+    let s = state.clone();
+    engine.register_fn("set_voxel", move |x: INT, y: INT, z: INT, t: INT| {
+        let mut s = s.borrow_mut();
+        if let Some(voxel_type) = s.voxel_type(t) {
+            s.writes.insert(
+                point(x, y, z),
+                Voxel {
+                    voxel_type,
+                    distance: VoxelDistance::from(-1.0),
+                },
+            );
+        }
+    });
+    let s = state.clone();
+    engine.register_fn("clear_voxel", move |x: INT, y: INT, z: INT| {
+        s.borrow_mut().writes.insert(point(x, y, z), EMPTY_VOXEL);
+    });
+    let s = state.clone();
+    engine.register_fn(
+        "sphere",
+        move |x: INT, y: INT, z: INT, radius: INT, t: INT| {
+            let mut s = s.borrow_mut();
+            if let Some(voxel_type) = s.voxel_type(t) {
+                add_sphere(&mut s, point(x, y, z), radius.max(0) as f32, voxel_type);
+            }
+        },
+    );
+
+    engine
+}
+
+fn add_sphere(state: &mut ScriptState, center: Point3i, radius: f32, voxel_type: VoxelType) {
+    // Pad the extent so the distances fade out smoothly around the surface.
+    let r = radius.ceil() as i32 + 2;
+    let extent = Extent3i::from_min_and_shape(center - PointN([r; 3]), PointN([2 * r + 1; 3]));
+    for p in extent.iter_points() {
+        let d = (p - center).norm() - radius;
+        let mut v = state.get(p);
+        let old_d: f32 = v.distance.into();
+        if d < old_d {
+            v.distance = VoxelDistance::from(d);
+            if d < 0.0 {
+                v.voxel_type = voxel_type;
+            }
+            state.writes.insert(p, v);
+        }
+    }
+}
+
+/// Runs `script` and queues its writes in the `backbuffer`. The `map` is only borrowed by the
+/// script while it runs. Returns the number of voxels written.
+pub fn run_voxel_script(
+    script: &str,
+    origin: Point3i,
+    map: &mut VoxelMap,
+    backbuffer: &mut EditedChunksBackBuffer,
+) -> Result<usize, ScriptError> {
+    // The script functions have to own everything they touch, so lend them the map.
+    let lent_map = std::mem::replace(map, VoxelMap::new(map.palette.clone()));
+    let state = Rc::new(RefCell::new(ScriptState {
+        map: lent_map,
+        local_cache: LocalChunkCache3::new(),
+        writes: HashMap::new(),
+        invalid_type: None,
+    }));
+
+    let result = {
+        let engine = make_engine(&state);
+        let mut scope = Scope::new();
+        scope.push("origin_x", origin.x() as INT);
+        scope.push("origin_y", origin.y() as INT);
+        scope.push("origin_z", origin.z() as INT);
+
+        engine.consume_with_scope(&mut scope, script)
+    };
+
+    let ScriptState {
+        map: lent_map,
+        writes,
+        invalid_type,
+        ..
+    } = match Rc::try_unwrap(state) {
+        Ok(state) => state.into_inner(),
+        Err(_) => panic!("Script engine outlived the script"),
+    };
+    *map = lent_map;
+
+    result?;
+    if let Some(t) = invalid_type {
+        return Err(ScriptError::InvalidVoxelType(t));
+    }
+    if writes.is_empty() {
+        return Ok(0);
+    }
+
+    let mut min = [i32::MAX; 3];
+    let mut max = [i32::MIN; 3];
+    for p in writes.keys() {
+        for i in 0..3 {
+            min[i] = min[i].min(p.0[i]);
+            max[i] = max[i].max(p.0[i]);
+        }
+    }
+    let extent = Extent3i::from_min_and_max(PointN(min), PointN(max));
+    let local_cache = LocalChunkCache3::new();
+    let reader = map.voxels.reader(&local_cache);
+    backbuffer.edit_voxels_out_of_place(&reader, &extent, |p: Point3i, v: &mut Voxel| {
+        if let Some(w) = writes.get(&p) {
+            *v = *w;
+        }
+    });
+
+    Ok(writes.len())
+}
+
+/// Reads the script at `path` and runs it with `run_voxel_script`.
+pub fn run_voxel_script_file(
+    path: impl AsRef<Path>,
+    origin: Point3i,
+    map: &mut VoxelMap,
+    backbuffer: &mut EditedChunksBackBuffer,
+) -> Result<usize, ScriptError> {
+    let script = std::fs::read_to_string(path)?;
+
+    run_voxel_script(&script, origin, map, backbuffer)
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::{test_palette, VoxelPipelineHarness};
+
+    use amethyst::core::ecs::prelude::*;
+
+    #[test]
+    fn test_script_builds_tower_above_origin() {
+        let mut harness = VoxelPipelineHarness::new(VoxelMap::new(test_palette()));
+        let script = r#"
+            for y in range(0, 5) {
+                set_voxel(origin_x, origin_y + y, origin_z, 1);
+            }
+            // Reads see the script's own writes.
+            if is_solid(origin_x, origin_y + 4, origin_z) {
+                clear_voxel(origin_x, origin_y, origin_z);
+            }
+        "#;
+        let num_written = harness.world.exec(
+            |(mut map, mut backbuffer): (
+                WriteExpect<VoxelMap>,
+                WriteExpect<EditedChunksBackBuffer>,
+            )| {
+                run_voxel_script(script, PointN([2, 3, 4]), &mut map, &mut backbuffer).unwrap()
+            },
+        );
+        harness.step();
+
+        assert_eq!(num_written, 5);
+        assert_eq!(harness.voxel(PointN([2, 3, 4])), EMPTY_VOXEL);
+        assert_eq!(harness.voxel(PointN([2, 7, 4])).voxel_type, VoxelType(1));
+    }
+
+    #[test]
+    fn test_invalid_voxel_type_fails_without_writing() {
+        let mut map = VoxelMap::new(test_palette());
+        let mut backbuffer = EditedChunksBackBuffer::new();
+        let result = run_voxel_script(
+            "set_voxel(0, 0, 0, 1); set_voxel(1, 0, 0, 200);",
+            PointN([0; 3]),
+            &mut map,
+            &mut backbuffer,
+        );
+
+        assert!(matches!(result, Err(ScriptError::InvalidVoxelType(200))));
+    }
+}
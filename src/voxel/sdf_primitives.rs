@@ -0,0 +1,252 @@
+//! Analytic signed distance functions for simple shapes, and rasterizing them into the map. Since
+//! the exact distance is known at every voxel, the resulting surface is much cleaner than what you
+//! get from many sphere brush strokes.
+
+use crate::voxel::{
+    double_buffer::EditedChunksBackBuffer, sphere_brush::SetVoxelOperation, Voxel,
+    VoxelChunkReader, VoxelDistance, VoxelType, EMPTY_VOXEL,
+};
+
+use building_blocks::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A shape centered on the origin, in voxel units.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum SdfPrimitive {
+    Sphere {
+        radius: f32,
+    },
+    Box {
+        half_extents: [f32; 3],
+    },
+    /// Lies flat in the XZ plane.
+    Torus {
+        major_radius: f32,
+        minor_radius: f32,
+    },
+    /// Stands on its base, with the apex pointing up +Y.
+    Cone {
+        radius: f32,
+        height: f32,
+    },
+}
+
+impl SdfPrimitive {
+    /// The signed distance from `p` (relative to the center) to the surface.
+    pub fn distance(&self, p: [f32; 3]) -> f32 {
+        match *self {
+            SdfPrimitive::Sphere { radius } => length3(p) - radius,
+            SdfPrimitive::Box { half_extents } => {
+                let q = [
+                    p[0].abs() - half_extents[0],
+                    p[1].abs() - half_extents[1],
+                    p[2].abs() - half_extents[2],
+                ];
+                let outside = length3([q[0].max(0.0), q[1].max(0.0), q[2].max(0.0)]);
+                let inside = q[0].max(q[1]).max(q[2]).min(0.0);
+
+                outside + inside
+            }
+            SdfPrimitive::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let ring = (p[0] * p[0] + p[2] * p[2]).sqrt() - major_radius;
+
+                (ring * ring + p[1] * p[1]).sqrt() - minor_radius
+            }
+            SdfPrimitive::Cone { radius, height } => cone_distance(p, radius, 0.5 * height),
+        }
+    }
+
+    /// Half of the size of the shape's bounding box on each axis.
+    pub fn half_extents(&self) -> [f32; 3] {
+        match *self {
+            SdfPrimitive::Sphere { radius } => [radius; 3],
+            SdfPrimitive::Box { half_extents } => half_extents,
+            SdfPrimitive::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let r = major_radius + minor_radius;
+
+                [r, minor_radius, r]
+            }
+            SdfPrimitive::Cone { radius, height } => [radius, 0.5 * height, radius],
+        }
+    }
+
+    /// The voxels that need to be written to rasterize the shape at `center`, padded so the
+    /// distances fade out smoothly around the surface.
+    pub fn extent(&self, center: Point3i) -> Extent3i {
+        let h = self.half_extents();
+        let r = PointN([
+            h[0].ceil() as i32 + 2,
+            h[1].ceil() as i32 + 2,
+            h[2].ceil() as i32 + 2,
+        ]);
+
+        Extent3i::from_min_and_max(center - r, center + r)
+    }
+}
+
+/// The kinds of `SdfPrimitive`, for tools that size a primitive with a single number.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum PrimitiveKind {
+    Sphere,
+    Box,
+    Torus,
+    Cone,
+}
+
+impl Default for PrimitiveKind {
+    fn default() -> Self {
+        PrimitiveKind::Sphere
+    }
+}
+
+impl PrimitiveKind {
+    pub fn next(self) -> Self {
+        match self {
+            PrimitiveKind::Sphere => PrimitiveKind::Box,
+            PrimitiveKind::Box => PrimitiveKind::Torus,
+            PrimitiveKind::Torus => PrimitiveKind::Cone,
+            PrimitiveKind::Cone => PrimitiveKind::Sphere,
+        }
+    }
+
+    /// A primitive of this kind that fits in a sphere of radius `size`.
+    pub fn with_size(self, size: f32) -> SdfPrimitive {
+        match self {
+            PrimitiveKind::Sphere => SdfPrimitive::Sphere { radius: size },
+            PrimitiveKind::Box => SdfPrimitive::Box {
+                half_extents: [size / 3.0f32.sqrt(); 3],
+            },
+            PrimitiveKind::Torus => SdfPrimitive::Torus {
+                major_radius: 0.7 * size,
+                minor_radius: 0.3 * size,
+            },
+            PrimitiveKind::Cone => SdfPrimitive::Cone {
+                radius: size / 2.0f32.sqrt(),
+                height: 2.0f32.sqrt() * size,
+            },
+        }
+    }
+}
+
+/// Rasterizes `primitive` at `center` into the map. `MakeSolid` takes the union with the existing
+/// voxels (the min of the distances) and fills the new solid voxels with `voxel_type`, while
+/// `RemoveSolid` subtracts the primitive (the max with its negated distance).
+pub fn rasterize_primitive(
+    map_reader: &VoxelChunkReader,
+    primitive: &SdfPrimitive,
+    center: Point3i,
+    operation: SetVoxelOperation,
+    voxel_type: VoxelType,
+    backbuffer: &mut EditedChunksBackBuffer,
+) {
+    backbuffer.edit_voxels_out_of_place(
+        map_reader,
+        &primitive.extent(center),
+        |p: Point3i, v: &mut Voxel| {
+            let offset = p - center;
+            let d = primitive.distance([offset.x() as f32, offset.y() as f32, offset.z() as f32]);
+            let old_d: f32 = v.distance.into();
+
+            match operation {
+                SetVoxelOperation::MakeSolid => {
+                    if d < old_d {
+                        v.distance = VoxelDistance::from(d);
+                        if v.distance.0 < 0 {
+                            v.voxel_type = voxel_type;
+                        }
+                    }
+                }
+                SetVoxelOperation::RemoveSolid => {
+                    if -d > old_d {
+                        v.distance = VoxelDistance::from(-d);
+                        if v.distance.0 >= 0 {
+                            v.voxel_type = EMPTY_VOXEL.voxel_type;
+                        }
+                    }
+                }
+            }
+        },
+    );
+}
+
+fn length3(p: [f32; 3]) -> f32 {
+    (p[0] * p[0] + p[1] * p[1] + p[2] * p[2]).sqrt()
+}
+
+/// Exact distance to a cone with its base centered at y = -`half_height` and apex at
+/// y = `half_height`, worked out in the 2D half-plane through the axis.
+fn cone_distance(p: [f32; 3], radius: f32, half_height: f32) -> f32 {
+    let qx = (p[0] * p[0] + p[2] * p[2]).sqrt();
+    let qy = p[1];
+
+    // Distance to the base disk (or the apex, above it).
+    let cap_r = if qy < 0.0 { radius } else { 0.0 };
+    let ca = [qx - qx.min(cap_r), qy.abs() - half_height];
+
+    // Distance to the slanted side, from the apex (0, h) to the base rim (r, -h).
+    let k = [-radius, 2.0 * half_height];
+    let to_apex = [-qx, half_height - qy];
+    let t = ((to_apex[0] * k[0] + to_apex[1] * k[1]) / (k[0] * k[0] + k[1] * k[1]))
+        .max(0.0)
+        .min(1.0);
+    let cb = [qx + k[0] * t, qy - half_height + k[1] * t];
+
+    let sign = if cb[0] < 0.0 && ca[1] < 0.0 {
+        -1.0
+    } else {
+        1.0
+    };
+    let dist_sq = (ca[0] * ca[0] + ca[1] * ca[1]).min(cb[0] * cb[0] + cb[1] * cb[1]);
+
+    sign * dist_sq.sqrt()
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_primitive_distances_at_surface_and_center() {
+        let size = 6.0;
+        for kind in [
+            PrimitiveKind::Sphere,
+            PrimitiveKind::Box,
+            PrimitiveKind::Cone,
+        ]
+        .iter()
+        {
+            let primitive = kind.with_size(size);
+            assert!(primitive.distance([0.0; 3]) < 0.0, "{:?}", primitive);
+            assert!(
+                primitive.distance([size + 1.0, 0.0, 0.0]) > 0.0,
+                "{:?}",
+                primitive
+            );
+        }
+
+        let cone = SdfPrimitive::Cone {
+            radius: 2.0,
+            height: 4.0,
+        };
+        assert!((cone.distance([0.0, 2.0, 0.0])).abs() < 1e-5);
+        assert!((cone.distance([2.0, -2.0, 0.0])).abs() < 1e-5);
+        assert!((cone.distance([0.0, -3.0, 0.0]) - 1.0).abs() < 1e-5);
+
+        let torus = PrimitiveKind::Torus.with_size(size);
+        assert!(torus.distance([0.0; 3]) > 0.0);
+        assert!(torus.distance([0.7 * size, 0.0, 0.0]) < 0.0);
+    }
+}
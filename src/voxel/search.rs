@@ -1,8 +1,12 @@
+use super::{raycast::traverse_voxels_on_ray, Voxel, VoxelType};
+
 use crate::geometry::{project_point_onto_line, Line};
 
 use amethyst::core::math as na;
 use building_blocks::{prelude::*, search::greedy_path};
 use ordered_float::NotNan;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
 
 /// Finds a path from `start` to `finish` along voxels. Prioritizes staying close to the
 /// line from `start` to `finish`, so you should get a path like:
@@ -20,6 +24,8 @@ use ordered_float::NotNan;
 ///               | ++++   ______|
 ///               |_______|
 /// ```
+///
+/// This is tuned for the camera controller. For NPC movement, use `find_path`.
 pub fn greedy_path_with_l1_and_linear_heuristic(
     start: Point3i,
     finish: Point3i,
@@ -40,9 +46,306 @@ pub fn greedy_path_with_l1_and_linear_heuristic(
         let p_line = project_point_onto_line(&pf, &line);
         let line_dist = (pf - p_line).norm();
 
-        // Break ties using disalignment metric.
-        unsafe { NotNan::unchecked_new(exact + 0.001 * line_dist) }
+        // Break ties using disalignment metric. The line is degenerate when `start == finish`, so
+        // fall back to the exact distance if the metric isn't a number.
+        NotNan::new(exact + 0.001 * line_dist).unwrap_or_else(|_| NotNan::new(exact).unwrap())
     };
 
     greedy_path(start, finish, predicate, heuristic, max_iterations)
 }
+
+/// Options for `find_path`.
+#[derive(Clone, Copy, Debug)]
+pub struct PathOptions {
+    /// Also step to the voxels that share an edge or a corner with the current voxel. A diagonal
+    /// step is only taken if the voxels on each axis it crosses are passable, so paths never cut
+    /// through the corners of solid voxels.
+    pub allow_diagonal: bool,
+    /// The search gives up after expanding this many voxels.
+    pub max_iterations: usize,
+    /// Remove the waypoints that can be skipped by walking in a straight line.
+    pub smooth: bool,
+}
+
+impl Default for PathOptions {
+    fn default() -> Self {
+        Self {
+            allow_diagonal: true,
+            max_iterations: 5000,
+            smooth: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PathResult {
+    /// If false, `path` ends at the voxel closest to `finish` that was reached.
+    pub reached_finish: bool,
+    /// The voxels to walk through, including both `start` and the last voxel.
+    pub path: Vec<Point3i>,
+}
+
+/// Finds the cheapest path of voxels from `start` to `finish` with A*, e.g. for NPC movement.
+///
+/// `cost_fn` returns the cost of stepping into a voxel, or `None` if the voxel can't be entered.
+/// Costs are scaled by the length of the step, so diagonal steps cost more. The heuristic is the
+/// straight-line distance, so paths are only optimal when every cost is at least 1.
+///
+/// Use `voxel_type_cost_fn` to give each voxel type its own cost, e.g. to make NPCs avoid mud.
+pub fn find_path(
+    start: Point3i,
+    finish: Point3i,
+    cost_fn: impl Fn(&Point3i) -> Option<f32>,
+    options: &PathOptions,
+) -> PathResult {
+    let offsets = neighbor_offsets(options.allow_diagonal);
+    let heuristic = |p: &Point3i| distance(p, &finish);
+
+    let mut open = BinaryHeap::new();
+    let mut best_costs: HashMap<Point3i, f32> = HashMap::new();
+    let mut came_from: HashMap<Point3i, Point3i> = HashMap::new();
+    open.push(Reverse((NotNan::new(heuristic(&start)).unwrap(), start)));
+    best_costs.insert(start, 0.0);
+
+    let mut closest = (heuristic(&start), start);
+    let mut reached_finish = false;
+    let mut iterations = 0;
+    while let Some(Reverse((_, p))) = open.pop() {
+        if p == finish {
+            reached_finish = true;
+            closest = (0.0, p);
+            break;
+        }
+        if iterations == options.max_iterations {
+            break;
+        }
+        iterations += 1;
+
+        let p_cost = best_costs[&p];
+        for offset in offsets.iter() {
+            let neighbor = p + *offset;
+            let step_cost = match cost_fn(&neighbor) {
+                Some(c) => c,
+                None => continue,
+            };
+            if !diagonal_is_clear(&p, offset, &cost_fn) {
+                continue;
+            }
+            let neighbor_cost = p_cost + step_cost * offset_length(offset);
+            if best_costs
+                .get(&neighbor)
+                .map_or(false, |c| *c <= neighbor_cost)
+            {
+                continue;
+            }
+
+            // A NaN cost from `cost_fn` can't be ordered, so the voxel is treated as blocked.
+            let h = heuristic(&neighbor);
+            let priority = match NotNan::new(neighbor_cost + h) {
+                Ok(priority) => priority,
+                Err(_) => continue,
+            };
+            best_costs.insert(neighbor, neighbor_cost);
+            came_from.insert(neighbor, p);
+
+            if h < closest.0 {
+                closest = (h, neighbor);
+            }
+            open.push(Reverse((priority, neighbor)));
+        }
+    }
+
+    let mut path = vec![closest.1];
+    while let Some(prev) = came_from.get(path.last().unwrap()) {
+        path.push(*prev);
+    }
+    path.reverse();
+
+    if options.smooth {
+        path = smooth_path(&path, |p| cost_fn(p).is_some());
+    }
+
+    PathResult {
+        reached_finish,
+        path,
+    }
+}
+
+/// A cost function for `find_path` that looks up the type of each voxel in `costs`. Voxel types
+/// without a cost can't be entered.
+pub fn voxel_type_cost_fn<'a, V>(
+    voxels: &'a V,
+    costs: &'a HashMap<VoxelType, f32>,
+) -> impl Fn(&Point3i) -> Option<f32> + 'a
+where
+    V: Get<Point3i, Item = Voxel>,
+{
+    move |p: &Point3i| costs.get(&voxels.get(*p).voxel_type).cloned()
+}
+
+/// Removes each waypoint that the previous remaining waypoint can see past, where a straight line
+/// between voxel centers only passes through voxels that satisfy `is_passable`.
+pub fn smooth_path(path: &[Point3i], is_passable: impl Fn(&Point3i) -> bool) -> Vec<Point3i> {
+    if path.len() < 3 {
+        return path.to_vec();
+    }
+
+    let mut smoothed = vec![path[0]];
+    let mut anchor = path[0];
+    for i in 1..path.len() - 1 {
+        if !is_line_clear(&anchor, &path[i + 1], &is_passable) {
+            anchor = path[i];
+            smoothed.push(anchor);
+        }
+    }
+    smoothed.push(*path.last().unwrap());
+
+    smoothed
+}
+
+fn is_line_clear(from: &Point3i, to: &Point3i, is_passable: &impl Fn(&Point3i) -> bool) -> bool {
+    // Between voxel centers, in voxel units rather than the world units of `voxel_center`.
+    let origin = Point3f::from(*from) + PointN([0.5; 3]);
+    let diff = Point3f::from(*to - *from);
+
+    traverse_voxels_on_ray(
+        [origin.x(), origin.y(), origin.z()],
+        [diff.x(), diff.y(), diff.z()],
+        1.0,
+        |p| !is_passable(&p),
+    )
+    .is_none()
+}
+
+fn neighbor_offsets(allow_diagonal: bool) -> Vec<Point3i> {
+    let mut offsets = Vec::new();
+    for z in -1..=1 {
+        for y in -1..=1 {
+            for x in -1..=1 {
+                let offset = PointN([x, y, z]);
+                let num_axes = num_nonzero_axes(&offset);
+                if num_axes == 1 || (allow_diagonal && num_axes > 1) {
+                    offsets.push(offset);
+                }
+            }
+        }
+    }
+
+    offsets
+}
+
+fn diagonal_is_clear(
+    p: &Point3i,
+    offset: &Point3i,
+    cost_fn: &impl Fn(&Point3i) -> Option<f32>,
+) -> bool {
+    if num_nonzero_axes(offset) == 1 {
+        return true;
+    }
+
+    (0..3).all(|axis| {
+        if offset.0[axis] == 0 {
+            return true;
+        }
+        let mut axis_offset = PointN([0; 3]);
+        axis_offset.0[axis] = offset.0[axis];
+
+        cost_fn(&(*p + axis_offset)).is_some()
+    })
+}
+
+fn num_nonzero_axes(offset: &Point3i) -> usize {
+    offset.0.iter().filter(|c| **c != 0).count()
+}
+
+fn offset_length(offset: &Point3i) -> f32 {
+    (num_nonzero_axes(offset) as f32).sqrt()
+}
+
+fn distance(a: &Point3i, b: &Point3i) -> f32 {
+    let diff = *b - *a;
+
+    diff.0.iter().map(|c| (c * c) as f32).sum::<f32>().sqrt()
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_goes_around_wall() {
+        // A wall at x = 2 for z in [-2, 2], on a single layer.
+        let cost_fn = |p: &Point3i| {
+            if p.y() != 0 || p.x().abs() > 5 || p.z().abs() > 5 {
+                None
+            } else if p.x() == 2 && p.z().abs() <= 2 {
+                None
+            } else {
+                Some(1.0)
+            }
+        };
+        let options = PathOptions {
+            smooth: false,
+            ..Default::default()
+        };
+
+        let start = PointN([0, 0, 0]);
+        let finish = PointN([4, 0, 0]);
+        let result = find_path(start, finish, cost_fn, &options);
+
+        assert!(result.reached_finish);
+        assert_eq!(result.path.first(), Some(&start));
+        assert_eq!(result.path.last(), Some(&finish));
+        for w in result.path.windows(2) {
+            assert!(cost_fn(&w[1]).is_some());
+            assert!((w[1] - w[0]).0.iter().all(|c| c.abs() <= 1));
+        }
+
+        let no_diagonal = PathOptions {
+            allow_diagonal: false,
+            ..options
+        };
+        let result = find_path(start, PointN([2, 0, 0]), cost_fn, &no_diagonal);
+        assert!(!result.reached_finish);
+        assert_eq!(result.path.last(), Some(&PointN([1, 0, 0])));
+    }
+
+    #[test]
+    fn test_nan_cost_is_blocked() {
+        let cost_fn = |p: &Point3i| {
+            if p.y() != 0 || p.x().abs() > 3 || p.z().abs() > 3 {
+                None
+            } else if p.x() == 1 {
+                Some(std::f32::NAN)
+            } else {
+                Some(1.0)
+            }
+        };
+        let result = find_path(
+            PointN([0, 0, 0]),
+            PointN([2, 0, 0]),
+            cost_fn,
+            &Default::default(),
+        );
+
+        assert!(!result.reached_finish);
+        assert_eq!(result.path, vec![PointN([0, 0, 0])]);
+    }
+
+    #[test]
+    fn test_smoothed_path_on_open_ground_is_straight() {
+        let start = PointN([0, 0, 0]);
+        let finish = PointN([6, 0, 3]);
+        let result = find_path(start, finish, |_p| Some(1.0), &PathOptions::default());
+
+        assert!(result.reached_finish);
+        assert_eq!(result.path, vec![start, finish]);
+    }
+}
@@ -0,0 +1,312 @@
+//! Recording every chunk that the `VoxelDoubleBufferingSystem` merges into the map, so an editing
+//! session can be played back exactly, whatever tools, scripts or simulations made the changes.
+//! Unlike the `EditJournal`, which only has the edits that can be described as plain data, this
+//! doesn't depend on edits doing the same thing when they're applied again, so it's the one to use
+//! when tracking down corrupted chunks. It's also a good source of time-lapse videos.
+
+use crate::{
+    assets::{read_bincode_file, write_bincode_file, BincodeFileError},
+    voxel::{
+        double_buffer::EditedChunksBackBuffer,
+        map_file::{compress_chunk, decompress_chunk, snapshot_chunks},
+        morton::morton_ordered_chunk_mins,
+        Voxel, VoxelMap,
+    },
+};
+
+use amethyst::core::ecs::prelude::*;
+use building_blocks::prelude::*;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RecordedChunk {
+    pub minimum: [i32; 3],
+    /// The voxels, compressed with `compress_chunk`.
+    pub lz4_voxels: Vec<u8>,
+}
+
+impl RecordedChunk {
+    fn compress(chunk_min: Point3i, chunk: &Array3x1<Voxel>) -> Result<Self, BincodeFileError> {
+        Ok(Self {
+            minimum: chunk_min.0,
+            lz4_voxels: compress_chunk(chunk, None)?,
+        })
+    }
+
+    fn decompress(
+        &self,
+        chunk_shape: Point3i,
+    ) -> Result<(Point3i, Array3x1<Voxel>), BincodeFileError> {
+        let chunk_min = PointN(self.minimum);
+
+        Ok((
+            chunk_min,
+            decompress_chunk(chunk_min, chunk_shape, &self.lz4_voxels)?,
+        ))
+    }
+}
+
+/// The chunks written by one merge of the backbuffer.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RecordedDelta {
+    /// Frames since the first recorded delta.
+    pub frame: u64,
+    /// Seconds since the first recorded delta.
+    pub seconds: f64,
+    pub chunks: Vec<RecordedChunk>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct SessionRecording {
+    /// Every chunk of the map when the recording started, so playback doesn't depend on the map
+    /// it's played onto.
+    pub initial_chunks: Vec<RecordedChunk>,
+    pub deltas: Vec<RecordedDelta>,
+}
+
+impl SessionRecording {
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), BincodeFileError> {
+        write_bincode_file(path, self)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, BincodeFileError> {
+        read_bincode_file(path)
+    }
+}
+
+fn compress_chunks<'a>(
+    chunks: impl IntoParallelIterator<Item = (Point3i, &'a Array3x1<Voxel>)>,
+) -> Result<Vec<RecordedChunk>, BincodeFileError> {
+    chunks
+        .into_par_iter()
+        .map(|(chunk_min, chunk)| RecordedChunk::compress(chunk_min, chunk))
+        .collect()
+}
+
+/// Records the merged chunks while a recording is started. The `VoxelDoubleBufferingSystem` does
+/// the recording, so this only needs to be started and taken when it's done.
+#[derive(Default)]
+pub struct SessionRecorder {
+    recording: Option<SessionRecording>,
+    /// The frame number and time of the first recorded delta.
+    start: Option<(u64, f64)>,
+}
+
+impl SessionRecorder {
+    /// Starts a new recording from the current state of `map`, replacing any recording in progress.
+    pub fn start(&mut self, map: &VoxelMap) -> Result<(), BincodeFileError> {
+        let chunks = snapshot_chunks(map);
+        let initial_chunks = compress_chunks(chunks.par_iter().map(|(p, c)| (*p, c)))?;
+        self.recording = Some(SessionRecording {
+            initial_chunks,
+            deltas: Vec::new(),
+        });
+        self.start = None;
+
+        Ok(())
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Stops recording and returns what was recorded.
+    pub fn take(&mut self) -> Option<SessionRecording> {
+        self.start = None;
+
+        self.recording.take()
+    }
+
+    pub fn record(
+        &mut self,
+        frame: u64,
+        seconds: f64,
+        chunks: Vec<(Point3i, &Array3x1<Voxel>)>,
+    ) -> Result<(), BincodeFileError> {
+        let recording = match self.recording.as_mut() {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+        if chunks.is_empty() {
+            return Ok(());
+        }
+
+        let (start_frame, start_seconds) = *self.start.get_or_insert((frame, seconds));
+        recording.deltas.push(RecordedDelta {
+            frame: frame - start_frame,
+            seconds: seconds - start_seconds,
+            chunks: compress_chunks(chunks)?,
+        });
+
+        Ok(())
+    }
+}
+
+/// Plays back a `SessionRecording` with the `SessionPlaybackSystem`.
+#[derive(Default)]
+pub struct SessionPlayback {
+    recording: SessionRecording,
+    /// Recorded frames to advance per app frame. 2.0 plays twice as fast as the recording.
+    speed: f64,
+    /// How far into the recording playback has gotten, in recorded frames.
+    elapsed_frames: f64,
+    /// `None` until the initial chunks are restored.
+    next_delta: Option<usize>,
+}
+
+impl SessionPlayback {
+    pub fn new(recording: SessionRecording, speed: f64) -> Self {
+        Self {
+            recording,
+            speed,
+            elapsed_frames: 0.0,
+            next_delta: None,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next_delta
+            .map_or(false, |next| next >= self.recording.deltas.len())
+    }
+
+    /// Returns the deltas that are due after advancing by one app frame. The first delta is due on
+    /// the first frame.
+    fn advance(&mut self) -> &[RecordedDelta] {
+        let deltas = &self.recording.deltas;
+        let start = self.next_delta.unwrap_or(0);
+        let mut next = start;
+        while next < deltas.len() && deltas[next].frame as f64 <= self.elapsed_frames {
+            next += 1;
+        }
+        self.next_delta = Some(next);
+        self.elapsed_frames += self.speed;
+
+        &deltas[start..next]
+    }
+}
+
+fn decompress_all(
+    chunks: &[RecordedChunk],
+    chunk_shape: Point3i,
+) -> Vec<(Point3i, Option<Array3x1<Voxel>>)> {
+    chunks
+        .par_iter()
+        .filter_map(|c| match c.decompress(chunk_shape) {
+            Ok((chunk_min, chunk)) => Some((chunk_min, Some(chunk))),
+            Err(e) => {
+                log::error!(
+                    "Failed to decompress recorded chunk {:?}: {:?}",
+                    c.minimum,
+                    e
+                );
+
+                None
+            }
+        })
+        .collect()
+}
+
+/// Writes the chunks of the `SessionPlayback` into the backbuffer as their recorded frames come up.
+/// The first frame puts the map back the way it was when recording started, replacing chunks that
+/// weren't in the map then with generated or empty ones. Like undo, playback isn't recorded in the
+/// `EditHistory`. Recordings can only be played back onto maps with the chunk shape they were
+/// recorded with.
+pub struct SessionPlaybackSystem;
+
+impl<'a> System<'a> for SessionPlaybackSystem {
+    type SystemData = (
+        Write<'a, SessionPlayback>,
+        ReadExpect<'a, VoxelMap>,
+        WriteExpect<'a, EditedChunksBackBuffer>,
+    );
+
+    fn run(&mut self, (mut playback, map, mut backbuffer): Self::SystemData) {
+        let recording = &playback.recording;
+        if (recording.initial_chunks.is_empty() && recording.deltas.is_empty())
+            || playback.is_finished()
+        {
+            return;
+        }
+
+        let local_cache = LocalChunkCache3::new();
+        let reader = map.voxels.reader(&local_cache);
+        let chunk_shape = map.chunk_shape();
+
+        if playback.next_delta.is_none() {
+            let mut restore = decompress_all(&playback.recording.initial_chunks, chunk_shape);
+            let initial_mins: HashSet<Point3i> = restore.iter().map(|(p, _)| *p).collect();
+            restore.extend(
+                morton_ordered_chunk_mins(&map)
+                    .into_iter()
+                    .filter(|p| !initial_mins.contains(p))
+                    .map(|p| (p, None)),
+            );
+            backbuffer.restore_chunks(&reader, restore);
+            log::info!("Started playing back the recorded session");
+        }
+
+        for delta in playback.advance() {
+            backbuffer.restore_chunks(&reader, decompress_all(&delta.chunks, chunk_shape));
+        }
+
+        if playback.is_finished() {
+            log::info!("Finished playing back the recorded session");
+        }
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        test_util::test_palette,
+        voxel::{empty_array, VoxelType},
+    };
+
+    #[test]
+    fn test_recorded_deltas_play_back_in_order() {
+        let map = VoxelMap::new(test_palette());
+        let chunk_shape = map.chunk_shape();
+        let mut recorder = SessionRecorder::default();
+        recorder.start(&map).unwrap();
+
+        let chunk_min = PointN([0; 3]);
+        let mut chunk = empty_array(Extent3i::from_min_and_shape(chunk_min, chunk_shape));
+        chunk.get_mut(PointN([1, 2, 3])).voxel_type = VoxelType(1);
+        recorder
+            .record(100, 5.0, vec![(chunk_min, &chunk)])
+            .unwrap();
+        // Frames without any merged chunks aren't recorded.
+        recorder.record(101, 5.1, Vec::new()).unwrap();
+        recorder
+            .record(104, 5.5, vec![(chunk_min, &chunk)])
+            .unwrap();
+
+        let recording = recorder.take().unwrap();
+        assert!(!recorder.is_recording());
+        let frames: Vec<u64> = recording.deltas.iter().map(|d| d.frame).collect();
+        assert_eq!(frames, vec![0, 4]);
+        assert!((recording.deltas[1].seconds - 0.5).abs() < 1e-9);
+        let (_, decompressed) = recording.deltas[0].chunks[0]
+            .decompress(chunk_shape)
+            .unwrap();
+        assert_eq!(decompressed.get(PointN([1, 2, 3])).voxel_type, VoxelType(1));
+
+        let mut playback = SessionPlayback::new(recording, 2.0);
+        assert_eq!(playback.advance().len(), 1);
+        assert_eq!(playback.advance().len(), 0);
+        assert_eq!(playback.advance().len(), 1);
+        assert!(playback.is_finished());
+    }
+}
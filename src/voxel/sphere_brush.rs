@@ -0,0 +1,148 @@
+use crate::voxel::{
+    centered_extent,
+    double_buffer::{EditSourceId, EditStamp, EditedChunksBackBuffer, QueuedEdit},
+    Voxel, VoxelDistanceInt, VoxelType, DISTANCE_STEPS_PER_SD8, EMPTY_VOXEL,
+};
+
+use building_blocks::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum SetVoxelOperation {
+    /// Set voxels in the solid to negative distances from the surface, and surrounding voxels to
+    /// the positive distance from the surface.
+    MakeSolid,
+    /// Set voxels in the solid to positive distances from the surface, and surrounding voxels to
+    /// the negative distance from the surface.
+    RemoveSolid,
+}
+
+/// How the strength of the sphere brush fades from its center to the edge of its shell.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum BrushFalloff {
+    /// Fades evenly to nothing at the edge.
+    Linear,
+    /// Stays strong near the center and fades quickly near the edge, for rounder results.
+    Smooth,
+    /// Full strength all the way to the edge.
+    Constant,
+}
+
+impl Default for BrushFalloff {
+    fn default() -> Self {
+        BrushFalloff::Linear
+    }
+}
+
+impl BrushFalloff {
+    pub fn next(self) -> Self {
+        match self {
+            BrushFalloff::Linear => BrushFalloff::Smooth,
+            BrushFalloff::Smooth => BrushFalloff::Constant,
+            BrushFalloff::Constant => BrushFalloff::Linear,
+        }
+    }
+
+    /// The strength at `t`, the fraction of the shell radius away from the center. Zero outside of
+    /// the shell.
+    pub fn weight(self, t: f32) -> f32 {
+        if t >= 1.0 {
+            return 0.0;
+        }
+        let t = t.max(0.0);
+        match self {
+            BrushFalloff::Linear => 1.0 - t,
+            BrushFalloff::Smooth => 1.0 - t * t * (3.0 - 2.0 * t),
+            BrushFalloff::Constant => 1.0,
+        }
+    }
+}
+
+/// One application of the sphere brush. This is plain data, so it can be recorded and applied
+/// again later.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SphereStroke {
+    pub operation: SetVoxelOperation,
+    pub center: Point3i,
+    pub radius: u32,
+    /// Ignored for `RemoveSolid`.
+    pub voxel_type: VoxelType,
+    /// The fraction of the radius beyond which the stroke has no effect.
+    pub shell_cutoff: f32,
+    /// How much the SDF changes at the center. Higher is harder.
+    pub sdf_growth_factor: f32,
+    pub falloff: BrushFalloff,
+}
+
+impl SphereStroke {
+    pub fn extent(&self) -> Extent3i {
+        centered_extent(self.center, self.radius)
+    }
+
+    fn shell_radius(&self) -> f32 {
+        self.shell_cutoff * self.radius as f32
+    }
+
+    /// Queues the stroke in the backbuffer. Only chunks that intersect the sphere are edited.
+    pub fn queue(
+        &self,
+        source: EditSourceId,
+        backbuffer: &mut EditedChunksBackBuffer,
+    ) -> EditStamp {
+        let stamp = backbuffer.next_stamp(source);
+        backbuffer.queue_stamped_edit(self.queued_edit(stamp));
+
+        stamp
+    }
+
+    /// The stroke as an edit with an existing stamp, e.g. when replaying a recording.
+    pub fn queued_edit(&self, stamp: EditStamp) -> QueuedEdit {
+        let stroke = *self;
+        let center = self.center;
+        let shell_radius = self.shell_radius();
+        let chunk_filter = move |chunk_extent: &Extent3i| {
+            let min = chunk_extent.minimum;
+            let max = chunk_extent.max();
+            let closest = PointN([
+                center.x().max(min.x()).min(max.x()),
+                center.y().max(min.y()).min(max.y()),
+                center.z().max(min.z()).min(max.z()),
+            ]);
+
+            (closest - center).norm() < shell_radius
+        };
+
+        QueuedEdit {
+            stamp,
+            extent: self.extent(),
+            chunk_filter: Box::new(chunk_filter),
+            edit: Box::new(move |p, v| stroke.edit_voxel(p, v)),
+        }
+    }
+
+    fn edit_voxel(&self, p: Point3i, v: &mut Voxel) {
+        let sign = match self.operation {
+            SetVoxelOperation::MakeSolid => -1,
+            SetVoxelOperation::RemoveSolid => 1,
+        };
+        let dist = (p - self.center).norm();
+
+        // Change the SDF faster closer to the center.
+        let sdf_delta = sign
+            * (self.sdf_growth_factor * self.falloff.weight(dist / self.shell_radius())).round()
+                as i16;
+        // The growth factor is in 8-bit distance steps, so the brush feels the same with `sd16`.
+        let new_dist = v.distance.0 as i32 + sdf_delta as i32 * DISTANCE_STEPS_PER_SD8 as i32;
+
+        v.distance.0 = new_dist
+            .max(VoxelDistanceInt::MIN as i32)
+            .min(VoxelDistanceInt::MAX as i32) as VoxelDistanceInt;
+
+        if sdf_delta < 0 && v.distance.0 < 0 {
+            // Only set to the brush type if the voxel is solid.
+            v.voxel_type = self.voxel_type;
+        } else if sdf_delta > 0 && v.distance.0 >= 0 {
+            v.voxel_type = EMPTY_VOXEL.voxel_type;
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use crate::voxel::{
+    double_buffer::EditedChunksBackBuffer, Voxel, VoxelChunkReader, VoxelDistance, VoxelType,
+    EMPTY_VOXEL,
+};
+
+use amethyst::core::math::{Point3, Vector2, Vector3};
+use building_blocks::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+/// A Catmull-Rom spline that passes through all of its control points.
+#[derive(Clone, Debug, Default)]
+pub struct CatmullRomSpline {
+    pub control_points: Vec<Point3<f32>>,
+}
+
+impl CatmullRomSpline {
+    pub fn new(control_points: Vec<Point3<f32>>) -> Self {
+        Self { control_points }
+    }
+
+    pub fn num_segments(&self) -> usize {
+        self.control_points.len().saturating_sub(1)
+    }
+
+    /// Evaluates segment `i` (between control points `i` and `i + 1`) at `t` in `[0, 1]`. The end
+    /// points are duplicated so the curve reaches the first and last control points.
+    pub fn evaluate_segment(&self, i: usize, t: f32) -> Point3<f32> {
+        let n = self.control_points.len();
+        let p = |j: isize| self.control_points[j.max(0).min(n as isize - 1) as usize].coords;
+        let i = i as isize;
+        let (p0, p1, p2, p3) = (p(i - 1), p(i), p(i + 1), p(i + 2));
+
+        let t2 = t * t;
+        let t3 = t2 * t;
+
+        Point3::from(
+            0.5 * ((2.0 * p1)
+                + (p2 - p0) * t
+                + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+                + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3),
+        )
+    }
+
+    /// Samples the curve into a polyline whose points are at most roughly `spacing` apart.
+    pub fn to_polyline(&self, spacing: f32) -> Vec<Point3<f32>> {
+        if self.control_points.len() < 2 {
+            return self.control_points.clone();
+        }
+
+        let mut polyline = vec![self.control_points[0]];
+        for i in 0..self.num_segments() {
+            let chord = (self.control_points[i + 1] - self.control_points[i]).norm();
+            let steps = (chord / spacing).ceil().max(1.0) as usize;
+            for s in 1..=steps {
+                polyline.push(self.evaluate_segment(i, s as f32 / steps as f32));
+            }
+        }
+
+        polyline
+    }
+}
+
+/// The cross section swept along a path.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct PathProfile {
+    /// Half of the width of the flattened road bed.
+    pub half_width: f32,
+    /// How much space is cleared above the road bed.
+    pub clearance: f32,
+    /// How deep the road bed is filled underneath, so roads can bridge small gaps.
+    pub fill_depth: f32,
+    /// The material painted onto the road bed and used for any fill underneath it.
+    pub voxel_type: VoxelType,
+}
+
+/// Closest point on the XZ projection of `polyline` to `p`. Returns the horizontal distance and the
+/// height of the polyline at that point, which gives the road a grade that varies linearly
+/// between samples.
+fn closest_on_polyline_xz(polyline: &[Point3<f32>], p: Vector2<f32>) -> (f32, f32) {
+    let mut best = (std::f32::MAX, 0.0);
+    for (a, b) in polyline.iter().zip(polyline.iter().skip(1)) {
+        let a_xz = Vector2::new(a.x, a.z);
+        let ab = Vector2::new(b.x, b.z) - a_xz;
+        let len2 = ab.norm_squared();
+        let t = if len2 > 0.0 {
+            ((p - a_xz).dot(&ab) / len2).max(0.0).min(1.0)
+        } else {
+            0.0
+        };
+        let dist = (a_xz + t * ab - p).norm();
+        if dist < best.0 {
+            best = (dist, a.y + t * (b.y - a.y));
+        }
+    }
+
+    best
+}
+
+/// Sweeps `profile` along `spline`, clearing the space above the road bed, filling the space below
+/// it, and painting the bed with the profile's material. The bed surface follows the heights of the
+/// control points, so the grade is consistent between them regardless of the terrain underneath.
+pub fn carve_path(
+    map_reader: &VoxelChunkReader,
+    spline: &CatmullRomSpline,
+    profile: &PathProfile,
+    backbuffer: &mut EditedChunksBackBuffer,
+) {
+    #[cfg(feature = "profiler")]
+    profile_scope!("carve_path");
+
+    let polyline = spline.to_polyline(0.5);
+    if polyline.len() < 2 {
+        return;
+    }
+
+    // Bound the whole sweep.
+    let pad = Vector3::new(
+        profile.half_width,
+        profile.clearance.max(profile.fill_depth),
+        profile.half_width,
+    );
+    let mut min = polyline[0].coords;
+    let mut max = polyline[0].coords;
+    for p in polyline.iter() {
+        min = min.inf(&p.coords);
+        max = max.sup(&p.coords);
+    }
+    let min = min - pad;
+    let max = max + pad;
+    let extent = Extent3i::from_min_and_max(
+        PointN([
+            min.x.floor() as i32,
+            min.y.floor() as i32,
+            min.z.floor() as i32,
+        ]),
+        PointN([
+            max.x.ceil() as i32,
+            max.y.ceil() as i32,
+            max.z.ceil() as i32,
+        ]),
+    );
+
+    backbuffer.edit_voxels_out_of_place(map_reader, &extent, |p: Point3i, v: &mut Voxel| {
+        let (lateral, bed_height) =
+            closest_on_polyline_xz(&polyline, Vector2::new(p.x() as f32, p.z() as f32));
+        if lateral > profile.half_width {
+            return;
+        }
+
+        let height_above_bed = p.y() as f32 - bed_height;
+        if height_above_bed > profile.clearance || height_above_bed < -profile.fill_depth {
+            return;
+        }
+
+        let old_dist: f32 = v.distance.into();
+        let new_dist = if height_above_bed >= 0.0 {
+            // Clear everything above the bed.
+            old_dist.max(height_above_bed)
+        } else {
+            // Fill everything below the bed.
+            old_dist.min(height_above_bed)
+        };
+        v.distance = VoxelDistance::from(new_dist);
+
+        if v.distance.0 >= 0 {
+            v.voxel_type = EMPTY_VOXEL.voxel_type;
+        } else if height_above_bed > -2.0 || v.voxel_type == EMPTY_VOXEL.voxel_type {
+            // Paint the bed, and give newly filled voxels some material.
+            v.voxel_type = profile.voxel_type;
+        }
+    });
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::assert_relative_eq_point3;
+
+    #[test]
+    fn test_spline_interpolates_control_points() {
+        let spline = CatmullRomSpline::new(vec![
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(5.0, 1.0, 0.0),
+            Point3::new(10.0, 0.0, 5.0),
+        ]);
+
+        assert_relative_eq_point3(&spline.evaluate_segment(0, 0.0), &spline.control_points[0]);
+        assert_relative_eq_point3(&spline.evaluate_segment(0, 1.0), &spline.control_points[1]);
+        assert_relative_eq_point3(&spline.evaluate_segment(1, 1.0), &spline.control_points[2]);
+    }
+
+    #[test]
+    fn test_polyline_ends_at_last_control_point() {
+        let spline =
+            CatmullRomSpline::new(vec![Point3::new(0.0, 0.0, 0.0), Point3::new(3.0, 0.0, 0.0)]);
+        let polyline = spline.to_polyline(0.5);
+
+        assert_eq!(polyline.len(), 7);
+        assert_relative_eq_point3(polyline.last().unwrap(), &Point3::new(3.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_closest_on_polyline_interpolates_height() {
+        let polyline = vec![Point3::new(0.0, 0.0, 0.0), Point3::new(10.0, 10.0, 0.0)];
+        let (dist, height) = closest_on_polyline_xz(&polyline, Vector2::new(5.0, 2.0));
+
+        assert!((dist - 2.0).abs() < 1e-5);
+        assert!((height - 5.0).abs() < 1e-5);
+    }
+}
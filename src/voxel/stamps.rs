@@ -0,0 +1,142 @@
+//! A library of named voxel "stamps" (saved `VoxelClipboard`s, like trees or rocks) that can be
+//! placed over and over. Each stamp is a ".stamp" file in the library directory, named after the
+//! file, so stamps can be shared by copying files.
+
+use crate::{
+    assets::BincodeFileError,
+    voxel::{clipboard::VoxelClipboard, double_buffer::EditedChunksBackBuffer, VoxelChunkReader},
+};
+
+use building_blocks::prelude::*;
+use std::path::{Path, PathBuf};
+
+pub const STAMP_EXTENSION: &str = "stamp";
+
+pub struct VoxelStamp {
+    pub name: String,
+    pub clipboard: VoxelClipboard,
+}
+
+impl VoxelStamp {
+    /// Pastes the stamp rotated by `quarter_turns` about the Y axis, with the center of its bottom
+    /// face on top of `base`.
+    pub fn place(
+        &self,
+        map_reader: &VoxelChunkReader,
+        base: Point3i,
+        quarter_turns: u8,
+        backbuffer: &mut EditedChunksBackBuffer,
+    ) {
+        let mut clipboard = self.clipboard.clone();
+        for _ in 0..quarter_turns % 4 {
+            clipboard.rotate_y_90();
+        }
+        let shape = clipboard.shape();
+        let min = base - PointN([shape.x() / 2, 0, shape.z() / 2]);
+        clipboard.paste(map_reader, min, backbuffer);
+    }
+}
+
+/// The stamps found in a directory, with one of them selected for the stamp brush.
+#[derive(Default)]
+pub struct StampLibrary {
+    dir: PathBuf,
+    stamps: Vec<VoxelStamp>,
+    selected: usize,
+    /// Quarter turns about the Y axis to apply when placing the selected stamp.
+    pub quarter_turns: u8,
+}
+
+impl StampLibrary {
+    /// Loads every ".stamp" file in `dir`, sorted by name. Files that fail to load are logged and
+    /// skipped, and a missing directory is an empty library.
+    pub fn load_dir(dir: impl AsRef<Path>) -> Self {
+        let dir = dir.as_ref().to_path_buf();
+        let mut stamps = Vec::new();
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => {
+                return Self {
+                    dir,
+                    ..Default::default()
+                }
+            }
+        };
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if path.extension().map_or(true, |ext| ext != STAMP_EXTENSION) {
+                continue;
+            }
+            let name = match path.file_stem() {
+                Some(stem) => stem.to_string_lossy().into_owned(),
+                None => continue,
+            };
+            match VoxelClipboard::load(&path) {
+                Ok(clipboard) => stamps.push(VoxelStamp { name, clipboard }),
+                Err(e) => log::error!("Failed to load stamp {:?}: {:?}", path, e),
+            }
+        }
+        stamps.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Self {
+            dir,
+            stamps,
+            selected: 0,
+            quarter_turns: 0,
+        }
+    }
+
+    pub fn stamps(&self) -> &[VoxelStamp] {
+        &self.stamps
+    }
+
+    pub fn selected(&self) -> Option<&VoxelStamp> {
+        self.stamps.get(self.selected)
+    }
+
+    pub fn select_next(&mut self) {
+        if !self.stamps.is_empty() {
+            self.selected = (self.selected + 1) % self.stamps.len();
+        }
+    }
+
+    /// Writes `clipboard` to the library directory as `name`, replacing any stamp with the same
+    /// name, and selects it.
+    pub fn save_stamp(
+        &mut self,
+        name: &str,
+        clipboard: VoxelClipboard,
+    ) -> Result<(), BincodeFileError> {
+        std::fs::create_dir_all(&self.dir)?;
+        clipboard.save(self.stamp_path(name))?;
+
+        let stamp = VoxelStamp {
+            name: name.to_string(),
+            clipboard,
+        };
+        self.selected = match self.stamps.binary_search_by(|s| s.name.as_str().cmp(name)) {
+            Ok(i) => {
+                self.stamps[i] = stamp;
+                i
+            }
+            Err(i) => {
+                self.stamps.insert(i, stamp);
+                i
+            }
+        };
+
+        Ok(())
+    }
+
+    /// A name like "stamp_3" that isn't used by any stamp yet.
+    pub fn unused_name(&self) -> String {
+        (self.stamps.len()..)
+            .map(|i| format!("stamp_{}", i))
+            .find(|name| self.stamps.iter().all(|s| &s.name != name))
+            .unwrap()
+    }
+
+    fn stamp_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", name, STAMP_EXTENSION))
+    }
+}
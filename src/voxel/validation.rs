@@ -0,0 +1,215 @@
+//! Consistency checks for the voxels stored in a map, for cleaning up maps that were damaged by
+//! bugs or palette changes.
+
+use crate::voxel::{morton::morton_ordered_chunk_mins, Voxel, VoxelMap, VoxelPalette, EMPTY_VOXEL};
+
+use building_blocks::prelude::*;
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum VoxelIssue {
+    /// The voxel type doesn't index into the palette.
+    TypeOutOfRange,
+    /// A voxel with a positive distance (empty space) that has a non-empty type.
+    EmptyWithType,
+    /// A voxel with a negative distance (solid) whose type is marked `is_empty`.
+    SolidMarkedEmpty,
+}
+
+impl VoxelIssue {
+    fn check(palette: &VoxelPalette, v: Voxel) -> Option<Self> {
+        let info = match palette.infos.get(v.voxel_type.0 as usize) {
+            Some(info) => info,
+            None => return Some(VoxelIssue::TypeOutOfRange),
+        };
+        if v.distance.0 >= 0 && v.voxel_type != EMPTY_VOXEL.voxel_type {
+            Some(VoxelIssue::EmptyWithType)
+        } else if v.distance.0 < 0 && info.flags.is_empty {
+            Some(VoxelIssue::SolidMarkedEmpty)
+        } else {
+            None
+        }
+    }
+
+    /// Empty space keeps its distance and loses its type. Solid voxels with unusable types become
+    /// empty space, since there's no way to know what they should have been.
+    fn fix(self, v: &mut Voxel) {
+        match self {
+            VoxelIssue::EmptyWithType => v.voxel_type = EMPTY_VOXEL.voxel_type,
+            VoxelIssue::TypeOutOfRange | VoxelIssue::SolidMarkedEmpty => {
+                if v.distance.0 >= 0 {
+                    v.voxel_type = EMPTY_VOXEL.voxel_type;
+                } else {
+                    *v = EMPTY_VOXEL;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MapValidationReport {
+    pub chunks_checked: usize,
+    /// How many voxels have each issue.
+    pub issue_counts: HashMap<VoxelIssue, usize>,
+    /// One voxel with each issue, to help find them in the editor.
+    pub issue_examples: HashMap<VoxelIssue, Point3i>,
+    /// Chunks that are entirely `EMPTY_VOXEL`, so they don't need to be stored.
+    pub ambient_chunks: Vec<Point3i>,
+}
+
+impl MapValidationReport {
+    pub fn is_clean(&self) -> bool {
+        self.issue_counts.is_empty() && self.ambient_chunks.is_empty()
+    }
+
+    fn add_chunk(&mut self, chunk: ChunkValidation) {
+        self.chunks_checked += 1;
+        for (issue, count, example) in chunk.issues.into_iter() {
+            *self.issue_counts.entry(issue).or_insert(0) += count;
+            self.issue_examples.entry(issue).or_insert(example);
+        }
+        if chunk.is_ambient {
+            self.ambient_chunks.push(chunk.chunk_min);
+        }
+    }
+}
+
+struct ChunkValidation {
+    chunk_min: Point3i,
+    issues: Vec<(VoxelIssue, usize, Point3i)>,
+    is_ambient: bool,
+    /// Only present if fixes were requested and something changed.
+    fixed_chunk: Option<Array3x1<Voxel>>,
+}
+
+fn validate_chunk(
+    palette: &VoxelPalette,
+    chunk_min: Point3i,
+    mut chunk: Array3x1<Voxel>,
+    fix: bool,
+) -> ChunkValidation {
+    let mut issues: HashMap<VoxelIssue, (usize, Point3i)> = HashMap::new();
+    let mut is_ambient = true;
+    let extent = *chunk.extent();
+    chunk.for_each_mut(&extent, |p: Point3i, v: &mut Voxel| {
+        if let Some(issue) = VoxelIssue::check(palette, *v) {
+            issues.entry(issue).or_insert((0, p)).0 += 1;
+            if fix {
+                issue.fix(v);
+            }
+        }
+        is_ambient &= *v == EMPTY_VOXEL;
+    });
+
+    let fixed_chunk = if fix && !issues.is_empty() {
+        Some(chunk)
+    } else {
+        None
+    };
+
+    ChunkValidation {
+        chunk_min,
+        issues: issues
+            .into_iter()
+            .map(|(issue, (count, example))| (issue, count, example))
+            .collect(),
+        is_ambient,
+        fixed_chunk,
+    }
+}
+
+fn validate_chunks(map: &VoxelMap, fix: bool) -> Vec<ChunkValidation> {
+    morton_ordered_chunk_mins(map)
+        .into_par_iter()
+        .filter_map(|chunk_min| {
+            let local_cache = LocalChunkCache3::new();
+            let reader = map.voxels.reader(&local_cache);
+            let chunk = reader.get_chunk(ChunkKey::new(0, chunk_min))?.clone();
+
+            Some(validate_chunk(&map.palette, chunk_min, chunk, fix))
+        })
+        .collect()
+}
+
+/// Scans every stored chunk for inconsistent voxels and chunks that don't need to be stored.
+pub fn validate_map(map: &VoxelMap) -> MapValidationReport {
+    let mut report = MapValidationReport::default();
+    for chunk in validate_chunks(map, false).into_iter() {
+        report.add_chunk(chunk);
+    }
+
+    report
+}
+
+/// Like `validate_map`, but also fixes the issues it finds (see `VoxelIssue::fix`) and removes
+/// ambient chunks. The report describes the map before it was fixed.
+pub fn fix_map(map: &mut VoxelMap) -> MapValidationReport {
+    let mut report = MapValidationReport::default();
+    for mut chunk in validate_chunks(map, true).into_iter() {
+        let key = ChunkKey::new(0, chunk.chunk_min);
+        if chunk.is_ambient {
+            map.voxels.pop_chunk(key);
+        } else if let Some(fixed) = chunk.fixed_chunk.take() {
+            map.voxels.write_chunk(key, fixed);
+        }
+        report.add_chunk(chunk);
+    }
+
+    report
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        test_util::test_palette,
+        voxel::{VoxelDistance, VoxelType},
+    };
+
+    #[test]
+    fn test_validate_and_fix_chunk() {
+        let extent = Extent3i::from_min_and_shape(PointN([0; 3]), PointN([2; 3]));
+        let mut chunk = Array3x1::fill(extent, EMPTY_VOXEL);
+        *chunk.get_mut(PointN([0, 0, 0])) = Voxel {
+            voxel_type: VoxelType(1),
+            distance: VoxelDistance(5),
+        };
+        *chunk.get_mut(PointN([1, 0, 0])) = Voxel {
+            voxel_type: VoxelType(7),
+            distance: VoxelDistance(-5),
+        };
+        *chunk.get_mut(PointN([0, 1, 0])) = Voxel {
+            voxel_type: VoxelType(0),
+            distance: VoxelDistance(-5),
+        };
+
+        let result = validate_chunk(&test_palette(), PointN([0; 3]), chunk, true);
+        let mut issues: Vec<VoxelIssue> = result.issues.iter().map(|(i, _, _)| *i).collect();
+        issues.sort_by_key(|i| *i as u8);
+        assert_eq!(
+            issues,
+            vec![
+                VoxelIssue::TypeOutOfRange,
+                VoxelIssue::EmptyWithType,
+                VoxelIssue::SolidMarkedEmpty
+            ]
+        );
+
+        // Every voxel was fixed to empty space, but the first kept its distance.
+        assert!(!result.is_ambient);
+        let fixed = result.fixed_chunk.unwrap();
+        assert_eq!(fixed.get(PointN([0, 0, 0])).voxel_type, VoxelType(0));
+        assert_eq!(fixed.get(PointN([1, 0, 0])), EMPTY_VOXEL);
+        assert_eq!(fixed.get(PointN([0, 1, 0])), EMPTY_VOXEL);
+    }
+}
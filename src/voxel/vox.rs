@@ -0,0 +1,81 @@
+//! Export to the MagicaVoxel .vox format, so structures can be opened in other voxel tools.
+
+use crate::voxel::{clipboard::VoxelClipboard, Voxel};
+
+use building_blocks::prelude::*;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+const VOX_VERSION: i32 = 150;
+/// .vox coordinates are stored as bytes.
+const MAX_VOX_DIM: i32 = 256;
+
+/// Writes the solid voxels of `clipboard` as a .vox model. Palette index 0 means empty in .vox, so
+/// each voxel type is written as the palette index one above it, and the last voxel type can't be
+/// exported. Since .vox is Z-up, the Y and Z axes are swapped.
+pub fn write_vox_file(path: impl AsRef<Path>, clipboard: &VoxelClipboard) -> io::Result<()> {
+    let shape = clipboard.shape();
+    if shape.0.iter().any(|&d| d > MAX_VOX_DIM) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(".vox models can't be larger than {0}^3", MAX_VOX_DIM),
+        ));
+    }
+
+    let mut xyzi = Vec::new();
+    let mut unexportable = false;
+    let extent = *clipboard.voxels.extent();
+    clipboard.voxels.for_each(&extent, |p: Point3i, v: Voxel| {
+        if v.distance.0 < 0 {
+            match v.voxel_type.0.checked_add(1) {
+                Some(index) => xyzi.push([p.x() as u8, p.z() as u8, p.y() as u8, index]),
+                None => unexportable = true,
+            }
+        }
+    });
+    if unexportable {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(".vox palettes can't hold voxel type {}", std::u8::MAX),
+        ));
+    }
+
+    let size_bytes = 12;
+    let xyzi_bytes = 4 + 4 * xyzi.len() as i32;
+    let children_bytes = (12 + size_bytes) + (12 + xyzi_bytes);
+
+    let mut w = BufWriter::new(File::create(path)?);
+    w.write_all(b"VOX ")?;
+    write_i32(&mut w, VOX_VERSION)?;
+
+    write_chunk_header(&mut w, b"MAIN", 0, children_bytes)?;
+
+    write_chunk_header(&mut w, b"SIZE", size_bytes, 0)?;
+    write_i32(&mut w, shape.x())?;
+    write_i32(&mut w, shape.z())?;
+    write_i32(&mut w, shape.y())?;
+
+    write_chunk_header(&mut w, b"XYZI", xyzi_bytes, 0)?;
+    write_i32(&mut w, xyzi.len() as i32)?;
+    for voxel in xyzi.iter() {
+        w.write_all(voxel)?;
+    }
+
+    w.flush()
+}
+
+fn write_chunk_header(
+    w: &mut impl Write,
+    id: &[u8; 4],
+    content_bytes: i32,
+    children_bytes: i32,
+) -> io::Result<()> {
+    w.write_all(id)?;
+    write_i32(w, content_bytes)?;
+    write_i32(w, children_bytes)
+}
+
+fn write_i32(w: &mut impl Write, x: i32) -> io::Result<()> {
+    w.write_all(&x.to_le_bytes())
+}
@@ -0,0 +1,173 @@
+use building_blocks::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// An inclusive box of voxels.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ZoneBox {
+    pub min: [i32; 3],
+    pub max: [i32; 3],
+}
+
+impl ZoneBox {
+    pub fn extent(&self) -> Extent3i {
+        Extent3i::from_min_and_max(PointN(self.min), PointN(self.max))
+    }
+}
+
+impl From<Extent3i> for ZoneBox {
+    fn from(extent: Extent3i) -> Self {
+        Self {
+            min: extent.minimum.0,
+            max: extent.max().0,
+        }
+    }
+}
+
+/// A named region of the map, made of any number of boxes. Games can use zones for things like
+/// music areas, quest triggers, or deciding which part of the map to stream in.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Zone {
+    pub name: String,
+    pub boxes: Vec<ZoneBox>,
+}
+
+impl Zone {
+    pub fn contains(&self, p: &Point3i) -> bool {
+        self.boxes.iter().any(|b| b.extent().contains(p))
+    }
+
+    /// The size of the smallest box containing `p`, if any.
+    fn smallest_box_containing(&self, p: &Point3i) -> Option<usize> {
+        self.boxes
+            .iter()
+            .map(|b| b.extent())
+            .filter(|e| e.contains(p))
+            .map(|e| e.num_points())
+            .min()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MapZones {
+    zones: Vec<Zone>,
+    /// Set whenever the zones change, so they can be saved with the map.
+    changed: bool,
+}
+
+impl MapZones {
+    pub fn new(zones: Vec<Zone>) -> Self {
+        Self {
+            zones,
+            changed: false,
+        }
+    }
+
+    /// Creates a zone with a single box and an unused name like "Zone 3", and returns the name.
+    pub fn add_zone(&mut self, extent: Extent3i) -> String {
+        let name = (1..)
+            .map(|i| format!("Zone {}", i))
+            .find(|name| self.find(name).is_none())
+            .unwrap();
+        self.zones.push(Zone {
+            name: name.clone(),
+            boxes: vec![extent.into()],
+        });
+        self.changed = true;
+
+        name
+    }
+
+    /// Returns false if there is no zone called `name`.
+    pub fn add_box(&mut self, name: &str, extent: Extent3i) -> bool {
+        match self.zones.iter_mut().find(|z| z.name == name) {
+            Some(zone) => {
+                zone.boxes.push(extent.into());
+                self.changed = true;
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes the zone that `zone_at(p)` would return.
+    pub fn remove_at(&mut self, p: &Point3i) -> Option<Zone> {
+        let i = self.index_at(p)?;
+        self.changed = true;
+
+        Some(self.zones.remove(i))
+    }
+
+    /// The zone containing `p`. Where zones overlap, the one with the smaller box wins, so zones
+    /// can be nested inside of larger ones.
+    pub fn zone_at(&self, p: &Point3i) -> Option<&Zone> {
+        self.index_at(p).map(|i| &self.zones[i])
+    }
+
+    fn index_at(&self, p: &Point3i) -> Option<usize> {
+        self.zones
+            .iter()
+            .enumerate()
+            .filter_map(|(i, z)| z.smallest_box_containing(p).map(|size| (i, size)))
+            .min_by_key(|(_, size)| *size)
+            .map(|(i, _)| i)
+    }
+
+    /// Every zone containing `p`.
+    pub fn zones_at<'a>(&'a self, p: &'a Point3i) -> impl Iterator<Item = &'a Zone> {
+        self.zones.iter().filter(move |z| z.contains(p))
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Zone> {
+        self.zones.iter().find(|z| z.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Zone> {
+        self.zones.iter()
+    }
+
+    pub fn has_changed(&self) -> bool {
+        self.changed
+    }
+}
+
+// ████████╗███████╗███████╗████████╗███████╗
+// ╚══██╔══╝██╔════╝██╔════╝╚══██╔══╝██╔════╝
+//    ██║   █████╗  ███████╗   ██║   ███████╗
+//    ██║   ██╔══╝  ╚════██║   ██║   ╚════██║
+//    ██║   ███████╗███████║   ██║   ███████║
+//    ╚═╝   ╚══════╝╚══════╝   ╚═╝   ╚══════╝
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nested_zones() {
+        let mut zones = MapZones::default();
+        let town = zones.add_zone(Extent3i::from_min_and_shape(
+            PointN([0; 3]),
+            PointN([100; 3]),
+        ));
+        let tavern = zones.add_zone(Extent3i::from_min_and_shape(
+            PointN([10; 3]),
+            PointN([5; 3]),
+        ));
+        assert!(zones.add_box(
+            &tavern,
+            Extent3i::from_min_and_shape(PointN([200; 3]), PointN([5; 3]))
+        ));
+
+        assert_eq!(zones.zone_at(&PointN([1; 3])).unwrap().name, town);
+        assert_eq!(zones.zone_at(&PointN([12; 3])).unwrap().name, tavern);
+        assert_eq!(zones.zone_at(&PointN([202; 3])).unwrap().name, tavern);
+        assert_eq!(zones.zones_at(&PointN([12; 3])).count(), 2);
+        assert!(zones.zone_at(&PointN([-1; 3])).is_none());
+
+        zones.remove_at(&PointN([1; 3]));
+        assert_eq!(
+            zones.add_zone(Extent3i::from_min_and_shape(PointN([0; 3]), PointN([1; 3]))),
+            "Zone 1"
+        );
+    }
+}